@@ -0,0 +1,269 @@
+use std::collections::{HashMap, HashSet};
+
+use log::error;
+use serde::{Deserialize, Serialize};
+use serenity::builder::{CreateActionRow, CreateAllowedMentions, CreateButton, CreateInvite, CreateMessage, EditMessage};
+use serenity::model::prelude::*;
+use serenity::prelude::*;
+
+use crate::{audit, CommandError, CommandResult, Persistent, require_permission, scheduler, store};
+use crate::memreport::{Introspect, Owner, Reference};
+
+/// Starts the custom ID of every button on an appeal, so that presses can be told apart.
+pub const BUTTON_PREFIX: &str = "appeal:";
+const ACCEPT_BUTTON: &str = "appeal:accept";
+const DENY_BUTTON: &str = "appeal:deny";
+
+/// How long someone has to appeal an action after it was taken.
+const OFFER_SECS: i64 = 30 * 24 * 60 * 60;
+
+/// How long the invites sent with accepted appeals stay valid.
+const INVITE_SECS: u32 = 24 * 60 * 60;
+
+pub struct StateKey;
+
+impl TypeMapKey for StateKey {
+    type Value = Persistent<State>;
+}
+
+#[derive(Serialize, Deserialize, Default, Clone, Eq, PartialEq)]
+pub struct State {
+    /// The staff channel each guild's appeals are posted to. Guilds without one don't take appeals.
+    channels: HashMap<GuildId, ChannelId>,
+    /// Actions that were taken against users who may still appeal them.
+    offers: Vec<Offer>,
+    /// Appeals waiting for staff, keyed by the message they were posted to staff as.
+    appeals: HashMap<MessageId, Appeal>,
+}
+
+impl Introspect for State {
+    fn entries(&self) -> Vec<(Owner, usize)> {
+        let channels = self.channels.keys().map(|guild| (Owner::Guild(*guild), 1));
+        let offers = self.offers.iter().map(|offer| (Owner::Guild(offer.guild), 1));
+        let appeals = self.appeals.values().map(|appeal| (Owner::Guild(appeal.offer.guild), 1));
+        channels.chain(offers).chain(appeals).collect()
+    }
+
+    fn references(&self) -> Vec<(Owner, Reference)> {
+        self.channels.iter().map(|(guild, channel)| (Owner::Guild(*guild), Reference::Channel(*channel))).collect()
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq)]
+struct Offer {
+    guild: GuildId,
+    user: UserId,
+    case: u64,
+    /// What was done, like "kicked", for telling the user and staff.
+    action: String,
+    at: i64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Eq, PartialEq)]
+struct Appeal {
+    offer: Offer,
+    staff_channel: ChannelId,
+    reason: String,
+}
+
+enum Outcome {
+    Accepted(UserId),
+    Denied(UserId),
+}
+
+pub async fn set_channel(ctx: &Context, command: &Message, channel: Option<ChannelId>) -> CommandResult<()> {
+    let guild = command.guild_id.ok_or(CommandError::NotAllowed)?;
+
+    let state = store::<StateKey>(ctx).await;
+    state.write(|state| {
+        match channel {
+            Some(channel) => state.channels.insert(guild, channel),
+            None => state.channels.remove(&guild),
+        };
+    }).await;
+
+    Ok(())
+}
+
+/// Lets a user know how to appeal an action that was taken against them, if the guild takes appeals.
+pub async fn offer(ctx: &Context, guild: GuildId, user: UserId, case: u64, action: &str, reason: &str) {
+    let state = store::<StateKey>(ctx).await;
+    if !state.read().await.channels.contains_key(&guild) {
+        return;
+    }
+
+    let offer = Offer { guild, user, case, action: action.to_owned(), at: scheduler::now() };
+    state.write(|state| {
+        state.offers.retain(|other| (other.guild, other.user) != (guild, user));
+        state.offers.push(offer);
+    }).await;
+
+    let guild_name = guild.name(&ctx.cache).unwrap_or_else(|| "a server".to_owned());
+    let content = format!(
+        "You were {} from **{}**: {}\nIf you think this was a mistake, you can appeal within 30 days by replying here with `appeal {} <why it should be undone>`.",
+        action, guild_name, reason, guild.get(),
+    );
+    if let Ok(dm) = user.create_dm_channel(&ctx.http).await {
+        let _ = dm.say(&ctx.http, content).await;
+    }
+}
+
+/// Handles `appeal <guild> <reason>` sent by DM, returning whether the message was one.
+pub async fn direct_message(ctx: &Context, message: &Message) -> bool {
+    if message.guild_id.is_some() || message.author.bot {
+        return false;
+    }
+
+    let tokens: Vec<&str> = message.content.split_whitespace().collect();
+    let (guild, reason) = match tokens.as_slice() {
+        ["appeal", guild, reason @ ..] if !reason.is_empty() => (*guild, reason.join(" ")),
+        ["appeal", ..] => {
+            let _ = message.channel_id.say(&ctx.http, "Appeal with `appeal <server id> <why it should be undone>`.").await;
+            return true;
+        }
+        _ => return false,
+    };
+
+    let reply = match guild.parse::<GuildId>() {
+        Ok(guild) => match submit(ctx, guild, message.author.id, reason).await {
+            Ok(true) => "📨 Your appeal was sent to the staff. I'll let you know once they have answered.",
+            Ok(false) => "There's nothing you can appeal in that server.",
+            Err(err) => {
+                error!("failed to post appeal by {} to {}: {:?}", message.author.id, guild, err);
+                "Your appeal couldn't be sent right now, please try again later."
+            }
+        },
+        Err(_) => "That isn't a server ID.",
+    };
+    let _ = message.channel_id.say(&ctx.http, reply).await;
+
+    true
+}
+
+/// Posts an appeal to the staff channel, returning whether there was anything to appeal.
+async fn submit(ctx: &Context, guild: GuildId, user: UserId, reason: String) -> serenity::Result<bool> {
+    let cutoff = scheduler::now() - OFFER_SECS;
+    let state = store::<StateKey>(ctx).await;
+    let taken = state.write(|state| {
+        let staff_channel = *state.channels.get(&guild)?;
+        let index = state.offers.iter().position(|offer| (offer.guild, offer.user) == (guild, user) && offer.at >= cutoff)?;
+        Some((state.offers.remove(index), staff_channel))
+    }).await;
+    let (offer, staff_channel) = match taken {
+        Some(taken) => taken,
+        None => return Ok(false),
+    };
+
+    let content = format!("📨 <@{}> appeals being {} (case #{}):\n> {}", user.get(), offer.action, offer.case, reason);
+    let buttons = CreateActionRow::Buttons(vec![
+        CreateButton::new(ACCEPT_BUTTON).label("Accept and invite").style(ButtonStyle::Success),
+        CreateButton::new(DENY_BUTTON).label("Deny").style(ButtonStyle::Danger),
+    ]);
+    let posted = staff_channel.send_message(&ctx.http, CreateMessage::new()
+        .content(content.chars().take(2000).collect::<String>())
+        .components(vec![buttons])
+        .allowed_mentions(CreateAllowedMentions::new())
+    ).await;
+    let message = match posted {
+        Ok(message) => message,
+        Err(err) => {
+            // give the offer back so that they can try again
+            state.write(|state| state.offers.push(offer)).await;
+            return Err(err);
+        }
+    };
+
+    state.write(|state| {
+        state.appeals.insert(message.id, Appeal { offer, staff_channel, reason });
+    }).await;
+
+    Ok(true)
+}
+
+/// Handles staff answering an appeal with its buttons, which needs the Kick Members permission.
+pub async fn answer(ctx: &Context, interaction: &ComponentInteraction, permissions: Permissions) -> CommandResult<()> {
+    let outcome = match interaction.data.custom_id.as_str() {
+        ACCEPT_BUTTON => Outcome::Accepted(interaction.user.id),
+        DENY_BUTTON => Outcome::Denied(interaction.user.id),
+        _ => return Err(CommandError::InvalidCommand),
+    };
+    require_permission(permissions, Permissions::KICK_MEMBERS)?;
+
+    // creating the invite can take longer than Discord waits for an answer
+    interaction.defer(&ctx.http).await?;
+    close(ctx, interaction.message.id, outcome).await?;
+
+    Ok(())
+}
+
+async fn close(ctx: &Context, message: MessageId, outcome: Outcome) -> serenity::Result<()> {
+    let state = store::<StateKey>(ctx).await;
+    let appeal = match state.write(|state| state.appeals.remove(&message)).await {
+        Some(appeal) => appeal,
+        None => return Ok(()),
+    };
+    let Offer { guild, user, case, .. } = appeal.offer;
+
+    let guild_name = guild.name(&ctx.cache).unwrap_or_default();
+    let (summary, reply) = match outcome {
+        Outcome::Accepted(staff) => {
+            let reply = match create_invite(ctx, guild).await {
+                Some(invite) => format!("✅ Your appeal to **{}** was accepted! You can rejoin with {}", guild_name, invite),
+                None => format!("✅ Your appeal to **{}** was accepted!", guild_name),
+            };
+            (format!("✅ <@{}> accepted the appeal of <@{}> (case #{})", staff.get(), user.get(), case), reply)
+        }
+        Outcome::Denied(staff) => (
+            format!("❌ <@{}> denied the appeal of <@{}> (case #{})", staff.get(), user.get(), case),
+            format!("❌ Your appeal to **{}** was denied.", guild_name),
+        ),
+    };
+
+    if let Ok(dm) = user.create_dm_channel(&ctx.http).await {
+        let _ = dm.say(&ctx.http, reply).await;
+    }
+
+    let content = format!("{}\n> {}", summary, appeal.reason);
+    let edit = EditMessage::new().content(content.chars().take(2000).collect::<String>()).components(Vec::new());
+    if let Err(err) = appeal.staff_channel.edit_message(&ctx.http, message, edit).await {
+        error!("failed to update appeal {}: {:?}", message, err);
+    }
+
+    audit::log(ctx, guild, summary).await;
+
+    Ok(())
+}
+
+/// Creates a single-use invite to the guild's system channel, or its first text channel.
+async fn create_invite(ctx: &Context, guild: GuildId) -> Option<String> {
+    let channel = ctx.cache.guild(guild).and_then(|guild| {
+        guild.system_channel_id.or_else(|| {
+            guild.channels.values()
+                .filter(|channel| channel.kind == ChannelType::Text)
+                .min_by_key(|channel| channel.position)
+                .map(|channel| channel.id)
+        })
+    })?;
+
+    match channel.create_invite(&ctx.http, CreateInvite::new().max_uses(1).max_age(INVITE_SECS).unique(true)).await {
+        Ok(invite) => Some(invite.url()),
+        Err(err) => {
+            error!("failed to create appeal invite for {}: {:?}", guild, err);
+            None
+        }
+    }
+}
+
+/// Forgets all guilds not in `guilds` and offers that can no longer be taken up, returning the
+/// guilds that were removed.
+pub async fn retain_guilds(ctx: &Context, guilds: &HashSet<GuildId>) -> Vec<GuildId> {
+    let cutoff = scheduler::now() - OFFER_SECS;
+    let state = store::<StateKey>(ctx).await;
+    state.write(|state| {
+        let removed = state.channels.keys().filter(|guild| !guilds.contains(guild)).copied().collect();
+        state.channels.retain(|guild, _| guilds.contains(guild));
+        state.offers.retain(|offer| guilds.contains(&offer.guild) && offer.at >= cutoff);
+        state.appeals.retain(|_, appeal| guilds.contains(&appeal.offer.guild));
+        removed
+    }).await
+}
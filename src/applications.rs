@@ -0,0 +1,168 @@
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use serenity::model::prelude::*;
+use serenity::prelude::*;
+
+use crate::{approvals, CommandError, CommandResult, Persistent, scheduler, store};
+use crate::memreport::{Introspect, Owner, Reference};
+
+/// How long an applicant has to answer each question.
+const ANSWER_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+
+/// Answers are cut off at this length so that the whole application fits in one review message.
+const MAX_ANSWER_LENGTH: usize = 150;
+
+const MAX_QUESTIONS: usize = 8;
+
+pub struct StateKey;
+
+impl TypeMapKey for StateKey {
+    type Value = Persistent<State>;
+}
+
+#[derive(Serialize, Deserialize, Default, Clone, Eq, PartialEq)]
+pub struct State {
+    guilds: HashMap<GuildId, GuildState>,
+}
+
+impl Introspect for State {
+    fn entries(&self) -> Vec<(Owner, usize)> {
+        self.guilds.iter().map(|(guild, state)| (Owner::Guild(*guild), state.forms.len())).collect()
+    }
+
+    fn references(&self) -> Vec<(Owner, Reference)> {
+        self.guilds.iter().flat_map(|(guild, state)| {
+            state.forms.iter().flat_map(move |(role, form)| {
+                vec![(Owner::Guild(*guild), Reference::Role(*role)), (Owner::Guild(*guild), Reference::Channel(form.review_channel))]
+            })
+        }).collect()
+    }
+}
+
+#[derive(Serialize, Deserialize, Default, Clone, Eq, PartialEq)]
+struct GuildState {
+    forms: HashMap<RoleId, Form>,
+}
+
+/// The questions asked to anyone applying for a role, and where their answers get reviewed.
+#[derive(Serialize, Deserialize, Clone, Eq, PartialEq)]
+struct Form {
+    review_channel: ChannelId,
+    questions: Vec<String>,
+}
+
+pub async fn set_form(ctx: &Context, command: &Message, role: RoleId, review_channel: ChannelId, questions: &[&str]) -> CommandResult<()> {
+    let guild = command.guild_id.ok_or(CommandError::NotAllowed)?;
+
+    if questions.is_empty() || questions.len() > MAX_QUESTIONS {
+        return Err(CommandError::InvalidCommand);
+    }
+
+    let form = Form {
+        review_channel,
+        questions: questions.iter().map(|question| question.to_string()).collect(),
+    };
+
+    let state = store::<StateKey>(ctx).await;
+    state.write(|state| {
+        state.guilds.entry(guild).or_default().forms.insert(role, form);
+    }).await;
+
+    Ok(())
+}
+
+pub async fn remove_form(ctx: &Context, command: &Message, role: RoleId) -> CommandResult<()> {
+    let guild = command.guild_id.ok_or(CommandError::NotAllowed)?;
+
+    let state = store::<StateKey>(ctx).await;
+    state.write(|state| {
+        let guild_state = state.guilds.get_mut(&guild).ok_or(CommandError::UnknownApplication)?;
+        guild_state.forms.remove(&role).ok_or(CommandError::UnknownApplication)?;
+        if guild_state.forms.is_empty() {
+            state.guilds.remove(&guild);
+        }
+        Ok(())
+    }).await
+}
+
+/// Walks the applicant through the role's questions over DM and submits their answers for review.
+pub async fn apply(ctx: &Context, command: &Message, role: RoleId) -> CommandResult<()> {
+    let guild = command.guild_id.ok_or(CommandError::NotAllowed)?;
+
+    let form = {
+        let state = store::<StateKey>(ctx).await;
+        let state = state.read().await;
+        state.guilds.get(&guild).and_then(|guild| guild.forms.get(&role)).cloned()
+    };
+    let form = form.ok_or(CommandError::UnknownApplication)?;
+
+    let user = command.author.id;
+    let dm = user.create_dm_channel(&ctx.http).await.map_err(|_| CommandError::DirectMessagesClosed)?;
+    dm.say(&ctx.http, format!(
+        "📝 Applying for a role in **{}**. Please answer each question in a single message.",
+        guild.name(ctx).unwrap_or_default(),
+    )).await.map_err(|_| CommandError::DirectMessagesClosed)?;
+
+    let mut answers = Vec::with_capacity(form.questions.len());
+    for (index, question) in form.questions.iter().enumerate() {
+        dm.say(&ctx.http, format!("**{}/{}** {}", index + 1, form.questions.len(), question)).await?;
+
+        let reply = dm.id.await_reply(ctx)
+            .author_id(user)
+            .timeout(ANSWER_TIMEOUT)
+            .await;
+        match reply {
+            Some(reply) => answers.push(truncate(&reply.content, MAX_ANSWER_LENGTH)),
+            None => {
+                dm.say(&ctx.http, "⌛ You took too long to answer, so the application was cancelled.").await?;
+                return Ok(());
+            }
+        }
+    }
+
+    let details: String = form.questions.iter().zip(&answers)
+        .map(|(question, answer)| format!("\n**{}**\n> {}", question, answer.replace('\n', "\n> ")))
+        .collect();
+
+    let request = approvals::Request {
+        guild,
+        staff_channel: form.review_channel,
+        user,
+        roles: vec![role],
+        origin: approvals::Origin::Application,
+        created_at: scheduler::now(),
+    };
+    approvals::open_request(ctx, request, &details).await?;
+
+    dm.say(&ctx.http, "✅ Thanks! Your application has been sent to the staff for review.").await?;
+
+    Ok(())
+}
+
+fn truncate(text: &str, max_length: usize) -> String {
+    match text.char_indices().nth(max_length) {
+        Some((end, _)) => format!("{}…", &text[..end]),
+        None => text.to_owned(),
+    }
+}
+
+/// Forgets all guilds not in `guilds`, returning the ones that were removed.
+/// The roles of a guild that members can apply for.
+pub async fn roles(ctx: &Context, guild: GuildId) -> Vec<RoleId> {
+    let state = store::<StateKey>(ctx).await;
+    let roles = state.read().await.guilds.get(&guild)
+        .map(|guild| guild.forms.keys().copied().collect())
+        .unwrap_or_default();
+    roles
+}
+
+pub async fn retain_guilds(ctx: &Context, guilds: &HashSet<GuildId>) -> Vec<GuildId> {
+    let state = store::<StateKey>(ctx).await;
+    state.write(|state| {
+        let removed = state.guilds.keys().filter(|guild| !guilds.contains(guild)).copied().collect();
+        state.guilds.retain(|guild, _| guilds.contains(guild));
+        removed
+    }).await
+}
@@ -0,0 +1,239 @@
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+
+use log::error;
+use serde::{Deserialize, Serialize};
+use serenity::builder::{CreateActionRow, CreateAllowedMentions, CreateButton, CreateMessage, EditMessage};
+use serenity::model::prelude::*;
+use serenity::prelude::*;
+
+use crate::{audit, CommandError, CommandResult, Persistent, read_only, require_permission, role_queue, scheduler, store};
+use crate::reaction_roles::Emoji;
+use crate::memreport::{Introspect, Owner};
+
+/// Starts the custom ID of every button on a request, so that presses can be told apart.
+pub const BUTTON_PREFIX: &str = "approval:";
+const APPROVE_BUTTON: &str = "approval:approve";
+const DENY_BUTTON: &str = "approval:deny";
+
+/// Requests nobody has answered within this time expire.
+const EXPIRY_SECS: i64 = 48 * 60 * 60;
+
+/// How often expired requests are cleaned up.
+pub const EXPIRY_PERIOD: Duration = Duration::from_secs(5 * 60);
+
+pub struct StateKey;
+
+impl TypeMapKey for StateKey {
+    type Value = Persistent<State>;
+}
+
+/// Pending role requests, keyed by the message they were posted to staff as.
+#[derive(Serialize, Deserialize, Default, Clone, Eq, PartialEq)]
+pub struct State {
+    requests: HashMap<MessageId, Request>,
+}
+
+impl Introspect for State {
+    fn entries(&self) -> Vec<(Owner, usize)> {
+        self.requests.values().map(|request| (Owner::Guild(request.guild), 1)).collect()
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Eq, PartialEq)]
+pub struct Request {
+    pub guild: GuildId,
+    pub staff_channel: ChannelId,
+    pub user: UserId,
+    pub roles: Vec<RoleId>,
+    pub origin: Origin,
+    pub created_at: i64,
+}
+
+/// What a request was made through, so that it can be cleaned up once answered.
+#[derive(Serialize, Deserialize, Clone, Eq, PartialEq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Origin {
+    /// A reaction on a selector that requires approval.
+    Selector { channel: ChannelId, message: MessageId, emoji: Emoji },
+    /// An application submitted through a DM questionnaire.
+    Application,
+}
+
+enum Outcome {
+    Approved(UserId),
+    Denied(UserId),
+    Expired,
+}
+
+/// Posts a request for staff to approve and tracks it until it's answered.
+pub async fn open_request(ctx: &Context, request: Request, details: &str) -> serenity::Result<()> {
+    let roles: Vec<String> = request.roles.iter().map(|role| format!("<@&{}>", role.get())).collect();
+    let content = format!("📝 <@{}> is requesting {}{}", request.user.get(), roles.join(" "), details);
+    let buttons = CreateActionRow::Buttons(vec![
+        CreateButton::new(APPROVE_BUTTON).label("Approve").style(ButtonStyle::Success),
+        CreateButton::new(DENY_BUTTON).label("Deny").style(ButtonStyle::Danger),
+    ]);
+
+    let message = request.staff_channel.send_message(&ctx.http, CreateMessage::new()
+        .content(content.chars().take(2000).collect::<String>())
+        .components(vec![buttons])
+        .allowed_mentions(CreateAllowedMentions::new())
+    ).await?;
+
+    let state = store::<StateKey>(ctx).await;
+    state.write(|state| {
+        state.requests.insert(message.id, request);
+    }).await;
+
+    Ok(())
+}
+
+/// Requests roles through a selector that requires approval, unless the same request is already pending.
+pub async fn request_selector_roles(ctx: &Context, guild: GuildId, user: UserId, staff_channel: ChannelId, roles: &[RoleId], origin: Origin) -> serenity::Result<()> {
+    let state = store::<StateKey>(ctx).await;
+    let pending = state.read().await.requests.values().any(|request| request.user == user && request.origin == origin);
+    if pending {
+        return Ok(());
+    }
+
+    let details = match &origin {
+        Origin::Selector { channel, message, .. } => format!(" via {}", audit::message_link(guild, *channel, *message)),
+        Origin::Application => String::new(),
+    };
+
+    let request = Request { guild, staff_channel, user, roles: roles.to_vec(), origin, created_at: scheduler::now() };
+    open_request(ctx, request, &details).await
+}
+
+/// Withdraws a pending request, for when the user takes back the reaction that made it.
+pub async fn cancel_request(ctx: &Context, user: UserId, origin: &Origin) {
+    let state = store::<StateKey>(ctx).await;
+    let cancelled: Vec<(MessageId, Request)> = state.write(|state| {
+        let cancelled: Vec<MessageId> = state.requests.iter()
+            .filter(|(_, request)| request.user == user && request.origin == *origin)
+            .map(|(message, _)| *message)
+            .collect();
+        cancelled.into_iter()
+            .filter_map(|message| Some((message, state.requests.remove(&message)?)))
+            .collect()
+    }).await;
+
+    for (message, request) in cancelled {
+        let _ = request.staff_channel.delete_message(&ctx.http, message).await;
+    }
+}
+
+/// Handles staff answering a request with its buttons, which needs the Manage Roles permission.
+pub async fn answer(ctx: &Context, interaction: &ComponentInteraction, permissions: Permissions) -> CommandResult<()> {
+    let outcome = match interaction.data.custom_id.as_str() {
+        APPROVE_BUTTON => Outcome::Approved(interaction.user.id),
+        DENY_BUTTON => Outcome::Denied(interaction.user.id),
+        _ => return Err(CommandError::InvalidCommand),
+    };
+    require_permission(permissions, Permissions::MANAGE_ROLES)?;
+
+    // granting the roles can take longer than Discord waits for an answer
+    interaction.defer(&ctx.http).await?;
+    close_request(ctx, interaction.message.id, outcome).await?;
+
+    Ok(())
+}
+
+/// Grants or refuses the roles of a request and records who answered it.
+async fn close_request(ctx: &Context, message: MessageId, outcome: Outcome) -> serenity::Result<()> {
+    let state = store::<StateKey>(ctx).await;
+    let request = match state.write(|state| state.requests.remove(&message)).await {
+        Some(request) => request,
+        None => return Ok(()),
+    };
+
+    let roles: Vec<String> = request.roles.iter().map(|role| format!("<@&{}>", role.get())).collect();
+    let summary = match outcome {
+        Outcome::Approved(staff) => {
+            if let Err(err) = role_queue::add_roles(ctx, request.guild, request.user, &request.roles).await {
+                // keep the request open, so that staff can approve it again
+                state.write(|state| {
+                    state.requests.insert(message, request);
+                }).await;
+                return Err(err);
+            }
+            notify_applicant(ctx, &request, "✅ Your application was approved!").await;
+            format!("✅ <@{}> approved {} for <@{}>", staff.get(), roles.join(" "), request.user.get())
+        }
+        Outcome::Denied(staff) => {
+            withdraw_origin(ctx, &request).await;
+            format!("❌ <@{}> denied {} for <@{}>", staff.get(), roles.join(" "), request.user.get())
+        }
+        Outcome::Expired => {
+            withdraw_origin(ctx, &request).await;
+            format!("⌛ The request for {} by <@{}> expired", roles.join(" "), request.user.get())
+        }
+    };
+
+    let result = request.staff_channel.edit_message(&ctx.http, message, EditMessage::new().content(&summary).components(Vec::new())).await;
+    if let Err(err) = result {
+        error!("failed to update role request {}: {:?}", message, err);
+    }
+
+    audit::log(ctx, request.guild, summary).await;
+
+    Ok(())
+}
+
+/// Undoes whatever made the request so that the user can try again.
+async fn withdraw_origin(ctx: &Context, request: &Request) {
+    match &request.origin {
+        Origin::Selector { channel, message, emoji } => {
+            let reaction_type = emoji.clone().into();
+            let _ = ctx.http.delete_reaction(*channel, *message, request.user, &reaction_type).await;
+        }
+        Origin::Application => notify_applicant(ctx, request, "❌ Your application was not accepted.").await,
+    }
+}
+
+/// Lets an applicant know how their application went. Other requests are answered visibly enough already.
+async fn notify_applicant(ctx: &Context, request: &Request, content: &str) {
+    if request.origin != Origin::Application {
+        return;
+    }
+
+    let guild_name = request.guild.name(ctx).unwrap_or_default();
+    let content = format!("{} (**{}**)", content, guild_name);
+    if let Ok(dm) = request.user.create_dm_channel(&ctx.http).await {
+        let _ = dm.say(&ctx.http, content).await;
+    }
+}
+
+/// Expires requests that nobody answered in time.
+pub async fn expire_requests(ctx: Context) {
+    let cutoff = scheduler::now() - EXPIRY_SECS;
+
+    let state = store::<StateKey>(&ctx).await;
+    let expired: Vec<(MessageId, GuildId)> = state.read().await.requests.iter()
+        .filter(|(_, request)| request.created_at < cutoff)
+        .map(|(message, request)| (*message, request.guild))
+        .collect();
+
+    for (message, guild) in expired {
+        if read_only::blocks(&ctx, guild, || format!("expired role request {}", message)).await {
+            continue;
+        }
+        if let Err(err) = close_request(&ctx, message, Outcome::Expired).await {
+            error!("failed to expire role request {}: {:?}", message, err);
+        }
+    }
+}
+
+/// Forgets the requests of all guilds not in `guilds`, returning the ones that were removed.
+pub async fn retain_guilds(ctx: &Context, guilds: &HashSet<GuildId>) -> Vec<GuildId> {
+    let state = store::<StateKey>(ctx).await;
+    state.write(|state| {
+        let removed: HashSet<GuildId> = state.requests.values()
+            .map(|request| request.guild)
+            .filter(|guild| !guilds.contains(guild))
+            .collect();
+        state.requests.retain(|_, request| guilds.contains(&request.guild));
+        removed.into_iter().collect()
+    }).await
+}
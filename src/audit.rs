@@ -0,0 +1,107 @@
+use std::collections::{HashMap, HashSet};
+
+use log::error;
+use serde::{Deserialize, Serialize};
+use serenity::builder::{CreateAllowedMentions, CreateMessage};
+use serenity::model::prelude::*;
+use serenity::prelude::*;
+
+use crate::{CommandError, CommandResult, history, Persistent, scheduler, store};
+use crate::memreport::{Introspect, Owner, Reference};
+
+pub struct StateKey;
+
+impl TypeMapKey for StateKey {
+    type Value = Persistent<State>;
+}
+
+#[derive(Serialize, Deserialize, Default, Clone, Eq, PartialEq)]
+pub struct State {
+    log_channels: HashMap<GuildId, ChannelId>,
+}
+
+impl Introspect for State {
+    fn entries(&self) -> Vec<(Owner, usize)> {
+        self.log_channels.keys().map(|guild| (Owner::Guild(*guild), 1)).collect()
+    }
+
+    fn references(&self) -> Vec<(Owner, Reference)> {
+        self.log_channels.iter().map(|(guild, channel)| (Owner::Guild(*guild), Reference::Channel(*channel))).collect()
+    }
+}
+
+pub async fn set_log_channel(ctx: &Context, command: &Message, channel: Option<ChannelId>) -> CommandResult<()> {
+    let guild = command.guild_id.ok_or(CommandError::NotAllowed)?;
+
+    let state = store::<StateKey>(ctx).await;
+    state.write(|state| {
+        match channel {
+            Some(channel) => state.log_channels.insert(guild, channel),
+            None => state.log_channels.remove(&guild),
+        };
+    }).await;
+
+    Ok(())
+}
+
+pub async fn log_channel(ctx: &Context, guild: GuildId) -> Option<ChannelId> {
+    let state = store::<StateKey>(ctx).await;
+    let channel = state.read().await.log_channels.get(&guild).copied();
+    channel
+}
+
+pub async fn is_configured(ctx: &Context, guild: GuildId) -> bool {
+    log_channel(ctx, guild).await.is_some()
+}
+
+/// Posts an entry to the guild's log channel, if one is configured, and records it in the history.
+/// Mentions are never pinged.
+pub async fn log(ctx: &Context, guild: GuildId, content: impl Into<String>) {
+    log_to(ctx, guild, None, content).await
+}
+
+/// Like `log`, but posts the entry to `channel` instead of the guild's log channel when one is given,
+/// like for selectors with a log channel of their own.
+pub async fn log_to(ctx: &Context, guild: GuildId, channel: Option<ChannelId>, content: impl Into<String>) {
+    let content = content.into();
+    history::record(ctx, history::Entry {
+        at: scheduler::now(),
+        guild,
+        action: history::AUDIT_ACTION.to_owned(),
+        user: None,
+        roles: Vec::new(),
+        summary: content.clone(),
+    }).await;
+
+    let channel = match channel {
+        Some(channel) => Some(channel),
+        None => log_channel(ctx, guild).await,
+    };
+    if let Some(channel) = channel {
+        post(ctx, guild, channel, content).await;
+    }
+}
+
+/// Posts to a log channel without recording anything, for activity the history already knows about.
+pub async fn post(ctx: &Context, guild: GuildId, channel: ChannelId, content: impl Into<String>) {
+    let content = content.into();
+    let result = channel.send_message(&ctx.http, CreateMessage::new().content(content).allowed_mentions(CreateAllowedMentions::new())).await;
+
+    if let Err(err) = result {
+        error!("failed to post to log channel of {}: {:?}", guild, err);
+    }
+}
+
+/// Forgets the log channels of all guilds not in `guilds`, returning the ones that were removed.
+pub async fn retain_guilds(ctx: &Context, guilds: &HashSet<GuildId>) -> Vec<GuildId> {
+    let state = store::<StateKey>(ctx).await;
+    state.write(|state| {
+        let removed = state.log_channels.keys().filter(|guild| !guilds.contains(guild)).copied().collect();
+        state.log_channels.retain(|guild, _| guilds.contains(guild));
+        removed
+    }).await
+}
+
+pub fn message_link(guild: GuildId, channel: ChannelId, message: MessageId) -> String {
+    format!("https://discord.com/channels/{}/{}/{}", guild.get(), channel.get(), message.get())
+}
@@ -0,0 +1,227 @@
+use std::collections::{HashMap, HashSet};
+
+use log::warn;
+use serde::{Deserialize, Serialize};
+use serenity::model::prelude::*;
+use serenity::prelude::*;
+
+use crate::{CommandError, CommandResult, Persistent, store};
+use crate::memreport::{Introspect, Owner};
+
+const PIN_EMOJI: &str = "📌";
+
+/// Discord refuses to pin any more messages in a channel past this limit.
+const MAX_PINS: usize = 50;
+
+pub struct StateKey;
+
+impl TypeMapKey for StateKey {
+    type Value = Persistent<State>;
+}
+
+#[derive(Serialize, Deserialize, Default, Clone, Eq, PartialEq)]
+pub struct State {
+    guilds: HashMap<GuildId, GuildState>,
+}
+
+impl Introspect for State {
+    fn entries(&self) -> Vec<(Owner, usize)> {
+        self.guilds.iter().map(|(guild, state)| {
+            (Owner::Guild(*guild), state.channels.values().map(|channel| 1 + channel.pinned.len()).sum())
+        }).collect()
+    }
+}
+
+#[derive(Serialize, Deserialize, Default, Clone, Eq, PartialEq)]
+struct GuildState {
+    channels: HashMap<ChannelId, ChannelState>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Eq, PartialEq)]
+struct ChannelState {
+    threshold: u64,
+    /// Messages pinned by us, oldest first.
+    pinned: Vec<MessageId>,
+}
+
+impl State {
+    fn channel(&self, guild: GuildId, channel: ChannelId) -> Option<&ChannelState> {
+        self.guilds.get(&guild).and_then(|guild| guild.channels.get(&channel))
+    }
+
+    fn channel_mut(&mut self, guild: GuildId, channel: ChannelId) -> Option<&mut ChannelState> {
+        self.guilds.get_mut(&guild).and_then(|guild| guild.channels.get_mut(&channel))
+    }
+}
+
+pub async fn set_threshold(ctx: &Context, command: &Message, threshold: Option<u64>) -> CommandResult<()> {
+    let guild = command.guild_id.ok_or(CommandError::NotAllowed)?;
+    set_channel_threshold(ctx, guild, command.channel_id, threshold).await;
+    Ok(())
+}
+
+pub async fn set_channel_threshold(ctx: &Context, guild: GuildId, channel: ChannelId, threshold: Option<u64>) {
+    let state = store::<StateKey>(ctx).await;
+    state.write(|state| {
+        let guild_state = state.guilds.entry(guild).or_default();
+        match threshold {
+            Some(threshold) => {
+                let channel = guild_state.channels.entry(channel)
+                    .or_insert_with(|| ChannelState { threshold, pinned: Vec::new() });
+                channel.threshold = threshold;
+            }
+            None => {
+                guild_state.channels.remove(&channel);
+                if guild_state.channels.is_empty() {
+                    state.guilds.remove(&guild);
+                }
+            }
+        }
+    }).await;
+}
+
+pub async fn thresholds(ctx: &Context, guild: GuildId) -> Vec<(ChannelId, u64)> {
+    let state = store::<StateKey>(ctx).await;
+    let thresholds = state.read().await.guilds.get(&guild)
+        .map(|guild| guild.channels.iter().map(|(channel, state)| (*channel, state.threshold)).collect())
+        .unwrap_or_default();
+    thresholds
+}
+
+pub async fn is_configured(ctx: &Context, guild: GuildId) -> bool {
+    !thresholds(ctx, guild).await.is_empty()
+}
+
+async fn threshold(ctx: &Context, guild: GuildId, channel: ChannelId) -> Option<u64> {
+    let state = store::<StateKey>(ctx).await;
+    let threshold = state.read().await.channel(guild, channel).map(|channel| channel.threshold);
+    threshold
+}
+
+fn is_pin_reaction(reaction: &Reaction) -> bool {
+    matches!(&reaction.emoji, ReactionType::Unicode(emoji) if emoji == PIN_EMOJI)
+}
+
+fn pin_count(message: &Message) -> u64 {
+    message.reactions.iter()
+        .find(|reaction| matches!(&reaction.reaction_type, ReactionType::Unicode(emoji) if emoji == PIN_EMOJI))
+        .map(|reaction| reaction.count)
+        .unwrap_or(0)
+}
+
+pub async fn add_reaction(ctx: &Context, reaction: &Reaction) -> serenity::Result<()> {
+    let guild = match reaction.guild_id {
+        Some(guild) if is_pin_reaction(reaction) => guild,
+        _ => return Ok(()),
+    };
+
+    let threshold = match threshold(ctx, guild, reaction.channel_id).await {
+        Some(threshold) => threshold,
+        None => return Ok(()),
+    };
+
+    let message = reaction.channel_id.message(&ctx.http, reaction.message_id).await?;
+    if message.pinned || pin_count(&message) < threshold {
+        return Ok(());
+    }
+
+    let pins = reaction.channel_id.pins(&ctx.http).await?;
+    if pins.len() >= MAX_PINS && !evict_oldest_pin(ctx, guild, reaction.channel_id).await? {
+        warn!("cannot autopin {} in {}: channel is at the pin limit", message.id, message.channel_id);
+        return Ok(());
+    }
+
+    message.pin(&ctx.http).await?;
+
+    let state = store::<StateKey>(ctx).await;
+    state.write(|state| {
+        if let Some(channel) = state.channel_mut(guild, reaction.channel_id) {
+            channel.pinned.push(message.id);
+        }
+    }).await;
+
+    Ok(())
+}
+
+pub async fn remove_reaction(ctx: &Context, reaction: &Reaction) -> serenity::Result<()> {
+    let guild = match reaction.guild_id {
+        Some(guild) if is_pin_reaction(reaction) => guild,
+        _ => return Ok(()),
+    };
+
+    let (threshold, pinned_by_us) = {
+        let state = store::<StateKey>(ctx).await;
+        let state = state.read().await;
+        match state.channel(guild, reaction.channel_id) {
+            Some(channel) => (channel.threshold, channel.pinned.contains(&reaction.message_id)),
+            None => return Ok(()),
+        }
+    };
+
+    // only ever unpin messages that we pinned ourselves
+    if !pinned_by_us {
+        return Ok(());
+    }
+
+    let message = reaction.channel_id.message(&ctx.http, reaction.message_id).await?;
+    if pin_count(&message) >= threshold {
+        return Ok(());
+    }
+
+    if message.pinned {
+        message.unpin(&ctx.http).await?;
+    }
+
+    forget_pin(ctx, guild, reaction.channel_id, message.id).await;
+
+    Ok(())
+}
+
+/// Unpins the oldest message we pinned in this channel to make room, returning whether one was found.
+async fn evict_oldest_pin(ctx: &Context, guild: GuildId, channel: ChannelId) -> serenity::Result<bool> {
+    let oldest = {
+        let state = store::<StateKey>(ctx).await;
+        let oldest = state.read().await.channel(guild, channel).and_then(|channel| channel.pinned.first().copied());
+        oldest
+    };
+
+    match oldest {
+        Some(oldest) => {
+            ctx.http.unpin_message(channel, oldest, None).await?;
+            forget_pin(ctx, guild, channel, oldest).await;
+            Ok(true)
+        }
+        None => Ok(false),
+    }
+}
+
+async fn forget_pin(ctx: &Context, guild: GuildId, channel: ChannelId, message: MessageId) {
+    let state = store::<StateKey>(ctx).await;
+    state.write(|state| {
+        if let Some(channel) = state.channel_mut(guild, channel) {
+            channel.pinned.retain(|pinned| *pinned != message);
+        }
+    }).await;
+}
+
+pub async fn delete_message(ctx: &Context, guild: GuildId, channel: ChannelId, message: MessageId) {
+    let pinned_by_us = {
+        let state = store::<StateKey>(ctx).await;
+        let pinned_by_us = state.read().await.channel(guild, channel).is_some_and(|channel| channel.pinned.contains(&message));
+        pinned_by_us
+    };
+
+    if pinned_by_us {
+        forget_pin(ctx, guild, channel, message).await;
+    }
+}
+
+/// Forgets all guilds not in `guilds`, returning the ones that were removed.
+pub async fn retain_guilds(ctx: &Context, guilds: &HashSet<GuildId>) -> Vec<GuildId> {
+    let state = store::<StateKey>(ctx).await;
+    state.write(|state| {
+        let removed = state.guilds.keys().filter(|guild| !guilds.contains(guild)).copied().collect();
+        state.guilds.retain(|guild, _| guilds.contains(guild));
+        removed
+    }).await
+}
@@ -0,0 +1,174 @@
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+
+use log::error;
+use serde::{Deserialize, Serialize};
+use serenity::builder::CreateMessage;
+use serenity::model::prelude::*;
+use serenity::prelude::*;
+
+use crate::{audit, CommandError, CommandResult, dormancy, guild_setup, Persistent, say_lines, scheduler, store};
+use crate::memreport::{Introspect, Owner, Reference};
+
+/// How often guilds are looked at for a backup being due.
+pub const PERIOD: Duration = Duration::from_secs(60 * 60);
+
+/// How long a guild waits between backups.
+pub const INTERVAL_SECS: i64 = 7 * 24 * 60 * 60;
+
+pub struct StateKey;
+
+impl TypeMapKey for StateKey {
+    type Value = Persistent<State>;
+}
+
+/// Guilds that get their setup posted to a staff channel every week, as a backup that doesn't
+/// depend on the bot's host.
+#[derive(Serialize, Deserialize, Default, Clone, Eq, PartialEq)]
+pub struct State {
+    guilds: HashMap<GuildId, Backup>,
+}
+
+impl Introspect for State {
+    fn entries(&self) -> Vec<(Owner, usize)> {
+        self.guilds.keys().map(|guild| (Owner::Guild(*guild), 1)).collect()
+    }
+
+    fn references(&self) -> Vec<(Owner, Reference)> {
+        self.guilds.iter().map(|(guild, backup)| (Owner::Guild(*guild), Reference::Channel(backup.channel))).collect()
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Backup {
+    pub channel: ChannelId,
+    #[serde(default)]
+    pub last_posted_at: Option<i64>,
+}
+
+impl Backup {
+    pub fn is_due(&self, now: i64) -> bool {
+        self.last_posted_at.is_none_or(|last_posted_at| now - last_posted_at >= INTERVAL_SECS)
+    }
+}
+
+/// Whether everyone in a guild can see a channel, going by the cache. Channels that aren't cached
+/// are taken to be private.
+fn is_public(ctx: &Context, guild: GuildId, channel: ChannelId) -> bool {
+    ctx.cache.guild(guild).is_some_and(|guild| {
+        let everyone = guild.roles.get(&RoleId::new(guild.id.get()));
+        match (guild.channels.get(&channel), everyone) {
+            (Some(channel), Some(everyone)) => {
+                let mut permissions = everyone.permissions;
+                for overwrite in &channel.permission_overwrites {
+                    if overwrite.kind == PermissionOverwriteType::Role(everyone.id) {
+                        permissions = (permissions - overwrite.deny) | overwrite.allow;
+                    }
+                }
+                permissions.administrator() || permissions.view_channel()
+            }
+            _ => false,
+        }
+    })
+}
+
+pub async fn set_channel(ctx: &Context, command: &Message, channel: Option<ChannelId>) -> CommandResult<()> {
+    let guild = command.guild_id.ok_or(CommandError::NotAllowed)?;
+    if let Some(channel) = channel {
+        if is_public(ctx, guild, channel) {
+            return Err(CommandError::PublicChannel);
+        }
+    }
+
+    let state = store::<StateKey>(ctx).await;
+    state.write(|state| {
+        match channel {
+            Some(channel) => {
+                let last_posted_at = state.guilds.get(&guild).and_then(|backup| backup.last_posted_at);
+                state.guilds.insert(guild, Backup { channel, last_posted_at })
+            }
+            None => state.guilds.remove(&guild),
+        };
+    }).await;
+
+    Ok(())
+}
+
+pub async fn status(ctx: &Context, command: &Message) -> CommandResult<()> {
+    let guild = command.guild_id.ok_or(CommandError::NotAllowed)?;
+
+    let backup = store::<StateKey>(ctx).await.read().await.guilds.get(&guild).copied();
+    let lines = match backup {
+        Some(backup) => {
+            let last = match backup.last_posted_at {
+                Some(at) => format!("last posted <t:{}:R>", at),
+                None => "not posted yet".to_owned(),
+            };
+            vec![format!("💾 A backup of the selectors and persisted roles is posted to <#{}> every week ({}).", backup.channel.get(), last)]
+        }
+        None => vec!["Backups are off. Post them weekly to a private channel with `backup channel <channel>`.".to_owned()],
+    };
+    say_lines(ctx, command.channel_id, &lines).await?;
+
+    Ok(())
+}
+
+/// Posts a backup straight away, without changing when the next one is due.
+pub async fn post_now(ctx: &Context, command: &Message) -> CommandResult<()> {
+    let guild = command.guild_id.ok_or(CommandError::NotAllowed)?;
+    let backup = store::<StateKey>(ctx).await.read().await.guilds.get(&guild).copied();
+    let backup = backup.ok_or(CommandError::BackupsOff)?;
+
+    post(ctx, guild, backup.channel).await?;
+    Ok(())
+}
+
+/// Posts the backup of every guild that is due.
+pub async fn run(ctx: Context) {
+    let now = scheduler::now();
+    let due: Vec<(GuildId, Backup)> = store::<StateKey>(&ctx).await.read().await.guilds.iter()
+        .filter(|(_, backup)| backup.is_due(now))
+        .map(|(guild, backup)| (*guild, *backup))
+        .collect();
+
+    for (guild, backup) in due {
+        dormancy::wake(&ctx, guild).await;
+        // a channel that became visible to everyone would hand the setup to anyone
+        let result = if is_public(&ctx, guild, backup.channel) {
+            audit::log(&ctx, guild, format!("⚠ Skipped the weekly backup, since everyone can see <#{}>.", backup.channel.get())).await;
+            Ok(())
+        } else {
+            post(&ctx, guild, backup.channel).await
+        };
+        if let Err(err) = result {
+            error!("failed to post backup of {}: {:?}", guild, err);
+            audit::log(&ctx, guild, format!("⚠ Couldn't post the weekly backup to <#{}>: {}", backup.channel.get(), err)).await;
+        }
+
+        let state = store::<StateKey>(&ctx).await;
+        state.write(|state| {
+            if let Some(backup) = state.guilds.get_mut(&guild) {
+                backup.last_posted_at = Some(now);
+            }
+        }).await;
+    }
+}
+
+async fn post(ctx: &Context, guild: GuildId, channel: ChannelId) -> serenity::Result<()> {
+    let setup = guild_setup::capture(ctx, guild).await?;
+    let date = chrono::Utc::now().format("%Y-%m-%d");
+    let attachment = setup.to_attachment(&format!("guild-setup-{}.json", date));
+    let content = format!("💾 Backup of {}. Attach it to `import guild-setup` to restore it.", setup.summary());
+    channel.send_files(&ctx.http, vec![attachment], CreateMessage::new().content(content)).await?;
+    Ok(())
+}
+
+/// Forgets all guilds not in `guilds`, returning the ones that were removed.
+pub async fn retain_guilds(ctx: &Context, guilds: &HashSet<GuildId>) -> Vec<GuildId> {
+    let state = store::<StateKey>(ctx).await;
+    state.write(|state| {
+        let removed = state.guilds.keys().filter(|guild| !guilds.contains(guild)).copied().collect();
+        state.guilds.retain(|guild, _| guilds.contains(guild));
+        removed
+    }).await
+}
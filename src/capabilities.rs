@@ -0,0 +1,60 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use log::{error, info, warn};
+use serenity::http::Http;
+use serenity::model::prelude::*;
+
+/// Whether the bot receives the privileged members intent, which it assumes until told otherwise.
+static MEMBERS_INTENT: AtomicBool = AtomicBool::new(true);
+
+/// What works differently when the bot runs without the members intent.
+pub const REDUCED_MODE: &[&str] = &[
+    "persisted roles are given back when Discord posts a join message, so only in servers with join messages on",
+    "persisted roles follow role changes only when members send messages or react",
+    "newly persisted roles start out empty instead of with everyone who has them",
+    "role caps can't be set, since their holders can't be counted",
+    "selector sweeps are skipped",
+    "membership screening and role snapshots don't run",
+];
+
+pub fn has_members_intent() -> bool {
+    MEMBERS_INTENT.load(Ordering::Relaxed)
+}
+
+pub fn set_members_intent(members_intent: bool) {
+    MEMBERS_INTENT.store(members_intent, Ordering::Relaxed);
+}
+
+/// Whether an application's flags on the developer portal let it receive the members intent.
+pub fn members_intent_granted(flags: ApplicationFlags) -> bool {
+    flags.intersects(ApplicationFlags::GATEWAY_GUILD_MEMBERS | ApplicationFlags::GATEWAY_GUILD_MEMBERS_LIMITED)
+}
+
+/// Works out whether to ask for the members intent. `configured` decides if set, and otherwise the
+/// application's flags do, assuming the intent is granted when they can't be fetched.
+pub async fn detect(http: &Http, configured: Option<bool>) -> bool {
+    if let Some(members_intent) = configured {
+        return members_intent;
+    }
+
+    match http.get_current_application_info().await {
+        Ok(application) => application.flags.is_none_or(members_intent_granted),
+        Err(err) => {
+            error!("failed to fetch application flags, assuming the members intent is granted: {:?}", err);
+            true
+        }
+    }
+}
+
+/// Says which mode the bot connected in, listing what doesn't work without the members intent.
+pub fn ready() {
+    if has_members_intent() {
+        info!("connected with the members intent");
+        return;
+    }
+
+    warn!("connected without the members intent, so:");
+    for limitation in REDUCED_MODE {
+        warn!(" - {}", limitation);
+    }
+}
@@ -0,0 +1,225 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+use serenity::model::prelude::*;
+use serenity::prelude::*;
+
+use crate::{audit, CommandError, CommandResult, Persistent, say_lines, scheduler, store};
+use crate::memreport::{Introspect, Owner};
+
+/// How many cases `cases` lists before summarizing the rest.
+const MAX_LISTED: usize = 25;
+
+pub struct StateKey;
+
+impl TypeMapKey for StateKey {
+    type Value = Persistent<State>;
+}
+
+/// Every moderation action taken in each guild, numbered in the order they were taken.
+#[derive(Serialize, Deserialize, Default, Clone, Eq, PartialEq)]
+pub struct State {
+    guilds: HashMap<GuildId, GuildCases>,
+}
+
+impl Introspect for State {
+    fn entries(&self) -> Vec<(Owner, usize)> {
+        self.guilds.iter().map(|(guild, cases)| (Owner::Guild(*guild), cases.cases.len())).collect()
+    }
+}
+
+#[derive(Serialize, Deserialize, Default, Clone, Eq, PartialEq)]
+struct GuildCases {
+    /// The ID of the latest case, which keeps counting up even if cases are removed.
+    last_id: u64,
+    cases: Vec<Case>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq)]
+pub struct Case {
+    pub id: u64,
+    pub kind: CaseKind,
+    pub user: UserId,
+    /// Who took the action, or `None` if the bot did so by itself.
+    pub moderator: Option<UserId>,
+    pub reason: Option<String>,
+    pub at: i64,
+    #[serde(default)]
+    pub edited_by: Option<UserId>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum CaseKind {
+    Warn,
+    Kick,
+    SelectorBan,
+    SelectorUnban,
+    /// A member was warned for pinging a role during its cooldown.
+    PingWarning,
+}
+
+impl fmt::Display for CaseKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            CaseKind::Warn => "Warning",
+            CaseKind::Kick => "Kick",
+            CaseKind::SelectorBan => "Selector ban",
+            CaseKind::SelectorUnban => "Selector unban",
+            CaseKind::PingWarning => "Ping cooldown warning",
+        })
+    }
+}
+
+impl Case {
+    pub fn describe(&self) -> Vec<String> {
+        let moderator = match self.moderator {
+            Some(moderator) => format!("<@{}>", moderator.get()),
+            None => "automatic".to_owned(),
+        };
+        let mut lines = vec![
+            format!("**Case #{}**: {}", self.id, self.kind),
+            format!("User: <@{}> ({})", self.user.get(), self.user.get()),
+            format!("Moderator: {}", moderator),
+            format!("Reason: {}", self.reason.as_deref().unwrap_or("none given")),
+            format!("At: <t:{}:f>", self.at),
+        ];
+        if let Some(editor) = self.edited_by {
+            lines.push(format!("Reason last edited by <@{}>", editor.get()));
+        }
+        lines
+    }
+
+    fn summary(&self) -> String {
+        let reason = self.reason.as_deref().map(|reason| format!(": {}", reason)).unwrap_or_default();
+        format!("#{} {} <t:{}:R>{}", self.id, self.kind, self.at, reason)
+    }
+}
+
+impl State {
+    /// Records a new case, returning its ID.
+    pub fn open_case(&mut self, guild: GuildId, kind: CaseKind, user: UserId, moderator: Option<UserId>, reason: Option<String>, at: i64) -> u64 {
+        let cases = self.guilds.entry(guild).or_default();
+        cases.last_id += 1;
+        let id = cases.last_id;
+        cases.cases.push(Case { id, kind, user, moderator, reason, at, edited_by: None });
+        id
+    }
+
+    pub fn case(&self, guild: GuildId, id: u64) -> Option<&Case> {
+        self.guilds.get(&guild)?.cases.iter().find(|case| case.id == id)
+    }
+
+    pub fn cases_of(&self, guild: GuildId, user: UserId) -> Vec<&Case> {
+        match self.guilds.get(&guild) {
+            Some(cases) => cases.cases.iter().filter(|case| case.user == user).collect(),
+            None => Vec::new(),
+        }
+    }
+
+    pub fn set_reason(&mut self, guild: GuildId, id: u64, reason: String, editor: UserId) -> bool {
+        let case = self.guilds.get_mut(&guild).and_then(|cases| cases.cases.iter_mut().find(|case| case.id == id));
+        match case {
+            Some(case) => {
+                case.reason = Some(reason);
+                case.edited_by = Some(editor);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// Records a moderation action, returning the ID of its case to mention in the audit log.
+pub async fn open(ctx: &Context, guild: GuildId, kind: CaseKind, user: UserId, moderator: Option<UserId>, reason: Option<&str>) -> u64 {
+    let now = scheduler::now();
+    let state = store::<StateKey>(ctx).await;
+    state.write(|state| state.open_case(guild, kind, user, moderator, reason.map(str::to_owned), now)).await
+}
+
+/// Warns a member by DM and records it as a case.
+pub async fn warn(ctx: &Context, command: &Message, user: UserId, reason: &str) -> CommandResult<()> {
+    let guild = command.guild_id.ok_or(CommandError::NotAllowed)?;
+
+    let id = open(ctx, guild, CaseKind::Warn, user, Some(command.author.id), Some(reason)).await;
+
+    let guild_name = guild.name(&ctx.cache).unwrap_or_else(|| "the server".to_owned());
+    let content = format!("⚠️ You were warned in **{}**: {}", guild_name, reason);
+    let delivered = match user.create_dm_channel(&ctx.http).await {
+        Ok(dm) => dm.say(&ctx.http, content).await.is_ok(),
+        Err(_) => false,
+    };
+
+    audit::log(ctx, guild, format!("⚠️ <@{}> warned <@{}> (case #{}): {}", command.author.id.get(), user.get(), id, reason)).await;
+
+    let reply = if delivered {
+        format!("⚠️ Warned <@{}> (case #{}).", user.get(), id)
+    } else {
+        format!("⚠️ Recorded the warning for <@{}> as case #{}, but I couldn't DM them.", user.get(), id)
+    };
+    say_lines(ctx, command.channel_id, &[reply]).await?;
+
+    Ok(())
+}
+
+pub async fn show(ctx: &Context, command: &Message, id: u64) -> CommandResult<()> {
+    let guild = command.guild_id.ok_or(CommandError::NotAllowed)?;
+
+    let lines = {
+        let state = store::<StateKey>(ctx).await;
+        let state = state.read().await;
+        state.case(guild, id).map(Case::describe).ok_or(CommandError::UnknownCase)?
+    };
+    say_lines(ctx, command.channel_id, &lines).await?;
+
+    Ok(())
+}
+
+pub async fn edit_reason(ctx: &Context, command: &Message, id: u64, reason: String) -> CommandResult<()> {
+    let guild = command.guild_id.ok_or(CommandError::NotAllowed)?;
+
+    let state = store::<StateKey>(ctx).await;
+    let log = format!("✏️ <@{}> changed the reason of case #{} to: {}", command.author.id.get(), id, reason);
+    if !state.write(|state| state.set_reason(guild, id, reason, command.author.id)).await {
+        return Err(CommandError::UnknownCase);
+    }
+
+    audit::log(ctx, guild, log).await;
+    Ok(())
+}
+
+pub async fn list(ctx: &Context, command: &Message, user: UserId) -> CommandResult<()> {
+    let guild = command.guild_id.ok_or(CommandError::NotAllowed)?;
+
+    let summaries: Vec<String> = {
+        let state = store::<StateKey>(ctx).await;
+        let state = state.read().await;
+        state.cases_of(guild, user).iter().rev().map(|case| case.summary()).collect()
+    };
+
+    if summaries.is_empty() {
+        say_lines(ctx, command.channel_id, &[format!("<@{}> has no cases.", user.get())]).await?;
+        return Ok(());
+    }
+
+    let mut lines = vec![format!("**Cases of <@{}>**, newest first", user.get())];
+    let total = summaries.len();
+    lines.extend(summaries.into_iter().take(MAX_LISTED));
+    if total > MAX_LISTED {
+        lines.push(format!("…and {} older cases", total - MAX_LISTED));
+    }
+    say_lines(ctx, command.channel_id, &lines).await?;
+
+    Ok(())
+}
+
+/// Forgets all guilds not in `guilds`, returning the ones that were removed.
+pub async fn retain_guilds(ctx: &Context, guilds: &HashSet<GuildId>) -> Vec<GuildId> {
+    let state = store::<StateKey>(ctx).await;
+    state.write(|state| {
+        let removed = state.guilds.keys().filter(|guild| !guilds.contains(guild)).copied().collect();
+        state.guilds.retain(|guild, _| guilds.contains(guild));
+        removed
+    }).await
+}
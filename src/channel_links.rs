@@ -0,0 +1,246 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::time::Duration;
+
+use log::error;
+use serde::{Deserialize, Serialize};
+use serenity::builder::{CreateAllowedMentions, CreateMessage};
+use serenity::model::prelude::*;
+use serenity::prelude::*;
+
+use crate::{audit, CommandError, CommandResult, Persistent, read_only, role_admin, store};
+use crate::memreport::{Introspect, Owner, Reference};
+
+/// How often linked channels are checked for overwrites that were changed by hand.
+pub const VERIFY_PERIOD: Duration = Duration::from_secs(30 * 60);
+
+pub struct StateKey;
+
+impl TypeMapKey for StateKey {
+    type Value = Persistent<State>;
+}
+
+#[derive(Serialize, Deserialize, Default, Clone, Eq, PartialEq)]
+pub struct State {
+    guilds: HashMap<GuildId, HashMap<ChannelId, BTreeSet<RoleId>>>,
+}
+
+impl Introspect for State {
+    fn entries(&self) -> Vec<(Owner, usize)> {
+        self.guilds.iter().map(|(guild, channels)| (Owner::Guild(*guild), channels.values().map(BTreeSet::len).sum())).collect()
+    }
+
+    fn references(&self) -> Vec<(Owner, Reference)> {
+        self.guilds.iter().flat_map(|(guild, channels)| {
+            channels.iter().flat_map(move |(channel, roles)| {
+                let roles = roles.iter().map(|role| Reference::Role(*role));
+                std::iter::once(Reference::Channel(*channel)).chain(roles).map(move |reference| (Owner::Guild(*guild), reference))
+            })
+        }).collect()
+    }
+}
+
+/// Returns the overwrites that must be created or replaced so that only holders of `roles` (and the
+/// bot itself) can see a channel. Permissions other than viewing the channel are left as they are.
+pub fn missing_overwrites(existing: &[PermissionOverwrite], everyone: RoleId, roles: &BTreeSet<RoleId>, bot: UserId) -> Vec<PermissionOverwrite> {
+    let mut wanted = vec![(PermissionOverwriteType::Role(everyone), false), (PermissionOverwriteType::Member(bot), true)];
+    wanted.extend(roles.iter().map(|role| (PermissionOverwriteType::Role(*role), true)));
+
+    wanted.into_iter().filter_map(|(kind, visible)| {
+        let current = existing.iter().find(|overwrite| overwrite.kind == kind);
+        let (allow, deny) = current.map(|overwrite| (overwrite.allow, overwrite.deny))
+            .unwrap_or((Permissions::empty(), Permissions::empty()));
+
+        let (new_allow, new_deny) = if visible {
+            (allow | Permissions::VIEW_CHANNEL, deny - Permissions::VIEW_CHANNEL)
+        } else {
+            (allow - Permissions::VIEW_CHANNEL, deny | Permissions::VIEW_CHANNEL)
+        };
+
+        if current.is_some() && (new_allow, new_deny) == (allow, deny) {
+            None
+        } else {
+            Some(PermissionOverwrite { allow: new_allow, deny: new_deny, kind })
+        }
+    }).collect()
+}
+
+/// Links a role to a channel so that only its holders can see it, applying the overwrites right away.
+pub async fn link(ctx: &Context, guild: GuildId, role: RoleId, channel: ChannelId) -> CommandResult<()> {
+    let state = store::<StateKey>(ctx).await;
+    let roles = state.write(|state| {
+        let roles = state.guilds.entry(guild).or_default().entry(channel).or_default();
+        roles.insert(role);
+        roles.clone()
+    }).await;
+
+    apply(ctx, guild, channel, &roles).await?;
+    Ok(())
+}
+
+pub async fn link_command(ctx: &Context, command: &Message, role: RoleId, channel: ChannelId) -> CommandResult<()> {
+    let guild = command.guild_id.ok_or(CommandError::NotAllowed)?;
+    role_admin::check_hierarchy(ctx, guild, command.author.id, role).await?;
+    check_channel(ctx, guild, channel).await?;
+
+    link(ctx, guild, role, channel).await?;
+
+    command.channel_id.send_message(&ctx.http, CreateMessage::new()
+        .content(format!("🔗 Only members with <@&{}> can now see <#{}>.", role, channel))
+        .allowed_mentions(CreateAllowedMentions::new())
+    ).await?;
+
+    Ok(())
+}
+
+/// Unlinks a role from a channel, taking away the access it granted. The channel stays hidden from
+/// everyone else.
+pub async fn unlink_command(ctx: &Context, command: &Message, role: RoleId, channel: ChannelId) -> CommandResult<()> {
+    let guild = command.guild_id.ok_or(CommandError::NotAllowed)?;
+
+    let state = store::<StateKey>(ctx).await;
+    let removed = state.write(|state| {
+        let channels = match state.guilds.get_mut(&guild) {
+            Some(channels) => channels,
+            None => return false,
+        };
+        let removed = channels.get_mut(&channel).map(|roles| roles.remove(&role)).unwrap_or(false);
+        channels.retain(|_, roles| !roles.is_empty());
+        if channels.is_empty() {
+            state.guilds.remove(&guild);
+        }
+        removed
+    }).await;
+
+    if !removed {
+        return Err(CommandError::UnknownChannelLink);
+    }
+
+    if let Some(guild_channel) = crate::cached_channel(&ctx.cache, channel) {
+        let kind = PermissionOverwriteType::Role(role);
+        if let Some(overwrite) = guild_channel.permission_overwrites.iter().find(|overwrite| overwrite.kind == kind) {
+            let allow = overwrite.allow - Permissions::VIEW_CHANNEL;
+            if allow.is_empty() && overwrite.deny.is_empty() {
+                channel.delete_permission(&ctx.http, kind).await?;
+            } else {
+                channel.create_permission(&ctx.http, PermissionOverwrite { allow, deny: overwrite.deny, kind }).await?;
+            }
+        }
+    }
+
+    command.channel_id.send_message(&ctx.http, CreateMessage::new()
+        .content(format!("Unlinked <@&{}> from <#{}>. The channel is still hidden from everyone else.", role, channel))
+        .allowed_mentions(CreateAllowedMentions::new())
+    ).await?;
+
+    Ok(())
+}
+
+pub async fn list(ctx: &Context, command: &Message) -> CommandResult<()> {
+    let guild = command.guild_id.ok_or(CommandError::NotAllowed)?;
+
+    let channels = {
+        let state = store::<StateKey>(ctx).await;
+        let state = state.read().await;
+        state.guilds.get(&guild).cloned().unwrap_or_default()
+    };
+
+    let content = if channels.is_empty() {
+        "No channels are linked to roles.".to_owned()
+    } else {
+        let mut lines: Vec<String> = channels.iter().map(|(channel, roles)| {
+            let roles: Vec<String> = roles.iter().map(|role| format!("<@&{}>", role)).collect();
+            format!("<#{}>: {}", channel, roles.join(", "))
+        }).collect();
+        lines.sort();
+        lines.join("\n")
+    };
+
+    command.channel_id.send_message(&ctx.http, CreateMessage::new().content(content).allowed_mentions(CreateAllowedMentions::new())).await?;
+
+    Ok(())
+}
+
+async fn check_channel(ctx: &Context, guild: GuildId, channel: ChannelId) -> CommandResult<()> {
+    match crate::cached_channel(&ctx.cache, channel) {
+        Some(found) if found.guild_id == guild => Ok(()),
+        _ => Err(CommandError::MalformedArgument(format!("<#{}>", channel))),
+    }
+}
+
+/// Brings the overwrites of a linked channel in line with its roles, returning whether anything changed.
+async fn apply(ctx: &Context, guild: GuildId, channel: ChannelId, roles: &BTreeSet<RoleId>) -> serenity::Result<bool> {
+    let existing = match crate::cached_channel(&ctx.cache, channel) {
+        Some(channel) => channel.permission_overwrites,
+        None => return Ok(false),
+    };
+
+    let bot = ctx.cache.current_user().id;
+    let missing = missing_overwrites(&existing, RoleId::new(guild.get()), roles, bot);
+    for overwrite in &missing {
+        channel.create_permission(&ctx.http, overwrite.clone()).await?;
+    }
+
+    Ok(!missing.is_empty())
+}
+
+/// Restores overwrites of linked channels that were changed by hand, and forgets links to deleted
+/// channels and roles.
+pub async fn verify(ctx: Context) {
+    let guilds = {
+        let state = store::<StateKey>(&ctx).await;
+        let state = state.read().await;
+        state.guilds.clone()
+    };
+
+    for (guild, channels) in guilds {
+        // without the guild in the cache we can't tell deleted channels and roles apart from missing ones
+        let roles = match ctx.cache.guild(guild).map(|guild| guild.roles.keys().copied().collect::<HashSet<RoleId>>()) {
+            Some(roles) => roles,
+            None => continue,
+        };
+
+        for (channel, linked) in channels {
+            let existing: BTreeSet<RoleId> = linked.iter().filter(|role| roles.contains(role)).copied().collect();
+            let channel_exists = crate::cached_channel(&ctx.cache, channel).is_some();
+
+            if !channel_exists || existing.len() != linked.len() {
+                let state = store::<StateKey>(&ctx).await;
+                state.write(|state| {
+                    if let Some(channels) = state.guilds.get_mut(&guild) {
+                        match channels.get_mut(&channel) {
+                            Some(roles) if channel_exists => roles.retain(|role| existing.contains(role)),
+                            _ => {
+                                channels.remove(&channel);
+                            }
+                        }
+                        channels.retain(|_, roles| !roles.is_empty());
+                        if channels.is_empty() {
+                            state.guilds.remove(&guild);
+                        }
+                    }
+                }).await;
+            }
+
+            // overwrites are only compared while restoring them, so nothing is logged when read-only
+            if !channel_exists || existing.is_empty() || read_only::is_active(&ctx, guild).await {
+                continue;
+            }
+
+            match apply(&ctx, guild, channel, &existing).await {
+                Ok(true) => audit::log(&ctx, guild, format!("🔗 Restored the permissions of <#{}> for its linked roles.", channel)).await,
+                Ok(false) => {}
+                Err(err) => error!("failed to verify linked channel {} in {}: {:?}", channel, guild, err),
+            }
+        }
+    }
+}
+
+/// Forgets the links of all guilds not in `guilds`, returning the ones that were removed.
+pub async fn retain_guilds(ctx: &Context, guilds: &HashSet<GuildId>) -> Vec<GuildId> {
+    let state = store::<StateKey>(ctx).await;
+    state.write(|state| {
+        let removed = state.guilds.keys().filter(|guild| !guilds.contains(guild)).copied().collect();
+        state.guilds.retain(|guild, _| guilds.contains(guild));
+        removed
+    }).await
+}
@@ -0,0 +1,157 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use serde::{Deserialize, Serialize};
+use serenity::model::prelude::*;
+use serenity::prelude::*;
+
+use crate::{CommandError, CommandResult, Persistent, rate_limit, say_lines, scheduler, store};
+use crate::memreport::{Introspect, Owner};
+
+/// How many uses are remembered per guild, dropping the oldest first.
+const MAX_RECORDED: usize = 500;
+
+/// How many commands and users `command stats` lists.
+const MAX_LISTED: usize = 10;
+
+/// Command names are made of at most this many words, so that arguments made of plain words
+/// don't split a command into many.
+const MAX_NAME_WORDS: usize = 3;
+
+pub struct StateKey;
+
+impl TypeMapKey for StateKey {
+    type Value = Persistent<State>;
+}
+
+/// Who used which command when, in each guild.
+#[derive(Serialize, Deserialize, Default, Clone, Eq, PartialEq)]
+pub struct State {
+    guilds: HashMap<GuildId, VecDeque<Usage>>,
+}
+
+impl Introspect for State {
+    fn entries(&self) -> Vec<(Owner, usize)> {
+        self.guilds.iter().map(|(guild, usages)| (Owner::Guild(*guild), usages.len())).collect()
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq)]
+pub struct Usage {
+    pub user: UserId,
+    pub command: String,
+    pub at: i64,
+    /// Whether the command was refused because the user used it too often.
+    #[serde(default)]
+    pub limited: bool,
+}
+
+/// How a guild's commands have been used, with the most used first.
+#[derive(Debug, Eq, PartialEq)]
+pub struct Stats {
+    pub total: usize,
+    pub commands: Vec<(String, usize)>,
+    pub users: Vec<(UserId, usize)>,
+    pub limited: usize,
+}
+
+impl State {
+    pub fn record(&mut self, guild: GuildId, usage: Usage) {
+        let usages = self.guilds.entry(guild).or_default();
+        usages.push_back(usage);
+        while usages.len() > MAX_RECORDED {
+            usages.pop_front();
+        }
+    }
+
+    pub fn stats(&self, guild: GuildId) -> Stats {
+        let usages = match self.guilds.get(&guild) {
+            Some(usages) => usages,
+            None => return Stats { total: 0, commands: Vec::new(), users: Vec::new(), limited: 0 },
+        };
+
+        let mut commands: HashMap<&str, usize> = HashMap::new();
+        let mut users: HashMap<UserId, usize> = HashMap::new();
+        for usage in usages.iter().filter(|usage| !usage.limited) {
+            *commands.entry(&usage.command).or_default() += 1;
+            *users.entry(usage.user).or_default() += 1;
+        }
+
+        let mut commands: Vec<(String, usize)> = commands.into_iter().map(|(command, count)| (command.to_owned(), count)).collect();
+        commands.sort_by(|(a_command, a), (b_command, b)| b.cmp(a).then_with(|| a_command.cmp(b_command)));
+        let mut users: Vec<(UserId, usize)> = users.into_iter().collect();
+        users.sort_by(|(a_user, a), (b_user, b)| b.cmp(a).then_with(|| a_user.cmp(b_user)));
+
+        Stats {
+            total: usages.len(),
+            commands,
+            users,
+            limited: usages.iter().filter(|usage| usage.limited).count(),
+        }
+    }
+}
+
+/// Names a command by its leading words, leaving out arguments like mentions, IDs and emoji.
+pub fn command_name(tokens: &[&str]) -> String {
+    let words: Vec<String> = tokens.iter()
+        .take_while(|token| token.chars().all(|c| c.is_ascii_alphabetic() || c == '-'))
+        .take(MAX_NAME_WORDS)
+        .map(|token| token.to_ascii_lowercase())
+        .collect();
+    words.join(" ")
+}
+
+/// Records a command before it's run, refusing it if the user has used it too often.
+pub async fn check(ctx: &Context, message: &Message, tokens: &[&str]) -> CommandResult<()> {
+    let command = command_name(tokens);
+
+    let limiter = {
+        let data = ctx.data.read().await;
+        data.get::<rate_limit::LimiterKey>().cloned()
+    };
+    let limited = match limiter {
+        Some(limiter) => limiter.check(message.author.id, &command).err(),
+        None => None,
+    };
+
+    if let Some(guild) = message.guild_id {
+        let usage = Usage { user: message.author.id, command, at: scheduler::now(), limited: limited.is_some() };
+        let state = store::<StateKey>(ctx).await;
+        state.write(|state| state.record(guild, usage)).await;
+    }
+
+    match limited {
+        Some(wait_secs) => Err(CommandError::RateLimited(wait_secs)),
+        None => Ok(()),
+    }
+}
+
+pub async fn show_stats(ctx: &Context, command: &Message) -> CommandResult<()> {
+    let guild = command.guild_id.ok_or(CommandError::NotAllowed)?;
+
+    let stats = store::<StateKey>(ctx).await.read().await.stats(guild);
+    if stats.total == 0 {
+        say_lines(ctx, command.channel_id, &["No commands have been used here yet.".to_owned()]).await?;
+        return Ok(());
+    }
+
+    let mut lines = vec![format!("**Commands**, out of the last {} uses", stats.total)];
+    lines.extend(stats.commands.iter().take(MAX_LISTED).map(|(command, count)| format!("`{}`: {}", command, count)));
+    lines.push("**Most active**".to_owned());
+    lines.extend(stats.users.iter().take(MAX_LISTED).map(|(user, count)| format!("<@{}>: {}", user.get(), count)));
+    if stats.limited > 0 {
+        lines.push(format!("⏳ {} uses were refused for being too quick", stats.limited));
+    }
+    say_lines(ctx, command.channel_id, &lines).await?;
+
+    Ok(())
+}
+
+/// Forgets all guilds not in `guilds`, returning the ones that were removed.
+pub async fn retain_guilds(ctx: &Context, guilds: &HashSet<GuildId>) -> Vec<GuildId> {
+    let state = store::<StateKey>(ctx).await;
+    state.write(|state| {
+        let removed = state.guilds.keys().filter(|guild| !guilds.contains(guild)).copied().collect();
+        state.guilds.retain(|guild, _| guilds.contains(guild));
+        removed
+    }).await
+}
@@ -0,0 +1,584 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+use serenity::builder::{CreateAttachment, CreateMessage};
+use serenity::futures::TryStreamExt;
+use serenity::http::Http;
+use serenity::model::prelude::*;
+use serenity::prelude::*;
+
+use crate::{audit, confirm, CommandError, CommandResult, Persistent, persistent_roles, quotas, reaction_roles, say_lines, store};
+use crate::quotas::Quota;
+use crate::reaction_roles::{Emoji, UnmappedPolicy};
+
+const CONFIG_FILE_NAME: &str = "config.toml";
+
+/// A mirror of a guild's selectors and persisted roles that can be edited by hand and imported
+/// again. Unlike a guild setup, it describes the existing selectors rather than how to recreate them.
+///
+/// Roles and channels are referred to by name, or by ID when their name is ambiguous, and selectors
+/// are keyed by the ID of their message.
+#[derive(Serialize, Deserialize, Default, Clone, Debug, Eq, PartialEq)]
+pub struct GuildConfig {
+    #[serde(default)]
+    pub notify_owner: bool,
+    #[serde(default)]
+    pub persisted_roles: BTreeSet<String>,
+    #[serde(default)]
+    pub selectors: BTreeMap<String, SelectorConfig>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq)]
+pub struct SelectorConfig {
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub approval_channel: Option<String>,
+    #[serde(default)]
+    pub exclusive: bool,
+    #[serde(default)]
+    pub confirm_switch: bool,
+    #[serde(default)]
+    pub unmapped: UnmappedPolicy,
+    /// Maps each emoji to the roles it grants.
+    #[serde(default)]
+    pub roles: BTreeMap<String, Vec<String>>,
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+impl GuildConfig {
+    pub fn parse(text: &str) -> Result<GuildConfig, toml::de::Error> {
+        toml::from_str(text)
+    }
+
+    pub fn to_toml(&self) -> String {
+        toml::to_string_pretty(self).expect("failed to serialize guild config")
+    }
+}
+
+/// The role and channel names of a guild, used to translate between IDs and the config file.
+pub struct Names {
+    roles: HashMap<RoleId, String>,
+    channels: HashMap<ChannelId, String>,
+}
+
+impl Names {
+    pub fn new(roles: HashMap<RoleId, String>, channels: HashMap<ChannelId, String>) -> Self {
+        Names { roles, channels }
+    }
+
+    pub async fn fetch(http: &Http, guild: GuildId) -> serenity::Result<Names> {
+        let roles = http.get_guild_roles(guild).await?
+            .into_iter()
+            .map(|role| (role.id, role.name))
+            .collect();
+        let channels = http.get_channels(guild).await?
+            .into_iter()
+            .map(|channel| (channel.id, channel.name))
+            .collect();
+        Ok(Names { roles, channels })
+    }
+
+    fn role_name(&self, role: RoleId) -> String {
+        describe_id(&self.roles, role.get(), role)
+    }
+
+    fn channel_name(&self, channel: ChannelId) -> String {
+        describe_id(&self.channels, channel.get(), channel)
+    }
+
+    fn resolve_role(&self, name: &str) -> Result<RoleId, String> {
+        resolve_id(&self.roles, name).map_err(|problem| format!("Role `{}` {}", name, problem))
+    }
+
+    fn resolve_channel(&self, name: &str) -> Result<ChannelId, String> {
+        resolve_id(&self.channels, name).map_err(|problem| format!("Channel `{}` {}", name, problem))
+    }
+}
+
+/// Names an ID, falling back to the raw ID when it has no name or shares its name with another.
+fn describe_id<K: Eq + std::hash::Hash>(names: &HashMap<K, String>, raw: u64, id: K) -> String {
+    match names.get(&id) {
+        Some(name) if names.values().filter(|other| *other == name).count() == 1 => name.clone(),
+        _ => raw.to_string(),
+    }
+}
+
+fn resolve_id<K: Copy + Eq + std::hash::Hash + FromStr>(names: &HashMap<K, String>, name: &str) -> Result<K, &'static str> {
+    let mut matches = names.iter().filter(|(_, other)| *other == name).map(|(id, _)| *id);
+    match (matches.next(), matches.next()) {
+        (Some(id), None) => Ok(id),
+        (Some(_), Some(_)) => Err("is ambiguous, use its ID instead"),
+        (None, _) => match name.parse::<K>() {
+            Ok(id) if names.contains_key(&id) => Ok(id),
+            _ => Err("doesn't exist"),
+        },
+    }
+}
+
+/// The configuration with all names resolved, as it gets applied to the stores.
+#[derive(Default)]
+pub struct Resolved {
+    notify_owner: bool,
+    persisted_roles: HashSet<RoleId>,
+    selectors: HashMap<MessageId, ResolvedSelector>,
+}
+
+struct ResolvedSelector {
+    enabled: bool,
+    approval_channel: Option<ChannelId>,
+    exclusive: bool,
+    confirm_switch: bool,
+    unmapped: UnmappedPolicy,
+    roles: Vec<(Emoji, Vec<RoleId>)>,
+}
+
+impl Resolved {
+    /// Captures the current configuration of the selectors in the given channels and of persisted roles.
+    pub fn snapshot(
+        selectors: &reaction_roles::State,
+        persistent: Option<&persistent_roles::GuildState>,
+        channels: &HashSet<ChannelId>,
+    ) -> Resolved {
+        let selectors = selectors.entries()
+            .filter(|(_, entry)| entry.channel.is_some_and(|channel| channels.contains(&channel)))
+            .map(|(message, entry)| {
+                let selector = &entry.selector;
+                (message, ResolvedSelector {
+                    enabled: selector.is_enabled(),
+                    approval_channel: selector.approval_channel(),
+                    exclusive: selector.is_exclusive(),
+                    confirm_switch: selector.confirms_switch(),
+                    unmapped: selector.unmapped_policy().clone(),
+                    roles: selector.iter().map(|(emoji, roles)| (emoji.clone(), roles.to_vec())).collect(),
+                })
+            })
+            .collect();
+
+        Resolved {
+            notify_owner: persistent.is_some_and(|guild| guild.notify_owner()),
+            persisted_roles: persistent.map(|guild| guild.roles().collect()).unwrap_or_default(),
+            selectors,
+        }
+    }
+
+    /// Resolves the names in `config`, collecting every problem found along the way. Only the
+    /// selectors in `known_selectors` may be configured.
+    pub fn resolve(config: &GuildConfig, names: &Names, known_selectors: &HashSet<MessageId>) -> Result<Resolved, Vec<String>> {
+        let mut problems = Vec::new();
+        let mut resolved = Resolved { notify_owner: config.notify_owner, ..Resolved::default() };
+
+        for name in &config.persisted_roles {
+            match names.resolve_role(name) {
+                Ok(role) => {
+                    resolved.persisted_roles.insert(role);
+                }
+                Err(problem) => problems.push(problem),
+            }
+        }
+
+        for (message, selector) in &config.selectors {
+            let message = match message.parse::<MessageId>() {
+                Ok(message) if known_selectors.contains(&message) => message,
+                _ => {
+                    problems.push(format!("`{}` is not a selector in this server", message));
+                    continue;
+                }
+            };
+
+            let approval_channel = match &selector.approval_channel {
+                Some(name) => match names.resolve_channel(name) {
+                    Ok(channel) => Some(channel),
+                    Err(problem) => {
+                        problems.push(problem);
+                        None
+                    }
+                },
+                None => None,
+            };
+
+            let mut roles = Vec::new();
+            for (emoji, role_names) in &selector.roles {
+                let mut emoji_roles = Vec::new();
+                for name in role_names {
+                    match names.resolve_role(name) {
+                        Ok(role) => emoji_roles.push(role),
+                        Err(problem) => problems.push(problem),
+                    }
+                }
+                if role_names.is_empty() {
+                    problems.push(format!("{} on selector `{}` has no roles", emoji, message.get()));
+                }
+                roles.push((emoji.parse().unwrap(), emoji_roles));
+            }
+
+            resolved.selectors.insert(message, ResolvedSelector {
+                enabled: selector.enabled,
+                approval_channel,
+                exclusive: selector.exclusive,
+                confirm_switch: selector.confirm_switch,
+                unmapped: selector.unmapped.clone(),
+                roles,
+            });
+        }
+
+        if problems.is_empty() {
+            Ok(resolved)
+        } else {
+            Err(problems)
+        }
+    }
+
+    pub fn describe(&self, names: &Names) -> GuildConfig {
+        let selectors = self.selectors.iter()
+            .map(|(message, selector)| {
+                let roles = selector.roles.iter()
+                    .map(|(emoji, roles)| (emoji.to_string(), roles.iter().map(|role| names.role_name(*role)).collect()))
+                    .collect();
+                (message.get().to_string(), SelectorConfig {
+                    enabled: selector.enabled,
+                    approval_channel: selector.approval_channel.map(|channel| names.channel_name(channel)),
+                    exclusive: selector.exclusive,
+                    confirm_switch: selector.confirm_switch,
+                    unmapped: selector.unmapped.clone(),
+                    roles,
+                })
+            })
+            .collect();
+
+        GuildConfig {
+            notify_owner: self.notify_owner,
+            persisted_roles: self.persisted_roles.iter().map(|role| names.role_name(*role)).collect(),
+            selectors,
+        }
+    }
+
+    /// Writes the configuration into the stores. Selectors that aren't part of it are left alone.
+    pub async fn apply(
+        &self,
+        http: &Http,
+        guild: GuildId,
+        selectors: &Persistent<reaction_roles::State>,
+        persistent: &Persistent<persistent_roles::State>,
+    ) -> serenity::Result<()> {
+        selectors.write(|state| {
+            for (message, resolved) in &self.selectors {
+                if let Some(selector) = state.selector_mut(*message) {
+                    selector.set_enabled(resolved.enabled);
+                    selector.set_approval_channel(resolved.approval_channel);
+                    selector.set_exclusive(resolved.exclusive);
+                    selector.set_confirm_switch(resolved.confirm_switch);
+                    selector.set_unmapped_policy(resolved.unmapped.clone());
+                    selector.replace_roles(resolved.roles.iter().cloned());
+                }
+            }
+        }).await;
+
+        let current: HashSet<RoleId> = persistent.read().await.guild(guild)
+            .map(|guild| guild.roles().collect())
+            .unwrap_or_default();
+        let added: Vec<RoleId> = self.persisted_roles.difference(&current).copied().collect();
+        let removed: Vec<RoleId> = current.difference(&self.persisted_roles).copied().collect();
+
+        // newly persisted roles start out with everyone who currently has them
+        let members: Vec<Member> = if !added.is_empty() && crate::capabilities::has_members_intent() {
+            guild.members_iter(http).try_collect().await?
+        } else {
+            Vec::new()
+        };
+
+        persistent.write(|state| {
+            let guild = state.guild_mut(guild);
+            guild.set_notify_owner(self.notify_owner);
+            for role in &removed {
+                guild.remove_role(*role);
+            }
+            for role in &added {
+                let users_with_role = members.iter()
+                    .filter(|member| member.roles.contains(role))
+                    .map(|member| member.user.id)
+                    .collect();
+                guild.add_role(*role, users_with_role);
+            }
+        }).await;
+
+        Ok(())
+    }
+}
+
+/// Lists how `new` differs from `current`. Selectors missing from `new` are not considered changed,
+/// since importing leaves them alone.
+pub fn diff(current: &GuildConfig, new: &GuildConfig) -> Vec<String> {
+    let mut changes = Vec::new();
+
+    if current.notify_owner != new.notify_owner {
+        changes.push(format!("~ notify_owner: {} → {}", current.notify_owner, new.notify_owner));
+    }
+
+    for role in new.persisted_roles.difference(&current.persisted_roles) {
+        changes.push(format!("+ persisted role `{}`", role));
+    }
+    for role in current.persisted_roles.difference(&new.persisted_roles) {
+        changes.push(format!("- persisted role `{}`", role));
+    }
+
+    for (message, new) in &new.selectors {
+        let current = match current.selectors.get(message) {
+            Some(current) => current,
+            None => continue,
+        };
+
+        if current.enabled != new.enabled {
+            changes.push(format!("~ selector {}: enabled {} → {}", message, current.enabled, new.enabled));
+        }
+        if current.approval_channel != new.approval_channel {
+            changes.push(format!(
+                "~ selector {}: approval channel {} → {}",
+                message, describe_channel(&current.approval_channel), describe_channel(&new.approval_channel),
+            ));
+        }
+        if current.exclusive != new.exclusive {
+            changes.push(format!("~ selector {}: exclusive {} → {}", message, current.exclusive, new.exclusive));
+        }
+        if current.confirm_switch != new.confirm_switch {
+            changes.push(format!("~ selector {}: confirm_switch {} → {}", message, current.confirm_switch, new.confirm_switch));
+        }
+        if current.unmapped != new.unmapped {
+            changes.push(format!(
+                "~ selector {}: unmapped reactions {} → {}",
+                message, describe_unmapped(&current.unmapped), describe_unmapped(&new.unmapped),
+            ));
+        }
+
+        for (emoji, roles) in &new.roles {
+            match current.roles.get(emoji) {
+                None => changes.push(format!("+ selector {}: {} → {}", message, emoji, describe_roles(roles))),
+                Some(current_roles) if current_roles != roles => changes.push(format!(
+                    "~ selector {}: {} → {} (was {})",
+                    message, emoji, describe_roles(roles), describe_roles(current_roles),
+                )),
+                Some(_) => (),
+            }
+        }
+        for emoji in current.roles.keys().filter(|emoji| !new.roles.contains_key(*emoji)) {
+            changes.push(format!("- selector {}: {}", message, emoji));
+        }
+    }
+
+    changes
+}
+
+fn describe_roles(roles: &[String]) -> String {
+    roles.iter().map(|role| format!("`{}`", role)).collect::<Vec<_>>().join(", ")
+}
+
+fn describe_channel(channel: &Option<String>) -> String {
+    match channel {
+        Some(channel) => format!("`#{}`", channel),
+        None => "none".to_owned(),
+    }
+}
+
+fn describe_unmapped(policy: &UnmappedPolicy) -> String {
+    match policy {
+        UnmappedPolicy::Delete => "deleted".to_owned(),
+        UnmappedPolicy::Ignore => "ignored".to_owned(),
+        UnmappedPolicy::Allow { emoji } => {
+            let mut emoji: Vec<String> = emoji.iter().map(|emoji| emoji.to_string()).collect();
+            emoji.sort();
+            format!("deleted except {}", emoji.join(" "))
+        }
+    }
+}
+
+fn guild_channels(names: &Names) -> HashSet<ChannelId> {
+    names.channels.keys().copied().collect()
+}
+
+pub async fn export(ctx: &Context, command: &Message) -> CommandResult<()> {
+    let guild = command.guild_id.ok_or(CommandError::NotAllowed)?;
+
+    let names = Names::fetch(&ctx.http, guild).await?;
+    let config = {
+        let selectors = store::<reaction_roles::StateKey>(ctx).await;
+        let persistent = store::<persistent_roles::StateKey>(ctx).await;
+        let selectors = selectors.read().await;
+        let persistent = persistent.read().await;
+        Resolved::snapshot(&selectors, persistent.guild(guild), &guild_channels(&names)).describe(&names)
+    };
+
+    let attachment = CreateAttachment::bytes(config.to_toml(), CONFIG_FILE_NAME);
+    let content = format!(
+        "Exported {} selectors and {} persisted roles. Edit the file and attach it to `import config` to apply your changes.",
+        config.selectors.len(), config.persisted_roles.len(),
+    );
+    command.channel_id.send_files(&ctx.http, vec![attachment], CreateMessage::new().content(content)).await?;
+
+    Ok(())
+}
+
+/// The changes needed to bring a guild in line with a config file.
+pub struct Plan {
+    resolved: Resolved,
+    pub changes: Vec<String>,
+}
+
+pub enum PlanError {
+    Serenity(serenity::Error),
+    /// The config refers to things that don't exist.
+    Invalid(Vec<String>),
+}
+
+impl From<serenity::Error> for PlanError {
+    fn from(err: serenity::Error) -> Self {
+        PlanError::Serenity(err)
+    }
+}
+
+/// Works out how `config` differs from the guild's current configuration.
+pub async fn plan(
+    http: &Http,
+    guild: GuildId,
+    config: &GuildConfig,
+    selectors: &Persistent<reaction_roles::State>,
+    persistent: &Persistent<persistent_roles::State>,
+) -> Result<Plan, PlanError> {
+    let names = Names::fetch(http, guild).await?;
+
+    let current = {
+        let selectors = selectors.read().await;
+        let persistent = persistent.read().await;
+        Resolved::snapshot(&selectors, persistent.guild(guild), &guild_channels(&names))
+    };
+    let known_selectors = current.selectors.keys().copied().collect();
+
+    let resolved = Resolved::resolve(config, &names, &known_selectors).map_err(PlanError::Invalid)?;
+    let changes = diff(&current.describe(&names), &resolved.describe(&names));
+
+    Ok(Plan { resolved, changes })
+}
+
+impl Plan {
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+
+    /// How many roles will be persisted once the plan is applied.
+    #[inline]
+    pub fn persisted_role_count(&self) -> usize {
+        self.resolved.persisted_roles.len()
+    }
+
+    pub async fn apply(
+        &self,
+        http: &Http,
+        guild: GuildId,
+        selectors: &Persistent<reaction_roles::State>,
+        persistent: &Persistent<persistent_roles::State>,
+    ) -> serenity::Result<()> {
+        self.resolved.apply(http, guild, selectors, persistent).await
+    }
+}
+
+pub async fn import(ctx: &Context, command: &Message) -> CommandResult<()> {
+    let guild = command.guild_id.ok_or(CommandError::NotAllowed)?;
+
+    let attachment = command.attachments.first().ok_or(CommandError::MissingAttachment)?;
+    let bytes = attachment.download().await?;
+    let text = String::from_utf8_lossy(&bytes);
+    let config = GuildConfig::parse(&text).map_err(|err| CommandError::MalformedConfig(err.to_string()))?;
+
+    let selectors = store::<reaction_roles::StateKey>(ctx).await;
+    let persistent = store::<persistent_roles::StateKey>(ctx).await;
+
+    let plan = match plan(&ctx.http, guild, &config, &selectors, &persistent).await {
+        Ok(plan) => plan,
+        Err(PlanError::Serenity(err)) => return Err(err.into()),
+        Err(PlanError::Invalid(problems)) => {
+            let lines: Vec<String> = problems.iter().map(|problem| format!("⚠ {}", problem)).collect();
+            say_lines(ctx, command.channel_id, &lines).await?;
+            return Err(CommandError::InvalidConfig(problems.len()));
+        }
+    };
+
+    if plan.is_empty() {
+        command.channel_id.say(&ctx.http, "That config matches the current one, nothing to change!").await?;
+        return Ok(());
+    }
+
+    quotas::check_total(ctx, guild, Quota::PersistedRoles, plan.persisted_role_count()).await?;
+
+    say_lines(ctx, command.channel_id, &plan.changes).await?;
+    let prompt = format!("Apply these {} changes?", plan.changes.len());
+    if !confirm::ask(ctx, command.channel_id, command.author.id, prompt).await? {
+        return Ok(());
+    }
+
+    plan.apply(&ctx.http, guild, &selectors, &persistent).await?;
+    audit::log(ctx, guild, format!("<@{}> imported a config file with {} changes", command.author.id.get(), plan.changes.len())).await;
+
+    Ok(())
+}
+
+/// Exports or imports a guild's config from the command line, without connecting to the gateway.
+/// The bot should not be running at the same time, since it would overwrite the stores.
+pub async fn run_cli(
+    token: &str,
+    args: &[String],
+    selectors: &Persistent<reaction_roles::State>,
+    persistent: &Persistent<persistent_roles::State>,
+) -> Result<(), String> {
+    let args: Vec<&str> = args.iter().map(|arg| arg.as_str()).collect();
+    let (export, guild, path, apply) = match args.as_slice() {
+        ["export-config", guild, path] => (true, guild, path, false),
+        ["import-config", guild, path] => (false, guild, path, false),
+        ["import-config", guild, path, "--apply"] => (false, guild, path, true),
+        _ => return Err("usage: export-config <guild> <file> | import-config <guild> <file> [--apply]".to_owned()),
+    };
+    let guild: GuildId = guild.parse().map_err(|_| format!("invalid guild ID: {}", guild))?;
+
+    let http = Http::new(token);
+
+    if export {
+        let names = Names::fetch(&http, guild).await.map_err(|err| format!("failed to fetch guild: {}", err))?;
+        let config = {
+            let selectors = selectors.read().await;
+            let persistent = persistent.read().await;
+            Resolved::snapshot(&selectors, persistent.guild(guild), &guild_channels(&names)).describe(&names)
+        };
+        std::fs::write(path, config.to_toml()).map_err(|err| format!("failed to write {}: {}", path, err))?;
+        println!("Exported {} selectors and {} persisted roles to {}", config.selectors.len(), config.persisted_roles.len(), path);
+        return Ok(());
+    }
+
+    let text = std::fs::read_to_string(path).map_err(|err| format!("failed to read {}: {}", path, err))?;
+    let config = GuildConfig::parse(&text).map_err(|err| format!("invalid config: {}", err))?;
+
+    let plan = match plan(&http, guild, &config, selectors, persistent).await {
+        Ok(plan) => plan,
+        Err(PlanError::Serenity(err)) => return Err(format!("failed to fetch guild: {}", err)),
+        Err(PlanError::Invalid(problems)) => return Err(format!("invalid config:\n{}", problems.join("\n"))),
+    };
+
+    if plan.is_empty() {
+        println!("Nothing to change");
+        return Ok(());
+    }
+    for change in &plan.changes {
+        println!("{}", change);
+    }
+
+    if apply {
+        plan.apply(&http, guild, selectors, persistent).await.map_err(|err| format!("failed to apply config: {}", err))?;
+        println!("Applied {} changes", plan.changes.len());
+    } else {
+        println!("Run again with --apply to apply these changes");
+    }
+
+    Ok(())
+}
@@ -0,0 +1,264 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
+use std::hash::{Hash, Hasher};
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use log::warn;
+use serde::{Deserialize, Serialize};
+use serenity::builder::{CreateAllowedMentions, CreateMessage};
+use serenity::model::prelude::*;
+use serenity::prelude::*;
+
+use crate::{audit, CommandError, CommandResult, dormancy, Persistent, persistent_roles, quotas, reaction_roles, scheduler, store};
+use crate::quotas::Quota;
+use crate::config_mirror::{self, GuildConfig, PlanError};
+use crate::memreport::{Introspect, Owner};
+
+pub const PERIOD: Duration = Duration::from_secs(15 * 60);
+
+/// How many changes are spelled out in the log channel before the rest are summarized.
+const MAX_LOGGED_CHANGES: usize = 15;
+
+/// The largest config file that's fetched, far above what any real guild's config takes.
+const MAX_CONFIG_BYTES: usize = 1024 * 1024;
+
+const FETCH_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Shared by every fetch, so that connections are reused.
+static CLIENT: OnceLock<reqwest::Client> = OnceLock::new();
+
+pub struct StateKey;
+
+impl TypeMapKey for StateKey {
+    type Value = Persistent<State>;
+}
+
+#[derive(Serialize, Deserialize, Default, Clone, Eq, PartialEq)]
+pub struct State {
+    guilds: HashMap<GuildId, Source>,
+}
+
+impl Introspect for State {
+    fn entries(&self) -> Vec<(Owner, usize)> {
+        self.guilds.keys().map(|guild| (Owner::Guild(*guild), 1)).collect()
+    }
+}
+
+/// Where a guild's config file is fetched from.
+#[derive(Serialize, Deserialize, Clone, Eq, PartialEq)]
+struct Source {
+    /// A raw URL to a config file, like one served from a Git repository.
+    url: String,
+    /// When set, drift is only reported instead of reconciled.
+    #[serde(default)]
+    report_only: bool,
+    /// Hash of the last report sent to the log channel, so the same drift or error isn't repeated.
+    #[serde(default)]
+    last_report: Option<u64>,
+    #[serde(default)]
+    last_synced_at: Option<i64>,
+}
+
+pub async fn set_source(ctx: &Context, command: &Message, url: Option<&str>, report_only: bool) -> CommandResult<()> {
+    let guild = command.guild_id.ok_or(CommandError::NotAllowed)?;
+
+    if let Some(url) = url {
+        if !url.starts_with("https://") && !url.starts_with("http://") {
+            return Err(CommandError::MalformedArgument(url.to_owned()));
+        }
+    }
+
+    let state = store::<StateKey>(ctx).await;
+    state.write(|state| {
+        match url {
+            Some(url) => state.guilds.insert(guild, Source {
+                url: url.to_owned(),
+                report_only,
+                last_report: None,
+                last_synced_at: None,
+            }),
+            None => state.guilds.remove(&guild),
+        };
+    }).await;
+
+    if url.is_some() {
+        sync_now(ctx, command).await?;
+    }
+
+    Ok(())
+}
+
+pub async fn status(ctx: &Context, command: &Message) -> CommandResult<()> {
+    let guild = command.guild_id.ok_or(CommandError::NotAllowed)?;
+
+    let source = {
+        let state = store::<StateKey>(ctx).await;
+        let state = state.read().await;
+        state.guilds.get(&guild).cloned()
+    };
+
+    let content = match source {
+        Some(source) => {
+            let mode = if source.report_only { "reporting drift from" } else { "syncing from" };
+            let synced = match source.last_synced_at {
+                Some(at) => format!("last checked <t:{}:R>", at),
+                None => "not checked yet".to_owned(),
+            };
+            format!("🔄 Config is {} <{}> ({}).", mode, source.url, synced)
+        }
+        None => "Config sync is off.".to_owned(),
+    };
+    command.channel_id.say(&ctx.http, content).await?;
+
+    Ok(())
+}
+
+pub async fn sync_now(ctx: &Context, command: &Message) -> CommandResult<()> {
+    let guild = command.guild_id.ok_or(CommandError::NotAllowed)?;
+
+    let source = {
+        let state = store::<StateKey>(ctx).await;
+        let state = state.read().await;
+        state.guilds.get(&guild).cloned()
+    };
+    let mut source = source.ok_or(CommandError::ConfigSyncOff)?;
+
+    // always report when asked for, even if nothing changed since the last report
+    source.last_report = None;
+    let url = source.url.clone();
+    let content = match sync_guild(ctx, guild, source).await {
+        Some(report) => report,
+        None => format!("✅ Config is in sync with <{}>.", url),
+    };
+    command.channel_id.send_message(&ctx.http, CreateMessage::new().content(content).allowed_mentions(CreateAllowedMentions::new())).await?;
+
+    Ok(())
+}
+
+pub async fn run(ctx: Context) {
+    let sources: Vec<(GuildId, Source)> = {
+        let state = store::<StateKey>(&ctx).await;
+        let state = state.read().await;
+        state.guilds.iter().map(|(guild, source)| (*guild, source.clone())).collect()
+    };
+
+    for (guild, source) in sources {
+        dormancy::wake(&ctx, guild).await;
+        sync_guild(&ctx, guild, source).await;
+    }
+}
+
+/// Syncs a guild and logs what happened, returning the report.
+async fn sync_guild(ctx: &Context, guild: GuildId, source: Source) -> Option<String> {
+    // applied changes are always worth logging, while repeated drift or errors are only logged once
+    let (report, always_log) = match reconcile(ctx, guild, &source).await {
+        Ok(changes) if changes.is_empty() => (None, false),
+        Ok(changes) => {
+            let verb = if source.report_only { "Config has drifted from" } else { "Synced config from" };
+            let mut lines = vec![format!("🔄 {} <{}>:", verb, source.url)];
+            lines.extend(changes.iter().take(MAX_LOGGED_CHANGES).cloned());
+            if changes.len() > MAX_LOGGED_CHANGES {
+                lines.push(format!("…and {} more changes", changes.len() - MAX_LOGGED_CHANGES));
+            }
+            (Some(lines.join("\n")), !source.report_only)
+        }
+        Err(problem) => {
+            warn!("failed to sync config of {}: {}", guild, problem);
+            (Some(format!("⚠ Couldn't sync config from <{}>: {}", source.url, problem)), false)
+        }
+    };
+
+    let report_hash = report.as_ref().map(|report| {
+        let mut hasher = DefaultHasher::new();
+        report.hash(&mut hasher);
+        hasher.finish()
+    });
+    let should_log = always_log || report_hash != source.last_report;
+
+    let now = scheduler::now();
+    let state = store::<StateKey>(ctx).await;
+    state.write(|state| {
+        if let Some(stored) = state.guilds.get_mut(&guild) {
+            if stored.url == source.url {
+                stored.last_report = report_hash;
+                stored.last_synced_at = Some(now);
+            }
+        }
+    }).await;
+
+    let report = report.map(|report| report.chars().take(2000).collect::<String>());
+    if let (true, Some(report)) = (should_log, &report) {
+        audit::log(ctx, guild, report.clone()).await;
+    }
+
+    report
+}
+
+/// Fetches the config and brings the guild in line with it, unless only reporting. Returns the
+/// changes that were found.
+async fn reconcile(ctx: &Context, guild: GuildId, source: &Source) -> Result<Vec<String>, String> {
+    let text = fetch(&source.url).await.map_err(|err| format!("couldn't fetch it ({})", err))?;
+    let config = GuildConfig::parse(&text).map_err(|err| format!("it's invalid ({})", err))?;
+
+    let selectors = store::<reaction_roles::StateKey>(ctx).await;
+    let persistent = store::<persistent_roles::StateKey>(ctx).await;
+
+    let plan = match config_mirror::plan(&ctx.http, guild, &config, &selectors, &persistent).await {
+        Ok(plan) => plan,
+        Err(PlanError::Serenity(err)) => return Err(format!("Discord error ({})", err)),
+        Err(PlanError::Invalid(problems)) => return Err(format!("it has problems:\n{}", problems.join("\n"))),
+    };
+
+    if !plan.is_empty() && !source.report_only {
+        quotas::check_total(ctx, guild, Quota::PersistedRoles, plan.persisted_role_count()).await
+            .map_err(|err| err.to_string())?;
+        plan.apply(&ctx.http, guild, &selectors, &persistent).await
+            .map_err(|err| format!("Discord error ({})", err))?;
+    }
+
+    Ok(plan.changes)
+}
+
+/// Fetches the config file, giving up on ones that are too large or take too long.
+async fn fetch(url: &str) -> Result<String, String> {
+    let client = CLIENT.get_or_init(|| {
+        reqwest::Client::builder()
+            .connect_timeout(Duration::from_secs(5))
+            .timeout(FETCH_TIMEOUT)
+            .build()
+            .expect("failed to build HTTP client")
+    });
+
+    let mut response = client.get(url).send().await
+        .and_then(|response| response.error_for_status())
+        .map_err(|err| err.to_string())?;
+    if response.content_length().is_some_and(|length| length > MAX_CONFIG_BYTES as u64) {
+        return Err("it's larger than 1MB".to_owned());
+    }
+
+    let mut body = Vec::new();
+    while let Some(chunk) = response.chunk().await.map_err(|err| err.to_string())? {
+        if body.len() + chunk.len() > MAX_CONFIG_BYTES {
+            return Err("it's larger than 1MB".to_owned());
+        }
+        body.extend_from_slice(&chunk);
+    }
+    String::from_utf8(body).map_err(|_| "it isn't UTF-8".to_owned())
+}
+
+pub async fn is_configured(ctx: &Context, guild: GuildId) -> bool {
+    let state = store::<StateKey>(ctx).await;
+    let configured = state.read().await.guilds.contains_key(&guild);
+    configured
+}
+
+/// Forgets all guilds not in `guilds`, returning the ones that were removed.
+pub async fn retain_guilds(ctx: &Context, guilds: &HashSet<GuildId>) -> Vec<GuildId> {
+    let state = store::<StateKey>(ctx).await;
+    state.write(|state| {
+        let removed = state.guilds.keys().filter(|guild| !guilds.contains(guild)).copied().collect();
+        state.guilds.retain(|guild, _| guilds.contains(guild));
+        removed
+    }).await
+}
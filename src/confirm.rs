@@ -0,0 +1,31 @@
+use std::time::Duration;
+
+use serenity::builder::{CreateAllowedMentions, CreateMessage};
+use serenity::model::prelude::*;
+use serenity::prelude::*;
+
+const CONFIRM_EMOJI: &str = "✅";
+const CANCEL_EMOJI: &str = "❌";
+
+/// How long to wait for an answer before treating it as a no.
+const TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Asks `user` a yes/no question in `channel`, to be answered by reacting. The prompt is removed
+/// once answered, and no answer counts as a no.
+pub async fn ask(ctx: &Context, channel: ChannelId, user: UserId, prompt: impl Into<String>) -> serenity::Result<bool> {
+    let content = format!("{}\nReact with {} to confirm or {} to cancel.", prompt.into(), CONFIRM_EMOJI, CANCEL_EMOJI);
+    let message = channel.send_message(&ctx.http, CreateMessage::new().content(content).allowed_mentions(CreateAllowedMentions::new())).await?;
+
+    message.react(ctx, ReactionType::Unicode(CONFIRM_EMOJI.to_owned())).await?;
+    message.react(ctx, ReactionType::Unicode(CANCEL_EMOJI.to_owned())).await?;
+
+    let answer = message.await_reaction(ctx)
+        .author_id(user)
+        .timeout(TIMEOUT)
+        .filter(|reaction| reaction.emoji.unicode_eq(CONFIRM_EMOJI) || reaction.emoji.unicode_eq(CANCEL_EMOJI))
+        .await;
+
+    let _ = message.delete(ctx).await;
+
+    Ok(answer.is_some_and(|answer| answer.emoji.unicode_eq(CONFIRM_EMOJI)))
+}
@@ -0,0 +1,143 @@
+use std::collections::{BTreeSet, HashMap};
+
+use serenity::model::prelude::*;
+use serenity::prelude::*;
+
+use crate::{CommandError, CommandResult, member_permissions, persistent_roles, ping_tracker, reaction_roles};
+use crate::audit;
+
+/// Permissions the bot needs across the guild for all of its features to work.
+const REQUIRED_PERMISSIONS: &[(Permissions, &str)] = &[
+    (Permissions::MANAGE_ROLES, "granting and restoring roles"),
+    (Permissions::ADD_REACTIONS, "adding selector reactions"),
+    (Permissions::READ_MESSAGE_HISTORY, "reading selector messages"),
+    (Permissions::MANAGE_MESSAGES, "removing reactions and pinning messages"),
+    (Permissions::SEND_MESSAGES, "replying to commands"),
+    (Permissions::VIEW_AUDIT_LOG, "attributing role changes in the log channel"),
+];
+
+/// A problem found in the guild along with how to fix it.
+struct Problem {
+    problem: String,
+    fix: String,
+}
+
+/// Checks the guild for common misconfigurations and replies with a checklist of problems to fix.
+pub async fn run(ctx: &Context, command: &Message) -> CommandResult<()> {
+    let guild = command.guild_id.ok_or(CommandError::NotAllowed)?;
+
+    let mut problems = Vec::new();
+    check_permissions(ctx, guild, &mut problems).await;
+    check_selector_messages(ctx, guild, &mut problems).await?;
+    check_roles(ctx, guild, &mut problems).await?;
+
+    for (_, description) in reaction_roles::describe_conflicts(ctx, guild).await? {
+        problems.push(Problem {
+            problem: description,
+            fix: "Map each role and emoji from only one selector entry".to_owned(),
+        });
+    }
+
+    if problems.is_empty() {
+        command.reply(ctx, "🩺 No problems found!").await?;
+    } else {
+        let lines: Vec<String> = problems.into_iter()
+            .map(|Problem { problem, fix }| format!("☐ {}\n    ↳ {}", problem, fix))
+            .collect();
+        crate::say_lines(ctx, command.channel_id, &lines).await?;
+    }
+
+    Ok(())
+}
+
+async fn check_permissions(ctx: &Context, guild: GuildId, problems: &mut Vec<Problem>) {
+    let current_user = ctx.cache.current_user().id;
+    let permissions = member_permissions(ctx, guild, current_user).await;
+
+    for (permission, purpose) in REQUIRED_PERMISSIONS {
+        if !permissions.contains(*permission) {
+            problems.push(Problem {
+                problem: format!("I'm missing the `{}` permission, needed for {}", permission, purpose),
+                fix: "Grant the permission to my role in the server settings".to_owned(),
+            });
+        }
+    }
+}
+
+async fn check_selector_messages(ctx: &Context, guild: GuildId, problems: &mut Vec<Problem>) -> serenity::Result<()> {
+    let channels = guild.channels(&ctx.http).await?;
+
+    for (message, entry) in reaction_roles::selectors_in(ctx, &channels).await {
+        let channel = match entry.channel {
+            Some(channel) => channel,
+            None => continue,
+        };
+
+        if channel.message(&ctx.http, message).await.is_err() {
+            problems.push(Problem {
+                problem: format!("The selector {} can no longer be found", audit::message_link(guild, channel, message)),
+                fix: "Check that I can read the channel, or re-register the selector if the message was deleted".to_owned(),
+            });
+        }
+    }
+
+    Ok(())
+}
+
+/// Checks every role referenced by the bot's configuration for existing and being assignable.
+async fn check_roles(ctx: &Context, guild: GuildId, problems: &mut Vec<Problem>) -> serenity::Result<()> {
+    let roles: HashMap<RoleId, Role> = ctx.http.get_guild_roles(guild).await?
+        .into_iter()
+        .map(|role| (role.id, role))
+        .collect();
+
+    let current_user = ctx.cache.current_user().id;
+    let bot = guild.member(ctx, current_user).await?;
+    let bot_position = bot.roles.iter()
+        .filter_map(|role| roles.get(role))
+        .map(|role| role.position)
+        .max()
+        .unwrap_or(0);
+
+    let mut referenced: BTreeSet<(RoleId, &str)> = BTreeSet::new();
+
+    let channels = guild.channels(&ctx.http).await?;
+    for (_, entry) in reaction_roles::selectors_in(ctx, &channels).await {
+        for (_, selector_roles) in entry.selector.iter() {
+            referenced.extend(selector_roles.iter().map(|role| (*role, "a role selector")));
+        }
+    }
+
+    let persisted = persistent_roles::guild_state(ctx, guild).await.unwrap_or_default();
+    referenced.extend(persisted.roles().map(|role| (role, "role persistence")));
+
+    for (role, _, _) in ping_tracker::cooldowns(ctx, guild).await {
+        referenced.insert((role, "a ping cooldown"));
+    }
+
+    for (role, usage) in referenced {
+        match roles.get(&role) {
+            None => problems.push(Problem {
+                problem: format!("Role `{}` used by {} no longer exists", role.get(), usage),
+                fix: "Remove it from the configuration".to_owned(),
+            }),
+            Some(role) if role.position >= bot_position => problems.push(Problem {
+                problem: format!("Role `{}` used by {} is above my highest role, so I can't manage it", role.name, usage),
+                fix: "Move my role above it in the server settings".to_owned(),
+            }),
+            _ => (),
+        }
+    }
+
+    let stale_users = persisted.users()
+        .filter(|(_, user_roles)| user_roles.iter().all(|role| !roles.contains_key(role)))
+        .count();
+    if stale_users > 0 {
+        problems.push(Problem {
+            problem: format!("{} members have only deleted roles persisted", stale_users),
+            fix: "Remove the deleted roles with `remove role persist`".to_owned(),
+        });
+    }
+
+    Ok(())
+}
@@ -0,0 +1,340 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::path::PathBuf;
+
+use async_trait::async_trait;
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use serenity::model::prelude::*;
+use serenity::prelude::*;
+
+use crate::{applications, autopin, cases, command_usage, emoji_stats, mentions, OpenOptions, OptionsKey, Persistable, Persistent, persistent_roles, reaction_roles, role_caps, role_dependencies, scheduler, screening, selector_bans, store, timezone, webhooks};
+use crate::memreport::{Introspect, Owner};
+
+/// Where the state of idle guilds is kept next to the stores, one file per guild.
+pub const ARCHIVE_DIRECTORY: &str = "archived_guilds";
+
+const DAY_SECS: i64 = 24 * 60 * 60;
+
+/// How stale a guild's recorded activity may get before it's recorded again, which keeps busy
+/// guilds from rewriting the store on every command.
+const ACTIVITY_RESOLUTION_SECS: i64 = 60 * 60;
+
+/// Held while state moves in or out of the archive, so that a guild isn't archived and loaded at once.
+static MOVING: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
+pub struct ConfigKey;
+
+impl TypeMapKey for ConfigKey {
+    type Value = DormancyConfig;
+}
+
+/// Moves the state of guilds that haven't used the bot for a while out of memory, which is off
+/// unless a number of days is given.
+#[derive(Serialize, Deserialize, Default, Clone, Eq, PartialEq)]
+pub struct DormancyConfig {
+    #[serde(default)]
+    pub archive_after_days: Option<u32>,
+}
+
+pub struct StateKey;
+
+impl TypeMapKey for StateKey {
+    type Value = Persistent<State>;
+}
+
+/// When each guild last used the bot, and which guilds have their state archived.
+#[derive(Serialize, Deserialize, Default, Clone, Eq, PartialEq)]
+pub struct State {
+    last_active: HashMap<GuildId, i64>,
+    #[serde(default)]
+    archived: BTreeSet<GuildId>,
+}
+
+impl Introspect for State {
+    fn entries(&self) -> Vec<(Owner, usize)> {
+        self.last_active.keys().map(|guild| (Owner::Guild(*guild), 1)).collect()
+    }
+}
+
+impl State {
+    /// Records a guild using the bot, returning whether anything changed.
+    pub fn record(&mut self, guild: GuildId, now: i64) -> bool {
+        match self.last_active.get(&guild) {
+            Some(last_active) if now - last_active < ACTIVITY_RESOLUTION_SECS => false,
+            _ => {
+                self.last_active.insert(guild, now);
+                true
+            }
+        }
+    }
+
+    /// The guilds of `guilds` that haven't been active for `idle_secs` and aren't archived yet.
+    /// Guilds seen for the first time count as active now, so turning archiving on doesn't archive
+    /// every guild at once.
+    pub fn idle_guilds(&mut self, guilds: &HashSet<GuildId>, now: i64, idle_secs: i64) -> Vec<GuildId> {
+        for guild in guilds {
+            self.last_active.entry(*guild).or_insert(now);
+        }
+        let mut idle: Vec<GuildId> = self.last_active.iter()
+            .filter(|(guild, last_active)| guilds.contains(guild) && !self.archived.contains(guild) && now - **last_active >= idle_secs)
+            .map(|(guild, _)| *guild)
+            .collect();
+        idle.sort();
+        idle
+    }
+
+    #[inline]
+    pub fn is_archived(&self, guild: GuildId) -> bool {
+        self.archived.contains(&guild)
+    }
+}
+
+/// A store whose guild entries can be moved out to the archive and back.
+#[async_trait]
+trait Archivable: Send + Sync {
+    async fn entries(&self, field: &str, keys: &HashSet<String>) -> HashMap<String, Value>;
+
+    async fn remove_entries(&self, field: &str, keys: &HashSet<String>);
+
+    async fn restore_entries(&self, field: &str, entries: HashMap<String, Value>);
+}
+
+#[async_trait]
+impl<T: Persistable + Send + Sync + 'static> Archivable for Persistent<T> {
+    async fn entries(&self, field: &str, keys: &HashSet<String>) -> HashMap<String, Value> {
+        Persistent::entries(self, field, keys).await
+    }
+
+    async fn remove_entries(&self, field: &str, keys: &HashSet<String>) {
+        Persistent::remove_entries(self, field, keys).await
+    }
+
+    async fn restore_entries(&self, field: &str, entries: HashMap<String, Value>) {
+        Persistent::restore_entries(self, field, entries).await
+    }
+}
+
+/// The stores that are archived, by the name their entries are archived under and the field
+/// holding their guilds. Selectors stay loaded, since reactions to them are how a guild wakes up,
+/// as do stores of scheduled work that has to happen on time and the audit log channels that work
+/// reports to.
+async fn stores(ctx: &Context) -> Vec<(&'static str, &'static str, Box<dyn Archivable>)> {
+    let data = ctx.data.read().await;
+    vec![
+        ("persistent_roles", "guilds", handle::<persistent_roles::StateKey>(&data)),
+        ("autopin", "guilds", handle::<autopin::StateKey>(&data)),
+        ("emoji_stats", "guilds", handle::<emoji_stats::StateKey>(&data)),
+        ("webhooks", "guilds", handle::<webhooks::StateKey>(&data)),
+        ("applications", "guilds", handle::<applications::StateKey>(&data)),
+        ("role_caps", "guilds", handle::<role_caps::StateKey>(&data)),
+        ("role_dependencies", "guilds", handle::<role_dependencies::StateKey>(&data)),
+        ("timezone", "guilds", handle::<timezone::StateKey>(&data)),
+        ("screening", "guilds", handle::<screening::StateKey>(&data)),
+        ("mentions", "guilds", handle::<mentions::StateKey>(&data)),
+        ("selector_bans", "guilds", handle::<selector_bans::StateKey>(&data)),
+        ("cases", "guilds", handle::<cases::StateKey>(&data)),
+        ("command_usage", "guilds", handle::<command_usage::StateKey>(&data)),
+        ("selector_names", "guilds", handle::<reaction_roles::names::StateKey>(&data)),
+        ("onboarding", "guilds", handle::<reaction_roles::onboarding::StateKey>(&data)),
+        ("progress_roles", "guilds", handle::<reaction_roles::progress::StateKey>(&data)),
+    ]
+}
+
+fn handle<K>(data: &TypeMap) -> Box<dyn Archivable>
+    where K: TypeMapKey,
+          K::Value: Archivable + Clone
+{
+    Box::new(data.get::<K>().expect("store not registered").clone())
+}
+
+async fn options(ctx: &Context) -> OpenOptions {
+    let data = ctx.data.read().await;
+    data.get::<OptionsKey>().cloned().unwrap_or_default()
+}
+
+/// The name of a guild's archive relative to the stores, which is also what it's replicated under.
+#[cfg(feature = "replication")]
+fn archive_file(options: &OpenOptions, guild: GuildId) -> String {
+    let directory = options.path(ARCHIVE_DIRECTORY);
+    let directory = directory.file_name().unwrap_or_default().to_string_lossy();
+    format!("{}/{}.json", directory, guild)
+}
+
+fn archive_path(options: &OpenOptions, guild: GuildId) -> PathBuf {
+    options.path(ARCHIVE_DIRECTORY).join(format!("{}.json", guild))
+}
+
+async fn is_archived(ctx: &Context, guild: GuildId) -> bool {
+    let state = store::<StateKey>(ctx).await;
+    let archived = state.read().await.is_archived(guild);
+    archived
+}
+
+/// Records a guild using the bot, through a command or a selector, loading its state back first if
+/// it was archived.
+pub async fn record_activity(ctx: &Context, guild: GuildId) {
+    wake(ctx, guild).await;
+
+    let enabled = {
+        let data = ctx.data.read().await;
+        data.get::<ConfigKey>().is_some_and(|config| config.archive_after_days.is_some())
+    };
+    if !enabled {
+        return;
+    }
+
+    let now = scheduler::now();
+    let state = store::<StateKey>(ctx).await;
+    let stale = state.read().await.last_active.get(&guild).is_none_or(|last_active| now - last_active >= ACTIVITY_RESOLUTION_SECS);
+    if stale {
+        state.write(|state| state.record(guild, now)).await;
+    }
+}
+
+/// Loads a guild's state back from the archive if it was archived, before an event of the guild is
+/// handled. Loading counts as activity, so the guild stays loaded for the whole idle period.
+pub async fn wake(ctx: &Context, guild: GuildId) {
+    if !is_archived(ctx, guild).await {
+        return;
+    }
+
+    let _moving = MOVING.lock().await;
+    if !is_archived(ctx, guild).await {
+        return;
+    }
+
+    let options = options(ctx).await;
+    let path = archive_path(&options, guild);
+    let archive: BTreeMap<String, Value> = match tokio::fs::read(&path).await {
+        Ok(bytes) => match serde_json::from_slice(&bytes) {
+            Ok(archive) => archive,
+            Err(err) => {
+                // the file is set aside rather than overwritten by the next archiving
+                error!("failed to read the archived state of {}, leaving it in place: {:?}", guild, err);
+                if !options.is_read_only() {
+                    let _ = tokio::fs::rename(&path, path.with_extension("json.invalid")).await;
+                }
+                BTreeMap::new()
+            }
+        },
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+            // the guild stays archived, so that its state can still be put back from a replica
+            error!("the archived state of {} is missing, so it stays archived", guild);
+            return;
+        }
+        Err(err) => {
+            error!("failed to open the archived state of {}: {:?}", guild, err);
+            return;
+        }
+    };
+
+    let key = guild.get().to_string();
+    for (name, field, handle) in stores(ctx).await {
+        if let Some(entry) = archive.get(name) {
+            handle.restore_entries(field, HashMap::from([(key.clone(), entry.clone())])).await;
+        }
+    }
+
+    let now = scheduler::now();
+    let state = store::<StateKey>(ctx).await;
+    state.write(|state| {
+        state.archived.remove(&guild);
+        state.last_active.insert(guild, now);
+    }).await;
+
+    if !options.is_read_only() {
+        if let Err(err) = tokio::fs::remove_file(&path).await {
+            if err.kind() != std::io::ErrorKind::NotFound {
+                error!("failed to remove the archived state of {}: {:?}", guild, err);
+            }
+        }
+    }
+    info!("loaded the archived state of {}", guild);
+}
+
+/// Archives the state of guilds among `guilds` that have been idle for as long as configured,
+/// returning how many were archived.
+pub async fn archive_idle(ctx: &Context, guilds: &HashSet<GuildId>) -> usize {
+    let archive_after_days = {
+        let data = ctx.data.read().await;
+        data.get::<ConfigKey>().and_then(|config| config.archive_after_days)
+    };
+    let archive_after_days = match archive_after_days {
+        Some(days) => days,
+        None => return 0,
+    };
+    // read-only stores would lose the state of archived guilds, since the archives can't be written
+    let options = options(ctx).await;
+    if options.is_read_only() {
+        return 0;
+    }
+
+    let _moving = MOVING.lock().await;
+
+    let now = scheduler::now();
+    let state = store::<StateKey>(ctx).await;
+    // guilds are marked first, so that their events wait for the archiving to finish and then load
+    // them back, and a crash part way leaves their state loaded
+    let idle = state.write(|state| {
+        let idle = state.idle_guilds(guilds, now, archive_after_days as i64 * DAY_SECS);
+        state.archived.extend(idle.iter().copied());
+        idle
+    }).await;
+    if idle.is_empty() {
+        return 0;
+    }
+
+    let keys: HashSet<String> = idle.iter().map(|guild| guild.get().to_string()).collect();
+    let stores = stores(ctx).await;
+
+    let mut archives: HashMap<String, BTreeMap<&'static str, Value>> = HashMap::new();
+    for (name, field, handle) in &stores {
+        for (key, entry) in handle.entries(field, &keys).await {
+            archives.entry(key).or_default().insert(*name, entry);
+        }
+    }
+
+    // the archives are on disk before anything is removed, so a crash in between loses nothing
+    if let Err(err) = tokio::fs::create_dir_all(options.path(ARCHIVE_DIRECTORY)).await {
+        error!("failed to create the archive directory: {:?}", err);
+        state.write(|state| state.archived.retain(|guild| !idle.contains(guild))).await;
+        return 0;
+    }
+    for guild in &idle {
+        let archive = archives.remove(&guild.get().to_string()).unwrap_or_default();
+        let bytes = serde_json::to_vec(&archive).expect("failed to serialize");
+        crate::persistent::write_file(&archive_path(&options, *guild), &bytes).await;
+        #[cfg(feature = "replication")]
+        crate::replication::replicate_as(&archive_file(&options, *guild), bytes);
+    }
+
+    for (_, field, handle) in &stores {
+        handle.remove_entries(field, &keys).await;
+    }
+
+    idle.len()
+}
+
+/// Forgets all guilds not in `guilds` along with their archived state, returning the ones that were
+/// removed.
+pub async fn retain_guilds(ctx: &Context, guilds: &HashSet<GuildId>) -> Vec<GuildId> {
+    let state = store::<StateKey>(ctx).await;
+    let removed: Vec<GuildId> = state.write(|state| {
+        let removed = state.last_active.keys().chain(state.archived.iter())
+            .filter(|guild| !guilds.contains(guild))
+            .copied()
+            .collect::<BTreeSet<_>>();
+        state.last_active.retain(|guild, _| guilds.contains(guild));
+        state.archived.retain(|guild| guilds.contains(guild));
+        removed.into_iter().collect()
+    }).await;
+
+    let options = options(ctx).await;
+    if !options.is_read_only() {
+        for guild in &removed {
+            let _ = tokio::fs::remove_file(archive_path(&options, *guild)).await;
+        }
+    }
+    removed
+}
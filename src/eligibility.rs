@@ -0,0 +1,219 @@
+use std::collections::{HashMap, HashSet};
+
+use serde::{Deserialize, Serialize};
+use serenity::builder::CreateMessage;
+use serenity::model::prelude::*;
+use serenity::prelude::*;
+
+use crate::{CommandError, CommandResult, Persistent, reaction_roles, scheduler, store};
+use crate::reaction_roles::Emoji;
+use crate::memreport::{Introspect, Owner};
+
+/// The longest requirement that can be set, so that nobody gets deferred for years by a typo.
+const MAX_REQUIREMENT_SECS: u64 = 365 * 24 * 60 * 60;
+
+pub struct StateKey;
+
+impl TypeMapKey for StateKey {
+    type Value = Persistent<State>;
+}
+
+#[derive(Serialize, Deserialize, Default, Clone, Eq, PartialEq)]
+pub struct State {
+    guilds: HashMap<GuildId, Requirements>,
+    /// Selector reactions from members who weren't eligible yet, to be granted once they are.
+    #[serde(default)]
+    deferred: Vec<DeferredGrant>,
+}
+
+impl Introspect for State {
+    fn entries(&self) -> Vec<(Owner, usize)> {
+        let guilds = self.guilds.keys().map(|guild| (Owner::Guild(*guild), 1));
+        guilds.chain(self.deferred.iter().map(|grant| (Owner::Guild(grant.guild), 1))).collect()
+    }
+}
+
+/// How old an account and how long a membership must be before selectors grant roles.
+#[derive(Serialize, Deserialize, Default, Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Requirements {
+    #[serde(default)]
+    pub account_age: Option<u64>,
+    #[serde(default)]
+    pub membership: Option<u64>,
+}
+
+impl Requirements {
+    fn is_empty(&self) -> bool {
+        self.account_age.is_none() && self.membership.is_none()
+    }
+
+    /// Returns when a member whose account was created at `created_at` and who joined at `joined_at`
+    /// becomes eligible, or `None` if they already are by `now`. Members who haven't joined count as
+    /// having joined `now`.
+    pub fn eligible_at(&self, created_at: i64, joined_at: Option<i64>, now: i64) -> Option<i64> {
+        let account = self.account_age.map(|age| created_at + age as i64);
+        let membership = self.membership.map(|age| joined_at.unwrap_or(now) + age as i64);
+        account.into_iter().chain(membership).max().filter(|at| *at > now)
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Eq, PartialEq)]
+struct DeferredGrant {
+    guild: GuildId,
+    user: UserId,
+    channel: ChannelId,
+    message: MessageId,
+    emoji: Emoji,
+    eligible_at: i64,
+}
+
+/// Which of the requirements a command sets.
+#[derive(Clone, Copy, Debug)]
+pub enum Requirement {
+    AccountAge,
+    Membership,
+}
+
+/// Parses a duration like `30m`, `12h`, `7d` or `2w` into seconds.
+pub fn parse_duration(duration: &str) -> Option<u64> {
+    let split = duration.find(|c: char| !c.is_ascii_digit()).unwrap_or(duration.len());
+    let (amount, unit) = duration.split_at(split);
+    let amount: u64 = amount.parse().ok()?;
+    let secs = match unit {
+        "m" => amount * 60,
+        "h" => amount * 60 * 60,
+        "d" => amount * 24 * 60 * 60,
+        "w" => amount * 7 * 24 * 60 * 60,
+        _ => return None,
+    };
+    Some(secs).filter(|secs| *secs > 0 && *secs <= MAX_REQUIREMENT_SECS)
+}
+
+fn describe_duration(secs: u64) -> String {
+    const UNITS: &[(u64, &str)] = &[(7 * 24 * 60 * 60, "w"), (24 * 60 * 60, "d"), (60 * 60, "h"), (60, "m")];
+    UNITS.iter()
+        .find(|(unit, _)| secs.is_multiple_of(*unit))
+        .map(|(unit, suffix)| format!("{}{}", secs / unit, suffix))
+        .unwrap_or_else(|| format!("{}s", secs))
+}
+
+pub async fn set_requirement(ctx: &Context, command: &Message, requirement: Requirement, secs: Option<u64>) -> CommandResult<()> {
+    let guild = command.guild_id.ok_or(CommandError::NotAllowed)?;
+
+    let state = store::<StateKey>(ctx).await;
+    state.write(|state| {
+        let requirements = state.guilds.entry(guild).or_default();
+        match requirement {
+            Requirement::AccountAge => requirements.account_age = secs,
+            Requirement::Membership => requirements.membership = secs,
+        }
+        if requirements.is_empty() {
+            state.guilds.remove(&guild);
+        }
+    }).await;
+
+    show(ctx, command).await
+}
+
+pub async fn show(ctx: &Context, command: &Message) -> CommandResult<()> {
+    let guild = command.guild_id.ok_or(CommandError::NotAllowed)?;
+    let requirements = requirements(ctx, guild).await;
+
+    let mut lines = Vec::new();
+    if let Some(age) = requirements.account_age {
+        lines.push(format!("Accounts must be at least {} old.", describe_duration(age)));
+    }
+    if let Some(membership) = requirements.membership {
+        lines.push(format!("Members must have been here for at least {}.", describe_duration(membership)));
+    }
+    let content = if lines.is_empty() {
+        "Anyone can get roles from selectors straight away.".to_owned()
+    } else {
+        format!("⏳ Before selectors give out roles:\n{}", lines.join("\n"))
+    };
+    command.channel_id.say(&ctx.http, content).await?;
+
+    Ok(())
+}
+
+async fn requirements(ctx: &Context, guild: GuildId) -> Requirements {
+    let state = store::<StateKey>(ctx).await;
+    let requirements = state.read().await.guilds.get(&guild).copied().unwrap_or_default();
+    requirements
+}
+
+/// Returns when the member becomes eligible for selector roles, or `None` if they already are.
+pub async fn eligible_at(ctx: &Context, member: &Member) -> Option<i64> {
+    let requirements = requirements(ctx, member.guild_id).await;
+    if requirements.is_empty() {
+        return None;
+    }
+
+    let created_at = member.user.id.created_at().timestamp();
+    let joined_at = member.joined_at.map(|joined_at| joined_at.timestamp());
+    requirements.eligible_at(created_at, joined_at, scheduler::now())
+}
+
+/// Remembers a selector reaction from a member who isn't eligible yet, and tells them when they will be.
+pub async fn defer(ctx: &Context, member: &Member, channel: ChannelId, message: MessageId, emoji: Emoji, eligible_at: i64) {
+    let grant = DeferredGrant { guild: member.guild_id, user: member.user.id, channel, message, emoji, eligible_at };
+
+    let state = store::<StateKey>(ctx).await;
+    state.write(|state| {
+        state.deferred.retain(|deferred| (deferred.user, deferred.message, &deferred.emoji) != (grant.user, grant.message, &grant.emoji));
+        state.deferred.push(grant);
+    }).await;
+
+    let content = format!(
+        "⏳ This server only gives out that role to accounts and members that have been around for a while. \
+        You'll get it automatically <t:{}:R>.",
+        eligible_at,
+    );
+    let _ = member.user.direct_message(&ctx.http, CreateMessage::new().content(content)).await;
+}
+
+/// Points deferred reactions on a selector at the message it was moved to, so that they're still
+/// granted once their members become eligible.
+pub async fn selector_moved(ctx: &Context, old: MessageId, channel: ChannelId, new: MessageId) {
+    let state = store::<StateKey>(ctx).await;
+    if !state.read().await.deferred.iter().any(|grant| grant.message == old) {
+        return;
+    }
+    state.write(|state| {
+        for grant in state.deferred.iter_mut().filter(|grant| grant.message == old) {
+            grant.channel = channel;
+            grant.message = new;
+        }
+    }).await;
+}
+
+/// Grants the roles of deferred reactions whose members have become eligible.
+pub async fn retry_deferred(ctx: Context) {
+    let now = scheduler::now();
+    let due: Vec<DeferredGrant> = {
+        let state = store::<StateKey>(&ctx).await;
+        if !state.read().await.deferred.iter().any(|grant| grant.eligible_at <= now) {
+            return;
+        }
+        state.write(|state| {
+            let (due, waiting) = state.deferred.drain(..).partition(|grant| grant.eligible_at <= now);
+            state.deferred = waiting;
+            due
+        }).await
+    };
+
+    for grant in due {
+        reaction_roles::grant_deferred(&ctx, grant.guild, grant.user, grant.channel, grant.message, grant.emoji).await;
+    }
+}
+
+/// Forgets all guilds not in `guilds`, returning the ones that were removed.
+pub async fn retain_guilds(ctx: &Context, guilds: &HashSet<GuildId>) -> Vec<GuildId> {
+    let state = store::<StateKey>(ctx).await;
+    state.write(|state| {
+        let removed = state.guilds.keys().filter(|guild| !guilds.contains(guild)).copied().collect();
+        state.guilds.retain(|guild, _| guilds.contains(guild));
+        state.deferred.retain(|grant| guilds.contains(&grant.guild));
+        removed
+    }).await
+}
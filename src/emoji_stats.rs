@@ -0,0 +1,176 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::time::Duration;
+
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use serenity::model::prelude::*;
+use serenity::prelude::*;
+
+use crate::{CommandError, CommandResult, Persistent, scheduler, store};
+use crate::memreport::{Introspect, Owner};
+
+/// How many days of usage are kept and counted.
+const WINDOW_DAYS: i64 = 30;
+
+/// How many emoji to list at each end of the ranking.
+const LIST_COUNT: usize = 10;
+
+/// How often uses counted in memory are written to the store, so that busy guilds don't rewrite it
+/// on every message.
+pub const FLUSH_PERIOD: Duration = Duration::from_secs(60);
+
+/// Uses counted since the last flush, by guild, emoji and day.
+static COUNTED: std::sync::Mutex<Option<Counts>> = std::sync::Mutex::new(None);
+
+type Counts = HashMap<(GuildId, EmojiId, i64), u64>;
+
+pub struct StateKey;
+
+impl TypeMapKey for StateKey {
+    type Value = Persistent<State>;
+}
+
+#[derive(Serialize, Deserialize, Default, Clone, Eq, PartialEq)]
+pub struct State {
+    guilds: HashMap<GuildId, GuildState>,
+}
+
+impl Introspect for State {
+    fn entries(&self) -> Vec<(Owner, usize)> {
+        self.guilds.iter().map(|(guild, state)| (Owner::Guild(*guild), state.emoji.values().map(BTreeMap::len).sum())).collect()
+    }
+}
+
+#[derive(Serialize, Deserialize, Default, Clone, Eq, PartialEq)]
+struct GuildState {
+    /// Uses of each custom emoji, bucketed by day since the unix epoch.
+    emoji: HashMap<EmojiId, BTreeMap<i64, u64>>,
+}
+
+impl GuildState {
+    fn add(&mut self, emoji: EmojiId, day: i64, count: u64) {
+        *self.emoji.entry(emoji).or_default().entry(day).or_default() += count;
+    }
+
+    /// Drops the buckets that have left the window as of `day`, along with emoji left without any.
+    fn prune(&mut self, day: i64) {
+        for days in self.emoji.values_mut() {
+            *days = days.split_off(&(day - WINDOW_DAYS + 1));
+        }
+        self.emoji.retain(|_, days| !days.is_empty());
+    }
+
+    fn count(&self, emoji: EmojiId, day: i64) -> u64 {
+        self.emoji.get(&emoji)
+            .map(|days| days.range(day - WINDOW_DAYS + 1..).map(|(_, count)| count).sum())
+            .unwrap_or(0)
+    }
+}
+
+fn today() -> i64 {
+    scheduler::now().div_euclid(24 * 60 * 60)
+}
+
+fn record(guild: GuildId, emoji: &[EmojiId]) {
+    if emoji.is_empty() {
+        return;
+    }
+
+    let day = today();
+    let mut counted = COUNTED.lock().unwrap();
+    let counted = counted.get_or_insert_with(HashMap::new);
+    for emoji in emoji {
+        *counted.entry((guild, *emoji, day)).or_default() += 1;
+    }
+}
+
+/// Writes the uses counted since the last flush to the store, and drops the days that have left the
+/// window.
+pub async fn flush(ctx: Context) {
+    let counted = COUNTED.lock().unwrap().take().unwrap_or_default();
+    let day = today();
+
+    let state = store::<StateKey>(&ctx).await;
+    state.write(|state| {
+        for ((guild, emoji, day), count) in counted {
+            state.guilds.entry(guild).or_default().add(emoji, day, count);
+        }
+        for guild in state.guilds.values_mut() {
+            guild.prune(day);
+        }
+        state.guilds.retain(|_, guild| !guild.emoji.is_empty());
+    }).await;
+}
+
+pub fn message(message: &Message) {
+    let guild = match message.guild_id {
+        Some(guild) if !message.author.bot => guild,
+        _ => return,
+    };
+
+    let pattern = Regex::new(r#"<a?:\w+:(\d+)>"#).unwrap();
+    // count each emoji once per message so that spamming one doesn't skew the stats
+    let emoji: HashSet<EmojiId> = pattern.captures_iter(&message.content)
+        .filter_map(|captures| captures[1].parse().ok())
+        .collect();
+
+    record(guild, &emoji.into_iter().collect::<Vec<_>>());
+}
+
+pub fn add_reaction(ctx: &Context, reaction: &Reaction) {
+    if let (Some(guild), ReactionType::Custom { id, .. }) = (reaction.guild_id, &reaction.emoji) {
+        if reaction.user_id != Some(ctx.cache.current_user().id) {
+            record(guild, &[*id]);
+        }
+    }
+}
+
+/// Lists the guild's most and least used custom emoji over the tracking window.
+pub async fn stats(ctx: &Context, command: &Message) -> CommandResult<()> {
+    let guild = command.guild_id.ok_or(CommandError::NotAllowed)?;
+
+    let emojis = guild.emojis(&ctx.http).await?;
+    flush(ctx.clone()).await;
+    let day = today();
+
+    let mut counts: Vec<(u64, String)> = {
+        let state = store::<StateKey>(ctx).await;
+        let state = state.read().await;
+        let guild_state = state.guilds.get(&guild);
+        emojis.iter()
+            .map(|emoji| (guild_state.map(|state| state.count(emoji.id, day)).unwrap_or(0), emoji.to_string()))
+            .collect()
+    };
+
+    if counts.is_empty() {
+        command.reply(ctx, "This server has no custom emoji!").await?;
+        return Ok(());
+    }
+
+    counts.sort_by(|(a, _), (b, _)| b.cmp(a));
+
+    let describe = |(count, emoji): &(u64, String)| format!("{} — {}", emoji, count);
+
+    let mut lines = vec![format!("**Most used emoji** (last {} days)", WINDOW_DAYS)];
+    lines.extend(counts.iter().take(LIST_COUNT).map(describe));
+
+    if counts.len() > LIST_COUNT {
+        lines.push(format!("**Least used emoji** (last {} days)", WINDOW_DAYS));
+        let least = counts.len().saturating_sub(LIST_COUNT).max(LIST_COUNT);
+        lines.extend(counts[least..].iter().rev().map(describe));
+    }
+
+    crate::say_lines(ctx, command.channel_id, &lines).await?;
+
+    Ok(())
+}
+
+/// Forgets all guilds not in `guilds`, returning the ones that were removed.
+pub async fn retain_guilds(ctx: &Context, guilds: &HashSet<GuildId>) -> Vec<GuildId> {
+    let state = store::<StateKey>(ctx).await;
+    state.write(|state| {
+        let removed = state.guilds.keys().filter(|guild| !guilds.contains(guild)).copied().collect();
+        state.guilds.retain(|guild, _| guilds.contains(guild));
+        removed
+    }).await
+}
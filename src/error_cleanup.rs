@@ -0,0 +1,137 @@
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use serenity::model::prelude::*;
+use serenity::prelude::*;
+
+use crate::{CommandError, CommandResult, Persistent, read_only, say_lines, scheduler, store};
+use crate::memreport::{Introspect, Owner};
+
+/// How often replies that are due are deleted.
+pub const PERIOD: Duration = Duration::from_secs(5);
+
+/// The longest error replies can be kept around for before they're deleted.
+pub const MAX_DELAY_SECS: u64 = 60 * 60;
+
+pub struct StateKey;
+
+impl TypeMapKey for StateKey {
+    type Value = Persistent<State>;
+}
+
+#[derive(Serialize, Deserialize, Default, Clone, Eq, PartialEq)]
+pub struct State {
+    guilds: HashMap<GuildId, Cleanup>,
+    /// Messages waiting to be deleted, kept across restarts so that none are left behind.
+    pending: Vec<Deletion>,
+}
+
+impl Introspect for State {
+    fn entries(&self) -> Vec<(Owner, usize)> {
+        let guilds = self.guilds.keys().map(|guild| (Owner::Guild(*guild), 1));
+        let pending = self.pending.iter().map(|deletion| (Owner::Guild(deletion.guild), 1));
+        guilds.chain(pending).collect()
+    }
+}
+
+/// How a guild wants error replies cleaned up.
+#[derive(Serialize, Deserialize, Clone, Copy, Eq, PartialEq)]
+struct Cleanup {
+    after_secs: u64,
+    /// Whether the failing command is deleted along with the reply.
+    commands: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Eq, PartialEq)]
+struct Deletion {
+    guild: GuildId,
+    channel: ChannelId,
+    message: MessageId,
+    at: i64,
+}
+
+pub async fn set_cleanup(ctx: &Context, command: &Message, after_secs: Option<u64>, commands: bool) -> CommandResult<()> {
+    let guild = command.guild_id.ok_or(CommandError::NotAllowed)?;
+
+    let state = store::<StateKey>(ctx).await;
+    state.write(|state| {
+        match after_secs {
+            Some(after_secs) => state.guilds.insert(guild, Cleanup { after_secs, commands }),
+            None => state.guilds.remove(&guild),
+        };
+    }).await;
+
+    Ok(())
+}
+
+pub async fn status(ctx: &Context, command: &Message) -> CommandResult<()> {
+    let guild = command.guild_id.ok_or(CommandError::NotAllowed)?;
+
+    let cleanup = store::<StateKey>(ctx).await.read().await.guilds.get(&guild).copied();
+    let line = match cleanup {
+        Some(Cleanup { after_secs, commands: true }) => format!("🧹 Error replies are deleted after {} seconds, along with the commands that failed.", after_secs),
+        Some(Cleanup { after_secs, commands: false }) => format!("🧹 Error replies are deleted after {} seconds.", after_secs),
+        None => "Error replies are kept. Clean them up with `error cleanup <seconds>`.".to_owned(),
+    };
+    say_lines(ctx, command.channel_id, &[line]).await?;
+
+    Ok(())
+}
+
+/// Remembers to delete an error reply, and the command it's about if the guild wants that too.
+pub async fn error_replied(ctx: &Context, command: &Message, reply: &Message) {
+    let guild = match command.guild_id {
+        Some(guild) => guild,
+        None => return,
+    };
+
+    let state = store::<StateKey>(ctx).await;
+    let cleanup = match state.read().await.guilds.get(&guild).copied() {
+        Some(cleanup) => cleanup,
+        None => return,
+    };
+
+    let at = scheduler::now() + cleanup.after_secs as i64;
+    let deletion = |message| Deletion { guild, channel: command.channel_id, message, at };
+    state.write(|state| {
+        state.pending.push(deletion(reply.id));
+        if cleanup.commands {
+            state.pending.push(deletion(command.id));
+        }
+    }).await;
+}
+
+/// Deletes the error replies that are due.
+pub async fn delete_due(ctx: Context) {
+    let now = scheduler::now();
+    let state = store::<StateKey>(&ctx).await;
+    if !state.read().await.pending.iter().any(|deletion| deletion.at <= now) {
+        return;
+    }
+
+    let due: Vec<Deletion> = state.write(|state| {
+        let (due, waiting) = state.pending.drain(..).partition(|deletion| deletion.at <= now);
+        state.pending = waiting;
+        due
+    }).await;
+
+    for deletion in due {
+        if read_only::blocks(&ctx, deletion.guild, || format!("deleted error reply {}", deletion.message)).await {
+            continue;
+        }
+        // the message may well have been deleted by hand already
+        let _ = deletion.channel.delete_message(&ctx.http, deletion.message).await;
+    }
+}
+
+/// Forgets all guilds not in `guilds`, returning the ones that were removed.
+pub async fn retain_guilds(ctx: &Context, guilds: &HashSet<GuildId>) -> Vec<GuildId> {
+    let state = store::<StateKey>(ctx).await;
+    state.write(|state| {
+        let removed = state.guilds.keys().filter(|guild| !guilds.contains(guild)).copied().collect();
+        state.guilds.retain(|guild, _| guilds.contains(guild));
+        state.pending.retain(|deletion| guilds.contains(&deletion.guild));
+        removed
+    }).await
+}
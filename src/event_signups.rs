@@ -0,0 +1,227 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+use serenity::builder::{CreateAllowedMentions, CreateMessage, EditMessage};
+use serenity::model::prelude::*;
+use serenity::prelude::*;
+
+use crate::{CommandError, CommandResult, members, Persistent, store};
+use crate::memreport::{Introspect, Owner};
+
+const SIGNUP_EMOJI: &str = "✅";
+
+pub struct StateKey;
+
+impl TypeMapKey for StateKey {
+    type Value = Persistent<State>;
+}
+
+#[derive(Serialize, Deserialize, Default, Clone, Eq, PartialEq)]
+pub struct State {
+    events: HashMap<MessageId, Event>,
+}
+
+impl Introspect for State {
+    fn entries(&self) -> Vec<(Owner, usize)> {
+        // events only know their message, so they can't be tied to a guild
+        self.events.values().map(|event| (Owner::Unknown, 1 + event.attendees.len() + event.waitlist.len())).collect()
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Eq, PartialEq)]
+pub struct Event {
+    title: String,
+    role: RoleId,
+    capacity: usize,
+    /// Members holding a spot, in signup order.
+    attendees: Vec<UserId>,
+    /// Members waiting for a spot to free up, in signup order.
+    waitlist: Vec<UserId>,
+}
+
+/// How a member's reaction changed the event.
+enum Change {
+    Attending,
+    Waitlisted,
+    Withdrew { promoted: Option<UserId> },
+    Unchanged,
+}
+
+impl Event {
+    fn sign_up(&mut self, user: UserId) -> Change {
+        if self.attendees.contains(&user) || self.waitlist.contains(&user) {
+            Change::Unchanged
+        } else if self.attendees.len() < self.capacity {
+            self.attendees.push(user);
+            Change::Attending
+        } else {
+            self.waitlist.push(user);
+            Change::Waitlisted
+        }
+    }
+
+    fn withdraw(&mut self, user: UserId) -> Change {
+        if let Some(index) = self.attendees.iter().position(|attendee| *attendee == user) {
+            self.attendees.remove(index);
+
+            let promoted = if !self.waitlist.is_empty() {
+                let promoted = self.waitlist.remove(0);
+                self.attendees.push(promoted);
+                Some(promoted)
+            } else {
+                None
+            };
+
+            Change::Withdrew { promoted }
+        } else if let Some(index) = self.waitlist.iter().position(|waiting| *waiting == user) {
+            self.waitlist.remove(index);
+            Change::Withdrew { promoted: None }
+        } else {
+            Change::Unchanged
+        }
+    }
+
+    fn render(&self) -> String {
+        let mut content = format!(
+            "**{}**\nReact with {} to sign up for <@&{}> ({}/{})",
+            self.title, SIGNUP_EMOJI, self.role.get(), self.attendees.len(), self.capacity,
+        );
+        if !self.waitlist.is_empty() {
+            content.push_str(&format!("\n{} waiting for a spot", self.waitlist.len()));
+        }
+        content
+    }
+}
+
+pub async fn create_event(ctx: &Context, command: &Message, title: &str, capacity: usize, role: RoleId) -> CommandResult<()> {
+    if command.guild_id.is_none() {
+        return Err(CommandError::NotAllowed);
+    }
+
+    let event = Event {
+        title: title.to_owned(),
+        role,
+        capacity,
+        attendees: Vec::new(),
+        waitlist: Vec::new(),
+    };
+
+    let message = command.channel_id.send_message(&ctx.http, CreateMessage::new()
+        .content(event.render())
+        .allowed_mentions(CreateAllowedMentions::new())
+    ).await?;
+
+    let state = store::<StateKey>(ctx).await;
+    state.write(|state| {
+        state.events.insert(message.id, event);
+    }).await;
+
+    message.react(ctx, ReactionType::Unicode(SIGNUP_EMOJI.to_owned())).await?;
+    command.delete(ctx).await?;
+
+    Ok(())
+}
+
+fn is_signup_reaction(reaction: &Reaction) -> bool {
+    matches!(&reaction.emoji, ReactionType::Unicode(emoji) if emoji == SIGNUP_EMOJI)
+}
+
+async fn is_event(ctx: &Context, message: MessageId) -> bool {
+    let state = store::<StateKey>(ctx).await;
+    let is_event = state.read().await.events.contains_key(&message);
+    is_event
+}
+
+pub async fn add_reaction(ctx: &Context, reaction: &Reaction) -> serenity::Result<()> {
+    let (guild, user) = match (reaction.guild_id, reaction.user_id) {
+        (Some(guild), Some(user)) if is_signup_reaction(reaction) => (guild, user),
+        _ => return Ok(()),
+    };
+
+    if !is_event(ctx, reaction.message_id).await {
+        return Ok(());
+    }
+
+    let member = members::member(ctx, guild, user).await?;
+    if member.user.bot {
+        return Ok(());
+    }
+
+    let state = store::<StateKey>(ctx).await;
+    let (change, role) = state.write(|state| {
+        match state.events.get_mut(&reaction.message_id) {
+            Some(event) => (event.sign_up(user), Some(event.role)),
+            None => (Change::Unchanged, None),
+        }
+    }).await;
+
+    match (change, role) {
+        (Change::Attending, Some(role)) => member.add_role(&ctx.http, role).await?,
+        (Change::Waitlisted, _) => (),
+        _ => return Ok(()),
+    }
+
+    refresh_message(ctx, reaction.channel_id, reaction.message_id).await
+}
+
+pub async fn remove_reaction(ctx: &Context, reaction: &Reaction) -> serenity::Result<()> {
+    let (guild, user) = match (reaction.guild_id, reaction.user_id) {
+        (Some(guild), Some(user)) if is_signup_reaction(reaction) => (guild, user),
+        _ => return Ok(()),
+    };
+
+    if !is_event(ctx, reaction.message_id).await {
+        return Ok(());
+    }
+
+    let state = store::<StateKey>(ctx).await;
+    let (change, role) = state.write(|state| {
+        match state.events.get_mut(&reaction.message_id) {
+            Some(event) => (event.withdraw(user), Some(event.role)),
+            None => (Change::Unchanged, None),
+        }
+    }).await;
+
+    let (promoted, role) = match (change, role) {
+        (Change::Withdrew { promoted }, Some(role)) => (promoted, role),
+        _ => return Ok(()),
+    };
+
+    // the member may have withdrawn from the waitlist, in which case they never had the role
+    let member = members::member(ctx, guild, user).await?;
+    if member.roles.contains(&role) {
+        member.remove_role(&ctx.http, role).await?;
+    }
+
+    if let Some(promoted) = promoted {
+        let promoted = members::member(ctx, guild, promoted).await?;
+        promoted.add_role(&ctx.http, role).await?;
+    }
+
+    refresh_message(ctx, reaction.channel_id, reaction.message_id).await
+}
+
+async fn refresh_message(ctx: &Context, channel: ChannelId, message: MessageId) -> serenity::Result<()> {
+    let content = {
+        let state = store::<StateKey>(ctx).await;
+        let content = state.read().await.events.get(&message).map(|event| event.render());
+        content
+    };
+
+    if let Some(content) = content {
+        channel.edit_message(&ctx.http, message, EditMessage::new().content(content)).await?;
+    }
+
+    Ok(())
+}
+
+pub async fn delete_message(ctx: &Context, message: MessageId) {
+    if !is_event(ctx, message).await {
+        return;
+    }
+
+    let state = store::<StateKey>(ctx).await;
+    state.write(|state| {
+        state.events.remove(&message);
+    }).await;
+}
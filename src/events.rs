@@ -0,0 +1,186 @@
+use std::path::PathBuf;
+
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use serenity::model::prelude::*;
+use serenity::prelude::*;
+use tokio::sync::broadcast;
+
+use crate::{history, scheduler, webhooks};
+
+/// How many events a slow subscriber may fall behind before it starts missing them.
+const CAPACITY: usize = 256;
+
+pub struct BusKey;
+
+impl TypeMapKey for BusKey {
+    type Value = Bus;
+}
+
+/// The event bus is off unless a socket path is configured.
+#[derive(Serialize, Deserialize, Default, Clone, Eq, PartialEq)]
+pub struct EventsConfig {
+    /// Path of the Unix socket to publish events on.
+    #[serde(default)]
+    pub socket: Option<PathBuf>,
+}
+
+/// An event published to subscribers as a single line of JSON, tagged by `type` and stamped with
+/// the unix time `at` which it happened.
+#[derive(Serialize, Clone, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Event {
+    /// Roles were given to a member by one of our features.
+    RolesGranted { guild: GuildId, user: UserId, roles: Vec<RoleId>, source: RoleSource },
+    /// Roles were taken from a member by one of our features.
+    RolesRevoked { guild: GuildId, user: UserId, roles: Vec<RoleId>, source: RoleSource },
+    MemberJoined { guild: GuildId, user: UserId },
+    MemberLeft { guild: GuildId, user: UserId },
+    /// A selector was registered, edited, removed or had its message deleted.
+    SelectorChanged { guild: GuildId, channel: ChannelId, message: MessageId, change: SelectorChange },
+}
+
+impl Event {
+    /// The `type` of every event, for validating subscriptions.
+    pub const TYPES: &'static [&'static str] = &["roles_granted", "roles_revoked", "member_joined", "member_left", "selector_changed"];
+}
+
+#[derive(Serialize, Clone, Copy, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum SelectorChange {
+    Registered,
+    Edited,
+    Removed,
+    Deleted,
+    Moved,
+}
+
+#[derive(Serialize, Clone, Copy, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum RoleSource {
+    Selector,
+    Persistence,
+    /// Taken away because the member lost a role it requires.
+    Dependency,
+}
+
+#[derive(Serialize)]
+struct Envelope<'a> {
+    at: i64,
+    #[serde(flatten)]
+    event: &'a Event,
+}
+
+/// A handle for publishing events to whoever is subscribed.
+#[derive(Clone)]
+pub struct Bus {
+    sender: Option<broadcast::Sender<String>>,
+}
+
+impl Bus {
+    /// A bus that drops every event, for when no socket is configured.
+    pub fn disabled() -> Self {
+        Bus { sender: None }
+    }
+
+    /// Starts listening for subscribers on the configured socket, if any.
+    pub async fn start(config: &EventsConfig) -> Self {
+        match &config.socket {
+            Some(path) => match listen(path.clone()).await {
+                Ok(sender) => Bus { sender: Some(sender) },
+                Err(err) => {
+                    error!("failed to open event socket at {:?}: {:?}", path, err);
+                    Bus::disabled()
+                }
+            },
+            None => Bus::disabled(),
+        }
+    }
+
+    fn publish(&self, event: &Value) {
+        if let Some(sender) = &self.sender {
+            // sending only fails when nobody is subscribed
+            if sender.receiver_count() > 0 {
+                let _ = sender.send(event.to_string());
+            }
+        }
+    }
+}
+
+/// Publishes an event to socket subscribers and the guild's webhooks.
+pub async fn publish(ctx: &Context, event: Event) {
+    let at = scheduler::now();
+    history::record_event(ctx, at, &event).await;
+
+    let event = serde_json::to_value(Envelope { at, event: &event }).expect("failed to serialize event");
+
+    {
+        let data = ctx.data.read().await;
+        if let Some(bus) = data.get::<BusKey>() {
+            bus.publish(&event);
+        }
+    }
+
+    webhooks::dispatch(ctx, &event).await;
+}
+
+#[cfg(unix)]
+async fn listen(path: PathBuf) -> std::io::Result<broadcast::Sender<String>> {
+    use std::os::unix::fs::FileTypeExt;
+    use tokio::io::AsyncWriteExt;
+    use tokio::net::UnixListener;
+
+    // a socket left behind by a previous run would stop us from binding, but anything else at the
+    // path is likely a mistake in the config and mustn't be deleted
+    match std::fs::symlink_metadata(&path) {
+        Ok(metadata) if metadata.file_type().is_socket() => std::fs::remove_file(&path)?,
+        Ok(_) => {
+            let message = format!("{} exists and isn't a socket", path.display());
+            return Err(std::io::Error::new(std::io::ErrorKind::AlreadyExists, message));
+        }
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => (),
+        Err(err) => return Err(err),
+    }
+
+    let listener = UnixListener::bind(&path)?;
+    let (sender, _) = broadcast::channel(CAPACITY);
+    info!("publishing events on {:?}", path);
+
+    let subscribe = sender.clone();
+    tokio::spawn(async move {
+        loop {
+            let mut stream = match listener.accept().await {
+                Ok((stream, _)) => stream,
+                Err(err) => {
+                    error!("failed to accept event subscriber: {:?}", err);
+                    continue;
+                }
+            };
+
+            let mut receiver = subscribe.subscribe();
+            tokio::spawn(async move {
+                loop {
+                    let line = match receiver.recv().await {
+                        Ok(line) => line,
+                        Err(broadcast::error::RecvError::Lagged(missed)) => {
+                            warn!("event subscriber fell behind and missed {} events", missed);
+                            continue;
+                        }
+                        Err(broadcast::error::RecvError::Closed) => break,
+                    };
+                    if stream.write_all(format!("{}\n", line).as_bytes()).await.is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+    });
+
+    Ok(sender)
+}
+
+#[cfg(not(unix))]
+async fn listen(_path: PathBuf) -> std::io::Result<broadcast::Sender<String>> {
+    Err(std::io::Error::new(std::io::ErrorKind::Unsupported, "the event bus requires Unix sockets"))
+}
@@ -0,0 +1,114 @@
+use std::collections::{HashMap, HashSet};
+
+use serde::{Deserialize, Serialize};
+use serenity::model::prelude::*;
+use serenity::prelude::*;
+
+use crate::{CommandError, CommandResult, say_lines};
+
+pub struct ConfigKey;
+
+impl TypeMapKey for ConfigKey {
+    type Value = ExperimentsConfig;
+}
+
+/// Features that are still being tried out, and so only run where they've been turned on.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub enum Feature {
+    /// Selector sweeps handling missed reactions as new ones, rather than only reporting them.
+    SweepFix,
+}
+
+impl Feature {
+    pub const ALL: &'static [Feature] = &[Feature::SweepFix];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Feature::SweepFix => "sweep-fix",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Feature> {
+        Feature::ALL.iter().copied().find(|feature| feature.name() == name)
+    }
+}
+
+/// Where experimental features run: everywhere in the canary guilds, and wherever a feature's own
+/// overrides say.
+#[derive(Serialize, Deserialize, Default, Clone, Eq, PartialEq)]
+pub struct ExperimentsConfig {
+    /// Guilds that get every experimental feature ahead of everyone else.
+    #[serde(default)]
+    pub canary_guilds: HashSet<GuildId>,
+    /// Overrides for single features, by name.
+    #[serde(default)]
+    pub features: HashMap<String, FeatureConfig>,
+}
+
+#[derive(Serialize, Deserialize, Default, Clone, Eq, PartialEq)]
+pub struct FeatureConfig {
+    /// Turns the feature on everywhere, like once it has been tried out in the canary guilds.
+    #[serde(default)]
+    pub everywhere: bool,
+    /// Channels the feature runs in, even outside the canary guilds.
+    #[serde(default)]
+    pub channels: HashSet<ChannelId>,
+    /// Channels the feature never runs in, even within the canary guilds.
+    #[serde(default)]
+    pub disabled_channels: HashSet<ChannelId>,
+}
+
+impl ExperimentsConfig {
+    /// Whether a feature runs in a guild, or in one of its channels. Channel overrides come first,
+    /// then the feature being on everywhere, and last the canary guilds.
+    pub fn is_enabled(&self, feature: Feature, guild: GuildId, channel: Option<ChannelId>) -> bool {
+        let config = self.features.get(feature.name());
+        if let (Some(config), Some(channel)) = (config, channel) {
+            if config.disabled_channels.contains(&channel) {
+                return false;
+            }
+            if config.channels.contains(&channel) {
+                return true;
+            }
+        }
+        config.is_some_and(|config| config.everywhere) || self.canary_guilds.contains(&guild)
+    }
+
+    /// Feature names in the config that don't belong to any feature.
+    pub fn unknown_features(&self) -> Vec<&str> {
+        let mut unknown: Vec<&str> = self.features.keys()
+            .map(String::as_str)
+            .filter(|name| Feature::from_name(name).is_none())
+            .collect();
+        unknown.sort();
+        unknown
+    }
+}
+
+async fn config(ctx: &Context) -> ExperimentsConfig {
+    let data = ctx.data.read().await;
+    data.get::<ConfigKey>().cloned().unwrap_or_default()
+}
+
+pub async fn is_enabled(ctx: &Context, feature: Feature, guild: GuildId, channel: Option<ChannelId>) -> bool {
+    config(ctx).await.is_enabled(feature, guild, channel)
+}
+
+/// Lists the experimental features and whether they run in the channel of the command.
+pub async fn status(ctx: &Context, command: &Message) -> CommandResult<()> {
+    let guild = command.guild_id.ok_or(CommandError::NotAllowed)?;
+    let config = config(ctx).await;
+
+    let mut lines = vec![if config.canary_guilds.contains(&guild) {
+        "🐤 This is a canary server, so experimental features run here first:".to_owned()
+    } else {
+        "🧪 Experimental features:".to_owned()
+    }];
+    lines.extend(Feature::ALL.iter().map(|feature| {
+        let mark = if config.is_enabled(*feature, guild, Some(command.channel_id)) { "✅" } else { "⬜" };
+        format!("{} `{}`", mark, feature.name())
+    }));
+    say_lines(ctx, command.channel_id, &lines).await?;
+
+    Ok(())
+}
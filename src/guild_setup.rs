@@ -0,0 +1,243 @@
+use std::collections::{BTreeSet, HashMap};
+
+use regex::{Captures, Regex};
+use serde::{Deserialize, Serialize};
+use serenity::builder::{CreateAllowedMentions, CreateAttachment, CreateMessage, EditRole};
+use serenity::model::prelude::*;
+use serenity::prelude::*;
+
+use crate::{autopin, CommandError, CommandResult, persistent_roles, ping_tracker, quotas, reaction_roles};
+use crate::quotas::Quota;
+use crate::ping_tracker::CooldownAction;
+use crate::reaction_roles::UnmappedPolicy;
+
+const SETUP_FILE_NAME: &str = "guild-setup.json";
+
+/// A portable description of the bot's configuration for a guild. Roles and channels are referred
+/// to by name so that the setup can be applied to a different guild.
+#[derive(Serialize, Deserialize, Default)]
+pub struct GuildSetup {
+    #[serde(default)]
+    pub selectors: Vec<SelectorSetup>,
+    #[serde(default)]
+    pub persisted_roles: Vec<String>,
+    #[serde(default)]
+    pub ping_cooldowns: Vec<PingCooldownSetup>,
+    #[serde(default)]
+    pub autopin: Vec<AutopinSetup>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SelectorSetup {
+    pub channel: String,
+    /// The selector message content, with role mentions replaced by `{role:Name}` placeholders.
+    pub content: String,
+    pub enabled: bool,
+    pub unmapped: UnmappedPolicy,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct PingCooldownSetup {
+    pub role: String,
+    pub cooldown_secs: u64,
+    pub action: CooldownAction,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct AutopinSetup {
+    pub channel: String,
+    pub threshold: u64,
+}
+
+impl GuildSetup {
+    pub fn summary(&self) -> String {
+        format!(
+            "{} selectors, {} persisted roles, {} ping cooldowns and {} autopin channels",
+            self.selectors.len(), self.persisted_roles.len(), self.ping_cooldowns.len(), self.autopin.len(),
+        )
+    }
+
+    pub fn to_attachment(&self, file_name: &str) -> CreateAttachment {
+        let bytes = serde_json::to_vec_pretty(self).expect("failed to serialize guild setup");
+        CreateAttachment::bytes(bytes, file_name)
+    }
+}
+
+/// Describes the bot's configuration for a guild, as `export guild-setup` and backups hand it out.
+pub async fn capture(ctx: &Context, guild: GuildId) -> serenity::Result<GuildSetup> {
+    let roles: HashMap<RoleId, String> = ctx.http.get_guild_roles(guild).await?
+        .into_iter()
+        .map(|role| (role.id, role.name))
+        .collect();
+    let channels = guild.channels(&ctx.http).await?;
+
+    let role_name = |role: &RoleId| roles.get(role).cloned();
+    let channel_name = |channel: &ChannelId| channels.get(channel).map(|channel| channel.name.clone());
+
+    let mut setup = GuildSetup::default();
+
+    for (message, entry) in reaction_roles::selectors_in(ctx, &channels).await {
+        let channel = match entry.channel {
+            Some(channel) => channel,
+            None => continue,
+        };
+        if let (Some(channel_name), Ok(message)) = (channel_name(&channel), channel.message(&ctx.http, message).await) {
+            setup.selectors.push(SelectorSetup {
+                channel: channel_name,
+                content: replace_role_mentions(&message.content, |role| role_name(&role)),
+                enabled: entry.selector.is_enabled(),
+                unmapped: entry.selector.unmapped_policy().clone(),
+            });
+        }
+    }
+
+    setup.persisted_roles = persistent_roles::persisted_roles(ctx, guild).await.iter()
+        .filter_map(role_name)
+        .collect();
+
+    setup.ping_cooldowns = ping_tracker::cooldowns(ctx, guild).await.into_iter()
+        .filter_map(|(role, cooldown_secs, action)| Some(PingCooldownSetup { role: role_name(&role)?, cooldown_secs, action }))
+        .collect();
+
+    setup.autopin = autopin::thresholds(ctx, guild).await.into_iter()
+        .filter_map(|(channel, threshold)| Some(AutopinSetup { channel: channel_name(&channel)?, threshold }))
+        .collect();
+
+    Ok(setup)
+}
+
+pub async fn export(ctx: &Context, command: &Message) -> CommandResult<()> {
+    let guild = command.guild_id.ok_or(CommandError::NotAllowed)?;
+
+    let setup = capture(ctx, guild).await?;
+    let content = format!("Exported {}.", setup.summary());
+    command.channel_id.send_files(&ctx.http, vec![setup.to_attachment(SETUP_FILE_NAME)], CreateMessage::new().content(content)).await?;
+
+    Ok(())
+}
+
+pub async fn import(ctx: &Context, command: &Message, create_roles: bool) -> CommandResult<()> {
+    let guild = command.guild_id.ok_or(CommandError::NotAllowed)?;
+
+    let attachment = command.attachments.first().ok_or(CommandError::MissingAttachment)?;
+    let bytes = attachment.download().await?;
+    let setup: GuildSetup = serde_json::from_slice(&bytes).map_err(|_| CommandError::MalformedSetup)?;
+
+    let mut roles: HashMap<String, RoleId> = ctx.http.get_guild_roles(guild).await?
+        .into_iter()
+        .map(|role| (role.name, role.id))
+        .collect();
+    let channels: HashMap<String, ChannelId> = guild.channels(&ctx.http).await?
+        .into_iter()
+        .map(|(id, channel)| (channel.name, id))
+        .collect();
+
+    let persisted = persistent_roles::persisted_roles(ctx, guild).await;
+    let newly_persisted = setup.persisted_roles.iter()
+        .filter(|name| !roles.get(*name).is_some_and(|role| persisted.contains(role)))
+        .count();
+    quotas::check(ctx, guild, Quota::Selectors, setup.selectors.len()).await?;
+    quotas::check(ctx, guild, Quota::PersistedRoles, newly_persisted).await?;
+
+    let mut report = Vec::new();
+
+    if create_roles {
+        let placeholder = role_placeholder_pattern();
+        let mut wanted: BTreeSet<String> = setup.persisted_roles.iter().cloned()
+            .chain(setup.ping_cooldowns.iter().map(|cooldown| cooldown.role.clone()))
+            .collect();
+        for selector in &setup.selectors {
+            wanted.extend(placeholder.captures_iter(&selector.content).map(|captures| captures[1].to_owned()));
+        }
+
+        wanted.retain(|name| !roles.contains_key(name));
+
+        for name in wanted {
+            let role = guild.create_role(&ctx.http, EditRole::new().name(&name)).await?;
+            report.push(format!("Created role `{}`", name));
+            roles.insert(name, role.id);
+        }
+    }
+
+    for selector in &setup.selectors {
+        let channel = match channels.get(&selector.channel) {
+            Some(channel) => *channel,
+            None => {
+                report.push(format!("Skipped selector in missing channel `#{}`", selector.channel));
+                continue;
+            }
+        };
+
+        let content = replace_role_placeholders(&selector.content, |name| roles.get(name).copied());
+        let message = channel.send_message(&ctx.http, CreateMessage::new().content(&content).allowed_mentions(CreateAllowedMentions::new())).await?;
+
+        let (enabled, unmapped) = (selector.enabled, selector.unmapped.clone());
+        reaction_roles::register_selector(ctx, &message, command.author.id, |entry| {
+            entry.selector.set_enabled(enabled);
+            entry.selector.set_unmapped_policy(unmapped);
+        }).await;
+        report.push(format!("Posted selector in <#{}>", channel.get()));
+    }
+
+    let mut persisted = Vec::new();
+    for name in &setup.persisted_roles {
+        match roles.get(name) {
+            Some(role) => persisted.push(*role),
+            None => report.push(format!("Skipped persisting missing role `{}`", name)),
+        }
+    }
+    if !persisted.is_empty() {
+        persistent_roles::persist_roles(ctx, guild, &persisted).await?;
+        report.push(format!("Persisting {} roles", persisted.len()));
+    }
+
+    for cooldown in &setup.ping_cooldowns {
+        match roles.get(&cooldown.role) {
+            Some(role) => ping_tracker::set_cooldown(ctx, guild, *role, Some((cooldown.cooldown_secs, cooldown.action))).await,
+            None => report.push(format!("Skipped ping cooldown for missing role `{}`", cooldown.role)),
+        }
+    }
+
+    for autopin in &setup.autopin {
+        match channels.get(&autopin.channel) {
+            Some(channel) => autopin::set_channel_threshold(ctx, guild, *channel, Some(autopin.threshold)).await,
+            None => report.push(format!("Skipped autopin for missing channel `#{}`", autopin.channel)),
+        }
+    }
+
+    if report.is_empty() {
+        report.push("Nothing to import!".to_owned());
+    }
+
+    command.channel_id.send_message(&ctx.http, CreateMessage::new().content(report.join("\n")).allowed_mentions(CreateAllowedMentions::new())).await?;
+
+    Ok(())
+}
+
+fn role_placeholder_pattern() -> Regex {
+    Regex::new(r#"\{role:([^}]*)\}"#).unwrap()
+}
+
+/// Replaces `<@&id>` role mentions with `{role:Name}` placeholders, leaving unknown roles untouched.
+fn replace_role_mentions<F>(content: &str, name: F) -> String
+    where F: Fn(RoleId) -> Option<String>
+{
+    let mention = Regex::new(r#"<@&(\d+)>"#).unwrap();
+    mention.replace_all(content, |captures: &Captures| {
+        captures[1].parse().ok()
+            .and_then(&name)
+            .map(|name| format!("{{role:{}}}", name))
+            .unwrap_or_else(|| captures[0].to_owned())
+    }).into_owned()
+}
+
+/// Replaces `{role:Name}` placeholders with role mentions, leaving unknown roles untouched.
+fn replace_role_placeholders<F>(content: &str, role: F) -> String
+    where F: Fn(&str) -> Option<RoleId>
+{
+    role_placeholder_pattern().replace_all(content, |captures: &Captures| {
+        role(&captures[1])
+            .map(|role| format!("<@&{}>", role.get()))
+            .unwrap_or_else(|| captures[0].to_owned())
+    }).into_owned()
+}
@@ -0,0 +1,168 @@
+use serenity::model::prelude::*;
+use serenity::prelude::*;
+
+use crate::{CommandResult, message_permissions, say_lines};
+
+/// A command as `help` shows it, with the permissions a member needs to use it.
+pub struct CommandInfo {
+    pub group: &'static str,
+    pub usage: &'static str,
+    pub summary: &'static str,
+    pub permissions: Permissions,
+}
+
+const fn command(group: &'static str, usage: &'static str, summary: &'static str, permissions: Permissions) -> CommandInfo {
+    CommandInfo { group, usage, summary, permissions }
+}
+
+const EVERYONE: Permissions = Permissions::empty();
+const ROLES: Permissions = Permissions::MANAGE_ROLES;
+const SERVER: Permissions = Permissions::MANAGE_GUILD;
+const KICK: Permissions = Permissions::KICK_MEMBERS;
+
+/// The text commands, in the order `help` lists them. Commands only the bot's owner can use are
+/// left out.
+pub const COMMANDS: &[CommandInfo] = &[
+    command("Roles", "roles", "lists the roles you can get from selectors", EVERYONE),
+    command("Roles", "sync my roles", "fixes your reactions to match the roles you have", EVERYONE),
+    command("Roles", "apply for <role>", "applies for a role that needs answering questions", EVERYONE),
+    command("Roles", "set application <role> <channel> <questions>", "makes a role need answering questions", ROLES),
+    command("Selectors", "add role selector <message>", "turns a message into a role selector", ROLES),
+    command("Selectors", "list role selectors", "lists the selectors of the server", ROLES),
+    command("Selectors", "remove role selector <selector>", "stops a message being a selector", ROLES),
+    command("Selectors", "restore role selector <selector>", "brings back a removed selector", ROLES),
+    command("Selectors", "move role selector <selector> <link>", "moves a selector to another message", ROLES),
+    command("Selectors", "name role selector <selector> <name>", "names a selector to refer to it by", ROLES),
+    command("Selectors", "pause role selector <selector>", "stops a selector giving roles for now", ROLES),
+    command("Selectors", "resume role selector <selector>", "lets a paused selector give roles again", ROLES),
+    command("Selectors", "refresh role selector <selector>", "reads a selector's message again", ROLES),
+    command("Selectors", "set role selector <selector> <setting>", "changes how a selector works", ROLES),
+    command("Selectors", "stats role selector <selector>", "shows how a selector is used", ROLES),
+    command("Selectors", "generate role selector from-category <category>", "makes a selector for the channels of a category", ROLES.union(Permissions::MANAGE_CHANNELS)),
+    command("Selectors", "clear reactions <selector>", "removes all reactions from a retired selector", Permissions::MANAGE_MESSAGES),
+    command("Selectors", "undo", "takes back the last change to a selector", ROLES),
+    command("Selectors", "selector ban <user>", "stops a member using selectors", ROLES),
+    command("Selectors", "selector unban <user>", "lets a member use selectors again", ROLES),
+    command("Selectors", "selector bans", "lists members who can't use selectors", ROLES),
+    command("Selectors", "onboarding dm <selectors>", "offers new members the roles of selectors over DM", ROLES),
+    command("Selectors", "progress role <role> [count] <message links>", "gives a role for reacting to enough of a set of messages", ROLES),
+    command("Selectors", "progress roles", "lists roles given for reacting to a set of messages", ROLES),
+    command("Selectors", "selector requirements", "shows what members need before using selectors", ROLES),
+    command("Selectors", "selector sweep", "shows how selectors are checked for missed reactions", ROLES),
+    command("Roles", "add role persist <roles>", "gives roles back to members who leave and rejoin", ROLES),
+    command("Roles", "add role persist matching <pattern>", "persists every role whose name matches, after confirming", ROLES),
+    command("Roles", "remove role persist <roles>", "stops giving roles back on rejoin", ROLES),
+    command("Roles", "set persist on-ban <keep|freeze|purge>", "decides what bans do to persisted roles", SERVER),
+    command("Roles", "role cap <role> <limit>", "limits how many members can have a role", ROLES),
+    command("Roles", "role requires <role> for <role>", "makes a role depend on another", ROLES),
+    command("Roles", "role dependencies", "lists roles that depend on others", ROLES),
+    command("Roles", "role color <role> <colour>", "changes the colour of a role", ROLES),
+    command("Roles", "role icon <role> <icon>", "changes the icon of a role", ROLES),
+    command("Roles", "role channel link <role> <channel>", "makes a channel only visible to a role", ROLES.union(Permissions::MANAGE_CHANNELS)),
+    command("Roles", "role channel links", "lists channels linked to roles", Permissions::MANAGE_CHANNELS),
+    command("Moderation", "warn <user> <reason>", "warns a member and opens a case", KICK),
+    command("Moderation", "case <id>", "shows a moderation case", KICK),
+    command("Moderation", "case edit <id> reason <reason>", "changes the reason of a case", KICK),
+    command("Moderation", "cases <user>", "lists the cases of a member", KICK),
+    command("Moderation", "appeals channel <channel>", "lets kicked members appeal", KICK),
+    command("Moderation", "verification", "shows how new members are verified", SERVER),
+    command("Moderation", "verification on <role> [channel]", "makes new members verify before joining in", SERVER.union(ROLES).union(KICK)),
+    command("Moderation", "screening", "shows how membership screening is handled", SERVER),
+    command("Moderation", "screening role <role>", "gives a role to members who pass screening", SERVER.union(ROLES)),
+    command("Moderation", "ping cooldown <role> <seconds> <action>", "limits how often a role can be pinged", ROLES),
+    command("Moderation", "ping stats <role>", "shows how often a role is pinged", ROLES),
+    command("Channels", "autopin <threshold>", "pins messages with enough 📌 reactions", Permissions::MANAGE_MESSAGES),
+    command("Channels", "slowmode schedule <channel> <days> <window> <rate>", "turns on slowmode at set times", Permissions::MANAGE_CHANNELS),
+    command("Channels", "event create <title> cap <capacity> <role>", "posts an event members can sign up for", ROLES),
+    command("Server", "setup status", "shows which features are set up", SERVER),
+    command("Server", "doctor", "lists anything that stops the bot from working", SERVER),
+    command("Server", "set log channel <channel>", "sets where the bot logs what it does", SERVER),
+    command("Server", "set timezone <zone>", "sets the time zone for schedules", SERVER),
+    command("Server", "export config", "uploads the server's selectors as a file", SERVER),
+    command("Server", "export guild-setup", "uploads the server's roles and channels as a file", SERVER),
+    command("Server", "import guild-setup", "applies a guild setup file", SERVER.union(ROLES)),
+    command("Server", "import config", "applies an edited config file", SERVER.union(ROLES)),
+    command("Server", "backup channel <channel>", "posts a backup of the server's setup there every week", SERVER),
+    command("Server", "backup now", "posts a backup straight away", SERVER),
+    command("Server", "config sync", "shows whether the server follows a config file", SERVER),
+    command("Server", "config sync <url>", "keeps the server in line with a config file", SERVER.union(ROLES)),
+    command("Server", "webhook add <url> <events>", "sends events to a webhook", SERVER),
+    command("Server", "list webhooks", "lists the server's webhooks", SERVER),
+    command("Server", "webhook remove <id>", "stops sending events to a webhook", SERVER),
+    command("Server", "search history <query>", "searches what happened in the server", ROLES),
+    command("Server", "data retention", "shows how long history is kept", SERVER),
+    command("Server", "data retention set <days>", "changes how long history is kept", SERVER),
+    command("Server", "command prefix", "shows how commands are given", EVERYONE),
+    command("Server", "command prefix <prefix>", "lets commands start with a prefix", SERVER),
+    command("Server", "mention hint <on|off>", "answers mentions of me with how to give commands", SERVER),
+    command("Server", "command stats", "shows which commands are used most", SERVER),
+    command("Server", "error cleanup <seconds>", "deletes error replies after a while", SERVER),
+    command("Server", "latency", "shows how quickly roles are given", SERVER),
+    command("Server", "latency alert <p95-ms> [minutes]", "warns when roles are given slowly", SERVER),
+    command("Server", "quotas", "shows the server's limits", SERVER),
+    command("Server", "emoji stats", "shows how much custom emoji are used", Permissions::MANAGE_GUILD_EXPRESSIONS),
+    command("Server", "experiments", "shows which experimental features run here", SERVER),
+    command("Server", "read-only", "shows whether the bot only watches the server", EVERYONE),
+    command("Server", "read-only <on|off>", "makes the bot only watch the server", Permissions::ADMINISTRATOR),
+    command("Server", "help [command]", "shows the commands you can use", EVERYONE),
+];
+
+/// The application commands, with the permissions they're registered with and checked against.
+pub const APP_COMMANDS: &[(&str, Permissions)] = &[
+    ("role-selector", ROLES),
+    ("Register as role selector", ROLES),
+    ("Preview selector parse", ROLES),
+    ("Show persisted roles", ROLES),
+    ("help", EVERYONE),
+];
+
+/// The permissions an application command needs, which are all of them for unknown commands.
+pub fn app_command_permissions(name: &str) -> Permissions {
+    APP_COMMANDS.iter()
+        .find(|(command, _)| *command == name)
+        .map(|(_, permissions)| *permissions)
+        .unwrap_or_else(Permissions::all)
+}
+
+/// The commands a member with the given permissions can use, optionally only those starting with
+/// `filter`.
+pub fn available(permissions: Permissions, filter: &str) -> Vec<&'static CommandInfo> {
+    let filter = filter.trim().to_lowercase();
+    COMMANDS.iter()
+        .filter(|info| permissions.contains(info.permissions))
+        .filter(|info| info.usage.starts_with(&filter))
+        .collect()
+}
+
+/// Lists commands by group, or describes each of them when only a few match.
+pub fn describe(commands: &[&CommandInfo]) -> Vec<String> {
+    if commands.is_empty() {
+        return vec!["No commands you can use match that.".to_owned()];
+    }
+    if commands.len() <= 5 {
+        return commands.iter().map(|info| format!("`{}`: {}", info.usage, info.summary)).collect();
+    }
+
+    let mut lines = vec!["Mention me followed by one of these commands. `help <command>` says what it does.".to_owned()];
+    let mut groups: Vec<&str> = Vec::new();
+    for info in commands {
+        if !groups.contains(&info.group) {
+            groups.push(info.group);
+        }
+    }
+    for group in groups {
+        let usages: Vec<String> = commands.iter()
+            .filter(|info| info.group == group)
+            .map(|info| format!("`{}`", info.usage))
+            .collect();
+        lines.push(format!("**{}**: {}", group, usages.join(", ")));
+    }
+    lines
+}
+
+pub async fn help(ctx: &Context, command: &Message, filter: &[&str]) -> CommandResult<()> {
+    let permissions = message_permissions(ctx, command).await;
+    let lines = describe(&available(permissions, &filter.join(" ")));
+    say_lines(ctx, command.channel_id, &lines).await?;
+    Ok(())
+}
@@ -0,0 +1,410 @@
+use std::collections::HashSet;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use chrono::{Duration, NaiveDate, TimeZone, Utc};
+use chrono_tz::Tz;
+use log::error;
+use rusqlite::{Connection, OpenFlags, params, params_from_iter};
+use rusqlite::types::Value;
+use serenity::model::prelude::*;
+use serenity::prelude::{Context, TypeMapKey};
+
+use crate::{CommandError, CommandResult, OpenOptions, parse_role_argument, parse_user_argument, say_lines, timezone};
+use crate::events::{Event, RoleSource, SelectorChange};
+
+/// The history's file, kept with the stores.
+pub const FILE: &str = "history.sqlite";
+
+/// How many matches a search shows, newest first.
+pub const MAX_RESULTS: usize = 25;
+
+/// The action recorded for entries posted to a guild's log channel.
+pub const AUDIT_ACTION: &str = "audit";
+
+pub struct HistoryKey;
+
+impl TypeMapKey for HistoryKey {
+    type Value = History;
+}
+
+/// A journal of everything that happened in each guild, indexed for searching.
+#[derive(Clone)]
+pub struct History {
+    connection: Arc<Mutex<Connection>>,
+    /// Whether nothing is recorded, like when the stores are opened read-only.
+    read_only: bool,
+}
+
+/// Something that happened in a guild, described in plain text with names resolved at the time.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Entry {
+    pub at: i64,
+    pub guild: GuildId,
+    /// An event `type`, or `audit` for log channel entries.
+    pub action: String,
+    pub user: Option<UserId>,
+    pub roles: Vec<RoleId>,
+    pub summary: String,
+}
+
+/// What to search the history of a guild for. Every given filter must match.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Query {
+    /// Words that must all appear in the summary.
+    pub terms: Vec<String>,
+    pub user: Option<UserId>,
+    pub role: Option<RoleId>,
+    pub action: Option<String>,
+    /// Unix time from which entries match.
+    pub since: Option<i64>,
+    /// Unix time before which entries match.
+    pub until: Option<i64>,
+}
+
+impl Query {
+    /// Parses search arguments like `user:@Gegy role:@Red action:roles_granted since:2021-08-01
+    /// until:2021-08-31 some words`. Dates are whole days in the given zone.
+    pub fn parse(tokens: &[&str], zone: Tz) -> CommandResult<Query> {
+        let mut query = Query::default();
+        for token in tokens {
+            let malformed = || CommandError::MalformedArgument((*token).to_owned());
+            match token.split_once(':') {
+                Some(("user", user)) => query.user = Some(parse_user_argument(user)?),
+                Some(("role", role)) => query.role = Some(parse_role_argument(role)?),
+                Some(("action", action)) => {
+                    if !Event::TYPES.contains(&action) && action != AUDIT_ACTION {
+                        return Err(malformed());
+                    }
+                    query.action = Some(action.to_owned());
+                }
+                Some(("since", date)) => query.since = Some(day_start(date, zone).ok_or_else(malformed)?),
+                Some(("until", date)) => {
+                    let end = day_start(date, zone).ok_or_else(malformed)? + Duration::days(1).num_seconds();
+                    query.until = Some(end);
+                }
+                _ => query.terms.push((*token).to_owned()),
+            }
+        }
+        Ok(query)
+    }
+
+    /// The terms as a full text query, with each term quoted so none is read as query syntax.
+    fn match_expression(&self) -> Option<String> {
+        if self.terms.is_empty() {
+            return None;
+        }
+        let terms: Vec<String> = self.terms.iter()
+            .map(|term| format!("\"{}\"", term.replace('"', "\"\"")))
+            .collect();
+        Some(terms.join(" "))
+    }
+}
+
+fn day_start(date: &str, zone: Tz) -> Option<i64> {
+    let date = NaiveDate::parse_from_str(date, "%Y-%m-%d").ok()?;
+    let start = zone.from_local_datetime(&date.and_hms_opt(0, 0, 0)?).earliest()?;
+    Some(start.timestamp())
+}
+
+impl History {
+    pub fn open(path: impl AsRef<Path>) -> rusqlite::Result<History> {
+        History::init(Connection::open(path)?)
+    }
+
+    pub fn open_in_memory() -> rusqlite::Result<History> {
+        History::init(Connection::open_in_memory()?)
+    }
+
+    /// Opens the history for searching only, never writing to its file. A history that doesn't
+    /// exist yet is searched as if it were empty.
+    pub fn open_read_only(path: impl AsRef<Path>) -> rusqlite::Result<History> {
+        let path = path.as_ref();
+        let connection = match path.exists() {
+            true => Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)?,
+            false => return Ok(History { read_only: true, ..History::open_in_memory()? }),
+        };
+        // opening doesn't read the file yet, so a broken one would only show up on the first search
+        connection.query_row("SELECT count(*) FROM sqlite_master", [], |_| Ok(()))?;
+        Ok(History { connection: Arc::new(Mutex::new(connection)), read_only: true })
+    }
+
+    /// Opens the history next to the stores, read-only if they are.
+    pub fn open_with(options: &OpenOptions) -> rusqlite::Result<History> {
+        let path = options.path(FILE);
+        if options.is_read_only() {
+            History::open_read_only(path)
+        } else {
+            History::open(path)
+        }
+    }
+
+    fn init(connection: Connection) -> rusqlite::Result<History> {
+        connection.execute_batch(
+            "CREATE TABLE IF NOT EXISTS entries (
+                id INTEGER PRIMARY KEY,
+                at INTEGER NOT NULL,
+                guild INTEGER NOT NULL,
+                action TEXT NOT NULL,
+                user INTEGER,
+                summary TEXT NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS entry_roles (
+                entry INTEGER NOT NULL REFERENCES entries(id) ON DELETE CASCADE,
+                role INTEGER NOT NULL
+            );
+            CREATE INDEX IF NOT EXISTS entries_by_guild ON entries (guild, at);
+            CREATE INDEX IF NOT EXISTS entries_by_user ON entries (guild, user, at);
+            CREATE INDEX IF NOT EXISTS entry_roles_by_role ON entry_roles (role, entry);
+            CREATE VIRTUAL TABLE IF NOT EXISTS entries_text USING fts5 (summary, content='entries', content_rowid='id');
+            CREATE TRIGGER IF NOT EXISTS entries_text_insert AFTER INSERT ON entries BEGIN
+                INSERT INTO entries_text (rowid, summary) VALUES (new.id, new.summary);
+            END;
+            CREATE TRIGGER IF NOT EXISTS entries_text_delete AFTER DELETE ON entries BEGIN
+                INSERT INTO entries_text (entries_text, rowid, summary) VALUES ('delete', old.id, old.summary);
+            END;
+            PRAGMA foreign_keys = ON;",
+        )?;
+        Ok(History { connection: Arc::new(Mutex::new(connection)), read_only: false })
+    }
+
+    pub fn record(&self, entry: &Entry) -> rusqlite::Result<()> {
+        if self.read_only {
+            return Ok(());
+        }
+        let mut connection = self.connection.lock().expect("history connection poisoned");
+        let transaction = connection.transaction()?;
+        transaction.execute(
+            "INSERT INTO entries (at, guild, action, user, summary) VALUES (?, ?, ?, ?, ?)",
+            params![entry.at, entry.guild.get() as i64, entry.action, entry.user.map(|user| user.get() as i64), entry.summary],
+        )?;
+        let id = transaction.last_insert_rowid();
+        for role in &entry.roles {
+            transaction.execute("INSERT INTO entry_roles (entry, role) VALUES (?, ?)", params![id, role.get() as i64])?;
+        }
+        transaction.commit()
+    }
+
+    /// Finds the newest entries of a guild matching the query.
+    pub fn search(&self, guild: GuildId, query: &Query, limit: usize) -> rusqlite::Result<Vec<Entry>> {
+        let mut conditions = vec!["guild = ?".to_owned()];
+        let mut values = vec![Value::Integer(guild.get() as i64)];
+
+        if let Some(user) = query.user {
+            conditions.push("user = ?".to_owned());
+            values.push(Value::Integer(user.get() as i64));
+        }
+        if let Some(role) = query.role {
+            conditions.push("id IN (SELECT entry FROM entry_roles WHERE role = ?)".to_owned());
+            values.push(Value::Integer(role.get() as i64));
+        }
+        if let Some(action) = &query.action {
+            conditions.push("action = ?".to_owned());
+            values.push(Value::Text(action.clone()));
+        }
+        if let Some(since) = query.since {
+            conditions.push("at >= ?".to_owned());
+            values.push(Value::Integer(since));
+        }
+        if let Some(until) = query.until {
+            conditions.push("at < ?".to_owned());
+            values.push(Value::Integer(until));
+        }
+        if let Some(expression) = query.match_expression() {
+            conditions.push("id IN (SELECT rowid FROM entries_text WHERE entries_text MATCH ?)".to_owned());
+            values.push(Value::Text(expression));
+        }
+        values.push(Value::Integer(limit as i64));
+
+        let sql = format!(
+            "SELECT id, at, guild, action, user, summary FROM entries WHERE {} ORDER BY at DESC, id DESC LIMIT ?",
+            conditions.join(" AND "),
+        );
+
+        let connection = self.connection.lock().expect("history connection poisoned");
+        let mut statement = connection.prepare(&sql)?;
+        let rows = statement.query_map(params_from_iter(values), |row| {
+            Ok((row.get::<_, i64>(0)?, Entry {
+                at: row.get(1)?,
+                guild: GuildId::new(row.get::<_, i64>(2)? as u64),
+                action: row.get(3)?,
+                user: row.get::<_, Option<i64>>(4)?.map(|user| UserId::new(user as u64)),
+                roles: Vec::new(),
+                summary: row.get(5)?,
+            }))
+        })?.collect::<rusqlite::Result<Vec<_>>>()?;
+
+        let mut roles = connection.prepare("SELECT role FROM entry_roles WHERE entry = ? ORDER BY rowid")?;
+        rows.into_iter().map(|(id, mut entry)| {
+            entry.roles = roles.query_map([id], |row| Ok(RoleId::new(row.get::<_, i64>(0)? as u64)))?
+                .collect::<rusqlite::Result<_>>()?;
+            Ok(entry)
+        }).collect()
+    }
+
+    /// Forgets the history of all guilds not in `guilds`, returning the ones that were removed.
+    pub fn retain_guilds(&self, guilds: &HashSet<GuildId>) -> rusqlite::Result<Vec<GuildId>> {
+        if self.read_only {
+            return Ok(Vec::new());
+        }
+        let connection = self.connection.lock().expect("history connection poisoned");
+        let stored: Vec<GuildId> = connection.prepare("SELECT DISTINCT guild FROM entries")?
+            .query_map([], |row| Ok(GuildId::new(row.get::<_, i64>(0)? as u64)))?
+            .collect::<rusqlite::Result<_>>()?;
+
+        let removed: Vec<GuildId> = stored.into_iter().filter(|guild| !guilds.contains(guild)).collect();
+        for guild in &removed {
+            connection.execute("DELETE FROM entries WHERE guild = ?", [guild.get() as i64])?;
+        }
+        Ok(removed)
+    }
+}
+
+/// Forgets the history of all guilds not in `guilds`, returning the ones that were removed.
+pub async fn retain_guilds(ctx: &Context, guilds: &HashSet<GuildId>) -> Vec<GuildId> {
+    let history = {
+        let data = ctx.data.read().await;
+        data.get::<HistoryKey>().cloned()
+    };
+
+    let history = match history {
+        Some(history) => history,
+        None => return Vec::new(),
+    };
+    let guilds = guilds.clone();
+    match tokio::task::spawn_blocking(move || history.retain_guilds(&guilds)).await {
+        Ok(Ok(removed)) => removed,
+        Ok(Err(err)) => {
+            error!("failed to prune history: {:?}", err);
+            Vec::new()
+        }
+        Err(_) => Vec::new(),
+    }
+}
+
+/// Records an entry in the background, logging rather than failing if the journal can't be written.
+pub async fn record(ctx: &Context, entry: Entry) {
+    let history = {
+        let data = ctx.data.read().await;
+        data.get::<HistoryKey>().cloned()
+    };
+
+    if let Some(history) = history {
+        let result = tokio::task::spawn_blocking(move || history.record(&entry)).await;
+        if let Ok(Err(err)) = result {
+            error!("failed to record history: {:?}", err);
+        }
+    }
+}
+
+/// Records an event with the names of everything it refers to, so the summary stays readable and
+/// searchable after they are renamed or deleted.
+pub async fn record_event(ctx: &Context, at: i64, event: &Event) {
+    let (guild, action, user, roles, summary) = match event {
+        Event::RolesGranted { guild, user, roles, source } => {
+            let summary = format!("{} was granted {} {}", user_name(ctx, *user).await, role_names(ctx, *guild, roles).await, source_description(*source));
+            (*guild, "roles_granted", Some(*user), roles.clone(), summary)
+        }
+        Event::RolesRevoked { guild, user, roles, source } => {
+            let summary = format!("{} lost {} {}", user_name(ctx, *user).await, role_names(ctx, *guild, roles).await, source_description(*source));
+            (*guild, "roles_revoked", Some(*user), roles.clone(), summary)
+        }
+        Event::MemberJoined { guild, user } => {
+            (*guild, "member_joined", Some(*user), Vec::new(), format!("{} joined", user_name(ctx, *user).await))
+        }
+        Event::MemberLeft { guild, user } => {
+            (*guild, "member_left", Some(*user), Vec::new(), format!("{} left", user_name(ctx, *user).await))
+        }
+        Event::SelectorChanged { guild, channel, message, change } => {
+            let channel_name = match crate::cached_channel(&ctx.cache, *channel) {
+                Some(channel) => format!("#{}", channel.name),
+                None => channel.get().to_string(),
+            };
+            let change = match change {
+                SelectorChange::Registered => "registered",
+                SelectorChange::Edited => "edited",
+                SelectorChange::Removed => "removed",
+                SelectorChange::Deleted => "deleted",
+                SelectorChange::Moved => "moved",
+            };
+            (*guild, "selector_changed", None, Vec::new(), format!("Selector {} in {} was {}", message, channel_name, change))
+        }
+    };
+
+    record(ctx, Entry { at, guild, action: action.to_owned(), user, roles, summary }).await;
+}
+
+async fn user_name(ctx: &Context, user: UserId) -> String {
+    match ctx.cache.user(user).map(|user| user.clone()) {
+        Some(found) => format!("{} ({})", found.tag(), user),
+        None => user.to_string(),
+    }
+}
+
+async fn role_names(ctx: &Context, guild: GuildId, roles: &[RoleId]) -> String {
+    let mut names = Vec::with_capacity(roles.len());
+    for role in roles {
+        match ctx.cache.guild(guild).and_then(|guild| guild.roles.get(role).cloned()) {
+            Some(found) => names.push(found.name),
+            None => names.push(role.to_string()),
+        }
+    }
+    names.join(", ")
+}
+
+fn source_description(source: RoleSource) -> &'static str {
+    match source {
+        RoleSource::Selector => "through a selector",
+        RoleSource::Persistence => "through role persistence",
+        RoleSource::Dependency => "because a role they require was taken",
+    }
+}
+
+/// Answers a `search history` command with the newest matching entries.
+pub async fn search(ctx: &Context, command: &Message, tokens: &[&str]) -> CommandResult<()> {
+    let guild = command.guild_id.ok_or(CommandError::NotAllowed)?;
+    let query = Query::parse(tokens, timezone::zone(ctx, guild).await)?;
+
+    let history = {
+        let data = ctx.data.read().await;
+        data.get::<HistoryKey>().cloned()
+    };
+    let history = history.ok_or(CommandError::NotAllowed)?;
+
+    let entries = tokio::task::spawn_blocking(move || history.search(guild, &query, MAX_RESULTS))
+        .await
+        .expect("history search panicked")
+        .map_err(|err| {
+            error!("failed to search history of {}: {:?}", guild, err);
+            CommandError::HistoryUnavailable
+        })?;
+
+    let lines: Vec<String> = if entries.is_empty() {
+        vec!["Nothing in the history matches that.".to_owned()]
+    } else {
+        entries.iter().map(|entry| format!("<t:{}:f> `{}` {}", entry.at, entry.action, entry.summary)).collect()
+    };
+    say_lines(ctx, command.channel_id, &lines).await?;
+
+    Ok(())
+}
+
+/// Runs `search-history <guild> [filters...] [words...]` against the history file, in UTC.
+pub fn run_cli(history: &History, args: &[String]) -> Result<(), String> {
+    let (guild, tokens) = match args {
+        [guild, tokens @ ..] => (guild, tokens),
+        _ => return Err("usage: search-history <guild> [user:<id>] [role:<id>] [action:<type>] [since:<date>] [until:<date>] [words...]".to_owned()),
+    };
+    let guild: GuildId = guild.parse().map_err(|_| format!("invalid guild ID: {}", guild))?;
+
+    let tokens: Vec<&str> = tokens.iter().map(String::as_str).collect();
+    let query = Query::parse(&tokens, Tz::UTC).map_err(|err| err.to_string())?;
+
+    let entries = history.search(guild, &query, MAX_RESULTS).map_err(|err| format!("failed to search history: {}", err))?;
+    for entry in &entries {
+        let at = Utc.timestamp_opt(entry.at, 0).unwrap();
+        println!("{} {} {}", at.to_rfc3339(), entry.action, entry.summary);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,189 @@
+use log::error;
+use serenity::builder::{
+    CreateAllowedMentions, CreateCommand, CreateCommandOption, CreateInteractionResponse,
+    CreateInteractionResponseFollowup, CreateInteractionResponseMessage, EditInteractionResponse,
+};
+use serenity::model::application::ResolvedTarget;
+use serenity::model::prelude::*;
+use serenity::prelude::*;
+
+use crate::{appeals, approvals, chunk_lines, CommandError, CommandResult, dormancy, help, persistent_roles, reaction_roles, read_only, require_permission, telemetry};
+
+const REGISTER_SELECTOR: &str = "Register as role selector";
+const PREVIEW_SELECTOR: &str = "Preview selector parse";
+const SHOW_PERSISTED_ROLES: &str = "Show persisted roles";
+const HELP: &str = "help";
+
+/// The application commands the bot offers, replacing whatever was registered before. Discord only
+/// shows members the commands their permissions allow, unless a server overrides that.
+pub fn commands() -> Vec<CreateCommand> {
+    let command = |name: &str| {
+        let command = CreateCommand::new(name).dm_permission(false);
+        // no permissions at all would hide the command from everyone but administrators
+        match help::app_command_permissions(name) {
+            permissions if permissions.is_empty() => command,
+            permissions => command.default_member_permissions(permissions),
+        }
+    };
+    let context_menu = |name: &str, kind: CommandType| command(name).kind(kind);
+
+    vec![
+        command(HELP).description("List the commands you can use"),
+        command("role-selector")
+            .description("Manage role selectors")
+            .add_option(CreateCommandOption::new(CommandOptionType::SubCommand, "create", "Create a role selector by filling in a form")),
+        context_menu(REGISTER_SELECTOR, CommandType::Message),
+        context_menu(PREVIEW_SELECTOR, CommandType::Message),
+        context_menu(SHOW_PERSISTED_ROLES, CommandType::User),
+    ]
+}
+
+pub async fn register(ctx: &Context) {
+    if let Err(err) = Command::set_global_commands(&ctx.http, commands()).await {
+        error!("failed to register application commands: {:?}", err);
+    }
+}
+
+pub async fn interaction_create(ctx: &Context, interaction: Interaction) {
+    match interaction {
+        Interaction::Command(command) => {
+            if let Some(guild) = command.guild_id {
+                dormancy::record_activity(ctx, guild).await;
+            }
+
+            let result = handle_command(ctx, &command).await;
+            telemetry::count_command();
+
+            if let Err(err) = result {
+                reply_error(ctx, &command, &err).await;
+            }
+        }
+        Interaction::Component(component) => {
+            if let Err(err) = handle_component(ctx, &component).await {
+                reply_component_error(ctx, &component, &err).await;
+            }
+        }
+        _ => {}
+    }
+}
+
+async fn handle_command(ctx: &Context, command: &CommandInteraction) -> CommandResult<()> {
+    // servers can open commands up to everyone in their integration settings, so check them again
+    let permissions = command.member.as_ref().and_then(|member| member.permissions).unwrap_or_else(Permissions::empty);
+    require_permission(permissions, help::app_command_permissions(&command.data.name))?;
+
+    if command.data.name != HELP && read_only::is_active(ctx, command.guild_id).await {
+        return Err(CommandError::ReadOnly);
+    }
+
+    let subcommand = command.data.options.first().map(|option| option.name.as_str());
+    match (command.data.name.as_str(), subcommand) {
+        (HELP, _) => {
+            let lines = help::describe(&help::available(permissions, ""));
+            reply(ctx, command, &lines).await
+        }
+        ("role-selector", Some("create")) => {
+            reaction_roles::draft::create(ctx, command).await
+        }
+        (REGISTER_SELECTOR, _) => {
+            let guild = command.guild_id.ok_or(CommandError::NotAllowed)?;
+            let mut message = target_message(command)?.clone();
+
+            // registering can ask whether to create missing roles, which takes longer than Discord waits
+            command.defer_ephemeral(&ctx.http).await?;
+            reaction_roles::register_message(ctx, guild, command.channel_id, command.user.id, &mut message).await?;
+            command.edit_response(&ctx.http, EditInteractionResponse::new().content("✅ Registered the selector.")).await?;
+            Ok(())
+        }
+        (PREVIEW_SELECTOR, _) => {
+            let guild = command.guild_id.ok_or(CommandError::NotAllowed)?;
+            let message = target_message(command)?;
+            let lines = reaction_roles::describe_parse(ctx, guild, &message.content).await;
+            reply(ctx, command, &lines).await
+        }
+        (SHOW_PERSISTED_ROLES, _) => {
+            let guild = command.guild_id.ok_or(CommandError::NotAllowed)?;
+            let user = match command.data.target() {
+                Some(ResolvedTarget::User(user, _)) => user.id,
+                _ => return Err(CommandError::InvalidCommand),
+            };
+            let lines = persistent_roles::describe_user(ctx, guild, user).await;
+            reply(ctx, command, &lines).await
+        }
+        _ => Err(CommandError::InvalidCommand),
+    }
+}
+
+/// Handles the buttons staff answer role requests and appeals with. Buttons of prompts that wait for
+/// an answer themselves, like the selector form's, are left to those.
+async fn handle_component(ctx: &Context, component: &ComponentInteraction) -> CommandResult<()> {
+    let custom_id = component.data.custom_id.as_str();
+    if !custom_id.starts_with(approvals::BUTTON_PREFIX) && !custom_id.starts_with(appeals::BUTTON_PREFIX) {
+        return Ok(());
+    }
+
+    let guild = component.guild_id.ok_or(CommandError::NotAllowed)?;
+    dormancy::record_activity(ctx, guild).await;
+    if read_only::is_active(ctx, Some(guild)).await {
+        return Err(CommandError::ReadOnly);
+    }
+
+    let permissions = component.member.as_ref().and_then(|member| member.permissions).unwrap_or_else(Permissions::empty);
+    if custom_id.starts_with(approvals::BUTTON_PREFIX) {
+        approvals::answer(ctx, component, permissions).await
+    } else {
+        appeals::answer(ctx, component, permissions).await
+    }
+}
+
+fn target_message(command: &CommandInteraction) -> CommandResult<&Message> {
+    match command.data.target() {
+        Some(ResolvedTarget::Message(message)) => Ok(message),
+        _ => Err(CommandError::InvalidMessageReference),
+    }
+}
+
+/// Answers a command with lines that only the user sees, following up with the rest if they don't
+/// fit in one message.
+async fn reply(ctx: &Context, command: &CommandInteraction, lines: &[String]) -> CommandResult<()> {
+    let mut chunks = chunk_lines(lines).into_iter();
+    let response = CreateInteractionResponseMessage::new()
+        .content(chunks.next().unwrap_or_default())
+        .allowed_mentions(CreateAllowedMentions::new())
+        .ephemeral(true);
+    command.create_response(&ctx.http, CreateInteractionResponse::Message(response)).await?;
+
+    for chunk in chunks {
+        let followup = CreateInteractionResponseFollowup::new()
+            .content(chunk)
+            .allowed_mentions(CreateAllowedMentions::new())
+            .ephemeral(true);
+        command.create_followup(&ctx.http, followup).await?;
+    }
+    Ok(())
+}
+
+/// Tells only the user about a failed command, in place of the answer if the command was deferred.
+async fn reply_error(ctx: &Context, command: &CommandInteraction, err: &CommandError) {
+    let response = CreateInteractionResponse::Message(CreateInteractionResponseMessage::new().content(err.to_string()).ephemeral(true));
+    if command.create_response(&ctx.http, response).await.is_ok() {
+        return;
+    }
+
+    if let Err(reply_err) = command.edit_response(&ctx.http, EditInteractionResponse::new().content(err.to_string())).await {
+        error!("failed to tell {} about a command error: {:?}", command.user.id, reply_err);
+    }
+}
+
+/// Tells only the user about a failed button press, following up if the press was already answered.
+async fn reply_component_error(ctx: &Context, component: &ComponentInteraction, err: &CommandError) {
+    let response = CreateInteractionResponse::Message(CreateInteractionResponseMessage::new().content(err.to_string()).ephemeral(true));
+    if component.create_response(&ctx.http, response).await.is_ok() {
+        return;
+    }
+
+    let followup = CreateInteractionResponseFollowup::new().content(err.to_string()).ephemeral(true);
+    if let Err(reply_err) = component.create_followup(&ctx.http, followup).await {
+        error!("failed to tell {} about a button error: {:?}", component.user.id, reply_err);
+    }
+}
@@ -0,0 +1,228 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time::Instant;
+
+use log::{error, warn};
+use serde::{Deserialize, Serialize};
+use serenity::model::prelude::*;
+use serenity::prelude::*;
+use tokio::sync::mpsc;
+
+use crate::{persistent_roles, reaction_roles, verification};
+use crate::metrics::GuildGauge;
+
+pub struct QueueKey;
+
+impl TypeMapKey for QueueKey {
+    type Value = JoinQueue;
+}
+
+/// Paces the work done for new members, like restoring their roles or sending their verification
+/// challenge, so that a raid or a mass join doesn't set off thousands of requests at once.
+#[derive(Serialize, Deserialize, Clone, Eq, PartialEq)]
+pub struct JoinQueueConfig {
+    /// How many joins of each priority can wait. Once as many are waiting, further returning
+    /// members are worked on straight away, and other joins are shed.
+    #[serde(default = "default_capacity")]
+    pub capacity: usize,
+    /// How many joins are worked on at the same time.
+    #[serde(default = "default_batch_size")]
+    pub batch_size: usize,
+}
+
+impl Default for JoinQueueConfig {
+    fn default() -> Self {
+        JoinQueueConfig {
+            capacity: default_capacity(),
+            batch_size: default_batch_size(),
+        }
+    }
+}
+
+fn default_capacity() -> usize {
+    1000
+}
+
+fn default_batch_size() -> usize {
+    8
+}
+
+/// Returning members with persisted roles go first, since those may well be roles like a mute.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Priority {
+    High,
+    Normal,
+}
+
+/// How joins since startup were worked through.
+#[derive(Serialize, Clone, Copy, Debug, Default)]
+pub struct JoinStats {
+    pub processed: u64,
+    pub prioritized: u64,
+    /// Joins dropped because the queue was full, whose roles and challenges were never handled.
+    pub shed: u64,
+    /// The longest a join waited in the queue, in milliseconds.
+    pub longest_wait_ms: u64,
+}
+
+#[derive(Default)]
+struct Counters {
+    depth: AtomicUsize,
+    processed: AtomicU64,
+    prioritized: AtomicU64,
+    shed: AtomicU64,
+    longest_wait_ms: AtomicU64,
+    guilds: GuildGauge,
+}
+
+struct Join {
+    ctx: Context,
+    member: Member,
+    queued_at: Instant,
+}
+
+/// A bounded queue of joins, worked through a batch at a time with the prioritized ones first. When
+/// it's full, prioritized joins skip the queue, since their roles may be a mute, while other joins
+/// are shed so that a raid can't pile up work without end.
+#[derive(Clone)]
+pub struct JoinQueue {
+    high: mpsc::Sender<Join>,
+    normal: mpsc::Sender<Join>,
+    counters: Arc<Counters>,
+}
+
+impl JoinQueue {
+    pub fn start(config: &JoinQueueConfig) -> Self {
+        let capacity = config.capacity.max(1);
+        let batch_size = config.batch_size.max(1);
+        let (high, mut high_receiver) = mpsc::channel::<Join>(capacity);
+        let (normal, mut normal_receiver) = mpsc::channel::<Join>(capacity);
+        let counters = Arc::new(Counters::default());
+
+        let worker_counters = counters.clone();
+        tokio::spawn(async move {
+            while let Some(batch) = next_batch(&mut high_receiver, &mut normal_receiver, batch_size).await {
+                let tasks: Vec<_> = batch.into_iter().map(|join| {
+                    let wait_ms = join.queued_at.elapsed().as_millis() as u64;
+                    worker_counters.longest_wait_ms.fetch_max(wait_ms, Ordering::Relaxed);
+                    (join.member.guild_id, tokio::spawn(process(join.ctx, join.member)))
+                }).collect();
+
+                for (guild, task) in tasks {
+                    if let Err(err) = task.await {
+                        error!("failed to process join: {:?}", err);
+                    }
+                    worker_counters.depth.fetch_sub(1, Ordering::SeqCst);
+                    worker_counters.guilds.decrement(guild);
+                    worker_counters.processed.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+        });
+
+        JoinQueue { high, normal, counters }
+    }
+
+    /// How many joins are waiting or in progress.
+    pub fn depth(&self) -> usize {
+        self.counters.depth.load(Ordering::SeqCst)
+    }
+
+    /// How many joins are waiting or in progress for each guild that has any.
+    pub fn guild_depths(&self) -> HashMap<GuildId, usize> {
+        self.counters.guilds.snapshot()
+    }
+
+    pub fn stats(&self) -> JoinStats {
+        JoinStats {
+            processed: self.counters.processed.load(Ordering::Relaxed),
+            prioritized: self.counters.prioritized.load(Ordering::Relaxed),
+            shed: self.counters.shed.load(Ordering::Relaxed),
+            longest_wait_ms: self.counters.longest_wait_ms.load(Ordering::Relaxed),
+        }
+    }
+
+    async fn submit(&self, ctx: Context, member: Member, priority: Priority) {
+        let sender = match priority {
+            Priority::High => {
+                self.counters.prioritized.fetch_add(1, Ordering::Relaxed);
+                &self.high
+            }
+            Priority::Normal => &self.normal,
+        };
+
+        self.counters.depth.fetch_add(1, Ordering::SeqCst);
+        self.counters.guilds.increment(member.guild_id);
+        let join = Join { ctx, member, queued_at: Instant::now() };
+        let join = match sender.try_send(join) {
+            Ok(()) => return,
+            Err(mpsc::error::TrySendError::Full(join)) | Err(mpsc::error::TrySendError::Closed(join)) => join,
+        };
+        self.counters.depth.fetch_sub(1, Ordering::SeqCst);
+        self.counters.guilds.decrement(join.member.guild_id);
+
+        if priority == Priority::High || sender.is_closed() {
+            process(join.ctx, join.member).await;
+        } else {
+            let shed = self.counters.shed.fetch_add(1, Ordering::Relaxed) + 1;
+            warn!("join queue is full, shed the join of {} to {} ({} shed so far)", join.member.user.id, join.member.guild_id, shed);
+        }
+    }
+}
+
+/// Waits for the next batch of up to `size` items, taking prioritized ones before any others.
+/// Nothing is returned once both queues are closed and empty.
+pub async fn next_batch<T>(high: &mut mpsc::Receiver<T>, normal: &mut mpsc::Receiver<T>, size: usize) -> Option<Vec<T>> {
+    let first = tokio::select! {
+        biased;
+        Some(item) = high.recv() => item,
+        Some(item) = normal.recv() => item,
+        else => return None,
+    };
+
+    let mut batch = vec![first];
+    while batch.len() < size {
+        match high.try_recv().or_else(|_| normal.try_recv()) {
+            Ok(item) => batch.push(item),
+            Err(_) => break,
+        }
+    }
+    Some(batch)
+}
+
+/// Queues the work for a member who just joined, going ahead straight away when there's no queue.
+pub async fn guild_member_addition(ctx: &Context, member: Member) {
+    let queue = {
+        let data = ctx.data.read().await;
+        data.get::<QueueKey>().cloned()
+    };
+
+    match queue {
+        Some(queue) => {
+            let priority = match persistent_roles::has_returning_roles(ctx, member.guild_id, member.user.id).await {
+                true => Priority::High,
+                false => Priority::Normal,
+            };
+            queue.submit(ctx.clone(), member, priority).await;
+        }
+        None => process(ctx.clone(), member).await,
+    }
+}
+
+async fn process(ctx: Context, mut member: Member) {
+    if !verification::guild_member_addition(&ctx, &member).await {
+        persistent_roles::guild_member_addition(&ctx, &mut member).await;
+        reaction_roles::onboarding::member_joined(&ctx, member.guild_id, member.user.id);
+    }
+}
+
+/// How many joins are queued, if the queue is running.
+pub async fn depth(ctx: &Context) -> Option<usize> {
+    let data = ctx.data.read().await;
+    data.get::<QueueKey>().map(JoinQueue::depth)
+}
+
+pub async fn stats(ctx: &Context) -> JoinStats {
+    let data = ctx.data.read().await;
+    data.get::<QueueKey>().map(JoinQueue::stats).unwrap_or_default()
+}
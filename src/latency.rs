@@ -0,0 +1,274 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use serenity::model::prelude::*;
+use serenity::prelude::*;
+
+use crate::{audit, CommandError, CommandResult, members, Persistent, role_queue, say_lines, scheduler, store};
+use crate::memreport::{Introspect, Owner};
+
+/// How often each guild's latency is checked against its limit.
+pub const PERIOD: Duration = Duration::from_secs(60);
+
+/// How far back grants are looked at when working out the latency.
+pub const WINDOW_SECS: i64 = 5 * 60;
+
+/// How many grants are needed in the window before the latency is trusted, so that a single slow
+/// grant in a quiet guild doesn't raise an alert.
+pub const MIN_SAMPLES: usize = 10;
+
+/// How long the latency has to stay over the limit before an alert is raised, unless set otherwise.
+pub const DEFAULT_SUSTAINED_MINS: u64 = 5;
+
+pub struct StateKey;
+
+impl TypeMapKey for StateKey {
+    type Value = Persistent<State>;
+}
+
+#[derive(Serialize, Deserialize, Default, Clone, Eq, PartialEq)]
+pub struct State {
+    limits: HashMap<GuildId, Limit>,
+}
+
+impl Introspect for State {
+    fn entries(&self) -> Vec<(Owner, usize)> {
+        self.limits.keys().map(|guild| (Owner::Guild(*guild), 1)).collect()
+    }
+}
+
+/// How slow a guild allows giving selector roles to get before its log channel is told about it.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Limit {
+    /// The 95th percentile of the time from a reaction to its roles being given, in milliseconds.
+    pub p95_ms: u64,
+    /// How long the latency has to stay over the limit before alerting.
+    pub sustained_mins: u64,
+}
+
+/// How long one reaction took to turn into roles.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Sample {
+    /// Unix timestamp of when the roles were given.
+    pub at: i64,
+    /// The whole time from receiving the reaction to the roles being given, in milliseconds.
+    pub total_ms: u64,
+    /// How much of that was spent looking up the member.
+    pub lookup_ms: u64,
+}
+
+/// What changed about a guild's latency since it was last checked.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Alert {
+    /// The latency has been over the limit for long enough, with the given p95.
+    Exceeded(u64),
+    /// The latency is back under the limit after an alert, with the given p95.
+    Recovered(u64),
+}
+
+/// The recent grants of one guild, along with whether it's currently over its limit.
+#[derive(Clone, Debug, Default)]
+pub struct Tracker {
+    samples: VecDeque<Sample>,
+    /// When the latency was first seen over the limit, if it still is.
+    over_since: Option<i64>,
+    alerted: bool,
+}
+
+impl Tracker {
+    pub fn record(&mut self, sample: Sample) {
+        self.samples.push_back(sample);
+    }
+
+    /// Drops the samples that fell out of the window.
+    pub fn prune(&mut self, now: i64) {
+        while self.samples.front().is_some_and(|sample| sample.at <= now - WINDOW_SECS) {
+            self.samples.pop_front();
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.samples.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.samples.is_empty()
+    }
+
+    /// The 95th percentile of the samples in the window, if there are enough of them.
+    pub fn p95(&self) -> Option<u64> {
+        if self.samples.len() < MIN_SAMPLES {
+            return None;
+        }
+        let mut totals: Vec<u64> = self.samples.iter().map(|sample| sample.total_ms).collect();
+        totals.sort_unstable();
+        let index = (totals.len() * 95).div_ceil(100) - 1;
+        Some(totals[index])
+    }
+
+    /// How much of the time of the grants over the limit was spent looking up members, from 0 to 1.
+    pub fn lookup_share(&self, limit_ms: u64) -> f64 {
+        let (lookup, total) = self.samples.iter()
+            .filter(|sample| sample.total_ms > limit_ms)
+            .fold((0, 0), |(lookup, total), sample| (lookup + sample.lookup_ms, total + sample.total_ms));
+        if total == 0 { 0.0 } else { lookup as f64 / total as f64 }
+    }
+
+    /// Compares the latency against the limit, returning an alert the first time it has been over
+    /// for the sustained period, and again once it's back under.
+    pub fn check(&mut self, limit: Limit, now: i64) -> Option<Alert> {
+        self.prune(now);
+
+        let p95 = match self.p95() {
+            Some(p95) if p95 > limit.p95_ms => p95,
+            p95 => {
+                self.over_since = None;
+                if !self.alerted {
+                    return None;
+                }
+                // a window that emptied out counts as having recovered
+                self.alerted = false;
+                return Some(Alert::Recovered(p95.unwrap_or(0)));
+            }
+        };
+
+        let over_since = *self.over_since.get_or_insert(now);
+        if !self.alerted && now - over_since >= limit.sustained_mins as i64 * 60 {
+            self.alerted = true;
+            return Some(Alert::Exceeded(p95));
+        }
+        None
+    }
+}
+
+static TRACKERS: std::sync::Mutex<Option<HashMap<GuildId, Tracker>>> = std::sync::Mutex::new(None);
+
+/// Member lookups as they were at the last check, to tell how they were answered since.
+static LAST_LOOKUPS: std::sync::Mutex<Option<members::LookupStats>> = std::sync::Mutex::new(None);
+
+/// Records how long it took to give a member the roles of a selector reaction.
+pub fn record(guild: GuildId, lookup: Duration, total: Duration) {
+    let sample = Sample {
+        at: scheduler::now(),
+        total_ms: total.as_millis() as u64,
+        lookup_ms: lookup.as_millis() as u64,
+    };
+    TRACKERS.lock().unwrap().get_or_insert_with(HashMap::new).entry(guild).or_default().record(sample);
+}
+
+pub async fn set_limit(ctx: &Context, command: &Message, limit: Option<Limit>) -> CommandResult<()> {
+    let guild = command.guild_id.ok_or(CommandError::NotAllowed)?;
+
+    let state = store::<StateKey>(ctx).await;
+    state.write(|state| {
+        match limit {
+            Some(limit) => state.limits.insert(guild, limit),
+            None => state.limits.remove(&guild),
+        };
+    }).await;
+
+    Ok(())
+}
+
+pub async fn status(ctx: &Context, command: &Message) -> CommandResult<()> {
+    let guild = command.guild_id.ok_or(CommandError::NotAllowed)?;
+
+    let limit = store::<StateKey>(ctx).await.read().await.limits.get(&guild).copied();
+    let (samples, p95) = {
+        let mut trackers = TRACKERS.lock().unwrap();
+        match trackers.as_mut().and_then(|trackers| trackers.get_mut(&guild)) {
+            Some(tracker) => {
+                tracker.prune(scheduler::now());
+                (tracker.len(), tracker.p95())
+            }
+            None => (0, None),
+        }
+    };
+
+    let mut lines = vec![match p95 {
+        Some(p95) => format!("⏱ Selector roles were given within {}ms for 95% of the {} reactions in the last {} minutes.", p95, samples, WINDOW_SECS / 60),
+        None => format!("⏱ Only {} selector roles were given in the last {} minutes, which is too few to tell the latency.", samples, WINDOW_SECS / 60),
+    }];
+    lines.push(match limit {
+        Some(limit) => format!("The log channel is alerted when that stays over {}ms for {} minutes.", limit.p95_ms, limit.sustained_mins),
+        None => "Latency alerts are off. Turn them on with `latency alert <milliseconds> [minutes]`.".to_owned(),
+    });
+    say_lines(ctx, command.channel_id, &lines).await?;
+
+    Ok(())
+}
+
+/// Checks every guild with a limit against its latency, alerting its log channel when it changes.
+pub async fn check(ctx: Context) {
+    let limits = store::<StateKey>(&ctx).await.read().await.limits.clone();
+    let now = scheduler::now();
+
+    let alerts: Vec<(GuildId, Limit, Alert, f64)> = {
+        let mut trackers = TRACKERS.lock().unwrap();
+        let trackers = trackers.get_or_insert_with(HashMap::new);
+        trackers.retain(|guild, tracker| {
+            tracker.prune(now);
+            limits.contains_key(guild) || !tracker.is_empty()
+        });
+
+        limits.iter().filter_map(|(guild, limit)| {
+            let tracker = trackers.entry(*guild).or_default();
+            let alert = tracker.check(*limit, now)?;
+            Some((*guild, *limit, alert, tracker.lookup_share(limit.p95_ms)))
+        }).collect()
+    };
+
+    let lookups = members::stats();
+    let previous = LAST_LOOKUPS.lock().unwrap().replace(lookups);
+    if alerts.is_empty() {
+        return;
+    }
+
+    let queued = role_queue::depth(&ctx).await.unwrap_or(0);
+    for (guild, limit, alert, lookup_share) in alerts {
+        let content = match alert {
+            Alert::Exceeded(p95) => {
+                let mut lines = vec![format!(
+                    "🐢 Giving selector roles has been slow for {} minutes: 95% of reactions took up to {}ms, over the limit of {}ms.",
+                    limit.sustained_mins, p95, limit.p95_ms,
+                )];
+                lines.extend(causes(lookup_share, queued, previous, lookups));
+                lines.join("\n")
+            }
+            Alert::Recovered(p95) => format!("🐇 Giving selector roles is back under {}ms, at {}ms for 95% of reactions.", limit.p95_ms, p95),
+        };
+        audit::log(&ctx, guild, content).await;
+    }
+}
+
+/// Suggests why giving roles could be slow, from where the time went and how busy the bot is.
+fn causes(lookup_share: f64, queued: usize, previous: Option<members::LookupStats>, lookups: members::LookupStats) -> Vec<String> {
+    let mut causes = Vec::new();
+
+    if lookup_share >= 0.5 {
+        let missed = previous.map(|previous| (lookups.chunk + lookups.rest) - (previous.chunk + previous.rest)).unwrap_or(0);
+        causes.push(format!(
+            "↳ {:.0}% of that time went into looking up members, so the member cache is likely cold ({} lookups across all servers missed it in the last minute).",
+            lookup_share * 100.0, missed,
+        ));
+    }
+    if queued > 0 {
+        causes.push(format!("↳ {} role changes are waiting in the queue, so Discord is likely rate limiting me.", queued));
+    }
+    if causes.is_empty() {
+        causes.push("↳ Discord's API itself seems to be slow to answer.".to_owned());
+    }
+
+    causes
+}
+
+/// Forgets the limits of all guilds not in `guilds`, returning the ones that were removed.
+pub async fn retain_guilds(ctx: &Context, guilds: &HashSet<GuildId>) -> Vec<GuildId> {
+    let state = store::<StateKey>(ctx).await;
+    state.write(|state| {
+        let removed = state.limits.keys().filter(|guild| !guilds.contains(guild)).copied().collect();
+        state.limits.retain(|guild, _| guilds.contains(guild));
+        removed
+    }).await
+}
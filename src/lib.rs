@@ -0,0 +1,1472 @@
+// TODO: use slash commands
+use std::str::FromStr;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+use serenity::builder::{CreateAllowedMentions, CreateMessage};
+use serenity::cache::Cache;
+use serenity::http::Http;
+use serenity::model::prelude::*;
+use serenity::prelude::*;
+
+pub use persistent::*;
+
+mod persistent;
+pub mod appeals;
+pub mod applications;
+pub mod approvals;
+pub mod audit;
+pub mod autopin;
+pub mod backups;
+pub mod capabilities;
+pub mod cases;
+pub mod channel_links;
+pub mod command_usage;
+pub mod config_mirror;
+pub mod config_sync;
+pub mod confirm;
+pub mod doctor;
+pub mod dormancy;
+pub mod eligibility;
+pub mod emoji_stats;
+pub mod error_cleanup;
+pub mod event_signups;
+pub mod events;
+pub mod experiments;
+pub mod guild_setup;
+pub mod help;
+pub mod history;
+pub mod interactions;
+pub mod join_queue;
+pub mod latency;
+pub mod logging;
+pub mod maintenance;
+pub mod members;
+pub mod mentions;
+pub mod memreport;
+pub mod metrics;
+pub mod reaction_roles;
+pub mod read_only;
+#[cfg(feature = "replication")]
+pub mod replication;
+pub mod retention;
+pub mod role_changes;
+pub mod role_admin;
+pub mod role_caps;
+pub mod role_dependencies;
+pub mod role_queue;
+pub mod role_snapshots;
+pub mod persistent_roles;
+pub mod ping_tracker;
+pub mod quotas;
+pub mod rate_limit;
+pub mod scheduler;
+pub mod screening;
+pub mod selector_bans;
+pub mod slowmode;
+pub mod status;
+pub mod telemetry;
+pub mod timezone;
+pub mod validate;
+pub mod verification;
+pub mod webhooks;
+
+#[derive(Serialize, Deserialize, Default, Clone, Eq, PartialEq)]
+pub struct Config {
+    pub discord_token: String,
+    #[serde(default)]
+    pub telemetry: telemetry::TelemetryConfig,
+    #[serde(default)]
+    pub events: events::EventsConfig,
+    #[serde(default)]
+    pub quotas: quotas::QuotaConfig,
+    #[serde(default)]
+    pub role_queue: role_queue::RoleQueueConfig,
+    #[serde(default)]
+    pub join_queue: join_queue::JoinQueueConfig,
+    #[serde(default)]
+    pub rate_limits: rate_limit::RateLimitConfig,
+    #[serde(default)]
+    pub logging: logging::LoggingConfig,
+    #[serde(default)]
+    pub experiments: experiments::ExperimentsConfig,
+    #[serde(default)]
+    pub metrics: metrics::MetricsConfig,
+    #[serde(default)]
+    pub dormancy: dormancy::DormancyConfig,
+    /// Starts the bot read-only in every guild, so that it watches without changing anything.
+    #[serde(default)]
+    pub read_only: bool,
+    /// Writes the stores pretty-printed with sorted keys, for keeping them in version control.
+    #[serde(default)]
+    pub pretty_state: bool,
+    #[serde(default)]
+    pub stores: StoreConfig,
+    /// Whether to ask for the privileged members intent, worked out from the application's flags
+    /// when not set.
+    #[serde(default)]
+    pub members_intent: Option<bool>,
+    #[cfg(feature = "replication")]
+    #[serde(default)]
+    pub replication: replication::ReplicationConfig,
+}
+
+pub struct Handler;
+
+/// The gateway events `Handler` needs to receive, leaving out members when that intent isn't granted.
+pub fn intents() -> GatewayIntents {
+    let intents = GatewayIntents::GUILD_MESSAGE_REACTIONS
+        | GatewayIntents::GUILD_MESSAGES
+        | GatewayIntents::MESSAGE_CONTENT
+        | GatewayIntents::GUILDS
+        | GatewayIntents::GUILD_MEMBERS
+        | GatewayIntents::GUILD_MODERATION
+        | GatewayIntents::DIRECT_MESSAGES
+        | GatewayIntents::DIRECT_MESSAGE_REACTIONS;
+
+    if capabilities::has_members_intent() {
+        intents
+    } else {
+        intents - GatewayIntents::GUILD_MEMBERS
+    }
+}
+
+/// Opens every store where the config's `stores` says and starts the services that `Handler` relies
+/// on, putting them into a client's data. Together with `Handler` and `intents`, this is all it takes
+/// to run the bot inside another serenity client.
+pub async fn install(data: &mut TypeMap, http: Arc<Http>, config: &Config) -> rusqlite::Result<()> {
+    set_pretty_state(config.pretty_state);
+    let options = config.stores.open_options();
+    data.insert::<reaction_roles::StateKey>(open_store(&options, "reaction_roles.json").await);
+    data.insert::<reaction_roles::archive::StateKey>(open_store(&options, "selector_archive.json").await);
+    data.insert::<reaction_roles::names::StateKey>(open_store(&options, "selector_names.json").await);
+    data.insert::<persistent_roles::StateKey>(open_store(&options, "persistent_roles.json").await);
+    data.insert::<autopin::StateKey>(open_store(&options, "autopin.json").await);
+    data.insert::<event_signups::StateKey>(open_store(&options, "event_signups.json").await);
+    data.insert::<ping_tracker::StateKey>(open_store(&options, "ping_tracker.json").await);
+    data.insert::<audit::StateKey>(open_store(&options, "audit.json").await);
+    data.insert::<slowmode::StateKey>(open_store(&options, "slowmode.json").await);
+    data.insert::<emoji_stats::StateKey>(open_store(&options, "emoji_stats.json").await);
+    data.insert::<webhooks::StateKey>(open_store(&options, "webhooks.json").await);
+    data.insert::<approvals::StateKey>(open_store(&options, "approvals.json").await);
+    data.insert::<applications::StateKey>(open_store(&options, "applications.json").await);
+    data.insert::<role_caps::StateKey>(open_store(&options, "role_caps.json").await);
+    data.insert::<role_dependencies::StateKey>(open_store(&options, "role_dependencies.json").await);
+    data.insert::<config_sync::StateKey>(open_store(&options, "config_sync.json").await);
+    data.insert::<timezone::StateKey>(open_store(&options, "timezone.json").await);
+    data.insert::<retention::StateKey>(open_store(&options, "retention.json").await);
+    data.insert::<quotas::StateKey>(open_store(&options, "quotas.json").await);
+    data.insert::<channel_links::StateKey>(open_store(&options, "channel_links.json").await);
+    data.insert::<screening::StateKey>(open_store(&options, "screening.json").await);
+    data.insert::<eligibility::StateKey>(open_store(&options, "eligibility.json").await);
+    data.insert::<reaction_roles::expiry::StateKey>(open_store(&options, "selector_expiry.json").await);
+    data.insert::<verification::StateKey>(open_store(&options, "verification.json").await);
+    data.insert::<selector_bans::StateKey>(open_store(&options, "selector_bans.json").await);
+    data.insert::<cases::StateKey>(open_store(&options, "cases.json").await);
+    data.insert::<appeals::StateKey>(open_store(&options, "appeals.json").await);
+    data.insert::<read_only::StateKey>(open_store(&options, "read_only.json").await);
+    data.insert::<command_usage::StateKey>(open_store(&options, "command_usage.json").await);
+    data.insert::<error_cleanup::StateKey>(open_store(&options, "error_cleanup.json").await);
+    data.insert::<mentions::StateKey>(open_store(&options, "mentions.json").await);
+    data.insert::<latency::StateKey>(open_store(&options, "latency.json").await);
+    data.insert::<role_snapshots::StateKey>(open_store(&options, "role_snapshots.json").await);
+    data.insert::<reaction_roles::sweep::StateKey>(open_store(&options, "selector_sweeps.json").await);
+    data.insert::<reaction_roles::onboarding::StateKey>(open_store(&options, "onboarding.json").await);
+    data.insert::<reaction_roles::progress::StateKey>(open_store(&options, "progress_roles.json").await);
+    data.insert::<dormancy::StateKey>(open_store(&options, "activity.json").await);
+    data.insert::<backups::StateKey>(open_store(&options, "backups.json").await);
+
+    read_only::set_global(config.read_only);
+    data.insert::<telemetry::ConfigKey>(config.telemetry.clone());
+    data.insert::<quotas::ConfigKey>(config.quotas.clone());
+    data.insert::<experiments::ConfigKey>(config.experiments.clone());
+    data.insert::<metrics::ConfigKey>(config.metrics.clone());
+    data.insert::<dormancy::ConfigKey>(config.dormancy.clone());
+    data.insert::<rate_limit::LimiterKey>(Arc::new(rate_limit::Limiter::new(config.rate_limits.clone())));
+    data.insert::<events::BusKey>(events::Bus::start(&config.events).await);
+    data.insert::<history::HistoryKey>(history::History::open_with(&options)?);
+    data.insert::<OptionsKey>(options);
+    data.insert::<role_queue::QueueKey>(role_queue::RoleQueue::start(http, &config.role_queue));
+    data.insert::<join_queue::QueueKey>(join_queue::JoinQueue::start(&config.join_queue));
+
+    Ok(())
+}
+
+async fn open_store<T: Persistable>(options: &OpenOptions, name: &str) -> Persistent<T> {
+    match options.open(name).await {
+        Ok(store) => store,
+        Err(err) => panic!("failed to open {}: {}", options.path(name).display(), err),
+    }
+}
+
+#[async_trait]
+impl EventHandler for Handler {
+    async fn guild_member_addition(&self, ctx: Context, member: Member) {
+        dormancy::wake(&ctx, member.guild_id).await;
+        member_joined(&ctx, member).await;
+    }
+
+    async fn guild_member_removal(&self, ctx: Context, guild_id: GuildId, user: User, member: Option<Member>) {
+        dormancy::wake(&ctx, guild_id).await;
+        if let Some(member) = member {
+            role_snapshots::guild_member_removal(&ctx, &member).await;
+            reaction_roles::template::roles_changed(member.roles);
+        }
+        events::publish(&ctx, events::Event::MemberLeft { guild: guild_id, user: user.id }).await;
+        persistent_roles::guild_member_removal(&ctx, guild_id, user.id).await;
+        role_caps::guild_member_removal(&ctx, guild_id, user.id).await;
+        verification::guild_member_removal(&ctx, guild_id, user.id).await;
+        reaction_roles::progress::guild_member_removal(&ctx, guild_id, user.id).await;
+    }
+
+    async fn guild_ban_addition(&self, ctx: Context, guild_id: GuildId, banned_user: User) {
+        dormancy::wake(&ctx, guild_id).await;
+        persistent_roles::guild_ban_addition(&ctx, guild_id, banned_user.id).await;
+    }
+
+    async fn guild_ban_removal(&self, ctx: Context, guild_id: GuildId, unbanned_user: User) {
+        dormancy::wake(&ctx, guild_id).await;
+        persistent_roles::guild_ban_removal(&ctx, guild_id, unbanned_user.id).await;
+    }
+
+    async fn guild_member_update(&self, ctx: Context, old: Option<Member>, member: Option<Member>, _event: GuildMemberUpdateEvent) {
+        // the member is only missing when its guild isn't cached yet
+        let member = match member {
+            Some(member) => member,
+            None => return,
+        };
+        dormancy::wake(&ctx, member.guild_id).await;
+
+        // members still being verified haven't had their persisted roles restored yet
+        if !verification::is_pending(&ctx, member.guild_id, member.user.id).await {
+            persistent_roles::guild_member_update(&ctx, &member).await;
+        }
+        role_caps::guild_member_update(&ctx, old.as_ref(), &member).await;
+        role_dependencies::guild_member_update(&ctx, old.as_ref(), &member).await;
+        reaction_roles::template::member_updated(old.as_ref(), &member);
+        role_changes::guild_member_update(&ctx, old.as_ref(), &member).await;
+        screening::guild_member_update(&ctx, old.as_ref(), &member).await;
+    }
+
+    async fn message(&self, ctx: Context, message: Message) {
+        if let Some(guild_id) = message.guild_id {
+            dormancy::wake(&ctx, guild_id).await;
+        }
+
+        // without the members intent, Discord's join message is the only sign of someone joining
+        if message.kind == MessageType::MemberJoin && !capabilities::has_members_intent() {
+            if let Some(guild_id) = message.guild_id {
+                match members::member(&ctx, guild_id, message.author.id).await {
+                    Ok(member) => member_joined(&ctx, member).await,
+                    Err(err) => error!("failed to fetch {} after their join message: {:?}", message.author.id, err),
+                }
+            }
+            return;
+        }
+
+        if verification::message(&ctx, &message).await || appeals::direct_message(&ctx, &message).await {
+            return;
+        }
+        ping_tracker::message(&ctx, &message).await;
+        emoji_stats::message(&message);
+
+        if message.author.bot {
+            return;
+        }
+        if let (Some(guild_id), Some(member)) = (message.guild_id, &message.member) {
+            if !capabilities::has_members_intent() {
+                persistent_roles::member_seen(&ctx, guild_id, message.author.id, &member.roles).await;
+            }
+        }
+        let settings = mentions::settings(&ctx, message.guild_id).await;
+        let bot = ctx.cache.current_user().id;
+        match mentions::classify(&message.content, bot, settings.prefix.as_deref()) {
+            mentions::Addressed::Command(tokens) => handle_command(&tokens, &ctx, &message).await,
+            mentions::Addressed::Bare => {
+                if let Err(err) = status::quick_start(&ctx, &message).await {
+                    error!("failed to send quick start: {:?}", err);
+                }
+            }
+            mentions::Addressed::Casual => mentions::casual_mention(&ctx, &message, &settings).await,
+            mentions::Addressed::NotAddressed => (),
+        }
+    }
+
+    async fn message_delete(&self, ctx: Context, channel_id: ChannelId, deleted_message_id: MessageId, guild_id: Option<GuildId>) {
+        if let Some(guild_id) = guild_id {
+            dormancy::wake(&ctx, guild_id).await;
+            autopin::delete_message(&ctx, guild_id, channel_id, deleted_message_id).await;
+            reaction_roles::progress::delete_message(&ctx, guild_id, deleted_message_id).await;
+        }
+        event_signups::delete_message(&ctx, deleted_message_id).await;
+        reaction_roles::delete_message(ctx, guild_id, channel_id, deleted_message_id).await;
+    }
+
+    async fn message_update(&self, ctx: Context, _old_if_available: Option<Message>, _new: Option<Message>, event: MessageUpdateEvent) {
+        if let Some(guild_id) = event.guild_id {
+            dormancy::wake(&ctx, guild_id).await;
+        }
+        reaction_roles::update_message(ctx, event).await;
+    }
+
+    async fn reaction_add(&self, ctx: Context, reaction: Reaction) {
+        if let Some(guild_id) = reaction.guild_id {
+            dormancy::wake(&ctx, guild_id).await;
+        }
+        emoji_stats::add_reaction(&ctx, &reaction);
+        if let Some(member) = reaction.member.as_ref().filter(|_| !capabilities::has_members_intent()) {
+            persistent_roles::member_seen(&ctx, member.guild_id, member.user.id, &member.roles).await;
+        }
+        if read_only::blocks(&ctx, reaction.guild_id, || format!("handled the {} reaction to {}", reaction.emoji, reaction.message_id)).await {
+            return;
+        }
+        if let Err(err) = autopin::add_reaction(&ctx, &reaction).await {
+            error!("failed to autopin message: {:?}", err);
+        }
+        if let Err(err) = event_signups::add_reaction(&ctx, &reaction).await {
+            error!("failed to sign up for event: {:?}", err);
+        }
+        if let Err(err) = reaction_roles::progress::add_reaction(&ctx, &reaction).await {
+            error!("failed to track reaction progress: {:?}", err);
+        }
+        if let Err(err) = reaction_roles::add_reaction(ctx, reaction).await {
+            error!("failed to add reaction role: {:?}", err);
+        }
+    }
+
+    async fn reaction_remove(&self, ctx: Context, reaction: Reaction) {
+        if let Some(guild_id) = reaction.guild_id {
+            dormancy::wake(&ctx, guild_id).await;
+        }
+        if read_only::blocks(&ctx, reaction.guild_id, || format!("handled the removed {} reaction from {}", reaction.emoji, reaction.message_id)).await {
+            return;
+        }
+        if let Err(err) = autopin::remove_reaction(&ctx, &reaction).await {
+            error!("failed to unpin message: {:?}", err);
+        }
+        if let Err(err) = event_signups::remove_reaction(&ctx, &reaction).await {
+            error!("failed to withdraw from event: {:?}", err);
+        }
+        reaction_roles::progress::remove_reaction(&ctx, &reaction).await;
+        if let Err(err) = reaction_roles::remove_reaction(&ctx, reaction).await {
+            error!("failed to remove reaction role: {:?}", err);
+        }
+    }
+
+    async fn thread_update(&self, ctx: Context, _old: Option<GuildChannel>, new: GuildChannel) {
+        reaction_roles::threads::thread_update(&ctx, &new).await;
+    }
+
+    async fn thread_delete(&self, ctx: Context, thread: PartialGuildChannel, _full_thread_data: Option<GuildChannel>) {
+        reaction_roles::threads::thread_delete(&ctx, &thread).await;
+    }
+
+    async fn guild_members_chunk(&self, _ctx: Context, chunk: GuildMembersChunkEvent) {
+        members::guild_members_chunk(&chunk);
+    }
+
+    async fn guild_create(&self, _ctx: Context, guild: Guild, _is_new: Option<bool>) {
+        logging::guild_create(guild.id);
+    }
+
+    async fn guild_role_update(&self, ctx: Context, old: Option<Role>, new: Role) {
+        let guild_id = new.guild_id;
+        dormancy::wake(&ctx, guild_id).await;
+        if let Some(old) = old.filter(|old| old.name != new.name) {
+            audit::log(&ctx, guild_id, format!("🏷 <@&{}> was renamed from `{}` to `{}`", new.id.get(), old.name, new.name)).await;
+            reaction_roles::rename_role(&ctx, guild_id, new.id, &old.name, &new.name).await;
+        }
+    }
+
+    async fn guild_delete(&self, ctx: Context, incomplete: UnavailableGuild, full: Option<Guild>) {
+        retention::guild_delete(&ctx, incomplete, full).await;
+    }
+
+    async fn ready(&self, ctx: Context, ready: serenity::model::gateway::Ready) {
+        logging::set_known_guilds(ready.guilds.iter().map(|guild| guild.id));
+        info!("bot is ready!");
+        capabilities::ready();
+        interactions::register(&ctx).await;
+        scheduler::start(&ctx);
+        metrics::start(&ctx).await;
+    }
+
+    async fn interaction_create(&self, ctx: Context, interaction: Interaction) {
+        interactions::interaction_create(&ctx, interaction).await;
+    }
+}
+
+async fn member_joined(ctx: &Context, member: Member) {
+    let guild_id = member.guild_id;
+    members::guild_member_addition(guild_id, member.user.id);
+    reaction_roles::template::roles_changed(member.roles.iter().copied());
+    events::publish(ctx, events::Event::MemberJoined { guild: guild_id, user: member.user.id }).await;
+    join_queue::guild_member_addition(ctx, member).await;
+}
+
+async fn handle_command(tokens: &[&str], ctx: &Context, message: &Message) {
+    if let Some(guild_id) = message.guild_id {
+        dormancy::record_activity(ctx, guild_id).await;
+    }
+    let result = match command_usage::check(ctx, message, tokens).await {
+        Ok(()) => try_handle_command(tokens, ctx, message).await,
+        Err(err) => Err(err),
+    };
+    telemetry::count_command();
+
+    // in locked channels, neither the reaction nor the reply would show up
+    let permissions = own_channel_permissions(ctx, message.channel_id).await;
+    let can_react = permissions.contains(Permissions::ADD_REACTIONS | Permissions::READ_MESSAGE_HISTORY);
+    let can_reply = permissions.contains(Permissions::SEND_MESSAGES | Permissions::READ_MESSAGE_HISTORY);
+
+    if can_react {
+        let reaction = if result.is_ok() { "✅" } else { "❌" };
+        let _ = message.react(&ctx, ReactionType::Unicode(reaction.to_owned())).await;
+    }
+
+    if let Err(err) = result {
+        let reply = if can_reply { message.reply(&ctx, err.to_string()).await.ok() } else { None };
+        if let Some(reply) = reply {
+            error_cleanup::error_replied(ctx, message, &reply).await;
+        } else {
+            let content = format!("❌ I can't reply in <#{}>, so here's what went wrong with your command: {}", message.channel_id, err);
+            if let Err(dm_err) = message.author.direct_message(&ctx, CreateMessage::new().content(content)).await {
+                error!("failed to tell {} about a command error: {:?}", message.author.id, dm_err);
+            }
+        }
+    }
+}
+
+/// The bot's own permissions in a channel. DMs and channels missing from the cache are assumed to
+/// allow everything, so that replies are at least attempted.
+pub(crate) async fn own_channel_permissions(ctx: &Context, channel: ChannelId) -> Permissions {
+    let channel = match cached_channel(&ctx.cache, channel) {
+        Some(channel) => channel,
+        None => return Permissions::all(),
+    };
+    // threads don't have overwrites of their own, they go by those of their parent
+    let channel = match channel.parent_id.filter(|_| channel.thread_metadata.is_some()) {
+        Some(parent) => match cached_channel(&ctx.cache, parent) {
+            Some(parent) => parent,
+            None => return Permissions::all(),
+        },
+        None => channel,
+    };
+    let current_user = ctx.cache.current_user().id;
+    ctx.cache.guild(channel.guild_id)
+        .and_then(|guild| Some(guild.user_permissions_in(&channel, guild.members.get(&current_user)?)))
+        .unwrap_or_else(Permissions::all)
+}
+
+async fn try_handle_command(tokens: &[&str], ctx: &Context, message: &Message) -> CommandResult<()> {
+    let permissions = message_permissions(ctx, message).await;
+
+    if !read_only::allows_command(tokens) && read_only::is_active(ctx, message.guild_id).await {
+        return Err(CommandError::ReadOnly);
+    }
+
+    match tokens {
+        ["apply", "for", role] => {
+            let role = parse_role_argument(role)?;
+            applications::apply(ctx, message, role).await
+        }
+        ["set", "application", role, channel, questions @ ..] => {
+            require_permission(permissions, Permissions::MANAGE_ROLES)?;
+            let role = parse_role_argument(role)?;
+            let channel = parse_channel_argument(channel)?;
+            applications::set_form(ctx, message, role, channel, questions).await
+        }
+        ["remove", "application", role] => {
+            require_permission(permissions, Permissions::MANAGE_ROLES)?;
+            let role = parse_role_argument(role)?;
+            applications::remove_form(ctx, message, role).await
+        }
+        ["add", "role", "selector", reference] => {
+            require_permission(permissions, Permissions::MANAGE_ROLES)?;
+            let reference = parse_argument(reference)?;
+            reaction_roles::add_selector(ctx, message, reference).await
+        }
+        ["help", filter @ ..] => help::help(ctx, message, filter).await,
+        ["sync", "my", "roles"] => reaction_roles::sync_member(ctx, message).await,
+        ["roles"] => reaction_roles::list_obtainable(ctx, message).await,
+        ["warn", user, reason @ ..] if !reason.is_empty() => {
+            require_permission(permissions, Permissions::KICK_MEMBERS)?;
+            let user = parse_user_argument(user)?;
+            cases::warn(ctx, message, user, &reason.join(" ")).await
+        }
+        ["case", id] => {
+            require_permission(permissions, Permissions::KICK_MEMBERS)?;
+            cases::show(ctx, message, parse_case_argument(id)?).await
+        }
+        ["case", "edit", id, "reason", reason @ ..] if !reason.is_empty() => {
+            require_permission(permissions, Permissions::KICK_MEMBERS)?;
+            cases::edit_reason(ctx, message, parse_case_argument(id)?, reason.join(" ")).await
+        }
+        ["cases", user] => {
+            require_permission(permissions, Permissions::KICK_MEMBERS)?;
+            let user = parse_user_argument(user)?;
+            cases::list(ctx, message, user).await
+        }
+        ["appeals", "off"] => {
+            require_permission(permissions, Permissions::KICK_MEMBERS)?;
+            appeals::set_channel(ctx, message, None).await
+        }
+        ["appeals", "channel", channel] => {
+            require_permission(permissions, Permissions::KICK_MEMBERS)?;
+            let channel = parse_channel_argument(channel)?;
+            appeals::set_channel(ctx, message, Some(channel)).await
+        }
+        ["selector", "ban", user] => {
+            require_permission(permissions, Permissions::MANAGE_ROLES)?;
+            let user = parse_user_argument(user)?;
+            selector_bans::ban(ctx, message, user).await
+        }
+        ["selector", "unban", user] => {
+            require_permission(permissions, Permissions::MANAGE_ROLES)?;
+            let user = parse_user_argument(user)?;
+            selector_bans::unban(ctx, message, user).await
+        }
+        ["selector", "sweep"] => {
+            require_permission(permissions, Permissions::MANAGE_ROLES)?;
+            reaction_roles::sweep::status(ctx, message).await
+        }
+        ["selector", "sweep", "off"] => {
+            require_permission(permissions, Permissions::MANAGE_ROLES)?;
+            reaction_roles::sweep::set_sweep(ctx, message, None).await
+        }
+        ["selector", "sweep", mode @ ("report" | "fix"), interval @ ..] if interval.len() <= 1 => {
+            require_permission(permissions, Permissions::MANAGE_ROLES)?;
+            let interval_secs = match interval {
+                [interval] => eligibility::parse_duration(interval).ok_or_else(|| CommandError::MalformedArgument((*interval).to_owned()))?,
+                _ => reaction_roles::sweep::DEFAULT_INTERVAL_SECS,
+            };
+            let sweep = reaction_roles::sweep::Sweep::new(*mode == "fix", interval_secs);
+            reaction_roles::sweep::set_sweep(ctx, message, Some(sweep)).await
+        }
+        ["onboarding", "dm"] => {
+            require_permission(permissions, Permissions::MANAGE_ROLES)?;
+            reaction_roles::onboarding::status(ctx, message).await
+        }
+        ["onboarding", "dm", "off"] => {
+            require_permission(permissions, Permissions::MANAGE_ROLES)?;
+            reaction_roles::onboarding::set_selectors(ctx, message, Vec::new()).await
+        }
+        ["onboarding", "dm", references @ ..] => {
+            require_permission(permissions, Permissions::MANAGE_ROLES)?;
+            let mut selectors = Vec::new();
+            for reference in references {
+                selectors.push(reaction_roles::names::resolve(ctx, message, reference).await?);
+            }
+            reaction_roles::onboarding::set_selectors(ctx, message, selectors).await
+        }
+        ["progress", "roles"] => {
+            require_permission(permissions, Permissions::MANAGE_ROLES)?;
+            reaction_roles::progress::list(ctx, message).await
+        }
+        ["progress", "role", role, "off"] => {
+            require_permission(permissions, Permissions::MANAGE_ROLES)?;
+            let role = parse_role_argument(role)?;
+            reaction_roles::progress::remove_tracker(ctx, message, role).await
+        }
+        ["progress", "role", role, links @ ..] if !links.is_empty() => {
+            require_permission(permissions, Permissions::MANAGE_ROLES)?;
+            let role = parse_role_argument(role)?;
+            let (required, links) = match links {
+                [count, links @ ..] if count.parse::<usize>().is_ok() => (Some(parse_argument(count)?), links),
+                _ => (None, links),
+            };
+            let mut messages = Vec::new();
+            for link in links {
+                let (guild, _, target) = parse_message_link(link)?;
+                if Some(guild) != message.guild_id {
+                    return Err(CommandError::InvalidMessageReference);
+                }
+                messages.push(target);
+            }
+            reaction_roles::progress::set_tracker(ctx, message, role, messages, required).await
+        }
+        ["selector", "bans"] => {
+            require_permission(permissions, Permissions::MANAGE_ROLES)?;
+            selector_bans::list(ctx, message).await
+        }
+        ["selector", "requirements"] => {
+            require_permission(permissions, Permissions::MANAGE_ROLES)?;
+            eligibility::show(ctx, message).await
+        }
+        ["selector", "requirements", requirement @ ("account-age" | "membership"), duration] => {
+            require_permission(permissions, Permissions::MANAGE_ROLES)?;
+            let requirement = match *requirement {
+                "account-age" => eligibility::Requirement::AccountAge,
+                _ => eligibility::Requirement::Membership,
+            };
+            let duration = match *duration {
+                "off" => None,
+                duration => Some(eligibility::parse_duration(duration).ok_or_else(|| CommandError::MalformedArgument(duration.to_owned()))?),
+            };
+            eligibility::set_requirement(ctx, message, requirement, duration).await
+        }
+        ["generate", "role", "selector", "from-category", category] => {
+            require_permission(permissions, Permissions::MANAGE_ROLES | Permissions::MANAGE_CHANNELS)?;
+            let category = parse_channel_argument(category)?;
+            reaction_roles::generate::from_category(ctx, message, category).await
+        }
+        ["list", "role", "selectors"] => {
+            require_permission(permissions, Permissions::MANAGE_ROLES)?;
+            reaction_roles::list_selectors(ctx, message).await
+        }
+        ["remove", "role", "selector", reference] => {
+            require_permission(permissions, Permissions::MANAGE_ROLES)?;
+            let reference = reaction_roles::names::resolve(ctx, message, reference).await?;
+            reaction_roles::remove_selector(ctx, message, reference).await
+        }
+        ["move", "role", "selector", reference, link] => {
+            require_permission(permissions, Permissions::MANAGE_ROLES)?;
+            let reference = reaction_roles::names::resolve(ctx, message, reference).await?;
+            let (guild, channel, target) = parse_message_link(link)?;
+            if Some(guild) != message.guild_id {
+                return Err(CommandError::InvalidMessageReference);
+            }
+            reaction_roles::move_selector(ctx, message, reference, channel, target).await
+        }
+        ["name", "role", "selector", reference, "off"] => {
+            require_permission(permissions, Permissions::MANAGE_ROLES)?;
+            let reference = reaction_roles::names::resolve(ctx, message, reference).await?;
+            reaction_roles::names::unname_selector(ctx, message, reference).await
+        }
+        ["name", "role", "selector", reference, name] => {
+            require_permission(permissions, Permissions::MANAGE_ROLES)?;
+            let reference = reaction_roles::names::resolve(ctx, message, reference).await?;
+            reaction_roles::names::name_selector(ctx, message, reference, name).await
+        }
+        ["stats", "role", "selector", reference] => {
+            require_permission(permissions, Permissions::MANAGE_ROLES)?;
+            let reference = reaction_roles::names::resolve(ctx, message, reference).await?;
+            reaction_roles::selector_stats(ctx, message, reference).await
+        }
+        ["clear", "reactions", reference, flags @ ..] => {
+            require_permission(permissions, Permissions::MANAGE_MESSAGES)?;
+            let reference = parse_argument(reference)?;
+            let strip_roles = match flags {
+                [] => false,
+                ["strip-roles"] => {
+                    require_permission(permissions, Permissions::MANAGE_ROLES)?;
+                    true
+                }
+                _ => return Err(CommandError::InvalidCommand),
+            };
+            reaction_roles::archive::clear_reactions(ctx, message, reference, strip_roles).await
+        }
+        ["undo"] => {
+            require_permission(permissions, Permissions::MANAGE_ROLES)?;
+            reaction_roles::archive::undo(ctx, message).await
+        }
+        ["restore", "role", "selector", reference] => {
+            require_permission(permissions, Permissions::MANAGE_ROLES)?;
+            let reference = parse_argument(reference)?;
+            reaction_roles::archive::restore(ctx, message, reference).await
+        }
+        ["pause", "role", "selector", reference, flags @ ..] => {
+            require_permission(permissions, Permissions::MANAGE_ROLES)?;
+            let reference = reaction_roles::names::resolve(ctx, message, reference).await?;
+            let clear_reactions = match flags {
+                [] => false,
+                ["clear"] => true,
+                _ => return Err(CommandError::InvalidCommand),
+            };
+            reaction_roles::pause_selector(ctx, message, reference, clear_reactions).await
+        }
+        ["resume", "role", "selector", reference] => {
+            require_permission(permissions, Permissions::MANAGE_ROLES)?;
+            let reference = reaction_roles::names::resolve(ctx, message, reference).await?;
+            reaction_roles::resume_selector(ctx, message, reference).await
+        }
+        ["refresh", "role", "selector", reference, policy @ ..] => {
+            require_permission(permissions, Permissions::MANAGE_ROLES)?;
+            let reference = reaction_roles::names::resolve(ctx, message, reference).await?;
+            let update = match policy {
+                ["every", minutes] => reaction_roles::RefreshUpdate::Interval(parse_argument(minutes)?),
+                ["depth", depth] => reaction_roles::RefreshUpdate::Depth(parse_argument(depth)?),
+                ["off"] => reaction_roles::RefreshUpdate::Off,
+                _ => return Err(CommandError::InvalidCommand),
+            };
+            reaction_roles::set_refresh(ctx, reference, update).await
+        }
+        ["set", "role", "selector", reference, "exclusive", toggle @ ("on" | "off")] => {
+            require_permission(permissions, Permissions::MANAGE_ROLES)?;
+            let reference = reaction_roles::names::resolve(ctx, message, reference).await?;
+            reaction_roles::set_exclusive(ctx, reference, *toggle == "on").await
+        }
+        ["set", "role", "selector", reference, "confirm-switch", toggle @ ("on" | "off")] => {
+            require_permission(permissions, Permissions::MANAGE_ROLES)?;
+            let reference = reaction_roles::names::resolve(ctx, message, reference).await?;
+            reaction_roles::set_confirm_switch(ctx, reference, *toggle == "on").await
+        }
+        ["set", "role", "selector", reference, "option", emoji, setting @ ..] => {
+            require_permission(permissions, Permissions::MANAGE_ROLES)?;
+            let reference = reaction_roles::names::resolve(ctx, message, reference).await?;
+            let emoji: reaction_roles::Emoji = parse_argument(emoji)?;
+            let update = match setting {
+                ["sticky", toggle @ ("on" | "off")] => reaction_roles::OptionUpdate::Sticky(*toggle == "on"),
+                ["add-only", toggle @ ("on" | "off")] => reaction_roles::OptionUpdate::AddOnly(*toggle == "on"),
+                ["expires", "off"] => reaction_roles::OptionUpdate::ExpiresAfter(None),
+                ["expires", duration] => {
+                    let after = eligibility::parse_duration(duration).ok_or_else(|| CommandError::MalformedArgument((*duration).to_owned()))?;
+                    reaction_roles::OptionUpdate::ExpiresAfter(Some(after))
+                }
+                ["description", "off"] => reaction_roles::OptionUpdate::Description(None),
+                ["description", description @ ..] if !description.is_empty() => {
+                    reaction_roles::OptionUpdate::Description(Some(description.join(" ")))
+                }
+                _ => return Err(CommandError::InvalidCommand),
+            };
+            reaction_roles::set_option(ctx, reference, &emoji, update).await
+        }
+        ["set", "role", "selector", reference, "preview", toggle @ ("on" | "off")] => {
+            require_permission(permissions, Permissions::MANAGE_ROLES)?;
+            let reference = reaction_roles::names::resolve(ctx, message, reference).await?;
+            reaction_roles::set_preview(ctx, reference, *toggle == "on").await
+        }
+        ["set", "role", "selector", reference, "approval", channel] => {
+            require_permission(permissions, Permissions::MANAGE_ROLES)?;
+            let reference = reaction_roles::names::resolve(ctx, message, reference).await?;
+            let channel = match *channel {
+                "off" => None,
+                channel => Some(parse_channel_argument(channel)?),
+            };
+            reaction_roles::set_approval_channel(ctx, reference, channel).await
+        }
+        ["set", "role", "selector", reference, "template", "off"] => {
+            require_permission(permissions, Permissions::MANAGE_ROLES)?;
+            let reference = reaction_roles::names::resolve(ctx, message, reference).await?;
+            reaction_roles::template::set_template(ctx, message, reference, None).await
+        }
+        ["set", "role", "selector", reference, "template", ..] => {
+            require_permission(permissions, Permissions::MANAGE_ROLES)?;
+            let reference = reaction_roles::names::resolve(ctx, message, reference).await?;
+            // the template is given on the lines after the command
+            let template = match message.content.split_once('\n') {
+                Some((_, template)) if !template.trim().is_empty() => template.trim().to_owned(),
+                _ => return Err(CommandError::InvalidCommand),
+            };
+            reaction_roles::template::set_template(ctx, message, reference, Some(template)).await
+        }
+        ["set", "role", "selector", reference, "log", channel] => {
+            require_permission(permissions, Permissions::MANAGE_ROLES)?;
+            let reference = reaction_roles::names::resolve(ctx, message, reference).await?;
+            let channel = match *channel {
+                "off" => None,
+                channel => Some(parse_channel_argument(channel)?),
+            };
+            reaction_roles::set_log_channel(ctx, reference, channel).await
+        }
+        ["set", "role", "selector", reference, "unmapped", policy @ ..] => {
+            require_permission(permissions, Permissions::MANAGE_ROLES)?;
+            let reference = reaction_roles::names::resolve(ctx, message, reference).await?;
+            let policy = match policy {
+                ["delete"] => reaction_roles::UnmappedPolicy::Delete,
+                ["ignore"] => reaction_roles::UnmappedPolicy::Ignore,
+                ["allow", emoji @ ..] if !emoji.is_empty() => {
+                    let emoji = emoji.iter()
+                        .map(|emoji| parse_argument(emoji))
+                        .collect::<CommandResult<_>>()?;
+                    reaction_roles::UnmappedPolicy::Allow { emoji }
+                }
+                _ => return Err(CommandError::InvalidCommand),
+            };
+            reaction_roles::set_unmapped_policy(ctx, reference, policy).await
+        }
+        ["add", "role", "persist", "matching", pattern @ ..] if !pattern.is_empty() => {
+            require_permission(permissions, Permissions::MANAGE_ROLES)?;
+            persistent_roles::add_roles_matching(ctx, message, &pattern.join(" ")).await
+        }
+        ["add", "role", "persist", refs @ ..] => {
+            require_permission(permissions, Permissions::MANAGE_ROLES)?;
+            persistent_roles::update_roles(ctx, message, refs, true).await
+        }
+        ["set", "persist", "failure", "dm", toggle @ ("on" | "off")] => {
+            require_permission(permissions, Permissions::MANAGE_GUILD)?;
+            persistent_roles::set_notify_owner(ctx, message, *toggle == "on").await
+        }
+        ["set", "persist", "on-ban", policy @ ("keep" | "freeze" | "purge")] => {
+            require_permission(permissions, Permissions::MANAGE_GUILD)?;
+            let policy = match *policy {
+                "freeze" => persistent_roles::BanPolicy::Freeze,
+                "purge" => persistent_roles::BanPolicy::Purge,
+                _ => persistent_roles::BanPolicy::Keep,
+            };
+            persistent_roles::set_on_ban(ctx, message, policy).await
+        }
+        ["set", "persist", "unban", "restore", toggle @ ("on" | "off")] => {
+            require_permission(permissions, Permissions::MANAGE_GUILD)?;
+            persistent_roles::set_restore_on_unban(ctx, message, *toggle == "on").await
+        }
+        ["remove", "role", "persist", refs @ ..] => {
+            require_permission(permissions, Permissions::MANAGE_ROLES)?;
+            persistent_roles::update_roles(ctx, message, refs, false).await
+        }
+        ["autopin", "off"] => {
+            require_permission(permissions, Permissions::MANAGE_MESSAGES)?;
+            autopin::set_threshold(ctx, message, None).await
+        }
+        ["autopin", threshold] => {
+            require_permission(permissions, Permissions::MANAGE_MESSAGES)?;
+            let threshold: u64 = parse_argument(threshold)?;
+            if threshold == 0 {
+                return Err(CommandError::MalformedArgument(threshold.to_string()));
+            }
+            autopin::set_threshold(ctx, message, Some(threshold)).await
+        }
+        ["screening"] => {
+            require_permission(permissions, Permissions::MANAGE_GUILD)?;
+            screening::status(ctx, message).await
+        }
+        ["screening", "log", toggle @ ("on" | "off")] => {
+            require_permission(permissions, Permissions::MANAGE_GUILD)?;
+            screening::set_logging(ctx, message, *toggle == "on").await
+        }
+        ["screening", "role", "off"] => {
+            require_permission(permissions, Permissions::MANAGE_GUILD)?;
+            screening::set_role(ctx, message, None).await
+        }
+        ["screening", "role", role] => {
+            require_permission(permissions, Permissions::MANAGE_GUILD | Permissions::MANAGE_ROLES)?;
+            let role = parse_role_argument(role)?;
+            screening::set_role(ctx, message, Some(role)).await
+        }
+        ["verification"] => {
+            require_permission(permissions, Permissions::MANAGE_GUILD)?;
+            verification::status(ctx, message).await
+        }
+        ["verification", "on", role, channel @ ..] if channel.len() <= 1 => {
+            require_permission(permissions, Permissions::MANAGE_GUILD | Permissions::MANAGE_ROLES | Permissions::KICK_MEMBERS)?;
+            let role = parse_role_argument(role)?;
+            let channel = channel.first().map(|channel| parse_channel_argument(channel)).transpose()?;
+            verification::enable(ctx, message, role, channel).await
+        }
+        ["verification", "off"] => {
+            require_permission(permissions, Permissions::MANAGE_GUILD)?;
+            verification::disable(ctx, message).await
+        }
+        ["verification", "kick-after", minutes] => {
+            require_permission(permissions, Permissions::MANAGE_GUILD | Permissions::KICK_MEMBERS)?;
+            let minutes = match *minutes {
+                "never" => None,
+                minutes => Some(parse_argument(minutes)?),
+            };
+            verification::set_kick_after(ctx, message, minutes).await
+        }
+        ["set", "timezone", zone] => {
+            require_permission(permissions, Permissions::MANAGE_GUILD)?;
+            let zone = timezone::parse_zone(zone).ok_or_else(|| CommandError::MalformedArgument(zone.to_string()))?;
+            timezone::set_zone(ctx, message, zone).await
+        }
+        ["slowmode", "schedule", channel, "off"] => {
+            require_permission(permissions, Permissions::MANAGE_CHANNELS)?;
+            let channel = parse_channel_argument(channel)?;
+            slowmode::set_schedule(ctx, message, channel, None).await
+        }
+        ["slowmode", "schedule", channel, days, window, rate] => {
+            require_permission(permissions, Permissions::MANAGE_CHANNELS)?;
+            let channel = parse_channel_argument(channel)?;
+            let days = parse_argument(days)?;
+            let window = parse_argument(window)?;
+            let rate = slowmode::parse_rate(rate).ok_or_else(|| CommandError::MalformedArgument(rate.to_string()))?;
+            slowmode::set_schedule(ctx, message, channel, Some(slowmode::Schedule::new(days, window, rate))).await
+        }
+        ["event", "create", title, "cap", capacity, role] => {
+            require_permission(permissions, Permissions::MANAGE_ROLES)?;
+            let capacity = parse_argument(capacity)?;
+            let role = parse_role_argument(role)?;
+            event_signups::create_event(ctx, message, title, capacity, role).await
+        }
+        ["ping", "cooldown", role, "off"] => {
+            require_permission(permissions, Permissions::MANAGE_ROLES)?;
+            let role = parse_role_argument(role)?;
+            ping_tracker::track_role(ctx, message, role, None).await
+        }
+        ["ping", "cooldown", role, seconds, action] => {
+            require_permission(permissions, Permissions::MANAGE_ROLES)?;
+            let role = parse_role_argument(role)?;
+            let seconds = parse_argument(seconds)?;
+            let action = match *action {
+                "warn" => ping_tracker::CooldownAction::Warn,
+                "lock" => ping_tracker::CooldownAction::Lock,
+                _ => return Err(CommandError::MalformedArgument(action.to_string())),
+            };
+            ping_tracker::track_role(ctx, message, role, Some((seconds, action))).await
+        }
+        ["ping", "stats", role] => {
+            require_permission(permissions, Permissions::MANAGE_ROLES)?;
+            let role = parse_role_argument(role)?;
+            ping_tracker::role_stats(ctx, message, role).await
+        }
+        ["export", "guild-setup"] => {
+            require_permission(permissions, Permissions::MANAGE_GUILD)?;
+            guild_setup::export(ctx, message).await
+        }
+        ["import", "guild-setup", flags @ ..] => {
+            require_permission(permissions, Permissions::MANAGE_GUILD | Permissions::MANAGE_ROLES)?;
+            let create_roles = match flags {
+                [] => false,
+                ["create-roles"] => true,
+                _ => return Err(CommandError::InvalidCommand),
+            };
+            guild_setup::import(ctx, message, create_roles).await
+        }
+        ["backup"] => {
+            require_permission(permissions, Permissions::MANAGE_GUILD)?;
+            backups::status(ctx, message).await
+        }
+        ["backup", "now"] => {
+            require_permission(permissions, Permissions::MANAGE_GUILD)?;
+            backups::post_now(ctx, message).await
+        }
+        ["backup", "channel", "off"] => {
+            require_permission(permissions, Permissions::MANAGE_GUILD)?;
+            backups::set_channel(ctx, message, None).await
+        }
+        ["backup", "channel", channel] => {
+            require_permission(permissions, Permissions::MANAGE_GUILD)?;
+            let channel = parse_channel_argument(channel)?;
+            backups::set_channel(ctx, message, Some(channel)).await
+        }
+        ["export", "config"] => {
+            require_permission(permissions, Permissions::MANAGE_GUILD)?;
+            config_mirror::export(ctx, message).await
+        }
+        ["import", "config"] => {
+            require_permission(permissions, Permissions::MANAGE_GUILD | Permissions::MANAGE_ROLES)?;
+            config_mirror::import(ctx, message).await
+        }
+        ["config", "sync"] => {
+            require_permission(permissions, Permissions::MANAGE_GUILD)?;
+            config_sync::status(ctx, message).await
+        }
+        ["config", "sync", "now"] => {
+            require_permission(permissions, Permissions::MANAGE_GUILD)?;
+            config_sync::sync_now(ctx, message).await
+        }
+        ["config", "sync", "off"] => {
+            require_permission(permissions, Permissions::MANAGE_GUILD)?;
+            config_sync::set_source(ctx, message, None, false).await
+        }
+        ["config", "sync", url, mode @ ..] => {
+            require_permission(permissions, Permissions::MANAGE_GUILD | Permissions::MANAGE_ROLES)?;
+            let report_only = match mode {
+                [] => false,
+                ["report"] => true,
+                _ => return Err(CommandError::InvalidCommand),
+            };
+            config_sync::set_source(ctx, message, Some(url.trim_start_matches('<').trim_end_matches('>')), report_only).await
+        }
+        ["read-only"] => read_only::status(ctx, message).await,
+        ["read-only", "global", toggle @ ("on" | "off")] => read_only::set_global_command(ctx, message, *toggle == "on").await,
+        ["read-only", toggle @ ("on" | "off")] => {
+            require_permission(permissions, Permissions::ADMINISTRATOR)?;
+            read_only::set_guild(ctx, message, *toggle == "on").await
+        }
+        ["experiments"] => {
+            require_permission(permissions, Permissions::MANAGE_GUILD)?;
+            experiments::status(ctx, message).await
+        }
+        ["command", "prefix"] => mentions::status(ctx, message).await,
+        ["command", "prefix", "off"] => {
+            require_permission(permissions, Permissions::MANAGE_GUILD)?;
+            mentions::set_prefix(ctx, message, None).await
+        }
+        ["command", "prefix", prefix] => {
+            require_permission(permissions, Permissions::MANAGE_GUILD)?;
+            mentions::set_prefix(ctx, message, Some(prefix)).await
+        }
+        ["mention", "hint", toggle @ ("on" | "off")] => {
+            require_permission(permissions, Permissions::MANAGE_GUILD)?;
+            mentions::set_casual_hint(ctx, message, *toggle == "on").await
+        }
+        ["error", "cleanup"] => {
+            require_permission(permissions, Permissions::MANAGE_GUILD)?;
+            error_cleanup::status(ctx, message).await
+        }
+        ["error", "cleanup", "off"] => {
+            require_permission(permissions, Permissions::MANAGE_GUILD)?;
+            error_cleanup::set_cleanup(ctx, message, None, false).await
+        }
+        ["error", "cleanup", secs, flags @ ..] => {
+            require_permission(permissions, Permissions::MANAGE_GUILD)?;
+            let commands = match flags {
+                [] => false,
+                ["with-commands"] => true,
+                _ => return Err(CommandError::InvalidCommand),
+            };
+            let secs: u64 = parse_argument(secs)?;
+            if secs == 0 || secs > error_cleanup::MAX_DELAY_SECS {
+                return Err(CommandError::MalformedArgument(secs.to_string()));
+            }
+            error_cleanup::set_cleanup(ctx, message, Some(secs), commands).await
+        }
+        ["latency"] => {
+            require_permission(permissions, Permissions::MANAGE_GUILD)?;
+            latency::status(ctx, message).await
+        }
+        ["latency", "alert", "off"] => {
+            require_permission(permissions, Permissions::MANAGE_GUILD)?;
+            latency::set_limit(ctx, message, None).await
+        }
+        ["latency", "alert", p95_ms, minutes @ ..] if minutes.len() <= 1 => {
+            require_permission(permissions, Permissions::MANAGE_GUILD)?;
+            let p95_ms = parse_argument(p95_ms)?;
+            let sustained_mins = match minutes {
+                [minutes] => parse_argument(minutes)?,
+                _ => latency::DEFAULT_SUSTAINED_MINS,
+            };
+            latency::set_limit(ctx, message, Some(latency::Limit { p95_ms, sustained_mins })).await
+        }
+        ["command", "stats"] => {
+            require_permission(permissions, Permissions::MANAGE_GUILD)?;
+            command_usage::show_stats(ctx, message).await
+        }
+        ["quotas"] => {
+            require_permission(permissions, Permissions::MANAGE_GUILD)?;
+            quotas::show(ctx, message).await
+        }
+        ["quota", "set", guild, quota, limit] => {
+            let guild = match *guild {
+                "here" => message.guild_id.ok_or(CommandError::NotAllowed)?,
+                guild => parse_argument(guild)?,
+            };
+            let quota = parse_argument(quota)?;
+            let limit = match *limit {
+                "default" => None,
+                limit => Some(parse_argument(limit)?),
+            };
+            quotas::set_limit(ctx, message, guild, quota, limit).await
+        }
+        ["memreport"] => memreport::report(ctx, message).await,
+        #[cfg(feature = "replication")]
+        ["migrate", "to", "replica", flags @ ..] => {
+            let dry_run = match flags {
+                [] => false,
+                ["dry-run"] => true,
+                _ => return Err(CommandError::InvalidCommand),
+            };
+            replication::migrate::command(ctx, message, dry_run).await
+        }
+        ["selfcheck"] => validate::selfcheck(ctx, message).await,
+        ["search", "history", query @ ..] => {
+            require_permission(permissions, Permissions::MANAGE_ROLES)?;
+            history::search(ctx, message, query).await
+        }
+        ["data", "retention"] => {
+            require_permission(permissions, Permissions::MANAGE_GUILD)?;
+            retention::status(ctx, message).await
+        }
+        ["data", "retention", "set", days] => {
+            require_permission(permissions, Permissions::MANAGE_GUILD)?;
+            let days = parse_argument(days)?;
+            retention::set_days(ctx, message, days).await
+        }
+        ["setup", "status"] => {
+            require_permission(permissions, Permissions::MANAGE_GUILD)?;
+            status::setup_status(ctx, message).await
+        }
+        ["doctor"] => {
+            require_permission(permissions, Permissions::MANAGE_GUILD)?;
+            doctor::run(ctx, message).await
+        }
+        ["telemetry", "status"] => telemetry::status(ctx, message).await,
+        ["role", "color" | "colour", role, colour] => {
+            require_permission(permissions, Permissions::MANAGE_ROLES)?;
+            let role = parse_role_argument(role)?;
+            let colour = role_admin::parse_colour(colour).ok_or_else(|| CommandError::MalformedArgument(colour.to_string()))?;
+            role_admin::set_colour(ctx, message, role, colour).await
+        }
+        ["role", "channel", "link", role, channel] => {
+            require_permission(permissions, Permissions::MANAGE_ROLES | Permissions::MANAGE_CHANNELS)?;
+            let role = parse_role_argument(role)?;
+            let channel = parse_channel_argument(channel)?;
+            channel_links::link_command(ctx, message, role, channel).await
+        }
+        ["role", "channel", "unlink", role, channel] => {
+            require_permission(permissions, Permissions::MANAGE_ROLES | Permissions::MANAGE_CHANNELS)?;
+            let role = parse_role_argument(role)?;
+            let channel = parse_channel_argument(channel)?;
+            channel_links::unlink_command(ctx, message, role, channel).await
+        }
+        ["role", "channel", "links"] => {
+            require_permission(permissions, Permissions::MANAGE_CHANNELS)?;
+            channel_links::list(ctx, message).await
+        }
+        ["role", "cap", role, limit] => {
+            require_permission(permissions, Permissions::MANAGE_ROLES)?;
+            let role = parse_role_argument(role)?;
+            let limit = match *limit {
+                "off" => None,
+                limit => Some(parse_argument(limit)?),
+            };
+            role_caps::set_cap(ctx, message, role, limit).await
+        }
+        ["role", "requires", prerequisite, "for", dependent, toggle @ ..] => {
+            require_permission(permissions, Permissions::MANAGE_ROLES)?;
+            let prerequisite = parse_role_argument(prerequisite)?;
+            let dependent = parse_role_argument(dependent)?;
+            let required = match toggle {
+                [] => true,
+                ["off"] => false,
+                _ => return Err(CommandError::InvalidCommand),
+            };
+            if prerequisite == dependent {
+                return Err(CommandError::RoleDependencyCycle);
+            }
+            role_dependencies::set_dependency(ctx, message, prerequisite, dependent, required).await
+        }
+        ["role", "dependencies"] => {
+            require_permission(permissions, Permissions::MANAGE_ROLES)?;
+            role_dependencies::list(ctx, message).await
+        }
+        ["role", "icon", role, icon @ ..] => {
+            require_permission(permissions, Permissions::MANAGE_ROLES)?;
+            let role = parse_role_argument(role)?;
+            let icon = match icon {
+                [] => role_admin::IconSource::Attachment,
+                ["off"] => role_admin::IconSource::None,
+                [emoji] => role_admin::IconSource::Emoji(emoji.to_string()),
+                _ => return Err(CommandError::InvalidCommand),
+            };
+            role_admin::set_icon(ctx, message, role, icon).await
+        }
+        ["emoji", "stats"] => {
+            require_permission(permissions, Permissions::MANAGE_GUILD_EXPRESSIONS)?;
+            emoji_stats::stats(ctx, message).await
+        }
+        ["webhook", "add", url, events, template @ ..] => {
+            require_permission(permissions, Permissions::MANAGE_GUILD)?;
+            let template = match template {
+                [] => None,
+                [template] => Some(*template),
+                _ => return Err(CommandError::InvalidCommand),
+            };
+            webhooks::add_webhook(ctx, message, url, events, template).await
+        }
+        ["webhook", "remove", id] => {
+            require_permission(permissions, Permissions::MANAGE_GUILD)?;
+            webhooks::remove_webhook(ctx, message, parse_argument(id)?).await
+        }
+        ["list", "webhooks"] => {
+            require_permission(permissions, Permissions::MANAGE_GUILD)?;
+            webhooks::list_webhooks(ctx, message).await
+        }
+        ["set", "log", "channel", "off"] => {
+            require_permission(permissions, Permissions::MANAGE_GUILD)?;
+            audit::set_log_channel(ctx, message, None).await
+        }
+        ["set", "log", "channel", channel] => {
+            require_permission(permissions, Permissions::MANAGE_GUILD)?;
+            let channel = parse_channel_argument(channel)?;
+            audit::set_log_channel(ctx, message, Some(channel)).await
+        }
+        _ => Err(CommandError::InvalidCommand),
+    }
+}
+
+/// Splits a message mentioning the bot into command tokens, leaving out the mentions of the bot.
+pub fn command_tokens(content: &str, bot: UserId) -> Vec<&str> {
+    tokenize(content).into_iter()
+        .filter(|token| serenity::utils::parse_user_mention(token) != Some(bot))
+        .collect()
+}
+
+/// Splits a command into whitespace-separated tokens, keeping "quoted phrases" together.
+pub(crate) fn tokenize(content: &str) -> Vec<&str> {
+    let mut tokens = Vec::new();
+
+    let mut rest = content.trim_start();
+    while !rest.is_empty() {
+        let (token, remainder) = match rest.strip_prefix('"') {
+            Some(quoted) => match quoted.find('"') {
+                Some(end) => (&quoted[..end], &quoted[end + 1..]),
+                None => (quoted, ""),
+            },
+            None => {
+                let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+                rest.split_at(end)
+            }
+        };
+
+        tokens.push(token);
+        rest = remainder.trim_start();
+    }
+
+    tokens
+}
+
+fn parse_argument<T: FromStr>(argument: &str) -> CommandResult<T> {
+    argument.parse::<T>().map_err(|_| CommandError::MalformedArgument(argument.to_owned()))
+}
+
+/// Parses a case ID, which may be written with a leading `#`.
+fn parse_case_argument(argument: &str) -> CommandResult<u64> {
+    parse_argument(argument.trim_start_matches('#'))
+}
+
+/// Parses a role given either as a mention or as a raw ID.
+pub(crate) fn parse_role_argument(argument: &str) -> CommandResult<RoleId> {
+    serenity::utils::parse_role_mention(argument)
+        .or_else(|| argument.parse().ok())
+        .ok_or_else(|| CommandError::MalformedArgument(argument.to_owned()))
+}
+
+/// Parses a user given either as a mention or as a raw ID.
+pub(crate) fn parse_user_argument(argument: &str) -> CommandResult<UserId> {
+    serenity::utils::parse_user_mention(argument)
+        .or_else(|| argument.parse().ok())
+        .ok_or_else(|| CommandError::MalformedArgument(argument.to_owned()))
+}
+
+/// Parses a channel given either as a mention or as a raw ID.
+fn parse_channel_argument(argument: &str) -> CommandResult<ChannelId> {
+    serenity::utils::parse_channel_mention(argument)
+        .or_else(|| argument.parse().ok())
+        .ok_or_else(|| CommandError::MalformedArgument(argument.to_owned()))
+}
+
+/// Parses a message link like `https://discord.com/channels/<guild>/<channel>/<message>`.
+pub fn parse_message_link(argument: &str) -> CommandResult<(GuildId, ChannelId, MessageId)> {
+    let malformed = || CommandError::MalformedArgument(argument.to_owned());
+
+    let link = argument.trim_start_matches('<').trim_end_matches('>');
+    let path = ["https://discord.com/channels/", "https://ptb.discord.com/channels/", "https://canary.discord.com/channels/", "https://discordapp.com/channels/"]
+        .iter()
+        .find_map(|prefix| link.strip_prefix(prefix))
+        .ok_or_else(malformed)?;
+
+    match path.split('/').collect::<Vec<_>>()[..] {
+        [guild, channel, message] => Ok((
+            guild.parse().map_err(|_| malformed())?,
+            channel.parse().map_err(|_| malformed())?,
+            message.parse().map_err(|_| malformed())?,
+        )),
+        _ => Err(malformed()),
+    }
+}
+
+/// Sends the given lines to a channel, split across as many messages as needed to stay within
+/// Discord's length limit. Mentions are never pinged.
+pub async fn say_lines(ctx: &Context, channel: ChannelId, lines: &[String]) -> serenity::Result<()> {
+    for chunk in chunk_lines(lines) {
+        channel.send_message(&ctx.http, CreateMessage::new().content(chunk).allowed_mentions(CreateAllowedMentions::new())).await?;
+    }
+
+    Ok(())
+}
+
+/// Joins lines into as few messages as fit within Discord's length limit.
+pub(crate) fn chunk_lines(lines: &[String]) -> Vec<String> {
+    const MAX_LENGTH: usize = 2000;
+
+    let mut chunks = vec![String::new()];
+    for line in lines {
+        let chunk = chunks.last_mut().unwrap();
+        if !chunk.is_empty() && chunk.len() + line.len() + 1 > MAX_LENGTH {
+            chunks.push(String::new());
+        }
+
+        let chunk = chunks.last_mut().unwrap();
+        if !chunk.is_empty() {
+            chunk.push('\n');
+        }
+        chunk.push_str(line);
+    }
+
+    chunks.retain(|chunk| !chunk.is_empty());
+    chunks
+}
+
+/// Whether a request failed because what it refers to no longer exists.
+pub fn is_not_found(err: &serenity::Error) -> bool {
+    match err {
+        serenity::Error::Http(err) => err.status_code().is_some_and(|status| status.as_u16() == 404),
+        _ => false,
+    }
+}
+
+/// A guild channel or active thread from the cache, looked up without knowing its guild.
+#[allow(deprecated)]
+pub fn cached_channel(cache: &Cache, channel: ChannelId) -> Option<GuildChannel> {
+    if let Some(channel) = cache.channel(channel) {
+        return Some(channel.clone());
+    }
+    // threads, like forum posts, are only cached with their guild
+    cache.guilds().into_iter().find_map(|guild| {
+        cache.guild(guild)?.threads.iter().find(|thread| thread.id == channel).cloned()
+    })
+}
+
+/// A guild channel from the cache, or fetched when it isn't cached, as is the case for archived
+/// threads.
+pub async fn resolve_channel(ctx: &Context, channel: ChannelId) -> serenity::Result<GuildChannel> {
+    if let Some(channel) = cached_channel(&ctx.cache, channel) {
+        return Ok(channel);
+    }
+    match channel.to_channel(ctx).await? {
+        Channel::Guild(channel) => Ok(channel),
+        _ => Err(serenity::Error::Model(serenity::model::ModelError::InvalidChannelType)),
+    }
+}
+
+pub async fn message_permissions(ctx: &Context, message: &Message) -> Permissions {
+    match message.guild_id {
+        Some(guild_id) => member_permissions(ctx, guild_id, message.author.id).await,
+        None => Permissions::empty(),
+    }
+}
+
+pub async fn member_permissions(ctx: &Context, guild: GuildId, user: UserId) -> Permissions {
+    if let Ok(member) = members::member(ctx, guild, user).await {
+        if let Some(guild) = ctx.cache.guild(guild) {
+            return guild.member_permissions(&member);
+        }
+    }
+    Permissions::empty()
+}
+
+#[inline]
+fn require_permission(permissions: Permissions, require: Permissions) -> CommandResult<()> {
+    if permissions.contains(require) {
+        Ok(())
+    } else {
+        Err(CommandError::NoPermission(require))
+    }
+}
+
+/// The bot's owner, fetched on first use since it doesn't change while the bot runs.
+static OWNER: std::sync::OnceLock<Option<UserId>> = std::sync::OnceLock::new();
+
+/// Fails unless `user` owns the bot, for commands that reach across guilds or change the bot itself.
+async fn require_owner(ctx: &Context, user: &User) -> CommandResult<()> {
+    let owner = match OWNER.get() {
+        Some(owner) => *owner,
+        None => {
+            let owner = ctx.http.get_current_application_info().await?.owner.map(|owner| owner.id);
+            *OWNER.get_or_init(|| owner)
+        }
+    };
+    if owner == Some(user.id) {
+        Ok(())
+    } else {
+        Err(CommandError::NotAllowed)
+    }
+}
+
+pub type CommandResult<T> = std::result::Result<T, CommandError>;
+
+// boxed, since serenity's errors would make every command result this large
+impl From<serenity::Error> for CommandError {
+    fn from(err: serenity::Error) -> Self {
+        CommandError::Serenity(Box::new(err))
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum CommandError {
+    #[error("Discord error!")]
+    Serenity(Box<serenity::Error>),
+    #[error("Invalid command!")]
+    InvalidCommand,
+    #[error("You are not allowed to do this!")]
+    NotAllowed,
+    #[error("You are missing `{0}` permission!")]
+    NoPermission(Permissions),
+    #[error("Invalid message reference! Are you sure it's in this channel?")]
+    InvalidMessageReference,
+    #[error("That message is not a role selector!")]
+    UnknownSelector,
+    #[error("There is no selector with that name!")]
+    UnknownSelectorName,
+    #[error("Selector names can only have up to 32 lowercase letters, digits, `-` and `_`, and can't be just digits!")]
+    InvalidSelectorName,
+    #[error("Another selector already has that name!")]
+    SelectorNameTaken,
+    #[error("That message is already a role selector!")]
+    AlreadySelector,
+    #[error("Selectors can only be refreshed after at most 100 newer messages!")]
+    RefreshTooDeep,
+    #[error("That emoji isn't an option of that selector!")]
+    UnknownSelectorOption,
+    #[error("There is no deleted selector with that ID to restore!")]
+    UnknownArchivedSelector,
+    #[error("That selector is still registered! Remove it with `remove role selector` first.")]
+    SelectorStillRegistered,
+    #[error("No selector was removed in the last day!")]
+    NothingToUndo,
+    #[error("That would make a role require itself!")]
+    RoleDependencyCycle,
+    #[error("That role doesn't require that role!")]
+    UnknownRoleDependency,
+    #[error("That role doesn't exist!")]
+    UnknownRole,
+    #[error("That role is not below your highest role!")]
+    RoleAboveYou,
+    #[error("That role is not below my highest role!")]
+    RoleAboveMe,
+    #[error("Role icons must be an emoji, or a PNG or JPEG image of at most 256KB!")]
+    InvalidRoleIcon,
+    #[error("There is no webhook with that ID!")]
+    UnknownWebhook,
+    #[error("Webhooks must use an HTTPS URL of a public host!")]
+    UnsafeWebhookUrl,
+    #[error("That role doesn't take applications!")]
+    UnknownApplication,
+    #[error("I can't send you direct messages! Please allow DMs from server members.")]
+    DirectMessagesClosed,
+    #[error("That role isn't linked to that channel!")]
+    UnknownChannelLink,
+    #[error("That category needs between 1 and {0} channels!")]
+    UnsuitableCategory(usize),
+    #[error("Verification is off! Turn it on with `verification on <role>` first.")]
+    VerificationOff,
+    #[error("That user isn't banned from using selectors!")]
+    NotSelectorBanned,
+    #[error("No roles matched that pattern!")]
+    NoMatchingRoles,
+    #[error("That role is not being tracked!")]
+    UntrackedRole,
+    #[error("I don't know which channel that selector is in yet! Try editing the message first.")]
+    UnknownSelectorChannel,
+    #[error("I can only do that with selectors I posted myself!")]
+    NotOwnSelector,
+    #[error("That template has no placeholders like `{{count:@role}}`!")]
+    NoTemplatePlaceholders,
+    #[error("Please attach a guild setup file!")]
+    MissingAttachment,
+    #[error("That guild setup file is invalid!")]
+    MalformedSetup,
+    #[error("That config file is invalid: {0}")]
+    MalformedConfig(String),
+    #[error("That config file has {0} problems, listed above!")]
+    InvalidConfig(usize),
+    #[error("Config sync is not set up! Use `config sync <url>` first.")]
+    ConfigSyncOff,
+    #[error("This server can have at most {1} {0}!")]
+    QuotaExceeded(quotas::Quota, usize),
+    #[error("The history can't be searched right now!")]
+    HistoryUnavailable,
+    #[error("There is no case with that ID!")]
+    UnknownCase,
+    #[error("I'm read-only right now, so I can't do that!")]
+    ReadOnly,
+    #[error("Everyone can see that channel! Backups need a channel only staff can see.")]
+    PublicChannel,
+    #[error("Backups are off! Turn them on with `backup channel <channel>` first.")]
+    BackupsOff,
+    #[error("I can't see the members of this server, so I can't do that!")]
+    NoMembersIntent,
+    #[error("You're using that command too quickly! Try again in {0} seconds.")]
+    RateLimited(u64),
+    #[error("Replication isn't set up in `config.json`!")]
+    ReplicationOff,
+    #[error("The replica can't be reached right now!")]
+    ReplicaUnavailable,
+    #[error("Malformed argument: {0}")]
+    MalformedArgument(String),
+}
@@ -0,0 +1,213 @@
+use std::collections::HashSet;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use chrono::{DateTime, NaiveDate, Utc};
+use log::{LevelFilter, Log, Metadata, Record};
+use serde::{Deserialize, Serialize};
+use serenity::model::prelude::*;
+
+const FILE_PREFIX: &str = "mossy-stone-brick-monster-egg.";
+const FILE_SUFFIX: &str = ".log";
+
+/// Guilds the bot is in, so that log lines mentioning them can be told apart from ones about users
+/// or messages.
+static KNOWN_GUILDS: Mutex<Option<HashSet<GuildId>>> = Mutex::new(None);
+
+/// Logging always goes to stderr, filtered by `RUST_LOG`. Log files are only written if configured.
+#[derive(Serialize, Deserialize, Default, Clone, Eq, PartialEq)]
+pub struct LoggingConfig {
+    #[serde(default)]
+    pub file: Option<FileLogConfig>,
+}
+
+/// Writes JSON log lines to a new file each day, deleting files older than the retention.
+#[derive(Serialize, Deserialize, Clone, Eq, PartialEq)]
+pub struct FileLogConfig {
+    pub directory: PathBuf,
+    #[serde(default = "default_retention_days")]
+    pub retention_days: u32,
+    /// The most verbose level written to the files: `error`, `warn`, `info`, `debug` or `trace`.
+    #[serde(default = "default_level")]
+    pub level: String,
+    /// If set, lines about other guilds are left out of the files. Lines that aren't about any
+    /// guild are always written.
+    #[serde(default)]
+    pub guilds: Option<Vec<GuildId>>,
+}
+
+fn default_retention_days() -> u32 {
+    14
+}
+
+fn default_level() -> String {
+    "info".to_owned()
+}
+
+/// Sets up logging to stderr and, if configured, to rotating log files.
+pub fn init(config: &LoggingConfig) {
+    let stderr = env_logger::Builder::from_default_env().build();
+
+    let file = config.file.as_ref().and_then(|config| {
+        match LogFiles::open(config) {
+            Ok(files) => Some(files),
+            Err(err) => {
+                eprintln!("failed to open log directory {:?}: {:?}", config.directory, err);
+                None
+            }
+        }
+    });
+
+    let file_level = file.as_ref().map(|files| files.level).unwrap_or(LevelFilter::Off);
+    log::set_max_level(stderr.filter().max(file_level));
+    let logger = Logger { stderr, file };
+    log::set_boxed_logger(Box::new(logger)).expect("logger already initialized");
+}
+
+/// Remembers which guilds the bot is in, for filtering log files.
+pub fn set_known_guilds<I: IntoIterator<Item = GuildId>>(guilds: I) {
+    *KNOWN_GUILDS.lock().unwrap() = Some(guilds.into_iter().collect());
+}
+
+pub fn guild_create(guild: GuildId) {
+    KNOWN_GUILDS.lock().unwrap().get_or_insert_with(HashSet::new).insert(guild);
+}
+
+struct Logger {
+    stderr: env_logger::Logger,
+    file: Option<LogFiles>,
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.stderr.enabled(metadata) || self.file.as_ref().is_some_and(|files| metadata.level() <= files.level)
+    }
+
+    fn log(&self, record: &Record) {
+        self.stderr.log(record);
+
+        if let Some(files) = &self.file {
+            if record.level() <= files.level {
+                let message = record.args().to_string();
+                let known = KNOWN_GUILDS.lock().unwrap().clone().unwrap_or_default();
+                if let Some(guild) = files.filter.guild_of(&message, &known) {
+                    let level = record.level().to_string();
+                    let line = Line { at: Utc::now(), level: &level, target: record.target(), guild, message: &message };
+                    let _ = files.write(&line);
+                }
+            }
+        }
+    }
+
+    fn flush(&self) {
+        self.stderr.flush();
+    }
+}
+
+/// One line of a log file.
+#[derive(Serialize)]
+pub struct Line<'a> {
+    pub at: DateTime<Utc>,
+    pub level: &'a str,
+    pub target: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub guild: Option<GuildId>,
+    pub message: &'a str,
+}
+
+/// Which guilds' lines belong in the log files.
+#[derive(Clone, Debug, Default)]
+pub struct GuildFilter {
+    guilds: Option<HashSet<GuildId>>,
+}
+
+impl GuildFilter {
+    pub fn new(guilds: Option<&[GuildId]>) -> Self {
+        GuildFilter { guilds: guilds.map(|guilds| guilds.iter().copied().collect()) }
+    }
+
+    /// Returns `None` if a message mentioning these IDs should be left out, or otherwise the guild it
+    /// is about, if any. `known` are the guilds the bot is in.
+    pub fn guild_of(&self, message: &str, known: &HashSet<GuildId>) -> Option<Option<GuildId>> {
+        let mentioned: Vec<GuildId> = snowflakes(message).filter(|id| known.contains(id)).collect();
+        match &self.guilds {
+            None => Some(mentioned.first().copied()),
+            Some(allowed) => match mentioned.first() {
+                None => Some(None),
+                Some(_) => mentioned.into_iter().find(|id| allowed.contains(id)).map(Some),
+            },
+        }
+    }
+}
+
+/// Finds everything that looks like a Discord ID in a message.
+fn snowflakes(message: &str) -> impl Iterator<Item = GuildId> + '_ {
+    message.split(|c: char| !c.is_ascii_digit())
+        .filter(|digits| (17..=20).contains(&digits.len()))
+        .filter_map(|digits| digits.parse().ok())
+}
+
+/// A log file per day in a directory, of which the newest few days are kept.
+pub struct LogFiles {
+    directory: PathBuf,
+    retention_days: u32,
+    level: LevelFilter,
+    filter: GuildFilter,
+    current: Mutex<Option<(NaiveDate, File)>>,
+}
+
+impl LogFiles {
+    pub fn open(config: &FileLogConfig) -> io::Result<Self> {
+        fs::create_dir_all(&config.directory)?;
+        let level = config.level.parse().unwrap_or(LevelFilter::Info);
+        Ok(LogFiles {
+            directory: config.directory.clone(),
+            retention_days: config.retention_days,
+            level,
+            filter: GuildFilter::new(config.guilds.as_deref()),
+            current: Mutex::new(None),
+        })
+    }
+
+    pub fn write(&self, line: &Line) -> io::Result<()> {
+        let date = line.at.naive_utc().date();
+        let mut current = self.current.lock().unwrap();
+
+        if current.as_ref().map(|(current, _)| *current) != Some(date) {
+            let file = OpenOptions::new().create(true).append(true).open(self.path_for(date))?;
+            *current = Some((date, file));
+            self.prune(date)?;
+        }
+
+        let (_, file) = current.as_mut().unwrap();
+        let mut json = serde_json::to_vec(line).map_err(io::Error::other)?;
+        json.push(b'\n');
+        file.write_all(&json)
+    }
+
+    fn path_for(&self, date: NaiveDate) -> PathBuf {
+        self.directory.join(format!("{}{}{}", FILE_PREFIX, date.format("%Y-%m-%d"), FILE_SUFFIX))
+    }
+
+    /// Deletes log files from more than `retention_days` before `today`.
+    fn prune(&self, today: NaiveDate) -> io::Result<()> {
+        let oldest = today - chrono::Duration::days(self.retention_days as i64);
+        for entry in fs::read_dir(&self.directory)? {
+            let path = entry?.path();
+            if let Some(date) = file_date(&path) {
+                if date < oldest {
+                    fs::remove_file(&path)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn file_date(path: &Path) -> Option<NaiveDate> {
+    let name = path.file_name()?.to_str()?;
+    let date = name.strip_prefix(FILE_PREFIX)?.strip_suffix(FILE_SUFFIX)?;
+    NaiveDate::parse_from_str(date, "%Y-%m-%d").ok()
+}
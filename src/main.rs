@@ -1,177 +1,113 @@
-// TODO: use slash commands
-use std::str::FromStr;
+use std::path::Path;
+#[cfg(feature = "replication")]
+use std::sync::Arc;
 
-use async_trait::async_trait;
-use log::{error, info};
-use serde::{Deserialize, Serialize};
-use serenity::client::bridge::gateway::GatewayIntents;
-use serenity::model::prelude::*;
+use log::warn;
+use serenity::gateway::GatewayError;
+use serenity::http::Http;
 use serenity::prelude::*;
 
-pub use persistent::*;
-
-mod persistent;
-mod reaction_roles;
-mod persistent_roles;
-
-#[derive(Serialize, Deserialize, Default, Clone, Eq, PartialEq)]
-pub struct Config {
-    pub discord_token: String,
-}
+use mossy_stone_brick_monster_egg::{capabilities, Config, config_mirror, Handler, history, logging, Persistent, validate};
+#[cfg(feature = "replication")]
+use mossy_stone_brick_monster_egg::replication;
 
 #[tokio::main]
 async fn main() {
-    env_logger::init();
-
-    let config: Persistent<Config> = Persistent::open("config.json").await;
-
-    let mut client = Client::builder(&config.discord_token)
-        .event_handler(Handler)
-        .intents(
-            GatewayIntents::GUILD_MESSAGE_REACTIONS
-                | GatewayIntents::GUILD_MESSAGES
-                | GatewayIntents::GUILDS
-                | GatewayIntents::GUILD_MEMBERS
-        )
-        .await
-        .expect("failed to create client");
-
-    {
-        let mut data = client.data.write().await;
-        data.insert::<reaction_roles::StateKey>(Persistent::open("reaction_roles.json").await);
-        data.insert::<persistent_roles::StateKey>(Persistent::open("persistent_roles.json").await);
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.first().map(String::as_str) == Some("validate") {
+        if let Err(err) = validate::run_cli(Path::new(".")) {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+        return;
     }
 
-    client.start().await.expect("failed to run client");
-}
-
-struct Handler;
-
-#[async_trait]
-impl EventHandler for Handler {
-    async fn guild_member_addition(&self, ctx: Context, _guild_id: GuildId, mut member: Member) {
-        persistent_roles::guild_member_addition(&ctx, &mut member).await;
+    #[cfg(feature = "replication")]
+    if args.first().map(String::as_str) == Some("migrate") {
+        if let Err(err) = replication::migrate::run_cli(&args[1..]).await {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+        return;
     }
 
-    async fn guild_member_update(&self, ctx: Context, _old: Option<Member>, member: Member) {
-        persistent_roles::guild_member_update(&ctx, &member).await;
+    // a fresh disk gets the stores back from the replica before they're checked
+    #[cfg(feature = "replication")]
+    let replica = replication::Replica::from_config_file(Path::new("config.json"));
+    #[cfg(feature = "replication")]
+    if let Some(replica) = &replica {
+        replica.restore(mossy_stone_brick_monster_egg::StoreConfig::from_config_file(Path::new("config.json")).directory()).await;
     }
 
-    async fn message(&self, ctx: Context, message: Message) {
-        if let Ok(true) = message.mentions_me(&ctx).await {
-            let tokens: Vec<&str> = message.content.split_ascii_whitespace().collect();
-            handle_command(&tokens[1..], &ctx, &message).await;
-        }
+    if !validate::startup_check(Path::new(".")) {
+        eprintln!("refusing to start, run `validate` for details");
+        std::process::exit(1);
     }
 
-    async fn message_delete(&self, ctx: Context, _channel_id: ChannelId, deleted_message_id: MessageId, _guild_id: Option<GuildId>) {
-        reaction_roles::delete_message(ctx, deleted_message_id).await;
-    }
+    let config: Persistent<Config> = Persistent::open("config.json").await;
+    logging::init(&config.read().await.logging);
+    mossy_stone_brick_monster_egg::set_pretty_state(config.read().await.pretty_state);
 
-    async fn message_update(&self, ctx: Context, _old_if_available: Option<Message>, _new: Option<Message>, event: MessageUpdateEvent) {
-        reaction_roles::update_message(ctx, event.channel_id, event.id, event.content).await;
-    }
+    let discord_token = config.read().await.discord_token.clone();
 
-    async fn reaction_add(&self, ctx: Context, reaction: Reaction) {
-        if let Err(err) = reaction_roles::add_reaction(ctx, reaction).await {
-            error!("failed to add reaction role: {:?}", err);
+    if args.first().map(String::as_str) == Some("search-history") {
+        let options = config.read().await.stores.open_options();
+        let history = history::History::open_read_only(options.path(history::FILE)).expect("failed to open history");
+        if let Err(err) = history::run_cli(&history, &args[1..]) {
+            eprintln!("{}", err);
+            std::process::exit(1);
         }
+        return;
     }
-
-    async fn reaction_remove(&self, ctx: Context, reaction: Reaction) {
-        if let Err(err) = reaction_roles::remove_reaction(&ctx, reaction).await {
-            error!("failed to remove reaction role: {:?}", err);
+    if !args.is_empty() {
+        // only applying an import changes the stores, so anything else leaves them as they are
+        let options = config.read().await.stores.open_options().read_only(!args.iter().any(|arg| arg == "--apply"));
+        let selectors = options.open("reaction_roles.json").await.expect("failed to open reaction_roles.json");
+        let persistent = options.open("persistent_roles.json").await.expect("failed to open persistent_roles.json");
+        if let Err(err) = config_mirror::run_cli(&discord_token, &args, &selectors, &persistent).await {
+            eprintln!("{}", err);
+            std::process::exit(1);
         }
+        return;
     }
 
-    async fn ready(&self, _ctx: Context, _ready: serenity::model::gateway::Ready) {
-        info!("bot is ready!")
-    }
-}
-
-async fn handle_command(tokens: &[&str], ctx: &Context, message: &Message) {
-    let result = try_handle_command(tokens, ctx, message).await;
-
-    let reaction = if result.is_ok() { "✅" } else { "❌" };
-    let _ = message.react(&ctx, ReactionType::Unicode(reaction.to_owned())).await;
-
-    if let Err(err) = result {
-        let _ = message.reply(&ctx, err).await;
+    #[cfg(feature = "replication")]
+    let replica = replica.map(Arc::new);
+    #[cfg(feature = "replication")]
+    if let Some(replica) = &replica {
+        replication::start(replica.clone());
     }
-}
-
-async fn try_handle_command(tokens: &[&str], ctx: &Context, message: &Message) -> CommandResult<()> {
-    let permissions = message_permissions(&ctx, &message).await;
 
-    match tokens {
-        ["add", "role", "selector", reference] => {
-            require_permission(permissions, Permissions::MANAGE_ROLES)?;
-            let reference = parse_argument(reference)?;
-            reaction_roles::add_selector(&ctx, &message, MessageId(reference)).await
-        }
-        ["add", "role", "persist", refs @ ..] => {
-            require_permission(permissions, Permissions::MANAGE_ROLES)?;
-            for reference in refs {
-                let reference = parse_argument(reference)?;
-                persistent_roles::add_role(&ctx, &message, RoleId(reference)).await?;
-            }
-            Ok(())
-        }
-        ["remove", "role", "persist", refs @ ..] => {
-            require_permission(permissions, Permissions::MANAGE_ROLES)?;
-            for reference in refs {
-                let reference = parse_argument(reference)?;
-                persistent_roles::remove_role(&ctx, &message, RoleId(reference)).await?;
+    let members_intent = capabilities::detect(&Http::new(&discord_token), config.read().await.members_intent).await;
+    capabilities::set_members_intent(members_intent);
+
+    loop {
+        let mut client = Client::builder(&discord_token, mossy_stone_brick_monster_egg::intents())
+            .event_handler(Handler)
+            .await
+            .expect("failed to create client");
+
+        {
+            let mut data = client.data.write().await;
+            let config = config.read().await;
+            mossy_stone_brick_monster_egg::install(&mut data, client.http.clone(), &config).await
+                .expect("failed to open history");
+            #[cfg(feature = "replication")]
+            if let Some(replica) = &replica {
+                data.insert::<replication::ReplicaKey>(replica.clone());
             }
-            Ok(())
         }
-        _ => Err(CommandError::InvalidCommand),
-    }
-}
 
-fn parse_argument<T: FromStr>(argument: &str) -> CommandResult<T> {
-    argument.parse::<T>().map_err(|_| CommandError::MalformedArgument(argument.to_owned()))
-}
-
-pub async fn message_permissions(ctx: &Context, message: &Message) -> Permissions {
-    match message.guild_id {
-        Some(guild_id) => member_permissions(ctx, guild_id, message.author.id).await,
-        None => Permissions::empty(),
-    }
-}
-
-pub async fn member_permissions(ctx: &Context, guild: GuildId, user: UserId) -> Permissions {
-    if let Ok(member) = guild.member(ctx, user).await {
-        if let Ok(permissions) = member.permissions(&ctx).await {
-            return permissions;
+        match client.start().await {
+            // the application's flags said otherwise, so go by what Discord actually allows
+            Err(serenity::Error::Gateway(GatewayError::DisallowedGatewayIntents)) if capabilities::has_members_intent() => {
+                warn!("the members intent isn't granted, connecting again without it");
+                capabilities::set_members_intent(false);
+            }
+            result => {
+                result.expect("failed to run client");
+                return;
+            }
         }
     }
-    Permissions::empty()
-}
-
-#[inline]
-fn require_permission(permissions: Permissions, require: Permissions) -> CommandResult<()> {
-    if permissions.contains(require) {
-        Ok(())
-    } else {
-        Err(CommandError::NoPermission(require))
-    }
-}
-
-pub type CommandResult<T> = std::result::Result<T, CommandError>;
-
-#[derive(thiserror::Error, Debug)]
-pub enum CommandError {
-    #[error("Discord error!")]
-    Serenity(#[from] serenity::Error),
-    #[error("Invalid command!")]
-    InvalidCommand,
-    #[error("You are not allowed to do this!")]
-    NotAllowed,
-    #[error("You are missing `{0}` permission!")]
-    NoPermission(Permissions),
-    #[error("Invalid message reference! Are you sure it's in this channel?")]
-    InvalidMessageReference,
-    #[error("Malformed argument: {0}")]
-    MalformedArgument(String),
 }
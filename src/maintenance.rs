@@ -0,0 +1,122 @@
+use std::collections::{BTreeSet, HashSet};
+use std::time::Duration;
+
+use log::info;
+use serenity::model::prelude::*;
+use serenity::prelude::*;
+
+use crate::{appeals, applications, approvals, audit, autopin, backups, cases, channel_links, command_usage, config_sync, dormancy, eligibility, emoji_stats, error_cleanup, event_signups, history, latency, mentions, persistent_roles, ping_tracker, quotas, reaction_roles, read_only, retention, role_caps, role_dependencies, role_snapshots, scheduler, screening, selector_bans, slowmode, store, timezone, verification, webhooks};
+
+/// How often maintenance runs.
+pub const PERIOD: Duration = Duration::from_secs(6 * 60 * 60);
+
+/// How long the persisted roles of users who left a guild are kept around for them to rejoin.
+const DEPARTED_RETENTION_SECS: i64 = 180 * 24 * 60 * 60;
+
+/// Prunes state that no longer refers to anything and compacts the stores on disk.
+pub async fn run(ctx: Context) {
+    // pruning relies on the cache knowing about every guild and channel we're in
+    if !ctx.cache.unavailable_guilds().len() == 0 {
+        return;
+    }
+
+    let current: HashSet<GuildId> = ctx.cache.guilds().into_iter().collect();
+    if current.is_empty() {
+        return;
+    }
+
+    // guilds we were removed from keep their data for a while in case we get added back
+    let guilds = retention::retained_guilds(&ctx, &current).await;
+
+    let selectors = reaction_roles::prune_selectors(&ctx).await;
+    let degraded = reaction_roles::check_access(&ctx).await;
+    let archived = reaction_roles::archive::prune(&ctx, scheduler::now()).await;
+
+    let mut left_guilds: BTreeSet<GuildId> = BTreeSet::new();
+    left_guilds.extend(persistent_roles::retain_guilds(&ctx, &guilds).await);
+    left_guilds.extend(autopin::retain_guilds(&ctx, &guilds).await);
+    left_guilds.extend(ping_tracker::retain_guilds(&ctx, &guilds).await);
+    left_guilds.extend(audit::retain_guilds(&ctx, &guilds).await);
+    left_guilds.extend(slowmode::retain_guilds(&ctx, &guilds).await);
+    left_guilds.extend(emoji_stats::retain_guilds(&ctx, &guilds).await);
+    left_guilds.extend(webhooks::retain_guilds(&ctx, &guilds).await);
+    left_guilds.extend(approvals::retain_guilds(&ctx, &guilds).await);
+    left_guilds.extend(applications::retain_guilds(&ctx, &guilds).await);
+    left_guilds.extend(role_caps::retain_guilds(&ctx, &guilds).await);
+    left_guilds.extend(role_dependencies::retain_guilds(&ctx, &guilds).await);
+    left_guilds.extend(config_sync::retain_guilds(&ctx, &guilds).await);
+    left_guilds.extend(timezone::retain_guilds(&ctx, &guilds).await);
+    left_guilds.extend(quotas::retain_guilds(&ctx, &guilds).await);
+    left_guilds.extend(reaction_roles::archive::retain_guilds(&ctx, &guilds).await);
+    left_guilds.extend(reaction_roles::names::retain_guilds(&ctx, &guilds).await);
+    left_guilds.extend(channel_links::retain_guilds(&ctx, &guilds).await);
+    left_guilds.extend(screening::retain_guilds(&ctx, &guilds).await);
+    left_guilds.extend(eligibility::retain_guilds(&ctx, &guilds).await);
+    left_guilds.extend(reaction_roles::expiry::retain_guilds(&ctx, &guilds).await);
+    left_guilds.extend(verification::retain_guilds(&ctx, &guilds).await);
+    left_guilds.extend(selector_bans::retain_guilds(&ctx, &guilds).await);
+    left_guilds.extend(cases::retain_guilds(&ctx, &guilds).await);
+    left_guilds.extend(appeals::retain_guilds(&ctx, &guilds).await);
+    left_guilds.extend(read_only::retain_guilds(&ctx, &guilds).await);
+    left_guilds.extend(command_usage::retain_guilds(&ctx, &guilds).await);
+    left_guilds.extend(error_cleanup::retain_guilds(&ctx, &guilds).await);
+    left_guilds.extend(mentions::retain_guilds(&ctx, &guilds).await);
+    left_guilds.extend(latency::retain_guilds(&ctx, &guilds).await);
+    left_guilds.extend(role_snapshots::retain_guilds(&ctx, &guilds).await);
+    left_guilds.extend(reaction_roles::sweep::retain_guilds(&ctx, &guilds).await);
+    left_guilds.extend(reaction_roles::onboarding::retain_guilds(&ctx, &guilds).await);
+    left_guilds.extend(reaction_roles::progress::retain_guilds(&ctx, &guilds).await);
+    left_guilds.extend(backups::retain_guilds(&ctx, &guilds).await);
+    left_guilds.extend(history::retain_guilds(&ctx, &guilds).await);
+    left_guilds.extend(dormancy::retain_guilds(&ctx, &guilds).await);
+
+    // uses still counted in memory go with the rest of a guild's state when it's archived
+    emoji_stats::flush(ctx.clone()).await;
+    let dormant = dormancy::archive_idle(&ctx, &current).await;
+    let departed_users = persistent_roles::prune_departed(&ctx, scheduler::now() - DEPARTED_RETENTION_SECS).await;
+    let snapshots = role_snapshots::prune(&ctx, scheduler::now() - role_snapshots::RETENTION_SECS).await;
+
+    store::<reaction_roles::StateKey>(&ctx).await.compact().await;
+    store::<reaction_roles::archive::StateKey>(&ctx).await.compact().await;
+    store::<reaction_roles::names::StateKey>(&ctx).await.compact().await;
+    store::<persistent_roles::StateKey>(&ctx).await.compact().await;
+    store::<autopin::StateKey>(&ctx).await.compact().await;
+    store::<event_signups::StateKey>(&ctx).await.compact().await;
+    store::<ping_tracker::StateKey>(&ctx).await.compact().await;
+    store::<audit::StateKey>(&ctx).await.compact().await;
+    store::<slowmode::StateKey>(&ctx).await.compact().await;
+    store::<emoji_stats::StateKey>(&ctx).await.compact().await;
+    store::<webhooks::StateKey>(&ctx).await.compact().await;
+    store::<approvals::StateKey>(&ctx).await.compact().await;
+    store::<applications::StateKey>(&ctx).await.compact().await;
+    store::<role_caps::StateKey>(&ctx).await.compact().await;
+    store::<role_dependencies::StateKey>(&ctx).await.compact().await;
+    store::<config_sync::StateKey>(&ctx).await.compact().await;
+    store::<timezone::StateKey>(&ctx).await.compact().await;
+    store::<retention::StateKey>(&ctx).await.compact().await;
+    store::<quotas::StateKey>(&ctx).await.compact().await;
+    store::<channel_links::StateKey>(&ctx).await.compact().await;
+    store::<screening::StateKey>(&ctx).await.compact().await;
+    store::<eligibility::StateKey>(&ctx).await.compact().await;
+    store::<reaction_roles::expiry::StateKey>(&ctx).await.compact().await;
+    store::<verification::StateKey>(&ctx).await.compact().await;
+    store::<selector_bans::StateKey>(&ctx).await.compact().await;
+    store::<cases::StateKey>(&ctx).await.compact().await;
+    store::<appeals::StateKey>(&ctx).await.compact().await;
+    store::<read_only::StateKey>(&ctx).await.compact().await;
+    store::<command_usage::StateKey>(&ctx).await.compact().await;
+    store::<error_cleanup::StateKey>(&ctx).await.compact().await;
+    store::<mentions::StateKey>(&ctx).await.compact().await;
+    store::<latency::StateKey>(&ctx).await.compact().await;
+    store::<role_snapshots::StateKey>(&ctx).await.compact().await;
+    store::<reaction_roles::sweep::StateKey>(&ctx).await.compact().await;
+    store::<reaction_roles::onboarding::StateKey>(&ctx).await.compact().await;
+    store::<reaction_roles::progress::StateKey>(&ctx).await.compact().await;
+    store::<backups::StateKey>(&ctx).await.compact().await;
+    store::<dormancy::StateKey>(&ctx).await.compact().await;
+
+    info!(
+        "maintenance pruned {} dead selectors, {} expired archived selectors, {} left guilds, {} departed users and {} role snapshots, and archived {} idle guilds ({} guilds retained after removal, {} selectors degraded)",
+        selectors, archived, left_guilds.len(), departed_users, snapshots, dormant, guilds.len() - current.len(), degraded,
+    );
+}
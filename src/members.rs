@@ -0,0 +1,142 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use serde::Serialize;
+use serenity::gateway::ChunkGuildFilter;
+use serenity::model::prelude::*;
+use serenity::prelude::*;
+use tokio::sync::oneshot;
+
+use crate::scheduler;
+
+/// How long to wait for the gateway to answer a member chunk request before asking the REST API.
+const CHUNK_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// How long a user is remembered as not being a member, so repeated events don't each hit the API.
+const NOT_FOUND_TTL_SECS: i64 = 60;
+
+static CACHE_HITS: AtomicU64 = AtomicU64::new(0);
+static CHUNK_HITS: AtomicU64 = AtomicU64::new(0);
+static REST_HITS: AtomicU64 = AtomicU64::new(0);
+static NOT_FOUND: AtomicU64 = AtomicU64::new(0);
+static NONCES: AtomicU64 = AtomicU64::new(0);
+
+/// Chunk requests waiting for their answer, by nonce.
+static WAITING: std::sync::Mutex<Option<HashMap<String, oneshot::Sender<Option<Member>>>>> = std::sync::Mutex::new(None);
+
+/// Users recently found not to be members, with when that stops being trusted.
+static NOT_MEMBERS: std::sync::Mutex<Option<HashMap<(GuildId, UserId), i64>>> = std::sync::Mutex::new(None);
+
+/// How member lookups since startup were answered.
+#[derive(Serialize, Clone, Copy, Debug, Default)]
+pub struct LookupStats {
+    pub cache: u64,
+    pub chunk: u64,
+    pub rest: u64,
+    pub not_found: u64,
+}
+
+pub fn stats() -> LookupStats {
+    LookupStats {
+        cache: CACHE_HITS.load(Ordering::Relaxed),
+        chunk: CHUNK_HITS.load(Ordering::Relaxed),
+        rest: REST_HITS.load(Ordering::Relaxed),
+        not_found: NOT_FOUND.load(Ordering::Relaxed),
+    }
+}
+
+/// Looks up a member from the cache, then by asking the gateway for them, and only then through the
+/// REST API. Users who aren't members are remembered for a little while.
+pub async fn member(ctx: &Context, guild: GuildId, user: UserId) -> serenity::Result<Member> {
+    if let Some(member) = ctx.cache.guild(guild).and_then(|guild| guild.members.get(&user).cloned()) {
+        CACHE_HITS.fetch_add(1, Ordering::Relaxed);
+        return Ok(member);
+    }
+
+    if is_known_missing(guild, user) {
+        NOT_FOUND.fetch_add(1, Ordering::Relaxed);
+        return Err(not_found());
+    }
+
+    match request_chunk(ctx, guild, user).await {
+        Some(Some(member)) => {
+            CHUNK_HITS.fetch_add(1, Ordering::Relaxed);
+            return Ok(member);
+        }
+        Some(None) => {
+            NOT_FOUND.fetch_add(1, Ordering::Relaxed);
+            remember_missing(guild, user);
+            return Err(not_found());
+        }
+        None => {}
+    }
+
+    match ctx.http.get_member(guild, user).await {
+        Ok(member) => {
+            REST_HITS.fetch_add(1, Ordering::Relaxed);
+            Ok(member)
+        }
+        Err(err) => {
+            if crate::is_not_found(&err) {
+                NOT_FOUND.fetch_add(1, Ordering::Relaxed);
+                remember_missing(guild, user);
+            }
+            Err(err)
+        }
+    }
+}
+
+fn not_found() -> serenity::Error {
+    serenity::Error::Model(ModelError::MemberNotFound)
+}
+
+/// Asks the gateway for a single member, returning `None` if it doesn't answer in time and
+/// `Some(None)` if the user isn't a member.
+async fn request_chunk(ctx: &Context, guild: GuildId, user: UserId) -> Option<Option<Member>> {
+    let nonce = format!("member-{}", NONCES.fetch_add(1, Ordering::Relaxed));
+    let (sender, receiver) = oneshot::channel();
+    WAITING.lock().unwrap().get_or_insert_with(HashMap::new).insert(nonce.clone(), sender);
+
+    ctx.shard.chunk_guild(guild, None, false, ChunkGuildFilter::UserIds(vec![user]), Some(nonce.clone()));
+
+    let result = tokio::time::timeout(CHUNK_TIMEOUT, receiver).await;
+    if let Some(waiting) = WAITING.lock().unwrap().as_mut() {
+        waiting.remove(&nonce);
+    }
+    result.ok()?.ok()
+}
+
+/// Hands members from a chunk to whoever asked for them.
+pub fn guild_members_chunk(chunk: &GuildMembersChunkEvent) {
+    let nonce = match &chunk.nonce {
+        Some(nonce) => nonce,
+        None => return,
+    };
+
+    let sender = WAITING.lock().unwrap().as_mut().and_then(|waiting| waiting.remove(nonce));
+    if let Some(sender) = sender {
+        let _ = sender.send(chunk.members.values().next().cloned());
+    }
+}
+
+fn is_known_missing(guild: GuildId, user: UserId) -> bool {
+    let not_members = NOT_MEMBERS.lock().unwrap();
+    let expires_at = not_members.as_ref().and_then(|not_members| not_members.get(&(guild, user)).copied());
+    expires_at.is_some_and(|expires_at| expires_at > scheduler::now())
+}
+
+fn remember_missing(guild: GuildId, user: UserId) {
+    let now = scheduler::now();
+    let mut not_members = NOT_MEMBERS.lock().unwrap();
+    let not_members = not_members.get_or_insert_with(HashMap::new);
+    not_members.retain(|_, expires_at| *expires_at > now);
+    not_members.insert((guild, user), now + NOT_FOUND_TTL_SECS);
+}
+
+/// Forgets that a user wasn't a member, for when they join.
+pub fn guild_member_addition(guild: GuildId, user: UserId) {
+    if let Some(not_members) = NOT_MEMBERS.lock().unwrap().as_mut() {
+        not_members.remove(&(guild, user));
+    }
+}
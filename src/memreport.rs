@@ -0,0 +1,202 @@
+use std::collections::HashMap;
+
+use serenity::model::prelude::*;
+use serenity::prelude::*;
+
+use crate::{appeals, applications, approvals, audit, autopin, backups, cases, channel_links, command_usage, CommandResult, config_sync, dormancy, eligibility, emoji_stats, error_cleanup, event_signups, latency, mentions, Persistable, Persistent, persistent_roles, ping_tracker, quotas, reaction_roles, read_only, require_owner, retention, role_caps, role_dependencies, role_snapshots, say_lines, screening, selector_bans, slowmode, store, timezone, verification, webhooks};
+
+/// How many guilds are listed in the report.
+const TOP_GUILDS: usize = 10;
+
+/// What a piece of stored state belongs to, so that it can be counted against a guild.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Owner {
+    Guild(GuildId),
+    /// State that only knows its channel, like selectors. The guild is looked up in the cache.
+    Channel(ChannelId),
+    /// State that can't be traced back to a guild at all.
+    Unknown,
+}
+
+/// A role or channel that stored state points at, which should still exist in its guild.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Reference {
+    Role(RoleId),
+    Channel(ChannelId),
+}
+
+/// Lets a store report how much it holds, so that guilds bloating the state can be found.
+pub trait Introspect {
+    /// The number of entries held for each owner. An owner may appear more than once.
+    fn entries(&self) -> Vec<(Owner, usize)>;
+
+    /// The roles and channels the store refers to, so that ones deleted from Discord can be found.
+    fn references(&self) -> Vec<(Owner, Reference)> {
+        Vec::new()
+    }
+}
+
+/// How much one store holds.
+#[derive(Debug, Default)]
+pub struct StoreUsage {
+    pub name: &'static str,
+    pub bytes: usize,
+    pub guilds: HashMap<GuildId, usize>,
+    pub unattributed: usize,
+}
+
+impl StoreUsage {
+    pub fn entries(&self) -> usize {
+        self.guilds.values().sum::<usize>() + self.unattributed
+    }
+}
+
+/// Measures a store, attributing entries owned by channels through `channel_guilds`.
+pub fn measure<T: Persistable + Introspect>(name: &'static str, state: &T, channel_guilds: &HashMap<ChannelId, GuildId>) -> StoreUsage {
+    let bytes = serde_json::to_vec(state).map(|bytes| bytes.len()).unwrap_or(0);
+    let mut usage = StoreUsage { name, bytes, ..StoreUsage::default() };
+
+    for (owner, count) in state.entries() {
+        let guild = match owner {
+            Owner::Guild(guild) => Some(guild),
+            Owner::Channel(channel) => channel_guilds.get(&channel).copied(),
+            Owner::Unknown => None,
+        };
+        match guild {
+            Some(guild) => *usage.guilds.entry(guild).or_default() += count,
+            None => usage.unattributed += count,
+        }
+    }
+
+    usage
+}
+
+async fn usage<K, T>(ctx: &Context, name: &'static str, channel_guilds: &HashMap<ChannelId, GuildId>) -> StoreUsage
+    where K: TypeMapKey<Value = Persistent<T>>,
+          T: Persistable + Introspect
+{
+    let state = store::<K>(ctx).await;
+    let state = state.read().await;
+    measure(name, &*state, channel_guilds)
+}
+
+/// Maps every cached channel to its guild, for attributing state that only knows its channel.
+pub(crate) async fn channel_guilds(ctx: &Context) -> HashMap<ChannelId, GuildId> {
+    let mut channel_guilds = HashMap::new();
+    for guild in ctx.cache.guilds() {
+        for channel in ctx.cache.guild(guild).map(|guild| guild.channels.keys().copied().collect::<Vec<_>>()).unwrap_or_default() {
+            channel_guilds.insert(channel, guild);
+        }
+    }
+    channel_guilds
+}
+
+async fn all_usage(ctx: &Context) -> Vec<StoreUsage> {
+    let channel_guilds = channel_guilds(ctx).await;
+    let channels = &channel_guilds;
+
+    vec![
+        usage::<reaction_roles::StateKey, _>(ctx, "reaction_roles", channels).await,
+        usage::<reaction_roles::archive::StateKey, _>(ctx, "selector_archive", channels).await,
+        usage::<reaction_roles::names::StateKey, _>(ctx, "selector_names", channels).await,
+        usage::<persistent_roles::StateKey, _>(ctx, "persistent_roles", channels).await,
+        usage::<autopin::StateKey, _>(ctx, "autopin", channels).await,
+        usage::<event_signups::StateKey, _>(ctx, "event_signups", channels).await,
+        usage::<ping_tracker::StateKey, _>(ctx, "ping_tracker", channels).await,
+        usage::<audit::StateKey, _>(ctx, "audit", channels).await,
+        usage::<slowmode::StateKey, _>(ctx, "slowmode", channels).await,
+        usage::<emoji_stats::StateKey, _>(ctx, "emoji_stats", channels).await,
+        usage::<webhooks::StateKey, _>(ctx, "webhooks", channels).await,
+        usage::<approvals::StateKey, _>(ctx, "approvals", channels).await,
+        usage::<applications::StateKey, _>(ctx, "applications", channels).await,
+        usage::<role_caps::StateKey, _>(ctx, "role_caps", channels).await,
+        usage::<role_dependencies::StateKey, _>(ctx, "role_dependencies", channels).await,
+        usage::<config_sync::StateKey, _>(ctx, "config_sync", channels).await,
+        usage::<timezone::StateKey, _>(ctx, "timezone", channels).await,
+        usage::<retention::StateKey, _>(ctx, "retention", channels).await,
+        usage::<quotas::StateKey, _>(ctx, "quotas", channels).await,
+        usage::<channel_links::StateKey, _>(ctx, "channel_links", channels).await,
+        usage::<screening::StateKey, _>(ctx, "screening", channels).await,
+        usage::<eligibility::StateKey, _>(ctx, "eligibility", channels).await,
+        usage::<reaction_roles::expiry::StateKey, _>(ctx, "selector_expiry", channels).await,
+        usage::<verification::StateKey, _>(ctx, "verification", channels).await,
+        usage::<selector_bans::StateKey, _>(ctx, "selector_bans", channels).await,
+        usage::<cases::StateKey, _>(ctx, "cases", channels).await,
+        usage::<appeals::StateKey, _>(ctx, "appeals", channels).await,
+        usage::<read_only::StateKey, _>(ctx, "read_only", channels).await,
+        usage::<command_usage::StateKey, _>(ctx, "command_usage", channels).await,
+        usage::<error_cleanup::StateKey, _>(ctx, "error_cleanup", channels).await,
+        usage::<mentions::StateKey, _>(ctx, "mentions", channels).await,
+        usage::<latency::StateKey, _>(ctx, "latency", channels).await,
+        usage::<role_snapshots::StateKey, _>(ctx, "role_snapshots", channels).await,
+        usage::<reaction_roles::sweep::StateKey, _>(ctx, "selector_sweeps", channels).await,
+        usage::<reaction_roles::onboarding::StateKey, _>(ctx, "onboarding", channels).await,
+        usage::<reaction_roles::progress::StateKey, _>(ctx, "progress_roles", channels).await,
+        usage::<backups::StateKey, _>(ctx, "backups", channels).await,
+        usage::<dormancy::StateKey, _>(ctx, "activity", channels).await,
+    ]
+}
+
+fn describe_bytes(bytes: usize) -> String {
+    if bytes < 1024 {
+        format!("{} B", bytes)
+    } else if bytes < 1024 * 1024 {
+        format!("{:.1} KiB", bytes as f64 / 1024.0)
+    } else {
+        format!("{:.1} MiB", bytes as f64 / (1024.0 * 1024.0))
+    }
+}
+
+/// Reports how much each store holds and which guilds hold the most.
+pub async fn report(ctx: &Context, command: &Message) -> CommandResult<()> {
+    require_owner(ctx, &command.author).await?;
+
+    let mut stores = all_usage(ctx).await;
+    stores.sort_by_key(|store| std::cmp::Reverse(store.bytes));
+
+    let mut lines = vec![
+        "**Memory report**".to_owned(),
+        format!(
+            "Cache: {} guilds, {} channels, {} users, {} unknown members",
+            ctx.cache.guild_count(),
+            ctx.cache.guild_channel_count(),
+            ctx.cache.user_count(),
+            ctx.cache.unknown_members(),
+        ),
+        String::new(),
+        "**Stores**".to_owned(),
+    ];
+
+    for store in &stores {
+        let unattributed = match store.unattributed {
+            0 => String::new(),
+            unattributed => format!(" ({} not tied to a guild)", unattributed),
+        };
+        lines.push(format!("`{}`: {} entries in {} guilds, {}{}", store.name, store.entries(), store.guilds.len(), describe_bytes(store.bytes), unattributed));
+    }
+
+    let mut guilds: HashMap<GuildId, (usize, &'static str, usize)> = HashMap::new();
+    for store in &stores {
+        for (guild, count) in &store.guilds {
+            let (total, largest, largest_count) = guilds.entry(*guild).or_insert((0, store.name, 0));
+            *total += count;
+            if count > largest_count {
+                *largest = store.name;
+                *largest_count = *count;
+            }
+        }
+    }
+    let mut guilds: Vec<_> = guilds.into_iter().collect();
+    guilds.sort_by_key(|(_, (total, _, _))| std::cmp::Reverse(*total));
+
+    lines.push(String::new());
+    lines.push("**Largest guilds**".to_owned());
+    for (guild, (total, largest, _)) in guilds.into_iter().take(TOP_GUILDS) {
+        let name = guild.name(&ctx.cache).unwrap_or_else(|| "unknown guild".to_owned());
+        lines.push(format!("{} ({}): {} entries, mostly `{}`", name, guild, total, largest));
+    }
+
+    say_lines(ctx, command.channel_id, &lines).await?;
+
+    Ok(())
+}
@@ -0,0 +1,164 @@
+use std::collections::{HashMap, HashSet};
+
+use log::error;
+use serde::{Deserialize, Serialize};
+use serenity::builder::{CreateAllowedMentions, CreateMessage};
+use serenity::model::prelude::*;
+use serenity::prelude::*;
+
+use crate::{command_tokens, CommandError, CommandResult, Persistent, say_lines, store, tokenize};
+use crate::memreport::{Introspect, Owner};
+
+/// The longest a command prefix can be.
+pub const MAX_PREFIX_LEN: usize = 8;
+
+pub struct StateKey;
+
+impl TypeMapKey for StateKey {
+    type Value = Persistent<State>;
+}
+
+#[derive(Serialize, Deserialize, Default, Clone, Eq, PartialEq)]
+pub struct State {
+    guilds: HashMap<GuildId, Settings>,
+}
+
+impl Introspect for State {
+    fn entries(&self) -> Vec<(Owner, usize)> {
+        self.guilds.keys().map(|guild| (Owner::Guild(*guild), 1)).collect()
+    }
+}
+
+/// How a guild gives the bot commands, besides mentioning it at the start of a message.
+#[derive(Serialize, Deserialize, Default, Clone, Eq, PartialEq)]
+pub struct Settings {
+    /// Starts a command without mentioning the bot, like `!`.
+    #[serde(default)]
+    pub prefix: Option<String>,
+    /// Whether mentions of the bot elsewhere in a message get a hint on how to give commands.
+    #[serde(default)]
+    pub casual_hint: bool,
+}
+
+/// How a message addresses the bot.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Addressed<'a> {
+    /// A command, given by starting the message with a mention of the bot or the guild's prefix.
+    Command(Vec<&'a str>),
+    /// A mention of the bot on its own.
+    Bare,
+    /// A mention of the bot somewhere else in a message, which isn't taken as a command.
+    Casual,
+    NotAddressed,
+}
+
+/// Works out whether a message gives the bot a command. Only a mention of the bot as the first token
+/// or a message starting with the prefix does, so that talking about the bot doesn't run commands.
+pub fn classify<'a>(content: &'a str, bot: UserId, prefix: Option<&str>) -> Addressed<'a> {
+    let is_bot = |token: &str| serenity::utils::parse_user_mention(token) == Some(bot);
+
+    let tokens = tokenize(content);
+    match tokens.first() {
+        Some(first) if is_bot(first) => {
+            let tokens = command_tokens(content, bot);
+            if tokens.is_empty() { Addressed::Bare } else { Addressed::Command(tokens) }
+        }
+        _ => {
+            let command = prefix.and_then(|prefix| content.trim_start().strip_prefix(prefix));
+            match command.map(|command| command_tokens(command, bot)) {
+                Some(tokens) if !tokens.is_empty() => Addressed::Command(tokens),
+                _ if mentions(content, bot) => Addressed::Casual,
+                _ => Addressed::NotAddressed,
+            }
+        }
+    }
+}
+
+fn mentions(content: &str, bot: UserId) -> bool {
+    content.contains(&format!("<@{}>", bot)) || content.contains(&format!("<@!{}>", bot))
+}
+
+pub async fn settings(ctx: &Context, guild: Option<GuildId>) -> Settings {
+    let guild = match guild {
+        Some(guild) => guild,
+        None => return Settings::default(),
+    };
+    let state = store::<StateKey>(ctx).await;
+    let settings = state.read().await.guilds.get(&guild).cloned().unwrap_or_default();
+    settings
+}
+
+async fn update(ctx: &Context, guild: GuildId, f: impl FnOnce(&mut Settings)) {
+    let state = store::<StateKey>(ctx).await;
+    state.write(|state| {
+        let settings = state.guilds.entry(guild).or_default();
+        f(settings);
+        if *settings == Settings::default() {
+            state.guilds.remove(&guild);
+        }
+    }).await;
+}
+
+pub async fn set_prefix(ctx: &Context, command: &Message, prefix: Option<&str>) -> CommandResult<()> {
+    let guild = command.guild_id.ok_or(CommandError::NotAllowed)?;
+    if let Some(prefix) = prefix {
+        if prefix.chars().count() > MAX_PREFIX_LEN || prefix.starts_with('<') {
+            return Err(CommandError::MalformedArgument(prefix.to_owned()));
+        }
+    }
+
+    update(ctx, guild, |settings| settings.prefix = prefix.map(str::to_owned)).await;
+
+    Ok(())
+}
+
+pub async fn set_casual_hint(ctx: &Context, command: &Message, casual_hint: bool) -> CommandResult<()> {
+    let guild = command.guild_id.ok_or(CommandError::NotAllowed)?;
+    update(ctx, guild, |settings| settings.casual_hint = casual_hint).await;
+    Ok(())
+}
+
+pub async fn status(ctx: &Context, command: &Message) -> CommandResult<()> {
+    let settings = settings(ctx, command.guild_id).await;
+
+    let mut lines = vec![match &settings.prefix {
+        Some(prefix) => format!("Commands start with a mention of me or with `{}`.", prefix),
+        None => "Commands start with a mention of me. Add a prefix with `command prefix <prefix>`.".to_owned(),
+    }];
+    if settings.casual_hint {
+        lines.push("Mentions of me elsewhere in a message get a hint on how to give commands.".to_owned());
+    }
+    say_lines(ctx, command.channel_id, &lines).await?;
+
+    Ok(())
+}
+
+/// Points someone who mentioned the bot in passing to how commands are given, if the guild wants that.
+pub async fn casual_mention(ctx: &Context, message: &Message, settings: &Settings) {
+    if !settings.casual_hint {
+        return;
+    }
+
+    let bot = ctx.cache.current_user().name.clone();
+    let content = match &settings.prefix {
+        Some(prefix) => format!("👋 To give me a command, start your message with `@{}` or `{}`.", bot, prefix),
+        None => format!("👋 To give me a command, start your message with `@{}`.", bot),
+    };
+    let reply = CreateMessage::new()
+        .content(content)
+        .reference_message(message)
+        .allowed_mentions(CreateAllowedMentions::new());
+    if let Err(err) = message.channel_id.send_message(&ctx.http, reply).await {
+        error!("failed to reply to a casual mention: {:?}", err);
+    }
+}
+
+/// Forgets all guilds not in `guilds`, returning the ones that were removed.
+pub async fn retain_guilds(ctx: &Context, guilds: &HashSet<GuildId>) -> Vec<GuildId> {
+    let state = store::<StateKey>(ctx).await;
+    state.write(|state| {
+        let removed = state.guilds.keys().filter(|guild| !guilds.contains(guild)).copied().collect();
+        state.guilds.retain(|guild, _| guilds.contains(guild));
+        removed
+    }).await
+}
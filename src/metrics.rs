@@ -0,0 +1,188 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+use serenity::model::prelude::*;
+use serenity::prelude::*;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+use crate::{join_queue, persistent_roles, reaction_roles, role_queue};
+
+/// How long a scrape may take to send its request.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+static STARTED: AtomicBool = AtomicBool::new(false);
+
+pub struct ConfigKey;
+
+impl TypeMapKey for ConfigKey {
+    type Value = MetricsConfig;
+}
+
+/// Serves gauges for Prometheus to scrape, which is off unless an address to listen on is given.
+#[derive(Serialize, Deserialize, Default, Clone, Eq, PartialEq)]
+pub struct MetricsConfig {
+    /// Where to serve `/metrics`, like `127.0.0.1:9100`.
+    #[serde(default)]
+    pub listen: Option<String>,
+    /// Also serves gauges for every guild, labelled with its ID, which adds a few series per guild.
+    #[serde(default)]
+    pub per_guild: bool,
+}
+
+/// A count per guild that goes up and down, like the work a guild has waiting in a queue.
+#[derive(Default)]
+pub struct GuildGauge(std::sync::Mutex<HashMap<GuildId, usize>>);
+
+impl GuildGauge {
+    pub fn increment(&self, guild: GuildId) {
+        *self.0.lock().unwrap().entry(guild).or_default() += 1;
+    }
+
+    pub fn decrement(&self, guild: GuildId) {
+        let mut counts = self.0.lock().unwrap();
+        if let Some(count) = counts.get_mut(&guild) {
+            *count = count.saturating_sub(1);
+            if *count == 0 {
+                counts.remove(&guild);
+            }
+        }
+    }
+
+    pub fn snapshot(&self) -> HashMap<GuildId, usize> {
+        self.0.lock().unwrap().clone()
+    }
+}
+
+/// A gauge with a value for the whole bot, or one for each guild.
+pub struct Metric {
+    pub name: &'static str,
+    pub help: &'static str,
+    pub values: Values,
+}
+
+pub enum Values {
+    Global(usize),
+    PerGuild(HashMap<GuildId, usize>),
+}
+
+/// Writes metrics in the Prometheus text format, with guilds sorted so that scrapes are stable.
+pub fn render(metrics: &[Metric]) -> String {
+    let mut out = String::new();
+    for metric in metrics {
+        let _ = writeln!(out, "# HELP {} {}", metric.name, metric.help);
+        let _ = writeln!(out, "# TYPE {} gauge", metric.name);
+        match &metric.values {
+            Values::Global(value) => {
+                let _ = writeln!(out, "{} {}", metric.name, value);
+            }
+            Values::PerGuild(values) => {
+                let mut values: Vec<(&GuildId, &usize)> = values.iter().collect();
+                values.sort();
+                for (guild, value) in values {
+                    let _ = writeln!(out, "{}{{guild=\"{}\"}} {}", metric.name, guild, value);
+                }
+            }
+        }
+    }
+    out
+}
+
+/// Reads the gauges from the stores and queues. Each store is only locked for as long as it takes
+/// to count it, so scrapes never hold up event handling.
+async fn collect(ctx: &Context, per_guild: bool) -> Vec<Metric> {
+    let (role_queue, join_queue) = {
+        let data = ctx.data.read().await;
+        (data.get::<role_queue::QueueKey>().cloned(), data.get::<join_queue::QueueKey>().cloned())
+    };
+
+    let mut metrics = vec![
+        Metric { name: "mossy_guilds", help: "Guilds the bot is in.", values: Values::Global(ctx.cache.guild_count()) },
+        Metric { name: "mossy_selectors", help: "Registered role selectors.", values: Values::Global(reaction_roles::selector_count(ctx).await) },
+        Metric { name: "mossy_role_queue_depth", help: "Role changes waiting or in progress.", values: Values::Global(role_queue.as_ref().map_or(0, |queue| queue.depth())) },
+        Metric { name: "mossy_join_queue_depth", help: "Joins waiting or in progress.", values: Values::Global(join_queue.as_ref().map_or(0, |queue| queue.depth())) },
+    ];
+
+    if per_guild {
+        metrics.extend(vec![
+            Metric { name: "mossy_guild_selectors", help: "Role selectors in a guild.", values: Values::PerGuild(reaction_roles::guild_selector_counts(ctx).await) },
+            Metric { name: "mossy_guild_persisted_users", help: "Users with persisted roles in a guild.", values: Values::PerGuild(persistent_roles::user_counts(ctx).await) },
+            Metric { name: "mossy_guild_role_queue_depth", help: "Role changes of a guild waiting or in progress.", values: Values::PerGuild(role_queue.map(|queue| queue.guild_depths()).unwrap_or_default()) },
+            Metric { name: "mossy_guild_join_queue_depth", help: "Joins to a guild waiting or in progress.", values: Values::PerGuild(join_queue.map(|queue| queue.guild_depths()).unwrap_or_default()) },
+        ]);
+    }
+
+    metrics
+}
+
+/// Starts serving metrics if they're configured, once for the lifetime of the process.
+pub async fn start(ctx: &Context) {
+    let config = {
+        let data = ctx.data.read().await;
+        data.get::<ConfigKey>().cloned().unwrap_or_default()
+    };
+    let listen = match config.listen {
+        Some(listen) => listen,
+        None => return,
+    };
+    if STARTED.swap(true, Ordering::SeqCst) {
+        return;
+    }
+
+    let listener = match TcpListener::bind(&listen).await {
+        Ok(listener) => listener,
+        Err(err) => {
+            error!("failed to serve metrics on {}: {:?}", listen, err);
+            return;
+        }
+    };
+    info!("serving metrics on {}", listen);
+
+    let per_guild = config.per_guild;
+    let ctx = ctx.clone();
+    tokio::spawn(async move {
+        loop {
+            let stream = match listener.accept().await {
+                Ok((stream, _)) => stream,
+                Err(err) => {
+                    error!("failed to accept metrics connection: {:?}", err);
+                    continue;
+                }
+            };
+            let ctx = ctx.clone();
+            tokio::spawn(async move {
+                if let Err(err) = serve(&ctx, stream, per_guild).await {
+                    error!("failed to serve metrics: {:?}", err);
+                }
+            });
+        }
+    });
+}
+
+async fn serve(ctx: &Context, mut stream: TcpStream, per_guild: bool) -> std::io::Result<()> {
+    let mut request = Vec::new();
+    let mut buffer = [0; 1024];
+    while !request.windows(4).any(|window| window == b"\r\n\r\n") && request.len() < 8 * 1024 {
+        let read = match tokio::time::timeout(REQUEST_TIMEOUT, stream.read(&mut buffer)).await {
+            Ok(read) => read?,
+            Err(_) => return Ok(()),
+        };
+        if read == 0 {
+            break;
+        }
+        request.extend_from_slice(&buffer[..read]);
+    }
+
+    let response = if request.starts_with(b"GET /metrics ") {
+        let body = render(&collect(ctx, per_guild).await);
+        format!("HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", body.len(), body)
+    } else {
+        "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_owned()
+    };
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await
+}
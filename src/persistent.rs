@@ -1,69 +1,383 @@
-use std::ops::Deref;
-use std::path::PathBuf;
+use std::collections::{HashMap, HashSet};
+use std::ffi::OsString;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
 
+use log::warn;
 use serde::de::DeserializeOwned;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use serenity::prelude::{Context, TypeMapKey};
 use tokio::fs::File;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::{Mutex, RwLock, RwLockReadGuard};
+
+#[cfg(feature = "replication")]
+use crate::replication;
+
+static PRETTY: AtomicBool = AtomicBool::new(false);
 
 pub trait Persistable: Serialize + DeserializeOwned + Default + Clone + Eq {}
 
 impl<T: Serialize + DeserializeOwned + Default + Clone + Eq> Persistable for T {}
 
+/// A shared handle to a value that is persisted to disk whenever it changes.
+///
+/// Handles are cheap to clone and can be passed into background tasks: reads go through an async
+/// `RwLock`, while writes are serialized so that the file on disk always reflects the latest state.
 pub struct Persistent<T: Persistable> {
+    inner: Arc<Inner<T>>,
+}
+
+struct Inner<T> {
     path: PathBuf,
-    inner: T,
+    value: RwLock<T>,
+    write_lock: Mutex<()>,
+    read_only: bool,
+}
+
+/// Where stores are kept, set by `stores` in `config.json`.
+#[derive(Serialize, Deserialize, Default, Clone, Debug, Eq, PartialEq)]
+pub struct StoreConfig {
+    /// The directory holding the stores, which is created if it doesn't exist yet.
+    #[serde(default)]
+    pub directory: Option<PathBuf>,
+    /// Put in front of every store's file name, like `staging_` to keep a staging bot's stores
+    /// apart from production's in the same directory.
+    #[serde(default)]
+    pub prefix: String,
+    /// Keeps every change in memory without writing it, for running a second copy of the bot
+    /// against stores it mustn't touch.
+    #[serde(default)]
+    pub read_only: bool,
+}
+
+impl StoreConfig {
+    /// Reads just the store settings from a config file, falling back to the defaults if they
+    /// can't be read, for before the config is loaded in full.
+    pub fn from_config_file(path: &Path) -> StoreConfig {
+        let config = std::fs::read(path).ok()
+            .and_then(|bytes| serde_json::from_slice::<Value>(&bytes).ok())
+            .and_then(|mut config| serde_json::from_value(config.get_mut("stores")?.take()).ok());
+        config.unwrap_or_default()
+    }
+
+    #[inline]
+    pub fn directory(&self) -> &Path {
+        self.directory.as_deref().unwrap_or_else(|| Path::new("."))
+    }
+
+    pub fn open_options(&self) -> OpenOptions {
+        let mut options = OpenOptions::new().prefix(&self.prefix).read_only(self.read_only);
+        if let Some(directory) = &self.directory {
+            options = options.directory(directory);
+        }
+        options
+    }
+}
+
+/// Where the running bot's stores were opened, for the files kept next to them like the history and
+/// the archives of idle guilds.
+pub struct OptionsKey;
+
+impl TypeMapKey for OptionsKey {
+    type Value = OpenOptions;
+}
+
+/// How a store is opened. By default, stores are opened for writing in the working directory,
+/// starting out empty when their file doesn't exist yet, and failing to open when it can't be loaded.
+#[derive(Clone, Debug)]
+pub struct OpenOptions {
+    directory: Option<PathBuf>,
+    prefix: String,
+    read_only: bool,
+    create_if_missing: bool,
+    strict: bool,
+}
+
+impl Default for OpenOptions {
+    fn default() -> Self {
+        OpenOptions::new()
+    }
+}
+
+impl OpenOptions {
+    pub fn new() -> Self {
+        OpenOptions {
+            directory: None,
+            prefix: String::new(),
+            read_only: false,
+            create_if_missing: true,
+            strict: true,
+        }
+    }
+
+    /// Opens stores relative to `directory`, creating it if needed.
+    pub fn directory(mut self, directory: impl Into<PathBuf>) -> Self {
+        self.directory = Some(directory.into());
+        self
+    }
+
+    /// Puts `prefix` in front of the file name of every store.
+    pub fn prefix(mut self, prefix: &str) -> Self {
+        self.prefix = prefix.to_owned();
+        self
+    }
+
+    /// Keeps changes in memory without ever writing to disk, for looking at stores without touching
+    /// them, like while the bot is running elsewhere.
+    pub fn read_only(mut self, read_only: bool) -> Self {
+        self.read_only = read_only;
+        self
+    }
+
+    #[inline]
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    /// The directory the stores are kept in.
+    #[inline]
+    pub fn dir(&self) -> &Path {
+        self.directory.as_deref().unwrap_or_else(|| Path::new("."))
+    }
+
+    /// What's put in front of the file name of every store.
+    #[inline]
+    pub fn file_prefix(&self) -> &str {
+        &self.prefix
+    }
+
+    /// Whether a store whose file doesn't exist starts out empty, rather than failing to open.
+    pub fn create_if_missing(mut self, create_if_missing: bool) -> Self {
+        self.create_if_missing = create_if_missing;
+        self
+    }
+
+    /// Whether a store that can't be loaded fails to open, rather than starting out empty with
+    /// the broken file moved aside as `<file>.invalid`.
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// The path a store named like `reaction_roles.json` is kept at.
+    pub fn path(&self, name: impl AsRef<Path>) -> PathBuf {
+        let name = name.as_ref();
+        let mut file = OsString::from(&self.prefix);
+        file.push(name.file_name().unwrap_or_default());
+        let path = name.with_file_name(file);
+        match &self.directory {
+            Some(directory) => directory.join(path),
+            None => path,
+        }
+    }
+
+    pub async fn open<T: Persistable>(&self, name: impl AsRef<Path>) -> io::Result<Persistent<T>> {
+        let path = self.path(name);
+
+        if !self.read_only {
+            if let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+                // fine to race with other stores creating the same directory
+                tokio::fs::create_dir_all(parent).await?;
+            }
+        }
+
+        let bytes = match tokio::fs::read(&path).await {
+            Ok(bytes) => Some(bytes),
+            Err(err) if err.kind() == io::ErrorKind::NotFound && self.create_if_missing => None,
+            Err(err) => return Err(err),
+        };
+
+        let value = match bytes.map(|bytes| serde_json::from_slice(&bytes)) {
+            Some(Ok(value)) => value,
+            Some(Err(err)) if self.strict => return Err(io::Error::new(io::ErrorKind::InvalidData, err)),
+            Some(Err(err)) => {
+                warn!("{} can't be loaded, so it starts out empty: {}", path.display(), err);
+                if !self.read_only {
+                    let mut aside = path.clone().into_os_string();
+                    aside.push(".invalid");
+                    tokio::fs::rename(&path, aside).await?;
+                }
+                T::default()
+            }
+            None => T::default(),
+        };
+
+        Ok(Persistent {
+            inner: Arc::new(Inner {
+                path,
+                value: RwLock::new(value),
+                write_lock: Mutex::new(()),
+                read_only: self.read_only,
+            }),
+        })
+    }
 }
 
 impl<T: Persistable> Persistent<T> {
+    /// Opens the store at `path` with the default `OpenOptions`, panicking if it can't be loaded.
     pub async fn open(path: impl Into<PathBuf>) -> Self {
         let path = path.into();
+        match OpenOptions::new().open(&path).await {
+            Ok(store) => store,
+            Err(err) => panic!("failed to open {}: {}", path.display(), err),
+        }
+    }
 
-        let inner = if path.exists() {
-            let mut file = File::open(&path).await.expect("failed to open file");
+    pub async fn write<F, R>(&self, f: F) -> R
+        where F: FnOnce(&mut T) -> R
+    {
+        // hold the write lock until the file is written so that writes land on disk in order
+        let _write_guard = self.inner.write_lock.lock().await;
+
+        let (result, bytes) = {
+            let mut value = self.inner.value.write().await;
 
-            let mut bytes = Vec::new();
-            file.read_to_end(&mut bytes).await.expect("failed to load file");
+            let previous = value.clone();
+            let result = f(&mut value);
 
-            serde_json::from_slice(&bytes).expect("failed to deserialize")
-        } else {
-            T::default()
+            // our state didn't change, don't bother trying to write the file
+            if previous == *value {
+                return result;
+            }
+
+            (result, to_bytes(&*value))
         };
 
-        Persistent { path, inner }
+        self.save(bytes).await;
+
+        result
+    }
+
+    /// Rewrites the file from the current value, dropping any legacy formats it was stored in.
+    pub async fn compact(&self) {
+        let _write_guard = self.inner.write_lock.lock().await;
+
+        let bytes = to_bytes(&*self.inner.value.read().await);
+
+        self.save(bytes).await;
+    }
+
+    /// Saves the serialized value. Once this returns, the value is on disk.
+    async fn save(&self, bytes: Vec<u8>) {
+        if self.inner.read_only {
+            return;
+        }
+
+        write_file(&self.inner.path, &bytes).await;
+
+        #[cfg(feature = "replication")]
+        replication::replicate(&self.inner.path, bytes);
     }
 
     #[inline]
-    pub async fn write<F, R>(&mut self, f: F) -> R
-        where F: FnOnce(&mut T) -> R
-    {
-        let previous = self.inner.clone();
-        let result = f(&mut self.inner);
+    pub async fn read(&self) -> RwLockReadGuard<'_, T> {
+        self.inner.value.read().await
+    }
 
-        // our state didn't change, don't bother trying to write the config
-        if previous == self.inner {
-            return result;
+    /// Copies the entries of `keys` from a map kept in the top-level `field` of the value, like the
+    /// `guilds` of most stores, as they would be written to disk.
+    pub async fn entries(&self, field: &str, keys: &HashSet<String>) -> HashMap<String, Value> {
+        let value = serde_json::to_value(&*self.read().await).expect("failed to serialize");
+        match value.get(field).and_then(Value::as_object) {
+            Some(map) => map.iter()
+                .filter(|(key, _)| keys.contains(*key))
+                .map(|(key, entry)| (key.clone(), entry.clone()))
+                .collect(),
+            None => HashMap::new(),
         }
+    }
 
-        let mut file = File::create(&self.path).await.expect("failed to create file");
+    /// Removes the entries of `keys` from a map kept in the top-level `field` of the value.
+    pub async fn remove_entries(&self, field: &str, keys: &HashSet<String>) {
+        self.edit_map(field, |map| map.retain(|key, _| !keys.contains(key))).await;
+    }
 
-        let bytes = serde_json::to_vec(&self.inner).expect("failed to serialize");
-        file.write_all(&bytes).await.expect("failed to write to file");
+    /// Puts entries copied with `entries` back, leaving alone any that were filled in since.
+    pub async fn restore_entries(&self, field: &str, entries: HashMap<String, Value>) {
+        self.edit_map(field, |map| {
+            for (key, entry) in entries {
+                map.entry(key).or_insert(entry);
+            }
+        }).await;
+    }
 
-        result
+    async fn edit_map<F>(&self, field: &str, f: F)
+        where F: FnOnce(&mut Map<String, Value>)
+    {
+        self.write(|value| {
+            let mut json = serde_json::to_value(&*value).expect("failed to serialize");
+            if let Some(map) = json.get_mut(field).and_then(Value::as_object_mut) {
+                f(map);
+                *value = serde_json::from_value(json).expect("failed to deserialize");
+            }
+        }).await;
     }
+}
 
+impl<T: Persistable> Clone for Persistent<T> {
     #[inline]
-    pub fn read(&self) -> &T {
-        &self.inner
+    fn clone(&self) -> Self {
+        Persistent { inner: self.inner.clone() }
     }
 }
 
-impl<T: Persistable> Deref for Persistent<T> {
-    type Target = T;
+/// Replaces the file through a temporary one, so that it's never left half-written, and syncs the
+/// directory after so that the replacement itself survives a crash.
+pub(crate) async fn write_file(path: &Path, bytes: &[u8]) {
+    let mut name = path.file_name().unwrap_or_default().to_owned();
+    name.push(".tmp");
+    let temporary = path.with_file_name(name);
 
-    #[inline]
-    fn deref(&self) -> &T {
-        &self.inner
+    let mut file = File::create(&temporary).await.expect("failed to create file");
+    file.write_all(bytes).await.expect("failed to write to file");
+    file.sync_all().await.expect("failed to sync file");
+    tokio::fs::rename(&temporary, path).await.expect("failed to replace file");
+
+    sync_directory(path).await;
+}
+
+/// Syncs the directory holding `path`, which is what makes a rename into it durable. Only Unix
+/// lets directories be opened like this.
+#[cfg(unix)]
+async fn sync_directory(path: &Path) {
+    let directory = path.parent().filter(|parent| !parent.as_os_str().is_empty()).unwrap_or_else(|| Path::new("."));
+    let directory = File::open(directory).await.expect("failed to open directory");
+    directory.sync_all().await.expect("failed to sync directory");
+}
+
+#[cfg(not(unix))]
+async fn sync_directory(_path: &Path) {}
+
+/// Has stores written pretty-printed with the keys of every map sorted, so that they diff cleanly
+/// when kept in version control.
+pub fn set_pretty_state(pretty: bool) {
+    PRETTY.store(pretty, Ordering::SeqCst);
+}
+
+/// Serializes a value the way stores are written to disk.
+fn to_bytes<T: Serialize>(value: &T) -> Vec<u8> {
+    if PRETTY.load(Ordering::SeqCst) {
+        // going through a `Value` sorts the keys, since its maps are ordered
+        let value = serde_json::to_value(value).expect("failed to serialize");
+        let mut bytes = serde_json::to_vec_pretty(&value).expect("failed to serialize");
+        bytes.push(b'\n');
+        bytes
+    } else {
+        serde_json::to_vec(value).expect("failed to serialize")
     }
 }
+
+/// Fetches a shared handle to the store registered under `K` in the client data.
+///
+/// The global data lock is only held for as long as it takes to clone the handle.
+pub async fn store<K>(ctx: &Context) -> K::Value
+    where K: TypeMapKey,
+          K::Value: Clone
+{
+    let data = ctx.data.read().await;
+    data.get::<K>().expect("store not registered").clone()
+}
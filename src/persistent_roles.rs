@@ -3,12 +3,20 @@ use std::future;
 use std::time::Duration;
 
 use log::error;
+use regex::Regex;
 use serde::{Deserialize, Serialize};
+use serenity::builder::{CreateAllowedMentions, CreateEmbed, CreateMessage};
 use serenity::futures::TryStreamExt;
 use serenity::model::prelude::*;
 use serenity::prelude::*;
 
-use crate::{CommandError, CommandResult, Persistent};
+use crate::{audit, capabilities, CommandError, CommandResult, confirm, events, members, parse_role_argument, Persistent, quotas, role_queue, role_snapshots, say_lines, scheduler, store};
+use crate::quotas::Quota;
+use crate::events::{Event, RoleSource};
+use crate::memreport::{Introspect, Owner, Reference};
+
+/// How many times pending roles are retried before they're given up on, which is two hours of retries.
+const MAX_RETRIES: u32 = 12;
 
 pub struct StateKey;
 
@@ -21,25 +29,213 @@ pub struct State {
     guilds: HashMap<GuildId, GuildState>,
 }
 
+impl Introspect for State {
+    fn entries(&self) -> Vec<(Owner, usize)> {
+        self.guilds.iter().map(|(guild, state)| {
+            (Owner::Guild(*guild), state.roles.len() + state.users.len() + state.departed.len() + state.pending.len())
+        }).collect()
+    }
+
+    fn references(&self) -> Vec<(Owner, Reference)> {
+        self.guilds.iter().flat_map(|(guild, state)| {
+            state.roles.iter().map(move |role| (Owner::Guild(*guild), Reference::Role(*role)))
+        }).collect()
+    }
+}
+
+impl State {
+    #[inline]
+    pub fn guild(&self, guild: GuildId) -> Option<&GuildState> {
+        self.guilds.get(&guild)
+    }
+
+    #[inline]
+    pub fn guild_mut(&mut self, guild: GuildId) -> &mut GuildState {
+        self.guilds.entry(guild).or_default()
+    }
+}
+
 #[derive(Serialize, Deserialize, Default, Clone, Eq, PartialEq)]
-struct GuildState {
+pub struct GuildState {
     roles: HashSet<RoleId>,
     users: HashMap<UserId, Vec<RoleId>>,
+    /// When users with persisted roles left the guild, so that they can be forgotten eventually.
+    #[serde(default)]
+    departed: HashMap<UserId, i64>,
+    /// Whether the guild owner should be sent a DM when persisted roles can't be restored.
+    #[serde(default)]
+    notify_owner: bool,
+    /// Persisted roles that failed to be restored to members who rejoined, to be retried later.
+    #[serde(default)]
+    pending: HashMap<UserId, Vec<RoleId>>,
+    /// How many times each user's pending roles were retried so far.
+    #[serde(default)]
+    retries: HashMap<UserId, u32>,
+    /// What happens to the persisted roles of banned users.
+    #[serde(default)]
+    on_ban: BanPolicy,
+    /// Whether unbanning a user with frozen roles lets them have the roles back, rather than forgetting them.
+    #[serde(default)]
+    restore_on_unban: bool,
+    /// Banned users whose persisted roles are kept, but not given back until they're unbanned.
+    #[serde(default)]
+    frozen: HashSet<UserId>,
+}
+
+/// What happens to the persisted roles of a user who gets banned.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum BanPolicy {
+    /// The roles stay as they are, so they come back if the user is unbanned and rejoins.
+    #[default]
+    Keep,
+    /// The roles are kept aside until the user is unbanned, which decides whether they come back.
+    Freeze,
+    /// The roles are forgotten.
+    Purge,
 }
 
 impl GuildState {
+    #[inline]
+    pub fn is_persisted(&self, role: RoleId) -> bool {
+        self.roles.contains(&role)
+    }
+
+    #[inline]
+    pub fn roles(&self) -> impl Iterator<Item=RoleId> + '_ {
+        self.roles.iter().copied()
+    }
+
+    #[inline]
+    pub fn notify_owner(&self) -> bool {
+        self.notify_owner
+    }
+
+    #[inline]
+    pub fn set_notify_owner(&mut self, notify_owner: bool) {
+        self.notify_owner = notify_owner;
+    }
+
+    #[inline]
+    pub fn on_ban(&self) -> BanPolicy {
+        self.on_ban
+    }
+
+    #[inline]
+    pub fn set_on_ban(&mut self, on_ban: BanPolicy) {
+        self.on_ban = on_ban;
+    }
+
+    #[inline]
+    pub fn set_restore_on_unban(&mut self, restore_on_unban: bool) {
+        self.restore_on_unban = restore_on_unban;
+    }
+
+    #[inline]
+    pub fn is_frozen(&self, user: UserId) -> bool {
+        self.frozen.contains(&user)
+    }
+
+    #[inline]
+    pub fn users(&self) -> impl Iterator<Item=(UserId, &[RoleId])> {
+        self.users.iter().map(|(user, roles)| (*user, roles.as_slice()))
+    }
+
+    #[inline]
+    pub fn user_roles(&self, user: UserId) -> Option<&[RoleId]> {
+        self.users.get(&user).map(|roles| roles.as_slice())
+    }
+
     pub fn set_user_roles(&mut self, user: UserId, roles: Vec<RoleId>) {
         if !roles.is_empty() {
             self.users.insert(user, roles);
         } else {
             self.users.remove(&user);
+            self.departed.remove(&user);
+            self.frozen.remove(&user);
+        }
+    }
+
+    /// Applies the ban policy to a user who was banned, returning the persisted roles it applied to.
+    pub fn ban(&mut self, user: UserId) -> Vec<RoleId> {
+        let roles = match self.users.get(&user) {
+            Some(roles) => roles.clone(),
+            None => return Vec::new(),
+        };
+        match self.on_ban {
+            BanPolicy::Keep => (),
+            BanPolicy::Freeze => {
+                self.frozen.insert(user);
+            }
+            BanPolicy::Purge => self.forget(user),
+        }
+        roles
+    }
+
+    /// Unfreezes the roles of a user who was unbanned, or forgets them unless they're to be restored.
+    /// Returns whether the user had frozen roles and if so, whether they were kept.
+    pub fn unban(&mut self, user: UserId) -> Option<bool> {
+        if !self.frozen.remove(&user) {
+            return None;
+        }
+        if !self.restore_on_unban {
+            self.forget(user);
+        }
+        Some(self.restore_on_unban)
+    }
+
+    fn forget(&mut self, user: UserId) {
+        self.users.remove(&user);
+        self.departed.remove(&user);
+        self.pending.remove(&user);
+        self.retries.remove(&user);
+        self.frozen.remove(&user);
+    }
+
+    pub fn mark_departed(&mut self, user: UserId, at: i64) {
+        self.pending.remove(&user);
+        self.retries.remove(&user);
+        if self.users.contains_key(&user) {
+            self.departed.insert(user, at);
+        }
+    }
+
+    /// When the user left the guild, if they left with persisted roles.
+    #[inline]
+    pub fn departed_at(&self, user: UserId) -> Option<i64> {
+        self.departed.get(&user).copied()
+    }
+
+    #[inline]
+    pub fn pending_roles(&self, user: UserId) -> Option<&[RoleId]> {
+        self.pending.get(&user).map(|roles| roles.as_slice())
+    }
+
+    #[inline]
+    pub fn mark_returned(&mut self, user: UserId) {
+        self.departed.remove(&user);
+    }
+
+    /// Forgets users who departed before the given time, returning how many were removed.
+    pub fn prune_departed(&mut self, before: i64) -> usize {
+        // bans can last for much longer, and frozen roles are kept until the unban
+        let expired: Vec<UserId> = self.departed.iter()
+            .filter(|(user, departed_at)| **departed_at < before && !self.frozen.contains(user))
+            .map(|(user, _)| *user)
+            .collect();
+
+        for user in &expired {
+            self.users.remove(user);
+            self.departed.remove(user);
         }
+
+        expired.len()
     }
 
     pub fn add_role(&mut self, role: RoleId, users_with_role: Vec<UserId>) {
         if self.roles.insert(role) {
             for user in users_with_role {
-                let roles = self.users.entry(user).or_insert_with(|| Vec::new());
+                let roles = self.users.entry(user).or_default();
                 roles.push(role);
             }
         }
@@ -61,20 +257,44 @@ impl GuildState {
 
             for user in empty_users {
                 self.users.remove(&user);
+                self.departed.remove(&user);
+                self.frozen.remove(&user);
             }
         }
     }
 }
 
+/// Describes which of a user's roles are persisted, and whether any are still waiting to be restored.
+pub async fn describe_user(ctx: &Context, guild: GuildId, user: UserId) -> Vec<String> {
+    let guild_state = guild_state(ctx, guild).await.unwrap_or_default();
+    let mentions = |roles: &[RoleId]| roles.iter().map(|role| format!("<@&{}>", role.get())).collect::<Vec<_>>().join(" ");
+
+    let mut lines = vec![match guild_state.user_roles(user) {
+        Some(roles) => format!("<@{}> has these persisted roles: {}", user.get(), mentions(roles)),
+        None => format!("<@{}> has no persisted roles.", user.get()),
+    }];
+    if guild_state.is_frozen(user) {
+        lines.push("They're banned, so the roles are frozen until they're unbanned.".to_owned());
+    } else if let Some(departed_at) = guild_state.departed_at(user) {
+        lines.push(format!("They left <t:{}:R>, and get the roles back if they rejoin.", departed_at));
+    }
+    if let Some(pending) = guild_state.pending_roles(user) {
+        lines.push(format!("These couldn't be restored yet and are retried: {}", mentions(pending)));
+    }
+    lines
+}
+
 pub async fn add_role(ctx: &Context, command: &Message, role: RoleId) -> CommandResult<()> {
     if let Some(guild) = command.guild_id {
-        let mut data = ctx.data.write().await;
+        if !persisted_roles(ctx, guild).await.contains(&role) {
+            quotas::check(ctx, guild, Quota::PersistedRoles, 1).await?;
+        }
 
         let users_with_role = users_with_role(ctx, guild, role).await?;
 
-        let state = data.get_mut::<StateKey>().unwrap();
+        let state = store::<StateKey>(ctx).await;
         state.write(|state| {
-            let guild = state.guilds.entry(guild).or_insert_with(|| GuildState::default());
+            let guild = state.guilds.entry(guild).or_default();
             guild.add_role(role, users_with_role);
         }).await;
 
@@ -84,7 +304,142 @@ pub async fn add_role(ctx: &Context, command: &Message, role: RoleId) -> Command
     }
 }
 
+/// Persists or stops persisting each of several roles, replying with how each one went rather than
+/// stopping at the first that fails.
+pub async fn update_roles(ctx: &Context, command: &Message, references: &[&str], persist: bool) -> CommandResult<()> {
+    let guild = command.guild_id.ok_or(CommandError::NotAllowed)?;
+    if references.is_empty() {
+        return Err(CommandError::InvalidCommand);
+    }
+    let existing: HashSet<RoleId> = ctx.http.get_guild_roles(guild).await?.into_iter().map(|role| role.id).collect();
+
+    let mut outcomes = Vec::with_capacity(references.len());
+    for reference in references {
+        let outcome = match parse_role_argument(reference) {
+            // deleted roles can still be stopped being persisted
+            Ok(role) if persist && !existing.contains(&role) => Err(CommandError::UnknownRole),
+            Ok(role) if persist => add_role(ctx, command, role).await.map(|_| role),
+            Ok(role) => remove_role(ctx, command, role).await.map(|_| role),
+            Err(err) => Err(err),
+        };
+        outcomes.push((*reference, outcome));
+    }
+
+    if outcomes.len() == 1 {
+        return outcomes.pop().unwrap().1.map(|_| ());
+    }
+
+    let lines: Vec<String> = outcomes.iter().map(|(reference, outcome)| match outcome {
+        Ok(role) => format!("✅ <@&{}>", role.get()),
+        Err(err) => format!("❌ `{}`: {}", reference, err),
+    }).collect();
+    let title = if persist { "Persisting roles" } else { "No longer persisting roles" };
+    command.channel_id.send_message(&ctx.http, CreateMessage::new()
+        .embed(CreateEmbed::new().title(title).description(lines.join("\n")))
+        .allowed_mentions(CreateAllowedMentions::new())
+    ).await?;
+
+    Ok(())
+}
+
+/// Persists every role whose name matches `pattern`, after the matches are confirmed. Roles managed
+/// by an integration and roles the bot can't give are left out, since they could never be restored.
+pub async fn add_roles_matching(ctx: &Context, command: &Message, pattern: &str) -> CommandResult<()> {
+    let guild = command.guild_id.ok_or(CommandError::NotAllowed)?;
+    let pattern = Regex::new(pattern).map_err(|_| CommandError::MalformedArgument(pattern.to_owned()))?;
+
+    let all_roles = ctx.http.get_guild_roles(guild).await?;
+    let bot = ctx.cache.current_user().id;
+    let bot = members::member(ctx, guild, bot).await?;
+    let bot_position = all_roles.iter()
+        .filter(|role| bot.roles.contains(&role.id))
+        .map(|role| role.position)
+        .max()
+        .unwrap_or(0);
+
+    let mut roles: Vec<Role> = all_roles.into_iter()
+        .filter(|role| role.id.get() != guild.get() && !role.managed && role.position < bot_position)
+        .filter(|role| pattern.is_match(&role.name))
+        .collect();
+
+    if roles.is_empty() {
+        return Err(CommandError::NoMatchingRoles);
+    }
+
+    roles.sort_by_key(|role| std::cmp::Reverse(role.position));
+
+    let role_ids: Vec<RoleId> = roles.iter().map(|role| role.id).collect();
+    let persisted = persisted_roles(ctx, guild).await;
+    quotas::check(ctx, guild, Quota::PersistedRoles, role_ids.iter().filter(|role| !persisted.contains(role)).count()).await?;
+
+    let lines: Vec<String> = roles.iter().map(|role| format!("`{}`", role.name)).collect();
+    say_lines(ctx, command.channel_id, &lines).await?;
+    let prompt = format!("Persist these {} roles?", roles.len());
+    if !confirm::ask(ctx, command.channel_id, command.author.id, prompt).await? {
+        return Ok(());
+    }
+
+    persist_roles(ctx, guild, &role_ids).await?;
+    command.reply(ctx, format!("Persisting {} roles.", roles.len())).await?;
+
+    Ok(())
+}
+
+/// Persists several roles at once, scanning the member list only once rather than once per role.
+pub async fn persist_roles(ctx: &Context, guild: GuildId, roles: &[RoleId]) -> serenity::Result<()> {
+    let members: Vec<Member> = if capabilities::has_members_intent() {
+        guild.members_iter(ctx).try_collect().await?
+    } else {
+        Vec::new()
+    };
+
+    let state = store::<StateKey>(ctx).await;
+    state.write(|state| {
+        let guild = state.guilds.entry(guild).or_default();
+        for role in roles {
+            let users_with_role = members.iter()
+                .filter(|member| member.roles.contains(role))
+                .map(|member| member.user.id)
+                .collect();
+            guild.add_role(*role, users_with_role);
+        }
+    }).await;
+
+    Ok(())
+}
+
+/// How many users have persisted roles in each guild.
+pub async fn user_counts(ctx: &Context) -> HashMap<GuildId, usize> {
+    let state = store::<StateKey>(ctx).await;
+    let counts = state.read().await.guilds.iter()
+        .map(|(guild, state)| (*guild, state.users.len()))
+        .collect();
+    counts
+}
+
+pub async fn guild_state(ctx: &Context, guild: GuildId) -> Option<GuildState> {
+    let state = store::<StateKey>(ctx).await;
+    let guild = state.read().await.guild(guild).cloned();
+    guild
+}
+
+pub async fn is_configured(ctx: &Context, guild: GuildId) -> bool {
+    !persisted_roles(ctx, guild).await.is_empty()
+}
+
+pub async fn persisted_roles(ctx: &Context, guild: GuildId) -> Vec<RoleId> {
+    let state = store::<StateKey>(ctx).await;
+    let roles = state.read().await.guilds.get(&guild)
+        .map(|guild| guild.roles.iter().copied().collect())
+        .unwrap_or_default();
+    roles
+}
+
 async fn users_with_role(ctx: &Context, guild: GuildId, role: RoleId) -> serenity::Result<Vec<UserId>> {
+    // listing members needs the members intent, so without it members are picked up as they're seen
+    if !capabilities::has_members_intent() {
+        return Ok(Vec::new());
+    }
     guild.members_iter(ctx)
         .try_filter(|member| future::ready(member.roles.contains(&role)))
         .map_ok(|member| member.user.id)
@@ -94,9 +449,7 @@ async fn users_with_role(ctx: &Context, guild: GuildId, role: RoleId) -> serenit
 
 pub async fn remove_role(ctx: &Context, command: &Message, role: RoleId) -> CommandResult<()> {
     if let Some(guild) = command.guild_id {
-        let mut data = ctx.data.write().await;
-
-        let state = data.get_mut::<StateKey>().unwrap();
+        let state = store::<StateKey>(ctx).await;
         state.write(|state| {
             if let Some(guild) = state.guilds.get_mut(&guild) {
                 guild.remove_role(role);
@@ -109,52 +462,343 @@ pub async fn remove_role(ctx: &Context, command: &Message, role: RoleId) -> Comm
     }
 }
 
+/// Whether a member has persisted roles waiting to be given back to them.
+pub async fn has_returning_roles(ctx: &Context, guild: GuildId, user: UserId) -> bool {
+    let state = store::<StateKey>(ctx).await;
+    let state = state.read().await;
+    state.guilds.get(&guild)
+        .filter(|guild| !guild.is_frozen(user))
+        .and_then(|guild| guild.users.get(&user))
+        .is_some_and(|roles| !roles.is_empty())
+}
+
 pub async fn guild_member_addition(ctx: &Context, member: &mut Member) {
-    let data = ctx.data.read().await;
-    let state = data.get::<StateKey>().unwrap();
+    let state = store::<StateKey>(ctx).await;
 
-    let roles = match state.guilds.get(&member.guild_id) {
-        Some(guild) => guild.users.get(&member.user.id).cloned().unwrap_or_default(),
-        None => Vec::default()
+    let roles = match state.read().await.guilds.get(&member.guild_id) {
+        Some(guild) if !guild.is_frozen(member.user.id) => guild.users.get(&member.user.id).cloned().unwrap_or_default(),
+        _ => Vec::default()
     };
 
+    let mut restored = Vec::new();
     if !roles.is_empty() {
-        let permissions = crate::member_permissions(ctx, member.guild_id, ctx.cache.current_user_id().await).await;
-        if !permissions.manage_roles() {
-            return;
+        state.write(|state| {
+            if let Some(guild) = state.guilds.get_mut(&member.guild_id) {
+                guild.mark_returned(member.user.id);
+            }
+        }).await;
+
+        let failures = restore_roles(ctx, member.guild_id, member.user.id, &roles).await;
+        set_pending(ctx, member.guild_id, member.user.id, &failures, false).await;
+        if !failures.is_empty() {
+            report_restore_failures(ctx, member, &failures).await;
+        }
+        restored = roles.into_iter().filter(|role| !failures.iter().any(|failure| failure.role == *role)).collect();
+    }
+
+    restored.extend(member.roles.iter().copied());
+    role_snapshots::member_rejoined(ctx, member.guild_id, member.user.id, &restored).await;
+}
+
+/// A persisted role that couldn't be restored.
+struct Failure {
+    role: RoleId,
+    reason: String,
+    /// Whether Discord refused the role outright, like when it's above the bot, so that retrying
+    /// won't help.
+    permanent: bool,
+}
+
+/// Keeps the failures worth retrying as the user's pending roles, counting a retry if this was one.
+/// Returns the roles given up on after too many retries.
+async fn set_pending(ctx: &Context, guild: GuildId, user: UserId, failures: &[Failure], retried: bool) -> Vec<RoleId> {
+    let retry: Vec<RoleId> = failures.iter().filter(|failure| !failure.permanent).map(|failure| failure.role).collect();
+
+    let state = store::<StateKey>(ctx).await;
+    state.write(|state| {
+        let guild = match state.guilds.get_mut(&guild) {
+            Some(guild) => guild,
+            None => return Vec::new(),
+        };
+        if retry.is_empty() {
+            guild.pending.remove(&user);
+            guild.retries.remove(&user);
+            return Vec::new();
+        }
+
+        let retries = guild.retries.entry(user).or_default();
+        if retried {
+            *retries += 1;
+        }
+        if *retries >= MAX_RETRIES {
+            guild.pending.remove(&user);
+            guild.retries.remove(&user);
+            return retry;
         }
+        guild.pending.insert(user, retry);
+        Vec::new()
+    }).await
+}
+
+/// Retries restoring persisted roles that failed to be applied before, only attempting the missing ones.
+pub async fn retry_pending(ctx: Context) {
+    let pending: Vec<(GuildId, UserId, Vec<RoleId>)> = {
+        let state = store::<StateKey>(&ctx).await;
+        let state = state.read().await;
+        state.guilds.iter()
+            .flat_map(|(guild, guild_state)| {
+                guild_state.pending.iter().map(move |(user, roles)| (*guild, *user, roles.clone()))
+            })
+            .collect()
+    };
+
+    for (guild, user, roles) in pending {
+        let member = match members::member(&ctx, guild, user).await {
+            Ok(member) => member,
+            Err(err) if crate::is_not_found(&err) => {
+                set_pending(&ctx, guild, user, &[], false).await;
+                continue;
+            }
+            Err(err) => {
+                error!("failed to look up {} to retry restoring roles: {:?}", user, err);
+                continue;
+            }
+        };
 
-        // magic delay to make sure adding the roles actually does so
-        tokio::time::sleep(Duration::from_secs(1)).await;
+        let missing: Vec<RoleId> = roles.into_iter().filter(|role| !member.roles.contains(role)).collect();
+        let failures = restore_roles(&ctx, guild, user, &missing).await;
+        let given_up = set_pending(&ctx, guild, user, &failures, true).await;
 
-        if let Err(err) = member.add_roles(&ctx, &roles).await {
-            error!("failed to add persisted roles ({:?}) to {}: {:?}", roles, member, err);
+        let dropped: Vec<&Failure> = failures.iter().filter(|failure| failure.permanent || given_up.contains(&failure.role)).collect();
+        if !dropped.is_empty() {
+            let mut lines = vec![format!("⚠️ Stopped retrying these persisted roles of <@{}>:", user.get())];
+            lines.extend(dropped.iter().map(|failure| format!("<@&{}>: {}", failure.role.get(), failure.reason)));
+            audit::log(&ctx, guild, lines.join("\n")).await;
         }
     }
 }
 
-pub async fn guild_member_update(ctx: &Context, member: &Member) {
-    if !has_guild(ctx, member.guild_id).await {
+/// Whether Discord refused a request in a way that retrying won't change, unlike rate limits and
+/// server errors.
+fn is_permanent(err: &serenity::Error) -> bool {
+    match err {
+        serenity::Error::Http(err) => err.status_code().is_some_and(|status| status.is_client_error() && status.as_u16() != 429),
+        _ => false,
+    }
+}
+
+/// Adds each role separately so that one role we can't assign doesn't stop the others from being
+/// restored, returning the roles that failed along with why.
+async fn restore_roles(ctx: &Context, guild: GuildId, user: UserId, roles: &[RoleId]) -> Vec<Failure> {
+    // roles that were deleted since being persisted can never be restored, so don't count them as failures
+    let roles: Vec<RoleId> = match ctx.cache.guild(guild).map(|guild| guild.roles.keys().copied().collect::<HashSet<_>>()) {
+        Some(existing) => roles.iter().copied().filter(|role| existing.contains(role)).collect(),
+        None => roles.to_vec(),
+    };
+    if roles.is_empty() {
+        return Vec::new();
+    }
+
+    let bot = ctx.cache.current_user().id;
+    let permissions = crate::member_permissions(ctx, guild, bot).await;
+    if !permissions.manage_roles() {
+        let reason = "I'm missing the Manage Roles permission";
+        return roles.iter().map(|role| Failure { role: *role, reason: reason.to_owned(), permanent: false }).collect();
+    }
+
+    // magic delay to make sure adding the roles actually does so
+    tokio::time::sleep(Duration::from_secs(1)).await;
+
+    let mut failures = Vec::new();
+    let mut granted = Vec::new();
+    for role in roles {
+        match role_queue::add_roles(ctx, guild, user, &[role]).await {
+            Ok(()) => granted.push(role),
+            Err(err) => {
+                error!("failed to restore persisted role {} to {}: {:?}", role, user, err);
+                failures.push(Failure { role, reason: err.to_string(), permanent: is_permanent(&err) });
+            }
+        }
+    }
+
+    if !granted.is_empty() {
+        events::publish(ctx, Event::RolesGranted { guild, user, roles: granted, source: RoleSource::Persistence }).await;
+    }
+
+    failures
+}
+
+async fn report_restore_failures(ctx: &Context, member: &Member, failures: &[Failure]) {
+    let guild = member.guild_id;
+
+    let mut lines = vec![format!("⚠️ Couldn't restore these persisted roles to <@{}>:", member.user.id.get())];
+    lines.extend(failures.iter().map(|failure| {
+        let retried = if failure.permanent { " (not retried)" } else { "" };
+        format!("<@&{}>: {}{}", failure.role.get(), failure.reason, retried)
+    }));
+    audit::log(ctx, guild, lines.join("\n")).await;
+
+    let notify_owner = {
+        let state = store::<StateKey>(ctx).await;
+        let notify_owner = state.read().await.guild(guild).is_some_and(|guild| guild.notify_owner);
+        notify_owner
+    };
+    if !notify_owner {
+        return;
+    }
+
+    let guild_name = ctx.cache.guild(guild).map(|guild| guild.name.clone()).unwrap_or_default();
+    let role_names: HashMap<RoleId, String> = ctx.cache.guild(guild).map(|guild| {
+        guild.roles.iter().map(|(id, role)| (*id, role.name.clone())).collect()
+    }).unwrap_or_default();
+    let failed: Vec<String> = failures.iter()
+        .map(|failure| {
+            let name = role_names.get(&failure.role).cloned().unwrap_or_else(|| failure.role.get().to_string());
+            format!("`{}`: {}", name, failure.reason)
+        })
+        .collect();
+    let content = format!(
+        "⚠️ I couldn't restore some persisted roles to {} in **{}**:\n{}",
+        member.user.tag(), guild_name, failed.join("\n"),
+    );
+
+    let owner = match ctx.cache.guild(guild).map(|guild| guild.owner_id) {
+        Some(owner) => owner,
+        None => return,
+    };
+    let result = match owner.create_dm_channel(&ctx.http).await {
+        Ok(dm) => dm.say(&ctx.http, content).await.map(|_| ()),
+        Err(err) => Err(err),
+    };
+    if let Err(err) = result {
+        error!("failed to notify owner of {} about role restore failures: {:?}", guild, err);
+    }
+}
+
+pub async fn set_notify_owner(ctx: &Context, command: &Message, notify_owner: bool) -> CommandResult<()> {
+    let guild = command.guild_id.ok_or(CommandError::NotAllowed)?;
+
+    let state = store::<StateKey>(ctx).await;
+    state.write(|state| {
+        state.guilds.entry(guild).or_default().notify_owner = notify_owner;
+    }).await;
+
+    Ok(())
+}
+
+pub async fn set_on_ban(ctx: &Context, command: &Message, on_ban: BanPolicy) -> CommandResult<()> {
+    let guild = command.guild_id.ok_or(CommandError::NotAllowed)?;
+
+    let state = store::<StateKey>(ctx).await;
+    state.write(|state| state.guilds.entry(guild).or_default().set_on_ban(on_ban)).await;
+
+    Ok(())
+}
+
+pub async fn set_restore_on_unban(ctx: &Context, command: &Message, restore_on_unban: bool) -> CommandResult<()> {
+    let guild = command.guild_id.ok_or(CommandError::NotAllowed)?;
+
+    let state = store::<StateKey>(ctx).await;
+    state.write(|state| state.guilds.entry(guild).or_default().set_restore_on_unban(restore_on_unban)).await;
+
+    Ok(())
+}
+
+pub async fn guild_ban_addition(ctx: &Context, guild: GuildId, user: UserId) {
+    if !has_guild(ctx, guild).await {
         return;
     }
 
-    let mut data = ctx.data.write().await;
-    let state = data.get_mut::<StateKey>().unwrap();
+    let state = store::<StateKey>(ctx).await;
+    let (policy, roles) = state.write(|state| {
+        let guild = state.guild_mut(guild);
+        (guild.on_ban(), guild.ban(user))
+    }).await;
+    if roles.is_empty() {
+        return;
+    }
+
+    let mentions: Vec<String> = roles.iter().map(|role| format!("<@&{}>", role.get())).collect();
+    let decision = match policy {
+        BanPolicy::Keep => "are kept, and come back if they're unbanned and rejoin",
+        BanPolicy::Freeze => "are frozen until they're unbanned",
+        BanPolicy::Purge => "were forgotten",
+    };
+    audit::log(ctx, guild, format!("🔨 <@{}> was banned, so their persisted roles {}: {}", user.get(), decision, mentions.join(" "))).await;
+}
 
+pub async fn guild_ban_removal(ctx: &Context, guild: GuildId, user: UserId) {
+    if !has_guild(ctx, guild).await {
+        return;
+    }
+
+    let state = store::<StateKey>(ctx).await;
+    let kept = state.write(|state| state.guild_mut(guild).unban(user)).await;
+    let decision = match kept {
+        Some(true) => "come back if they rejoin",
+        Some(false) => "were forgotten",
+        None => return,
+    };
+    audit::log(ctx, guild, format!("🔓 <@{}> was unbanned, so their frozen persisted roles {}.", user.get(), decision)).await;
+}
+
+pub async fn guild_member_update(ctx: &Context, member: &Member) {
+    member_seen(ctx, member.guild_id, member.user.id, &member.roles).await;
+}
+
+/// Takes note of the roles a member has, whether from a member update or, without the members
+/// intent, from a message or reaction of theirs.
+pub async fn member_seen(ctx: &Context, guild: GuildId, user: UserId, roles: &[RoleId]) {
+    if !has_guild(ctx, guild).await {
+        return;
+    }
+
+    let state = store::<StateKey>(ctx).await;
     state.write(|state| {
-        if let Some(guild) = state.guilds.get_mut(&member.guild_id) {
-            let roles = member.roles.iter()
+        if let Some(guild) = state.guilds.get_mut(&guild) {
+            let roles = roles.iter()
                 .filter(|role| guild.roles.contains(role))
                 .cloned()
                 .collect();
 
-            guild.set_user_roles(member.user.id, roles);
+            guild.set_user_roles(user, roles);
         }
     }).await;
 }
 
+pub async fn guild_member_removal(ctx: &Context, guild: GuildId, user: UserId) {
+    if !has_guild(ctx, guild).await {
+        return;
+    }
+
+    let state = store::<StateKey>(ctx).await;
+    state.write(|state| {
+        if let Some(guild) = state.guilds.get_mut(&guild) {
+            guild.mark_departed(user, scheduler::now());
+        }
+    }).await;
+}
+
+/// Forgets the persisted roles of users who departed before the given time across all guilds.
+pub async fn prune_departed(ctx: &Context, before: i64) -> usize {
+    let state = store::<StateKey>(ctx).await;
+    state.write(|state| {
+        state.guilds.values_mut().map(|guild| guild.prune_departed(before)).sum()
+    }).await
+}
+
 async fn has_guild(ctx: &Context, guild: GuildId) -> bool {
-    let data = ctx.data.read().await;
-    let state = data.get::<StateKey>().unwrap();
-    state.guilds.contains_key(&guild)
+    let state = store::<StateKey>(ctx).await;
+    let has_guild = state.read().await.guilds.contains_key(&guild);
+    has_guild
+}
+
+/// Forgets all guilds not in `guilds`, returning the ones that were removed.
+pub async fn retain_guilds(ctx: &Context, guilds: &HashSet<GuildId>) -> Vec<GuildId> {
+    let state = store::<StateKey>(ctx).await;
+    state.write(|state| {
+        let removed = state.guilds.keys().filter(|guild| !guilds.contains(guild)).copied().collect();
+        state.guilds.retain(|guild, _| guilds.contains(guild));
+        removed
+    }).await
 }
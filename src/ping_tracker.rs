@@ -0,0 +1,294 @@
+use std::collections::{HashMap, HashSet};
+
+use log::error;
+use serde::{Deserialize, Serialize};
+use serenity::builder::{CreateAllowedMentions, CreateMessage, EditRole};
+use serenity::model::prelude::*;
+use serenity::prelude::*;
+
+use crate::{audit, cases, CommandError, CommandResult, Persistent, read_only, scheduler, store};
+use crate::cases::CaseKind;
+use crate::memreport::{Introspect, Owner, Reference};
+
+pub struct StateKey;
+
+impl TypeMapKey for StateKey {
+    type Value = Persistent<State>;
+}
+
+#[derive(Serialize, Deserialize, Default, Clone, Eq, PartialEq)]
+pub struct State {
+    guilds: HashMap<GuildId, GuildState>,
+}
+
+impl Introspect for State {
+    fn entries(&self) -> Vec<(Owner, usize)> {
+        self.guilds.iter().map(|(guild, state)| (Owner::Guild(*guild), state.roles.len())).collect()
+    }
+
+    fn references(&self) -> Vec<(Owner, Reference)> {
+        self.guilds.iter().flat_map(|(guild, state)| {
+            state.roles.keys().map(move |role| (Owner::Guild(*guild), Reference::Role(*role)))
+        }).collect()
+    }
+}
+
+#[derive(Serialize, Deserialize, Default, Clone, Eq, PartialEq)]
+struct GuildState {
+    roles: HashMap<RoleId, TrackedRole>,
+    /// Roles made unmentionable for their cooldown, kept apart from the tracked roles so that a
+    /// role that stops being tracked while locked is still unlocked.
+    #[serde(default)]
+    locks: HashMap<RoleId, Lock>,
+}
+
+impl GuildState {
+    fn is_empty(&self) -> bool {
+        self.roles.is_empty() && self.locks.is_empty()
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Eq, PartialEq)]
+struct TrackedRole {
+    cooldown_secs: u64,
+    action: CooldownAction,
+    /// Unix timestamp of the last time this role was mentioned.
+    last_mention: Option<i64>,
+    mentions: u64,
+    mentioners: HashMap<UserId, u64>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Eq, PartialEq)]
+struct Lock {
+    /// Unix timestamp of when the role can be mentioned again.
+    until: i64,
+    /// Whether the role was mentionable before it was locked, which is what it's put back to.
+    was_mentionable: bool,
+}
+
+/// What to do when a tracked role is mentioned again before its cooldown has passed.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum CooldownAction {
+    /// Reply to the message with a warning.
+    Warn,
+    /// Make the role unmentionable until the cooldown has passed.
+    Lock,
+}
+
+pub async fn track_role(ctx: &Context, command: &Message, role: RoleId, cooldown: Option<(u64, CooldownAction)>) -> CommandResult<()> {
+    let guild = command.guild_id.ok_or(CommandError::NotAllowed)?;
+    set_cooldown(ctx, guild, role, cooldown).await;
+    Ok(())
+}
+
+pub async fn set_cooldown(ctx: &Context, guild: GuildId, role: RoleId, cooldown: Option<(u64, CooldownAction)>) {
+    let state = store::<StateKey>(ctx).await;
+    state.write(|state| {
+        let guild_state = state.guilds.entry(guild).or_default();
+        match cooldown {
+            Some((cooldown_secs, action)) => {
+                let tracked = guild_state.roles.entry(role).or_insert_with(|| TrackedRole {
+                    cooldown_secs,
+                    action,
+                    last_mention: None,
+                    mentions: 0,
+                    mentioners: HashMap::new(),
+                });
+                tracked.cooldown_secs = cooldown_secs;
+                tracked.action = action;
+            }
+            None => {
+                guild_state.roles.remove(&role);
+                if guild_state.is_empty() {
+                    state.guilds.remove(&guild);
+                }
+            }
+        }
+    }).await;
+}
+
+pub async fn cooldowns(ctx: &Context, guild: GuildId) -> Vec<(RoleId, u64, CooldownAction)> {
+    let state = store::<StateKey>(ctx).await;
+    let cooldowns = state.read().await.guilds.get(&guild)
+        .map(|guild| guild.roles.iter().map(|(role, tracked)| (*role, tracked.cooldown_secs, tracked.action)).collect())
+        .unwrap_or_default();
+    cooldowns
+}
+
+pub async fn is_configured(ctx: &Context, guild: GuildId) -> bool {
+    !cooldowns(ctx, guild).await.is_empty()
+}
+
+pub async fn role_stats(ctx: &Context, command: &Message, role: RoleId) -> CommandResult<()> {
+    let guild = command.guild_id.ok_or(CommandError::NotAllowed)?;
+
+    let tracked = {
+        let state = store::<StateKey>(ctx).await;
+        let state = state.read().await;
+        state.guilds.get(&guild).and_then(|guild| guild.roles.get(&role)).cloned()
+    };
+    let tracked = tracked.ok_or(CommandError::UntrackedRole)?;
+
+    let mut mentioners: Vec<(UserId, u64)> = tracked.mentioners.into_iter().collect();
+    mentioners.sort_by_key(|(_, count)| std::cmp::Reverse(*count));
+
+    let mut content = format!(
+        "<@&{}> has been mentioned {} times (cooldown: {}s, action: {:?})",
+        role.get(), tracked.mentions, tracked.cooldown_secs, tracked.action,
+    );
+    if let Some(last_mention) = tracked.last_mention {
+        content.push_str(&format!("\nLast mentioned <t:{}:R>", last_mention));
+    }
+    for (user, count) in mentioners.iter().take(10) {
+        content.push_str(&format!("\n<@{}>: {}", user.get(), count));
+    }
+
+    command.channel_id.send_message(&ctx.http, CreateMessage::new().content(content).allowed_mentions(CreateAllowedMentions::new())).await?;
+
+    Ok(())
+}
+
+pub async fn message(ctx: &Context, message: &Message) {
+    let guild = match message.guild_id {
+        Some(guild) if !message.author.bot && !message.mention_roles.is_empty() => guild,
+        _ => return,
+    };
+
+    let state = store::<StateKey>(ctx).await;
+    if !state.read().await.guilds.contains_key(&guild) {
+        return;
+    }
+
+    let now = message.timestamp.timestamp();
+
+    let violations: Vec<(RoleId, CooldownAction, i64)> = state.write(|state| {
+        let guild = match state.guilds.get_mut(&guild) {
+            Some(guild) => guild,
+            None => return Vec::new(),
+        };
+
+        let mut violations = Vec::new();
+        for role in &message.mention_roles {
+            if let Some(tracked) = guild.roles.get_mut(role) {
+                let cooldown_end = tracked.last_mention.map(|last| last + tracked.cooldown_secs as i64);
+                if let Some(cooldown_end) = cooldown_end.filter(|end| *end > now) {
+                    violations.push((*role, tracked.action, cooldown_end));
+                }
+
+                tracked.last_mention = Some(now);
+                tracked.mentions += 1;
+                *tracked.mentioners.entry(message.author.id).or_default() += 1;
+            }
+        }
+
+        violations
+    }).await;
+
+    for (role, action, cooldown_end) in violations {
+        let result = match action {
+            CooldownAction::Warn => warn_mention(ctx, message, role, cooldown_end).await,
+            CooldownAction::Lock => lock_role(ctx, guild, role, cooldown_end).await,
+        };
+        if let Err(err) = result {
+            error!("failed to apply ping cooldown for {}: {:?}", role, err);
+        }
+    }
+}
+
+async fn warn_mention(ctx: &Context, message: &Message, role: RoleId, cooldown_end: i64) -> serenity::Result<()> {
+    let content = format!(
+        "⚠️ <@&{}> was pinged recently! Please wait until <t:{}:R> before pinging it again.",
+        role.get(), cooldown_end,
+    );
+    message.channel_id.send_message(&ctx.http, CreateMessage::new()
+        .content(content)
+        .reference_message(message)
+        .allowed_mentions(CreateAllowedMentions::new())
+    ).await?;
+
+    if let Some(guild) = message.guild_id {
+        let user = message.author.id;
+        let reason = format!("Pinged <@&{}> during its cooldown", role.get());
+        let case = cases::open(ctx, guild, CaseKind::PingWarning, user, None, Some(&reason)).await;
+        audit::log(ctx, guild, format!("⚠️ Warned <@{}> (case #{}): {}", user.get(), case, reason)).await;
+    }
+
+    Ok(())
+}
+
+/// Makes the role unmentionable until `until`, or extends its lock if it's locked already. The lock
+/// is kept in the store and lifted by `unlock_due`, so that it outlasts a restart.
+async fn lock_role(ctx: &Context, guild: GuildId, role: RoleId, until: i64) -> serenity::Result<()> {
+    if read_only::blocks(ctx, guild, || format!("locked {} until {}", role, until)).await {
+        return Ok(());
+    }
+
+    let state = store::<StateKey>(ctx).await;
+    let extended = state.write(|state| {
+        match state.guilds.get_mut(&guild).and_then(|guild| guild.locks.get_mut(&role)) {
+            Some(lock) => {
+                lock.until = lock.until.max(until);
+                true
+            }
+            None => false,
+        }
+    }).await;
+    if extended {
+        return Ok(());
+    }
+
+    let was_mentionable = ctx.cache.guild(guild)
+        .and_then(|guild| guild.roles.get(&role).map(|role| role.mentionable))
+        .unwrap_or(true);
+    // the lock is stored before the role is changed, so that a restart in between can't leave it
+    // unmentionable for good
+    state.write(|state| {
+        state.guilds.entry(guild).or_default().locks.insert(role, Lock { until, was_mentionable });
+    }).await;
+
+    guild.edit_role(&ctx.http, role, EditRole::new().mentionable(false)).await?;
+
+    Ok(())
+}
+
+/// Puts back roles whose lock has run out. Locks that fail to lift are tried again on the next
+/// run, unless the role is gone.
+pub async fn unlock_due(ctx: Context) {
+    let now = scheduler::now();
+    let state = store::<StateKey>(&ctx).await;
+    let due: Vec<(GuildId, RoleId, Lock)> = state.read().await.guilds.iter()
+        .flat_map(|(guild, state)| state.locks.iter().map(move |(role, lock)| (*guild, *role, *lock)))
+        .filter(|(_, _, lock)| lock.until <= now)
+        .collect();
+
+    for (guild, role, lock) in due {
+        if lock.was_mentionable {
+            if let Err(err) = guild.edit_role(&ctx.http, role, EditRole::new().mentionable(true)).await {
+                error!("failed to unlock ping role {}: {:?}", role, err);
+                if ctx.cache.guild(guild).is_none_or(|guild| guild.roles.contains_key(&role)) {
+                    continue;
+                }
+            }
+        }
+
+        state.write(|state| {
+            if let Some(guild_state) = state.guilds.get_mut(&guild) {
+                guild_state.locks.remove(&role);
+                if guild_state.is_empty() {
+                    state.guilds.remove(&guild);
+                }
+            }
+        }).await;
+    }
+}
+
+/// Forgets all guilds not in `guilds`, returning the ones that were removed.
+pub async fn retain_guilds(ctx: &Context, guilds: &HashSet<GuildId>) -> Vec<GuildId> {
+    let state = store::<StateKey>(ctx).await;
+    state.write(|state| {
+        let removed = state.guilds.keys().filter(|guild| !guilds.contains(guild)).copied().collect();
+        state.guilds.retain(|guild, _| guilds.contains(guild));
+        removed
+    }).await
+}
@@ -0,0 +1,181 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+use serenity::model::prelude::*;
+use serenity::prelude::*;
+
+use crate::{CommandError, CommandResult, Persistent, persistent_roles, reaction_roles, require_owner, slowmode, store};
+use crate::memreport::{Introspect, Owner};
+
+pub struct ConfigKey;
+
+impl TypeMapKey for ConfigKey {
+    type Value = QuotaConfig;
+}
+
+/// Limits on how much configuration a guild may have, so that one guild can't overload a shared
+/// deployment. The defaults apply to every guild without an override.
+#[derive(Serialize, Deserialize, Default, Clone, Eq, PartialEq)]
+pub struct QuotaConfig {
+    #[serde(default)]
+    pub defaults: Limits,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Limits {
+    pub selectors: usize,
+    pub persisted_roles: usize,
+    pub schedules: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            selectors: 50,
+            persisted_roles: 100,
+            schedules: 25,
+        }
+    }
+}
+
+impl Limits {
+    fn get(&self, quota: Quota) -> usize {
+        match quota {
+            Quota::Selectors => self.selectors,
+            Quota::PersistedRoles => self.persisted_roles,
+            Quota::Schedules => self.schedules,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Eq, PartialEq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum Quota {
+    Selectors,
+    PersistedRoles,
+    Schedules,
+}
+
+impl Quota {
+    pub const ALL: [Quota; 3] = [Quota::Selectors, Quota::PersistedRoles, Quota::Schedules];
+}
+
+impl fmt::Display for Quota {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Quota::Selectors => "role selectors",
+            Quota::PersistedRoles => "persisted roles",
+            Quota::Schedules => "slowmode schedules",
+        })
+    }
+}
+
+impl FromStr for Quota {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, ()> {
+        match s {
+            "selectors" => Ok(Quota::Selectors),
+            "persisted-roles" => Ok(Quota::PersistedRoles),
+            "schedules" => Ok(Quota::Schedules),
+            _ => Err(()),
+        }
+    }
+}
+
+pub struct StateKey;
+
+impl TypeMapKey for StateKey {
+    type Value = Persistent<State>;
+}
+
+/// Per-guild limits set by the bot owner, replacing the defaults.
+#[derive(Serialize, Deserialize, Default, Clone, Eq, PartialEq)]
+pub struct State {
+    guilds: HashMap<GuildId, HashMap<Quota, usize>>,
+}
+
+impl Introspect for State {
+    fn entries(&self) -> Vec<(Owner, usize)> {
+        self.guilds.iter().map(|(guild, limits)| (Owner::Guild(*guild), limits.len())).collect()
+    }
+}
+
+pub async fn limit(ctx: &Context, guild: GuildId, quota: Quota) -> usize {
+    let state = store::<StateKey>(ctx).await;
+    let custom = state.read().await.guilds.get(&guild).and_then(|limits| limits.get(&quota).copied());
+
+    match custom {
+        Some(limit) => limit,
+        None => {
+            let data = ctx.data.read().await;
+            data.get::<ConfigKey>().map(|config| config.defaults).unwrap_or_default().get(quota)
+        }
+    }
+}
+
+async fn usage(ctx: &Context, guild: GuildId, quota: Quota) -> usize {
+    match quota {
+        Quota::Selectors => reaction_roles::guild_selector_count(ctx, guild).await,
+        Quota::PersistedRoles => persistent_roles::persisted_roles(ctx, guild).await.len(),
+        Quota::Schedules => slowmode::schedule_count(ctx, guild).await,
+    }
+}
+
+/// Fails if adding `added` more of something would take the guild past its limit.
+pub async fn check(ctx: &Context, guild: GuildId, quota: Quota, added: usize) -> CommandResult<()> {
+    check_total(ctx, guild, quota, usage(ctx, guild, quota).await + added).await
+}
+
+/// Fails if the guild would end up with more than its limit of something.
+pub async fn check_total(ctx: &Context, guild: GuildId, quota: Quota, total: usize) -> CommandResult<()> {
+    let limit = limit(ctx, guild, quota).await;
+    if total > limit {
+        return Err(CommandError::QuotaExceeded(quota, limit));
+    }
+    Ok(())
+}
+
+pub async fn show(ctx: &Context, command: &Message) -> CommandResult<()> {
+    let guild = command.guild_id.ok_or(CommandError::NotAllowed)?;
+
+    let mut lines = vec!["**Usage limits**".to_owned()];
+    for quota in Quota::ALL.iter().copied() {
+        let (usage, limit) = (usage(ctx, guild, quota).await, limit(ctx, guild, quota).await);
+        lines.push(format!("{}: {}/{}", quota, usage, limit));
+    }
+    crate::say_lines(ctx, command.channel_id, &lines).await?;
+
+    Ok(())
+}
+
+/// Overrides a guild's limit, or goes back to the default with `None`.
+pub async fn set_limit(ctx: &Context, command: &Message, guild: GuildId, quota: Quota, limit: Option<usize>) -> CommandResult<()> {
+    require_owner(ctx, &command.author).await?;
+
+    let state = store::<StateKey>(ctx).await;
+    state.write(|state| {
+        let limits = state.guilds.entry(guild).or_default();
+        match limit {
+            Some(limit) => limits.insert(quota, limit),
+            None => limits.remove(&quota),
+        };
+        if limits.is_empty() {
+            state.guilds.remove(&guild);
+        }
+    }).await;
+
+    Ok(())
+}
+
+/// Forgets all guilds not in `guilds`, returning the ones that were removed.
+pub async fn retain_guilds(ctx: &Context, guilds: &HashSet<GuildId>) -> Vec<GuildId> {
+    let state = store::<StateKey>(ctx).await;
+    state.write(|state| {
+        let removed = state.guilds.keys().filter(|guild| !guilds.contains(guild)).copied().collect();
+        state.guilds.retain(|guild, _| guilds.contains(guild));
+        removed
+    }).await
+}
@@ -0,0 +1,111 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use serde::{Deserialize, Serialize};
+use serenity::model::prelude::*;
+use serenity::prelude::*;
+
+/// How many buckets are kept before the full ones, which behave like new ones, are dropped.
+const MAX_BUCKETS: usize = 10_000;
+
+pub struct LimiterKey;
+
+impl TypeMapKey for LimiterKey {
+    type Value = Arc<Limiter>;
+}
+
+/// How often each user may use each command, to stop command spam. Commands without a limit of
+/// their own use the default.
+#[derive(Serialize, Deserialize, Default, Clone, Eq, PartialEq)]
+pub struct RateLimitConfig {
+    #[serde(default)]
+    pub default: Limit,
+    /// Limits of single commands, by their name like `add role selector`.
+    #[serde(default)]
+    pub commands: HashMap<String, Limit>,
+}
+
+impl RateLimitConfig {
+    pub fn limit(&self, command: &str) -> Limit {
+        self.commands.get(command).copied().unwrap_or(self.default)
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Limit {
+    /// How many times the command can be used in a row.
+    pub burst: u32,
+    /// How many more uses are allowed each minute after that.
+    pub per_minute: u32,
+}
+
+impl Default for Limit {
+    fn default() -> Self {
+        Limit { burst: 10, per_minute: 20 }
+    }
+}
+
+/// A bucket of uses that refills at a steady rate, up to the burst.
+#[derive(Clone, Debug)]
+pub struct TokenBucket {
+    tokens: f64,
+    /// When the bucket was last refilled, in seconds.
+    refilled_at: f64,
+}
+
+impl TokenBucket {
+    pub fn full(limit: Limit, now: f64) -> Self {
+        TokenBucket { tokens: limit.burst as f64, refilled_at: now }
+    }
+
+    /// Takes a use out of the bucket, or returns how many seconds it takes until there is one.
+    pub fn take(&mut self, limit: Limit, now: f64) -> Result<(), f64> {
+        let per_second = limit.per_minute as f64 / 60.0;
+        self.tokens = (self.tokens + (now - self.refilled_at) * per_second).min(limit.burst as f64);
+        self.refilled_at = now;
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            Ok(())
+        } else if per_second > 0.0 {
+            Err((1.0 - self.tokens) / per_second)
+        } else {
+            Err(f64::INFINITY)
+        }
+    }
+
+    fn is_full(&self, limit: Limit, now: f64) -> bool {
+        self.tokens + (now - self.refilled_at) * limit.per_minute as f64 / 60.0 >= limit.burst as f64
+    }
+}
+
+/// Keeps a bucket for each user and command.
+pub struct Limiter {
+    config: RateLimitConfig,
+    started: Instant,
+    buckets: Mutex<HashMap<(UserId, String), TokenBucket>>,
+}
+
+impl Limiter {
+    pub fn new(config: RateLimitConfig) -> Self {
+        Limiter { config, started: Instant::now(), buckets: Mutex::new(HashMap::new()) }
+    }
+
+    /// Counts a use of a command, returning how many seconds the user has to wait if they've used
+    /// it too often.
+    pub fn check(&self, user: UserId, command: &str) -> Result<(), u64> {
+        let limit = self.config.limit(command);
+        let now = self.started.elapsed().as_secs_f64();
+
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() >= MAX_BUCKETS {
+            buckets.retain(|(_, command), bucket| !bucket.is_full(self.config.limit(command), now));
+        }
+
+        buckets.entry((user, command.to_owned()))
+            .or_insert_with(|| TokenBucket::full(limit, now))
+            .take(limit, now)
+            .map_err(|wait| wait.ceil().min(u64::MAX as f64) as u64)
+    }
+}
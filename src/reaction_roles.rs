@@ -1,14 +1,38 @@
-use std::collections::HashMap;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
+use std::hash::{Hash, Hasher};
+use std::time::Instant;
 
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
+use serenity::builder::{CreateAllowedMentions, CreateAttachment, CreateEmbed, CreateMessage, EditMessage, EditRole, GetMessages};
 use serenity::model::prelude::*;
 use serenity::prelude::*;
 
-use selector::*;
+pub use archive::ArchivedSelector;
+pub use conflicts::{Conflict, find_conflicts};
+pub use selector::{Emoji, MappingChange, Selector, SelectorOption, UnmappedPolicy};
 
-use super::{CommandError, CommandResult, Persistent};
+use log::error;
 
+use super::{applications, approvals, audit, CommandError, confirm, CommandResult, dormancy, eligibility, events, latency, members, Persistent, quotas, read_only, role_caps, role_queue, scheduler, selector_bans, store};
+use super::quotas::Quota;
+use super::events::{Event, RoleSource, SelectorChange};
+use super::memreport::{Introspect, Owner, Reference};
+
+pub mod archive;
+mod conflicts;
+pub mod draft;
+pub mod expiry;
+pub mod generate;
+pub mod names;
+pub mod onboarding;
+pub mod preview;
+pub mod progress;
+pub mod seed;
 mod selector;
+pub mod sweep;
+pub mod template;
+pub mod threads;
 
 pub struct StateKey;
 
@@ -17,138 +41,780 @@ impl TypeMapKey for StateKey {
 }
 
 #[derive(Serialize, Deserialize, Default, Clone, Eq, PartialEq)]
-pub struct State(HashMap<MessageId, Selector>);
+pub struct State(#[serde(deserialize_with = "deserialize_entries")] HashMap<MessageId, SelectorEntry>);
+
+impl Introspect for State {
+    fn entries(&self) -> Vec<(Owner, usize)> {
+        self.0.values().map(|entry| (entry.channel.map(Owner::Channel).unwrap_or(Owner::Unknown), 1)).collect()
+    }
+
+    fn references(&self) -> Vec<(Owner, Reference)> {
+        self.0.values().flat_map(|entry| {
+            let owner = entry.channel.map(Owner::Channel).unwrap_or(Owner::Unknown);
+            let roles = entry.selector.iter().flat_map(|(_, roles)| roles.iter().copied().map(Reference::Role));
+            let requires = entry.selector.options().iter().flat_map(|option| option.requires.iter())
+                .chain(entry.selector.requires())
+                .copied()
+                .map(Reference::Role);
+            let approval = entry.selector.approval_channel().map(Reference::Channel);
+            let log = entry.log_channel.map(Reference::Channel);
+            entry.channel.map(Reference::Channel).into_iter().chain(approval).chain(log).chain(roles).chain(requires).map(move |reference| (owner, reference)).collect::<Vec<_>>()
+        }).collect()
+    }
+}
+
+/// A registered selector along with what we know about the message it lives on.
+#[derive(Serialize, Deserialize, Clone, Eq, PartialEq)]
+pub struct SelectorEntry {
+    /// The channel of the selector message, unknown for selectors registered before it was tracked.
+    #[serde(default)]
+    pub channel: Option<ChannelId>,
+    pub selector: Selector,
+    /// Hash of the message content that the selector was last parsed from and applied for.
+    #[serde(default)]
+    pub last_applied_hash: Option<u64>,
+    /// The user who registered the selector.
+    #[serde(default)]
+    pub registered_by: Option<UserId>,
+    #[serde(default)]
+    pub registered_at: Option<i64>,
+    /// The user who last edited the selector message, changing its mapping.
+    #[serde(default)]
+    pub last_edited_by: Option<UserId>,
+    #[serde(default)]
+    pub last_edited_at: Option<i64>,
+    /// When set, the selector gets reposted to the bottom of its channel to keep it visible.
+    #[serde(default)]
+    pub refresh: Option<RefreshPolicy>,
+    /// The message content that the selector was last parsed from, kept so it can be restored.
+    #[serde(default)]
+    pub content: Option<String>,
+    /// When set, our own selector messages are posted with a preview of the role colours.
+    #[serde(default)]
+    pub preview: bool,
+    /// When set, roles given and taken by the selector are logged here, along with changes to the
+    /// selector that would otherwise go to the guild's log channel.
+    #[serde(default)]
+    pub log_channel: Option<ChannelId>,
+    /// For our own selector messages, content with placeholders like `{count:@role}` that the message
+    /// is kept rendered from.
+    #[serde(default)]
+    pub template: Option<String>,
+    /// Set while the bot is missing these permissions in the selector's channel, as last found by
+    /// maintenance, so that it can't keep the selector's reactions in line with the message.
+    #[serde(default)]
+    pub missing_permissions: Option<Permissions>,
+}
+
+#[derive(Serialize, Deserialize, Default, Clone, Eq, PartialEq)]
+pub struct RefreshPolicy {
+    /// Repost after this many minutes have passed since the selector was last posted.
+    pub interval_mins: Option<u64>,
+    /// Repost once this many messages have been sent after the selector.
+    pub depth: Option<u64>,
+    /// Unix timestamp of when the selector was last (re)posted.
+    pub last_posted: i64,
+}
+
+/// A change to one option of a selector requested through a command.
+pub enum OptionUpdate {
+    Sticky(bool),
+    AddOnly(bool),
+    Description(Option<String>),
+    ExpiresAfter(Option<u64>),
+}
+
+/// A change to a selector's refresh policy requested through a command.
+pub enum RefreshUpdate {
+    Interval(u64),
+    Depth(u64),
+    Off,
+}
+
+impl SelectorEntry {
+    pub fn new(channel: ChannelId, selector: Selector, content: &str) -> Self {
+        SelectorEntry {
+            channel: Some(channel),
+            selector,
+            last_applied_hash: Some(content_hash(content)),
+            registered_by: None,
+            registered_at: None,
+            last_edited_by: None,
+            last_edited_at: None,
+            refresh: None,
+            content: Some(content.to_owned()),
+            preview: false,
+            log_channel: None,
+            template: None,
+            missing_permissions: None,
+        }
+    }
+
+    /// The selector message content, rebuilt from the mapping if it was registered before content was stored.
+    pub fn render_content(&self) -> String {
+        match &self.content {
+            Some(content) => content.clone(),
+            None => self.selector.iter()
+                .map(|(emoji, roles)| {
+                    let mentions: Vec<String> = roles.iter().map(|role| format!("<@&{}>", role.get())).collect();
+                    format!("{} {}", emoji, mentions.join(" "))
+                })
+                .collect::<Vec<_>>()
+                .join("\n"),
+        }
+    }
+}
+
+/// Hashes selector message content so that edits which don't change it can be skipped.
+///
+/// The hash is only stable for a given build, but a mismatch merely causes one redundant update.
+fn content_hash(content: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    content.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Selectors used to be stored directly by message, without any entry metadata.
+fn deserialize_entries<'de, D>(deserializer: D) -> Result<HashMap<MessageId, SelectorEntry>, D::Error>
+    where D: Deserializer<'de>
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum StoredEntry {
+        Entry(Box<SelectorEntry>),
+        Bare(selector::StoredSelector),
+    }
+
+    let stored: HashMap<MessageId, StoredEntry> = HashMap::deserialize(deserializer)?;
+    Ok(stored.into_iter()
+        .map(|(message, entry)| match entry {
+            StoredEntry::Entry(entry) => (message, *entry),
+            StoredEntry::Bare(selector) => (message, SelectorEntry {
+                channel: None,
+                selector: selector.into(),
+                last_applied_hash: None,
+                registered_by: None,
+                registered_at: None,
+                last_edited_by: None,
+                last_edited_at: None,
+                refresh: None,
+                content: None,
+                preview: false,
+                log_channel: None,
+                template: None,
+                missing_permissions: None,
+            }),
+        })
+        .collect())
+}
 
 impl State {
     #[inline]
-    pub fn insert_selector(&mut self, message: MessageId, selector: Selector) {
-        self.0.insert(message, selector);
+    pub fn insert_selector(&mut self, message: MessageId, entry: SelectorEntry) {
+        self.0.insert(message, entry);
     }
 
     #[inline]
-    pub fn remove_selector(&mut self, message: MessageId) -> Option<Selector> {
+    pub fn remove_selector(&mut self, message: MessageId) -> Option<SelectorEntry> {
         self.0.remove(&message)
     }
 
     #[inline]
-    pub fn selector(&self, message: MessageId) -> Option<&Selector> {
+    pub fn entry(&self, message: MessageId) -> Option<&SelectorEntry> {
         self.0.get(&message)
     }
 
+    #[inline]
+    pub fn entry_mut(&mut self, message: MessageId) -> Option<&mut SelectorEntry> {
+        self.0.get_mut(&message)
+    }
+
+    #[inline]
+    pub fn selector(&self, message: MessageId) -> Option<&Selector> {
+        self.0.get(&message).map(|entry| &entry.selector)
+    }
+
+    #[inline]
+    pub fn selector_mut(&mut self, message: MessageId) -> Option<&mut Selector> {
+        self.0.get_mut(&message).map(|entry| &mut entry.selector)
+    }
+
     #[inline]
     pub fn is_selector(&self, message: MessageId) -> bool {
         self.0.contains_key(&message)
     }
+
+    #[inline]
+    pub fn entries(&self) -> impl Iterator<Item=(MessageId, &SelectorEntry)> {
+        self.0.iter().map(|(message, entry)| (*message, entry))
+    }
 }
 
 pub async fn add_reaction(ctx: Context, reaction: Reaction) -> serenity::Result<()> {
+    let received = Instant::now();
     let (guild, user) = match (reaction.guild_id, reaction.user_id) {
         (Some(guild), Some(user)) => (guild, user),
         _ => return Ok(()),
     };
 
-    let data = ctx.data.read().await;
-    let messages = data.get::<StateKey>().unwrap();
+    let emoji = reaction.emoji.into();
+    handle_reaction(&ctx, guild, user, reaction.channel_id, reaction.message_id, &emoji, Some(received)).await
+}
+
+/// Handles a member reacting to a selector, whether the reaction just arrived or was found by a
+/// sweep after its event was missed. Only reactions that just arrived have a `received` time to
+/// measure latency from.
+async fn handle_reaction(ctx: &Context, guild: GuildId, user: UserId, channel: ChannelId, message: MessageId, emoji: &Emoji, received: Option<Instant>) -> serenity::Result<()> {
+    let reaction_type: ReactionType = emoji.clone().into();
+    let delete_reaction = || ctx.http.delete_reaction(channel, message, user, &reaction_type);
 
-    if let Some(selector) = messages.selector(reaction.message_id) {
-        let emoji = reaction.emoji.clone().into();
-        match selector.get_role(&emoji) {
-            Some(role) => {
-                let mut member: Member = guild.member(&ctx, user).await?;
-                if !member.user.bot {
-                    member.add_role(&ctx.http, role).await?;
+    if let Some(selector) = get_enabled_selector(ctx, message).await {
+        dormancy::record_activity(ctx, guild).await;
+        if selector_bans::is_banned(ctx, guild, user).await {
+            remove_own_reaction(ctx, channel, message, user, emoji).await;
+            return Ok(());
+        }
+
+        match selector.get_roles(emoji) {
+            Some(_) => {
+                let mut member = members::member(ctx, guild, user).await?;
+                let looked_up = received.map(|received| received.elapsed());
+                if member.user.bot {
+                    return Ok(());
+                }
+
+                if let Some(eligible_at) = eligibility::eligible_at(ctx, &member).await {
+                    remove_own_reaction(ctx, channel, message, user, emoji).await;
+                    eligibility::defer(ctx, &member, channel, message, emoji.clone(), eligible_at).await;
+                    return Ok(());
+                }
+
+                let outcome = choose(ctx, &selector, &mut member, channel, message, emoji).await?;
+                if let (Outcome::Granted, Some(received), Some(looked_up)) = (&outcome, received, looked_up) {
+                    latency::record(guild, looked_up, received.elapsed());
+                }
+
+                match outcome {
+                    Outcome::Full(full_role, limit) => {
+                        delete_reaction().await?;
+                        explain_full_role(ctx, guild, &member.user, full_role, limit).await;
+                    }
+                    Outcome::Unqualified(missing) => {
+                        delete_reaction().await?;
+                        explain_missing_requirements(ctx, guild, &member.user, &missing).await;
+                    }
+                    Outcome::Declined => delete_reaction().await?,
+                    Outcome::Granted if selector.option(emoji).is_some_and(|option| option.add_only) => {
+                        remove_own_reaction(ctx, channel, message, user, emoji).await;
+                    }
+                    Outcome::Granted | Outcome::Requested => {}
+                }
+            }
+            None => {
+                if selector.should_remove_unmapped(emoji) {
+                    delete_reaction().await?;
                 }
             }
-            None => reaction.delete(&ctx.http).await?,
         }
     }
 
     Ok(())
 }
 
+/// What came of a member choosing an emoji on a selector.
+enum Outcome {
+    Granted,
+    /// The roles need approval, which has been requested.
+    Requested,
+    /// The member would go over the cap of a role, with the given limit.
+    Full(RoleId, usize),
+    /// The member declined switching away from the roles of another emoji on an exclusive selector.
+    Declined,
+    /// The member doesn't hold the given roles, which the selector or emoji requires.
+    Unqualified(Vec<RoleId>),
+}
+
+/// Gives a member the roles of an emoji on a selector, or requests them if they need approval.
+async fn choose(ctx: &Context, selector: &Selector, member: &mut Member, channel: ChannelId, message: MessageId, emoji: &Emoji) -> serenity::Result<Outcome> {
+    let (guild, user) = (member.guild_id, member.user.id);
+    let roles = selector.get_roles(emoji).unwrap_or_default();
+
+    let missing = selector.missing_requirements(emoji, &member.roles);
+    if !missing.is_empty() {
+        return Ok(Outcome::Unqualified(missing));
+    }
+
+    if let Err((full_role, limit)) = role_caps::try_reserve(ctx, guild, user, roles).await {
+        return Ok(Outcome::Full(full_role, limit));
+    }
+
+    if let Some(staff_channel) = selector.approval_channel() {
+        let origin = approvals::Origin::Selector { channel, message, emoji: emoji.clone() };
+        approvals::request_selector_roles(ctx, guild, user, staff_channel, roles, origin).await?;
+        return Ok(Outcome::Requested);
+    }
+
+    if selector.excludes_any(emoji) && !switch_roles(ctx, channel, message, selector, member, emoji).await? {
+        role_caps::release(ctx, guild, user, roles).await;
+        return Ok(Outcome::Declined);
+    }
+
+    role_queue::add_roles(ctx, guild, user, roles).await?;
+    events::publish(ctx, Event::RolesGranted { guild, user, roles: roles.to_vec(), source: RoleSource::Selector }).await;
+    log_activity(ctx, guild, channel, message, format!("➕ <@{}> got {}", user.get(), role_mentions(roles))).await;
+
+    if let Some(after) = selector.option(emoji).and_then(|option| option.expires_after) {
+        let expires_at = scheduler::now() + after as i64;
+        let grant = expiry::ExpiringGrant { guild, user, channel, message, emoji: emoji.clone(), roles: roles.to_vec(), expires_at };
+        expiry::schedule(ctx, grant).await;
+    }
+
+    Ok(Outcome::Granted)
+}
+
+/// Grants the roles of a selector reaction that was held back until the member became eligible.
+pub async fn grant_deferred(ctx: &Context, guild: GuildId, user: UserId, channel: ChannelId, message: MessageId, emoji: Emoji) {
+    let selector = match get_enabled_selector(ctx, message).await {
+        Some(selector) if selector.contains(&emoji) => selector,
+        _ => return,
+    };
+
+    // members who left in the meantime don't get anything
+    let mut member = match members::member(ctx, guild, user).await {
+        Ok(member) => member,
+        Err(_) => return,
+    };
+
+    let roles = selector.get_roles(&emoji).unwrap_or_default();
+    let content = match choose(ctx, &selector, &mut member, channel, message, &emoji).await {
+        Ok(Outcome::Granted) => format!("✅ You can now get roles in that server, so I gave you **{}**.", role_names(ctx, guild, roles).await),
+        Ok(Outcome::Full(full_role, limit)) => {
+            explain_full_role(ctx, guild, &member.user, full_role, limit).await;
+            return;
+        }
+        Ok(Outcome::Unqualified(missing)) => {
+            explain_missing_requirements(ctx, guild, &member.user, &missing).await;
+            return;
+        }
+        Ok(Outcome::Requested) | Ok(Outcome::Declined) => return,
+        Err(err) => {
+            error!("failed to grant deferred roles to {} in {}: {:?}", user, guild, err);
+            return;
+        }
+    };
+    let _ = member.user.direct_message(&ctx.http, CreateMessage::new().content(content)).await;
+}
+
 pub async fn remove_reaction(ctx: &Context, reaction: Reaction) -> serenity::Result<()> {
     let (guild, user) = match (reaction.guild_id, reaction.user_id) {
         (Some(guild), Some(user)) => (guild, user),
         _ => return Ok(()),
     };
 
-    let data = ctx.data.read().await;
-    let messages = data.get::<StateKey>().unwrap();
+    if let Some(selector) = get_enabled_selector(ctx, reaction.message_id).await {
+        dormancy::record_activity(ctx, guild).await;
+        let emoji: Emoji = reaction.emoji.clone().into();
+        if take_removed_reaction(reaction.message_id, user, &emoji) {
+            return Ok(());
+        }
+
+        if let Some(option) = selector.option(&emoji) {
+            let roles = option.roles.as_slice();
+            if selector.approval_channel().is_some() {
+                let origin = approvals::Origin::Selector { channel: reaction.channel_id, message: reaction.message_id, emoji: emoji.clone() };
+                approvals::cancel_request(ctx, user, &origin).await;
+            }
+
+            if option.sticky || option.add_only {
+                return Ok(());
+            }
+
+            expiry::cancel(ctx, user, reaction.message_id, &emoji).await;
+            role_caps::release(ctx, guild, user, roles).await;
 
-    if let Some(selector) = messages.selector(reaction.message_id) {
-        let emoji = reaction.emoji.clone().into();
-        if let Some(role) = selector.get_role(&emoji) {
-            let mut member: Member = guild.member(ctx, user).await?;
-            member.remove_role(&ctx.http, role).await?;
+            role_queue::remove_roles(ctx, guild, user, roles).await?;
+            events::publish(ctx, Event::RolesRevoked { guild, user, roles: roles.to_vec(), source: RoleSource::Selector }).await;
+            log_activity(ctx, guild, reaction.channel_id, reaction.message_id, format!("➖ <@{}> gave up {}", user.get(), role_mentions(roles))).await;
         }
     }
 
     Ok(())
 }
 
-async fn is_message_selector(ctx: &Context, message: MessageId) -> bool {
-    let data = ctx.data.read().await;
-    let messages = data.get::<StateKey>().unwrap();
+/// Takes away the roles of a selector option once they have expired, along with the member's
+/// reaction so that they can choose it again.
+async fn revoke_expired(ctx: &Context, grant: expiry::ExpiringGrant) {
+    let expiry::ExpiringGrant { guild, user, channel, message, emoji, roles, .. } = grant;
+
+    role_caps::release(ctx, guild, user, &roles).await;
+    if let Err(err) = role_queue::remove_roles(ctx, guild, user, &roles).await {
+        error!("failed to remove expired roles from {} in {}: {:?}", user, guild, err);
+        return;
+    }
+    log_activity(ctx, guild, channel, message, format!("⌛ {} expired for <@{}>", role_mentions(&roles), user.get())).await;
+    events::publish(ctx, Event::RolesRevoked { guild, user, roles, source: RoleSource::Selector }).await;
+
+    remove_own_reaction(ctx, channel, message, user, &emoji).await;
+}
+
+/// Reactions we removed ourselves whose roles the member doesn't hold, like when switching a member
+/// between the emoji of an exclusive selector, so removing them shouldn't take any roles away.
+static REMOVED_REACTIONS: std::sync::Mutex<Vec<(MessageId, UserId, Emoji)>> = std::sync::Mutex::new(Vec::new());
+
+fn take_removed_reaction(message: MessageId, user: UserId, emoji: &Emoji) -> bool {
+    let mut removed = REMOVED_REACTIONS.lock().unwrap();
+    match removed.iter().position(|(m, u, e)| *m == message && *u == user && e == emoji) {
+        Some(index) => {
+            removed.swap_remove(index);
+            true
+        }
+        None => false,
+    }
+}
+
+/// Removes a member's reaction without taking away the roles of its emoji.
+async fn remove_own_reaction(ctx: &Context, channel: ChannelId, message: MessageId, user: UserId, emoji: &Emoji) {
+    REMOVED_REACTIONS.lock().unwrap().push((message, user, emoji.clone()));
+    let reaction_type = emoji.clone().into();
+    if ctx.http.delete_reaction(channel, message, user, &reaction_type).await.is_err() {
+        take_removed_reaction(message, user, emoji);
+    }
+}
+
+/// Whether `user` reacted to a message with `emoji`. Reaction users are listed by ID, so asking for
+/// the first user after the one before them finds them with a single request.
+async fn has_reacted(ctx: &Context, channel: ChannelId, message: MessageId, user: UserId, emoji: &Emoji) -> serenity::Result<bool> {
+    let users = ctx.http.get_reaction_users(channel, message, &emoji.clone().into(), 1, Some(user.get() - 1)).await?;
+    Ok(users.first().is_some_and(|first| first.id == user))
+}
+
+/// Brings the invoker's reactions on every selector in line with the roles they actually hold,
+/// removing reactions for roles they no longer have. Reactions can't be added on their behalf, so
+/// roles they hold without having reacted are listed instead.
+pub async fn sync_member(ctx: &Context, command: &Message) -> CommandResult<()> {
+    let guild = command.guild_id.ok_or(CommandError::NotAllowed)?;
+    let user = command.author.id;
+    let member = members::member(ctx, guild, user).await?;
+    // reactions that are waiting for the member to become eligible aren't stale
+    let eligible = eligibility::eligible_at(ctx, &member).await.is_none();
+
+    let channels = ctx.cache.guild(guild).map(|guild| guild.channels.clone()).unwrap_or_default();
+    let mut removed = 0;
+    let mut unreacted = Vec::new();
+
+    for (message, entry) in selectors_in(ctx, &channels).await {
+        let channel = match entry.channel {
+            Some(channel) => channel,
+            None => continue,
+        };
+        // with approval, roles arrive later than the reaction
+        if !entry.selector.is_enabled() || entry.selector.approval_channel().is_some() {
+            continue;
+        }
+
+        for (emoji, roles) in entry.selector.iter() {
+            let holds = !roles.is_empty() && roles.iter().all(|role| member.roles.contains(role));
+            let reacted = has_reacted(ctx, channel, message, user, emoji).await?;
+            if reacted && !holds && eligible {
+                remove_own_reaction(ctx, channel, message, user, emoji).await;
+                removed += 1;
+            } else if !reacted && holds {
+                unreacted.push(format!("{} on {}", emoji, audit::message_link(guild, channel, message)));
+            }
+        }
+    }
+
+    let mut lines = Vec::new();
+    if removed > 0 {
+        lines.push(format!("🔄 Removed {} of your reactions for roles you no longer have.", removed));
+    }
+    if !unreacted.is_empty() {
+        lines.push("You have the roles of these selector options without having reacted, so react to them if you'd like to be able to remove the roles again:".to_owned());
+        lines.extend(unreacted);
+    }
+    if lines.is_empty() {
+        lines.push("🔄 Your selector reactions already match your roles.".to_owned());
+    }
+    crate::say_lines(ctx, command.channel_id, &lines).await?;
+
+    Ok(())
+}
+
+/// Lists the roles anyone can get in the guild through selectors and applications, marking the ones
+/// the invoker already has.
+pub async fn list_obtainable(ctx: &Context, command: &Message) -> CommandResult<()> {
+    let guild = command.guild_id.ok_or(CommandError::NotAllowed)?;
+    let member = members::member(ctx, guild, command.author.id).await?;
+    let held = |role: &RoleId| member.roles.contains(role);
+    let mention = |role: &RoleId| format!("<@&{}>{}", role.get(), if held(role) { " ✅" } else { "" });
+
+    let channels = ctx.cache.guild(guild).map(|guild| guild.channels.clone()).unwrap_or_default();
+    let mut selectors = selectors_in(ctx, &channels).await;
+    selectors.sort_by_key(|(message, _)| *message);
+
+    let mut lines = Vec::new();
+    for (message, entry) in &selectors {
+        let channel = match entry.channel {
+            Some(channel) if entry.selector.is_enabled() => channel,
+            _ => continue,
+        };
+
+        let approval = if entry.selector.approval_channel().is_some() { " (needs approval)" } else { "" };
+        lines.push(format!("**{}**{}", audit::message_link(guild, channel, *message), approval));
+        for option in entry.selector.options() {
+            let roles: Vec<String> = option.roles.iter().map(mention).collect();
+            let mut line = format!("{} {}", option.emoji, roles.join(" "));
+            if let Some(description) = &option.description {
+                line.push_str(&format!(" — {}", description));
+            }
+            lines.push(line);
+        }
+    }
+
+    let applications = applications::roles(ctx, guild).await;
+    if !applications.is_empty() {
+        lines.push("**Apply with `apply for <role>`**".to_owned());
+        lines.extend(applications.iter().map(mention));
+    }
+
+    if lines.is_empty() {
+        command.reply(ctx, "There are no roles you can get yourself in this server.").await?;
+    } else {
+        crate::say_lines(ctx, command.channel_id, &lines).await?;
+    }
+
+    Ok(())
+}
+
+/// Takes away the roles a member holds from the emoji that `emoji` excludes, on an exclusive selector
+/// or in its group, asking them first if the selector wants that. Returns whether the switch should
+/// go ahead.
+async fn switch_roles(ctx: &Context, channel: ChannelId, message: MessageId, selector: &Selector, member: &mut Member, emoji: &Emoji) -> serenity::Result<bool> {
+    let new_roles = selector.get_roles(emoji).unwrap_or_default();
+    let previous: Vec<(&Emoji, Vec<RoleId>)> = selector.iter()
+        .filter(|(other, _)| selector.excludes(emoji, other))
+        .map(|(other, roles)| {
+            let held = roles.iter()
+                .filter(|role| member.roles.contains(role) && !new_roles.contains(role))
+                .copied()
+                .collect::<Vec<_>>();
+            (other, held)
+        })
+        .filter(|(_, held)| !held.is_empty())
+        .collect();
+
+    let stripped: Vec<RoleId> = previous.iter().flat_map(|(_, roles)| roles.iter().copied()).collect();
+    if stripped.is_empty() {
+        return Ok(true);
+    }
+
+    if selector.confirms_switch() {
+        let guild = member.guild_id;
+        let prompt = format!(
+            "Switch from **{}** to **{}**?",
+            role_names(ctx, guild, &stripped).await, role_names(ctx, guild, new_roles).await,
+        );
 
-    messages.is_selector(message)
+        // members who don't accept DMs can't be asked, so they keep their roles as if they declined
+        let answer = match member.user.create_dm_channel(&ctx.http).await {
+            Ok(dm) => confirm::ask(ctx, dm.id, member.user.id, prompt).await.unwrap_or(false),
+            Err(_) => false,
+        };
+        if !answer {
+            return Ok(false);
+        }
+    }
+
+    role_queue::remove_roles(ctx, member.guild_id, member.user.id, &stripped).await?;
+    log_activity(ctx, member.guild_id, channel, message, format!("🔀 <@{}> switched away from {}", member.user.id.get(), role_mentions(&stripped))).await;
+    events::publish(ctx, Event::RolesRevoked { guild: member.guild_id, user: member.user.id, roles: stripped, source: RoleSource::Selector }).await;
+
+    for (other, _) in previous {
+        remove_own_reaction(ctx, channel, message, member.user.id, other).await;
+    }
+
+    Ok(true)
+}
+
+async fn role_names(ctx: &Context, guild: GuildId, roles: &[RoleId]) -> String {
+    let mut names = Vec::new();
+    for role in roles {
+        let name = ctx.cache.guild(guild).and_then(|guild| guild.roles.get(role).cloned()).map(|role| role.name);
+        names.push(name.unwrap_or_else(|| "a deleted role".to_owned()));
+    }
+    names.join(", ")
 }
 
-pub async fn delete_message(ctx: Context, message: MessageId) {
+async fn explain_full_role(ctx: &Context, guild: GuildId, user: &User, role: RoleId, limit: usize) {
+    let role_name = ctx.cache.guild(guild).and_then(|guild| guild.roles.get(&role).cloned())
+        .map(|role| role.name)
+        .unwrap_or_else(|| "That role".to_owned());
+    let content = format!("🚫 **{}** is full: only {} members can hold it at a time. Try again once someone gives it up!", role_name, limit);
+    let _ = user.direct_message(&ctx.http, CreateMessage::new().content(content)).await;
+}
+
+fn role_mentions(roles: &[RoleId]) -> String {
+    roles.iter().map(|role| format!("<@&{}>", role.get())).collect::<Vec<_>>().join(" ")
+}
+
+async fn selector_log_channel(ctx: &Context, message: MessageId) -> Option<ChannelId> {
+    let messages = store::<StateKey>(ctx).await;
+    let channel = messages.read().await.entry(message).and_then(|entry| entry.log_channel);
+    channel
+}
+
+/// Logs roles given or taken by a selector, if it has a log channel of its own.
+async fn log_activity(ctx: &Context, guild: GuildId, channel: ChannelId, message: MessageId, content: String) {
+    if let Some(log_channel) = selector_log_channel(ctx, message).await {
+        let link = audit::message_link(guild, channel, message);
+        audit::post(ctx, guild, log_channel, format!("{} through {}", content, link)).await;
+    }
+}
+
+async fn explain_missing_requirements(ctx: &Context, guild: GuildId, user: &User, missing: &[RoleId]) {
+    let content = format!("🔒 You need **{}** before you can get that role. Once you have it, react again!", role_names(ctx, guild, missing).await);
+    let _ = user.direct_message(&ctx.http, CreateMessage::new().content(content)).await;
+}
+
+async fn get_selector(ctx: &Context, message: MessageId) -> Option<Selector> {
+    let messages = store::<StateKey>(ctx).await;
+    let selector = messages.read().await.selector(message).cloned();
+    selector
+}
+
+/// Returns the selector registered for this message, unless it is paused and should be ignored.
+async fn get_enabled_selector(ctx: &Context, message: MessageId) -> Option<Selector> {
+    get_selector(ctx, message).await.filter(|selector| selector.is_enabled())
+}
+
+async fn selector_channel(ctx: &Context, message: MessageId) -> Option<ChannelId> {
+    let messages = store::<StateKey>(ctx).await;
+    let channel = messages.read().await.entry(message).and_then(|entry| entry.channel);
+    channel
+}
+
+async fn is_message_selector(ctx: &Context, message: MessageId) -> bool {
+    let messages = store::<StateKey>(ctx).await;
+    let is_selector = messages.read().await.is_selector(message);
+    is_selector
+}
+
+pub async fn delete_message(ctx: Context, guild: Option<GuildId>, channel: ChannelId, message: MessageId) {
     if !is_message_selector(&ctx, message).await {
         return;
     }
 
-    let mut data = ctx.data.write().await;
-    let messages = data.get_mut::<StateKey>().unwrap();
+    let messages = store::<StateKey>(&ctx).await;
+    let entry = messages.write(|messages| messages.remove_selector(message)).await;
+    names::forget(&ctx, message).await;
 
-    messages.write(|messages| {
-        messages.remove_selector(message);
-    }).await;
+    if let (Some(guild), Some(entry)) = (guild, entry) {
+        let log_channel = entry.log_channel;
+        archive::archive(&ctx, guild, message, entry, archive::Removal::MessageDeleted).await;
+        events::publish(&ctx, Event::SelectorChanged { guild, channel, message, change: SelectorChange::Deleted }).await;
+        audit::log_to(&ctx, guild, log_channel, format!(
+            "Selector {} was deleted. Restore it with `undo` or `restore role selector {}`",
+            audit::message_link(guild, channel, message), message.get(),
+        )).await;
+    }
 }
 
-pub async fn update_message(mut ctx: Context, channel: ChannelId, message: MessageId, content: Option<String>) {
-    if let Some(content) = content {
+pub async fn update_message(ctx: Context, event: MessageUpdateEvent) {
+    let (channel, message) = (event.channel_id, event.id);
+    if let Some(content) = event.content {
         if !is_message_selector(&ctx, message).await {
             return;
         }
 
-        {
-            let mut data = ctx.data.write().await;
-            let messages = data.get_mut::<StateKey>().unwrap();
+        let hash = content_hash(&content);
+        let editor = event.author.map(|author| author.id);
+        let now = scheduler::now();
+        let names = roles_by_name(&ctx, event.guild_id, &content).await;
 
-            messages.write(|messages| {
-                messages.insert_selector(message, Selector::parse(&content));
-            }).await;
-        }
+        let messages = store::<StateKey>(&ctx).await;
+        let changed = messages.write(|messages| {
+            match messages.entry_mut(message) {
+                Some(entry) if entry.last_applied_hash != Some(hash) || entry.channel != Some(channel) => {
+                    entry.selector.reparse_with(&content, |name| names.get(&name.to_lowercase()).copied());
+                    entry.content = Some(content.clone());
+                    entry.channel = Some(channel);
+                    entry.last_applied_hash = Some(hash);
+                    if editor.is_some() {
+                        entry.last_edited_by = editor;
+                        entry.last_edited_at = Some(now);
+                    }
+                    true
+                }
+                _ => false,
+            }
+        }).await;
+
+        // edits that didn't touch the content (e.g. embeds resolving) don't need reconciling
+        if changed {
+            apply_selector_reactions(&ctx, channel, message).await;
+
+            if let Some(guild) = event.guild_id {
+                events::publish(&ctx, Event::SelectorChanged { guild, channel, message, change: SelectorChange::Edited }).await;
+            }
 
-        apply_selector_reactions(&mut ctx, channel, message).await;
+            if let (Some(guild), Some(editor)) = (event.guild_id, editor) {
+                let link = audit::message_link(guild, channel, message);
+                let log_channel = selector_log_channel(&ctx, message).await;
+                audit::log_to(&ctx, guild, log_channel, format!("<@{}> edited selector {}", editor.get(), link)).await;
+            }
+        }
     }
 }
 
 async fn apply_selector_reactions(ctx: &Context, channel: ChannelId, message: MessageId) {
-    let data = ctx.data.read().await;
-    let messages = data.get::<StateKey>().unwrap();
+    let guild = crate::cached_channel(&ctx.cache, channel).map(|channel| channel.guild_id);
+    if read_only::blocks(ctx, guild, || format!("updated the reactions of selector {}", message)).await {
+        return;
+    }
 
-    if let Some(selector) = messages.selector(message) {
+    if let Some(selector) = get_enabled_selector(ctx, message).await {
         if let Ok(target_message) = channel.message(&ctx.http, message).await {
-            let current_user = ctx.cache.current_user_id().await;
+            let current_user = ctx.cache.current_user().id;
 
             let own_reactions: Vec<selector::Emoji> = target_message.reactions.iter()
                 .filter(|reaction| reaction.me)
                 .map(|reaction| selector::Emoji::from(reaction.reaction_type.clone()))
                 .collect();
 
+            // reactions are shown in the order they were first added, so the ones out of line with
+            // the message are taken back and added again. That only moves reactions nobody else
+            // has added yet.
+            let present: Vec<(selector::Emoji, bool)> = target_message.reactions.iter()
+                .map(|reaction| (selector::Emoji::from(reaction.reaction_type.clone()), reaction.me && reaction.count == 1))
+                .filter(|(emoji, _)| selector.contains(emoji))
+                .collect();
+            let in_order = present.iter().zip(selector.iter())
+                .take_while(|((present, _), (emoji, _))| present == *emoji)
+                .count();
+            let movable: Vec<&selector::Emoji> = present[in_order..].iter()
+                .filter(|(_, only_ours)| *only_ours)
+                .map(|(emoji, _)| emoji)
+                .collect();
+
             for reaction in &own_reactions {
-                if !selector.contains(reaction) {
+                if !selector.contains(reaction) || movable.contains(&reaction) {
                     let reaction_type = reaction.clone().into();
-                    let _ = ctx.http.delete_reaction(channel.0, message.0, Some(current_user.0), &reaction_type).await;
+                    if let Err(err) = ctx.http.delete_reaction(channel, message, current_user, &reaction_type).await {
+                        error!("failed to remove the {} reaction from selector {}: {:?}", reaction, message, err);
+                    }
                 }
             }
 
-            for (emoji, _) in selector.iter() {
-                if !own_reactions.contains(emoji) {
-                    let _ = target_message.react(ctx, emoji.clone()).await;
-                }
+            let missing: Vec<Emoji> = selector.iter()
+                .filter(|(emoji, _)| !own_reactions.contains(emoji) || movable.contains(emoji))
+                .map(|(emoji, _)| emoji.clone())
+                .collect();
+            let failures = seed::seed(ctx, &target_message, &missing).await;
+
+            if let (Some(guild), Some(summary)) = (guild, seed::summary(missing.len(), &failures)) {
+                let link = audit::message_link(guild, channel, message);
+                let log_channel = selector_log_channel(ctx, message).await;
+                audit::log_to(ctx, guild, log_channel, format!("Selector {}:\n{}", link, summary)).await;
             }
         }
     }
@@ -157,20 +823,871 @@ async fn apply_selector_reactions(ctx: &Context, channel: ChannelId, message: Me
 pub async fn add_selector(ctx: &Context, command: &Message, message_id: MessageId) -> CommandResult<()> {
     command.delete(ctx).await?;
 
-    if let Ok(target_message) = command.channel_id.message(&ctx.http, message_id).await {
-        {
-            let mut data = ctx.data.write().await;
-            let messages = data.get_mut::<StateKey>().unwrap();
-            messages.write(|messages| {
-                let selector = Selector::parse(&target_message.content);
-                messages.insert_selector(message_id, selector);
-            }).await;
+    let guild = command.guild_id.ok_or(CommandError::NotAllowed)?;
+    let mut target_message = command.channel_id.message(&ctx.http, message_id).await
+        .map_err(|_| CommandError::InvalidMessageReference)?;
+    register_message(ctx, guild, command.channel_id, command.author.id, &mut target_message).await
+}
+
+/// Registers a message as a selector on behalf of `user`, or updates it if it already is one. Any
+/// questions and reports go to `channel`.
+pub async fn register_message(ctx: &Context, guild: GuildId, channel: ChannelId, user: UserId, message: &mut Message) -> CommandResult<()> {
+    // messages fetched over HTTP don't say which guild they're in
+    message.guild_id = Some(guild);
+
+    if is_message_selector(ctx, message.id).await {
+        create_missing_roles(ctx, guild, channel, user, message).await?;
+        reregister_selector(ctx, guild, channel, user, message).await?;
+    } else {
+        quotas::check(ctx, guild, Quota::Selectors, 1).await?;
+        create_missing_roles(ctx, guild, channel, user, message).await?;
+        register_selector(ctx, message, user, |_| {}).await;
+    }
+    Ok(())
+}
+
+/// Describes how a message would be read as a selector, without registering it.
+pub async fn describe_parse(ctx: &Context, guild: GuildId, content: &str) -> Vec<String> {
+    let names = roles_by_name(ctx, Some(guild), content).await;
+    let resolve = |name: &str| names.get(&name.to_lowercase()).copied();
+    let selector = Selector::parse_with(content, resolve);
+
+    let mut lines = Vec::new();
+    if selector.options().is_empty() {
+        lines.push("No line of this message pairs an emoji with a role.".to_owned());
+    } else {
+        lines.push(format!("As a selector, this message would have {} options:", selector.options().len()));
+    }
+    for option in selector.options() {
+        let mut line = format!("{} → {}", option.emoji, role_mentions(&option.roles));
+        if !option.requires.is_empty() {
+            line.push_str(&format!(" (requires {})", role_mentions(&option.requires)));
         }
+        if let Some(group) = &option.group {
+            line.push_str(&format!(" [only one of `{}`]", group));
+        }
+        lines.push(line);
+    }
+    if !selector.requires().is_empty() {
+        lines.push(format!("Every option requires {}", role_mentions(selector.requires())));
+    }
 
-        apply_selector_reactions(ctx, command.channel_id, message_id).await;
+    let unresolved = Selector::unresolved_names(content, resolve);
+    if !unresolved.is_empty() {
+        let listed: Vec<String> = unresolved.iter().map(|name| format!("`{}`", name)).collect();
+        lines.push(format!("⚠️ These names aren't roles: {}", listed.join(", ")));
+    }
+    if selector.options().len() > draft::MAX_OPTIONS {
+        lines.push(format!("⚠️ Discord only allows {} reactions on a message", draft::MAX_OPTIONS));
+    }
+    lines
+}
 
-        Ok(())
+/// Registering a message that already is a selector updates its roles from the message, keeping
+/// its settings, and lists what changed.
+async fn reregister_selector(ctx: &Context, guild: GuildId, channel: ChannelId, user: UserId, message: &Message) -> CommandResult<()> {
+    let now = scheduler::now();
+    let names = roles_by_name(ctx, message.guild_id, &message.content).await;
+
+    let messages = store::<StateKey>(ctx).await;
+    let changes = messages.write(|messages| {
+        let entry = messages.entry_mut(message.id)?;
+        let previous = entry.selector.clone();
+        entry.selector.reparse_with(&message.content, |name| names.get(&name.to_lowercase()).copied());
+        entry.content = Some(message.content.clone());
+        entry.channel = Some(message.channel_id);
+        entry.last_applied_hash = Some(content_hash(&message.content));
+        let changes = previous.diff(&entry.selector);
+        if !changes.is_empty() {
+            entry.last_edited_by = Some(user);
+            entry.last_edited_at = Some(now);
+        }
+        Some(changes)
+    }).await.ok_or(CommandError::UnknownSelector)?;
+
+    apply_selector_reactions(ctx, message.channel_id, message.id).await;
+
+    let link = audit::message_link(guild, message.channel_id, message.id);
+    if changes.is_empty() {
+        let content = format!("🔁 {} is already a selector, and its roles haven't changed.", link);
+        crate::say_lines(ctx, channel, &[content]).await?;
+        return Ok(());
+    }
+
+    let mut lines = vec![format!("🔁 {} was already a selector, so I updated it instead:", link)];
+    lines.extend(changes.iter().map(describe_change));
+    crate::say_lines(ctx, channel, &lines).await?;
+
+    lines[0] = format!("<@{}> updated selector {} by registering it again:", user.get(), link);
+    audit::log(ctx, guild, lines.join("\n")).await;
+    events::publish(ctx, Event::SelectorChanged { guild, channel: message.channel_id, message: message.id, change: SelectorChange::Edited }).await;
+
+    if let Err(err) = warn_conflicts(ctx, guild, message, user).await {
+        error!("failed to check selector {} for conflicts: {:?}", message.id, err);
+    }
+
+    Ok(())
+}
+
+fn describe_change(change: &MappingChange) -> String {
+    let mentions = |roles: &[RoleId]| roles.iter().map(|role| format!("<@&{}>", role.get())).collect::<Vec<_>>().join(" ");
+    match change {
+        MappingChange::Added { emoji, roles } => format!("➕ {} {}", emoji, mentions(roles)),
+        MappingChange::Removed { emoji, roles } => format!("➖ {} {}", emoji, mentions(roles)),
+        MappingChange::Changed { emoji, from, to } => format!("✏️ {} {} → {}", emoji, mentions(from), mentions(to)),
+    }
+}
+
+/// Looks up the guild's roles by lowercase name, if the content refers to any roles by name.
+async fn roles_by_name(ctx: &Context, guild: Option<GuildId>, content: &str) -> HashMap<String, RoleId> {
+    let guild = match guild {
+        Some(guild) if selector::role_name_pattern().is_match(content) => guild,
+        _ => return HashMap::new(),
+    };
+
+    match ctx.http.get_guild_roles(guild).await {
+        Ok(roles) => roles.into_iter().map(|role| (role.name.to_lowercase(), role.id)).collect(),
+        Err(err) => {
+            error!("failed to look up roles of {}: {:?}", guild, err);
+            HashMap::new()
+        }
+    }
+}
+
+/// Replaces a role's name in backticks with its new name, or returns `None` if the content doesn't
+/// name it. Names are matched ignoring case, like when selectors are parsed.
+pub fn rename_in_content(content: &str, old_name: &str, new_name: &str) -> Option<String> {
+    let pattern = selector::role_name_pattern();
+    let old_name = old_name.to_lowercase();
+    if !pattern.captures_iter(content).any(|captures| captures[1].to_lowercase() == old_name) {
+        return None;
+    }
+
+    let renamed = pattern.replace_all(content, |captures: &regex::Captures| {
+        if captures[1].to_lowercase() == old_name {
+            format!("`{}`", new_name)
+        } else {
+            captures[0].to_owned()
+        }
+    });
+    Some(renamed.into_owned())
+}
+
+/// Keeps selectors that name a role in backticks readable after it was renamed. Our own messages get
+/// the new name written in; selectors posted by others are listed in the log channel so they can be
+/// edited by hand, since editing them with the old name would no longer find the role.
+pub async fn rename_role(ctx: &Context, guild: GuildId, role: RoleId, old_name: &str, new_name: &str) {
+    if read_only::blocks(ctx, guild, || format!("renamed {} in selectors", role)).await {
+        return;
+    }
+
+    let channels = ctx.cache.guild(guild).map(|guild| guild.channels.clone()).unwrap_or_default();
+    let current_user = ctx.cache.current_user().id;
+
+    let mut stale = Vec::new();
+    for (message, entry) in selectors_in(ctx, &channels).await {
+        let (channel, content) = match (entry.channel, &entry.content) {
+            (Some(channel), Some(content)) => (channel, content),
+            _ => continue,
+        };
+        if !entry.selector.iter().any(|(_, roles)| roles.contains(&role)) {
+            continue;
+        }
+        let renamed = match rename_in_content(content, old_name, new_name) {
+            Some(renamed) => renamed,
+            None => continue,
+        };
+
+        let mut target = match channel.message(&ctx.http, message).await {
+            Ok(target) => target,
+            Err(_) => continue,
+        };
+        if target.author.id != current_user {
+            stale.push(audit::message_link(guild, channel, message));
+            continue;
+        }
+
+        // store the new content first, so that the edit event doesn't reparse the selector
+        let hash = content_hash(&renamed);
+        let messages = store::<StateKey>(ctx).await;
+        messages.write(|messages| {
+            if let Some(entry) = messages.entry_mut(message) {
+                entry.content = Some(renamed.clone());
+                entry.last_applied_hash = Some(hash);
+            }
+        }).await;
+
+        if let Err(err) = target.edit(ctx, EditMessage::new().content(&renamed)).await {
+            error!("failed to rename role in selector {}: {:?}", message, err);
+        }
+    }
+
+    if !stale.is_empty() {
+        audit::log(ctx, guild, format!(
+            "These selectors still call <@&{}> `{}`. Edit them to use the new name, or its mention: {}",
+            role.get(), old_name, stale.join(", "),
+        )).await;
+    }
+}
+
+/// Offers to create any roles that the selector names but which don't exist yet. Our own messages
+/// get the names replaced with mentions of the new roles.
+async fn create_missing_roles(ctx: &Context, guild: GuildId, channel: ChannelId, user: UserId, message: &mut Message) -> CommandResult<()> {
+    let mut names = roles_by_name(ctx, Some(guild), &message.content).await;
+    let mut missing = Selector::unresolved_names(&message.content, |name| names.get(&name.to_lowercase()).copied());
+    let mut seen = HashSet::new();
+    missing.retain(|name| seen.insert(name.to_lowercase()));
+    if missing.is_empty() {
+        return Ok(());
+    }
+
+    let listed: Vec<String> = missing.iter().map(|name| format!("`{}`", name)).collect();
+    let prompt = format!("This selector names roles that don't exist yet: {}. Should I create them?", listed.join(", "));
+    if !crate::confirm::ask(ctx, channel, user, prompt).await? {
+        return Ok(());
+    }
+
+    for name in &missing {
+        let role = guild.create_role(&ctx.http, EditRole::new().name(name).permissions(Permissions::empty()).hoist(false).mentionable(false)).await?;
+        names.insert(name.to_lowercase(), role.id);
+    }
+
+    audit::log(ctx, guild, format!("<@{}> created roles for a selector: {}", user.get(), listed.join(", "))).await;
+
+    if message.author.id == ctx.cache.current_user().id {
+        let content = mention_named_roles(&message.content, &names);
+        message.edit(ctx, EditMessage::new().content(content)).await?;
+    }
+
+    Ok(())
+}
+
+/// Replaces role names in backticks with mentions of the roles, leaving unknown names as they are.
+fn mention_named_roles(content: &str, names: &HashMap<String, RoleId>) -> String {
+    selector::role_name_pattern().replace_all(content, |captures: &regex::Captures| {
+        match names.get(&captures[1].to_lowercase()) {
+            Some(role) => format!("<@&{}>", role.get()),
+            None => captures[0].to_owned(),
+        }
+    }).into_owned()
+}
+
+/// Registers `message` as a selector, letting `configure` adjust the entry before it is stored.
+pub async fn register_selector<F>(ctx: &Context, message: &Message, registered_by: UserId, configure: F)
+    where F: FnOnce(&mut SelectorEntry)
+{
+    let now = scheduler::now();
+    let names = roles_by_name(ctx, message.guild_id, &message.content).await;
+
+    let messages = store::<StateKey>(ctx).await;
+    messages.write(|messages| {
+        let selector = Selector::parse_with(&message.content, |name| names.get(&name.to_lowercase()).copied());
+        let mut entry = SelectorEntry::new(message.channel_id, selector, &message.content);
+        entry.registered_by = Some(registered_by);
+        entry.registered_at = Some(now);
+        configure(&mut entry);
+        messages.insert_selector(message.id, entry);
+    }).await;
+
+    apply_selector_reactions(ctx, message.channel_id, message.id).await;
+    threads::selector_registered(ctx, message.channel_id).await;
+
+    if let Some(guild) = message.guild_id {
+        let link = audit::message_link(guild, message.channel_id, message.id);
+        audit::log(ctx, guild, format!("<@{}> registered selector {}", registered_by.get(), link)).await;
+        events::publish(ctx, Event::SelectorChanged {
+            guild,
+            channel: message.channel_id,
+            message: message.id,
+            change: SelectorChange::Registered,
+        }).await;
+
+        if let Err(err) = warn_conflicts(ctx, guild, message, registered_by).await {
+            error!("failed to check selector {} for conflicts: {:?}", message.id, err);
+        }
+    }
+}
+
+/// Tells whoever registered a selector about any conflicts it has with other selectors.
+async fn warn_conflicts(ctx: &Context, guild: GuildId, message: &Message, registered_by: UserId) -> serenity::Result<()> {
+    let warnings: Vec<String> = describe_conflicts(ctx, guild).await?
+        .into_iter()
+        .filter(|(conflict, _)| conflict.involves(message.id))
+        .map(|(_, description)| format!("⚠️ {}", description))
+        .collect();
+
+    if warnings.is_empty() {
+        return Ok(());
+    }
+
+    audit::log(ctx, guild, warnings.join("\n")).await;
+
+    let dm = registered_by.create_dm_channel(&ctx.http).await?;
+    crate::say_lines(ctx, dm.id, &warnings).await
+}
+
+/// Finds conflicts between all selectors in the guild, along with a human-readable description.
+pub async fn describe_conflicts(ctx: &Context, guild: GuildId) -> serenity::Result<Vec<(Conflict, String)>> {
+    let channels = guild.channels(&ctx.http).await?;
+    let selectors = selectors_in(ctx, &channels).await;
+
+    let selector_channels: HashMap<MessageId, ChannelId> = selectors.iter()
+        .filter_map(|(message, entry)| Some((*message, entry.channel?)))
+        .collect();
+
+    Ok(find_conflicts(&selectors).into_iter()
+        .map(|conflict| {
+            let description = conflict.describe(guild, &selector_channels);
+            (conflict, description)
+        })
+        .collect())
+}
+
+pub async fn list_selectors(ctx: &Context, command: &Message) -> CommandResult<()> {
+    let guild = command.guild_id.ok_or(CommandError::NotAllowed)?;
+    let channels = guild.channels(&ctx.http).await?;
+
+    let mut selectors = selectors_in(ctx, &channels).await;
+    selectors.sort_by_key(|(message, _)| *message);
+    let names = store::<names::StateKey>(ctx).await.read().await.clone();
+
+    let lines: Vec<String> = selectors.iter()
+        .filter_map(|(message, entry)| {
+            let mut line = format!(
+                "• {} — {} emoji",
+                audit::message_link(guild, entry.channel?, *message),
+                entry.selector.iter().count(),
+            );
+            if let Some(name) = names.name_of(guild, *message) {
+                line.push_str(&format!(", named `{}`", name));
+            }
+            if !entry.selector.is_enabled() {
+                line.push_str(", paused");
+            }
+            if let Some(log_channel) = entry.log_channel {
+                line.push_str(&format!(", logged in <#{}>", log_channel.get()));
+            }
+            if let Some(missing) = entry.missing_permissions {
+                line.push_str(&format!(", **degraded**: I'm missing `{}`", missing));
+            }
+            if let Some(user) = entry.registered_by {
+                line.push_str(&format!("\n    registered by <@{}>{}", user.get(), relative_time(entry.registered_at)));
+            }
+            if let Some(user) = entry.last_edited_by {
+                line.push_str(&format!("\n    last edited by <@{}>{}", user.get(), relative_time(entry.last_edited_at)));
+            }
+            Some(line)
+        })
+        .collect();
+
+    if lines.is_empty() {
+        command.reply(ctx, "There are no role selectors in this server.").await?;
     } else {
-        Err(CommandError::InvalidMessageReference)
+        crate::say_lines(ctx, command.channel_id, &lines).await?;
+    }
+
+    Ok(())
+}
+
+/// Shows how many members reacted to each option of a selector, and how many hold its roles.
+pub async fn selector_stats(ctx: &Context, command: &Message, message: MessageId) -> CommandResult<()> {
+    let guild = command.guild_id.ok_or(CommandError::NotAllowed)?;
+
+    let entry = store::<StateKey>(ctx).await.read().await.entry(message).cloned();
+    let entry = entry.ok_or(CommandError::UnknownSelector)?;
+    let channel = entry.channel.ok_or(CommandError::UnknownSelectorChannel)?;
+    let target = channel.message(&ctx.http, message).await?;
+
+    let holders = holder_counts(ctx, guild).await;
+
+    let mut header = format!("**Selector** {}", audit::message_link(guild, channel, message));
+    if let Some(name) = names::name_of(ctx, guild, message).await {
+        header.push_str(&format!(" `{}`", name));
+    }
+    if !entry.selector.is_enabled() {
+        header.push_str(", paused");
+    }
+
+    let mut lines = vec![header];
+    for (emoji, roles) in entry.selector.iter() {
+        let reactions = target.reactions.iter()
+            .find(|reaction| selector::Emoji::from(reaction.reaction_type.clone()) == *emoji)
+            .map(|reaction| reaction.count - reaction.me as u64)
+            .unwrap_or(0);
+        let mentions: Vec<String> = roles.iter()
+            .map(|role| format!("<@&{}> ({} members)", role.get(), holders.get(role).copied().unwrap_or(0)))
+            .collect();
+        lines.push(format!("{} {} reactions — {}", emoji, reactions, mentions.join(", ")));
+    }
+    crate::say_lines(ctx, command.channel_id, &lines).await?;
+
+    Ok(())
+}
+
+/// How many cached members hold each role of a guild.
+async fn holder_counts(ctx: &Context, guild: GuildId) -> HashMap<RoleId, usize> {
+    ctx.cache.guild(guild).map(|guild| {
+        let mut holders = HashMap::new();
+        for member in guild.members.values() {
+            for role in &member.roles {
+                *holders.entry(*role).or_default() += 1;
+            }
+        }
+        holders
+    }).unwrap_or_default()
+}
+
+fn relative_time(timestamp: Option<i64>) -> String {
+    timestamp.map(|timestamp| format!(" <t:{}:R>", timestamp)).unwrap_or_default()
+}
+
+/// Returns all selectors that live in any of the given channels.
+pub async fn is_configured(ctx: &Context, guild: GuildId) -> bool {
+    match guild.channels(&ctx.http).await {
+        Ok(channels) => !selectors_in(ctx, &channels).await.is_empty(),
+        Err(_) => false,
     }
 }
+
+pub async fn selector_count(ctx: &Context) -> usize {
+    let state = store::<StateKey>(ctx).await;
+    let count = state.read().await.0.len();
+    count
+}
+
+/// How many selectors each cached guild has, going through the store only once.
+pub async fn guild_selector_counts(ctx: &Context) -> HashMap<GuildId, usize> {
+    let guilds: HashMap<ChannelId, GuildId> = ctx.cache.guilds().into_iter()
+        .filter_map(|guild| ctx.cache.guild(guild).map(|guild| guild.channels.keys().map(|channel| (*channel, guild.id)).collect::<Vec<_>>()))
+        .flatten()
+        .collect();
+
+    let channels: Vec<ChannelId> = store::<StateKey>(ctx).await.read().await.0.values()
+        .filter_map(|entry| entry.channel)
+        .collect();
+
+    let mut counts = HashMap::new();
+    for guild in channels.iter().filter_map(|channel| guilds.get(channel)) {
+        *counts.entry(*guild).or_default() += 1;
+    }
+    counts
+}
+
+pub async fn guild_selector_count(ctx: &Context, guild: GuildId) -> usize {
+    let channels = ctx.cache.guild(guild).map(|guild| guild.channels.clone()).unwrap_or_default();
+    selectors_in(ctx, &channels).await.len()
+}
+
+pub async fn selectors_in<C>(ctx: &Context, channels: &HashMap<ChannelId, C>) -> Vec<(MessageId, SelectorEntry)> {
+    let messages = store::<StateKey>(ctx).await;
+    let selectors = messages.read().await.0.iter()
+        .filter(|(_, entry)| entry.channel.is_some_and(|channel| channels.contains_key(&channel)))
+        .map(|(message, entry)| (*message, entry.clone()))
+        .collect();
+    selectors
+}
+
+/// Unregisters a selector, leaving its message in place. It can be brought back with `undo` for a while.
+pub async fn remove_selector(ctx: &Context, command: &Message, message: MessageId) -> CommandResult<()> {
+    let guild = command.guild_id.ok_or(CommandError::NotAllowed)?;
+
+    let channel = selector_channel(ctx, message).await.unwrap_or(command.channel_id);
+    clear_selector_reactions(ctx, channel, message).await;
+
+    let messages = store::<StateKey>(ctx).await;
+    let entry = messages.write(|messages| messages.remove_selector(message)).await;
+    let entry = entry.ok_or(CommandError::UnknownSelector)?;
+    names::forget(ctx, message).await;
+
+    let log_channel = entry.log_channel;
+    archive::archive(ctx, guild, message, entry, archive::Removal::Unregistered).await;
+    events::publish(ctx, Event::SelectorChanged { guild, channel, message, change: SelectorChange::Removed }).await;
+    audit::log_to(ctx, guild, log_channel, format!(
+        "<@{}> removed selector {}. Bring it back with `undo`",
+        command.author.id.get(), audit::message_link(guild, channel, message),
+    )).await;
+
+    Ok(())
+}
+
+/// Moves a selector's registration over to another message, like a copy of it posted elsewhere,
+/// taking the bot's reactions off the old message and adding them to the new one.
+pub async fn move_selector(ctx: &Context, command: &Message, old: MessageId, channel: ChannelId, new: MessageId) -> CommandResult<()> {
+    let guild = command.guild_id.ok_or(CommandError::NotAllowed)?;
+
+    if old == new {
+        return Err(CommandError::InvalidMessageReference);
+    }
+    let old_channel = selector_channel(ctx, old).await;
+    if !is_message_selector(ctx, old).await {
+        return Err(CommandError::UnknownSelector);
+    }
+    if is_message_selector(ctx, new).await {
+        return Err(CommandError::AlreadySelector);
+    }
+    if crate::resolve_channel(ctx, channel).await.ok().map(|channel| channel.guild_id) != Some(guild) {
+        return Err(CommandError::InvalidMessageReference);
+    }
+    channel.message(&ctx.http, new).await.map_err(|_| CommandError::InvalidMessageReference)?;
+
+    let old_channel = old_channel.unwrap_or(command.channel_id);
+    clear_selector_reactions(ctx, old_channel, old).await;
+
+    let messages = store::<StateKey>(ctx).await;
+    let log_channel: Option<ChannelId> = messages.write(|messages| {
+        let mut entry = messages.remove_selector(old).ok_or(CommandError::UnknownSelector)?;
+        entry.channel = Some(channel);
+        let log_channel = entry.log_channel;
+        messages.insert_selector(new, entry);
+        Ok::<_, CommandError>(log_channel)
+    }).await?;
+    names::moved(ctx, old, new).await;
+    expiry::moved(ctx, old, channel, new).await;
+    eligibility::selector_moved(ctx, old, channel, new).await;
+
+    apply_selector_reactions(ctx, channel, new).await;
+    threads::selector_registered(ctx, channel).await;
+
+    events::publish(ctx, Event::SelectorChanged { guild, channel, message: new, change: SelectorChange::Moved }).await;
+    audit::log_to(ctx, guild, log_channel, format!(
+        "<@{}> moved selector {} to {}",
+        command.author.id.get(), audit::message_link(guild, old_channel, old), audit::message_link(guild, channel, new),
+    )).await;
+
+    Ok(())
+}
+
+pub async fn pause_selector(ctx: &Context, command: &Message, message_id: MessageId, clear_reactions: bool) -> CommandResult<()> {
+    set_selector_enabled(ctx, message_id, false).await?;
+    command.delete(ctx).await?;
+
+    if clear_reactions {
+        let channel = selector_channel(ctx, message_id).await.unwrap_or(command.channel_id);
+        clear_selector_reactions(ctx, channel, message_id).await;
+    }
+
+    Ok(())
+}
+
+pub async fn resume_selector(ctx: &Context, command: &Message, message_id: MessageId) -> CommandResult<()> {
+    set_selector_enabled(ctx, message_id, true).await?;
+    command.delete(ctx).await?;
+
+    let channel = selector_channel(ctx, message_id).await.unwrap_or(command.channel_id);
+    apply_selector_reactions(ctx, channel, message_id).await;
+
+    Ok(())
+}
+
+pub async fn set_unmapped_policy(ctx: &Context, message: MessageId, policy: UnmappedPolicy) -> CommandResult<()> {
+    edit_selector(ctx, message, |selector| selector.set_unmapped_policy(policy)).await
+}
+
+pub async fn set_exclusive(ctx: &Context, message: MessageId, exclusive: bool) -> CommandResult<()> {
+    edit_selector(ctx, message, |selector| selector.set_exclusive(exclusive)).await
+}
+
+pub async fn set_confirm_switch(ctx: &Context, message: MessageId, confirm_switch: bool) -> CommandResult<()> {
+    edit_selector(ctx, message, |selector| selector.set_confirm_switch(confirm_switch)).await
+}
+
+pub async fn set_option(ctx: &Context, message: MessageId, emoji: &Emoji, update: OptionUpdate) -> CommandResult<()> {
+    let messages = store::<StateKey>(ctx).await;
+    messages.write(|messages| {
+        let selector = messages.selector_mut(message).ok_or(CommandError::UnknownSelector)?;
+        let option = selector.option_mut(emoji).ok_or(CommandError::UnknownSelectorOption)?;
+        match update {
+            OptionUpdate::Sticky(sticky) => option.sticky = sticky,
+            OptionUpdate::AddOnly(add_only) => option.add_only = add_only,
+            OptionUpdate::Description(description) => option.description = description,
+            OptionUpdate::ExpiresAfter(after) => option.expires_after = after,
+        }
+        Ok(())
+    }).await
+}
+
+/// Turns the role colour preview of one of our own selectors on or off, posting it again to add or
+/// remove the preview.
+pub async fn set_preview(ctx: &Context, message: MessageId, preview: bool) -> CommandResult<()> {
+    if !is_message_selector(ctx, message).await {
+        return Err(CommandError::UnknownSelector);
+    }
+    let channel = selector_channel(ctx, message).await.ok_or(CommandError::UnknownSelectorChannel)?;
+
+    let current_user = ctx.cache.current_user().id;
+    if channel.message(&ctx.http, message).await?.author.id != current_user {
+        return Err(CommandError::NotOwnSelector);
+    }
+
+    let messages = store::<StateKey>(ctx).await;
+    messages.write(|messages| {
+        if let Some(entry) = messages.entry_mut(message) {
+            entry.preview = preview;
+        }
+    }).await;
+
+    repost_selector(ctx, channel, message).await?;
+    Ok(())
+}
+
+pub async fn set_log_channel(ctx: &Context, message: MessageId, channel: Option<ChannelId>) -> CommandResult<()> {
+    let messages = store::<StateKey>(ctx).await;
+    messages.write(|messages| {
+        let entry = messages.entry_mut(message).ok_or(CommandError::UnknownSelector)?;
+        entry.log_channel = channel;
+        Ok(())
+    }).await
+}
+
+pub async fn set_approval_channel(ctx: &Context, message: MessageId, channel: Option<ChannelId>) -> CommandResult<()> {
+    edit_selector(ctx, message, |selector| selector.set_approval_channel(channel)).await
+}
+
+async fn set_selector_enabled(ctx: &Context, message: MessageId, enabled: bool) -> CommandResult<()> {
+    edit_selector(ctx, message, |selector| selector.set_enabled(enabled)).await
+}
+
+async fn edit_selector<F>(ctx: &Context, message: MessageId, f: F) -> CommandResult<()>
+    where F: FnOnce(&mut Selector)
+{
+    let messages = store::<StateKey>(ctx).await;
+    messages.write(|messages| {
+        match messages.selector_mut(message) {
+            Some(selector) => {
+                f(selector);
+                Ok(())
+            }
+            None => Err(CommandError::UnknownSelector),
+        }
+    }).await
+}
+
+async fn clear_selector_reactions(ctx: &Context, channel: ChannelId, message: MessageId) {
+    if let Some(selector) = get_selector(ctx, message).await {
+        let current_user = ctx.cache.current_user().id;
+        for (emoji, _) in selector.iter() {
+            let reaction_type = emoji.clone().into();
+            let _ = ctx.http.delete_reaction(channel, message, current_user, &reaction_type).await;
+        }
+    }
+}
+
+/// Removes selectors whose channel or message no longer exists, returning how many were removed.
+///
+/// This relies on the cache holding every channel the bot can see, so it must only run once all guilds
+/// are available. Archived threads aren't cached, so channels missing from it are looked up first.
+pub async fn prune_selectors(ctx: &Context) -> usize {
+    let state = store::<StateKey>(ctx).await;
+    let selectors: Vec<(MessageId, Option<ChannelId>)> = state.read().await.0.iter()
+        .map(|(message, entry)| (*message, entry.channel))
+        .collect();
+
+    let mut dead = Vec::new();
+    for (message, channel) in selectors {
+        // legacy selectors don't know their channel, so we can't check them
+        let channel = match channel {
+            Some(channel) => channel,
+            None => continue,
+        };
+
+        let exists = match crate::resolve_channel(ctx, channel).await {
+            Ok(_) => !matches!(channel.message(&ctx.http, message).await, Err(err) if crate::is_not_found(&err)),
+            Err(err) => !crate::is_not_found(&err),
+        };
+
+        if !exists {
+            dead.push(message);
+        }
+    }
+
+    state.write(|state| {
+        for message in &dead {
+            state.0.remove(message);
+        }
+    }).await;
+
+    dead.len()
+}
+
+/// The permissions the bot needs in a selector's channel to keep its reactions in line with the message.
+fn selector_permissions() -> Permissions {
+    Permissions::VIEW_CHANNEL | Permissions::READ_MESSAGE_HISTORY | Permissions::ADD_REACTIONS | Permissions::MANAGE_MESSAGES
+}
+
+/// Marks selectors in channels where the bot lost permissions it needs as degraded, and ones where it
+/// got them back as healthy again, telling their log channel either way. Returns how many selectors
+/// are degraded.
+pub async fn check_access(ctx: &Context) -> usize {
+    let state = store::<StateKey>(ctx).await;
+    let selectors: Vec<(MessageId, ChannelId, Option<Permissions>, Option<ChannelId>)> = state.read().await.0.iter()
+        .filter_map(|(message, entry)| Some((*message, entry.channel?, entry.missing_permissions, entry.log_channel)))
+        .collect();
+
+    let mut degraded = 0;
+    let mut changes = Vec::new();
+    for (message, channel, was_missing, log_channel) in selectors {
+        // selectors in channels we can't see at all are left to pruning, and archived threads get
+        // reopened when they're archived
+        let guild = match crate::cached_channel(&ctx.cache, channel) {
+            Some(channel) => channel.guild_id,
+            None => continue,
+        };
+
+        let missing = selector_permissions().difference(crate::own_channel_permissions(ctx, channel).await);
+        let missing = Some(missing).filter(|missing| !missing.is_empty());
+        if missing.is_some() {
+            degraded += 1;
+        }
+        if missing != was_missing {
+            changes.push((message, guild, channel, log_channel, missing));
+        }
+    }
+    if changes.is_empty() {
+        return degraded;
+    }
+
+    state.write(|state| {
+        for (message, _, _, _, missing) in &changes {
+            if let Some(entry) = state.entry_mut(*message) {
+                entry.missing_permissions = *missing;
+            }
+        }
+    }).await;
+
+    for (message, guild, channel, log_channel, missing) in changes {
+        let link = audit::message_link(guild, channel, message);
+        let content = match missing {
+            Some(missing) => format!("⚠️ I can't keep selector {} up to date anymore, since I'm missing `{}` in <#{}>", link, missing, channel.get()),
+            None => format!("✅ I can keep selector {} up to date again", link),
+        };
+        audit::log_to(ctx, guild, log_channel, content).await;
+    }
+
+    degraded
+}
+
+/// The most messages a selector can be refreshed after, since Discord returns at most 100 messages
+/// at once.
+pub const MAX_REFRESH_DEPTH: u64 = 100;
+
+pub async fn set_refresh(ctx: &Context, message: MessageId, update: RefreshUpdate) -> CommandResult<()> {
+    if let RefreshUpdate::Interval(0) | RefreshUpdate::Depth(0) = update {
+        return Err(CommandError::MalformedArgument("0".to_owned()));
+    }
+    if let RefreshUpdate::Depth(depth) = update {
+        if depth > MAX_REFRESH_DEPTH {
+            return Err(CommandError::RefreshTooDeep);
+        }
+    }
+
+    let now = scheduler::now();
+
+    let messages = store::<StateKey>(ctx).await;
+    messages.write(|messages| {
+        let entry = messages.entry_mut(message).ok_or(CommandError::UnknownSelector)?;
+        if entry.channel.is_none() {
+            return Err(CommandError::UnknownSelectorChannel);
+        }
+
+        let refresh = entry.refresh.get_or_insert_with(|| RefreshPolicy { last_posted: now, ..Default::default() });
+        match update {
+            RefreshUpdate::Interval(interval_mins) => refresh.interval_mins = Some(interval_mins),
+            RefreshUpdate::Depth(depth) => refresh.depth = Some(depth),
+            RefreshUpdate::Off => entry.refresh = None,
+        }
+
+        Ok(())
+    }).await
+}
+
+/// Reposts every selector whose refresh policy says it has become stale.
+pub async fn refresh_selectors(ctx: Context) {
+    let candidates: Vec<(MessageId, ChannelId, RefreshPolicy)> = {
+        let messages = store::<StateKey>(&ctx).await;
+        let messages = messages.read().await;
+        messages.0.iter()
+            .filter(|(_, entry)| entry.selector.is_enabled())
+            .filter_map(|(message, entry)| Some((*message, entry.channel?, entry.refresh.clone()?)))
+            .collect()
+    };
+
+    let now = scheduler::now();
+
+    for (message, channel, refresh) in candidates {
+        match is_refresh_due(&ctx, channel, message, &refresh, now).await {
+            Ok(true) => {
+                let guild = crate::cached_channel(&ctx.cache, channel).map(|channel| channel.guild_id);
+                if read_only::blocks(&ctx, guild, || format!("reposted selector {}", message)).await {
+                    continue;
+                }
+                if let Err(err) = repost_selector(&ctx, channel, message).await {
+                    error!("failed to repost selector {}: {:?}", message, err);
+                }
+            }
+            Ok(false) => (),
+            Err(err) => error!("failed to check selector {} for refresh: {:?}", message, err),
+        }
+    }
+}
+
+async fn is_refresh_due(ctx: &Context, channel: ChannelId, message: MessageId, refresh: &RefreshPolicy, now: i64) -> serenity::Result<bool> {
+    if let Some(interval_mins) = refresh.interval_mins {
+        if now - refresh.last_posted >= interval_mins as i64 * 60 {
+            return Ok(true);
+        }
+    }
+
+    if let Some(depth) = refresh.depth {
+        let newer = channel.messages(&ctx.http, GetMessages::new().after(message).limit(depth.min(MAX_REFRESH_DEPTH) as u8)).await?;
+        if newer.len() as u64 >= depth {
+            return Ok(true);
+        }
+    }
+
+    Ok(false)
+}
+
+/// Posts a copy of the selector at the bottom of its channel, moves the registration over to it and
+/// deletes the old message.
+async fn repost_selector(ctx: &Context, channel: ChannelId, message: MessageId) -> serenity::Result<MessageId> {
+    let old_message = channel.message(&ctx.http, message).await?;
+
+    let preview = match store::<StateKey>(ctx).await.read().await.entry(message) {
+        Some(entry) if entry.preview => Some(entry.selector.clone()),
+        _ => None,
+    };
+    let guild = crate::cached_channel(&ctx.cache, channel).map(|channel| channel.guild_id);
+    let swatches = match (preview, guild) {
+        (Some(selector), Some(guild)) => preview::swatches(ctx, guild, &selector).await,
+        _ => Vec::new(),
+    };
+    let image = preview::render(&swatches);
+
+    let mut builder = CreateMessage::new()
+        .content(&old_message.content)
+        .allowed_mentions(CreateAllowedMentions::new());
+    if !swatches.is_empty() {
+        let lines: Vec<String> = swatches.iter().map(preview::Swatch::describe).collect();
+        builder = builder
+            .add_file(CreateAttachment::bytes(image, preview::FILE_NAME))
+            .embed(CreateEmbed::new().description(lines.join("\n")).attachment(preview::FILE_NAME));
+    }
+    let new_message = channel.send_message(&ctx.http, builder).await?;
+
+    let now = scheduler::now();
+    let messages = store::<StateKey>(ctx).await;
+    messages.write(|messages| {
+        if let Some(mut entry) = messages.remove_selector(message) {
+            if let Some(refresh) = &mut entry.refresh {
+                refresh.last_posted = now;
+            }
+            messages.insert_selector(new_message.id, entry);
+        }
+    }).await;
+    names::moved(ctx, message, new_message.id).await;
+
+    apply_selector_reactions(ctx, channel, new_message.id).await;
+    old_message.delete(&ctx.http).await?;
+
+    Ok(new_message.id)
+}
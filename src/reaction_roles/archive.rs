@@ -0,0 +1,244 @@
+use std::collections::{HashMap, HashSet};
+
+use serde::{Deserialize, Serialize};
+use serenity::builder::{CreateAllowedMentions, CreateMessage};
+use serenity::model::prelude::*;
+use serenity::prelude::*;
+
+use log::error;
+
+use super::{register_selector, Emoji, SelectorEntry};
+use crate::{audit, confirm, CommandError, CommandResult, events, Persistent, quotas, role_queue, scheduler, store};
+use crate::events::{Event, RoleSource};
+use crate::quotas::Quota;
+use crate::memreport::{Introspect, Owner};
+
+/// How long deleted selectors are kept around to be restored.
+pub const RETENTION_SECS: i64 = 30 * 24 * 60 * 60;
+
+/// How long unregistered selectors are kept around, and how far back `undo` looks.
+pub const UNDO_SECS: i64 = 24 * 60 * 60;
+
+pub struct StateKey;
+
+impl TypeMapKey for StateKey {
+    type Value = Persistent<State>;
+}
+
+/// Snapshots of removed selectors, keyed by the message they used to live on.
+#[derive(Serialize, Deserialize, Default, Clone, Eq, PartialEq)]
+pub struct State {
+    selectors: HashMap<MessageId, ArchivedSelector>,
+}
+
+impl Introspect for State {
+    fn entries(&self) -> Vec<(Owner, usize)> {
+        self.selectors.values().map(|archived| (Owner::Guild(archived.guild), 1)).collect()
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Eq, PartialEq)]
+pub struct ArchivedSelector {
+    pub guild: GuildId,
+    pub entry: SelectorEntry,
+    pub deleted_at: i64,
+    #[serde(default)]
+    pub removal: Removal,
+}
+
+/// How a selector came to be removed.
+#[derive(Serialize, Deserialize, Default, Clone, Copy, Debug, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Removal {
+    /// The selector message was deleted, so restoring it means posting it again.
+    #[default]
+    MessageDeleted,
+    /// The selector was unregistered by command, leaving the message in place.
+    Unregistered,
+}
+
+impl ArchivedSelector {
+    fn expires_at(&self) -> i64 {
+        match self.removal {
+            Removal::MessageDeleted => self.deleted_at + RETENTION_SECS,
+            Removal::Unregistered => self.deleted_at + UNDO_SECS,
+        }
+    }
+}
+
+pub async fn archive(ctx: &Context, guild: GuildId, message: MessageId, entry: SelectorEntry, removal: Removal) {
+    let deleted_at = scheduler::now();
+
+    let state = store::<StateKey>(ctx).await;
+    state.write(|state| {
+        state.selectors.insert(message, ArchivedSelector { guild, entry, deleted_at, removal });
+    }).await;
+}
+
+/// Reposts a deleted selector as our own message in its original channel and registers it again.
+pub async fn restore(ctx: &Context, command: &Message, message: MessageId) -> CommandResult<()> {
+    let guild = command.guild_id.ok_or(CommandError::NotAllowed)?;
+
+    let state = store::<StateKey>(ctx).await;
+    let archived = state.read().await.selectors.get(&message)
+        .filter(|archived| archived.guild == guild)
+        .cloned();
+    let archived = archived.ok_or(CommandError::UnknownArchivedSelector)?;
+
+    restore_archived(ctx, command, message, archived).await
+}
+
+/// Restores the most recently removed selector in the guild, if it was removed within the undo window.
+pub async fn undo(ctx: &Context, command: &Message) -> CommandResult<()> {
+    let guild = command.guild_id.ok_or(CommandError::NotAllowed)?;
+    let since = scheduler::now() - UNDO_SECS;
+
+    let state = store::<StateKey>(ctx).await;
+    let latest = state.read().await.selectors.iter()
+        .filter(|(_, archived)| archived.guild == guild && archived.deleted_at >= since)
+        .max_by_key(|(_, archived)| archived.deleted_at)
+        .map(|(message, archived)| (*message, archived.clone()));
+    let (message, archived) = latest.ok_or(CommandError::NothingToUndo)?;
+
+    restore_archived(ctx, command, message, archived).await
+}
+
+async fn restore_archived(ctx: &Context, command: &Message, message: MessageId, archived: ArchivedSelector) -> CommandResult<()> {
+    let guild = archived.guild;
+    quotas::check(ctx, guild, Quota::Selectors, 1).await?;
+    let channel = archived.entry.channel.unwrap_or(command.channel_id);
+
+    // an unregistered selector's message is still around, so it can simply be registered again
+    let existing = match archived.removal {
+        Removal::Unregistered => channel.message(&ctx.http, message).await.ok(),
+        Removal::MessageDeleted => None,
+    };
+    let restored = match existing {
+        Some(existing) => existing,
+        None => {
+            let content = archived.entry.render_content();
+            channel.send_message(&ctx.http, CreateMessage::new().content(&content).allowed_mentions(CreateAllowedMentions::new())).await?
+        }
+    };
+
+    let now = scheduler::now();
+    let ArchivedSelector { entry, .. } = archived;
+    register_selector(ctx, &restored, command.author.id, |restored| {
+        restored.selector = entry.selector;
+        restored.template = entry.template;
+        restored.refresh = entry.refresh.map(|mut refresh| {
+            refresh.last_posted = now;
+            refresh
+        });
+    }).await;
+
+    let state = store::<StateKey>(ctx).await;
+    state.write(|state| {
+        state.selectors.remove(&message);
+    }).await;
+
+    audit::log(ctx, guild, format!(
+        "<@{}> restored removed selector `{}` as {}",
+        command.author.id.get(), message.get(), audit::message_link(guild, channel, restored.id),
+    )).await;
+
+    Ok(())
+}
+
+/// Removes every reaction from the message of a selector that is no longer registered, optionally
+/// taking the roles of each emoji from the members who reacted with it.
+pub async fn clear_reactions(ctx: &Context, command: &Message, message: MessageId, strip_roles: bool) -> CommandResult<()> {
+    let guild = command.guild_id.ok_or(CommandError::NotAllowed)?;
+
+    if store::<super::StateKey>(ctx).await.read().await.is_selector(message) {
+        return Err(CommandError::SelectorStillRegistered);
+    }
+
+    let state = store::<StateKey>(ctx).await;
+    let archived = state.read().await.selectors.get(&message)
+        .filter(|archived| archived.guild == guild)
+        .cloned();
+    if strip_roles && archived.is_none() {
+        return Err(CommandError::UnknownArchivedSelector);
+    }
+
+    let channel = archived.as_ref().and_then(|archived| archived.entry.channel).unwrap_or(command.channel_id);
+    let target = channel.message(&ctx.http, message).await.map_err(|_| CommandError::InvalidMessageReference)?;
+
+    let mut stripped: Vec<(UserId, Vec<RoleId>)> = Vec::new();
+    if let (true, Some(archived)) = (strip_roles, &archived) {
+        for reaction in &target.reactions {
+            let roles = match archived.entry.selector.get_roles(&Emoji::from(reaction.reaction_type.clone())) {
+                Some(roles) => roles.to_vec(),
+                None => continue,
+            };
+            for user in reaction_users(ctx, channel, message, &reaction.reaction_type).await? {
+                stripped.push((user, roles.clone()));
+            }
+        }
+
+        let members: HashSet<UserId> = stripped.iter().map(|(user, _)| *user).collect();
+        let prompt = format!("This takes the selector's roles away from the {} members who reacted to it. Should I go ahead?", members.len());
+        if !members.is_empty() && !confirm::ask(ctx, command.channel_id, command.author.id, prompt).await? {
+            return Ok(());
+        }
+    }
+
+    target.delete_reactions(&ctx.http).await?;
+
+    for (user, roles) in &stripped {
+        if let Err(err) = role_queue::remove_roles(ctx, guild, *user, roles).await {
+            error!("failed to take the roles of a retired selector from {} in {}: {:?}", user, guild, err);
+            continue;
+        }
+        events::publish(ctx, Event::RolesRevoked { guild, user: *user, roles: roles.clone(), source: RoleSource::Selector }).await;
+    }
+
+    let mut log = format!("<@{}> cleared the reactions of retired selector {}", command.author.id.get(), audit::message_link(guild, channel, message));
+    if strip_roles {
+        let members: HashSet<UserId> = stripped.iter().map(|(user, _)| *user).collect();
+        log.push_str(&format!(", taking its roles from {} members", members.len()));
+    }
+    audit::log(ctx, guild, log).await;
+
+    Ok(())
+}
+
+/// Every user who reacted to a message with an emoji, besides bots, fetched a page at a time.
+async fn reaction_users(ctx: &Context, channel: ChannelId, message: MessageId, reaction: &ReactionType) -> serenity::Result<Vec<UserId>> {
+    const PAGE_SIZE: u8 = 100;
+
+    let mut users = Vec::new();
+    let mut after = None;
+    loop {
+        let page = channel.reaction_users(&ctx.http, message, reaction.clone(), Some(PAGE_SIZE), after).await?;
+        after = page.last().map(|user| user.id);
+        users.extend(page.iter().filter(|user| !user.bot).map(|user| user.id));
+        if page.len() < PAGE_SIZE as usize {
+            return Ok(users);
+        }
+    }
+}
+
+/// Purges archived selectors that have outlived their retention, returning how many were removed.
+pub async fn prune(ctx: &Context, now: i64) -> usize {
+    let state = store::<StateKey>(ctx).await;
+    state.write(|state| {
+        let count = state.selectors.len();
+        state.selectors.retain(|_, archived| archived.expires_at() > now);
+        count - state.selectors.len()
+    }).await
+}
+
+/// Forgets the archived selectors of all guilds not in `guilds`, returning the ones that were removed.
+pub async fn retain_guilds(ctx: &Context, guilds: &HashSet<GuildId>) -> Vec<GuildId> {
+    let state = store::<StateKey>(ctx).await;
+    state.write(|state| {
+        let removed: HashSet<GuildId> = state.selectors.values()
+            .map(|archived| archived.guild)
+            .filter(|guild| !guilds.contains(guild))
+            .collect();
+        state.selectors.retain(|_, archived| guilds.contains(&archived.guild));
+        removed.into_iter().collect()
+    }).await
+}
@@ -0,0 +1,92 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use serenity::model::prelude::*;
+
+use super::{Emoji, SelectorEntry};
+use crate::audit;
+
+/// A configuration problem spanning one or more selectors.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Conflict {
+    /// The same emoji grants different roles on selectors in the same channel.
+    AmbiguousEmoji { channel: ChannelId, emoji: Emoji, selectors: Vec<MessageId> },
+    /// A role is granted by several selector entries, so removing any one of those reactions takes
+    /// the role away even though the member still has another reaction granting it.
+    SharedRole { role: RoleId, selectors: Vec<MessageId> },
+}
+
+impl Conflict {
+    pub fn involves(&self, message: MessageId) -> bool {
+        match self {
+            Conflict::AmbiguousEmoji { selectors, .. } | Conflict::SharedRole { selectors, .. } => selectors.contains(&message),
+        }
+    }
+
+    pub fn describe(&self, guild: GuildId, channels: &HashMap<MessageId, ChannelId>) -> String {
+        let links = |selectors: &[MessageId]| -> String {
+            selectors.iter()
+                .filter_map(|message| Some(audit::message_link(guild, *channels.get(message)?, *message)))
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+
+        match self {
+            Conflict::AmbiguousEmoji { channel, emoji, selectors } => format!(
+                "{} grants different roles on selectors in <#{}>: {}",
+                emoji, channel.get(), links(selectors),
+            ),
+            Conflict::SharedRole { role, selectors } => format!(
+                "<@&{}> is granted by multiple selector entries, so removing one reaction removes the role: {}",
+                role.get(), links(selectors),
+            ),
+        }
+    }
+}
+
+type EmojiMapping = (MessageId, Vec<RoleId>);
+
+/// Checks the given selectors, which should all belong to the same guild, for conflicts.
+pub fn find_conflicts(selectors: &[(MessageId, SelectorEntry)]) -> Vec<Conflict> {
+    let mut conflicts = Vec::new();
+
+    // every mapping of each emoji within a channel, as the selector and its sorted role bundle
+    let mut emoji_roles: HashMap<(ChannelId, Emoji), Vec<EmojiMapping>> = HashMap::new();
+    let mut role_grants: BTreeMap<RoleId, Vec<MessageId>> = BTreeMap::new();
+
+    for (message, entry) in selectors {
+        for (emoji, roles) in entry.selector.iter() {
+            if let Some(channel) = entry.channel {
+                let mut roles = roles.to_vec();
+                roles.sort();
+                emoji_roles.entry((channel, emoji.clone())).or_default().push((*message, roles));
+            }
+
+            for role in roles {
+                role_grants.entry(*role).or_default().push(*message);
+            }
+        }
+    }
+
+    for ((channel, emoji), mappings) in emoji_roles {
+        let distinct_roles: BTreeSet<&Vec<RoleId>> = mappings.iter().map(|(_, roles)| roles).collect();
+        if distinct_roles.len() > 1 {
+            conflicts.push(Conflict::AmbiguousEmoji {
+                channel,
+                emoji,
+                selectors: dedup(mappings.iter().map(|(message, _)| *message)),
+            });
+        }
+    }
+
+    for (role, grants) in role_grants {
+        if grants.len() > 1 {
+            conflicts.push(Conflict::SharedRole { role, selectors: dedup(grants.into_iter()) });
+        }
+    }
+
+    conflicts
+}
+
+fn dedup(messages: impl Iterator<Item=MessageId>) -> Vec<MessageId> {
+    messages.collect::<BTreeSet<_>>().into_iter().collect()
+}
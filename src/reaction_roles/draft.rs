@@ -0,0 +1,182 @@
+use std::collections::HashSet;
+use std::fmt;
+use std::time::Duration;
+
+use serenity::builder::{
+    CreateActionRow, CreateAllowedMentions, CreateButton, CreateInputText, CreateInteractionResponse,
+    CreateInteractionResponseMessage, CreateMessage, CreateModal, EditInteractionResponse,
+};
+use serenity::collector::ModalInteractionCollector;
+use serenity::model::prelude::*;
+use serenity::prelude::*;
+
+use super::{mention_named_roles, preview, register_selector, roles_by_name, Emoji, Selector};
+use crate::{audit, CommandError, CommandResult, quotas};
+use crate::quotas::Quota;
+
+/// Discord allows at most 20 different reactions on a message.
+pub const MAX_OPTIONS: usize = 20;
+
+/// How long to wait for the form to be filled in.
+const FORM_TIMEOUT: Duration = Duration::from_secs(15 * 60);
+
+/// How long to wait for the preview to be confirmed before treating it as cancelled.
+const CONFIRM_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+
+const TITLE_INPUT: &str = "title";
+const ROLES_INPUT: &str = "roles";
+const POST_BUTTON: &str = "post";
+const CANCEL_BUTTON: &str = "cancel";
+
+/// Something that keeps the lines of a form from making a selector.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Problem {
+    /// A line, counted from 1, that doesn't pair an emoji with a role.
+    NoMapping(usize),
+    UnknownRole(String),
+    DuplicateEmoji(Emoji),
+    TooManyOptions(usize),
+    Empty,
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Problem::NoMapping(line) => write!(f, "Line {} doesn't pair an emoji with a role", line),
+            Problem::UnknownRole(name) => write!(f, "There is no role called `{}`", name),
+            Problem::DuplicateEmoji(emoji) => write!(f, "{} is used on more than one line", emoji),
+            Problem::TooManyOptions(count) => write!(f, "Discord only allows {} reactions on a message, but this has {}", MAX_OPTIONS, count),
+            Problem::Empty => write!(f, "There are no emoji and roles to put on the selector"),
+        }
+    }
+}
+
+/// Checks the lines of a form, each of which has to pair an emoji with roles or add a requirement,
+/// and returns the selector they make. Roles are given in backticks by name, or as mentions.
+pub fn validate<F>(lines: &str, resolve: F) -> Result<Selector, Vec<Problem>>
+    where F: Fn(&str) -> Option<RoleId>
+{
+    let mut problems = Vec::new();
+    let mut seen = HashSet::new();
+    for (index, line) in lines.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let unresolved = Selector::unresolved_names(line, &resolve);
+        let parsed = Selector::parse_with(line, &resolve);
+        if !unresolved.is_empty() {
+            problems.extend(unresolved.into_iter().map(Problem::UnknownRole));
+        } else if parsed.options().is_empty() && parsed.requires().is_empty() {
+            problems.push(Problem::NoMapping(index + 1));
+        }
+
+        for (emoji, _) in parsed.iter() {
+            if !seen.insert(emoji.clone()) {
+                problems.push(Problem::DuplicateEmoji(emoji.clone()));
+            }
+        }
+    }
+
+    let selector = Selector::parse_with(lines, &resolve);
+    if selector.options().len() > MAX_OPTIONS {
+        problems.push(Problem::TooManyOptions(selector.options().len()));
+    }
+    if problems.is_empty() && selector.options().is_empty() {
+        problems.push(Problem::Empty);
+    }
+
+    if problems.is_empty() { Ok(selector) } else { Err(problems) }
+}
+
+/// Asks for a selector's title and lines in a form, previews the roles they name and posts the
+/// selector in the command's channel once confirmed.
+pub async fn create(ctx: &Context, command: &CommandInteraction) -> CommandResult<()> {
+    let guild = command.guild_id.ok_or(CommandError::NotAllowed)?;
+    quotas::check(ctx, guild, Quota::Selectors, 1).await?;
+
+    // the form is told apart from others by the command it was opened for
+    let form_id = format!("role-selector-create:{}", command.id);
+    let form = CreateModal::new(&form_id, "Create a role selector").components(vec![
+        CreateActionRow::InputText(CreateInputText::new(InputTextStyle::Short, "Title", TITLE_INPUT)
+            .placeholder("Pick your colour!")
+            .max_length(200)
+            .required(false)),
+        CreateActionRow::InputText(CreateInputText::new(InputTextStyle::Paragraph, "Emoji and roles, one per line", ROLES_INPUT)
+            .placeholder("🔴 `Red`\n🔵 `Blue` [requires `Member`]")),
+    ]);
+    command.create_response(&ctx.http, CreateInteractionResponse::Modal(form)).await?;
+
+    let submission = ModalInteractionCollector::new(&ctx.shard)
+        .custom_ids(vec![form_id])
+        .timeout(FORM_TIMEOUT)
+        .await;
+    let submission = match submission {
+        Some(submission) => submission,
+        None => return Ok(()),
+    };
+    submission.defer_ephemeral(&ctx.http).await?;
+
+    let title = input(&submission, TITLE_INPUT);
+    let lines = input(&submission, ROLES_INPUT);
+    let names = roles_by_name(ctx, Some(guild), &lines).await;
+    let selector = match validate(&lines, |name| names.get(&name.to_lowercase()).copied()) {
+        Ok(selector) => selector,
+        Err(problems) => {
+            let mut reply = vec!["That can't be made into a selector:".to_owned()];
+            reply.extend(problems.iter().map(|problem| format!("• {}", problem)));
+            submission.edit_response(&ctx.http, EditInteractionResponse::new().content(reply.join("\n"))).await?;
+            return Ok(());
+        }
+    };
+
+    let mut preview = vec!["Here are the roles the selector hands out:".to_owned()];
+    preview.extend(preview::swatches(ctx, guild, &selector).await.iter().map(preview::Swatch::describe));
+    let buttons = CreateActionRow::Buttons(vec![
+        CreateButton::new(POST_BUTTON).label("Post").style(ButtonStyle::Success),
+        CreateButton::new(CANCEL_BUTTON).label("Cancel").style(ButtonStyle::Secondary),
+    ]);
+    let prompt = submission.edit_response(&ctx.http, EditInteractionResponse::new()
+        .content(preview.join("\n"))
+        .components(vec![buttons])
+    ).await?;
+
+    let answer = prompt.await_component_interaction(&ctx.shard)
+        .author_id(command.user.id)
+        .timeout(CONFIRM_TIMEOUT)
+        .await;
+    let answer = match answer {
+        Some(answer) if answer.data.custom_id == POST_BUTTON => answer,
+        _ => {
+            submission.edit_response(&ctx.http, EditInteractionResponse::new().content("Cancelled.").components(Vec::new())).await?;
+            return Ok(());
+        }
+    };
+    answer.create_response(&ctx.http, CreateInteractionResponse::UpdateMessage(
+        CreateInteractionResponseMessage::new().content("Posting the selector…").components(Vec::new())
+    )).await?;
+
+    let lines = mention_named_roles(&lines, &names);
+    let content = if title.trim().is_empty() { lines } else { format!("{}\n{}", title.trim(), lines) };
+    let message = command.channel_id.send_message(&ctx.http, CreateMessage::new()
+        .content(content)
+        .allowed_mentions(CreateAllowedMentions::new())
+    ).await?;
+    register_selector(ctx, &message, command.user.id, |_| {}).await;
+
+    let link = audit::message_link(guild, message.channel_id, message.id);
+    submission.edit_response(&ctx.http, EditInteractionResponse::new().content(format!("✅ Posted {}", link))).await?;
+
+    Ok(())
+}
+
+/// The value of a form's text input, which is empty if it was left out.
+fn input(submission: &ModalInteraction, custom_id: &str) -> String {
+    submission.data.components.iter()
+        .flat_map(|row| &row.components)
+        .find_map(|component| match component {
+            ActionRowComponent::InputText(text) if text.custom_id == custom_id => text.value.clone(),
+            _ => None,
+        })
+        .unwrap_or_default()
+}
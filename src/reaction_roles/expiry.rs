@@ -0,0 +1,123 @@
+use std::collections::HashSet;
+
+use serde::{Deserialize, Serialize};
+use serenity::model::prelude::*;
+use serenity::prelude::*;
+
+use super::Emoji;
+use crate::{Persistent, read_only, scheduler, store};
+use crate::memreport::{Introspect, Owner, Reference};
+
+pub struct StateKey;
+
+impl TypeMapKey for StateKey {
+    type Value = Persistent<State>;
+}
+
+/// Roles granted by selector options that expire, to be taken away again once they do.
+#[derive(Serialize, Deserialize, Default, Clone, Eq, PartialEq)]
+pub struct State {
+    grants: Vec<ExpiringGrant>,
+}
+
+impl Introspect for State {
+    fn entries(&self) -> Vec<(Owner, usize)> {
+        self.grants.iter().map(|grant| (Owner::Guild(grant.guild), 1)).collect()
+    }
+
+    fn references(&self) -> Vec<(Owner, Reference)> {
+        self.grants.iter()
+            .flat_map(|grant| grant.roles.iter().map(move |role| (Owner::Guild(grant.guild), Reference::Role(*role))))
+            .collect()
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Eq, PartialEq)]
+pub struct ExpiringGrant {
+    pub guild: GuildId,
+    pub user: UserId,
+    pub channel: ChannelId,
+    pub message: MessageId,
+    pub emoji: Emoji,
+    /// The roles that were granted, which are the ones taken away even if the option changed since.
+    pub roles: Vec<RoleId>,
+    pub expires_at: i64,
+}
+
+/// Remembers to take the roles of a selector option away again, replacing any earlier grant of it.
+pub async fn schedule(ctx: &Context, grant: ExpiringGrant) {
+    let state = store::<StateKey>(ctx).await;
+    state.write(|state| {
+        state.grants.retain(|other| (other.user, other.message, &other.emoji) != (grant.user, grant.message, &grant.emoji));
+        state.grants.push(grant);
+    }).await;
+}
+
+/// Forgets about a grant whose roles were already taken away.
+pub async fn cancel(ctx: &Context, user: UserId, message: MessageId, emoji: &Emoji) {
+    let state = store::<StateKey>(ctx).await;
+    if !state.read().await.grants.iter().any(|grant| (grant.user, grant.message, &grant.emoji) == (user, message, emoji)) {
+        return;
+    }
+    state.write(|state| {
+        state.grants.retain(|grant| (grant.user, grant.message, &grant.emoji) != (user, message, emoji));
+    }).await;
+}
+
+/// Points grants of a selector at the message it was moved to, so that they're still cancelled
+/// when the reaction there is removed.
+pub async fn moved(ctx: &Context, old: MessageId, channel: ChannelId, new: MessageId) {
+    let state = store::<StateKey>(ctx).await;
+    if !state.read().await.grants.iter().any(|grant| grant.message == old) {
+        return;
+    }
+    state.write(|state| {
+        for grant in state.grants.iter_mut().filter(|grant| grant.message == old) {
+            grant.channel = channel;
+            grant.message = new;
+        }
+    }).await;
+}
+
+/// Takes away the roles of grants that have expired.
+pub async fn expire_grants(ctx: Context) {
+    let now = scheduler::now();
+    let due: Vec<ExpiringGrant> = {
+        let state = store::<StateKey>(&ctx).await;
+        let due_guilds: HashSet<GuildId> = state.read().await.grants.iter()
+            .filter(|grant| grant.expires_at <= now)
+            .map(|grant| grant.guild)
+            .collect();
+
+        // grants of read-only guilds are kept until their roles can be taken away
+        let mut held = HashSet::new();
+        for guild in due_guilds {
+            if read_only::blocks(&ctx, guild, || "taken expired selector roles away".to_owned()).await {
+                held.insert(guild);
+            }
+        }
+
+        state.write(|state| {
+            let (due, waiting) = state.grants.drain(..).partition(|grant| grant.expires_at <= now && !held.contains(&grant.guild));
+            state.grants = waiting;
+            due
+        }).await
+    };
+
+    for grant in due {
+        super::revoke_expired(&ctx, grant).await;
+    }
+}
+
+/// Forgets the grants of all guilds not in `guilds`, returning the ones that were removed.
+pub async fn retain_guilds(ctx: &Context, guilds: &HashSet<GuildId>) -> Vec<GuildId> {
+    let state = store::<StateKey>(ctx).await;
+    state.write(|state| {
+        let removed: HashSet<GuildId> = state.grants.iter()
+            .map(|grant| grant.guild)
+            .filter(|guild| !guilds.contains(guild))
+            .collect();
+        state.grants.retain(|grant| guilds.contains(&grant.guild));
+        removed.into_iter().collect()
+    }).await
+}
@@ -0,0 +1,86 @@
+use std::collections::HashMap;
+
+use serenity::builder::{CreateAllowedMentions, CreateMessage, EditRole};
+use serenity::model::prelude::*;
+use serenity::prelude::*;
+
+use super::register_selector;
+use crate::{audit, channel_links, CommandError, CommandResult, confirm, quotas, role_admin};
+use crate::quotas::Quota;
+
+/// Emoji handed out to the channels of a generated selector, in order. Each is a single code point
+/// so that selector parsing picks it up as one emoji.
+const EMOJI: &[&str] = &[
+    "🔴", "🟠", "🟡", "🟢", "🔵", "🟣", "🟤", "⚫", "⚪", "🟥",
+    "🟧", "🟨", "🟩", "🟦", "🟪", "🟫", "⬛", "⬜", "🔶", "🔷",
+];
+
+/// Creates a selector with one emoji for each channel in a category, each granting a role that is
+/// linked to the channel so that only its holders can see it. Roles named after a channel are reused,
+/// and missing ones are created once confirmed.
+pub async fn from_category(ctx: &Context, command: &Message, category: ChannelId) -> CommandResult<()> {
+    let guild = command.guild_id.ok_or(CommandError::NotAllowed)?;
+    quotas::check(ctx, guild, Quota::Selectors, 1).await?;
+
+    let channels = guild.channels(&ctx.http).await?;
+    let category_name = match channels.get(&category) {
+        Some(channel) if channel.kind == ChannelType::Category => channel.name.clone(),
+        _ => return Err(CommandError::MalformedArgument(format!("<#{}>", category))),
+    };
+
+    let mut children: Vec<&GuildChannel> = channels.values()
+        .filter(|channel| channel.parent_id == Some(category))
+        .collect();
+    children.sort_by_key(|channel| (channel.position, channel.id));
+    if children.is_empty() || children.len() > EMOJI.len() {
+        return Err(CommandError::UnsuitableCategory(EMOJI.len()));
+    }
+
+    let roles: HashMap<String, RoleId> = ctx.http.get_guild_roles(guild).await?
+        .into_iter()
+        .map(|role| (role.name.to_lowercase(), role.id))
+        .collect();
+
+    let missing: Vec<&str> = children.iter()
+        .filter(|channel| !roles.contains_key(&channel.name.to_lowercase()))
+        .map(|channel| channel.name.as_str())
+        .collect();
+
+    let mut prompt = format!("This will hide the {} channels in **{}** from everyone except holders of a matching role", children.len(), category_name);
+    if !missing.is_empty() {
+        let listed: Vec<String> = missing.iter().map(|name| format!("`{}`", name)).collect();
+        prompt.push_str(&format!(", creating these roles: {}", listed.join(", ")));
+    }
+    prompt.push_str(". Should I go ahead?");
+    if !confirm::ask(ctx, command.channel_id, command.author.id, prompt).await? {
+        return Ok(());
+    }
+
+    let mut lines = vec![format!("**Pick the channels you want to see in {}:**", category_name)];
+    for (channel, emoji) in children.iter().zip(EMOJI) {
+        let role = match roles.get(&channel.name.to_lowercase()) {
+            Some(role) => {
+                role_admin::check_hierarchy(ctx, guild, command.author.id, *role).await?;
+                *role
+            }
+            None => {
+                let role = guild.create_role(&ctx.http, EditRole::new().name(&channel.name).permissions(Permissions::empty()).hoist(false).mentionable(false)).await?;
+                role.id
+            }
+        };
+
+        channel_links::link(ctx, guild, role, channel.id).await?;
+        lines.push(format!("{} <@&{}> {}", emoji, role, channel.name));
+    }
+
+    let message = command.channel_id.send_message(&ctx.http, CreateMessage::new().content(lines.join("\n")).allowed_mentions(CreateAllowedMentions::new())).await?;
+
+    audit::log(ctx, guild, format!(
+        "<@{}> generated a selector for the {} channels in **{}**, creating {} roles",
+        command.author.id.get(), children.len(), category_name, missing.len(),
+    )).await;
+
+    register_selector(ctx, &message, command.author.id, |_| {}).await;
+
+    Ok(())
+}
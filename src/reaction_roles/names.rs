@@ -0,0 +1,150 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use serde::{Deserialize, Serialize};
+use serenity::model::prelude::*;
+use serenity::prelude::*;
+
+use crate::{audit, CommandError, CommandResult, Persistent, store};
+use crate::memreport::{Introspect, Owner};
+
+/// The longest a selector name can be.
+pub const MAX_NAME_LEN: usize = 32;
+
+pub struct StateKey;
+
+impl TypeMapKey for StateKey {
+    type Value = Persistent<State>;
+}
+
+/// Names given to selectors in each guild, so that commands can refer to them without the message ID.
+#[derive(Serialize, Deserialize, Default, Clone, Eq, PartialEq)]
+pub struct State {
+    guilds: HashMap<GuildId, BTreeMap<String, MessageId>>,
+}
+
+impl Introspect for State {
+    fn entries(&self) -> Vec<(Owner, usize)> {
+        self.guilds.iter().map(|(guild, names)| (Owner::Guild(*guild), names.len())).collect()
+    }
+}
+
+impl State {
+    pub fn resolve(&self, guild: GuildId, name: &str) -> Option<MessageId> {
+        self.guilds.get(&guild)?.get(name).copied()
+    }
+
+    pub fn name_of(&self, guild: GuildId, message: MessageId) -> Option<&str> {
+        self.guilds.get(&guild)?.iter()
+            .find(|(_, named)| **named == message)
+            .map(|(name, _)| name.as_str())
+    }
+
+    /// Names a selector, replacing any name it had before. Fails if another selector has the name.
+    pub fn set_name(&mut self, guild: GuildId, message: MessageId, name: String) -> bool {
+        let names = self.guilds.entry(guild).or_default();
+        match names.get(&name) {
+            Some(named) if *named != message => return false,
+            _ => (),
+        }
+        names.retain(|_, named| *named != message);
+        names.insert(name, message);
+        true
+    }
+
+    /// Moves the name of a selector over to the message it was reposted as.
+    pub fn moved(&mut self, old: MessageId, new: MessageId) {
+        for named in self.guilds.values_mut().flat_map(|names| names.values_mut()) {
+            if *named == old {
+                *named = new;
+            }
+        }
+    }
+
+    pub fn forget(&mut self, message: MessageId) {
+        for names in self.guilds.values_mut() {
+            names.retain(|_, named| *named != message);
+        }
+        self.guilds.retain(|_, names| !names.is_empty());
+    }
+}
+
+/// Selector names are short lowercase words, which can't be all digits so that they're never
+/// mistaken for message IDs.
+pub fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= MAX_NAME_LEN
+        && name.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '_')
+        && !name.chars().all(|c| c.is_ascii_digit())
+}
+
+/// Finds the selector a command refers to, either by its message ID or by its name in the guild.
+pub async fn resolve(ctx: &Context, command: &Message, reference: &str) -> CommandResult<MessageId> {
+    if let Ok(id) = reference.parse() {
+        return Ok(id);
+    }
+
+    let name = reference.to_ascii_lowercase();
+    if !is_valid_name(&name) {
+        return Err(CommandError::MalformedArgument(reference.to_owned()));
+    }
+    let guild = command.guild_id.ok_or(CommandError::NotAllowed)?;
+    let state = store::<StateKey>(ctx).await;
+    let message = state.read().await.resolve(guild, &name);
+    message.ok_or(CommandError::UnknownSelectorName)
+}
+
+pub async fn name_selector(ctx: &Context, command: &Message, message: MessageId, name: &str) -> CommandResult<()> {
+    let guild = command.guild_id.ok_or(CommandError::NotAllowed)?;
+
+    let name = name.to_ascii_lowercase();
+    if !is_valid_name(&name) {
+        return Err(CommandError::InvalidSelectorName);
+    }
+    let channel = super::selector_channel(ctx, message).await.ok_or(CommandError::UnknownSelector)?;
+
+    let state = store::<StateKey>(ctx).await;
+    if !state.write(|state| state.set_name(guild, message, name.clone())).await {
+        return Err(CommandError::SelectorNameTaken);
+    }
+
+    audit::log(ctx, guild, format!(
+        "<@{}> named selector {} `{}`",
+        command.author.id.get(), audit::message_link(guild, channel, message), name,
+    )).await;
+
+    Ok(())
+}
+
+pub async fn unname_selector(ctx: &Context, command: &Message, message: MessageId) -> CommandResult<()> {
+    let guild = command.guild_id.ok_or(CommandError::NotAllowed)?;
+
+    let state = store::<StateKey>(ctx).await;
+    if state.read().await.name_of(guild, message).is_none() {
+        return Err(CommandError::UnknownSelectorName);
+    }
+    state.write(|state| state.forget(message)).await;
+
+    Ok(())
+}
+
+pub async fn name_of(ctx: &Context, guild: GuildId, message: MessageId) -> Option<String> {
+    store::<StateKey>(ctx).await.read().await.name_of(guild, message).map(str::to_owned)
+}
+
+pub async fn moved(ctx: &Context, old: MessageId, new: MessageId) {
+    store::<StateKey>(ctx).await.write(|state| state.moved(old, new)).await;
+}
+
+pub async fn forget(ctx: &Context, message: MessageId) {
+    store::<StateKey>(ctx).await.write(|state| state.forget(message)).await;
+}
+
+/// Forgets all guilds not in `guilds`, returning the ones that were removed.
+pub async fn retain_guilds(ctx: &Context, guilds: &HashSet<GuildId>) -> Vec<GuildId> {
+    let state = store::<StateKey>(ctx).await;
+    state.write(|state| {
+        let removed = state.guilds.keys().filter(|guild| !guilds.contains(guild)).copied().collect();
+        state.guilds.retain(|guild, _| guilds.contains(guild));
+        removed
+    }).await
+}
@@ -0,0 +1,263 @@
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+
+use log::error;
+use serde::{Deserialize, Serialize};
+use serenity::model::prelude::*;
+use serenity::prelude::*;
+use tokio::time::Instant;
+
+use super::{choose, get_enabled_selector, role_names, selector_channel, Emoji, Outcome};
+use crate::{CommandError, CommandResult, eligibility, members, Persistent, read_only, say_lines, selector_bans, store};
+use crate::memreport::{Introspect, Owner};
+
+/// How long a new member has to answer before the offer is withdrawn.
+const ANSWER_TIMEOUT: Duration = Duration::from_secs(15 * 60);
+
+/// The most selectors a guild can offer when members join, which keeps the list within one DM.
+pub const MAX_SELECTORS: usize = 5;
+
+pub struct StateKey;
+
+impl TypeMapKey for StateKey {
+    type Value = Persistent<State>;
+}
+
+/// The selectors whose roles are offered to new members over DM, by guild.
+#[derive(Serialize, Deserialize, Default, Clone, Eq, PartialEq)]
+pub struct State {
+    guilds: HashMap<GuildId, Vec<MessageId>>,
+}
+
+impl Introspect for State {
+    fn entries(&self) -> Vec<(Owner, usize)> {
+        self.guilds.iter().map(|(guild, selectors)| (Owner::Guild(*guild), selectors.len())).collect()
+    }
+}
+
+/// Members who are being asked which roles they want, so that they're only asked once at a time.
+static CONVERSATIONS: std::sync::Mutex<Option<HashSet<(GuildId, UserId)>>> = std::sync::Mutex::new(None);
+
+/// One numbered choice in the DM: an option of a selector.
+#[derive(Clone, Debug)]
+pub struct Choice {
+    pub channel: ChannelId,
+    pub message: MessageId,
+    pub emoji: Emoji,
+    pub roles: Vec<RoleId>,
+    pub description: Option<String>,
+}
+
+/// Reads a reply like `1 3`, `2, 4` or `none` into the indices of the chosen options, or `None`
+/// if it isn't one.
+pub fn parse_choices(reply: &str, count: usize) -> Option<Vec<usize>> {
+    let reply = reply.trim();
+    if reply.eq_ignore_ascii_case("none") || reply.eq_ignore_ascii_case("skip") {
+        return Some(Vec::new());
+    }
+
+    let mut chosen = Vec::new();
+    for token in reply.split(|c: char| c == ',' || c.is_whitespace()).filter(|token| !token.is_empty()) {
+        let number: usize = token.parse().ok()?;
+        if number == 0 || number > count {
+            return None;
+        }
+        if !chosen.contains(&(number - 1)) {
+            chosen.push(number - 1);
+        }
+    }
+    if chosen.is_empty() { None } else { Some(chosen) }
+}
+
+pub async fn set_selectors(ctx: &Context, command: &Message, selectors: Vec<MessageId>) -> CommandResult<()> {
+    let guild = command.guild_id.ok_or(CommandError::NotAllowed)?;
+    if selectors.len() > MAX_SELECTORS {
+        return Err(CommandError::InvalidCommand);
+    }
+    for selector in &selectors {
+        if selector_channel(ctx, *selector).await.is_none() {
+            return Err(CommandError::UnknownSelector);
+        }
+    }
+
+    let state = store::<StateKey>(ctx).await;
+    state.write(|state| {
+        if selectors.is_empty() {
+            state.guilds.remove(&guild);
+        } else {
+            state.guilds.insert(guild, selectors);
+        }
+    }).await;
+
+    Ok(())
+}
+
+pub async fn status(ctx: &Context, command: &Message) -> CommandResult<()> {
+    let guild = command.guild_id.ok_or(CommandError::NotAllowed)?;
+    let selectors = store::<StateKey>(ctx).await.read().await.guilds.get(&guild).cloned().unwrap_or_default();
+
+    let lines = if selectors.is_empty() {
+        vec!["New members aren't offered roles over DM. Offer them with `onboarding dm <selectors>`.".to_owned()]
+    } else {
+        let choices = choices(ctx, &selectors).await;
+        vec![format!("👋 New members are offered {} roles over DM from {} selectors.", choices.len(), selectors.len())]
+    };
+    say_lines(ctx, command.channel_id, &lines).await?;
+
+    Ok(())
+}
+
+/// The options of the given selectors that can be chosen, leaving out paused and deleted selectors.
+async fn choices(ctx: &Context, selectors: &[MessageId]) -> Vec<Choice> {
+    let mut choices = Vec::new();
+    for message in selectors {
+        let (selector, channel) = match (get_enabled_selector(ctx, *message).await, selector_channel(ctx, *message).await) {
+            (Some(selector), Some(channel)) => (selector, channel),
+            _ => continue,
+        };
+        choices.extend(selector.options().iter()
+            .filter(|option| !option.roles.is_empty())
+            .map(|option| Choice {
+                channel,
+                message: *message,
+                emoji: option.emoji.clone(),
+                roles: option.roles.clone(),
+                description: option.description.clone(),
+            }));
+    }
+    choices
+}
+
+/// Offers a member who just joined the roles of the guild's onboarding selectors over DM, without
+/// holding up whoever called it.
+pub fn member_joined(ctx: &Context, guild: GuildId, user: UserId) {
+    let ctx = ctx.clone();
+    tokio::spawn(async move {
+        if !start_conversation(guild, user) {
+            return;
+        }
+        if let Err(err) = offer(&ctx, guild, user).await {
+            error!("failed to offer onboarding roles to {} in {}: {:?}", user, guild, err);
+        }
+        end_conversation(guild, user);
+    });
+}
+
+fn start_conversation(guild: GuildId, user: UserId) -> bool {
+    CONVERSATIONS.lock().unwrap().get_or_insert_with(HashSet::new).insert((guild, user))
+}
+
+fn end_conversation(guild: GuildId, user: UserId) {
+    if let Some(conversations) = CONVERSATIONS.lock().unwrap().as_mut() {
+        conversations.remove(&(guild, user));
+    }
+}
+
+async fn offer(ctx: &Context, guild: GuildId, user: UserId) -> serenity::Result<()> {
+    let selectors = store::<StateKey>(ctx).await.read().await.guilds.get(&guild).cloned().unwrap_or_default();
+    if selectors.is_empty() || selector_bans::is_banned(ctx, guild, user).await {
+        return Ok(());
+    }
+    if read_only::blocks(ctx, guild, || format!("offered roles to {} over DM", user)).await {
+        return Ok(());
+    }
+    let choices = choices(ctx, &selectors).await;
+    if choices.is_empty() {
+        return Ok(());
+    }
+
+    // members who can't be messaged just use the selectors like everyone else
+    let dm = match user.create_dm_channel(&ctx.http).await {
+        Ok(dm) => dm,
+        Err(_) => return Ok(()),
+    };
+
+    let mut lines = vec![format!(
+        "👋 Welcome to **{}**! Reply with the numbers of the roles you'd like, like `1 3`, or `none`.",
+        guild.name(ctx).unwrap_or_default(),
+    )];
+    for (index, choice) in choices.iter().enumerate() {
+        let mut line = format!("`{}` {} {}", index + 1, choice.emoji, role_names(ctx, guild, &choice.roles).await);
+        if let Some(description) = &choice.description {
+            line.push_str(&format!(" — {}", description));
+        }
+        lines.push(line);
+    }
+    if say_lines(ctx, dm.id, &lines).await.is_err() {
+        return Ok(());
+    }
+
+    let deadline = Instant::now() + ANSWER_TIMEOUT;
+    let chosen = loop {
+        let reply = dm.id.await_reply(ctx)
+            .author_id(user)
+            .timeout(deadline.saturating_duration_since(Instant::now()))
+            .await;
+        let reply = match reply {
+            Some(reply) => reply,
+            None => {
+                dm.say(&ctx.http, "⌛ You didn't answer in time, but you can still pick roles in the server.").await?;
+                return Ok(());
+            }
+        };
+        match parse_choices(&reply.content, choices.len()) {
+            Some(chosen) => break chosen,
+            None => {
+                dm.say(&ctx.http, format!("Please reply with numbers from 1 to {}, or `none`.", choices.len())).await?;
+            }
+        }
+    };
+
+    let mut results = Vec::new();
+    for index in chosen {
+        results.push(grant(ctx, guild, user, &choices[index]).await);
+    }
+    let content = if results.is_empty() {
+        "👍 No roles then. You can still pick some in the server later.".to_owned()
+    } else {
+        results.join("\n")
+    };
+    dm.say(&ctx.http, content).await?;
+
+    Ok(())
+}
+
+/// Gives a member the roles of a choice as if they had reacted to its selector, saying how it went.
+async fn grant(ctx: &Context, guild: GuildId, user: UserId, choice: &Choice) -> String {
+    let names = role_names(ctx, guild, &choice.roles).await;
+
+    // the selector may have changed while the member was choosing
+    let selector = match get_enabled_selector(ctx, choice.message).await {
+        Some(selector) if selector.get_roles(&choice.emoji) == Some(choice.roles.as_slice()) => selector,
+        _ => return format!("❌ **{}** can't be picked anymore.", names),
+    };
+    let mut member = match members::member(ctx, guild, user).await {
+        Ok(member) => member,
+        Err(_) => return format!("❌ You couldn't get **{}**, since you're no longer in the server.", names),
+    };
+    if eligibility::eligible_at(ctx, &member).await.is_some() {
+        return format!("⏳ You can't get **{}** yet, since you're too new. Pick it in the server later.", names);
+    }
+
+    match choose(ctx, &selector, &mut member, choice.channel, choice.message, &choice.emoji).await {
+        Ok(Outcome::Granted) => format!("✅ You got **{}**.", names),
+        Ok(Outcome::Requested) => format!("📨 **{}** needs approval, which has been requested.", names),
+        Ok(Outcome::Full(_, limit)) => format!("❌ **{}** already has {} members, which is as many as it can.", names, limit),
+        Ok(Outcome::Unqualified(missing)) => format!("❌ **{}** needs **{}** first.", names, role_names(ctx, guild, &missing).await),
+        Ok(Outcome::Declined) => format!("❌ You kept your other roles instead of **{}**.", names),
+        Err(err) => {
+            error!("failed to give onboarding roles to {} in {}: {:?}", user, guild, err);
+            format!("❌ **{}** couldn't be given right now.", names)
+        }
+    }
+}
+
+/// Forgets all guilds not in `guilds`, returning the ones that were removed.
+pub async fn retain_guilds(ctx: &Context, guilds: &HashSet<GuildId>) -> Vec<GuildId> {
+    let state = store::<StateKey>(ctx).await;
+    state.write(|state| {
+        let removed = state.guilds.keys().filter(|guild| !guilds.contains(guild)).copied().collect();
+        state.guilds.retain(|guild, _| guilds.contains(guild));
+        removed
+    }).await
+}
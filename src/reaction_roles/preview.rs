@@ -0,0 +1,136 @@
+use std::io::Write;
+
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use serenity::model::prelude::*;
+use serenity::prelude::*;
+
+use super::Selector;
+
+pub const FILE_NAME: &str = "roles.png";
+
+const SWATCH_WIDTH: u32 = 240;
+const SWATCH_HEIGHT: u32 = 24;
+const GAP: u32 = 4;
+
+/// The colour Discord shows roles without a colour in.
+const DEFAULT_COLOUR: u32 = 0x99aab5;
+
+/// The coloured squares available as emoji, to stand in for role colours in text.
+const SQUARES: &[(u32, &str)] = &[
+    (0xdd2e44, "🟥"),
+    (0xf4900c, "🟧"),
+    (0xfdcb58, "🟨"),
+    (0x78b159, "🟩"),
+    (0x55acee, "🟦"),
+    (0xaa8ed6, "🟪"),
+    (0xc1694f, "🟫"),
+    (0x31373d, "⬛"),
+    (0xe6e7e8, "⬜"),
+];
+
+/// One role of a selector as it is shown in the preview.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Swatch {
+    pub emoji: String,
+    pub name: String,
+    /// The role's colour, or 0 if it doesn't have one.
+    pub colour: u32,
+}
+
+impl Swatch {
+    fn shown_colour(&self) -> u32 {
+        if self.colour == 0 { DEFAULT_COLOUR } else { self.colour }
+    }
+
+    /// A line naming the role, with the square closest to its colour and the exact colour.
+    pub fn describe(&self) -> String {
+        match self.colour {
+            0 => format!("{} ⬜ **{}** (no colour)", self.emoji, self.name),
+            colour => format!("{} {} **{}** `#{:06x}`", self.emoji, nearest_square(colour), self.name, colour),
+        }
+    }
+}
+
+/// Looks up the names and colours of the roles of a selector, in the order of its emoji.
+pub async fn swatches(ctx: &Context, guild: GuildId, selector: &Selector) -> Vec<Swatch> {
+    let mut swatches = Vec::new();
+    for (emoji, roles) in selector.iter() {
+        for role in roles {
+            if let Some(role) = ctx.cache.guild(guild).and_then(|guild| guild.roles.get(role).cloned()) {
+                swatches.push(Swatch { emoji: emoji.to_string(), name: role.name, colour: role.colour.0 });
+            }
+        }
+    }
+    swatches
+}
+
+/// Finds the coloured square emoji closest to a colour.
+pub fn nearest_square(colour: u32) -> &'static str {
+    let channels = |colour: u32| [(colour >> 16) & 0xff, (colour >> 8) & 0xff, colour & 0xff];
+    let [r, g, b] = channels(colour);
+    SQUARES.iter()
+        .min_by_key(|(square, _)| {
+            let [sr, sg, sb] = channels(*square);
+            let distance = |a: u32, b: u32| (a as i64 - b as i64).pow(2);
+            distance(r, sr) + distance(g, sg) + distance(b, sb)
+        })
+        .map(|(_, emoji)| *emoji)
+        .unwrap()
+}
+
+/// Draws a bar in the colour of each swatch, stacked from top to bottom in the order of the list.
+pub fn render(swatches: &[Swatch]) -> Vec<u8> {
+    let count = swatches.len().max(1) as u32;
+    let height = count * SWATCH_HEIGHT + (count - 1) * GAP;
+
+    let mut pixels = Vec::with_capacity((height * (SWATCH_WIDTH * 3 + 1)) as usize);
+    for y in 0..height {
+        // every row starts with the filter type, which is always none
+        pixels.push(0);
+        let index = (y / (SWATCH_HEIGHT + GAP)) as usize;
+        let in_gap = y % (SWATCH_HEIGHT + GAP) >= SWATCH_HEIGHT;
+        let colour = match swatches.get(index) {
+            Some(swatch) if !in_gap => Some(swatch.shown_colour()),
+            _ => None,
+        };
+        for _ in 0..SWATCH_WIDTH {
+            match colour {
+                Some(colour) => pixels.extend_from_slice(&[(colour >> 16) as u8, (colour >> 8) as u8, colour as u8]),
+                None => pixels.extend_from_slice(&[0xff, 0xff, 0xff]),
+            }
+        }
+    }
+
+    encode_png(SWATCH_WIDTH, height, &pixels)
+}
+
+/// Encodes filtered 8-bit RGB scanlines as a PNG.
+fn encode_png(width: u32, height: u32, scanlines: &[u8]) -> Vec<u8> {
+    let mut header = Vec::with_capacity(13);
+    header.extend_from_slice(&width.to_be_bytes());
+    header.extend_from_slice(&height.to_be_bytes());
+    // 8 bits per channel, RGB, default compression, filtering and no interlacing
+    header.extend_from_slice(&[8, 2, 0, 0, 0]);
+
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(scanlines).expect("writing to a vec can't fail");
+    let data = encoder.finish().expect("writing to a vec can't fail");
+
+    let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+    write_chunk(&mut png, b"IHDR", &header);
+    write_chunk(&mut png, b"IDAT", &data);
+    write_chunk(&mut png, b"IEND", &[]);
+    png
+}
+
+fn write_chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    png.extend_from_slice(kind);
+    png.extend_from_slice(data);
+
+    let mut crc = crc32fast::Hasher::new();
+    crc.update(kind);
+    crc.update(data);
+    png.extend_from_slice(&crc.finalize().to_be_bytes());
+}
@@ -0,0 +1,258 @@
+use std::collections::{HashMap, HashSet};
+
+use serde::{Deserialize, Serialize};
+use serenity::model::prelude::*;
+use serenity::prelude::*;
+
+use crate::{audit, CommandError, CommandResult, events, Persistent, role_queue, say_lines, selector_bans, store};
+use crate::events::{Event, RoleSource};
+use crate::memreport::{Introspect, Owner, Reference};
+
+/// The most messages a role can track, which keeps its setup within one command.
+pub const MAX_MESSAGES: usize = 10;
+
+pub struct StateKey;
+
+impl TypeMapKey for StateKey {
+    type Value = Persistent<State>;
+}
+
+/// Roles given for reacting to enough messages of a set, like every rules message, by guild.
+#[derive(Serialize, Deserialize, Default, Clone, Eq, PartialEq)]
+pub struct State {
+    guilds: HashMap<GuildId, Vec<Tracker>>,
+}
+
+impl Introspect for State {
+    fn entries(&self) -> Vec<(Owner, usize)> {
+        self.guilds.iter()
+            .map(|(guild, trackers)| (Owner::Guild(*guild), trackers.iter().map(|tracker| 1 + tracker.progress.len()).sum()))
+            .collect()
+    }
+
+    fn references(&self) -> Vec<(Owner, Reference)> {
+        self.guilds.iter()
+            .flat_map(|(guild, trackers)| trackers.iter().map(move |tracker| (Owner::Guild(*guild), Reference::Role(tracker.role))))
+            .collect()
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq)]
+pub struct Tracker {
+    pub role: RoleId,
+    pub messages: Vec<MessageId>,
+    /// How many of the messages a member has to react to.
+    pub required: usize,
+    /// The messages each member has reacted to so far, until they reach `required`.
+    #[serde(default)]
+    pub progress: HashMap<UserId, HashSet<MessageId>>,
+}
+
+impl Tracker {
+    pub fn new(role: RoleId, messages: Vec<MessageId>, required: usize) -> Tracker {
+        Tracker { role, messages, required, progress: HashMap::new() }
+    }
+
+    /// Records a member reacting to a message, returning whether that was the last one they needed.
+    /// Their progress is forgotten once complete, since they have the role from then on.
+    pub fn react(&mut self, user: UserId, message: MessageId) -> bool {
+        if !self.messages.contains(&message) {
+            return false;
+        }
+
+        let reacted = self.progress.entry(user).or_default();
+        reacted.insert(message);
+        if reacted.len() >= self.required {
+            self.progress.remove(&user);
+            true
+        } else {
+            false
+        }
+    }
+
+    pub fn unreact(&mut self, user: UserId, message: MessageId) {
+        if let Some(reacted) = self.progress.get_mut(&user) {
+            reacted.remove(&message);
+            if reacted.is_empty() {
+                self.progress.remove(&user);
+            }
+        }
+    }
+
+    /// Stops tracking a deleted message, lowering how many are required if there are too few left.
+    pub fn forget_message(&mut self, message: MessageId) {
+        self.messages.retain(|tracked| *tracked != message);
+        self.required = self.required.min(self.messages.len());
+        self.progress.retain(|_, reacted| {
+            reacted.remove(&message);
+            !reacted.is_empty()
+        });
+    }
+
+    pub fn forget_user(&mut self, user: UserId) {
+        self.progress.remove(&user);
+    }
+}
+
+pub async fn set_tracker(ctx: &Context, command: &Message, role: RoleId, messages: Vec<MessageId>, required: Option<usize>) -> CommandResult<()> {
+    let guild = command.guild_id.ok_or(CommandError::NotAllowed)?;
+    if messages.is_empty() || messages.len() > MAX_MESSAGES {
+        return Err(CommandError::InvalidCommand);
+    }
+    let required = required.unwrap_or(messages.len());
+    if required == 0 || required > messages.len() {
+        return Err(CommandError::InvalidCommand);
+    }
+    if ctx.cache.guild(guild).is_some_and(|guild| !guild.roles.contains_key(&role)) {
+        return Err(CommandError::UnknownRole);
+    }
+
+    let state = store::<StateKey>(ctx).await;
+    state.write(|state| {
+        let trackers = state.guilds.entry(guild).or_default();
+        trackers.retain(|tracker| tracker.role != role);
+        trackers.push(Tracker::new(role, messages, required));
+    }).await;
+
+    Ok(())
+}
+
+pub async fn remove_tracker(ctx: &Context, command: &Message, role: RoleId) -> CommandResult<()> {
+    let guild = command.guild_id.ok_or(CommandError::NotAllowed)?;
+
+    let state = store::<StateKey>(ctx).await;
+    let removed = state.write(|state| {
+        let trackers = match state.guilds.get_mut(&guild) {
+            Some(trackers) => trackers,
+            None => return false,
+        };
+        let count = trackers.len();
+        trackers.retain(|tracker| tracker.role != role);
+        let removed = trackers.len() < count;
+        if trackers.is_empty() {
+            state.guilds.remove(&guild);
+        }
+        removed
+    }).await;
+
+    if removed { Ok(()) } else { Err(CommandError::UntrackedRole) }
+}
+
+pub async fn list(ctx: &Context, command: &Message) -> CommandResult<()> {
+    let guild = command.guild_id.ok_or(CommandError::NotAllowed)?;
+    let trackers = store::<StateKey>(ctx).await.read().await.guilds.get(&guild).cloned().unwrap_or_default();
+
+    let lines = if trackers.is_empty() {
+        vec!["No roles are given for reacting to a set of messages. Add one with `progress role <role> [count] <message links>`.".to_owned()]
+    } else {
+        let mut lines = vec!["**Progress roles**".to_owned()];
+        for tracker in trackers {
+            lines.push(format!(
+                "<@&{}>: react to {} of {} messages ({} members part of the way)",
+                tracker.role.get(), tracker.required, tracker.messages.len(), tracker.progress.len(),
+            ));
+        }
+        lines
+    };
+    say_lines(ctx, command.channel_id, &lines).await?;
+
+    Ok(())
+}
+
+pub async fn add_reaction(ctx: &Context, reaction: &Reaction) -> serenity::Result<()> {
+    let (guild, user) = match (reaction.guild_id, reaction.user_id) {
+        (Some(guild), Some(user)) => (guild, user),
+        _ => return Ok(()),
+    };
+    if reaction.member.as_ref().is_some_and(|member| member.user.bot) || !is_tracked(ctx, guild, reaction.message_id).await {
+        return Ok(());
+    }
+    if selector_bans::is_banned(ctx, guild, user).await {
+        return Ok(());
+    }
+
+    let state = store::<StateKey>(ctx).await;
+    let completed: Vec<RoleId> = state.write(|state| {
+        let trackers = state.guilds.get_mut(&guild).map(|trackers| trackers.iter_mut()).into_iter().flatten();
+        trackers.filter_map(|tracker| tracker.react(user, reaction.message_id).then_some(tracker.role)).collect()
+    }).await;
+
+    if !completed.is_empty() {
+        role_queue::add_roles(ctx, guild, user, &completed).await?;
+        events::publish(ctx, Event::RolesGranted { guild, user, roles: completed.clone(), source: RoleSource::Selector }).await;
+        let roles: Vec<String> = completed.iter().map(|role| format!("<@&{}>", role.get())).collect();
+        audit::log(ctx, guild, format!("➕ <@{}> got {} for reacting to enough messages", user.get(), roles.join(", "))).await;
+    }
+
+    Ok(())
+}
+
+/// Takes a removed reaction out of a member's progress. Roles already given are kept.
+pub async fn remove_reaction(ctx: &Context, reaction: &Reaction) {
+    let (guild, user) = match (reaction.guild_id, reaction.user_id) {
+        (Some(guild), Some(user)) => (guild, user),
+        _ => return,
+    };
+    if !is_tracked(ctx, guild, reaction.message_id).await {
+        return;
+    }
+
+    let state = store::<StateKey>(ctx).await;
+    state.write(|state| {
+        for tracker in state.guilds.get_mut(&guild).into_iter().flatten() {
+            tracker.unreact(user, reaction.message_id);
+        }
+    }).await;
+}
+
+async fn is_tracked(ctx: &Context, guild: GuildId, message: MessageId) -> bool {
+    let state = store::<StateKey>(ctx).await;
+    let tracked = state.read().await.guilds.get(&guild)
+        .is_some_and(|trackers| trackers.iter().any(|tracker| tracker.messages.contains(&message)));
+    tracked
+}
+
+/// Stops tracking a deleted message, dropping roles that have no messages left.
+pub async fn delete_message(ctx: &Context, guild: GuildId, message: MessageId) {
+    if !is_tracked(ctx, guild, message).await {
+        return;
+    }
+
+    let state = store::<StateKey>(ctx).await;
+    state.write(|state| {
+        if let Some(trackers) = state.guilds.get_mut(&guild) {
+            for tracker in trackers.iter_mut() {
+                tracker.forget_message(message);
+            }
+            trackers.retain(|tracker| !tracker.messages.is_empty());
+            if trackers.is_empty() {
+                state.guilds.remove(&guild);
+            }
+        }
+    }).await;
+}
+
+pub async fn guild_member_removal(ctx: &Context, guild: GuildId, user: UserId) {
+    let state = store::<StateKey>(ctx).await;
+    let has_progress = state.read().await.guilds.get(&guild)
+        .is_some_and(|trackers| trackers.iter().any(|tracker| tracker.progress.contains_key(&user)));
+    if !has_progress {
+        return;
+    }
+
+    state.write(|state| {
+        for tracker in state.guilds.get_mut(&guild).into_iter().flatten() {
+            tracker.forget_user(user);
+        }
+    }).await;
+}
+
+/// Forgets all guilds not in `guilds`, returning the ones that were removed.
+pub async fn retain_guilds(ctx: &Context, guilds: &HashSet<GuildId>) -> Vec<GuildId> {
+    let state = store::<StateKey>(ctx).await;
+    state.write(|state| {
+        let removed = state.guilds.keys().filter(|guild| !guilds.contains(guild)).copied().collect();
+        state.guilds.retain(|guild, _| guilds.contains(guild));
+        removed
+    }).await
+}
@@ -0,0 +1,76 @@
+use serenity::builder::EditMessage;
+use serenity::model::prelude::*;
+use serenity::prelude::*;
+use tokio::sync::Semaphore;
+
+use log::error;
+
+use super::Emoji;
+
+/// How many reactions a selector needs to be given before progress is shown while adding them.
+pub const PROGRESS_THRESHOLD: usize = 8;
+
+/// How many reactions are added between updates of the progress message.
+const PROGRESS_EVERY: usize = 4;
+
+/// How many selectors can have their reactions added at the same time. Each selector's reactions
+/// are added one after another, since they show up in the order they were added.
+const MAX_CONCURRENT: usize = 2;
+
+static SEEDING: Semaphore = Semaphore::const_new(MAX_CONCURRENT);
+
+/// Adds the bot's reactions to a selector message in order, returning the emoji that couldn't be
+/// added and why. Large selectors get a message in their channel showing how far along it is, which
+/// is removed once done, or turned into a summary if any emoji failed.
+pub async fn seed(ctx: &Context, message: &Message, emoji: &[Emoji]) -> Vec<(Emoji, String)> {
+    let _permit = SEEDING.acquire().await.expect("seeding semaphore closed");
+
+    let mut progress = None;
+    if emoji.len() >= PROGRESS_THRESHOLD {
+        match message.channel_id.say(&ctx.http, progress_line(0, emoji.len())).await {
+            Ok(posted) => progress = Some(posted),
+            Err(err) => error!("failed to post reaction progress for {}: {:?}", message.id, err),
+        }
+    }
+
+    let mut failures = Vec::new();
+    for (index, emoji_to_add) in emoji.iter().enumerate() {
+        if let Err(err) = message.react(ctx, emoji_to_add.clone()).await {
+            failures.push((emoji_to_add.clone(), err.to_string()));
+        }
+
+        let added = index + 1;
+        if let Some(progress) = progress.as_mut().filter(|_| added % PROGRESS_EVERY == 0 && added < emoji.len()) {
+            if let Err(err) = progress.edit(ctx, EditMessage::new().content(progress_line(added, emoji.len()))).await {
+                error!("failed to update reaction progress for {}: {:?}", message.id, err);
+            }
+        }
+    }
+
+    if let Some(mut progress) = progress {
+        let result = match summary(emoji.len(), &failures) {
+            Some(summary) => progress.edit(ctx, EditMessage::new().content(summary)).await,
+            None => progress.delete(ctx).await,
+        };
+        if let Err(err) = result {
+            error!("failed to finish reaction progress for {}: {:?}", message.id, err);
+        }
+    }
+
+    failures
+}
+
+pub fn progress_line(added: usize, total: usize) -> String {
+    format!("⏳ Adding reactions to the selector: {}/{}", added, total)
+}
+
+/// Sums up the emoji that couldn't be added, if there were any.
+pub fn summary(total: usize, failures: &[(Emoji, String)]) -> Option<String> {
+    if failures.is_empty() {
+        return None;
+    }
+
+    let mut lines = vec![format!("⚠️ Added {} of {} reactions to the selector. These couldn't be added:", total - failures.len(), total)];
+    lines.extend(failures.iter().map(|(emoji, reason)| format!("{}: {}", emoji, reason)));
+    Some(lines.join("\n"))
+}
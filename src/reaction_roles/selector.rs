@@ -1,56 +1,469 @@
-use std::collections::HashMap;
+use std::collections::HashSet;
+use std::fmt;
+use std::hash::{Hash, Hasher};
 use std::str::FromStr;
 
+use indexmap::IndexMap;
 use regex::Regex;
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use serenity::model::prelude::*;
 
-#[derive(Serialize, Deserialize, Default, Clone, Eq, PartialEq)]
-pub struct Selector(HashMap<Emoji, RoleId>);
+/// The options of a selector, each mapping an emoji to the bundle of roles it grants, in the order
+/// they appear in the message.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Selector {
+    #[serde(rename = "roles", deserialize_with = "deserialize_options")]
+    options: Vec<SelectorOption>,
+    #[serde(default = "default_enabled")]
+    enabled: bool,
+    #[serde(default)]
+    unmapped: UnmappedPolicy,
+    /// When set, reacting posts a request to this staff channel and roles are only granted once approved.
+    #[serde(default)]
+    approval_channel: Option<ChannelId>,
+    /// When set, members can only hold the roles of one emoji at a time. Choosing another emoji
+    /// takes away the roles of the previous one. Not applied while approval is required.
+    #[serde(default)]
+    exclusive: bool,
+    /// When set, exclusive selectors ask members before taking roles away.
+    #[serde(default)]
+    confirm_switch: bool,
+    /// Roles members need to hold before they can get any roles from the selector.
+    #[serde(default)]
+    requires: Vec<RoleId>,
+}
+
+/// One emoji of a selector with the roles it grants and how it behaves.
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq)]
+pub struct SelectorOption {
+    pub emoji: Emoji,
+    pub roles: Vec<RoleId>,
+    /// Removing the reaction doesn't take the roles away again.
+    #[serde(default)]
+    pub sticky: bool,
+    /// The reaction is removed once the roles are granted, so that the option can only add roles.
+    #[serde(default)]
+    pub add_only: bool,
+    /// Explains what the roles are for where the selector is listed.
+    #[serde(default)]
+    pub description: Option<String>,
+    /// The roles are taken away again this many seconds after being granted.
+    #[serde(default)]
+    pub expires_after: Option<u64>,
+    /// Roles members need to hold before they can get the roles of this emoji, on top of those the
+    /// whole selector requires.
+    #[serde(default)]
+    pub requires: Vec<RoleId>,
+    /// The exclusive group the emoji is listed under in the message, like `--- group: colors ---`.
+    /// Members can only hold the roles of one emoji of each group at a time.
+    #[serde(default)]
+    pub group: Option<String>,
+}
+
+impl SelectorOption {
+    pub fn new(emoji: Emoji, roles: Vec<RoleId>) -> Self {
+        SelectorOption { emoji, roles, sticky: false, add_only: false, description: None, expires_after: None, requires: Vec::new(), group: None }
+    }
+}
+
+/// How the roles of one emoji differ between two versions of a selector.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum MappingChange {
+    Added { emoji: Emoji, roles: Vec<RoleId> },
+    Removed { emoji: Emoji, roles: Vec<RoleId> },
+    Changed { emoji: Emoji, from: Vec<RoleId>, to: Vec<RoleId> },
+}
+
+/// Selectors used to store their options as a map from each emoji to its roles, without any flags.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum StoredOptions {
+    Current(Vec<SelectorOption>),
+    Map(IndexMap<Emoji, StoredRoles>),
+}
+
+fn deserialize_options<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<SelectorOption>, D::Error> {
+    Ok(match StoredOptions::deserialize(deserializer)? {
+        StoredOptions::Current(options) => options,
+        StoredOptions::Map(roles) => roles.into_iter()
+            .map(|(emoji, roles)| SelectorOption::new(emoji, roles.into()))
+            .collect(),
+    })
+}
+
+/// Controls what happens to reactions on a selector that don't map to any roles.
+#[derive(Serialize, Deserialize, Default, Clone, Debug, Eq, PartialEq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum UnmappedPolicy {
+    /// Remove every unmapped reaction.
+    #[default]
+    Delete,
+    /// Leave unmapped reactions alone.
+    Ignore,
+    /// Leave only the given emoji alone and remove all other unmapped reactions.
+    Allow { emoji: HashSet<Emoji> },
+}
+
+// selectors that only differ in the order of their emoji are different, so that reordering gets saved
+impl PartialEq for Selector {
+    fn eq(&self, other: &Self) -> bool {
+        self.options == other.options
+            && self.enabled == other.enabled
+            && self.unmapped == other.unmapped
+            && self.approval_channel == other.approval_channel
+            && self.exclusive == other.exclusive
+            && self.confirm_switch == other.confirm_switch
+            && self.requires == other.requires
+    }
+}
+
+impl Eq for Selector {}
 
 impl Selector {
     pub fn new() -> Self {
-        Selector(HashMap::new())
+        Selector {
+            options: Vec::new(),
+            enabled: true,
+            unmapped: UnmappedPolicy::default(),
+            approval_channel: None,
+            exclusive: false,
+            confirm_switch: false,
+            requires: Vec::new(),
+        }
+    }
+
+    /// Sets the roles of an emoji, adding it as a new option at the end if it isn't one yet.
+    pub fn insert_roles(&mut self, emoji: Emoji, roles: Vec<RoleId>) {
+        match self.option_mut(&emoji) {
+            Some(option) => option.roles = roles,
+            None => self.options.push(SelectorOption::new(emoji, roles)),
+        }
     }
 
     #[inline]
-    pub fn insert_role(&mut self, emoji: Emoji, role: RoleId) {
-        self.0.insert(emoji, role);
+    pub fn clear_roles(&mut self) {
+        self.options.clear();
     }
 
     #[inline]
-    pub fn get_role(&self, emoji: &Emoji) -> Option<RoleId> {
-        self.0.get(emoji).copied()
+    pub fn get_roles(&self, emoji: &Emoji) -> Option<&[RoleId]> {
+        self.option(emoji).map(|option| option.roles.as_slice())
     }
 
     #[inline]
     pub fn contains(&self, emoji: &Emoji) -> bool {
-        self.0.contains_key(emoji)
+        self.option(emoji).is_some()
+    }
+
+    #[inline]
+    pub fn iter(&self) -> impl Iterator<Item=(&Emoji, &[RoleId])> {
+        self.options.iter().map(|option| (&option.emoji, option.roles.as_slice()))
+    }
+
+    #[inline]
+    pub fn options(&self) -> &[SelectorOption] {
+        &self.options
+    }
+
+    #[inline]
+    pub fn option(&self, emoji: &Emoji) -> Option<&SelectorOption> {
+        self.options.iter().find(|option| &option.emoji == emoji)
+    }
+
+    #[inline]
+    pub fn option_mut(&mut self, emoji: &Emoji) -> Option<&mut SelectorOption> {
+        self.options.iter_mut().find(|option| &option.emoji == emoji)
+    }
+
+    #[inline]
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    #[inline]
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+
+    #[inline]
+    pub fn unmapped_policy(&self) -> &UnmappedPolicy {
+        &self.unmapped
+    }
+
+    #[inline]
+    pub fn set_unmapped_policy(&mut self, policy: UnmappedPolicy) {
+        self.unmapped = policy;
+    }
+
+    #[inline]
+    pub fn approval_channel(&self) -> Option<ChannelId> {
+        self.approval_channel
+    }
+
+    #[inline]
+    pub fn set_approval_channel(&mut self, channel: Option<ChannelId>) {
+        self.approval_channel = channel;
+    }
+
+    #[inline]
+    pub fn is_exclusive(&self) -> bool {
+        self.exclusive
+    }
+
+    #[inline]
+    pub fn set_exclusive(&mut self, exclusive: bool) {
+        self.exclusive = exclusive;
     }
 
+    /// The roles members need to hold before they can get any roles from the selector.
     #[inline]
-    pub fn iter(&self) -> impl Iterator<Item=(&Emoji, &RoleId)> {
-        self.0.iter()
+    pub fn requires(&self) -> &[RoleId] {
+        &self.requires
+    }
+
+    /// The roles a member needs for the roles of an emoji but doesn't hold.
+    pub fn missing_requirements(&self, emoji: &Emoji, held: &[RoleId]) -> Vec<RoleId> {
+        let option_requires = self.option(emoji).map(|option| option.requires.as_slice()).unwrap_or_default();
+        let mut missing = Vec::new();
+        for role in self.requires.iter().chain(option_requires) {
+            if !held.contains(role) && !missing.contains(role) {
+                missing.push(*role);
+            }
+        }
+        missing
+    }
+
+    /// Whether choosing `emoji` takes away the roles of `other`, because the whole selector is
+    /// exclusive or both are in the same group.
+    pub fn excludes(&self, emoji: &Emoji, other: &Emoji) -> bool {
+        if emoji == other {
+            return false;
+        }
+        if self.exclusive {
+            return true;
+        }
+        let group = |emoji| self.option(emoji).and_then(|option| option.group.as_deref());
+        matches!((group(emoji), group(other)), (Some(group), Some(other)) if group == other)
+    }
+
+    /// Whether choosing `emoji` takes away the roles of any other emoji.
+    pub fn excludes_any(&self, emoji: &Emoji) -> bool {
+        self.iter().any(|(other, _)| self.excludes(emoji, other))
+    }
+
+    #[inline]
+    pub fn confirms_switch(&self) -> bool {
+        self.confirm_switch
+    }
+
+    #[inline]
+    pub fn set_confirm_switch(&mut self, confirm_switch: bool) {
+        self.confirm_switch = confirm_switch;
+    }
+
+    /// Lists how the roles of each emoji differ in `new`, in the order of the emoji in `new` with
+    /// removed emoji last.
+    pub fn diff(&self, new: &Selector) -> Vec<MappingChange> {
+        let mut changes = Vec::new();
+        for (emoji, roles) in new.iter() {
+            match self.get_roles(emoji) {
+                None => changes.push(MappingChange::Added { emoji: emoji.clone(), roles: roles.to_vec() }),
+                Some(previous) if previous != roles => changes.push(MappingChange::Changed {
+                    emoji: emoji.clone(),
+                    from: previous.to_vec(),
+                    to: roles.to_vec(),
+                }),
+                Some(_) => {}
+            }
+        }
+        for (emoji, roles) in self.iter() {
+            if !new.contains(emoji) {
+                changes.push(MappingChange::Removed { emoji: emoji.clone(), roles: roles.to_vec() });
+            }
+        }
+        changes
+    }
+
+    /// Returns whether an unmapped reaction with this emoji should be removed from the selector.
+    pub fn should_remove_unmapped(&self, emoji: &Emoji) -> bool {
+        match &self.unmapped {
+            UnmappedPolicy::Delete => true,
+            UnmappedPolicy::Ignore => false,
+            UnmappedPolicy::Allow { emoji: allowed } => !allowed.contains(emoji),
+        }
+    }
+
+    /// Replaces the emoji mapping with the one parsed from `content`, keeping all other settings.
+    pub fn reparse(&mut self, content: &str) {
+        self.reparse_with(content, |_| None);
+    }
+
+    /// Replaces the mapping, requirements and groups with those parsed from `content`, keeping all other
+    /// settings. Emoji that are still there keep their flags.
+    pub fn reparse_with<F>(&mut self, content: &str, resolve: F)
+        where F: Fn(&str) -> Option<RoleId>
+    {
+        let parsed = Selector::parse_with(content, resolve);
+        self.replace_roles(parsed.options.iter().map(|option| (option.emoji.clone(), option.roles.clone())));
+        for option in &mut self.options {
+            let parsed = parsed.option(&option.emoji);
+            option.requires = parsed.map(|parsed| parsed.requires.clone()).unwrap_or_default();
+            option.group = parsed.and_then(|parsed| parsed.group.clone());
+        }
+        self.requires = parsed.requires;
+    }
+
+    /// Replaces the mapping with the given emoji and their roles. Emoji that are still there keep
+    /// their flags.
+    pub fn replace_roles<I>(&mut self, roles: I)
+        where I: IntoIterator<Item=(Emoji, Vec<RoleId>)>
+    {
+        self.options = roles.into_iter()
+            .map(|(emoji, roles)| match self.option(&emoji) {
+                Some(previous) => SelectorOption { roles, ..previous.clone() },
+                None => SelectorOption::new(emoji, roles),
+            })
+            .collect();
+    }
+}
+
+impl Default for Selector {
+    fn default() -> Self {
+        Selector::new()
+    }
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+pub(super) enum StoredRoles {
+    Single(RoleId),
+    Bundle(Vec<RoleId>),
+}
+
+impl From<StoredRoles> for Vec<RoleId> {
+    fn from(roles: StoredRoles) -> Self {
+        match roles {
+            StoredRoles::Single(role) => vec![role],
+            StoredRoles::Bundle(roles) => roles,
+        }
+    }
+}
+
+/// Accepts both the current selector format and the legacy format, where a selector was stored as
+/// a plain map from each emoji to a single role.
+#[derive(Deserialize)]
+#[serde(untagged)]
+pub(super) enum StoredSelector {
+    Current(Selector),
+    Legacy(IndexMap<Emoji, StoredRoles>),
+}
+
+impl From<StoredSelector> for Selector {
+    fn from(stored: StoredSelector) -> Self {
+        match stored {
+            StoredSelector::Current(selector) => selector,
+            StoredSelector::Legacy(roles) => {
+                let mut selector = Selector::new();
+                for (emoji, roles) in roles {
+                    selector.insert_roles(emoji, roles.into());
+                }
+                selector
+            }
+        }
     }
 }
 
 impl Selector {
     pub fn parse(content: &str) -> Selector {
+        Selector::parse_with(content, |_| None)
+    }
+
+    /// Parses a selector where roles may also be referred to by name in backticks, like `` `Red Team` ``,
+    /// with `resolve` looking those names up.
+    pub fn parse_with<F>(content: &str, resolve: F) -> Selector
+        where F: Fn(&str) -> Option<RoleId>
+    {
+        Selector::parse_lines(content, resolve, &mut Vec::new())
+    }
+
+    /// Finds the role names on selector lines that `resolve` doesn't know about.
+    pub fn unresolved_names<F>(content: &str, resolve: F) -> Vec<String>
+        where F: Fn(&str) -> Option<RoleId>
+    {
+        let mut unresolved = Vec::new();
+        Selector::parse_lines(content, resolve, &mut unresolved);
+        unresolved
+    }
+
+    fn parse_lines<F>(content: &str, resolve: F, unresolved: &mut Vec<String>) -> Selector
+        where F: Fn(&str) -> Option<RoleId>
+    {
         let role_pattern = Regex::new(r#"<@&([^>]*)>"#).unwrap();
-        let custom_emoji_pattern = Regex::new(r#"<:([^>]*>)"#).unwrap();
+        let name_pattern = role_name_pattern();
+        let requires_pattern = Regex::new(r#"(?i)\[requires ([^\]]*)\]"#).unwrap();
+        let custom_emoji_pattern = Regex::new(r#"<a?:([^>]*>)"#).unwrap();
         let unicode_emoji_pattern = Regex::new(r#"[\p{Emoji}--\p{Digit}]"#).unwrap();
+        // `--- group: colors ---` starts an exclusive group and a bare `---` ends it
+        let group_pattern = Regex::new(r#"(?i)^\s*-{3,}\s*(?:group:\s*(.+?)\s*-{3,})?\s*$"#).unwrap();
 
         let mut selector = Selector::new();
+        let mut group: Option<String> = None;
 
         for line in content.lines() {
-            let mut roles = role_pattern.find_iter(line)
-                .filter_map(|role| {
-                    let role = role.as_str();
-                    serenity::utils::parse_role(role)
+            if let Some(captures) = group_pattern.captures(line) {
+                group = captures.get(1).map(|name| name.as_str().to_lowercase());
+                continue;
+            }
+
+            // requirements are written like `[requires @Verified]`, and their roles aren't granted
+            let requires: Vec<RoleId> = requires_pattern.captures_iter(line)
+                .filter_map(|captures| captures.get(1))
+                .flat_map(|required| {
+                    let required = required.as_str();
+                    let mut roles: Vec<(usize, RoleId)> = role_pattern.find_iter(required)
+                        .filter_map(|role| serenity::utils::parse_role_mention(role.as_str()).map(|id| (role.start(), id)))
+                        .chain(name_pattern.captures_iter(required).filter_map(|captures| {
+                            let name = captures.get(1)?;
+                            resolve(name.as_str()).map(|role| (name.start(), role))
+                        }))
+                        .collect();
+                    roles.sort_by_key(|(position, _)| *position);
+                    roles.into_iter().map(|(_, role)| role)
                 })
-                .map(|role| RoleId(role));
+                .collect();
+            let line = requires_pattern.replace_all(line, "");
+            let line = line.as_ref();
+
+            let mentions = role_pattern.find_iter(line)
+                .filter_map(|role| {
+                    serenity::utils::parse_role_mention(role.as_str()).map(|id| (role.start(), id))
+                });
+
+            let mut line_unresolved = Vec::new();
+            let names = name_pattern.captures_iter(line)
+                .filter_map(|captures| {
+                    let name = captures.get(1)?;
+                    match resolve(name.as_str()) {
+                        Some(role) => Some((name.start(), role)),
+                        None => {
+                            line_unresolved.push(name.as_str().to_owned());
+                            None
+                        }
+                    }
+                });
+
+            let mut roles: Vec<(usize, RoleId)> = mentions.chain(names).collect();
+            roles.sort_by_key(|(position, _)| *position);
+            let roles: Vec<RoleId> = roles.into_iter().map(|(_, role)| role).collect();
 
-            let custom_emoji = custom_emoji_pattern.find_iter(line)
+            // role names shouldn't be mistaken for emoji
+            let line = name_pattern.replace_all(line, "");
+
+            let custom_emoji = custom_emoji_pattern.find_iter(&line)
                 .filter_map(|custom_emoji| {
                     let custom_emoji = custom_emoji.as_str();
                     serenity::utils::parse_emoji(custom_emoji)
@@ -63,7 +476,7 @@ impl Selector {
                     })
                 });
 
-            let unicode_emoji = unicode_emoji_pattern.find_iter(line)
+            let unicode_emoji = unicode_emoji_pattern.find_iter(&line)
                 .map(|unicode_emoji| {
                     let unicode_emoji = unicode_emoji.as_str().to_owned();
                     Emoji::from(ReactionType::Unicode(unicode_emoji))
@@ -71,8 +484,18 @@ impl Selector {
 
             let mut emoji = custom_emoji.chain(unicode_emoji);
 
-            if let (Some(role), Some(emoji)) = (roles.next(), emoji.next()) {
-                selector.insert_role(emoji, role);
+            if let Some(emoji) = emoji.next() {
+                unresolved.append(&mut line_unresolved);
+                if !roles.is_empty() {
+                    selector.insert_roles(emoji.clone(), roles);
+                    if let Some(option) = selector.option_mut(&emoji) {
+                        option.requires = requires;
+                        option.group = group.clone();
+                    }
+                }
+            } else {
+                // a requirement on a line of its own applies to the whole selector
+                selector.requires.extend(requires);
             }
         }
 
@@ -80,37 +503,68 @@ impl Selector {
     }
 }
 
-#[derive(Clone, Debug, Eq, PartialEq, Hash, Serialize, Deserialize)]
-pub struct Emoji(String);
+/// Matches role names given in backticks.
+pub fn role_name_pattern() -> Regex {
+    Regex::new(r#"`([^`\n]+)`"#).unwrap()
+}
+
+/// An emoji used in a selector. Custom emoji are told apart by their ID alone, so they still match
+/// after being renamed or when posted in their animated form.
+///
+/// Stored as the text it's written as, like `🎮` or `<:name:id>`, which also keeps emoji usable as
+/// keys of JSON objects.
+#[derive(Clone, Debug)]
+pub enum Emoji {
+    Unicode(String),
+    Custom { id: EmojiId, name: Option<String> },
+}
+
+impl PartialEq for Emoji {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Emoji::Unicode(emoji), Emoji::Unicode(other)) => emoji == other,
+            (Emoji::Custom { id, .. }, Emoji::Custom { id: other, .. }) => id == other,
+            _ => false,
+        }
+    }
+}
+
+impl Eq for Emoji {}
+
+impl Hash for Emoji {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        match self {
+            Emoji::Unicode(emoji) => emoji.hash(state),
+            Emoji::Custom { id, .. } => id.hash(state),
+        }
+    }
+}
 
 impl From<ReactionType> for Emoji {
     fn from(reaction: ReactionType) -> Self {
         match reaction {
-            ReactionType::Custom {
-                animated: _,
-                id,
-                name
-            } => match name {
-                Some(name) => Emoji(format!("<:{}:{}>", name, id)),
-                None => Emoji(format!("<:{}>", id)),
-            },
-            ReactionType::Unicode(unicode) => Emoji(unicode),
+            ReactionType::Custom { animated: _, id, name } => Emoji::Custom { id, name },
+            ReactionType::Unicode(unicode) => Emoji::Unicode(unicode),
             _ => panic!("unknown reaction type")
         }
     }
 }
 
-impl Into<ReactionType> for Emoji {
-    fn into(self) -> ReactionType {
-        match EmojiIdentifier::from_str(&self.0) {
-            Ok(custom) => {
-                ReactionType::Custom {
-                    animated: false,
-                    id: custom.id,
-                    name: Some(custom.name),
-                }
-            }
-            Err(_) => ReactionType::Unicode(self.0),
+impl From<Emoji> for ReactionType {
+    fn from(emoji: Emoji) -> Self {
+        match emoji {
+            Emoji::Custom { id, name } => ReactionType::Custom { animated: false, id, name },
+            Emoji::Unicode(unicode) => ReactionType::Unicode(unicode),
+        }
+    }
+}
+
+impl fmt::Display for Emoji {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Emoji::Unicode(emoji) => f.write_str(emoji),
+            Emoji::Custom { id, name: Some(name) } => write!(f, "<:{}:{}>", name, id),
+            Emoji::Custom { id, name: None } => write!(f, "<:{}>", id),
         }
     }
 }
@@ -118,7 +572,29 @@ impl Into<ReactionType> for Emoji {
 impl FromStr for Emoji {
     type Err = ();
 
+    /// Reads custom emoji written as `<:name:id>` or `<a:name:id>`, as well as `<:id>` for ones
+    /// that were stored without a name. Anything else is taken to be a unicode emoji.
     fn from_str(s: &str) -> Result<Self, ()> {
-        Ok(Emoji(s.to_owned()))
+        if let Some(custom) = serenity::utils::parse_emoji(s) {
+            return Ok(Emoji::Custom { id: custom.id, name: Some(custom.name) });
+        }
+        let id = s.strip_prefix("<:").and_then(|id| id.strip_suffix('>')).and_then(|id| id.parse::<u64>().ok());
+        match id {
+            Some(id) if id != 0 => Ok(Emoji::Custom { id: EmojiId::new(id), name: None }),
+            _ => Ok(Emoji::Unicode(s.to_owned())),
+        }
+    }
+}
+
+impl Serialize for Emoji {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Emoji {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let emoji = String::deserialize(deserializer)?;
+        Ok(emoji.parse().unwrap_or(Emoji::Unicode(emoji)))
     }
 }
@@ -0,0 +1,326 @@
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+
+use log::error;
+use serde::{Deserialize, Serialize};
+use serenity::model::prelude::*;
+use serenity::prelude::*;
+
+use super::{handle_reaction, selectors_in, Emoji, SelectorEntry};
+use crate::{audit, capabilities, CommandError, CommandResult, dormancy, experiments, members, Persistent, read_only, say_lines, scheduler, store};
+use crate::experiments::Feature;
+use crate::memreport::{Introspect, Owner};
+
+/// How often guilds are looked at for a sweep being due.
+pub const PERIOD: Duration = Duration::from_secs(60 * 60);
+
+/// How long a guild waits between sweeps, unless set otherwise.
+pub const DEFAULT_INTERVAL_SECS: u64 = 24 * 60 * 60;
+
+/// How many selectors a sweep checks, going on from the last one the next time around.
+pub const SELECTORS_PER_SWEEP: usize = 5;
+
+/// The most requests a sweep of one guild may make, which is what leaves a selector to the next sweep.
+const MAX_REQUESTS: usize = 200;
+
+/// How long a sweep waits before each request, so that it never gets in the way of live reactions.
+const REQUEST_DELAY: Duration = Duration::from_millis(1500);
+
+const PAGE_SIZE: u8 = 100;
+
+pub struct StateKey;
+
+impl TypeMapKey for StateKey {
+    type Value = Persistent<State>;
+}
+
+/// Guilds that have their selectors checked for roles that don't match the reactions, such as when
+/// gateway events were missed.
+#[derive(Serialize, Deserialize, Default, Clone, Eq, PartialEq)]
+pub struct State {
+    guilds: HashMap<GuildId, Sweep>,
+}
+
+impl Introspect for State {
+    fn entries(&self) -> Vec<(Owner, usize)> {
+        self.guilds.keys().map(|guild| (Owner::Guild(*guild), 1)).collect()
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Sweep {
+    /// Whether members who reacted without getting the roles are given them, instead of only being reported.
+    pub fix: bool,
+    pub interval_secs: u64,
+    #[serde(default)]
+    pub last_run_at: Option<i64>,
+    /// The last selector that was checked.
+    #[serde(default)]
+    pub cursor: Option<MessageId>,
+}
+
+impl Sweep {
+    pub fn new(fix: bool, interval_secs: u64) -> Self {
+        Sweep { fix, interval_secs, last_run_at: None, cursor: None }
+    }
+
+    pub fn is_due(&self, now: i64) -> bool {
+        self.last_run_at.is_none_or(|last_run_at| now - last_run_at >= self.interval_secs as i64)
+    }
+}
+
+/// The selectors to check next, going on after `cursor` in message order and wrapping around.
+pub fn sample(selectors: &[MessageId], cursor: Option<MessageId>, count: usize) -> Vec<MessageId> {
+    let mut sorted = selectors.to_vec();
+    sorted.sort();
+    let start = match cursor {
+        Some(cursor) => sorted.iter().position(|message| *message > cursor).unwrap_or(0),
+        None => 0,
+    };
+    sorted.iter().cycle().skip(start).take(count.min(sorted.len())).copied().collect()
+}
+
+/// How the members who reacted with an emoji differ from the ones holding its roles.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Drift {
+    /// Members who reacted without holding the roles.
+    pub unrewarded: Vec<UserId>,
+    /// Members who hold the roles without having reacted.
+    pub unreacted: Vec<UserId>,
+}
+
+impl Drift {
+    pub fn compare(reactors: &HashSet<UserId>, holders: &HashSet<UserId>) -> Self {
+        let mut unrewarded: Vec<UserId> = reactors.difference(holders).copied().collect();
+        let mut unreacted: Vec<UserId> = holders.difference(reactors).copied().collect();
+        unrewarded.sort();
+        unreacted.sort();
+        Drift { unrewarded, unreacted }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.unrewarded.is_empty() && self.unreacted.is_empty()
+    }
+}
+
+/// Requests a sweep is still allowed to make.
+struct Budget(usize);
+
+impl Budget {
+    /// Waits for the next request, unless the budget is used up.
+    async fn spend(&mut self) -> bool {
+        if self.0 == 0 {
+            return false;
+        }
+        self.0 -= 1;
+        tokio::time::sleep(REQUEST_DELAY).await;
+        true
+    }
+}
+
+pub async fn set_sweep(ctx: &Context, command: &Message, sweep: Option<Sweep>) -> CommandResult<()> {
+    let guild = command.guild_id.ok_or(CommandError::NotAllowed)?;
+
+    let state = store::<StateKey>(ctx).await;
+    state.write(|state| {
+        match sweep {
+            // keep going from where the last sweep stopped
+            Some(mut sweep) => {
+                if let Some(previous) = state.guilds.get(&guild) {
+                    sweep.last_run_at = previous.last_run_at;
+                    sweep.cursor = previous.cursor;
+                }
+                state.guilds.insert(guild, sweep)
+            }
+            None => state.guilds.remove(&guild),
+        };
+    }).await;
+
+    Ok(())
+}
+
+pub async fn status(ctx: &Context, command: &Message) -> CommandResult<()> {
+    let guild = command.guild_id.ok_or(CommandError::NotAllowed)?;
+
+    let sweep = store::<StateKey>(ctx).await.read().await.guilds.get(&guild).copied();
+    let lines = match sweep {
+        Some(sweep) => {
+            let mode = if sweep.fix { "fixing" } else { "reporting" };
+            let last = match sweep.last_run_at {
+                Some(at) => format!("last swept <t:{}:R>", at),
+                None => "not swept yet".to_owned(),
+            };
+            vec![format!(
+                "🔍 Every {} hours, {} selectors are checked for roles that don't match their reactions, {} what's found ({}).",
+                sweep.interval_secs / (60 * 60), SELECTORS_PER_SWEEP, mode, last,
+            )]
+        }
+        None => vec!["Selector sweeps are off. Turn them on with `selector sweep report|fix [interval]`.".to_owned()],
+    };
+    say_lines(ctx, command.channel_id, &lines).await?;
+
+    Ok(())
+}
+
+/// Sweeps every guild that is due, a few selectors at a time.
+pub async fn run(ctx: Context) {
+    // the roles of members can only be compared to reactions with all of them cached
+    if !capabilities::has_members_intent() {
+        return;
+    }
+
+    let now = scheduler::now();
+    let due: Vec<(GuildId, Sweep)> = store::<StateKey>(&ctx).await.read().await.guilds.iter()
+        .filter(|(_, sweep)| sweep.is_due(now))
+        .map(|(guild, sweep)| (*guild, *sweep))
+        .collect();
+
+    for (guild, sweep) in due {
+        dormancy::wake(&ctx, guild).await;
+        let cursor = sweep_guild(&ctx, guild, sweep).await;
+        let state = store::<StateKey>(&ctx).await;
+        state.write(|state| {
+            if let Some(sweep) = state.guilds.get_mut(&guild) {
+                sweep.last_run_at = Some(now);
+                sweep.cursor = cursor;
+            }
+        }).await;
+    }
+}
+
+/// Checks the next few selectors of a guild, returning the last one that was checked in full.
+async fn sweep_guild(ctx: &Context, guild: GuildId, sweep: Sweep) -> Option<MessageId> {
+    // selectors can also sit in threads, which the cache keeps apart from the channels
+    let channels: HashMap<ChannelId, ()> = ctx.cache.guild(guild)
+        .map(|guild| guild.channels.keys().chain(guild.threads.iter().map(|thread| &thread.id)).map(|channel| (*channel, ())).collect())
+        .unwrap_or_default();
+    let selectors: HashMap<MessageId, SelectorEntry> = selectors_in(ctx, &channels).await.into_iter().collect();
+    let messages: Vec<MessageId> = selectors.keys().copied().collect();
+    // fixing would change roles, so read-only guilds only get reports
+    let fix = sweep.fix && !read_only::is_active(ctx, guild).await;
+
+    let mut budget = Budget(MAX_REQUESTS);
+    let mut cursor = sweep.cursor;
+    for message in sample(&messages, sweep.cursor, SELECTORS_PER_SWEEP) {
+        match sweep_selector(ctx, guild, message, &selectors[&message], fix, &mut budget).await {
+            Ok(true) => cursor = Some(message),
+            Ok(false) => break,
+            Err(err) => {
+                error!("failed to sweep selector {}: {:?}", message, err);
+                cursor = Some(message);
+            }
+        }
+    }
+    cursor
+}
+
+/// Compares the reactions on a selector to the roles of its members, reporting and possibly fixing
+/// what doesn't match. Returns whether the selector was checked before the budget ran out.
+async fn sweep_selector(ctx: &Context, guild: GuildId, message: MessageId, entry: &SelectorEntry, fix: bool, budget: &mut Budget) -> serenity::Result<bool> {
+    let channel = match entry.channel {
+        Some(channel) => channel,
+        None => return Ok(true),
+    };
+    // with approval, roles arrive later than the reaction
+    if !entry.selector.is_enabled() || entry.selector.approval_channel().is_some() {
+        return Ok(true);
+    }
+
+    let fix = fix && experiments::is_enabled(ctx, Feature::SweepFix, guild, Some(channel)).await;
+
+    let mut lines = Vec::new();
+    for option in entry.selector.options() {
+        if option.roles.is_empty() {
+            continue;
+        }
+        let reactors = match reaction_users(ctx, channel, message, &option.emoji, budget).await? {
+            Some(reactors) => reactors,
+            None => return Ok(false),
+        };
+        let holders: HashSet<UserId> = ctx.cache.guild(guild)
+            .map(|guild| guild.members.values()
+                .filter(|member| option.roles.iter().all(|role| member.roles.contains(role)))
+                .map(|member| member.user.id)
+                .collect())
+            .unwrap_or_default();
+        let mut drift = Drift::compare(&reactors, &holders);
+
+        // members missing from the cache may well hold the roles
+        let mut unrewarded = Vec::new();
+        for user in drift.unrewarded {
+            if ctx.cache.guild(guild).is_some_and(|guild| guild.members.contains_key(&user)) {
+                unrewarded.push(user);
+                continue;
+            }
+            if !budget.spend().await {
+                return Ok(false);
+            }
+            match members::member(ctx, guild, user).await {
+                Ok(member) if !option.roles.iter().all(|role| member.roles.contains(role)) => unrewarded.push(user),
+                _ => (),
+            }
+        }
+        drift.unrewarded = unrewarded;
+        // sticky and add-only options are expected to leave roles without a reaction
+        if option.sticky || option.add_only {
+            drift.unreacted.clear();
+        }
+        if drift.is_empty() {
+            continue;
+        }
+
+        if !drift.unrewarded.is_empty() {
+            let mut given = 0;
+            if fix {
+                for user in &drift.unrewarded {
+                    match handle_reaction(ctx, guild, *user, channel, message, &option.emoji, None).await {
+                        Ok(()) => given += 1,
+                        Err(err) => error!("failed to replay the {} reaction of {} on {}: {:?}", option.emoji, user, message, err),
+                    }
+                }
+            }
+            let fixed = if fix { format!(", handled as new reactions for {}", given) } else { String::new() };
+            lines.push(format!("{}: {} members reacted without having its roles{}", option.emoji, drift.unrewarded.len(), fixed));
+        }
+        if !drift.unreacted.is_empty() {
+            lines.push(format!("{}: {} members hold its roles without having reacted", option.emoji, drift.unreacted.len()));
+        }
+    }
+
+    if !lines.is_empty() {
+        let link = audit::message_link(guild, channel, message);
+        lines.insert(0, format!("🔍 The roles of selector {} don't match its reactions:", link));
+        audit::log_to(ctx, guild, entry.log_channel, lines.join("\n")).await;
+    }
+
+    Ok(true)
+}
+
+/// Everyone besides bots who reacted to a message with an emoji, fetched a page at a time within the
+/// budget. Nothing is returned when the budget runs out.
+async fn reaction_users(ctx: &Context, channel: ChannelId, message: MessageId, emoji: &Emoji, budget: &mut Budget) -> serenity::Result<Option<HashSet<UserId>>> {
+    let reaction: ReactionType = emoji.clone().into();
+    let mut users = HashSet::new();
+    let mut after = None;
+    loop {
+        if !budget.spend().await {
+            return Ok(None);
+        }
+        let page = channel.reaction_users(&ctx.http, message, reaction.clone(), Some(PAGE_SIZE), after).await?;
+        after = page.last().map(|user| user.id);
+        users.extend(page.iter().filter(|user| !user.bot).map(|user| user.id));
+        if page.len() < PAGE_SIZE as usize {
+            return Ok(Some(users));
+        }
+    }
+}
+
+/// Forgets all guilds not in `guilds`, returning the ones that were removed.
+pub async fn retain_guilds(ctx: &Context, guilds: &HashSet<GuildId>) -> Vec<GuildId> {
+    let state = store::<StateKey>(ctx).await;
+    state.write(|state| {
+        let removed = state.guilds.keys().filter(|guild| !guilds.contains(guild)).copied().collect();
+        state.guilds.retain(|guild, _| guilds.contains(guild));
+        removed
+    }).await
+}
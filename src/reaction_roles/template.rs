@@ -0,0 +1,172 @@
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeSet, HashMap};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use log::error;
+use regex::{Captures, Regex};
+use serenity::builder::EditMessage;
+use serenity::model::prelude::*;
+use serenity::prelude::*;
+
+use super::{content_hash, holder_counts, roles_by_name, selector_channel, StateKey};
+use crate::{CommandError, CommandResult, read_only, store};
+
+/// How often templates are rendered again for roles whose holders changed.
+pub const PERIOD: Duration = Duration::from_secs(60);
+
+/// How many selector messages are edited per run at most, so that busy guilds don't run into
+/// Discord's rate limits. The rest wait for the next run.
+const MAX_EDITS_PER_RUN: usize = 5;
+
+/// Roles whose holders changed since templates were last rendered.
+static DIRTY_ROLES: Mutex<BTreeSet<RoleId>> = Mutex::new(BTreeSet::new());
+
+fn placeholder_pattern() -> Regex {
+    Regex::new(r#"\{count:\s*(?:<@&(\d+)>|(\d+))\s*\}"#).unwrap()
+}
+
+fn placeholder_role(captures: &Captures) -> Option<RoleId> {
+    let id = captures.get(1).or_else(|| captures.get(2))?;
+    id.as_str().parse().ok()
+}
+
+/// The roles that the placeholders of a template count the holders of.
+pub fn placeholders(template: &str) -> Vec<RoleId> {
+    placeholder_pattern().captures_iter(template)
+        .filter_map(|captures| placeholder_role(&captures))
+        .collect()
+}
+
+/// Fills in the placeholders of a template, like `{count:@Blue}` with how many members hold Blue.
+pub fn render<F>(template: &str, count: F) -> String
+    where F: Fn(RoleId) -> usize
+{
+    placeholder_pattern().replace_all(template, |captures: &Captures| {
+        match placeholder_role(captures) {
+            Some(role) => count(role).to_string(),
+            None => captures[0].to_owned(),
+        }
+    }).into_owned()
+}
+
+/// Notes that the holders of some roles changed, so that templates counting them are rendered again.
+pub fn roles_changed<I: IntoIterator<Item=RoleId>>(roles: I) {
+    DIRTY_ROLES.lock().unwrap().extend(roles);
+}
+
+pub fn member_updated(old: Option<&Member>, member: &Member) {
+    match old {
+        Some(old) => {
+            let added = member.roles.iter().filter(|role| !old.roles.contains(role));
+            let removed = old.roles.iter().filter(|role| !member.roles.contains(role));
+            roles_changed(added.chain(removed).copied());
+        }
+        None => roles_changed(member.roles.iter().copied()),
+    }
+}
+
+/// Makes a selector the bot posted render its message from a template, or stops it.
+pub async fn set_template(ctx: &Context, command: &Message, message: MessageId, template: Option<String>) -> CommandResult<()> {
+    let guild = command.guild_id.ok_or(CommandError::NotAllowed)?;
+    let channel = selector_channel(ctx, message).await.ok_or(CommandError::UnknownSelector)?;
+
+    let mut target = channel.message(&ctx.http, message).await?;
+    if target.author.id != ctx.cache.current_user().id {
+        return Err(CommandError::NotOwnSelector);
+    }
+
+    let template = match template {
+        Some(template) => template,
+        None => {
+            let messages = store::<StateKey>(ctx).await;
+            return messages.write(|messages| {
+                let entry = messages.entry_mut(message).ok_or(CommandError::UnknownSelector)?;
+                entry.template = None;
+                Ok(())
+            }).await;
+        }
+    };
+    if placeholders(&template).is_empty() {
+        return Err(CommandError::NoTemplatePlaceholders);
+    }
+
+    let counts = holder_counts(ctx, guild).await;
+    let rendered = render(&template, |role| counts.get(&role).copied().unwrap_or(0));
+    let names = roles_by_name(ctx, Some(guild), &rendered).await;
+
+    // store the new content first, so that the edit event doesn't reparse the selector again
+    let messages = store::<StateKey>(ctx).await;
+    let stored: CommandResult<()> = messages.write(|messages| {
+        let entry = messages.entry_mut(message).ok_or(CommandError::UnknownSelector)?;
+        entry.selector.reparse_with(&rendered, |name| names.get(&name.to_lowercase()).copied());
+        entry.content = Some(rendered.clone());
+        entry.last_applied_hash = Some(content_hash(&rendered));
+        entry.template = Some(template);
+        Ok(())
+    }).await;
+    stored?;
+
+    target.edit(ctx, EditMessage::new().content(&rendered)).await?;
+    super::apply_selector_reactions(ctx, channel, message).await;
+
+    Ok(())
+}
+
+/// Renders the templates that count roles whose holders changed, editing the messages that now read differently.
+pub async fn refresh(ctx: Context) {
+    let dirty = std::mem::take(&mut *DIRTY_ROLES.lock().unwrap());
+    if dirty.is_empty() {
+        return;
+    }
+
+    let candidates: Vec<(MessageId, ChannelId, String)> = {
+        let messages = store::<StateKey>(&ctx).await;
+        let messages = messages.read().await;
+        messages.entries()
+            .filter_map(|(message, entry)| Some((message, entry.channel?, entry.template.clone()?)))
+            .filter(|(_, _, template)| placeholders(template).iter().any(|role| dirty.contains(role)))
+            .collect()
+    };
+
+    let mut counts: HashMap<GuildId, HashMap<RoleId, usize>> = HashMap::new();
+    for (index, (message, channel, template)) in candidates.into_iter().enumerate() {
+        if index >= MAX_EDITS_PER_RUN {
+            roles_changed(placeholders(&template));
+            continue;
+        }
+
+        let guild = match crate::cached_channel(&ctx.cache, channel) {
+            Some(channel) => channel.guild_id,
+            None => continue,
+        };
+        if read_only::blocks(&ctx, guild, || format!("updated the counts on selector {}", message)).await {
+            continue;
+        }
+
+        let guild_counts = match counts.entry(guild) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => entry.insert(holder_counts(&ctx, guild).await),
+        };
+        let rendered = render(&template, |role| guild_counts.get(&role).copied().unwrap_or(0));
+        let hash = content_hash(&rendered);
+
+        let messages = store::<StateKey>(&ctx).await;
+        let changed = messages.write(|messages| {
+            match messages.entry_mut(message) {
+                Some(entry) if entry.last_applied_hash != Some(hash) => {
+                    entry.content = Some(rendered.clone());
+                    entry.last_applied_hash = Some(hash);
+                    true
+                }
+                _ => false,
+            }
+        }).await;
+
+        if changed {
+            if let Err(err) = channel.edit_message(&ctx.http, message, EditMessage::new().content(&rendered)).await {
+                error!("failed to update the counts on selector {}: {:?}", message, err);
+            }
+        }
+    }
+}
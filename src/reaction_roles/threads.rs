@@ -0,0 +1,76 @@
+use serenity::builder::EditThread;
+use serenity::model::prelude::*;
+use serenity::prelude::*;
+
+use log::error;
+
+use super::{delete_message, StateKey};
+use crate::{audit, read_only, store};
+
+/// Selectors in a thread, like a forum post, with their log channels.
+async fn selectors_in(ctx: &Context, thread: ChannelId) -> Vec<(MessageId, Option<ChannelId>)> {
+    let state = store::<StateKey>(ctx).await;
+    let selectors = state.read().await.0.iter()
+        .filter(|(_, entry)| entry.channel == Some(thread))
+        .map(|(message, entry)| (*message, entry.log_channel))
+        .collect();
+    selectors
+}
+
+/// Makes a thread that just got a selector archive as late as Discord allows, so that it needs
+/// reopening less often.
+pub async fn selector_registered(ctx: &Context, channel: ChannelId) {
+    let metadata = match crate::cached_channel(&ctx.cache, channel).and_then(|channel| channel.thread_metadata) {
+        Some(metadata) => metadata,
+        None => return,
+    };
+    if metadata.auto_archive_duration == AutoArchiveDuration::OneWeek {
+        return;
+    }
+
+    let builder = EditThread::new().auto_archive_duration(AutoArchiveDuration::OneWeek);
+    if let Err(err) = channel.edit_thread(ctx, builder).await {
+        error!("failed to extend the archive duration of thread {}: {:?}", channel, err);
+    }
+}
+
+/// Reopens threads holding selectors when Discord archives them, since nobody can react to messages
+/// in archived threads. Locked threads were closed by moderators, so they're left alone.
+pub async fn thread_update(ctx: &Context, thread: &GuildChannel) {
+    let metadata = match &thread.thread_metadata {
+        Some(metadata) if metadata.archived => metadata,
+        _ => return,
+    };
+    let selectors = selectors_in(ctx, thread.id).await;
+    if selectors.is_empty() {
+        return;
+    }
+
+    let guild = thread.guild_id;
+    let problem = if metadata.locked {
+        "was locked, so members can't react to it until a moderator reopens its thread".to_owned()
+    } else if read_only::blocks(ctx, Some(guild), || format!("reopened the archived thread {}", thread.id)).await {
+        return;
+    } else {
+        match thread.id.edit_thread(ctx, EditThread::new().archived(false)).await {
+            Ok(_) => return,
+            Err(err) => {
+                error!("failed to reopen thread {}: {:?}", thread.id, err);
+                "was archived and I couldn't reopen its thread, so members can't react to it".to_owned()
+            }
+        }
+    };
+
+    for (message, log_channel) in selectors {
+        let link = audit::message_link(guild, thread.id, message);
+        audit::log_to(ctx, guild, log_channel, format!("⚠️ Selector {} {}", link, problem)).await;
+    }
+}
+
+/// Deleting a thread doesn't delete its messages one by one, so its selectors are removed here as if
+/// their messages were deleted.
+pub async fn thread_delete(ctx: &Context, thread: &PartialGuildChannel) {
+    for (message, _) in selectors_in(ctx, thread.id).await {
+        delete_message(ctx.clone(), Some(thread.guild_id), thread.id, message).await;
+    }
+}
@@ -0,0 +1,164 @@
+use std::collections::{BTreeSet, HashSet};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use log::info;
+use serde::{Deserialize, Serialize};
+use serenity::model::prelude::*;
+use serenity::prelude::*;
+
+use crate::{audit, CommandError, CommandResult, Persistent, require_owner, say_lines, store};
+use crate::memreport::{Introspect, Owner};
+
+/// Whether every guild is read-only, set from `config.json` or by the bot's owner until the next restart.
+static GLOBAL: AtomicBool = AtomicBool::new(false);
+
+pub struct StateKey;
+
+impl TypeMapKey for StateKey {
+    type Value = Persistent<State>;
+}
+
+/// Guilds in which the bot only watches, without changing anything.
+#[derive(Serialize, Deserialize, Default, Clone, Eq, PartialEq)]
+pub struct State {
+    guilds: BTreeSet<GuildId>,
+}
+
+impl Introspect for State {
+    fn entries(&self) -> Vec<(Owner, usize)> {
+        self.guilds.iter().map(|guild| (Owner::Guild(*guild), 1)).collect()
+    }
+}
+
+/// Whether a command can still be used in read-only mode, because it only shows things.
+pub fn allows_command(tokens: &[&str]) -> bool {
+    matches!(
+        tokens,
+        ["read-only", ..]
+            | ["roles"]
+            | ["help", ..]
+            | ["case", _]
+            | ["cases", _]
+            | ["selector", "bans"]
+            | ["onboarding", "dm"]
+            | ["progress", "roles"]
+            | ["selector", "requirements"]
+            | ["selector", "sweep"]
+            | ["command", "prefix"]
+            | ["experiments"]
+            | ["list", "role", "selectors"]
+            | ["stats", "role", "selector", _]
+            | ["list", "webhooks"]
+            | ["screening"]
+            | ["verification"]
+            | ["ping", "stats", _]
+            | ["export", "guild-setup"]
+            | ["export", "config"]
+            | ["backup"]
+            | ["backup", "now"]
+            | ["config", "sync"]
+            | ["command", "stats"]
+            | ["error", "cleanup"]
+            | ["latency"]
+            | ["quotas"]
+            | ["memreport"]
+            | ["migrate", "to", "replica", ..]
+            | ["selfcheck"]
+            | ["search", "history", ..]
+            | ["data", "retention"]
+            | ["setup", "status"]
+            | ["doctor"]
+            | ["telemetry", "status"]
+            | ["role", "channel", "links"]
+            | ["role", "dependencies"]
+            | ["emoji", "stats"]
+    )
+}
+
+pub fn set_global(read_only: bool) {
+    GLOBAL.store(read_only, Ordering::SeqCst);
+}
+
+pub fn is_global() -> bool {
+    GLOBAL.load(Ordering::SeqCst)
+}
+
+/// Whether the bot is read-only in a guild, or everywhere when there's no guild.
+pub async fn is_active(ctx: &Context, guild: impl Into<Option<GuildId>>) -> bool {
+    if is_global() {
+        return true;
+    }
+    match guild.into() {
+        Some(guild) => store::<StateKey>(ctx).await.read().await.guilds.contains(&guild),
+        None => false,
+    }
+}
+
+/// Checks whether an action has to be skipped because the bot is read-only, logging what it
+/// would have done if so.
+pub async fn blocks<F: FnOnce() -> String>(ctx: &Context, guild: impl Into<Option<GuildId>>, action: F) -> bool {
+    let guild = guild.into();
+    if !is_active(ctx, guild).await {
+        return false;
+    }
+    match guild {
+        Some(guild) => info!("read-only: would have {} in {}", action(), guild),
+        None => info!("read-only: would have {}", action()),
+    }
+    true
+}
+
+pub async fn set_guild(ctx: &Context, command: &Message, read_only: bool) -> CommandResult<()> {
+    let guild = command.guild_id.ok_or(CommandError::NotAllowed)?;
+
+    let state = store::<StateKey>(ctx).await;
+    state.write(|state| {
+        if read_only {
+            state.guilds.insert(guild);
+        } else {
+            state.guilds.remove(&guild);
+        }
+    }).await;
+
+    let log = if read_only {
+        format!("👀 <@{}> made me read-only: I'll keep watching, but won't change anything", command.author.id.get())
+    } else {
+        format!("✍️ <@{}> turned read-only mode off", command.author.id.get())
+    };
+    audit::log(ctx, guild, log).await;
+
+    Ok(())
+}
+
+/// Makes every guild read-only until the next restart.
+pub async fn set_global_command(ctx: &Context, command: &Message, read_only: bool) -> CommandResult<()> {
+    require_owner(ctx, &command.author).await?;
+
+    set_global(read_only);
+    info!("{} turned global read-only mode {}", command.author.id, if read_only { "on" } else { "off" });
+
+    Ok(())
+}
+
+pub async fn status(ctx: &Context, command: &Message) -> CommandResult<()> {
+    let line = if is_global() {
+        "👀 I'm read-only everywhere for now, so I won't change anything."
+    } else if is_active(ctx, command.guild_id).await {
+        "👀 I'm read-only in this server, so I won't change anything. Turn it off with `read-only off`."
+    } else {
+        "I'm not read-only here."
+    };
+    say_lines(ctx, command.channel_id, &[line.to_owned()]).await?;
+
+    Ok(())
+}
+
+/// Forgets all guilds not in `guilds`, returning the ones that were removed.
+pub async fn retain_guilds(ctx: &Context, guilds: &HashSet<GuildId>) -> Vec<GuildId> {
+    let state = store::<StateKey>(ctx).await;
+    state.write(|state| {
+        let removed = state.guilds.iter().filter(|guild| !guilds.contains(guild)).copied().collect();
+        state.guilds.retain(|guild| guilds.contains(guild));
+        removed
+    }).await
+}
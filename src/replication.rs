@@ -0,0 +1,243 @@
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+
+use indexmap::IndexMap;
+use log::error;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use serenity::futures::FutureExt;
+use serenity::prelude::TypeMapKey;
+use tokio::sync::mpsc;
+
+use crate::dormancy;
+
+pub mod migrate;
+pub mod s3;
+
+/// How long to wait before trying a failed copy again.
+const RETRY_DELAY: Duration = Duration::from_secs(30);
+
+/// Copies of store writes waiting to be sent to the replica, once replication has started.
+static WRITES: OnceLock<mpsc::UnboundedSender<(String, Vec<u8>)>> = OnceLock::new();
+
+/// Where every store is copied to, so that a bot on a fresh disk can pick up where it left off.
+/// Only one of the two should be set; the bucket is used if both are.
+#[derive(Serialize, Deserialize, Default, Clone, Eq, PartialEq)]
+pub struct ReplicationConfig {
+    /// A directory on another disk, like a mounted volume.
+    #[serde(default)]
+    pub directory: Option<PathBuf>,
+    #[serde(default)]
+    pub s3: Option<s3::S3Config>,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum ReplicaError {
+    #[error("{0}")]
+    Io(#[from] io::Error),
+    #[error("{0}")]
+    Http(#[from] reqwest::Error),
+    #[error("bucket responded with {0}")]
+    Status(reqwest::StatusCode),
+    #[error("the bucket endpoint isn't a valid URL")]
+    InvalidEndpoint,
+}
+
+pub struct ReplicaKey;
+
+impl TypeMapKey for ReplicaKey {
+    type Value = Arc<Replica>;
+}
+
+pub enum Replica {
+    Directory(PathBuf),
+    S3(s3::Bucket),
+}
+
+impl Replica {
+    pub fn new(config: &ReplicationConfig) -> Option<Replica> {
+        match (&config.s3, &config.directory) {
+            (Some(s3), _) => Some(Replica::S3(s3::Bucket::new(s3.clone()))),
+            (None, Some(directory)) => Some(Replica::Directory(directory.clone())),
+            (None, None) => None,
+        }
+    }
+
+    /// Reads the replica settings from the config file without loading the rest of it, since
+    /// stores have to be restored before the config is validated along with them.
+    pub fn from_config_file(path: &Path) -> Option<Replica> {
+        let bytes = std::fs::read(path).ok()?;
+        let mut config: Value = serde_json::from_slice(&bytes).ok()?;
+        let config: ReplicationConfig = serde_json::from_value(config.get_mut("replication")?.take()).ok()?;
+        Replica::new(&config)
+    }
+
+    pub async fn put(&self, file: &str, bytes: &[u8]) -> Result<(), ReplicaError> {
+        match self {
+            Replica::Directory(directory) => {
+                let path = directory.join(file);
+                if let Some(parent) = path.parent() {
+                    tokio::fs::create_dir_all(parent).await?;
+                }
+                // write next to the copy and move it over, so that a crash can't leave it half-written
+                let temporary = directory.join(format!("{}.tmp", file));
+                tokio::fs::write(&temporary, bytes).await?;
+                tokio::fs::rename(&temporary, path).await?;
+                Ok(())
+            }
+            Replica::S3(bucket) => bucket.put(file, bytes).await,
+        }
+    }
+
+    pub async fn get(&self, file: &str) -> Result<Option<Vec<u8>>, ReplicaError> {
+        match self {
+            Replica::Directory(directory) => match tokio::fs::read(directory.join(file)).await {
+                Ok(bytes) => Ok(Some(bytes)),
+                Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+                Err(err) => Err(err.into()),
+            },
+            Replica::S3(bucket) => bucket.get(file).await,
+        }
+    }
+
+    /// Lists the names of the stores held by the replica, along with the archived state of idle
+    /// guilds like `archived_guilds/1.json`.
+    pub async fn list(&self) -> Result<Vec<String>, ReplicaError> {
+        match self {
+            Replica::Directory(directory) => {
+                let mut files = list_json(directory, None).await?;
+                let mut entries = match tokio::fs::read_dir(directory).await {
+                    Ok(entries) => entries,
+                    Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(files),
+                    Err(err) => return Err(err.into()),
+                };
+                while let Some(entry) = entries.next_entry().await? {
+                    if !entry.file_type().await?.is_dir() {
+                        continue;
+                    }
+                    if let Some(name) = entry.file_name().to_str().filter(|name| name.ends_with(dormancy::ARCHIVE_DIRECTORY)) {
+                        files.extend(list_json(&entry.path(), Some(name)).await?);
+                    }
+                }
+                files.sort();
+                Ok(files)
+            }
+            Replica::S3(bucket) => bucket.list().await,
+        }
+    }
+
+    /// Copies every store in the replica into `dir` that is missing there or isn't valid JSON,
+    /// returning the names of the stores that were restored. Copies that are broken themselves are
+    /// left alone. This runs before logging is set up, so problems are printed to stderr.
+    pub async fn restore(&self, dir: &Path) -> Vec<String> {
+        let files = match self.list().await {
+            Ok(files) => files,
+            Err(err) => {
+                eprintln!("failed to list the replica, nothing was restored: {}", err);
+                return Vec::new();
+            }
+        };
+
+        let mut restored = Vec::new();
+        for file in files {
+            let path = dir.join(&file);
+            if let Ok(bytes) = tokio::fs::read(&path).await {
+                if serde_json::from_slice::<Value>(&bytes).is_ok() {
+                    continue;
+                }
+            }
+
+            let bytes = match self.get(&file).await {
+                Ok(Some(bytes)) if serde_json::from_slice::<Value>(&bytes).is_ok() => bytes,
+                Ok(_) => {
+                    eprintln!("the replica of {} is broken too, so it wasn't restored", file);
+                    continue;
+                }
+                Err(err) => {
+                    eprintln!("failed to fetch the replica of {}: {}", file, err);
+                    continue;
+                }
+            };
+            if let Some(parent) = path.parent() {
+                let _ = tokio::fs::create_dir_all(parent).await;
+            }
+            match tokio::fs::write(&path, bytes).await {
+                Ok(()) => {
+                    eprintln!("restored {} from the replica", file);
+                    restored.push(file);
+                }
+                Err(err) => eprintln!("failed to restore {}: {}", file, err),
+            }
+        }
+
+        restored
+    }
+}
+
+/// Lists the JSON files directly in `directory`, named relative to its parent when `name` is given.
+async fn list_json(directory: &Path, name: Option<&str>) -> io::Result<Vec<String>> {
+    let mut files = Vec::new();
+    let mut entries = match tokio::fs::read_dir(directory).await {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(files),
+        Err(err) => return Err(err),
+    };
+    while let Some(entry) = entries.next_entry().await? {
+        if let Some(file) = entry.file_name().to_str().filter(|file| file.ends_with(".json")) {
+            match name {
+                Some(name) => files.push(format!("{}/{}", name, file)),
+                None => files.push(file.to_owned()),
+            }
+        }
+    }
+    Ok(files)
+}
+
+/// Starts copying every store write to the replica in the background.
+pub fn start(replica: Arc<Replica>) {
+    let (sender, writes) = mpsc::unbounded_channel();
+    if WRITES.set(sender).is_ok() {
+        tokio::spawn(run(replica, writes));
+    }
+}
+
+/// Queues a copy of a store that was just written, if replication has started.
+pub fn replicate(path: &Path, bytes: Vec<u8>) {
+    if let Some(file) = path.file_name().and_then(|file| file.to_str()) {
+        replicate_as(file, bytes);
+    }
+}
+
+/// Queues a copy of a file that isn't a store under the given name, which may lie in a directory
+/// like `archived_guilds/1.json`.
+pub fn replicate_as(file: &str, bytes: Vec<u8>) {
+    if let Some(sender) = WRITES.get() {
+        let _ = sender.send((file.to_owned(), bytes));
+    }
+}
+
+async fn run(replica: Arc<Replica>, mut writes: mpsc::UnboundedReceiver<(String, Vec<u8>)>) {
+    // only the latest write of each store matters, so writes that queue up while the replica is
+    // slow replace each other
+    let mut pending: IndexMap<String, Vec<u8>> = IndexMap::new();
+    loop {
+        if pending.is_empty() {
+            match writes.recv().await {
+                Some((file, bytes)) => pending.insert(file, bytes),
+                None => return,
+            };
+        }
+        while let Some(Some((file, bytes))) = writes.recv().now_or_never() {
+            pending.insert(file, bytes);
+        }
+
+        let (file, bytes) = pending.shift_remove_index(0).expect("pending writes can't be empty");
+        if let Err(err) = replica.put(&file, &bytes).await {
+            error!("failed to replicate {}: {}", file, err);
+            pending.entry(file).or_insert(bytes);
+            tokio::time::sleep(RETRY_DELAY).await;
+        }
+    }
+}
@@ -0,0 +1,144 @@
+use std::path::{Path, PathBuf};
+
+use log::error;
+use serde_json::Value;
+use serenity::model::prelude::*;
+use serenity::prelude::*;
+
+use super::{Replica, ReplicaError, ReplicaKey};
+use crate::{CommandError, CommandResult, OpenOptions, OptionsKey, require_owner, say_lines, StoreConfig};
+
+/// The config is set up by hand for each place the bot runs, so it's never copied along.
+const CONFIG_FILE: &str = "config.json";
+
+/// How copying a single store went.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Outcome {
+    /// The store was copied and read back the same, with its size in bytes.
+    Copied(usize),
+    /// The store would have been copied in a dry run, with its size in bytes.
+    WouldCopy(usize),
+    /// The store isn't valid JSON, so it wasn't copied.
+    Invalid,
+    /// The copy failed, or read back differently.
+    Failed(String),
+}
+
+pub struct Migration {
+    pub stores: Vec<(String, Outcome)>,
+}
+
+impl Migration {
+    /// Whether every store was (or would be) copied.
+    pub fn is_complete(&self) -> bool {
+        self.stores.iter().all(|(_, outcome)| matches!(outcome, Outcome::Copied(_) | Outcome::WouldCopy(_)))
+    }
+
+    pub fn describe(&self) -> Vec<String> {
+        let mut lines: Vec<String> = self.stores.iter()
+            .map(|(file, outcome)| match outcome {
+                Outcome::Copied(bytes) => format!("✔ {} ({} bytes)", file, bytes),
+                Outcome::WouldCopy(bytes) => format!("… {} would be copied ({} bytes)", file, bytes),
+                Outcome::Invalid => format!("✖ {} isn't valid JSON, so it wasn't copied", file),
+                Outcome::Failed(err) => format!("✖ {} couldn't be copied: {}", file, err),
+            })
+            .collect();
+        if lines.is_empty() {
+            lines.push("There were no stores to copy.".to_owned());
+        }
+        lines
+    }
+}
+
+/// Copies every store whose name starts with `prefix` from one place to another, reading each copy
+/// back to check that it arrived intact. A dry run only checks that the stores can be read.
+pub async fn migrate(from: &Replica, to: &Replica, prefix: &str, dry_run: bool) -> Result<Migration, ReplicaError> {
+    let mut stores = Vec::new();
+    for file in from.list().await? {
+        if file == CONFIG_FILE || !file.starts_with(prefix) {
+            continue;
+        }
+        let outcome = copy(from, to, &file, dry_run).await;
+        stores.push((file, outcome));
+    }
+    Ok(Migration { stores })
+}
+
+async fn copy(from: &Replica, to: &Replica, file: &str, dry_run: bool) -> Outcome {
+    let bytes = match from.get(file).await {
+        Ok(Some(bytes)) => bytes,
+        Ok(None) => return Outcome::Failed("it disappeared".to_owned()),
+        Err(err) => return Outcome::Failed(err.to_string()),
+    };
+    if serde_json::from_slice::<Value>(&bytes).is_err() {
+        return Outcome::Invalid;
+    }
+    if dry_run {
+        return Outcome::WouldCopy(bytes.len());
+    }
+
+    if let Err(err) = to.put(file, &bytes).await {
+        return Outcome::Failed(err.to_string());
+    }
+    match to.get(file).await {
+        Ok(Some(copied)) if copied == bytes => Outcome::Copied(bytes.len()),
+        Ok(_) => Outcome::Failed("it read back differently".to_owned()),
+        Err(err) => Outcome::Failed(err.to_string()),
+    }
+}
+
+/// Copies the stores to the replica, like when it's newly set up and stores that haven't changed
+/// since haven't been copied yet.
+pub async fn command(ctx: &Context, command: &Message, dry_run: bool) -> CommandResult<()> {
+    require_owner(ctx, &command.author).await?;
+
+    let (replica, options) = {
+        let data = ctx.data.read().await;
+        let replica = data.get::<ReplicaKey>().cloned().ok_or(CommandError::ReplicationOff)?;
+        (replica, data.get::<OptionsKey>().cloned().unwrap_or_default())
+    };
+
+    let local = Replica::Directory(options.dir().to_owned());
+    let migration = match migrate(&local, &replica, options.file_prefix(), dry_run).await {
+        Ok(migration) => migration,
+        Err(err) => {
+            error!("failed to copy the stores to the replica: {}", err);
+            return Err(CommandError::ReplicaUnavailable);
+        }
+    };
+    say_lines(ctx, command.channel_id, &migration.describe()).await?;
+
+    Ok(())
+}
+
+/// Copies the stores between two places, as `migrate` on the command line while the bot is stopped.
+pub async fn run_cli(args: &[String]) -> Result<(), String> {
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    let (from, to, dry_run) = match args.as_slice() {
+        ["--from", from, "--to", to] => (*from, *to, false),
+        ["--from", from, "--to", to, "--dry-run"] => (*from, *to, true),
+        _ => return Err("usage: migrate --from <local|replica|directory> --to <local|replica|directory> [--dry-run]".to_owned()),
+    };
+
+    // the bot's stores are the ones with its prefix, wherever they're copied from
+    let stores = StoreConfig::from_config_file(Path::new(CONFIG_FILE)).open_options();
+    let from = storage(from, &stores)?;
+    let to = storage(to, &stores)?;
+    let migration = migrate(&from, &to, stores.file_prefix(), dry_run).await.map_err(|err| format!("failed to list the stores: {}", err))?;
+    for line in migration.describe() {
+        println!("{}", line);
+    }
+
+    if !migration.is_complete() {
+        return Err("some stores weren't copied".to_owned());
+    }
+    Ok(())
+}
+
+fn storage(name: &str, stores: &OpenOptions) -> Result<Replica, String> {
+    match name {
+        "local" => Ok(Replica::Directory(stores.dir().to_owned())),
+        "replica" => Replica::from_config_file(Path::new(CONFIG_FILE)).ok_or_else(|| "replication isn't set up in config.json".to_owned()),
+        directory => Ok(Replica::Directory(PathBuf::from(directory))),
+    }
+}
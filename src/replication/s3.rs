@@ -0,0 +1,194 @@
+use chrono::Utc;
+use regex::Regex;
+use reqwest::{Method, StatusCode, Url};
+use ring::{digest, hmac};
+use serde::{Deserialize, Serialize};
+
+use super::ReplicaError;
+
+/// A bucket of any S3-compatible storage, addressed by path so that self-hosted ones work too.
+#[derive(Serialize, Deserialize, Clone, Eq, PartialEq)]
+pub struct S3Config {
+    /// Like `https://s3.eu-central-1.amazonaws.com` or `http://minio:9000`.
+    pub endpoint: String,
+    pub bucket: String,
+    #[serde(default = "default_region")]
+    pub region: String,
+    /// Put in front of the name of each store, like `mossy/`.
+    #[serde(default)]
+    pub prefix: String,
+    pub access_key: String,
+    pub secret_key: String,
+}
+
+fn default_region() -> String {
+    "us-east-1".to_owned()
+}
+
+pub struct Bucket {
+    config: S3Config,
+    client: reqwest::Client,
+}
+
+impl Bucket {
+    pub fn new(config: S3Config) -> Self {
+        Bucket { config, client: reqwest::Client::new() }
+    }
+
+    pub async fn put(&self, file: &str, bytes: &[u8]) -> Result<(), ReplicaError> {
+        let response = self.request(Method::PUT, &self.key(file), &[], bytes.to_vec()).await?;
+        match response.status() {
+            status if status.is_success() => Ok(()),
+            status => Err(ReplicaError::Status(status)),
+        }
+    }
+
+    pub async fn get(&self, file: &str) -> Result<Option<Vec<u8>>, ReplicaError> {
+        let response = self.request(Method::GET, &self.key(file), &[], Vec::new()).await?;
+        match response.status() {
+            StatusCode::NOT_FOUND => Ok(None),
+            status if status.is_success() => Ok(Some(response.bytes().await?.to_vec())),
+            status => Err(ReplicaError::Status(status)),
+        }
+    }
+
+    /// Lists the stores under the prefix. A bucket answers with at most 1000 keys, which is far
+    /// more than there are stores.
+    pub async fn list(&self) -> Result<Vec<String>, ReplicaError> {
+        let query = [("list-type", "2"), ("prefix", self.config.prefix.as_str())];
+        let response = self.request(Method::GET, "", &query, Vec::new()).await?;
+        if !response.status().is_success() {
+            return Err(ReplicaError::Status(response.status()));
+        }
+
+        let body = response.text().await?;
+        let files = list_keys(&body).into_iter()
+            .filter_map(|key| key.strip_prefix(&self.config.prefix).map(str::to_owned))
+            .filter(|file| !file.contains('/') && file.ends_with(".json"))
+            .collect();
+        Ok(files)
+    }
+
+    fn key(&self, file: &str) -> String {
+        format!("{}{}", self.config.prefix, file)
+    }
+
+    async fn request(&self, method: Method, key: &str, query: &[(&str, &str)], body: Vec<u8>) -> Result<reqwest::Response, ReplicaError> {
+        let path = if key.is_empty() {
+            format!("/{}", uri_encode(&self.config.bucket, true))
+        } else {
+            format!("/{}/{}", uri_encode(&self.config.bucket, true), uri_encode(key, false))
+        };
+        let mut query: Vec<(String, String)> = query.iter().map(|(name, value)| (uri_encode(name, true), uri_encode(value, true))).collect();
+        query.sort();
+        let query = query.iter().map(|(name, value)| format!("{}={}", name, value)).collect::<Vec<_>>().join("&");
+
+        let mut url = format!("{}{}", self.config.endpoint.trim_end_matches('/'), path);
+        if !query.is_empty() {
+            url = format!("{}?{}", url, query);
+        }
+        let url = Url::parse(&url).map_err(|_| ReplicaError::InvalidEndpoint)?;
+        let host = match (url.host_str(), url.port()) {
+            (Some(host), Some(port)) => format!("{}:{}", host, port),
+            (Some(host), None) => host.to_owned(),
+            (None, _) => return Err(ReplicaError::InvalidEndpoint),
+        };
+
+        let now = Utc::now();
+        let request = SignedRequest {
+            method: method.as_str(),
+            path: &path,
+            query: &query,
+            host: &host,
+            payload_hash: hex(digest::digest(&digest::SHA256, &body).as_ref()),
+            timestamp: now.format("%Y%m%dT%H%M%SZ").to_string(),
+        };
+        let authorization = request.authorization(&self.config);
+
+        let response = self.client.request(method.clone(), url)
+            .header("x-amz-date", &request.timestamp)
+            .header("x-amz-content-sha256", &request.payload_hash)
+            .header("authorization", authorization)
+            .body(body)
+            .send()
+            .await?;
+        Ok(response)
+    }
+}
+
+/// The parts of a request that are signed with AWS Signature Version 4.
+pub struct SignedRequest<'a> {
+    pub method: &'a str,
+    /// The URI-encoded path.
+    pub path: &'a str,
+    /// The URI-encoded query, sorted by name.
+    pub query: &'a str,
+    pub host: &'a str,
+    pub payload_hash: String,
+    /// When the request is made, like `20130524T000000Z`.
+    pub timestamp: String,
+}
+
+impl SignedRequest<'_> {
+    pub fn authorization(&self, config: &S3Config) -> String {
+        const SIGNED_HEADERS: &str = "host;x-amz-content-sha256;x-amz-date";
+
+        let canonical = format!(
+            "{}\n{}\n{}\nhost:{}\nx-amz-content-sha256:{}\nx-amz-date:{}\n\n{}\n{}",
+            self.method, self.path, self.query, self.host, self.payload_hash, self.timestamp, SIGNED_HEADERS, self.payload_hash,
+        );
+
+        let date = &self.timestamp[..8];
+        let scope = format!("{}/{}/s3/aws4_request", date, config.region);
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+            self.timestamp, scope, hex(digest::digest(&digest::SHA256, canonical.as_bytes()).as_ref()),
+        );
+
+        let key = signing_key(&config.secret_key, date, &config.region, "s3");
+        let signature = hex(hmac::sign(&hmac::Key::new(hmac::HMAC_SHA256, &key), string_to_sign.as_bytes()).as_ref());
+
+        format!(
+            "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+            config.access_key, scope, SIGNED_HEADERS, signature,
+        )
+    }
+}
+
+/// Derives the key that requests to a service are signed with on a given day, like `20130524`.
+fn signing_key(secret_key: &str, date: &str, region: &str, service: &str) -> Vec<u8> {
+    let sign = |key: &[u8], data: &str| hmac::sign(&hmac::Key::new(hmac::HMAC_SHA256, key), data.as_bytes()).as_ref().to_vec();
+    let key = sign(format!("AWS4{}", secret_key).as_bytes(), date);
+    let key = sign(&key, region);
+    let key = sign(&key, service);
+    sign(&key, "aws4_request")
+}
+
+/// Finds the keys in a `ListObjectsV2` response.
+pub fn list_keys(body: &str) -> Vec<String> {
+    let key = Regex::new("<Key>([^<]*)</Key>").unwrap();
+    key.captures_iter(body)
+        .map(|captures| unescape_xml(&captures[1]))
+        .collect()
+}
+
+fn unescape_xml(text: &str) -> String {
+    text.replace("&lt;", "<").replace("&gt;", ">").replace("&quot;", "\"").replace("&apos;", "'").replace("&amp;", "&")
+}
+
+/// Encodes everything but unreserved characters, and slashes unless `encode_slash` is set.
+fn uri_encode(text: &str, encode_slash: bool) -> String {
+    let mut encoded = String::with_capacity(text.len());
+    for byte in text.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => encoded.push(byte as char),
+            b'/' if !encode_slash => encoded.push('/'),
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
@@ -0,0 +1,174 @@
+use std::collections::{HashMap, HashSet};
+
+use log::info;
+use serde::{Deserialize, Serialize};
+use serenity::model::prelude::*;
+use serenity::prelude::*;
+
+use crate::{CommandError, CommandResult, Persistent, scheduler, store};
+use crate::memreport::{Introspect, Owner};
+
+const DAY_SECS: i64 = 24 * 60 * 60;
+
+/// How long a guild's data is kept after the bot is removed, unless the guild chose otherwise.
+pub const DEFAULT_RETENTION_DAYS: u32 = 30;
+
+pub const MAX_RETENTION_DAYS: u32 = 365;
+
+/// How long before deletion the guild owner gets warned.
+const NOTICE_SECS: i64 = 3 * DAY_SECS;
+
+pub struct StateKey;
+
+impl TypeMapKey for StateKey {
+    type Value = Persistent<State>;
+}
+
+#[derive(Serialize, Deserialize, Default, Clone, Eq, PartialEq)]
+pub struct State {
+    guilds: HashMap<GuildId, GuildRetention>,
+}
+
+impl Introspect for State {
+    fn entries(&self) -> Vec<(Owner, usize)> {
+        self.guilds.keys().map(|guild| (Owner::Guild(*guild), 1)).collect()
+    }
+}
+
+#[derive(Serialize, Deserialize, Default, Clone, Eq, PartialEq)]
+struct GuildRetention {
+    /// How many days to keep data for after removal, or the default when unset.
+    #[serde(default)]
+    days: Option<u32>,
+    /// When we were last in the guild, which is when the retention period starts once we're removed.
+    last_seen: i64,
+    /// Who to warn before the data gets deleted.
+    #[serde(default)]
+    owner: Option<UserId>,
+    #[serde(default)]
+    name: Option<String>,
+    #[serde(default)]
+    notified: bool,
+}
+
+impl GuildRetention {
+    fn expires_at(&self) -> i64 {
+        self.last_seen + self.days.unwrap_or(DEFAULT_RETENTION_DAYS) as i64 * DAY_SECS
+    }
+}
+
+pub async fn set_days(ctx: &Context, command: &Message, days: u32) -> CommandResult<()> {
+    let guild = command.guild_id.ok_or(CommandError::NotAllowed)?;
+
+    if days > MAX_RETENTION_DAYS {
+        return Err(CommandError::MalformedArgument(days.to_string()));
+    }
+
+    let now = scheduler::now();
+    let state = store::<StateKey>(ctx).await;
+    state.write(|state| {
+        let retention = state.guilds.entry(guild).or_default();
+        retention.days = Some(days);
+        retention.last_seen = now;
+    }).await;
+
+    Ok(())
+}
+
+pub async fn status(ctx: &Context, command: &Message) -> CommandResult<()> {
+    let guild = command.guild_id.ok_or(CommandError::NotAllowed)?;
+
+    let days = {
+        let state = store::<StateKey>(ctx).await;
+        let state = state.read().await;
+        state.guilds.get(&guild).and_then(|retention| retention.days)
+    };
+
+    let content = match days.unwrap_or(DEFAULT_RETENTION_DAYS) {
+        0 => "🗑 This server's data is deleted as soon as I'm removed.".to_owned(),
+        days => format!("🗑 This server's data is kept for {} days after I'm removed, in case I'm added back.", days),
+    };
+    command.channel_id.say(&ctx.http, content).await?;
+
+    Ok(())
+}
+
+/// Starts the retention period of a guild we were removed from. Outages don't count as removal.
+pub async fn guild_delete(ctx: &Context, incomplete: UnavailableGuild, full: Option<Guild>) {
+    if incomplete.unavailable {
+        return;
+    }
+
+    let now = scheduler::now();
+    let state = store::<StateKey>(ctx).await;
+    state.write(|state| {
+        let retention = state.guilds.entry(incomplete.id).or_default();
+        retention.last_seen = now;
+        if let Some(guild) = full {
+            retention.owner = Some(guild.owner_id);
+            retention.name = Some(guild.name);
+        }
+    }).await;
+}
+
+/// Works out which guilds' data should be kept: every guild we're in, and those we were removed from
+/// within their retention period. Owners are warned before their guild's data is deleted.
+///
+/// Guilds that we left before their first maintenance run aren't known here, and aren't kept.
+pub async fn retained_guilds(ctx: &Context, current: &HashSet<GuildId>) -> HashSet<GuildId> {
+    let now = scheduler::now();
+
+    let mut owners = HashMap::new();
+    for guild in current {
+        let owner = ctx.cache.guild(*guild).map(|guild| (guild.owner_id, guild.name.clone()));
+        if let Some(owner) = owner {
+            owners.insert(*guild, owner);
+        }
+    }
+
+    let state = store::<StateKey>(ctx).await;
+    let (retained, notices) = state.write(|state| {
+        for guild in current {
+            let retention = state.guilds.entry(*guild).or_default();
+            retention.last_seen = now;
+            retention.notified = false;
+            if let Some((owner, name)) = owners.remove(guild) {
+                retention.owner = Some(owner);
+                retention.name = Some(name);
+            }
+        }
+
+        let expired: Vec<GuildId> = state.guilds.iter()
+            .filter(|(guild, retention)| !current.contains(guild) && retention.expires_at() <= now)
+            .map(|(guild, _)| *guild)
+            .collect();
+        for guild in &expired {
+            state.guilds.remove(guild);
+        }
+
+        let mut notices = Vec::new();
+        for (guild, retention) in &mut state.guilds {
+            if !current.contains(guild) && !retention.notified && retention.expires_at() - NOTICE_SECS <= now {
+                retention.notified = true;
+                if let Some(owner) = retention.owner {
+                    notices.push((owner, retention.name.clone().unwrap_or_else(|| guild.get().to_string()), retention.expires_at()));
+                }
+            }
+        }
+
+        (state.guilds.keys().copied().collect::<HashSet<_>>(), notices)
+    }).await;
+
+    for (owner, name, expires_at) in notices {
+        info!("warning owner {} that data of {} will be deleted", owner, name);
+        let content = format!(
+            "👋 I was removed from **{}**, so its role selectors and other settings will be deleted <t:{}:R>. Add me back before then to keep them!",
+            name, expires_at,
+        );
+        if let Ok(dm) = owner.create_dm_channel(&ctx.http).await {
+            let _ = dm.say(&ctx.http, content).await;
+        }
+    }
+
+    retained
+}
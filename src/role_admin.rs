@@ -0,0 +1,136 @@
+use std::collections::HashMap;
+
+use serde_json::{json, Value};
+use serenity::builder::EditRole;
+use serenity::model::prelude::*;
+use serenity::prelude::*;
+
+use crate::{audit, CommandError, CommandResult};
+
+/// Discord's size limit for role icons.
+const MAX_ICON_BYTES: u32 = 256 * 1024;
+
+/// Where a new role icon should come from.
+pub enum IconSource {
+    /// A unicode emoji, or a custom emoji whose image gets uploaded.
+    Emoji(String),
+    /// The image attached to the command.
+    Attachment,
+    /// Remove the icon.
+    None,
+}
+
+/// Parses a hex colour like `#ff8800` or `ff8800`.
+pub fn parse_colour(colour: &str) -> Option<u64> {
+    let hex = colour.trim_start_matches('#');
+    if hex.len() != 6 {
+        return None;
+    }
+    u64::from_str_radix(hex, 16).ok()
+}
+
+/// Checks that both the user and the bot rank above `role`, so that nobody can use us to edit roles
+/// they couldn't edit themselves.
+pub async fn check_hierarchy(ctx: &Context, guild: GuildId, user: UserId, role: RoleId) -> CommandResult<Role> {
+    let roles: HashMap<RoleId, Role> = ctx.http.get_guild_roles(guild).await?
+        .into_iter()
+        .map(|role| (role.id, role))
+        .collect();
+    let role = roles.get(&role).cloned().ok_or(CommandError::UnknownRole)?;
+
+    let highest_position = |member: &Member| {
+        member.roles.iter()
+            .filter_map(|role| roles.get(role))
+            .map(|role| role.position)
+            .max()
+            .unwrap_or(0)
+    };
+
+    let owner = ctx.cache.guild(guild).map(|guild| guild.owner_id);
+    if owner != Some(user) {
+        let member = guild.member(ctx, user).await?;
+        if highest_position(&member) <= role.position {
+            return Err(CommandError::RoleAboveYou);
+        }
+    }
+
+    let bot = ctx.cache.current_user().id;
+    let bot = guild.member(ctx, bot).await?;
+    if highest_position(&bot) <= role.position {
+        return Err(CommandError::RoleAboveMe);
+    }
+
+    Ok(role)
+}
+
+pub async fn set_colour(ctx: &Context, command: &Message, role: RoleId, colour: u64) -> CommandResult<()> {
+    let guild = command.guild_id.ok_or(CommandError::NotAllowed)?;
+    let role = check_hierarchy(ctx, guild, command.author.id, role).await?;
+
+    guild.edit_role(&ctx.http, role.id, EditRole::new().colour(colour)).await?;
+
+    audit::log(ctx, guild, format!(
+        "<@{}> changed the colour of <@&{}> from #{:06x} to #{:06x}",
+        command.author.id.get(), role.id.get(), role.colour.0, colour,
+    )).await;
+
+    Ok(())
+}
+
+pub async fn set_icon(ctx: &Context, command: &Message, role: RoleId, source: IconSource) -> CommandResult<()> {
+    let guild = command.guild_id.ok_or(CommandError::NotAllowed)?;
+    let role = check_hierarchy(ctx, guild, command.author.id, role).await?;
+
+    let (icon, unicode_emoji) = match source {
+        IconSource::Emoji(emoji) => match serenity::utils::parse_emoji(&emoji) {
+            Some(custom) => {
+                let url = format!("https://cdn.discordapp.com/emojis/{}.png", custom.id.get());
+                (Some(download_icon(&url).await?), None)
+            }
+            None if !emoji.is_ascii() => (None, Some(emoji)),
+            None => return Err(CommandError::InvalidRoleIcon),
+        },
+        IconSource::Attachment => {
+            let attachment = command.attachments.first().ok_or(CommandError::MissingAttachment)?;
+            let mime = icon_mime(&attachment.filename).ok_or(CommandError::InvalidRoleIcon)?;
+            if attachment.size > MAX_ICON_BYTES {
+                return Err(CommandError::InvalidRoleIcon);
+            }
+            (Some(data_uri(mime, &attachment.download().await?)), None)
+        }
+        IconSource::None => (None, None),
+    };
+
+    let map = json!({ "icon": icon, "unicode_emoji": unicode_emoji });
+    if let Value::Object(map) = map {
+        ctx.http.edit_role(guild, role.id, &map, None).await?;
+    }
+
+    audit::log(ctx, guild, format!("<@{}> changed the icon of <@&{}>", command.author.id.get(), role.id.get())).await;
+
+    Ok(())
+}
+
+async fn download_icon(url: &str) -> CommandResult<String> {
+    let response = reqwest::get(url).await
+        .and_then(|response| response.error_for_status())
+        .map_err(|_| CommandError::InvalidRoleIcon)?;
+    let bytes = response.bytes().await.map_err(|_| CommandError::InvalidRoleIcon)?;
+    if bytes.len() > MAX_ICON_BYTES as usize {
+        return Err(CommandError::InvalidRoleIcon);
+    }
+    Ok(data_uri("image/png", &bytes))
+}
+
+fn icon_mime(filename: &str) -> Option<&'static str> {
+    let extension = filename.rsplit('.').next()?.to_lowercase();
+    match extension.as_str() {
+        "png" => Some("image/png"),
+        "jpg" | "jpeg" => Some("image/jpeg"),
+        _ => None,
+    }
+}
+
+fn data_uri(mime: &str, bytes: &[u8]) -> String {
+    format!("data:{};base64,{}", mime, base64::encode(bytes))
+}
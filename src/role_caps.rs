@@ -0,0 +1,173 @@
+use std::collections::{HashMap, HashSet};
+
+use serde::{Deserialize, Serialize};
+use serenity::model::prelude::*;
+use serenity::prelude::*;
+
+use crate::{capabilities, CommandError, CommandResult, Persistent, store};
+use crate::memreport::{Introspect, Owner, Reference};
+
+pub struct StateKey;
+
+impl TypeMapKey for StateKey {
+    type Value = Persistent<State>;
+}
+
+#[derive(Serialize, Deserialize, Default, Clone, Eq, PartialEq)]
+pub struct State {
+    guilds: HashMap<GuildId, GuildState>,
+}
+
+impl Introspect for State {
+    fn entries(&self) -> Vec<(Owner, usize)> {
+        self.guilds.iter().map(|(guild, state)| (Owner::Guild(*guild), state.caps.len())).collect()
+    }
+
+    fn references(&self) -> Vec<(Owner, Reference)> {
+        self.guilds.iter().flat_map(|(guild, state)| {
+            state.caps.keys().map(move |role| (Owner::Guild(*guild), Reference::Role(*role)))
+        }).collect()
+    }
+}
+
+#[derive(Serialize, Deserialize, Default, Clone, Eq, PartialEq)]
+struct GuildState {
+    caps: HashMap<RoleId, Cap>,
+}
+
+/// Limits how many members may hold a role through selectors.
+#[derive(Serialize, Deserialize, Clone, Eq, PartialEq)]
+struct Cap {
+    limit: usize,
+    /// Members holding the role, including those with a pending approval request for it.
+    holders: HashSet<UserId>,
+}
+
+impl Cap {
+    fn is_full_for(&self, user: UserId) -> bool {
+        !self.holders.contains(&user) && self.holders.len() >= self.limit
+    }
+}
+
+pub async fn set_cap(ctx: &Context, command: &Message, role: RoleId, limit: Option<usize>) -> CommandResult<()> {
+    let guild = command.guild_id.ok_or(CommandError::NotAllowed)?;
+
+    let limit = match limit {
+        Some(limit) => limit,
+        None => {
+            let state = store::<StateKey>(ctx).await;
+            state.write(|state| {
+                if let Some(guild_state) = state.guilds.get_mut(&guild) {
+                    guild_state.caps.remove(&role);
+                    if guild_state.caps.is_empty() {
+                        state.guilds.remove(&guild);
+                    }
+                }
+            }).await;
+            return Ok(());
+        }
+    };
+
+    if !capabilities::has_members_intent() {
+        return Err(CommandError::NoMembersIntent);
+    }
+
+    // count the current holders so that the cap applies to members who already have the role
+    let holders: HashSet<UserId> = ctx.cache.guild(guild).map(|guild| {
+        guild.members.values()
+            .filter(|member| member.roles.contains(&role))
+            .map(|member| member.user.id)
+            .collect()
+    }).unwrap_or_default();
+    let count = holders.len();
+
+    let state = store::<StateKey>(ctx).await;
+    state.write(|state| {
+        state.guilds.entry(guild).or_default().caps.insert(role, Cap { limit, holders });
+    }).await;
+
+    command.channel_id.say(&ctx.http, format!("🔒 At most {} members can now hold that role ({} currently do).", limit, count)).await?;
+
+    Ok(())
+}
+
+/// Takes a slot in every capped role of `roles` for the user, returning the first role that is
+/// already full instead. Nothing is reserved unless all roles have room.
+pub async fn try_reserve(ctx: &Context, guild: GuildId, user: UserId, roles: &[RoleId]) -> Result<(), (RoleId, usize)> {
+    let state = store::<StateKey>(ctx).await;
+    state.write(|state| {
+        let guild_state = match state.guilds.get_mut(&guild) {
+            Some(guild_state) => guild_state,
+            None => return Ok(()),
+        };
+
+        for role in roles {
+            if let Some(cap) = guild_state.caps.get(role) {
+                if cap.is_full_for(user) {
+                    return Err((*role, cap.limit));
+                }
+            }
+        }
+
+        for role in roles {
+            if let Some(cap) = guild_state.caps.get_mut(role) {
+                cap.holders.insert(user);
+            }
+        }
+        Ok(())
+    }).await
+}
+
+/// Frees the user's slots in any capped roles of `roles`.
+pub async fn release(ctx: &Context, guild: GuildId, user: UserId, roles: &[RoleId]) {
+    let state = store::<StateKey>(ctx).await;
+    state.write(|state| {
+        if let Some(guild_state) = state.guilds.get_mut(&guild) {
+            for role in roles {
+                if let Some(cap) = guild_state.caps.get_mut(role) {
+                    cap.holders.remove(&user);
+                }
+            }
+        }
+    }).await;
+}
+
+/// Keeps holder counts in line with roles given or taken away outside of selectors.
+pub async fn guild_member_update(ctx: &Context, old: Option<&Member>, member: &Member) {
+    let guild = member.guild_id;
+    let user = member.user.id;
+
+    let state = store::<StateKey>(ctx).await;
+    state.write(|state| {
+        if let Some(guild_state) = state.guilds.get_mut(&guild) {
+            for (role, cap) in &mut guild_state.caps {
+                if member.roles.contains(role) {
+                    cap.holders.insert(user);
+                } else if old.is_some_and(|old| old.roles.contains(role)) {
+                    cap.holders.remove(&user);
+                }
+            }
+        }
+    }).await;
+}
+
+pub async fn guild_member_removal(ctx: &Context, guild: GuildId, user: UserId) {
+    let state = store::<StateKey>(ctx).await;
+    state.write(|state| {
+        if let Some(guild_state) = state.guilds.get_mut(&guild) {
+            for cap in guild_state.caps.values_mut() {
+                cap.holders.remove(&user);
+            }
+        }
+    }).await;
+}
+
+/// Forgets all guilds not in `guilds`, returning the ones that were removed.
+pub async fn retain_guilds(ctx: &Context, guilds: &HashSet<GuildId>) -> Vec<GuildId> {
+    let state = store::<StateKey>(ctx).await;
+    state.write(|state| {
+        let removed = state.guilds.keys().filter(|guild| !guilds.contains(guild)).copied().collect();
+        state.guilds.retain(|guild, _| guilds.contains(guild));
+        removed
+    }).await
+}
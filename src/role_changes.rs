@@ -0,0 +1,98 @@
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+
+use log::warn;
+use serenity::model::guild::audit_log::{self, Change};
+use serenity::model::prelude::*;
+use serenity::prelude::*;
+
+use crate::{audit, scheduler};
+
+/// Audit log entries can show up a little after the member update event, so we try a few times.
+const ATTRIBUTION_ATTEMPTS: usize = 3;
+const ATTRIBUTION_RETRY_DELAY: Duration = Duration::from_secs(2);
+
+/// Only audit log entries this recent are considered to belong to a change.
+const ATTRIBUTION_WINDOW_SECS: i64 = 60;
+
+/// Reports a member's role changes to the log channel, attributed to whoever made them.
+pub async fn guild_member_update(ctx: &Context, old: Option<&Member>, member: &Member) {
+    let old = match old {
+        Some(old) => old,
+        None => return,
+    };
+
+    let guild = member.guild_id;
+    if !audit::is_configured(ctx, guild).await {
+        return;
+    }
+
+    let old_roles: HashSet<RoleId> = old.roles.iter().copied().collect();
+    let new_roles: HashSet<RoleId> = member.roles.iter().copied().collect();
+    let added: Vec<RoleId> = new_roles.difference(&old_roles).copied().collect();
+    let removed: Vec<RoleId> = old_roles.difference(&new_roles).copied().collect();
+    if added.is_empty() && removed.is_empty() {
+        return;
+    }
+
+    let changed: Vec<RoleId> = added.iter().chain(removed.iter()).copied().collect();
+    let executors = attribute(ctx, guild, member.user.id, &changed).await;
+    let current_user = ctx.cache.current_user().id;
+
+    let describe = |role: &RoleId| match executors.get(role) {
+        Some(executor) if *executor == current_user => format!("<@&{}> by me", role.get()),
+        Some(executor) => format!("<@&{}> by <@{}>", role.get(), executor.get()),
+        None => format!("<@&{}> by an unknown user", role.get()),
+    };
+
+    let mut lines = vec![format!("Roles of <@{}> changed:", member.user.id.get())];
+    lines.extend(added.iter().map(|role| format!("➕ {}", describe(role))));
+    lines.extend(removed.iter().map(|role| format!("➖ {}", describe(role))));
+
+    audit::log(ctx, guild, lines.join("\n")).await;
+}
+
+/// Looks through the audit log for who added or removed each of the given roles on `user`.
+async fn attribute(ctx: &Context, guild: GuildId, user: UserId, roles: &[RoleId]) -> HashMap<RoleId, UserId> {
+    let mut executors = HashMap::new();
+
+    for attempt in 0..ATTRIBUTION_ATTEMPTS {
+        if attempt > 0 {
+            tokio::time::sleep(ATTRIBUTION_RETRY_DELAY).await;
+        }
+
+        let action = audit_log::Action::Member(audit_log::MemberAction::RoleUpdate);
+        let logs = match guild.audit_logs(&ctx.http, Some(action), None, None, Some(25)).await {
+            Ok(logs) => logs,
+            Err(err) => {
+                warn!("failed to read audit log of {} to attribute role changes: {:?}", guild, err);
+                return executors;
+            }
+        };
+
+        let since = scheduler::now() - ATTRIBUTION_WINDOW_SECS;
+        let mut entries: Vec<_> = logs.entries.into_iter()
+            .filter(|entry| entry.target_id == Some(GenericId::new(user.get())) && entry.id.created_at().timestamp() >= since)
+            .collect();
+        // the newest entry wins when a role was changed several times
+        entries.sort_by_key(|entry| entry.id);
+
+        for entry in entries {
+            for change in entry.changes.iter().flatten() {
+                let changed_roles = match change {
+                    Change::RolesAdded { new, .. } | Change::RolesRemove { new, .. } => new.iter().flatten(),
+                    _ => continue,
+                };
+                for role in changed_roles {
+                    executors.insert(role.id, entry.user_id);
+                }
+            }
+        }
+
+        if roles.iter().all(|role| executors.contains_key(role)) {
+            break;
+        }
+    }
+
+    executors
+}
@@ -0,0 +1,211 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+use log::error;
+use serde::{Deserialize, Serialize};
+use serenity::model::prelude::*;
+use serenity::prelude::*;
+
+use crate::{audit, CommandError, CommandResult, events, Persistent, role_queue, say_lines, store};
+use crate::events::{Event, RoleSource};
+use crate::memreport::{Introspect, Owner, Reference};
+
+pub struct StateKey;
+
+impl TypeMapKey for StateKey {
+    type Value = Persistent<State>;
+}
+
+/// Roles that members may only keep while they hold other roles, by guild.
+#[derive(Serialize, Deserialize, Default, Clone, Eq, PartialEq)]
+pub struct State {
+    guilds: HashMap<GuildId, GuildState>,
+}
+
+impl Introspect for State {
+    fn entries(&self) -> Vec<(Owner, usize)> {
+        self.guilds.iter().map(|(guild, state)| (Owner::Guild(*guild), state.requires.len())).collect()
+    }
+
+    fn references(&self) -> Vec<(Owner, Reference)> {
+        self.guilds.iter().flat_map(|(guild, state)| {
+            state.requires.iter().flat_map(move |(dependent, prerequisites)| {
+                std::iter::once(*dependent).chain(prerequisites.iter().copied())
+                    .map(move |role| (Owner::Guild(*guild), Reference::Role(role)))
+            })
+        }).collect()
+    }
+}
+
+#[derive(Serialize, Deserialize, Default, Clone, Eq, PartialEq)]
+struct GuildState {
+    /// The roles each dependent role requires, all of which have to be held to keep it.
+    requires: HashMap<RoleId, BTreeSet<RoleId>>,
+}
+
+impl GuildState {
+    /// Whether `role` requires `prerequisite`, directly or through other roles.
+    fn depends_on(&self, role: RoleId, prerequisite: RoleId) -> bool {
+        let mut seen = HashSet::new();
+        let mut pending = vec![role];
+        while let Some(role) = pending.pop() {
+            if role == prerequisite {
+                return true;
+            }
+            if seen.insert(role) {
+                pending.extend(self.requires.get(&role).into_iter().flatten().copied());
+            }
+        }
+        false
+    }
+}
+
+impl State {
+    /// Makes `dependent` require `prerequisite`, unless that would make a role require itself.
+    pub fn add_dependency(&mut self, guild: GuildId, prerequisite: RoleId, dependent: RoleId) -> bool {
+        let guild_state = self.guilds.entry(guild).or_default();
+        if guild_state.depends_on(prerequisite, dependent) {
+            return false;
+        }
+        guild_state.requires.entry(dependent).or_default().insert(prerequisite);
+        true
+    }
+
+    /// Removes a dependency, returning whether there was one.
+    pub fn remove_dependency(&mut self, guild: GuildId, prerequisite: RoleId, dependent: RoleId) -> bool {
+        let guild_state = match self.guilds.get_mut(&guild) {
+            Some(guild_state) => guild_state,
+            None => return false,
+        };
+        let removed = match guild_state.requires.get_mut(&dependent) {
+            Some(prerequisites) => prerequisites.remove(&prerequisite),
+            None => false,
+        };
+
+        guild_state.requires.retain(|_, prerequisites| !prerequisites.is_empty());
+        if guild_state.requires.is_empty() {
+            self.guilds.remove(&guild);
+        }
+        removed
+    }
+
+    pub fn is_prerequisite(&self, guild: GuildId, role: RoleId) -> bool {
+        self.guilds.get(&guild)
+            .is_some_and(|guild_state| guild_state.requires.values().any(|prerequisites| prerequisites.contains(&role)))
+    }
+
+    /// The roles of `held` that have to go because a role they require isn't held, including those
+    /// that only lose their prerequisite once the others are gone.
+    pub fn unmet(&self, guild: GuildId, held: &[RoleId]) -> Vec<RoleId> {
+        let guild_state = match self.guilds.get(&guild) {
+            Some(guild_state) => guild_state,
+            None => return Vec::new(),
+        };
+
+        let mut kept: HashSet<RoleId> = held.iter().copied().collect();
+        let mut unmet = Vec::new();
+        loop {
+            let lost: Vec<RoleId> = held.iter()
+                .filter(|role| kept.contains(role))
+                .filter(|role| guild_state.requires.get(role).is_some_and(|prerequisites| !prerequisites.iter().all(|prerequisite| kept.contains(prerequisite))))
+                .copied()
+                .collect();
+            if lost.is_empty() {
+                return unmet;
+            }
+            for role in lost {
+                kept.remove(&role);
+                unmet.push(role);
+            }
+        }
+    }
+
+    /// Each dependent role along with the roles it requires, for listing.
+    pub fn dependencies(&self, guild: GuildId) -> Vec<(RoleId, Vec<RoleId>)> {
+        let mut dependencies: Vec<(RoleId, Vec<RoleId>)> = self.guilds.get(&guild).into_iter()
+            .flat_map(|guild_state| guild_state.requires.iter())
+            .map(|(dependent, prerequisites)| (*dependent, prerequisites.iter().copied().collect()))
+            .collect();
+        dependencies.sort();
+        dependencies
+    }
+}
+
+pub async fn set_dependency(ctx: &Context, command: &Message, prerequisite: RoleId, dependent: RoleId, required: bool) -> CommandResult<()> {
+    let guild = command.guild_id.ok_or(CommandError::NotAllowed)?;
+
+    let state = store::<StateKey>(ctx).await;
+    if required {
+        if !state.write(|state| state.add_dependency(guild, prerequisite, dependent)).await {
+            return Err(CommandError::RoleDependencyCycle);
+        }
+        audit::log(ctx, guild, format!(
+            "🔗 <@{}> made <@&{}> require <@&{}>", command.author.id.get(), dependent.get(), prerequisite.get(),
+        )).await;
+    } else if !state.write(|state| state.remove_dependency(guild, prerequisite, dependent)).await {
+        return Err(CommandError::UnknownRoleDependency);
+    }
+
+    Ok(())
+}
+
+pub async fn list(ctx: &Context, command: &Message) -> CommandResult<()> {
+    let guild = command.guild_id.ok_or(CommandError::NotAllowed)?;
+
+    let dependencies = store::<StateKey>(ctx).await.read().await.dependencies(guild);
+    let lines: Vec<String> = if dependencies.is_empty() {
+        vec!["No roles require other roles. Add one with `role requires <role> for <role>`.".to_owned()]
+    } else {
+        dependencies.iter()
+            .map(|(dependent, prerequisites)| {
+                let prerequisites: Vec<String> = prerequisites.iter().map(|role| format!("<@&{}>", role.get())).collect();
+                format!("• <@&{}> requires {}", dependent.get(), prerequisites.join(" and "))
+            })
+            .collect()
+    };
+    say_lines(ctx, command.channel_id, &lines).await?;
+
+    Ok(())
+}
+
+/// Takes away the roles that a member no longer holds the prerequisites of.
+pub async fn guild_member_update(ctx: &Context, old: Option<&Member>, member: &Member) {
+    let guild = member.guild_id;
+    let old = match old {
+        Some(old) => old,
+        None => return,
+    };
+
+    let state = store::<StateKey>(ctx).await;
+    let unmet = {
+        let state = state.read().await;
+        let lost_prerequisite = old.roles.iter()
+            .any(|role| !member.roles.contains(role) && state.is_prerequisite(guild, *role));
+        if !lost_prerequisite {
+            return;
+        }
+        state.unmet(guild, &member.roles)
+    };
+    if unmet.is_empty() {
+        return;
+    }
+
+    let user = member.user.id;
+    if let Err(err) = role_queue::remove_roles(ctx, guild, user, &unmet).await {
+        error!("failed to remove roles that {} no longer qualifies for in {}: {:?}", user, guild, err);
+        return;
+    }
+    events::publish(ctx, Event::RolesRevoked { guild, user, roles: unmet.clone(), source: RoleSource::Dependency }).await;
+
+    let roles: Vec<String> = unmet.iter().map(|role| format!("<@&{}>", role.get())).collect();
+    audit::log(ctx, guild, format!("🔗 Took {} from <@{}>, who no longer has the roles they require", roles.join(" "), user.get())).await;
+}
+
+/// Forgets all guilds not in `guilds`, returning the ones that were removed.
+pub async fn retain_guilds(ctx: &Context, guilds: &HashSet<GuildId>) -> Vec<GuildId> {
+    let state = store::<StateKey>(ctx).await;
+    state.write(|state| {
+        let removed = state.guilds.keys().filter(|guild| !guilds.contains(guild)).copied().collect();
+        state.guilds.retain(|guild, _| guilds.contains(guild));
+        removed
+    }).await
+}
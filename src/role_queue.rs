@@ -0,0 +1,186 @@
+use std::collections::HashMap;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use serenity::http::Http;
+use serenity::model::prelude::*;
+use serenity::prelude::*;
+use tokio::sync::{mpsc, oneshot};
+
+use crate::metrics::GuildGauge;
+use crate::read_only;
+
+pub struct QueueKey;
+
+impl TypeMapKey for QueueKey {
+    type Value = RoleQueue;
+}
+
+/// Paces role changes so that bursts, like hundreds of members reacting to a new selector at once,
+/// don't run into Discord's rate limits.
+#[derive(Serialize, Deserialize, Clone, Eq, PartialEq)]
+pub struct RoleQueueConfig {
+    /// How many members can have their roles changed at the same time.
+    #[serde(default = "default_concurrency")]
+    pub concurrency: usize,
+    /// The least time between two role changes, in milliseconds.
+    #[serde(default = "default_interval_ms")]
+    pub interval_ms: u64,
+}
+
+impl Default for RoleQueueConfig {
+    fn default() -> Self {
+        RoleQueueConfig {
+            concurrency: default_concurrency(),
+            interval_ms: default_interval_ms(),
+        }
+    }
+}
+
+fn default_concurrency() -> usize {
+    4
+}
+
+fn default_interval_ms() -> u64 {
+    100
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Change {
+    Add,
+    Remove,
+}
+
+struct Mutation {
+    guild: GuildId,
+    user: UserId,
+    change: Change,
+    roles: Vec<RoleId>,
+    done: oneshot::Sender<serenity::Result<()>>,
+}
+
+/// A queue of role changes, worked through by a fixed number of lanes. All changes for a member go
+/// through the same lane, so they are applied in the order they were made.
+#[derive(Clone)]
+pub struct RoleQueue {
+    lanes: Vec<mpsc::UnboundedSender<Mutation>>,
+    depth: Arc<AtomicUsize>,
+    guild_depths: Arc<GuildGauge>,
+}
+
+impl RoleQueue {
+    pub fn start(http: Arc<Http>, config: &RoleQueueConfig) -> Self {
+        let mut interval = tokio::time::interval(Duration::from_millis(config.interval_ms.max(1)));
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        let pacing = Arc::new(Mutex::new(interval));
+        let depth = Arc::new(AtomicUsize::new(0));
+        let guild_depths = Arc::new(GuildGauge::default());
+
+        let lanes = (0..config.concurrency.max(1)).map(|_| {
+            let (sender, mut receiver) = mpsc::unbounded_channel::<Mutation>();
+            let (http, pacing, depth, guild_depths) = (http.clone(), pacing.clone(), depth.clone(), guild_depths.clone());
+            tokio::spawn(async move {
+                while let Some(mutation) = receiver.recv().await {
+                    let result = apply(&http, &pacing, &mutation).await;
+                    depth.fetch_sub(1, Ordering::SeqCst);
+                    guild_depths.decrement(mutation.guild);
+                    let _ = mutation.done.send(result);
+                }
+            });
+            sender
+        }).collect();
+
+        RoleQueue { lanes, depth, guild_depths }
+    }
+
+    /// How many role changes are waiting or in progress.
+    pub fn depth(&self) -> usize {
+        self.depth.load(Ordering::SeqCst)
+    }
+
+    /// How many role changes are waiting or in progress for each guild that has any.
+    pub fn guild_depths(&self) -> HashMap<GuildId, usize> {
+        self.guild_depths.snapshot()
+    }
+
+    async fn submit(&self, guild: GuildId, user: UserId, change: Change, roles: &[RoleId]) -> serenity::Result<()> {
+        let mut hasher = DefaultHasher::new();
+        (guild, user).hash(&mut hasher);
+        let lane = &self.lanes[hasher.finish() as usize % self.lanes.len()];
+
+        let (done, result) = oneshot::channel();
+        self.depth.fetch_add(1, Ordering::SeqCst);
+        self.guild_depths.increment(guild);
+        let mutation = Mutation { guild, user, change, roles: roles.to_vec(), done };
+        if lane.send(mutation).is_err() {
+            self.depth.fetch_sub(1, Ordering::SeqCst);
+            self.guild_depths.decrement(guild);
+            return Err(serenity::Error::Other("role queue stopped"));
+        }
+
+        result.await.unwrap_or(Err(serenity::Error::Other("role queue stopped")))
+    }
+}
+
+async fn apply(http: &Http, pacing: &Mutex<tokio::time::Interval>, mutation: &Mutation) -> serenity::Result<()> {
+    for role in &mutation.roles {
+        pacing.lock().await.tick().await;
+        match mutation.change {
+            Change::Add => http.add_member_role(mutation.guild, mutation.user, *role, None).await?,
+            Change::Remove => http.remove_member_role(mutation.guild, mutation.user, *role, None).await?,
+        }
+    }
+    Ok(())
+}
+
+/// Gives roles to a member through the queue, waiting until they have been given.
+pub async fn add_roles(ctx: &Context, guild: GuildId, user: UserId, roles: &[RoleId]) -> serenity::Result<()> {
+    submit(ctx, guild, user, Change::Add, roles).await
+}
+
+/// Takes roles from a member through the queue, waiting until they have been taken.
+pub async fn remove_roles(ctx: &Context, guild: GuildId, user: UserId, roles: &[RoleId]) -> serenity::Result<()> {
+    submit(ctx, guild, user, Change::Remove, roles).await
+}
+
+async fn submit(ctx: &Context, guild: GuildId, user: UserId, change: Change, roles: &[RoleId]) -> serenity::Result<()> {
+    if roles.is_empty() {
+        return Ok(());
+    }
+
+    let action = || match change {
+        Change::Add => format!("given {} roles to {}", roles.len(), user),
+        Change::Remove => format!("taken {} roles from {}", roles.len(), user),
+    };
+    if read_only::blocks(ctx, guild, action).await {
+        return Ok(());
+    }
+
+    let queue = {
+        let data = ctx.data.read().await;
+        data.get::<QueueKey>().cloned()
+    };
+
+    match queue {
+        Some(queue) => queue.submit(guild, user, change, roles).await,
+        None => {
+            for role in roles {
+                match change {
+                    Change::Add => ctx.http.add_member_role(guild, user, *role, None).await?,
+                    Change::Remove => ctx.http.remove_member_role(guild, user, *role, None).await?,
+                }
+            }
+            Ok(())
+        }
+    }
+}
+
+/// How many role changes are queued, if the queue is running.
+pub async fn depth(ctx: &Context) -> Option<usize> {
+    let data = ctx.data.read().await;
+    data.get::<QueueKey>().map(RoleQueue::depth)
+}
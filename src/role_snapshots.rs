@@ -0,0 +1,143 @@
+use std::collections::{HashMap, HashSet};
+
+use serde::{Deserialize, Serialize};
+use serenity::model::prelude::*;
+use serenity::prelude::*;
+
+use crate::{audit, Persistent, scheduler, store};
+use crate::memreport::{Introspect, Owner, Reference};
+
+/// How long the roles of members who left are kept for when they rejoin.
+pub const RETENTION_SECS: i64 = 90 * 24 * 60 * 60;
+
+pub struct StateKey;
+
+impl TypeMapKey for StateKey {
+    type Value = Persistent<State>;
+}
+
+/// All the roles members held when they left, not just the persisted ones, so that admins can see
+/// which roles they didn't get back when they rejoin.
+#[derive(Serialize, Deserialize, Default, Clone, Eq, PartialEq)]
+pub struct State {
+    guilds: HashMap<GuildId, HashMap<UserId, Snapshot>>,
+}
+
+impl Introspect for State {
+    fn entries(&self) -> Vec<(Owner, usize)> {
+        self.guilds.iter().map(|(guild, snapshots)| (Owner::Guild(*guild), snapshots.len())).collect()
+    }
+
+    fn references(&self) -> Vec<(Owner, Reference)> {
+        self.guilds.iter().flat_map(|(guild, snapshots)| {
+            snapshots.values().flat_map(move |snapshot| snapshot.roles.iter().map(move |role| (Owner::Guild(*guild), Reference::Role(*role))))
+        }).collect()
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq)]
+pub struct Snapshot {
+    pub roles: Vec<RoleId>,
+    pub left_at: i64,
+}
+
+impl Snapshot {
+    /// Splits the roles of the snapshot into the ones the member holds again and the ones they're
+    /// missing, leaving out roles that don't exist anymore.
+    pub fn diff(&self, held: &[RoleId], existing: &HashSet<RoleId>) -> (Vec<RoleId>, Vec<RoleId>) {
+        self.roles.iter()
+            .copied()
+            .filter(|role| existing.contains(role))
+            .partition(|role| held.contains(role))
+    }
+}
+
+impl State {
+    pub fn record(&mut self, guild: GuildId, user: UserId, snapshot: Snapshot) {
+        if snapshot.roles.is_empty() {
+            return;
+        }
+        self.guilds.entry(guild).or_default().insert(user, snapshot);
+    }
+
+    pub fn take(&mut self, guild: GuildId, user: UserId) -> Option<Snapshot> {
+        let snapshots = self.guilds.get_mut(&guild)?;
+        let snapshot = snapshots.remove(&user);
+        if snapshots.is_empty() {
+            self.guilds.remove(&guild);
+        }
+        snapshot
+    }
+
+    /// Forgets snapshots taken before the given time, returning how many were removed.
+    pub fn prune(&mut self, before: i64) -> usize {
+        let mut pruned = 0;
+        for snapshots in self.guilds.values_mut() {
+            let len = snapshots.len();
+            snapshots.retain(|_, snapshot| snapshot.left_at >= before);
+            pruned += len - snapshots.len();
+        }
+        self.guilds.retain(|_, snapshots| !snapshots.is_empty());
+        pruned
+    }
+}
+
+/// Remembers the roles of a member who left. Only guilds with a log channel get snapshots, since
+/// that's where they are reported.
+pub async fn guild_member_removal(ctx: &Context, member: &Member) {
+    if !audit::is_configured(ctx, member.guild_id).await {
+        return;
+    }
+
+    let snapshot = Snapshot { roles: member.roles.clone(), left_at: scheduler::now() };
+    let state = store::<StateKey>(ctx).await;
+    state.write(|state| state.record(member.guild_id, member.user.id, snapshot)).await;
+}
+
+/// Tells the log channel which of the roles a member had when they left they got back on rejoining,
+/// and which have to be given back by hand.
+pub async fn member_rejoined(ctx: &Context, guild: GuildId, user: UserId, restored: &[RoleId]) {
+    let state = store::<StateKey>(ctx).await;
+    if !state.read().await.guilds.get(&guild).is_some_and(|snapshots| snapshots.contains_key(&user)) {
+        return;
+    }
+    let snapshot = match state.write(|state| state.take(guild, user)).await {
+        Some(snapshot) => snapshot,
+        None => return,
+    };
+
+    // managed roles, like those of bots and boosters, can't be given back by hand anyway
+    let existing: HashSet<RoleId> = ctx.cache.guild(guild)
+        .map(|guild| guild.roles.values().filter(|role| !role.managed).map(|role| role.id).collect())
+        .unwrap_or_default();
+    let (restored, missing) = snapshot.diff(restored, &existing);
+    if restored.is_empty() && missing.is_empty() {
+        return;
+    }
+
+    let mentions = |roles: &[RoleId]| roles.iter().map(|role| format!("<@&{}>", role.get())).collect::<Vec<_>>().join(" ");
+    let mut lines = vec![format!("🔁 <@{}> rejoined after leaving <t:{}:R>.", user.get(), snapshot.left_at)];
+    if !restored.is_empty() {
+        lines.push(format!("Restored: {}", mentions(&restored)));
+    }
+    if !missing.is_empty() {
+        lines.push(format!("Not restored: {}", mentions(&missing)));
+    }
+    audit::log(ctx, guild, lines.join("\n")).await;
+}
+
+/// Forgets snapshots of members who left before the given time across all guilds.
+pub async fn prune(ctx: &Context, before: i64) -> usize {
+    let state = store::<StateKey>(ctx).await;
+    state.write(|state| state.prune(before)).await
+}
+
+/// Forgets all guilds not in `guilds`, returning the ones that were removed.
+pub async fn retain_guilds(ctx: &Context, guilds: &HashSet<GuildId>) -> Vec<GuildId> {
+    let state = store::<StateKey>(ctx).await;
+    state.write(|state| {
+        let removed = state.guilds.keys().filter(|guild| !guilds.contains(guild)).copied().collect();
+        state.guilds.retain(|guild, _| guilds.contains(guild));
+        removed
+    }).await
+}
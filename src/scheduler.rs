@@ -0,0 +1,59 @@
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serenity::prelude::*;
+
+use crate::{approvals, backups, channel_links, config_sync, eligibility, emoji_stats, error_cleanup, latency, maintenance, persistent_roles, ping_tracker, reaction_roles, slowmode, telemetry, verification};
+
+static STARTED: AtomicBool = AtomicBool::new(false);
+
+/// Starts all periodic background tasks. Only the first call has any effect, so this is safe to call
+/// from `ready`, which fires again whenever the gateway reconnects.
+pub fn start(ctx: &Context) {
+    if STARTED.swap(true, Ordering::SeqCst) {
+        return;
+    }
+
+    every(ctx, Duration::from_secs(60), reaction_roles::refresh_selectors);
+    every(ctx, reaction_roles::template::PERIOD, reaction_roles::template::refresh);
+    every(ctx, Duration::from_secs(60), slowmode::apply_schedules);
+    every(ctx, Duration::from_secs(60), eligibility::retry_deferred);
+    every(ctx, Duration::from_secs(60), reaction_roles::expiry::expire_grants);
+    every(ctx, Duration::from_secs(60), verification::expire_challenges);
+    every(ctx, Duration::from_secs(10), ping_tracker::unlock_due);
+    every(ctx, Duration::from_secs(10 * 60), persistent_roles::retry_pending);
+    every(ctx, approvals::EXPIRY_PERIOD, approvals::expire_requests);
+    every(ctx, error_cleanup::PERIOD, error_cleanup::delete_due);
+    every(ctx, latency::PERIOD, latency::check);
+    every(ctx, emoji_stats::FLUSH_PERIOD, emoji_stats::flush);
+    every(ctx, reaction_roles::sweep::PERIOD, reaction_roles::sweep::run);
+    every(ctx, config_sync::PERIOD, config_sync::run);
+    every(ctx, backups::PERIOD, backups::run);
+    every(ctx, channel_links::VERIFY_PERIOD, channel_links::verify);
+    every(ctx, maintenance::PERIOD, maintenance::run);
+    every(ctx, telemetry::PERIOD, telemetry::report);
+}
+
+/// Runs `task` every `period`, starting immediately. Ticks are skipped while a previous run is still going.
+pub fn every<F, Fut>(ctx: &Context, period: Duration, task: F)
+    where F: Fn(Context) -> Fut + Send + 'static,
+          Fut: Future<Output=()> + Send
+{
+    let ctx = ctx.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(period);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+        loop {
+            interval.tick().await;
+            task(ctx.clone()).await;
+        }
+    });
+}
+
+/// The current time as a unix timestamp in seconds.
+pub fn now() -> i64 {
+    SystemTime::now().duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs() as i64)
+        .unwrap_or(0)
+}
@@ -0,0 +1,139 @@
+use std::collections::{HashMap, HashSet};
+
+use log::error;
+use serde::{Deserialize, Serialize};
+use serenity::builder::{CreateAllowedMentions, CreateMessage};
+use serenity::model::prelude::*;
+use serenity::prelude::*;
+
+use crate::{audit, CommandError, CommandResult, Persistent, read_only, role_admin, scheduler, store};
+use crate::memreport::{Introspect, Owner, Reference};
+
+pub struct StateKey;
+
+impl TypeMapKey for StateKey {
+    type Value = Persistent<State>;
+}
+
+#[derive(Serialize, Deserialize, Default, Clone, Eq, PartialEq)]
+pub struct State {
+    guilds: HashMap<GuildId, Screening>,
+}
+
+impl Introspect for State {
+    fn entries(&self) -> Vec<(Owner, usize)> {
+        self.guilds.keys().map(|guild| (Owner::Guild(*guild), 1)).collect()
+    }
+
+    fn references(&self) -> Vec<(Owner, Reference)> {
+        self.guilds.iter().filter_map(|(guild, screening)| Some((Owner::Guild(*guild), Reference::Role(screening.role?)))).collect()
+    }
+}
+
+/// What happens once a member completes membership screening.
+#[derive(Serialize, Deserialize, Default, Clone, Eq, PartialEq)]
+struct Screening {
+    /// Whether to post when members accept the rules to the log channel.
+    #[serde(default)]
+    log: bool,
+    /// A role given to members once they accept the rules.
+    #[serde(default)]
+    role: Option<RoleId>,
+}
+
+impl Screening {
+    fn is_empty(&self) -> bool {
+        !self.log && self.role.is_none()
+    }
+}
+
+pub async fn set_logging(ctx: &Context, command: &Message, log: bool) -> CommandResult<()> {
+    let guild = command.guild_id.ok_or(CommandError::NotAllowed)?;
+    update(ctx, guild, |screening| screening.log = log).await;
+    Ok(())
+}
+
+pub async fn set_role(ctx: &Context, command: &Message, role: Option<RoleId>) -> CommandResult<()> {
+    let guild = command.guild_id.ok_or(CommandError::NotAllowed)?;
+    if let Some(role) = role {
+        role_admin::check_hierarchy(ctx, guild, command.author.id, role).await?;
+    }
+    update(ctx, guild, |screening| screening.role = role).await;
+    Ok(())
+}
+
+async fn update<F: FnOnce(&mut Screening)>(ctx: &Context, guild: GuildId, f: F) {
+    let state = store::<StateKey>(ctx).await;
+    state.write(|state| {
+        let screening = state.guilds.entry(guild).or_default();
+        f(screening);
+        if screening.is_empty() {
+            state.guilds.remove(&guild);
+        }
+    }).await;
+}
+
+pub async fn status(ctx: &Context, command: &Message) -> CommandResult<()> {
+    let guild = command.guild_id.ok_or(CommandError::NotAllowed)?;
+
+    let screening = {
+        let state = store::<StateKey>(ctx).await;
+        let state = state.read().await;
+        state.guilds.get(&guild).cloned().unwrap_or_default()
+    };
+
+    let logging = if screening.log { "logged" } else { "not logged" };
+    let role = match screening.role {
+        Some(role) => format!("they get <@&{}>", role),
+        None => "no role is given".to_owned(),
+    };
+    command.channel_id.send_message(&ctx.http, CreateMessage::new()
+        .content(format!("📋 When members complete screening, it is {} and {}.", logging, role))
+        .allowed_mentions(CreateAllowedMentions::new())
+    ).await?;
+
+    Ok(())
+}
+
+/// Notices members passing membership screening, which shows up as their pending flag being cleared.
+pub async fn guild_member_update(ctx: &Context, old: Option<&Member>, member: &Member) {
+    let was_pending = old.map(|old| old.pending).unwrap_or(false);
+    if !was_pending || member.pending {
+        return;
+    }
+
+    let guild = member.guild_id;
+    let screening = {
+        let state = store::<StateKey>(ctx).await;
+        let state = state.read().await;
+        state.guilds.get(&guild).cloned()
+    };
+    let screening = match screening {
+        Some(screening) => screening,
+        None => return,
+    };
+
+    if screening.log {
+        audit::log(ctx, guild, format!("📋 <@{}> accepted the rules <t:{}:f>", member.user.id.get(), scheduler::now())).await;
+    }
+
+    if let Some(role) = screening.role {
+        if read_only::blocks(ctx, guild, || format!("given {} to {} after screening", role, member.user.id)).await {
+            return;
+        }
+        if let Err(err) = ctx.http.add_member_role(guild, member.user.id, role, None).await {
+            error!("failed to give screened role to {} in {}: {:?}", member.user.id, guild, err);
+            audit::log(ctx, guild, format!("⚠ Couldn't give <@&{}> to <@{}> after screening: {}", role.get(), member.user.id.get(), err)).await;
+        }
+    }
+}
+
+/// Forgets all guilds not in `guilds`, returning the ones that were removed.
+pub async fn retain_guilds(ctx: &Context, guilds: &HashSet<GuildId>) -> Vec<GuildId> {
+    let state = store::<StateKey>(ctx).await;
+    state.write(|state| {
+        let removed = state.guilds.keys().filter(|guild| !guilds.contains(guild)).copied().collect();
+        state.guilds.retain(|guild, _| guilds.contains(guild));
+        removed
+    }).await
+}
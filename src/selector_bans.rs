@@ -0,0 +1,102 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+use serde::{Deserialize, Serialize};
+use serenity::builder::{CreateAllowedMentions, CreateMessage};
+use serenity::model::prelude::*;
+use serenity::prelude::*;
+
+use crate::{audit, cases, CommandError, CommandResult, Persistent, store};
+use crate::cases::CaseKind;
+use crate::memreport::{Introspect, Owner};
+
+pub struct StateKey;
+
+impl TypeMapKey for StateKey {
+    type Value = Persistent<State>;
+}
+
+/// Users who may not get roles from any selector in a guild.
+#[derive(Serialize, Deserialize, Default, Clone, Eq, PartialEq)]
+pub struct State {
+    guilds: HashMap<GuildId, BTreeSet<UserId>>,
+}
+
+impl Introspect for State {
+    fn entries(&self) -> Vec<(Owner, usize)> {
+        self.guilds.iter().map(|(guild, users)| (Owner::Guild(*guild), users.len())).collect()
+    }
+}
+
+pub async fn ban(ctx: &Context, command: &Message, user: UserId) -> CommandResult<()> {
+    let guild = command.guild_id.ok_or(CommandError::NotAllowed)?;
+
+    let state = store::<StateKey>(ctx).await;
+    state.write(|state| {
+        state.guilds.entry(guild).or_default().insert(user);
+    }).await;
+
+    let case = cases::open(ctx, guild, CaseKind::SelectorBan, user, Some(command.author.id), None).await;
+    audit::log(ctx, guild, format!("<@{}> banned <@{}> from using selectors (case #{})", command.author.id.get(), user.get(), case)).await;
+    Ok(())
+}
+
+pub async fn unban(ctx: &Context, command: &Message, user: UserId) -> CommandResult<()> {
+    let guild = command.guild_id.ok_or(CommandError::NotAllowed)?;
+
+    let state = store::<StateKey>(ctx).await;
+    let removed = state.write(|state| {
+        let users = match state.guilds.get_mut(&guild) {
+            Some(users) => users,
+            None => return false,
+        };
+        let removed = users.remove(&user);
+        if users.is_empty() {
+            state.guilds.remove(&guild);
+        }
+        removed
+    }).await;
+
+    if !removed {
+        return Err(CommandError::NotSelectorBanned);
+    }
+
+    let case = cases::open(ctx, guild, CaseKind::SelectorUnban, user, Some(command.author.id), None).await;
+    audit::log(ctx, guild, format!("<@{}> allowed <@{}> to use selectors again (case #{})", command.author.id.get(), user.get(), case)).await;
+    Ok(())
+}
+
+pub async fn list(ctx: &Context, command: &Message) -> CommandResult<()> {
+    let guild = command.guild_id.ok_or(CommandError::NotAllowed)?;
+
+    let users = {
+        let state = store::<StateKey>(ctx).await;
+        let state = state.read().await;
+        state.guilds.get(&guild).cloned().unwrap_or_default()
+    };
+
+    let content = if users.is_empty() {
+        "Nobody is banned from using selectors.".to_owned()
+    } else {
+        let users: Vec<String> = users.iter().map(|user| format!("<@{}>", user.get())).collect();
+        format!("🚫 Banned from using selectors: {}", users.join(", "))
+    };
+    command.channel_id.send_message(&ctx.http, CreateMessage::new().content(content).allowed_mentions(CreateAllowedMentions::new())).await?;
+
+    Ok(())
+}
+
+pub async fn is_banned(ctx: &Context, guild: GuildId, user: UserId) -> bool {
+    let state = store::<StateKey>(ctx).await;
+    let banned = state.read().await.guilds.get(&guild).is_some_and(|users| users.contains(&user));
+    banned
+}
+
+/// Forgets all guilds not in `guilds`, returning the ones that were removed.
+pub async fn retain_guilds(ctx: &Context, guilds: &HashSet<GuildId>) -> Vec<GuildId> {
+    let state = store::<StateKey>(ctx).await;
+    state.write(|state| {
+        let removed = state.guilds.keys().filter(|guild| !guilds.contains(guild)).copied().collect();
+        state.guilds.retain(|guild, _| guilds.contains(guild));
+        removed
+    }).await
+}
@@ -0,0 +1,259 @@
+use std::collections::{HashMap, HashSet};
+use std::str::FromStr;
+
+use chrono::{Datelike, TimeZone, Timelike, Utc};
+use chrono_tz::Tz;
+use log::error;
+use serde::{Deserialize, Serialize};
+use serenity::builder::EditChannel;
+use serenity::model::prelude::*;
+use serenity::prelude::*;
+
+use crate::{CommandError, CommandResult, Persistent, quotas, read_only, scheduler, store, timezone};
+use crate::quotas::Quota;
+use crate::memreport::{Introspect, Owner, Reference};
+
+const DAY_NAMES: [&str; 7] = ["sun", "mon", "tue", "wed", "thu", "fri", "sat"];
+
+/// Discord's maximum slowmode rate.
+const MAX_RATE_SECS: u64 = 6 * 60 * 60;
+
+pub struct StateKey;
+
+impl TypeMapKey for StateKey {
+    type Value = Persistent<State>;
+}
+
+#[derive(Serialize, Deserialize, Default, Clone, Eq, PartialEq)]
+pub struct State {
+    guilds: HashMap<GuildId, GuildState>,
+}
+
+impl Introspect for State {
+    fn entries(&self) -> Vec<(Owner, usize)> {
+        self.guilds.iter().map(|(guild, state)| (Owner::Guild(*guild), state.channels.len())).collect()
+    }
+
+    fn references(&self) -> Vec<(Owner, Reference)> {
+        self.guilds.iter().flat_map(|(guild, state)| {
+            state.channels.keys().map(move |channel| (Owner::Guild(*guild), Reference::Channel(*channel)))
+        }).collect()
+    }
+}
+
+#[derive(Serialize, Deserialize, Default, Clone, Eq, PartialEq)]
+struct GuildState {
+    channels: HashMap<ChannelId, Schedule>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Eq, PartialEq)]
+pub struct Schedule {
+    pub days: DaySet,
+    pub window: TimeWindow,
+    pub rate_secs: u64,
+    /// The time zone the days and window are in, so that they follow daylight saving time.
+    #[serde(default = "utc")]
+    pub zone: Tz,
+    /// Whether we last switched the channel's slowmode on, so we only touch it when the window starts or ends.
+    #[serde(default)]
+    active: bool,
+}
+
+impl Schedule {
+    pub fn new(days: DaySet, window: TimeWindow, rate_secs: u64) -> Self {
+        Schedule { days, window, rate_secs, zone: Tz::UTC, active: false }
+    }
+
+    pub fn with_zone(self, zone: Tz) -> Self {
+        Schedule { zone, ..self }
+    }
+
+    /// Whether the given unix time falls within the schedule, in the schedule's time zone. Windows
+    /// that wrap past midnight belong to the day they start on.
+    pub fn is_active(&self, now: i64) -> bool {
+        let local = Utc.timestamp_opt(now, 0).unwrap().with_timezone(&self.zone);
+        let weekday = local.weekday().num_days_from_sunday() as u8;
+        let previous_weekday = (weekday + 6) % 7;
+        let minute = (local.hour() * 60 + local.minute()) as u16;
+
+        let TimeWindow { start, end } = self.window;
+        if start <= end {
+            self.days.contains(weekday) && minute >= start && minute < end
+        } else {
+            (self.days.contains(weekday) && minute >= start)
+                || (self.days.contains(previous_weekday) && minute < end)
+        }
+    }
+}
+
+fn utc() -> Tz {
+    Tz::UTC
+}
+
+/// A set of weekdays, as a bitmask with Sunday as the lowest bit.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Eq, PartialEq)]
+pub struct DaySet(u8);
+
+impl DaySet {
+    #[inline]
+    pub fn contains(&self, weekday: u8) -> bool {
+        self.0 & (1 << weekday) != 0
+    }
+}
+
+impl FromStr for DaySet {
+    type Err = ();
+
+    /// Parses `daily`, `weekdays`, `weekends` or a comma-separated list of day names like `fri,sat`.
+    fn from_str(s: &str) -> Result<Self, ()> {
+        match s.to_lowercase().as_str() {
+            "daily" => Ok(DaySet(0b1111111)),
+            "weekdays" => Ok(DaySet(0b0111110)),
+            "weekends" => Ok(DaySet(0b1000001)),
+            days => {
+                let mut set = 0;
+                for day in days.split(',') {
+                    let index = DAY_NAMES.iter().position(|name| day.starts_with(name)).ok_or(())?;
+                    set |= 1 << index;
+                }
+                Ok(DaySet(set))
+            }
+        }
+    }
+}
+
+/// A time of day range in minutes since midnight, which wraps past midnight if `end` is before `start`.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Eq, PartialEq)]
+pub struct TimeWindow {
+    pub start: u16,
+    pub end: u16,
+}
+
+impl FromStr for TimeWindow {
+    type Err = ();
+
+    /// Parses a range like `18:00-23:00` or `8pm-11pm`.
+    fn from_str(s: &str) -> Result<Self, ()> {
+        let (start, end) = s.split_once('-').ok_or(())?;
+        let parse_time = |time| timezone::parse_time_of_day(time).ok_or(());
+        let (start, end) = (parse_time(start)?, parse_time(end)?);
+        if start == end {
+            return Err(());
+        }
+        Ok(TimeWindow { start, end })
+    }
+}
+
+/// Parses a slowmode rate like `10s`, `5m` or `1h` into seconds.
+pub fn parse_rate(rate: &str) -> Option<u64> {
+    let split = rate.find(|c: char| !c.is_ascii_digit()).unwrap_or(rate.len());
+    let (amount, unit) = rate.split_at(split);
+    let amount: u64 = amount.parse().ok()?;
+    let secs = match unit {
+        "" | "s" => amount,
+        "m" => amount * 60,
+        "h" => amount * 60 * 60,
+        _ => return None,
+    };
+    Some(secs).filter(|secs| *secs > 0 && *secs <= MAX_RATE_SECS)
+}
+
+pub async fn set_schedule(ctx: &Context, command: &Message, channel: ChannelId, schedule: Option<Schedule>) -> CommandResult<()> {
+    let guild = command.guild_id.ok_or(CommandError::NotAllowed)?;
+
+    let schedule = match schedule {
+        Some(schedule) => Some(schedule.with_zone(timezone::zone(ctx, guild).await)),
+        None => None,
+    };
+
+    let state = store::<StateKey>(ctx).await;
+    let is_new = !state.read().await.guilds.get(&guild).is_some_and(|guild| guild.channels.contains_key(&channel));
+    if schedule.is_some() && is_new {
+        quotas::check(ctx, guild, Quota::Schedules, 1).await?;
+    }
+
+    let previous = state.write(|state| {
+        let guild_state = state.guilds.entry(guild).or_default();
+        let previous = match schedule {
+            Some(schedule) => guild_state.channels.insert(channel, schedule),
+            None => guild_state.channels.remove(&channel),
+        };
+        if guild_state.channels.is_empty() {
+            state.guilds.remove(&guild);
+        }
+        previous
+    }).await;
+
+    // don't leave slowmode stuck on if the schedule went away mid-window
+    if previous.is_some_and(|previous| previous.active) {
+        channel.edit(&ctx.http, EditChannel::new().rate_limit_per_user(0)).await?;
+    }
+
+    apply_schedules(ctx.clone()).await;
+
+    Ok(())
+}
+
+/// Moves the guild's schedules to a new time zone, keeping their wall-clock times.
+pub async fn set_zone(ctx: &Context, guild: GuildId, zone: Tz) {
+    let state = store::<StateKey>(ctx).await;
+    state.write(|state| {
+        if let Some(guild_state) = state.guilds.get_mut(&guild) {
+            for schedule in guild_state.channels.values_mut() {
+                schedule.zone = zone;
+            }
+        }
+    }).await;
+
+    apply_schedules(ctx.clone()).await;
+}
+
+pub async fn schedule_count(ctx: &Context, guild: GuildId) -> usize {
+    let state = store::<StateKey>(ctx).await;
+    let count = state.read().await.guilds.get(&guild).map(|guild| guild.channels.len()).unwrap_or(0);
+    count
+}
+
+pub async fn is_configured(ctx: &Context, guild: GuildId) -> bool {
+    let state = store::<StateKey>(ctx).await;
+    let configured = state.read().await.guilds.contains_key(&guild);
+    configured
+}
+
+/// Switches slowmode on or off in every scheduled channel whose window has started or ended.
+pub async fn apply_schedules(ctx: Context) {
+    let now = scheduler::now();
+
+    let state = store::<StateKey>(&ctx).await;
+    let transitions: Vec<(GuildId, ChannelId, u64)> = state.read().await.guilds.iter()
+        .flat_map(|(guild, guild_state)| guild_state.channels.iter().map(move |(channel, schedule)| (*guild, *channel, schedule)))
+        .filter(|(_, _, schedule)| schedule.is_active(now) != schedule.active)
+        .map(|(guild, channel, schedule)| (guild, channel, if schedule.active { 0 } else { schedule.rate_secs }))
+        .collect();
+
+    for (guild, channel, rate_secs) in transitions {
+        if read_only::blocks(&ctx, guild, || format!("set the slowmode of {} to {}s", channel, rate_secs)).await {
+            continue;
+        }
+        if let Err(err) = channel.edit(&ctx.http, EditChannel::new().rate_limit_per_user(rate_secs as u16)).await {
+            error!("failed to set slowmode of {} to {}s: {:?}", channel, rate_secs, err);
+            continue;
+        }
+
+        state.write(|state| {
+            if let Some(schedule) = state.guilds.get_mut(&guild).and_then(|guild| guild.channels.get_mut(&channel)) {
+                schedule.active = rate_secs != 0;
+            }
+        }).await;
+    }
+}
+
+/// Forgets all guilds not in `guilds`, returning the ones that were removed.
+pub async fn retain_guilds(ctx: &Context, guilds: &HashSet<GuildId>) -> Vec<GuildId> {
+    let state = store::<StateKey>(ctx).await;
+    state.write(|state| {
+        let removed = state.guilds.keys().filter(|guild| !guilds.contains(guild)).copied().collect();
+        state.guilds.retain(|guild, _| guilds.contains(guild));
+        removed
+    }).await
+}
@@ -0,0 +1,110 @@
+use serenity::builder::{CreateAllowedMentions, CreateEmbed, CreateMessage};
+use serenity::model::prelude::*;
+use serenity::prelude::*;
+
+use crate::{audit, autopin, CommandError, config_sync, CommandResult, join_queue, persistent_roles, ping_tracker, reaction_roles, role_queue, slowmode};
+
+/// A feature that can be set up for a guild, along with how to do so.
+struct Feature {
+    name: &'static str,
+    configured: bool,
+    hint: &'static str,
+}
+
+async fn features(ctx: &Context, guild: GuildId) -> Vec<Feature> {
+    vec![
+        Feature {
+            name: "Log channel",
+            configured: audit::is_configured(ctx, guild).await,
+            hint: "set one with `set log channel #channel`",
+        },
+        Feature {
+            name: "Role selectors",
+            configured: reaction_roles::is_configured(ctx, guild).await,
+            hint: "register one with `add role selector <message id>`",
+        },
+        Feature {
+            name: "Role persistence",
+            configured: persistent_roles::is_configured(ctx, guild).await,
+            hint: "keep roles across rejoins with `add role persist <roles>`",
+        },
+        Feature {
+            name: "Ping cooldowns",
+            configured: ping_tracker::is_configured(ctx, guild).await,
+            hint: "limit pings with `ping cooldown <role> <secs> warn|lock`",
+        },
+        Feature {
+            name: "Autopin",
+            configured: autopin::is_configured(ctx, guild).await,
+            hint: "enable it in a channel with `autopin <reactions>`",
+        },
+        Feature {
+            name: "Slowmode schedules",
+            configured: slowmode::is_configured(ctx, guild).await,
+            hint: "schedule one with `slowmode schedule #channel weekdays 18:00-23:00 10s`",
+        },
+        Feature {
+            name: "Config sync",
+            configured: config_sync::is_configured(ctx, guild).await,
+            hint: "manage config from version control with `config sync <raw url>`",
+        },
+    ]
+}
+
+/// Shows which features are set up for the guild, with suggestions for the ones that aren't.
+pub async fn setup_status(ctx: &Context, command: &Message) -> CommandResult<()> {
+    let guild = command.guild_id.ok_or(CommandError::NotAllowed)?;
+
+    let features = features(ctx, guild).await;
+    let configured = features.iter().filter(|feature| feature.configured).count();
+    let mut lines = vec![format!("**Setup status** ({}/{} features configured)", configured, features.len())];
+    lines.extend(features.iter().map(|feature| {
+        if feature.configured {
+            format!("✅ {}", feature.name)
+        } else {
+            format!("⬜ {} — {}", feature.name, feature.hint)
+        }
+    }));
+
+    if let Some(depth) = role_queue::depth(ctx).await.filter(|depth| *depth > 0) {
+        lines.push(format!("⏳ {} role changes are queued", depth));
+    }
+    if let Some(depth) = join_queue::depth(ctx).await.filter(|depth| *depth > 0) {
+        lines.push(format!("⏳ {} joins are queued", depth));
+    }
+
+    crate::say_lines(ctx, command.channel_id, &lines).await?;
+
+    Ok(())
+}
+
+/// Introduces the bot to someone who mentioned it without a command.
+pub async fn quick_start(ctx: &Context, message: &Message) -> serenity::Result<()> {
+    let bot_name = ctx.cache.current_user().name.clone();
+    let setup = match message.guild_id {
+        Some(guild) => {
+            let features = features(ctx, guild).await;
+            let configured = features.iter().filter(|feature| feature.configured).count();
+            let next = features.iter().find(|feature| !feature.configured);
+            let mut setup = format!("{}/{} features are set up here.", configured, features.len());
+            if let Some(next) = next {
+                setup.push_str(&format!(" Next: {}.", next.hint));
+            }
+            setup
+        }
+        None => "Commands only work in servers.".to_owned(),
+    };
+
+    let embed = CreateEmbed::new()
+        .title("👋 Quick start")
+        .description(format!(
+            "I manage roles through reactions. Mention me followed by a command, like `@{} setup status`.",
+            bot_name,
+        ))
+        .field("Getting started", "Write a message with an emoji and a role on each line, then register it with `add role selector <message id>`.", false)
+        .field("This server", setup, false)
+        .field("Checking for problems", "`doctor` lists anything that stops me from working properly.", false);
+    message.channel_id.send_message(&ctx.http, CreateMessage::new().embed(embed).allowed_mentions(CreateAllowedMentions::new())).await?;
+
+    Ok(())
+}
@@ -0,0 +1,110 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+use serenity::model::prelude::*;
+use serenity::prelude::*;
+
+use crate::{CommandResult, join_queue, members, reaction_roles, require_owner};
+
+/// How often a report is sent, and so the window that command counts cover.
+pub const PERIOD: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Commands handled since the last report.
+static COMMANDS: AtomicU64 = AtomicU64::new(0);
+
+pub struct ConfigKey;
+
+impl TypeMapKey for ConfigKey {
+    type Value = TelemetryConfig;
+}
+
+/// Telemetry is off unless explicitly enabled with an endpoint to report to.
+#[derive(Serialize, Deserialize, Default, Clone, Eq, PartialEq)]
+pub struct TelemetryConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default)]
+    pub endpoint: Option<String>,
+}
+
+impl TelemetryConfig {
+    fn endpoint(&self) -> Option<&str> {
+        self.endpoint.as_deref().filter(|_| self.enabled)
+    }
+}
+
+/// Everything that gets sent: aggregate counts only, with no IDs, names or message content.
+#[derive(Serialize)]
+pub struct Report {
+    pub version: &'static str,
+    pub guilds: usize,
+    pub selectors: usize,
+    pub commands_per_day: u64,
+    /// How member lookups were answered since startup.
+    pub member_lookups: members::LookupStats,
+    /// How joins were worked through since startup.
+    pub joins: join_queue::JoinStats,
+}
+
+pub fn count_command() {
+    COMMANDS.fetch_add(1, Ordering::Relaxed);
+}
+
+async fn config(ctx: &Context) -> TelemetryConfig {
+    let data = ctx.data.read().await;
+    data.get::<ConfigKey>().cloned().unwrap_or_default()
+}
+
+async fn collect(ctx: &Context) -> Report {
+    Report {
+        version: env!("CARGO_PKG_VERSION"),
+        guilds: ctx.cache.guild_count(),
+        selectors: reaction_roles::selector_count(ctx).await,
+        commands_per_day: COMMANDS.load(Ordering::Relaxed),
+        member_lookups: members::stats(),
+        joins: join_queue::stats(ctx).await,
+    }
+}
+
+/// Sends a report to the configured endpoint, if telemetry is enabled.
+pub async fn report(ctx: Context) {
+    let config = config(&ctx).await;
+    let endpoint = match config.endpoint() {
+        Some(endpoint) => endpoint,
+        None => return,
+    };
+
+    let report = collect(&ctx).await;
+    let result = reqwest::Client::new().post(endpoint)
+        .json(&report)
+        .send().await
+        .and_then(|response| response.error_for_status());
+
+    match result {
+        Ok(_) => {
+            COMMANDS.fetch_sub(report.commands_per_day, Ordering::Relaxed);
+            info!("sent telemetry report to {}", endpoint);
+        }
+        Err(err) => error!("failed to send telemetry report: {:?}", err),
+    }
+}
+
+/// Shows whether telemetry is enabled and exactly what the next report would contain, which covers
+/// every guild and names the endpoint.
+pub async fn status(ctx: &Context, command: &Message) -> CommandResult<()> {
+    require_owner(ctx, &command.author).await?;
+
+    let config = config(ctx).await;
+    let report = collect(ctx).await;
+    let payload = serde_json::to_string_pretty(&report).expect("failed to serialize telemetry report");
+
+    let content = match config.endpoint() {
+        Some(endpoint) => format!("📊 Telemetry is **enabled**, reporting daily to `{}`:\n```json\n{}\n```", endpoint, payload),
+        None => format!("📊 Telemetry is **disabled**. If enabled, this would be sent daily:\n```json\n{}\n```", payload),
+    };
+    command.reply(ctx, content).await?;
+
+    Ok(())
+}
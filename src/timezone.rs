@@ -0,0 +1,112 @@
+use std::collections::{HashMap, HashSet};
+
+use chrono::{TimeZone, Utc};
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+use serenity::model::prelude::*;
+use serenity::prelude::*;
+
+use crate::{CommandError, CommandResult, Persistent, scheduler, slowmode, store};
+use crate::memreport::{Introspect, Owner};
+
+pub struct StateKey;
+
+impl TypeMapKey for StateKey {
+    type Value = Persistent<State>;
+}
+
+/// The time zone each guild's schedules are written in. Guilds without one use UTC.
+#[derive(Serialize, Deserialize, Default, Clone, Eq, PartialEq)]
+pub struct State {
+    guilds: HashMap<GuildId, Tz>,
+}
+
+impl Introspect for State {
+    fn entries(&self) -> Vec<(Owner, usize)> {
+        self.guilds.keys().map(|guild| (Owner::Guild(*guild), 1)).collect()
+    }
+}
+
+/// Parses an IANA time zone name like `Europe/Berlin`, ignoring case.
+pub fn parse_zone(zone: &str) -> Option<Tz> {
+    zone.parse().ok().or_else(|| {
+        chrono_tz::TZ_VARIANTS.iter().copied().find(|tz| tz.name().eq_ignore_ascii_case(zone))
+    })
+}
+
+/// Parses a time of day like `18:30`, `8pm`, `8:30am`, `noon` or `midnight` into minutes since midnight.
+/// `24:00` is accepted as the end of the day.
+pub fn parse_time_of_day(time: &str) -> Option<u16> {
+    let time = time.trim().to_lowercase();
+    match time.as_str() {
+        "noon" => return Some(12 * 60),
+        "midnight" => return Some(0),
+        _ => (),
+    }
+
+    let (time, meridiem) = if let Some(time) = time.strip_suffix("am") {
+        (time, Some(0))
+    } else if let Some(time) = time.strip_suffix("pm") {
+        (time, Some(12))
+    } else {
+        (time.as_str(), None)
+    };
+
+    let (hours, minutes) = match time.split_once(':') {
+        Some((hours, minutes)) => (hours, minutes),
+        None if meridiem.is_some() => (time, "0"),
+        None => return None,
+    };
+    let (hours, minutes): (u16, u16) = (hours.parse().ok()?, minutes.parse().ok()?);
+    if minutes >= 60 {
+        return None;
+    }
+
+    let hours = match meridiem {
+        Some(offset) if (1..=12).contains(&hours) => hours % 12 + offset,
+        Some(_) => return None,
+        None if hours < 24 || (hours == 24 && minutes == 0) => hours,
+        None => return None,
+    };
+    Some(hours * 60 + minutes)
+}
+
+pub async fn zone(ctx: &Context, guild: GuildId) -> Tz {
+    let state = store::<StateKey>(ctx).await;
+    let zone = state.read().await.guilds.get(&guild).copied().unwrap_or(Tz::UTC);
+    zone
+}
+
+/// Sets the guild's time zone. Existing schedules keep their wall-clock times in the new zone.
+pub async fn set_zone(ctx: &Context, command: &Message, zone: Tz) -> CommandResult<()> {
+    let guild = command.guild_id.ok_or(CommandError::NotAllowed)?;
+
+    let state = store::<StateKey>(ctx).await;
+    state.write(|state| {
+        if zone == Tz::UTC {
+            state.guilds.remove(&guild);
+        } else {
+            state.guilds.insert(guild, zone);
+        }
+    }).await;
+
+    slowmode::set_zone(ctx, guild, zone).await;
+
+    let local = Utc.timestamp_opt(scheduler::now(), 0).unwrap().with_timezone(&zone);
+    command.channel_id.say(&ctx.http, format!(
+        "🕒 Schedules in this server now use {} time (currently {}).",
+        zone.name(), local.format("%a %H:%M"),
+    )).await?;
+
+    Ok(())
+}
+
+/// Forgets all guilds not in `guilds`, returning the ones that were removed.
+pub async fn retain_guilds(ctx: &Context, guilds: &HashSet<GuildId>) -> Vec<GuildId> {
+    let state = store::<StateKey>(ctx).await;
+    state.write(|state| {
+        let removed = state.guilds.keys().filter(|guild| !guilds.contains(guild)).copied().collect();
+        state.guilds.retain(|guild, _| guilds.contains(guild));
+        removed
+    }).await
+}
@@ -0,0 +1,356 @@
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::path::Path;
+
+use serde_json::Value;
+use serenity::model::prelude::*;
+use serenity::prelude::*;
+
+use crate::{appeals, applications, approvals, audit, autopin, backups, cases, channel_links, command_usage, CommandResult, Config, config_sync, dormancy, eligibility, emoji_stats, error_cleanup, event_signups, history, latency, memreport, mentions, Persistable, Persistent, persistent_roles, ping_tracker, quotas, reaction_roles, read_only, require_owner, retention, role_caps, role_dependencies, role_snapshots, say_lines, screening, selector_bans, slowmode, store, timezone, verification, webhooks};
+use crate::memreport::{Introspect, Owner, Reference};
+
+/// How many dangling IDs `selfcheck` lists before summarizing the rest.
+const MAX_DANGLING: usize = 20;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Ord, PartialOrd)]
+pub enum Severity {
+    Warning,
+    /// The bot can't start with this problem.
+    Fatal,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Issue {
+    pub severity: Severity,
+    pub file: String,
+    pub message: String,
+}
+
+impl fmt::Display for Issue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let marker = match self.severity {
+            Severity::Warning => "⚠",
+            Severity::Fatal => "✖",
+        };
+        write!(f, "{} {}: {}", marker, self.file, self.message)
+    }
+}
+
+/// Problems found in the config and persisted state.
+#[derive(Clone, Debug, Default)]
+pub struct Report {
+    pub issues: Vec<Issue>,
+}
+
+impl Report {
+    fn warn(&mut self, file: &str, message: String) {
+        self.issues.push(Issue { severity: Severity::Warning, file: file.to_owned(), message });
+    }
+
+    fn fatal(&mut self, file: &str, message: String) {
+        self.issues.push(Issue { severity: Severity::Fatal, file: file.to_owned(), message });
+    }
+
+    pub fn is_fatal(&self) -> bool {
+        self.issues.iter().any(|issue| issue.severity == Severity::Fatal)
+    }
+}
+
+/// Checks the config and every persisted store in `dir`: that they can be loaded, and that they
+/// don't contain fields the bot doesn't know about, which are usually typos and get dropped.
+pub fn check_files(dir: &Path) -> Report {
+    let mut report = Report::default();
+
+    let config = check_file::<Config>(dir, Path::new("config.json"), &mut report);
+    if let Some(config) = &config {
+        check_config(config, &mut report);
+    }
+    // the stores are looked for where the config keeps them
+    let stores = config.map(|config| config.stores.open_options()).unwrap_or_default();
+
+    check_file::<reaction_roles::State>(dir, &stores.path("reaction_roles.json"), &mut report);
+    check_file::<reaction_roles::archive::State>(dir, &stores.path("selector_archive.json"), &mut report);
+    check_file::<reaction_roles::names::State>(dir, &stores.path("selector_names.json"), &mut report);
+    check_file::<persistent_roles::State>(dir, &stores.path("persistent_roles.json"), &mut report);
+    check_file::<autopin::State>(dir, &stores.path("autopin.json"), &mut report);
+    check_file::<event_signups::State>(dir, &stores.path("event_signups.json"), &mut report);
+    check_file::<ping_tracker::State>(dir, &stores.path("ping_tracker.json"), &mut report);
+    check_file::<audit::State>(dir, &stores.path("audit.json"), &mut report);
+    check_file::<slowmode::State>(dir, &stores.path("slowmode.json"), &mut report);
+    check_file::<emoji_stats::State>(dir, &stores.path("emoji_stats.json"), &mut report);
+    check_file::<webhooks::State>(dir, &stores.path("webhooks.json"), &mut report);
+    check_file::<approvals::State>(dir, &stores.path("approvals.json"), &mut report);
+    check_file::<applications::State>(dir, &stores.path("applications.json"), &mut report);
+    check_file::<role_caps::State>(dir, &stores.path("role_caps.json"), &mut report);
+    check_file::<role_dependencies::State>(dir, &stores.path("role_dependencies.json"), &mut report);
+    check_file::<config_sync::State>(dir, &stores.path("config_sync.json"), &mut report);
+    check_file::<timezone::State>(dir, &stores.path("timezone.json"), &mut report);
+    check_file::<retention::State>(dir, &stores.path("retention.json"), &mut report);
+    check_file::<quotas::State>(dir, &stores.path("quotas.json"), &mut report);
+    check_file::<channel_links::State>(dir, &stores.path("channel_links.json"), &mut report);
+    check_file::<screening::State>(dir, &stores.path("screening.json"), &mut report);
+    check_file::<eligibility::State>(dir, &stores.path("eligibility.json"), &mut report);
+    check_file::<reaction_roles::expiry::State>(dir, &stores.path("selector_expiry.json"), &mut report);
+    check_file::<verification::State>(dir, &stores.path("verification.json"), &mut report);
+    check_file::<selector_bans::State>(dir, &stores.path("selector_bans.json"), &mut report);
+    check_file::<cases::State>(dir, &stores.path("cases.json"), &mut report);
+    check_file::<appeals::State>(dir, &stores.path("appeals.json"), &mut report);
+    check_file::<read_only::State>(dir, &stores.path("read_only.json"), &mut report);
+    check_file::<command_usage::State>(dir, &stores.path("command_usage.json"), &mut report);
+    check_file::<error_cleanup::State>(dir, &stores.path("error_cleanup.json"), &mut report);
+    check_file::<mentions::State>(dir, &stores.path("mentions.json"), &mut report);
+    check_file::<latency::State>(dir, &stores.path("latency.json"), &mut report);
+    check_file::<role_snapshots::State>(dir, &stores.path("role_snapshots.json"), &mut report);
+    check_file::<reaction_roles::sweep::State>(dir, &stores.path("selector_sweeps.json"), &mut report);
+    check_file::<reaction_roles::onboarding::State>(dir, &stores.path("onboarding.json"), &mut report);
+    check_file::<reaction_roles::progress::State>(dir, &stores.path("progress_roles.json"), &mut report);
+    check_file::<backups::State>(dir, &stores.path("backups.json"), &mut report);
+    check_file::<dormancy::State>(dir, &stores.path("activity.json"), &mut report);
+
+    let history = dir.join(stores.path(history::FILE));
+    if history.exists() {
+        if let Err(err) = history::History::open_read_only(history) {
+            report.fatal(history::FILE, format!("can't be opened: {}", err));
+        }
+    }
+
+    report
+}
+
+/// Loads a file if it exists, reporting whether it could be and any fields that would be lost.
+fn check_file<T: Persistable>(dir: &Path, file: &Path, report: &mut Report) -> Option<T> {
+    let path = dir.join(file);
+    let file = &*file.to_string_lossy();
+    if !path.exists() {
+        return None;
+    }
+
+    let bytes = match std::fs::read(&path) {
+        Ok(bytes) => bytes,
+        Err(err) => {
+            report.fatal(file, format!("can't be read: {}", err));
+            return None;
+        }
+    };
+    let raw: Value = match serde_json::from_slice(&bytes) {
+        Ok(raw) => raw,
+        Err(err) => {
+            report.fatal(file, format!("isn't valid JSON: {}", err));
+            return None;
+        }
+    };
+    let value: T = match serde_json::from_value(raw.clone()) {
+        Ok(value) => value,
+        Err(err) => {
+            report.fatal(file, format!("can't be loaded: {}", err));
+            return None;
+        }
+    };
+
+    if let Ok(known) = serde_json::to_value(&value) {
+        for field in unknown_fields(&raw, &known) {
+            report.warn(file, format!("unknown field `{}` will be ignored", field));
+        }
+    }
+
+    Some(value)
+}
+
+/// Lists the paths of object fields in `raw` that are missing from `known`, which is what `raw`
+/// looks like once it has been loaded and saved again. Only keys that look like field names count,
+/// since stores in an older format are keyed by IDs and emoji that get moved around on loading.
+pub fn unknown_fields(raw: &Value, known: &Value) -> Vec<String> {
+    let mut fields = Vec::new();
+    collect_unknown_fields(raw, known, "", &mut fields);
+    fields
+}
+
+fn collect_unknown_fields(raw: &Value, known: &Value, path: &str, fields: &mut Vec<String>) {
+    let join = |key: &str| if path.is_empty() { key.to_owned() } else { format!("{}.{}", path, key) };
+    match (raw, known) {
+        (Value::Object(raw), Value::Object(known)) => {
+            for (key, value) in raw {
+                match known.get(key) {
+                    Some(known) => collect_unknown_fields(value, known, &join(key), fields),
+                    None if is_field_name(key) => fields.push(join(key)),
+                    None => {}
+                }
+            }
+        }
+        (Value::Array(raw), Value::Array(known)) => {
+            for (index, (raw, known)) in raw.iter().zip(known).enumerate() {
+                collect_unknown_fields(raw, known, &join(&index.to_string()), fields);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn is_field_name(key: &str) -> bool {
+    key.starts_with(|c: char| c.is_ascii_lowercase())
+        && key.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+}
+
+fn check_config(config: &Config, report: &mut Report) {
+    if config.discord_token.trim().is_empty() {
+        report.fatal("config.json", "`discord_token` is empty".to_owned());
+    }
+    if config.telemetry.enabled && config.telemetry.endpoint.is_none() {
+        report.warn("config.json", "telemetry is enabled without an `endpoint`, so nothing is sent".to_owned());
+    }
+    if let Some(file) = &config.logging.file {
+        if file.level.parse::<log::LevelFilter>().is_err() {
+            report.warn("config.json", format!("`logging.file.level` `{}` isn't a log level, so `info` is used", file.level));
+        }
+    }
+    #[cfg(feature = "replication")]
+    if config.replication.directory.is_some() && config.replication.s3.is_some() {
+        report.warn("config.json", "both `replication.directory` and `replication.s3` are set, so only the bucket is used".to_owned());
+    }
+    let command_limits = config.rate_limits.commands.iter().map(|(command, limit)| (command.as_str(), limit));
+    for (command, limit) in std::iter::once(("default", &config.rate_limits.default)).chain(command_limits) {
+        if limit.burst == 0 {
+            report.warn("config.json", format!("the rate limit of `{}` has a `burst` of 0, so it can't be used at all", command));
+        }
+    }
+    if config.role_queue.concurrency == 0 {
+        report.warn("config.json", "`role_queue.concurrency` is 0, so 1 is used".to_owned());
+    }
+    for name in config.experiments.unknown_features() {
+        report.warn("config.json", format!("`experiments.features` has `{}`, which isn't an experimental feature", name));
+    }
+    if config.join_queue.capacity == 0 || config.join_queue.batch_size == 0 {
+        report.warn("config.json", "`join_queue.capacity` and `join_queue.batch_size` of 0 are taken as 1".to_owned());
+    }
+    if let Some(listen) = &config.metrics.listen {
+        if listen.parse::<std::net::SocketAddr>().is_err() {
+            report.warn("config.json", format!("`metrics.listen` is `{}`, which isn't an address like `127.0.0.1:9100`", listen));
+        }
+    }
+    if config.stores.prefix.contains(['/', '\\']) {
+        report.warn("config.json", format!("`stores.prefix` `{}` has a path separator, use `stores.directory` for directories", config.stores.prefix));
+    }
+    if config.dormancy.archive_after_days == Some(0) {
+        report.warn("config.json", "`dormancy.archive_after_days` is 0, so guilds are archived whenever maintenance runs".to_owned());
+    }
+    if config.metrics.per_guild && config.metrics.listen.is_none() {
+        report.warn("config.json", "`metrics.per_guild` is set, but metrics aren't served without `metrics.listen`".to_owned());
+    }
+}
+
+/// Prints the report of the files in `dir` to stderr, returning whether it's safe to start. This
+/// runs before logging is set up, since that needs the config to be loaded.
+pub fn startup_check(dir: &Path) -> bool {
+    let report = check_files(dir);
+    for issue in &report.issues {
+        eprintln!("{}", issue);
+    }
+    !report.is_fatal()
+}
+
+/// Prints the report of the files in `dir`, as `validate` on the command line.
+pub fn run_cli(dir: &Path) -> Result<(), String> {
+    let report = check_files(dir);
+    for issue in &report.issues {
+        println!("{}", issue);
+    }
+    if report.is_fatal() {
+        return Err("the bot can't start until the problems marked ✖ are fixed".to_owned());
+    }
+    if report.issues.is_empty() {
+        println!("No problems found");
+    }
+    Ok(())
+}
+
+/// The roles and channels that currently exist in a guild.
+struct GuildIds {
+    roles: HashSet<RoleId>,
+    channels: HashSet<ChannelId>,
+}
+
+async fn dangling<K, T>(ctx: &Context, file: &'static str, channel_guilds: &HashMap<ChannelId, GuildId>, found: &mut Vec<(&'static str, GuildId, Reference)>)
+    where K: TypeMapKey<Value = Persistent<T>>,
+          T: Persistable + Introspect
+{
+    let references = {
+        let state = store::<K>(ctx).await;
+        let state = state.read().await;
+        state.references()
+    };
+
+    let mut guilds: HashMap<GuildId, Option<GuildIds>> = HashMap::new();
+    for (owner, reference) in references {
+        let guild = match owner {
+            Owner::Guild(guild) => guild,
+            Owner::Channel(channel) => match channel_guilds.get(&channel) {
+                Some(guild) => *guild,
+                None => continue,
+            },
+            Owner::Unknown => continue,
+        };
+
+        if let Entry::Vacant(entry) = guilds.entry(guild) {
+            entry.insert(ctx.cache.guild(guild).map(|guild| GuildIds {
+                roles: guild.roles.keys().copied().collect(),
+                channels: guild.channels.keys().copied().collect(),
+            }));
+        }
+
+        // guilds we're no longer in are cleaned up by data retention instead
+        if let Some(ids) = &guilds[&guild] {
+            let exists = match reference {
+                Reference::Role(role) => ids.roles.contains(&role),
+                Reference::Channel(channel) => ids.channels.contains(&channel),
+            };
+            if !exists {
+                found.push((file, guild, reference));
+            }
+        }
+    }
+}
+
+/// Checks the files like on startup, and the stores for roles and channels that were deleted.
+pub async fn selfcheck(ctx: &Context, command: &Message) -> CommandResult<()> {
+    require_owner(ctx, &command.author).await?;
+
+    let mut report = check_files(Path::new("."));
+
+    let channel_guilds = memreport::channel_guilds(ctx).await;
+    let channels = &channel_guilds;
+    let mut found = Vec::new();
+    dangling::<reaction_roles::StateKey, _>(ctx, "reaction_roles.json", channels, &mut found).await;
+    dangling::<persistent_roles::StateKey, _>(ctx, "persistent_roles.json", channels, &mut found).await;
+    dangling::<audit::StateKey, _>(ctx, "audit.json", channels, &mut found).await;
+    dangling::<slowmode::StateKey, _>(ctx, "slowmode.json", channels, &mut found).await;
+    dangling::<ping_tracker::StateKey, _>(ctx, "ping_tracker.json", channels, &mut found).await;
+    dangling::<applications::StateKey, _>(ctx, "applications.json", channels, &mut found).await;
+    dangling::<role_caps::StateKey, _>(ctx, "role_caps.json", channels, &mut found).await;
+    dangling::<role_dependencies::StateKey, _>(ctx, "role_dependencies.json", channels, &mut found).await;
+    dangling::<channel_links::StateKey, _>(ctx, "channel_links.json", channels, &mut found).await;
+    dangling::<screening::StateKey, _>(ctx, "screening.json", channels, &mut found).await;
+    dangling::<verification::StateKey, _>(ctx, "verification.json", channels, &mut found).await;
+    dangling::<reaction_roles::expiry::StateKey, _>(ctx, "selector_expiry.json", channels, &mut found).await;
+    dangling::<appeals::StateKey, _>(ctx, "appeals.json", channels, &mut found).await;
+
+    let total = found.len();
+    for (file, guild, reference) in found.into_iter().take(MAX_DANGLING) {
+        let message = match reference {
+            Reference::Role(role) => format!("role {} in guild {} no longer exists", role, guild),
+            Reference::Channel(channel) => format!("channel {} in guild {} no longer exists", channel, guild),
+        };
+        report.warn(file, message);
+    }
+
+    let mut lines: Vec<String> = report.issues.iter().map(Issue::to_string).collect();
+    if total > MAX_DANGLING {
+        lines.push(format!("…and {} more deleted roles and channels", total - MAX_DANGLING));
+    }
+    if lines.is_empty() {
+        lines.push("🩺 No problems found!".to_owned());
+    }
+    say_lines(ctx, command.channel_id, &lines).await?;
+
+    Ok(())
+}
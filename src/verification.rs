@@ -0,0 +1,424 @@
+use std::collections::hash_map::RandomState;
+use std::collections::{HashMap, HashSet};
+use std::hash::{BuildHasher, Hasher};
+
+use log::error;
+use serde::{Deserialize, Serialize};
+use serenity::builder::{CreateAllowedMentions, CreateMessage};
+use serenity::model::prelude::*;
+use serenity::prelude::*;
+
+use crate::{appeals, audit, cases, CommandError, CommandResult, members, Persistent, persistent_roles, reaction_roles, read_only, role_admin, scheduler, store};
+use crate::cases::CaseKind;
+use crate::memreport::{Introspect, Owner, Reference};
+
+/// How many wrong answers a member may give before being kicked.
+const MAX_ATTEMPTS: u8 = 3;
+
+const DEFAULT_KICK_AFTER_MINS: u64 = 30;
+
+pub struct StateKey;
+
+impl TypeMapKey for StateKey {
+    type Value = Persistent<State>;
+}
+
+#[derive(Serialize, Deserialize, Default, Clone, Eq, PartialEq)]
+pub struct State {
+    guilds: HashMap<GuildId, Verification>,
+    #[serde(default)]
+    pending: Vec<Challenge>,
+}
+
+impl Introspect for State {
+    fn entries(&self) -> Vec<(Owner, usize)> {
+        let guilds = self.guilds.keys().map(|guild| (Owner::Guild(*guild), 1));
+        guilds.chain(self.pending.iter().map(|challenge| (Owner::Guild(challenge.guild), 1))).collect()
+    }
+
+    fn references(&self) -> Vec<(Owner, Reference)> {
+        self.guilds.iter().flat_map(|(guild, verification)| {
+            let channel = verification.channel.map(Reference::Channel);
+            std::iter::once(Reference::Role(verification.role)).chain(channel).map(move |reference| (Owner::Guild(*guild), reference))
+        }).collect()
+    }
+}
+
+/// Asks new members to solve a challenge before they get the verified role and their persisted roles.
+#[derive(Serialize, Deserialize, Clone, Eq, PartialEq)]
+struct Verification {
+    role: RoleId,
+    /// Where members who don't accept DMs are challenged instead.
+    #[serde(default)]
+    channel: Option<ChannelId>,
+    /// How long members have to answer before being kicked, or `None` to never kick them.
+    #[serde(default = "default_kick_after")]
+    kick_after_mins: Option<u64>,
+}
+
+fn default_kick_after() -> Option<u64> {
+    Some(DEFAULT_KICK_AFTER_MINS)
+}
+
+#[derive(Serialize, Deserialize, Clone, Eq, PartialEq)]
+struct Challenge {
+    guild: GuildId,
+    user: UserId,
+    /// The channel the challenge was posted in, which is a DM unless DMs were closed.
+    channel: ChannelId,
+    /// The message asking the question, kept until it's sent for challenges that are queued.
+    #[serde(default)]
+    question: String,
+    answer: String,
+    attempts: u8,
+    expires_at: Option<i64>,
+    /// Whether the challenge waits for the member to answer another guild's challenge over the same
+    /// DM first, since answers there can't tell the guilds apart.
+    #[serde(default)]
+    queued: bool,
+}
+
+/// A simple sum to solve, as the question and its answer.
+fn new_challenge() -> (String, String) {
+    let random = |bound: u64| RandomState::new().build_hasher().finish() % bound;
+    let (a, b) = (2 + random(18), 2 + random(18));
+    (format!("What is {} + {}?", a, b), (a + b).to_string())
+}
+
+pub async fn enable(ctx: &Context, command: &Message, role: RoleId, channel: Option<ChannelId>) -> CommandResult<()> {
+    let guild = command.guild_id.ok_or(CommandError::NotAllowed)?;
+    role_admin::check_hierarchy(ctx, guild, command.author.id, role).await?;
+
+    let state = store::<StateKey>(ctx).await;
+    state.write(|state| {
+        let kick_after_mins = state.guilds.get(&guild).map(|verification| verification.kick_after_mins).unwrap_or_else(default_kick_after);
+        state.guilds.insert(guild, Verification { role, channel, kick_after_mins });
+    }).await;
+
+    status(ctx, command).await
+}
+
+/// Turns verification off. Members who are still being challenged are let in.
+pub async fn disable(ctx: &Context, command: &Message) -> CommandResult<()> {
+    let guild = command.guild_id.ok_or(CommandError::NotAllowed)?;
+
+    let state = store::<StateKey>(ctx).await;
+    let (verification, waiting) = state.write(|state| {
+        let waiting: Vec<Challenge> = state.pending.iter().filter(|challenge| challenge.guild == guild).cloned().collect();
+        state.pending.retain(|challenge| challenge.guild != guild);
+        (state.guilds.remove(&guild), waiting)
+    }).await;
+
+    for challenge in &waiting {
+        send_queued(ctx, challenge.user, challenge.channel).await;
+    }
+    if let Some(verification) = verification {
+        for challenge in waiting {
+            admit(ctx, guild, challenge.user, verification.role).await;
+        }
+    }
+
+    Ok(())
+}
+
+pub async fn set_kick_after(ctx: &Context, command: &Message, minutes: Option<u64>) -> CommandResult<()> {
+    let guild = command.guild_id.ok_or(CommandError::NotAllowed)?;
+
+    let state = store::<StateKey>(ctx).await;
+    let updated = state.write(|state| {
+        match state.guilds.get_mut(&guild) {
+            Some(verification) => {
+                verification.kick_after_mins = minutes;
+                true
+            }
+            None => false,
+        }
+    }).await;
+
+    if !updated {
+        return Err(CommandError::VerificationOff);
+    }
+    status(ctx, command).await
+}
+
+pub async fn status(ctx: &Context, command: &Message) -> CommandResult<()> {
+    let guild = command.guild_id.ok_or(CommandError::NotAllowed)?;
+
+    let (verification, waiting) = {
+        let state = store::<StateKey>(ctx).await;
+        let state = state.read().await;
+        let waiting = state.pending.iter().filter(|challenge| challenge.guild == guild).count();
+        (state.guilds.get(&guild).cloned(), waiting)
+    };
+
+    let content = match verification {
+        Some(verification) => {
+            let fallback = match verification.channel {
+                Some(channel) => format!(", or in <#{}> if their DMs are closed", channel),
+                None => String::new(),
+            };
+            let kick = match verification.kick_after_mins {
+                Some(minutes) => format!("Members who don't answer within {} minutes are kicked.", minutes),
+                None => "Members who don't answer are never kicked.".to_owned(),
+            };
+            format!(
+                "🧩 New members are challenged over DM{} and get <@&{}> once they answer. {} {} members are being challenged.",
+                fallback, verification.role, kick, waiting,
+            )
+        }
+        None => "Verification is off.".to_owned(),
+    };
+    command.channel_id.send_message(&ctx.http, CreateMessage::new().content(content).allowed_mentions(CreateAllowedMentions::new())).await?;
+
+    Ok(())
+}
+
+/// Challenges a new member if the guild requires verification, returning whether they were. Their
+/// persisted roles are held back until they answer.
+pub async fn guild_member_addition(ctx: &Context, member: &Member) -> bool {
+    let guild = member.guild_id;
+    let verification = {
+        let state = store::<StateKey>(ctx).await;
+        let state = state.read().await;
+        state.guilds.get(&guild).cloned()
+    };
+    let verification = match verification {
+        Some(verification) if !member.user.bot => verification,
+        _ => return false,
+    };
+
+    let (question, answer) = new_challenge();
+    let name = guild.name(&ctx.cache).unwrap_or_else(|| "the server".to_owned());
+    let content = format!("👋 Welcome to **{}**! To get in, please answer this: **{}**", name, question);
+    let expires_at = verification.kick_after_mins.map(|minutes| scheduler::now() + minutes as i64 * 60);
+    let challenge = |channel| Challenge {
+        guild, user: member.user.id, channel, question: content.clone(), answer: answer.clone(), attempts: 0, expires_at, queued: false,
+    };
+
+    // the challenge is stored before it's sent, so that one from another guild joined at the same
+    // time is queued behind it
+    if let Ok(dm) = member.user.create_dm_channel(&ctx.http).await {
+        if add_challenge(ctx, challenge(dm.id)).await {
+            return true;
+        }
+        if dm.say(&ctx.http, &content).await.is_ok() {
+            return true;
+        }
+        remove_challenge(ctx, guild, member.user.id).await;
+    }
+
+    let channel = match verification.channel {
+        Some(channel) => {
+            let posted = channel.send_message(&ctx.http, CreateMessage::new()
+                .content(format!("<@{}> {}", member.user.id.get(), content))
+                .allowed_mentions(CreateAllowedMentions::new().users(vec![member.user.id]))
+            ).await;
+            match posted {
+                Ok(_) => channel,
+                Err(err) => {
+                    error!("failed to challenge {} in {}: {:?}", member.user.id, guild, err);
+                    audit::log(ctx, guild, format!("⚠ Couldn't send <@{}> their verification challenge.", member.user.id.get())).await;
+                    return false;
+                }
+            }
+        }
+        None => {
+            audit::log(ctx, guild, format!("⚠ Couldn't DM <@{}> their verification challenge. Set a verification channel for members with closed DMs.", member.user.id.get())).await;
+            return false;
+        }
+    };
+
+    add_challenge(ctx, challenge(channel)).await;
+
+    true
+}
+
+/// Stores a member's challenge, replacing any earlier one of the same guild, and returning whether
+/// it was queued behind a challenge of another guild in the same channel.
+async fn add_challenge(ctx: &Context, mut challenge: Challenge) -> bool {
+    let state = store::<StateKey>(ctx).await;
+    state.write(|state| {
+        state.pending.retain(|pending| (pending.guild, pending.user) != (challenge.guild, challenge.user));
+        challenge.queued = state.pending.iter().any(|pending| is_active_in(pending, challenge.user, challenge.channel));
+        if challenge.queued {
+            challenge.expires_at = None;
+        }
+        let queued = challenge.queued;
+        state.pending.push(challenge);
+        queued
+    }).await
+}
+
+fn is_active_in(challenge: &Challenge, user: UserId, channel: ChannelId) -> bool {
+    challenge.user == user && challenge.channel == channel && !challenge.queued
+}
+
+/// Sends the next challenge queued for a member in a channel, once the one before it is gone. Its
+/// time to answer starts from now.
+async fn send_queued(ctx: &Context, user: UserId, channel: ChannelId) {
+    let now = scheduler::now();
+    let state = store::<StateKey>(ctx).await;
+    let next = state.write(|state| {
+        if state.pending.iter().any(|pending| is_active_in(pending, user, channel)) {
+            return None;
+        }
+        let next = state.pending.iter_mut().find(|pending| pending.user == user && pending.channel == channel)?;
+        let kick_after_mins = state.guilds.get(&next.guild).and_then(|verification| verification.kick_after_mins);
+        next.queued = false;
+        next.expires_at = kick_after_mins.map(|minutes| now + minutes as i64 * 60);
+        Some(next.question.clone())
+    }).await;
+
+    if let Some(question) = next {
+        if let Err(err) = channel.say(&ctx.http, question).await {
+            error!("failed to send the queued challenge of {}: {:?}", user, err);
+        }
+    }
+}
+
+/// Checks answers to challenges, returning whether the message was one.
+pub async fn message(ctx: &Context, message: &Message) -> bool {
+    let challenge = {
+        let state = store::<StateKey>(ctx).await;
+        let state = state.read().await;
+        state.pending.iter()
+            .find(|challenge| is_active_in(challenge, message.author.id, message.channel_id))
+            .cloned()
+    };
+    let challenge = match challenge {
+        Some(challenge) => challenge,
+        None => return false,
+    };
+
+    // answers in a shared channel shouldn't be left around for others to copy
+    if message.guild_id.is_some() && !read_only::blocks(ctx, message.guild_id, || format!("deleted the answer of {}", message.author.id)).await {
+        let _ = message.delete(ctx).await;
+    }
+
+    if message.content.trim() == challenge.answer {
+        let role = remove_challenge(ctx, challenge.guild, challenge.user).await;
+        if let Some(role) = role {
+            admit(ctx, challenge.guild, challenge.user, role).await;
+            let _ = message.channel_id.say(&ctx.http, "✅ Thanks, you're in!").await;
+        }
+        return true;
+    }
+
+    let attempts = challenge.attempts + 1;
+    if attempts >= MAX_ATTEMPTS {
+        remove_challenge(ctx, challenge.guild, challenge.user).await;
+        kick(ctx, challenge.guild, challenge.user, "Failed verification").await;
+        return true;
+    }
+
+    let state = store::<StateKey>(ctx).await;
+    state.write(|state| {
+        if let Some(pending) = state.pending.iter_mut().find(|pending| (pending.guild, pending.user) == (challenge.guild, challenge.user)) {
+            pending.attempts = attempts;
+        }
+    }).await;
+
+    let remaining = MAX_ATTEMPTS - attempts;
+    let _ = message.channel_id.say(&ctx.http, format!("❌ That's not right. You have {} more tries.", remaining)).await;
+
+    true
+}
+
+/// Removes a member's challenge, sending the next one queued behind it, and returning the verified
+/// role of their guild.
+async fn remove_challenge(ctx: &Context, guild: GuildId, user: UserId) -> Option<RoleId> {
+    let state = store::<StateKey>(ctx).await;
+    let (role, channel) = state.write(|state| {
+        let channel = state.pending.iter()
+            .find(|challenge| (challenge.guild, challenge.user) == (guild, user))
+            .map(|challenge| challenge.channel);
+        state.pending.retain(|challenge| (challenge.guild, challenge.user) != (guild, user));
+        (state.guilds.get(&guild).map(|verification| verification.role), channel)
+    }).await;
+
+    if let Some(channel) = channel {
+        send_queued(ctx, user, channel).await;
+    }
+    role
+}
+
+/// Gives a verified member their persisted roles and the verified role.
+async fn admit(ctx: &Context, guild: GuildId, user: UserId, role: RoleId) {
+    if read_only::blocks(ctx, guild, || format!("admitted {} after verification", user)).await {
+        return;
+    }
+
+    let mut member = match members::member(ctx, guild, user).await {
+        Ok(member) => member,
+        Err(_) => return,
+    };
+
+    persistent_roles::guild_member_addition(ctx, &mut member).await;
+    reaction_roles::onboarding::member_joined(ctx, guild, user);
+
+    if let Err(err) = member.add_role(&ctx.http, role).await {
+        error!("failed to give verified role to {} in {}: {:?}", user, guild, err);
+        audit::log(ctx, guild, format!("⚠ Couldn't give <@&{}> to <@{}> after verification: {}", role.get(), user.get(), err)).await;
+    }
+}
+
+async fn kick(ctx: &Context, guild: GuildId, user: UserId, reason: &str) {
+    if read_only::blocks(ctx, guild, || format!("kicked {}: {}", user, reason)).await {
+        return;
+    }
+
+    match guild.kick_with_reason(&ctx.http, user, reason).await {
+        Ok(()) => {
+            let case = cases::open(ctx, guild, CaseKind::Kick, user, None, Some(reason)).await;
+            audit::log(ctx, guild, format!("🧩 Kicked <@{}> (case #{}): {}", user.get(), case, reason)).await;
+            appeals::offer(ctx, guild, user, case, "kicked", reason).await;
+        }
+        Err(err) => error!("failed to kick {} from {}: {:?}", user, guild, err),
+    }
+}
+
+/// Whether a member still has to answer their challenge, in which case their roles shouldn't be
+/// persisted yet.
+pub async fn is_pending(ctx: &Context, guild: GuildId, user: UserId) -> bool {
+    let state = store::<StateKey>(ctx).await;
+    let pending = state.read().await.pending.iter().any(|challenge| (challenge.guild, challenge.user) == (guild, user));
+    pending
+}
+
+pub async fn guild_member_removal(ctx: &Context, guild: GuildId, user: UserId) {
+    if is_pending(ctx, guild, user).await {
+        remove_challenge(ctx, guild, user).await;
+    }
+}
+
+/// Kicks members who didn't answer their challenge in time.
+pub async fn expire_challenges(ctx: Context) {
+    let now = scheduler::now();
+    let expired: Vec<Challenge> = {
+        let state = store::<StateKey>(&ctx).await;
+        if !state.read().await.pending.iter().any(|challenge| challenge.expires_at.is_some_and(|at| at <= now)) {
+            return;
+        }
+        state.write(|state| {
+            let (expired, waiting) = state.pending.drain(..).partition(|challenge| challenge.expires_at.is_some_and(|at| at <= now));
+            state.pending = waiting;
+            expired
+        }).await
+    };
+
+    for challenge in expired {
+        send_queued(&ctx, challenge.user, challenge.channel).await;
+        kick(&ctx, challenge.guild, challenge.user, "Didn't complete verification in time").await;
+    }
+}
+
+/// Forgets all guilds not in `guilds`, returning the ones that were removed.
+pub async fn retain_guilds(ctx: &Context, guilds: &HashSet<GuildId>) -> Vec<GuildId> {
+    let state = store::<StateKey>(ctx).await;
+    state.write(|state| {
+        let removed = state.guilds.keys().filter(|guild| !guilds.contains(guild)).copied().collect();
+        state.guilds.retain(|guild, _| guilds.contains(guild));
+        state.pending.retain(|challenge| guilds.contains(&challenge.guild));
+        removed
+    }).await
+}
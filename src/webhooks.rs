@@ -0,0 +1,284 @@
+use std::collections::{HashMap, HashSet};
+use std::net::IpAddr;
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use log::error;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use serenity::model::prelude::*;
+use serenity::prelude::*;
+
+use crate::{CommandError, CommandResult, Persistent, store};
+use crate::events::Event;
+use crate::memreport::{Introspect, Owner};
+
+/// How many times delivery is attempted before giving up, backing off exponentially in between.
+const MAX_ATTEMPTS: u32 = 4;
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// Shared by every delivery, so that connections are reused. Redirects aren't followed, since they
+/// could lead to a host that wouldn't have been accepted.
+static CLIENT: OnceLock<reqwest::Client> = OnceLock::new();
+
+pub struct StateKey;
+
+impl TypeMapKey for StateKey {
+    type Value = Persistent<State>;
+}
+
+#[derive(Serialize, Deserialize, Default, Clone, Eq, PartialEq)]
+pub struct State {
+    guilds: HashMap<GuildId, GuildState>,
+}
+
+impl Introspect for State {
+    fn entries(&self) -> Vec<(Owner, usize)> {
+        self.guilds.iter().map(|(guild, state)| (Owner::Guild(*guild), state.webhooks.len())).collect()
+    }
+}
+
+#[derive(Serialize, Deserialize, Default, Clone, Eq, PartialEq)]
+struct GuildState {
+    next_id: u32,
+    webhooks: Vec<Webhook>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Eq, PartialEq)]
+struct Webhook {
+    id: u32,
+    url: String,
+    /// The event types to fire on, or every event if empty.
+    events: HashSet<String>,
+    /// The payload, with `{field}` placeholders for the event's fields and `{json}` for the whole event.
+    template: Option<String>,
+}
+
+impl Webhook {
+    fn is_discord(&self) -> bool {
+        self.url.starts_with("https://discord.com/api/webhooks/") || self.url.starts_with("https://discordapp.com/api/webhooks/")
+    }
+
+    fn accepts(&self, event_type: &str) -> bool {
+        self.events.is_empty() || self.events.contains(event_type)
+    }
+
+    /// Builds the request body: Discord webhooks get a message, anything else gets JSON.
+    fn payload(&self, event: &Value) -> Value {
+        let rendered = self.template.as_ref().map(|template| render(template, event));
+        match (self.is_discord(), rendered) {
+            (true, Some(content)) => json!({ "content": content, "allowed_mentions": { "parse": [] } }),
+            (true, None) => json!({ "content": format!("```json\n{}\n```", event), "allowed_mentions": { "parse": [] } }),
+            (false, Some(body)) => serde_json::from_str(&body).unwrap_or(Value::String(body)),
+            (false, None) => event.clone(),
+        }
+    }
+}
+
+/// Substitutes `{field}` placeholders with the event's top-level fields.
+pub fn render(template: &str, event: &Value) -> String {
+    let mut rendered = template.replace("{json}", &event.to_string());
+    if let Value::Object(fields) = event {
+        for (key, value) in fields {
+            let value = match value {
+                Value::String(value) => value.clone(),
+                value => value.to_string(),
+            };
+            rendered = rendered.replace(&format!("{{{}}}", key), &value);
+        }
+    }
+    rendered
+}
+
+/// Whether an address is reachable from the internet, rather than being the bot's own machine, its
+/// network, or a cloud metadata service.
+pub fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            // 100.64.0.0/10 is shared address space for carrier-grade NAT
+            let shared = a == 100 && (b & 0xc0) == 64;
+            !(ip.is_loopback() || ip.is_private() || ip.is_link_local() || ip.is_unspecified()
+                || ip.is_broadcast() || ip.is_documentation() || ip.is_multicast() || shared || a == 0)
+        }
+        IpAddr::V6(ip) => {
+            if let Some(ip) = ip.to_ipv4_mapped() {
+                return is_public(IpAddr::V4(ip));
+            }
+            let first = ip.segments()[0];
+            // fc00::/7 is unique local and fe80::/10 link-local
+            let local = (first & 0xfe00) == 0xfc00 || (first & 0xffc0) == 0xfe80;
+            !(ip.is_loopback() || ip.is_unspecified() || ip.is_multicast() || local)
+        }
+    }
+}
+
+/// Checks that a webhook URL uses HTTPS and points at a public host, looking its name up to see
+/// where it leads.
+pub async fn check_url(url: &str) -> CommandResult<()> {
+    let parsed = reqwest::Url::parse(url).map_err(|_| CommandError::MalformedArgument(url.to_owned()))?;
+    if parsed.scheme() != "https" {
+        return Err(CommandError::UnsafeWebhookUrl);
+    }
+    let host = parsed.host_str().ok_or(CommandError::UnsafeWebhookUrl)?;
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+
+    if let Ok(ip) = host.parse::<IpAddr>() {
+        return if is_public(ip) { Ok(()) } else { Err(CommandError::UnsafeWebhookUrl) };
+    }
+    let host = host.to_ascii_lowercase();
+    if host == "localhost" || host.ends_with(".localhost") || host.ends_with(".internal") || host.ends_with(".local") {
+        return Err(CommandError::UnsafeWebhookUrl);
+    }
+
+    let port = parsed.port_or_known_default().unwrap_or(443);
+    let addresses: Vec<_> = tokio::net::lookup_host((host.as_str(), port)).await
+        .map_err(|_| CommandError::UnsafeWebhookUrl)?
+        .collect();
+    if addresses.is_empty() || !addresses.iter().all(|address| is_public(address.ip())) {
+        return Err(CommandError::UnsafeWebhookUrl);
+    }
+    Ok(())
+}
+
+pub async fn add_webhook(ctx: &Context, command: &Message, url: &str, events: &str, template: Option<&str>) -> CommandResult<()> {
+    let guild = command.guild_id.ok_or(CommandError::NotAllowed)?;
+
+    check_url(url).await?;
+
+    let events: HashSet<String> = match events {
+        "all" => HashSet::new(),
+        events => events.split(',').map(str::to_owned).collect(),
+    };
+    if let Some(unknown) = events.iter().find(|event| !Event::TYPES.contains(&event.as_str())) {
+        return Err(CommandError::MalformedArgument(unknown.clone()));
+    }
+
+    let state = store::<StateKey>(ctx).await;
+    let id = state.write(|state| {
+        let guild = state.guilds.entry(guild).or_default();
+        guild.next_id += 1;
+        let id = guild.next_id;
+        guild.webhooks.push(Webhook { id, url: url.to_owned(), events, template: template.map(str::to_owned) });
+        id
+    }).await;
+
+    // the command contains the webhook URL, which shouldn't stay visible in the channel
+    let _ = command.delete(ctx).await;
+    command.channel_id.say(&ctx.http, format!("Added webhook #{}", id)).await?;
+
+    Ok(())
+}
+
+pub async fn remove_webhook(ctx: &Context, command: &Message, id: u32) -> CommandResult<()> {
+    let guild = command.guild_id.ok_or(CommandError::NotAllowed)?;
+
+    let state = store::<StateKey>(ctx).await;
+    state.write(|state| {
+        let guild_state = state.guilds.get_mut(&guild).ok_or(CommandError::UnknownWebhook)?;
+        let index = guild_state.webhooks.iter().position(|webhook| webhook.id == id).ok_or(CommandError::UnknownWebhook)?;
+        guild_state.webhooks.remove(index);
+        Ok(())
+    }).await
+}
+
+pub async fn list_webhooks(ctx: &Context, command: &Message) -> CommandResult<()> {
+    let guild = command.guild_id.ok_or(CommandError::NotAllowed)?;
+
+    let state = store::<StateKey>(ctx).await;
+    let lines: Vec<String> = state.read().await.guilds.get(&guild)
+        .map(|guild| guild.webhooks.iter()
+            .map(|webhook| {
+                let mut events: Vec<&str> = webhook.events.iter().map(String::as_str).collect();
+                events.sort_unstable();
+                let events = if events.is_empty() { "all events".to_owned() } else { events.join(", ") };
+                // only show where the webhook goes, since the rest of the URL is a secret
+                let host = webhook.url.split('/').nth(2).unwrap_or_default();
+                format!("#{} → `{}` on {}{}", webhook.id, host, events, if webhook.template.is_some() { " (templated)" } else { "" })
+            })
+            .collect())
+        .unwrap_or_default();
+
+    if lines.is_empty() {
+        command.reply(ctx, "No webhooks are set up!").await?;
+    } else {
+        crate::say_lines(ctx, command.channel_id, &lines).await?;
+    }
+
+    Ok(())
+}
+
+/// Fires every webhook of the event's guild which is subscribed to it.
+pub async fn dispatch(ctx: &Context, event: &Value) {
+    let guild = match event.get("guild").and_then(Value::as_str).and_then(|guild| guild.parse().ok()) {
+        Some(guild) => guild,
+        None => return,
+    };
+    let event_type = event.get("type").and_then(Value::as_str).unwrap_or_default();
+
+    let state = store::<StateKey>(ctx).await;
+    let webhooks: Vec<Webhook> = state.read().await.guilds.get(&guild)
+        .map(|guild| guild.webhooks.iter().filter(|webhook| webhook.accepts(event_type)).cloned().collect())
+        .unwrap_or_default();
+
+    for webhook in webhooks {
+        // webhooks added before URLs were checked may still use plain HTTP
+        if !webhook.url.starts_with("https://") {
+            error!("not delivering webhook #{} of {}, since it doesn't use HTTPS", webhook.id, guild);
+            continue;
+        }
+        let payload = webhook.payload(event);
+        tokio::spawn(async move {
+            if let Err(err) = deliver(&webhook.url, &payload).await {
+                error!("failed to deliver webhook #{} of {}: {:?}", webhook.id, guild, err);
+            }
+        });
+    }
+}
+
+fn client() -> &'static reqwest::Client {
+    CLIENT.get_or_init(|| {
+        reqwest::Client::builder()
+            .connect_timeout(CONNECT_TIMEOUT)
+            .timeout(REQUEST_TIMEOUT)
+            .redirect(reqwest::redirect::Policy::none())
+            .build()
+            .expect("failed to build HTTP client")
+    })
+}
+
+/// Posts the payload, retrying on connection failures, rate limits and server errors.
+async fn deliver(url: &str, payload: &Value) -> reqwest::Result<()> {
+    let client = client();
+    let mut backoff = INITIAL_BACKOFF;
+
+    for attempt in 1..=MAX_ATTEMPTS {
+        let result = client.post(url).json(payload).send().await;
+        let retry = match &result {
+            Ok(response) => response.status().is_server_error() || response.status().as_u16() == 429,
+            Err(err) => err.is_connect() || err.is_timeout(),
+        };
+
+        if !retry || attempt == MAX_ATTEMPTS {
+            return result.and_then(|response| response.error_for_status()).map(|_| ());
+        }
+
+        tokio::time::sleep(backoff).await;
+        backoff *= 2;
+    }
+
+    Ok(())
+}
+
+/// Forgets all guilds not in `guilds`, returning the ones that were removed.
+pub async fn retain_guilds(ctx: &Context, guilds: &HashSet<GuildId>) -> Vec<GuildId> {
+    let state = store::<StateKey>(ctx).await;
+    state.write(|state| {
+        let removed = state.guilds.keys().filter(|guild| !guilds.contains(guild)).copied().collect();
+        state.guilds.retain(|guild, _| guilds.contains(guild));
+        removed
+    }).await
+}
@@ -0,0 +1,13 @@
+use serenity::model::prelude::*;
+
+use mossy_stone_brick_monster_egg::backups::{Backup, INTERVAL_SECS};
+
+#[test]
+fn backups_are_due_weekly() {
+    let mut backup = Backup { channel: ChannelId::new(1), last_posted_at: None };
+    assert!(backup.is_due(0));
+
+    backup.last_posted_at = Some(1000);
+    assert!(!backup.is_due(1000 + INTERVAL_SECS - 1));
+    assert!(backup.is_due(1000 + INTERVAL_SECS));
+}
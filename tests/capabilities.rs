@@ -0,0 +1,21 @@
+use serenity::model::prelude::*;
+
+use mossy_stone_brick_monster_egg::capabilities::{members_intent_granted, set_members_intent};
+use mossy_stone_brick_monster_egg::intents;
+
+#[test]
+fn members_intent_follows_application_flags() {
+    assert!(members_intent_granted(ApplicationFlags::GATEWAY_GUILD_MEMBERS));
+    assert!(members_intent_granted(ApplicationFlags::GATEWAY_GUILD_MEMBERS_LIMITED | ApplicationFlags::GATEWAY_PRESENCE));
+    assert!(!members_intent_granted(ApplicationFlags::GATEWAY_MESSAGE_CONTENT));
+}
+
+#[test]
+fn reduced_mode_leaves_out_members_intent() {
+    set_members_intent(false);
+    assert!(!intents().contains(GatewayIntents::GUILD_MEMBERS));
+    assert!(intents().contains(GatewayIntents::GUILD_MESSAGES));
+
+    set_members_intent(true);
+    assert!(intents().contains(GatewayIntents::GUILD_MEMBERS));
+}
@@ -0,0 +1,38 @@
+use serenity::model::prelude::*;
+
+use mossy_stone_brick_monster_egg::cases::{CaseKind, State};
+
+const GUILD: GuildId = GuildId::new(829374619283740000);
+const OTHER_GUILD: GuildId = GuildId::new(829374619283740001);
+const USER: UserId = UserId::new(829374619283741111);
+const MODERATOR: UserId = UserId::new(829374619283742222);
+
+#[test]
+fn numbers_cases_per_guild() {
+    let mut state = State::default();
+    assert_eq!(state.open_case(GUILD, CaseKind::Warn, USER, Some(MODERATOR), Some("spam".to_owned()), 100), 1);
+    assert_eq!(state.open_case(GUILD, CaseKind::Kick, USER, None, None, 200), 2);
+    assert_eq!(state.open_case(OTHER_GUILD, CaseKind::Warn, USER, Some(MODERATOR), None, 300), 1);
+
+    let case = state.case(GUILD, 2).unwrap();
+    assert_eq!(case.kind, CaseKind::Kick);
+    assert_eq!(case.moderator, None);
+    assert!(state.case(GUILD, 3).is_none());
+
+    let ids: Vec<u64> = state.cases_of(GUILD, USER).iter().map(|case| case.id).collect();
+    assert_eq!(ids, vec![1, 2]);
+    assert!(state.cases_of(GUILD, MODERATOR).is_empty());
+}
+
+#[test]
+fn edits_reasons() {
+    let mut state = State::default();
+    let id = state.open_case(GUILD, CaseKind::SelectorBan, USER, Some(MODERATOR), None, 100);
+
+    assert!(state.set_reason(GUILD, id, "abusing selectors".to_owned(), MODERATOR));
+    assert!(!state.set_reason(OTHER_GUILD, id, "wrong guild".to_owned(), MODERATOR));
+
+    let case = state.case(GUILD, id).unwrap();
+    assert_eq!(case.reason.as_deref(), Some("abusing selectors"));
+    assert_eq!(case.edited_by, Some(MODERATOR));
+}
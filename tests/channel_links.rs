@@ -0,0 +1,40 @@
+use std::collections::BTreeSet;
+
+use serenity::model::prelude::*;
+
+use mossy_stone_brick_monster_egg::channel_links::missing_overwrites;
+
+const EVERYONE: RoleId = RoleId::new(829374619283740000);
+const MEMBERS: RoleId = RoleId::new(829374619283746001);
+const BOT: UserId = UserId::new(829374619283749999);
+
+fn overwrite(kind: PermissionOverwriteType, allow: Permissions, deny: Permissions) -> PermissionOverwrite {
+    PermissionOverwrite { allow, deny, kind }
+}
+
+#[test]
+fn hides_channel_from_everyone_but_linked_roles() {
+    let roles: BTreeSet<RoleId> = vec![MEMBERS].into_iter().collect();
+    let missing = missing_overwrites(&[], EVERYONE, &roles, BOT);
+
+    assert_eq!(missing.len(), 3);
+    assert!(missing.iter().any(|o| o.kind == PermissionOverwriteType::Role(EVERYONE) && o.deny == Permissions::VIEW_CHANNEL));
+    assert!(missing.iter().any(|o| o.kind == PermissionOverwriteType::Role(MEMBERS) && o.allow == Permissions::VIEW_CHANNEL));
+    assert!(missing.iter().any(|o| o.kind == PermissionOverwriteType::Member(BOT) && o.allow == Permissions::VIEW_CHANNEL));
+}
+
+#[test]
+fn keeps_other_permissions_and_skips_correct_overwrites() {
+    let roles: BTreeSet<RoleId> = vec![MEMBERS].into_iter().collect();
+    let existing = vec![
+        overwrite(PermissionOverwriteType::Role(EVERYONE), Permissions::VIEW_CHANNEL, Permissions::SEND_MESSAGES),
+        overwrite(PermissionOverwriteType::Role(MEMBERS), Permissions::VIEW_CHANNEL | Permissions::SEND_MESSAGES, Permissions::empty()),
+        overwrite(PermissionOverwriteType::Member(BOT), Permissions::VIEW_CHANNEL, Permissions::empty()),
+    ];
+
+    let missing = missing_overwrites(&existing, EVERYONE, &roles, BOT);
+    assert_eq!(missing.len(), 1);
+    assert_eq!(missing[0].kind, PermissionOverwriteType::Role(EVERYONE));
+    assert_eq!(missing[0].allow, Permissions::empty());
+    assert_eq!(missing[0].deny, Permissions::VIEW_CHANNEL | Permissions::SEND_MESSAGES);
+}
@@ -0,0 +1,60 @@
+use serenity::model::id::{ChannelId, GuildId, MessageId, UserId};
+
+use mossy_stone_brick_monster_egg::{command_tokens, parse_message_link};
+use mossy_stone_brick_monster_egg::mentions::{classify, Addressed};
+
+const BOT: UserId = UserId::new(1234);
+
+#[test]
+fn strips_bot_mentions() {
+    assert_eq!(command_tokens("<@1234> setup status", BOT), vec!["setup", "status"]);
+    assert_eq!(command_tokens("<@!1234>\u{3000}\u{3000}setup  status ", BOT), vec!["setup", "status"]);
+    assert_eq!(command_tokens("role cap <@&55> 10 <@1234>", BOT), vec!["role", "cap", "<@&55>", "10"]);
+    assert_eq!(command_tokens("<@999> <@1234> undo", BOT), vec!["<@999>", "undo"]);
+}
+
+#[test]
+fn bare_mentions_have_no_tokens() {
+    assert!(command_tokens("<@1234>", BOT).is_empty());
+    assert!(command_tokens("  <@!1234>  ", BOT).is_empty());
+    assert!(command_tokens("", BOT).is_empty());
+}
+
+#[test]
+fn only_leading_mentions_are_commands() {
+    assert_eq!(classify("<@1234> setup status", BOT, None), Addressed::Command(vec!["setup", "status"]));
+    assert_eq!(classify("  <@!1234>  ", BOT, None), Addressed::Bare);
+    assert_eq!(classify("thanks <@1234>, that worked", BOT, None), Addressed::Casual);
+    assert_eq!(classify("<@999> <@1234> undo", BOT, None), Addressed::Casual);
+    assert_eq!(classify("nothing to see here", BOT, None), Addressed::NotAddressed);
+}
+
+#[test]
+fn prefixed_messages_are_commands() {
+    assert_eq!(classify("!setup status", BOT, Some("!")), Addressed::Command(vec!["setup", "status"]));
+    assert_eq!(classify("  ! doctor", BOT, Some("!")), Addressed::Command(vec!["doctor"]));
+    assert_eq!(classify("!", BOT, Some("!")), Addressed::NotAddressed);
+    assert_eq!(classify("wow! <@1234>", BOT, Some("!")), Addressed::Casual);
+    assert_eq!(classify("!setup status", BOT, None), Addressed::NotAddressed);
+}
+
+#[test]
+fn keeps_quoted_phrases() {
+    assert_eq!(
+        command_tokens(r#"<@1234> set application <@&1> <#2> "Why?" "How old are you?""#, BOT),
+        vec!["set", "application", "<@&1>", "<#2>", "Why?", "How old are you?"],
+    );
+}
+
+#[test]
+fn parses_message_links() {
+    let expected = (GuildId::new(11), ChannelId::new(22), MessageId::new(33));
+    assert_eq!(parse_message_link("https://discord.com/channels/11/22/33").unwrap(), expected);
+    assert_eq!(parse_message_link("<https://canary.discord.com/channels/11/22/33>").unwrap(), expected);
+    assert_eq!(parse_message_link("https://discordapp.com/channels/11/22/33").unwrap(), expected);
+
+    assert!(parse_message_link("https://discord.com/channels/11/22").is_err());
+    assert!(parse_message_link("https://discord.com/channels/@me/22/33").is_err());
+    assert!(parse_message_link("https://example.com/channels/11/22/33").is_err());
+    assert!(parse_message_link("33").is_err());
+}
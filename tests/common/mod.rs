@@ -0,0 +1,14 @@
+use std::path::{Path, PathBuf};
+
+use tempfile::TempDir;
+
+/// Copies the named fixture into a fresh temporary directory so tests can freely write to it.
+pub fn fixture(name: &str) -> (TempDir, PathBuf) {
+    let dir = tempfile::tempdir().expect("failed to create temporary directory");
+
+    let source = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures").join(name);
+    let target = dir.path().join(name);
+    std::fs::copy(&source, &target).expect("failed to copy fixture");
+
+    (dir, target)
+}
@@ -0,0 +1,93 @@
+use std::collections::{HashMap, HashSet};
+
+use serenity::model::prelude::*;
+
+use mossy_stone_brick_monster_egg::config_mirror::{diff, GuildConfig, Names, Resolved};
+
+fn names() -> Names {
+    let roles = vec![
+        (RoleId::new(10), "Red Team".to_owned()),
+        (RoleId::new(11), "Blue Team".to_owned()),
+        (RoleId::new(12), "Helper".to_owned()),
+        (RoleId::new(13), "Helper".to_owned()),
+    ];
+    let channels = vec![(ChannelId::new(20), "staff".to_owned())];
+    Names::new(roles.into_iter().collect(), channels.into_iter().collect::<HashMap<_, _>>())
+}
+
+fn selectors() -> HashSet<MessageId> {
+    vec![MessageId::new(100)].into_iter().collect()
+}
+
+const CONFIG: &str = r#"
+notify_owner = true
+persisted_roles = ["12", "Red Team"]
+
+[selectors.100]
+enabled = true
+approval_channel = "staff"
+
+[selectors.100.unmapped]
+kind = "ignore"
+
+[selectors.100.roles]
+"🔴" = ["Red Team"]
+"🔵" = ["Blue Team", "13"]
+"#;
+
+#[test]
+fn round_trips_through_toml() {
+    let config = GuildConfig::parse(CONFIG).unwrap();
+    let resolved = Resolved::resolve(&config, &names(), &selectors()).ok().unwrap();
+
+    let described = resolved.describe(&names());
+    assert_eq!(described, config);
+    assert_eq!(GuildConfig::parse(&described.to_toml()).unwrap(), config);
+}
+
+#[test]
+fn reports_every_problem() {
+    let config = GuildConfig::parse(r#"
+        persisted_roles = ["Helper", "Green Team"]
+
+        [selectors.101.roles]
+        "🔴" = ["Red Team"]
+
+        [selectors.100]
+        approval_channel = "general"
+    "#).unwrap();
+
+    let problems = Resolved::resolve(&config, &names(), &selectors()).err().unwrap();
+    assert_eq!(problems, vec![
+        "Role `Green Team` doesn't exist",
+        "Role `Helper` is ambiguous, use its ID instead",
+        "Channel `general` doesn't exist",
+        "`101` is not a selector in this server",
+    ]);
+}
+
+#[test]
+fn diffs_only_listed_selectors() {
+    let current = GuildConfig::parse(CONFIG).unwrap();
+    let new = GuildConfig::parse(r#"
+        persisted_roles = ["Red Team", "Blue Team"]
+
+        [selectors.100.roles]
+        "🔴" = ["Blue Team"]
+        "🟢" = ["Red Team"]
+    "#).unwrap();
+
+    assert_eq!(diff(&current, &new), vec![
+        "~ notify_owner: true → false",
+        "+ persisted role `Blue Team`",
+        "- persisted role `12`",
+        "~ selector 100: approval channel `#staff` → none",
+        "~ selector 100: unmapped reactions ignored → deleted",
+        "~ selector 100: 🔴 → `Blue Team` (was `Red Team`)",
+        "+ selector 100: 🟢 → `Red Team`",
+        "- selector 100: 🔵",
+    ]);
+
+    let untouched = GuildConfig::parse("notify_owner = true\npersisted_roles = [\"12\", \"Red Team\"]").unwrap();
+    assert!(diff(&current, &untouched).is_empty());
+}
@@ -0,0 +1,48 @@
+use serenity::model::prelude::*;
+
+use mossy_stone_brick_monster_egg::reaction_roles::{Conflict, find_conflicts, Selector, SelectorEntry};
+
+fn entry(channel: u64, content: &str) -> SelectorEntry {
+    SelectorEntry::new(ChannelId::new(channel), Selector::parse(content), content)
+}
+
+#[test]
+fn finds_ambiguous_emoji_in_channel() {
+    let selectors = vec![
+        (MessageId::new(1), entry(10, "🔴 <@&100>")),
+        (MessageId::new(2), entry(10, "🔴 <@&200>")),
+        (MessageId::new(3), entry(20, "🔴 <@&300>")),
+    ];
+
+    let conflicts = find_conflicts(&selectors);
+    assert_eq!(conflicts, vec![Conflict::AmbiguousEmoji {
+        channel: ChannelId::new(10),
+        emoji: "🔴".parse().unwrap(),
+        selectors: vec![MessageId::new(1), MessageId::new(2)],
+    }]);
+}
+
+#[test]
+fn finds_roles_granted_by_multiple_entries() {
+    let selectors = vec![
+        (MessageId::new(1), entry(10, "🔴 <@&100>\n🔵 <@&200>")),
+        (MessageId::new(2), entry(20, "🟢 <@&100>")),
+    ];
+
+    let conflicts = find_conflicts(&selectors);
+    assert_eq!(conflicts, vec![Conflict::SharedRole {
+        role: RoleId::new(100),
+        selectors: vec![MessageId::new(1), MessageId::new(2)],
+    }]);
+    assert!(conflicts[0].involves(MessageId::new(2)));
+}
+
+#[test]
+fn independent_selectors_have_no_conflicts() {
+    let selectors = vec![
+        (MessageId::new(1), entry(10, "🔴 <@&100>")),
+        (MessageId::new(2), entry(10, "🔵 <@&200>")),
+    ];
+
+    assert!(find_conflicts(&selectors).is_empty());
+}
@@ -0,0 +1,48 @@
+use std::collections::HashSet;
+
+use serenity::model::prelude::*;
+
+use mossy_stone_brick_monster_egg::{dormancy, Persistent, persistent_roles};
+
+const DAY_SECS: i64 = 24 * 60 * 60;
+
+#[test]
+fn finds_guilds_idle_for_long_enough() {
+    let (busy, idle) = (GuildId::new(1), GuildId::new(2));
+    let guilds: HashSet<GuildId> = vec![busy, idle].into_iter().collect();
+    let mut state = dormancy::State::default();
+
+    // guilds seen for the first time start out active
+    assert!(state.idle_guilds(&guilds, 0, 30 * DAY_SECS).is_empty());
+
+    assert!(state.record(busy, 20 * DAY_SECS));
+    // activity within the hour isn't recorded again
+    assert!(!state.record(busy, 20 * DAY_SECS + 60));
+
+    assert_eq!(state.idle_guilds(&guilds, 30 * DAY_SECS, 30 * DAY_SECS), vec![idle]);
+    assert!(!state.is_archived(idle));
+}
+
+#[tokio::test]
+async fn moves_guild_entries_out_and_back() {
+    let dir = tempfile::tempdir().unwrap();
+    let store: Persistent<persistent_roles::State> = Persistent::open(dir.path().join("persistent_roles.json")).await;
+    let (kept, archived) = (GuildId::new(1), GuildId::new(2));
+    store.write(|state| {
+        state.guild_mut(kept).set_user_roles(UserId::new(10), vec![RoleId::new(100)]);
+        state.guild_mut(archived).set_user_roles(UserId::new(20), vec![RoleId::new(200)]);
+    }).await;
+
+    let keys: HashSet<String> = vec![archived.get().to_string()].into_iter().collect();
+    let entries = store.entries("guilds", &keys).await;
+    assert_eq!(entries.len(), 1);
+
+    store.remove_entries("guilds", &keys).await;
+    assert!(store.read().await.guild(archived).is_none());
+    assert!(store.read().await.guild(kept).is_some());
+
+    store.restore_entries("guilds", entries).await;
+    let state = store.read().await;
+    let users: Vec<(UserId, Vec<RoleId>)> = state.guild(archived).unwrap().users().map(|(user, roles)| (user, roles.to_vec())).collect();
+    assert_eq!(users, vec![(UserId::new(20), vec![RoleId::new(200)])]);
+}
@@ -0,0 +1,27 @@
+use mossy_stone_brick_monster_egg::eligibility::{parse_duration, Requirements};
+
+const DAY: i64 = 24 * 60 * 60;
+const NOW: i64 = 1628208000;
+
+#[test]
+fn parses_durations() {
+    assert_eq!(parse_duration("30m"), Some(30 * 60));
+    assert_eq!(parse_duration("7d"), Some(7 * DAY as u64));
+    assert_eq!(parse_duration("2w"), Some(14 * DAY as u64));
+    assert_eq!(parse_duration("0d"), None);
+    assert_eq!(parse_duration("7"), None);
+    assert_eq!(parse_duration("400d"), None);
+}
+
+#[test]
+fn waits_for_the_latest_requirement() {
+    let requirements = Requirements { account_age: Some(7 * DAY as u64), membership: Some(DAY as u64) };
+
+    // a week-old account that just joined waits a day for membership
+    assert_eq!(requirements.eligible_at(NOW - 7 * DAY, Some(NOW), NOW), Some(NOW + DAY));
+    // a day-old account waits for the account age even though it joined long ago
+    assert_eq!(requirements.eligible_at(NOW - DAY, Some(NOW - 2 * DAY), NOW), Some(NOW + 6 * DAY));
+    assert_eq!(requirements.eligible_at(NOW - 30 * DAY, Some(NOW - 2 * DAY), NOW), None);
+
+    assert_eq!(Requirements::default().eligible_at(NOW, Some(NOW), NOW), None);
+}
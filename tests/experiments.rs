@@ -0,0 +1,41 @@
+use serenity::model::prelude::*;
+
+use mossy_stone_brick_monster_egg::experiments::{ExperimentsConfig, Feature, FeatureConfig};
+
+const CANARY: GuildId = GuildId::new(1);
+const OTHER: GuildId = GuildId::new(2);
+const CHANNEL: ChannelId = ChannelId::new(10);
+
+#[test]
+fn features_run_in_canary_guilds_only() {
+    let mut config = ExperimentsConfig::default();
+    config.canary_guilds.insert(CANARY);
+
+    assert!(config.is_enabled(Feature::SweepFix, CANARY, None));
+    assert!(config.is_enabled(Feature::SweepFix, CANARY, Some(CHANNEL)));
+    assert!(!config.is_enabled(Feature::SweepFix, OTHER, None));
+
+    config.features.insert("sweep-fix".to_owned(), FeatureConfig { everywhere: true, ..FeatureConfig::default() });
+    assert!(config.is_enabled(Feature::SweepFix, OTHER, None));
+}
+
+#[test]
+fn channel_overrides_come_first() {
+    let mut config = ExperimentsConfig::default();
+    config.canary_guilds.insert(CANARY);
+    let mut feature = FeatureConfig::default();
+    feature.channels.insert(CHANNEL);
+    feature.disabled_channels.insert(ChannelId::new(11));
+    config.features.insert("sweep-fix".to_owned(), feature);
+
+    assert!(config.is_enabled(Feature::SweepFix, OTHER, Some(CHANNEL)));
+    assert!(!config.is_enabled(Feature::SweepFix, OTHER, Some(ChannelId::new(12))));
+    assert!(!config.is_enabled(Feature::SweepFix, CANARY, Some(ChannelId::new(11))));
+}
+
+#[test]
+fn finds_unknown_feature_names() {
+    let config: ExperimentsConfig = serde_json::from_str(r#"{ "features": { "sweep-fix": {}, "dashboard": {} } }"#).unwrap();
+    assert_eq!(config.unknown_features(), vec!["dashboard"]);
+    assert_eq!(Feature::from_name("sweep-fix"), Some(Feature::SweepFix));
+}
@@ -0,0 +1,49 @@
+use serenity::model::prelude::*;
+
+use mossy_stone_brick_monster_egg::help::{app_command_permissions, available, describe, COMMANDS};
+
+fn usages(permissions: Permissions, filter: &str) -> Vec<&'static str> {
+    available(permissions, filter).iter().map(|info| info.usage).collect()
+}
+
+#[test]
+fn members_only_see_commands_they_can_use() {
+    let everyone = usages(Permissions::empty(), "");
+    assert!(everyone.contains(&"roles"));
+    assert!(everyone.contains(&"help [command]"));
+    assert!(!everyone.contains(&"add role selector <message>"));
+
+    let moderators = usages(Permissions::MANAGE_ROLES, "");
+    assert!(moderators.contains(&"add role selector <message>"));
+    assert!(!moderators.contains(&"set log channel <channel>"));
+    // needs both permissions
+    assert!(!moderators.contains(&"generate role selector from-category <category>"));
+
+    assert_eq!(usages(Permissions::all(), "").len(), COMMANDS.len());
+}
+
+#[test]
+fn filters_by_leading_words() {
+    assert_eq!(usages(Permissions::all(), "selector ban"), vec!["selector ban <user>", "selector bans"]);
+    assert!(usages(Permissions::empty(), "selector").is_empty());
+}
+
+#[test]
+fn describes_few_commands_in_detail() {
+    let lines = describe(&available(Permissions::all(), "undo"));
+    assert_eq!(lines, vec!["`undo`: takes back the last change to a selector".to_owned()]);
+
+    let lines = describe(&available(Permissions::all(), ""));
+    assert!(lines.iter().any(|line| line.starts_with("**Selectors**: ")));
+
+    let lines = describe(&available(Permissions::empty(), "undo"));
+    assert_eq!(lines.len(), 1);
+    assert!(!lines[0].contains("undo"));
+}
+
+#[test]
+fn application_commands_carry_permissions() {
+    assert_eq!(app_command_permissions("help"), Permissions::empty());
+    assert_eq!(app_command_permissions("role-selector"), Permissions::MANAGE_ROLES);
+    assert_eq!(app_command_permissions("unknown"), Permissions::all());
+}
@@ -0,0 +1,90 @@
+use chrono_tz::Tz;
+use serenity::model::prelude::*;
+
+use mossy_stone_brick_monster_egg::history::{Entry, History, Query};
+
+// 2021-08-06 00:00 UTC
+const FRIDAY: i64 = 1628208000;
+const DAY: i64 = 24 * 60 * 60;
+
+const GUILD: GuildId = GuildId::new(829374619283740000);
+const GEGY: UserId = UserId::new(829374619283741111);
+const OTHER: UserId = UserId::new(829374619283742222);
+const VETERAN: RoleId = RoleId::new(829374619283746001);
+const MUTED: RoleId = RoleId::new(829374619283746002);
+
+fn granted(at: i64, user: UserId, role: RoleId, name: &str) -> Entry {
+    Entry {
+        at,
+        guild: GUILD,
+        action: "roles_granted".to_owned(),
+        user: Some(user),
+        roles: vec![role],
+        summary: format!("{} was granted {} through a selector", user, name),
+    }
+}
+
+fn history() -> History {
+    let history = History::open_in_memory().unwrap();
+    history.record(&granted(FRIDAY, GEGY, VETERAN, "Veteran")).unwrap();
+    history.record(&granted(FRIDAY + DAY, OTHER, VETERAN, "Veteran")).unwrap();
+    history.record(&granted(FRIDAY + 2 * DAY, GEGY, MUTED, "Muted")).unwrap();
+    history.record(&Entry {
+        at: FRIDAY + 3 * DAY,
+        guild: GuildId::new(1),
+        action: "roles_granted".to_owned(),
+        user: Some(GEGY),
+        roles: vec![VETERAN],
+        summary: "Gegy was granted Veteran through a selector".to_owned(),
+    }).unwrap();
+    history
+}
+
+fn search(history: &History, query: &str) -> Vec<i64> {
+    let tokens: Vec<&str> = query.split_whitespace().collect();
+    let query = Query::parse(&tokens, Tz::UTC).unwrap();
+    history.search(GUILD, &query, 10).unwrap().iter().map(|entry| entry.at).collect()
+}
+
+#[test]
+fn parses_filters() {
+    let query = Query::parse(&["user:<@!829374619283741111>", "role:<@&829374619283746001>", "since:2021-08-06", "Veteran"], Tz::UTC).unwrap();
+    assert_eq!(query.user, Some(GEGY));
+    assert_eq!(query.role, Some(VETERAN));
+    assert_eq!(query.since, Some(FRIDAY));
+    assert_eq!(query.terms, vec!["Veteran".to_owned()]);
+
+    let until = Query::parse(&["until:2021-08-06"], Tz::Europe__Berlin).unwrap();
+    assert_eq!(until.until, Some(FRIDAY + DAY - 2 * 60 * 60));
+
+    assert!(Query::parse(&["action:exploded"], Tz::UTC).is_err());
+    assert!(Query::parse(&["since:yesterday"], Tz::UTC).is_err());
+}
+
+#[test]
+fn filters_by_user_role_and_date() {
+    let history = history();
+    assert_eq!(search(&history, ""), vec![FRIDAY + 2 * DAY, FRIDAY + DAY, FRIDAY]);
+    assert_eq!(search(&history, "user:829374619283741111"), vec![FRIDAY + 2 * DAY, FRIDAY]);
+    assert_eq!(search(&history, "role:829374619283746001"), vec![FRIDAY + DAY, FRIDAY]);
+    assert_eq!(search(&history, "user:829374619283741111 role:829374619283746001"), vec![FRIDAY]);
+    assert_eq!(search(&history, "since:2021-08-07 until:2021-08-07"), vec![FRIDAY + DAY]);
+    assert_eq!(search(&history, "action:member_joined"), Vec::<i64>::new());
+}
+
+#[test]
+fn searches_summaries() {
+    let history = history();
+    assert_eq!(search(&history, "muted"), vec![FRIDAY + 2 * DAY]);
+    assert_eq!(search(&history, "granted veteran"), vec![FRIDAY + DAY, FRIDAY]);
+    assert_eq!(search(&history, "\"NEAR(granted"), Vec::<i64>::new());
+}
+
+#[test]
+fn forgets_removed_guilds() {
+    let history = history();
+    let removed = history.retain_guilds(&vec![GUILD].into_iter().collect()).unwrap();
+    assert_eq!(removed, vec![GuildId::new(1)]);
+    assert_eq!(history.search(GuildId::new(1), &Query::default(), 10).unwrap(), Vec::new());
+    assert_eq!(search(&history, "veteran").len(), 2);
+}
@@ -0,0 +1,20 @@
+use tokio::sync::mpsc;
+
+use mossy_stone_brick_monster_egg::join_queue::next_batch;
+
+#[tokio::test]
+async fn batches_prioritized_joins_first() {
+    let (high, mut high_receiver) = mpsc::channel(10);
+    let (normal, mut normal_receiver) = mpsc::channel(10);
+    for join in [1, 2, 3] {
+        normal.send(join).await.unwrap();
+    }
+    high.send(10).await.unwrap();
+    high.send(11).await.unwrap();
+
+    assert_eq!(next_batch(&mut high_receiver, &mut normal_receiver, 3).await, Some(vec![10, 11, 1]));
+    assert_eq!(next_batch(&mut high_receiver, &mut normal_receiver, 3).await, Some(vec![2, 3]));
+
+    drop((high, normal));
+    assert_eq!(next_batch(&mut high_receiver, &mut normal_receiver, 3).await, None);
+}
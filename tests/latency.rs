@@ -0,0 +1,82 @@
+use mossy_stone_brick_monster_egg::latency::{Alert, Limit, MIN_SAMPLES, Sample, Tracker, WINDOW_SECS};
+
+const LIMIT: Limit = Limit { p95_ms: 500, sustained_mins: 5 };
+
+fn tracker_with(at: i64, totals: &[u64]) -> Tracker {
+    let mut tracker = Tracker::default();
+    for total_ms in totals {
+        tracker.record(Sample { at, total_ms: *total_ms, lookup_ms: 0 });
+    }
+    tracker
+}
+
+#[test]
+fn needs_enough_samples_for_a_percentile() {
+    let tracker = tracker_with(0, &[100; MIN_SAMPLES - 1]);
+    assert_eq!(tracker.p95(), None);
+
+    let mut totals: Vec<u64> = (1..=20).map(|n| n * 10).collect();
+    totals.reverse();
+    assert_eq!(tracker_with(0, &totals).p95(), Some(190));
+}
+
+#[test]
+fn drops_samples_outside_the_window() {
+    let mut tracker = tracker_with(0, &[100; MIN_SAMPLES]);
+    tracker.prune(WINDOW_SECS - 1);
+    assert_eq!(tracker.len(), MIN_SAMPLES);
+    tracker.prune(WINDOW_SECS);
+    assert!(tracker.is_empty());
+}
+
+#[test]
+fn alerts_once_when_latency_stays_over_the_limit() {
+    let mut tracker = tracker_with(0, &[1000; MIN_SAMPLES]);
+    assert_eq!(tracker.check(LIMIT, 0), None);
+
+    // keep the window full of slow grants while time passes
+    for minute in 1..=4 {
+        tracker.record(Sample { at: minute * 60, total_ms: 1000, lookup_ms: 0 });
+        assert_eq!(tracker.check(LIMIT, minute * 60), None);
+    }
+    for _ in 0..MIN_SAMPLES {
+        tracker.record(Sample { at: 300, total_ms: 1000, lookup_ms: 0 });
+    }
+    assert_eq!(tracker.check(LIMIT, 300), Some(Alert::Exceeded(1000)));
+    assert_eq!(tracker.check(LIMIT, 360), None);
+
+    for _ in 0..MIN_SAMPLES * 100 {
+        tracker.record(Sample { at: 400, total_ms: 100, lookup_ms: 0 });
+    }
+    assert_eq!(tracker.check(LIMIT, 400), Some(Alert::Recovered(100)));
+    assert_eq!(tracker.check(LIMIT, 460), None);
+}
+
+#[test]
+fn short_spikes_do_not_alert() {
+    let mut tracker = tracker_with(0, &[1000; MIN_SAMPLES]);
+    assert_eq!(tracker.check(LIMIT, 0), None);
+
+    for _ in 0..MIN_SAMPLES * 20 {
+        tracker.record(Sample { at: 120, total_ms: 100, lookup_ms: 0 });
+    }
+    assert_eq!(tracker.check(LIMIT, 120), None);
+
+    // being over again starts the sustained period over
+    for _ in 0..MIN_SAMPLES * 20 {
+        tracker.record(Sample { at: 200, total_ms: 1000, lookup_ms: 0 });
+    }
+    assert_eq!(tracker.check(LIMIT, 200), None);
+    assert_eq!(tracker.check(LIMIT, 200 + 4 * 60), None);
+}
+
+#[test]
+fn measures_time_spent_on_lookups_of_slow_grants() {
+    let mut tracker = Tracker::default();
+    tracker.record(Sample { at: 0, total_ms: 1000, lookup_ms: 800 });
+    tracker.record(Sample { at: 0, total_ms: 1000, lookup_ms: 600 });
+    tracker.record(Sample { at: 0, total_ms: 100, lookup_ms: 0 });
+
+    assert!((tracker.lookup_share(LIMIT.p95_ms) - 0.7).abs() < 1e-9);
+    assert_eq!(tracker.lookup_share(5000), 0.0);
+}
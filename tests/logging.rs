@@ -0,0 +1,60 @@
+use std::collections::HashSet;
+
+use chrono::{TimeZone, Utc};
+use serenity::model::prelude::*;
+
+use mossy_stone_brick_monster_egg::logging::{FileLogConfig, GuildFilter, Line, LogFiles};
+
+const GUILD: GuildId = GuildId::new(829374619283740000);
+const OTHER_GUILD: GuildId = GuildId::new(829374619283740001);
+
+fn line(at: chrono::DateTime<Utc>, message: &str) -> Line<'_> {
+    Line { at, level: "INFO", target: "test", guild: None, message }
+}
+
+#[test]
+fn rotates_daily_and_deletes_old_files() {
+    let directory = tempfile::tempdir().unwrap();
+    let files = LogFiles::open(&FileLogConfig {
+        directory: directory.path().to_owned(),
+        retention_days: 2,
+        level: "info".to_owned(),
+        guilds: None,
+    }).unwrap();
+
+    files.write(&line(Utc.with_ymd_and_hms(2021, 8, 1, 12, 0, 0).unwrap(), "first")).unwrap();
+    files.write(&line(Utc.with_ymd_and_hms(2021, 8, 2, 12, 0, 0).unwrap(), "second")).unwrap();
+    files.write(&line(Utc.with_ymd_and_hms(2021, 8, 2, 13, 0, 0).unwrap(), "third")).unwrap();
+
+    let mut names: Vec<String> = std::fs::read_dir(directory.path()).unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .collect();
+    names.sort();
+    assert_eq!(names, vec![
+        "mossy-stone-brick-monster-egg.2021-08-01.log",
+        "mossy-stone-brick-monster-egg.2021-08-02.log",
+    ]);
+
+    let second_day = std::fs::read_to_string(directory.path().join(&names[1])).unwrap();
+    assert_eq!(second_day.lines().count(), 2);
+    assert!(second_day.contains("\"message\":\"third\""));
+
+    files.write(&line(Utc.with_ymd_and_hms(2021, 8, 4, 0, 0, 0).unwrap(), "fourth")).unwrap();
+    assert!(!directory.path().join(&names[0]).exists());
+    assert!(directory.path().join(&names[1]).exists());
+}
+
+#[test]
+fn filters_lines_about_other_guilds() {
+    let known: HashSet<GuildId> = vec![GUILD, OTHER_GUILD].into_iter().collect();
+    let filter = GuildFilter::new(Some(&[GUILD]));
+
+    let ours = format!("failed to give role to 829374619283741111 in {}", GUILD);
+    assert_eq!(filter.guild_of(&ours, &known), Some(Some(GUILD)));
+
+    let theirs = format!("failed to give role to 829374619283741111 in {}", OTHER_GUILD);
+    assert_eq!(filter.guild_of(&theirs, &known), None);
+
+    assert_eq!(filter.guild_of("bot is ready!", &known), Some(None));
+    assert_eq!(GuildFilter::new(None).guild_of(&theirs, &known), Some(Some(OTHER_GUILD)));
+}
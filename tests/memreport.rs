@@ -0,0 +1,43 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+use serenity::model::prelude::*;
+
+use mossy_stone_brick_monster_egg::memreport::{self, Introspect, Owner};
+use mossy_stone_brick_monster_egg::selector_bans;
+
+const GUILD: GuildId = GuildId::new(829374619283740000);
+const OTHER_GUILD: GuildId = GuildId::new(829374619283740001);
+const CHANNEL: ChannelId = ChannelId::new(829374619283742000);
+
+#[derive(Serialize, Deserialize, Default, Clone, Eq, PartialEq)]
+struct ByChannel(Vec<Option<ChannelId>>);
+
+impl Introspect for ByChannel {
+    fn entries(&self) -> Vec<(Owner, usize)> {
+        self.0.iter().map(|channel| (channel.map(Owner::Channel).unwrap_or(Owner::Unknown), 1)).collect()
+    }
+}
+
+#[test]
+fn counts_entries_per_guild() {
+    let state: selector_bans::State = serde_json::from_str(
+        r#"{"guilds":{"829374619283740000":["1","2","3"],"829374619283740001":["1"]}}"#,
+    ).unwrap();
+
+    let usage = memreport::measure("selector_bans", &state, &HashMap::new());
+    assert_eq!(usage.guilds.get(&GUILD), Some(&3));
+    assert_eq!(usage.guilds.get(&OTHER_GUILD), Some(&1));
+    assert_eq!(usage.entries(), 4);
+    assert_eq!(usage.bytes, serde_json::to_vec(&state).unwrap().len());
+}
+
+#[test]
+fn attributes_channels_through_their_guild() {
+    let state = ByChannel(vec![Some(CHANNEL), Some(CHANNEL), Some(ChannelId::new(1)), None]);
+    let channel_guilds = vec![(CHANNEL, GUILD)].into_iter().collect();
+
+    let usage = memreport::measure("selectors", &state, &channel_guilds);
+    assert_eq!(usage.guilds.get(&GUILD), Some(&2));
+    assert_eq!(usage.unattributed, 2);
+}
@@ -0,0 +1,41 @@
+use std::collections::HashMap;
+
+use serenity::model::prelude::*;
+
+use mossy_stone_brick_monster_egg::metrics::{GuildGauge, Metric, render, Values};
+
+#[test]
+fn renders_prometheus_text() {
+    let mut per_guild = HashMap::new();
+    per_guild.insert(GuildId::new(20), 1);
+    per_guild.insert(GuildId::new(10), 3);
+
+    let metrics = vec![
+        Metric { name: "mossy_guilds", help: "Guilds the bot is in.", values: Values::Global(2) },
+        Metric { name: "mossy_guild_selectors", help: "Role selectors in a guild.", values: Values::PerGuild(per_guild) },
+    ];
+
+    assert_eq!(render(&metrics), "\
+# HELP mossy_guilds Guilds the bot is in.
+# TYPE mossy_guilds gauge
+mossy_guilds 2
+# HELP mossy_guild_selectors Role selectors in a guild.
+# TYPE mossy_guild_selectors gauge
+mossy_guild_selectors{guild=\"10\"} 3
+mossy_guild_selectors{guild=\"20\"} 1
+");
+}
+
+#[test]
+fn guild_gauges_forget_guilds_at_zero() {
+    let gauge = GuildGauge::default();
+    let guild = GuildId::new(1);
+    gauge.increment(guild);
+    gauge.increment(guild);
+    gauge.decrement(guild);
+    assert_eq!(gauge.snapshot().get(&guild), Some(&1));
+
+    gauge.decrement(guild);
+    gauge.decrement(guild);
+    assert!(gauge.snapshot().is_empty());
+}
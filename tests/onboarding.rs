@@ -0,0 +1,16 @@
+use mossy_stone_brick_monster_egg::reaction_roles::onboarding::parse_choices;
+
+#[test]
+fn parses_numbered_replies() {
+    assert_eq!(parse_choices("1 3", 4), Some(vec![0, 2]));
+    assert_eq!(parse_choices(" 2, 4 ,2", 4), Some(vec![1, 3]));
+    assert_eq!(parse_choices("None", 4), Some(vec![]));
+}
+
+#[test]
+fn rejects_other_replies() {
+    assert_eq!(parse_choices("5", 4), None);
+    assert_eq!(parse_choices("0", 4), None);
+    assert_eq!(parse_choices("the first one", 4), None);
+    assert_eq!(parse_choices("", 4), None);
+}
@@ -0,0 +1,69 @@
+use std::collections::HashMap;
+
+use mossy_stone_brick_monster_egg::{OpenOptions, Persistent};
+use mossy_stone_brick_monster_egg::history::{Entry, History};
+use serenity::model::prelude::*;
+
+type Counts = HashMap<String, u32>;
+
+#[tokio::test]
+async fn creates_prefixed_stores_in_missing_directories() {
+    let dir = tempfile::tempdir().expect("failed to create temporary directory");
+    let options = OpenOptions::new().directory(dir.path().join("state/staging")).prefix("staging_");
+
+    let store: Persistent<Counts> = options.open("counts.json").await.unwrap();
+    store.write(|counts| counts.insert("a".to_owned(), 1)).await;
+
+    let path = dir.path().join("state/staging/staging_counts.json");
+    assert_eq!(options.path("counts.json"), path);
+    assert!(path.exists());
+}
+
+#[tokio::test]
+async fn read_only_stores_never_write() {
+    let dir = tempfile::tempdir().expect("failed to create temporary directory");
+    let path = dir.path().join("counts.json");
+    std::fs::write(&path, "{\"a\":1}").unwrap();
+
+    let store: Persistent<Counts> = OpenOptions::new().read_only(true).open(&path).await.unwrap();
+    store.write(|counts| counts.insert("b".to_owned(), 2)).await;
+    assert_eq!(store.read().await.len(), 2);
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "{\"a\":1}");
+}
+
+#[tokio::test]
+async fn missing_and_broken_stores_follow_the_options() {
+    let dir = tempfile::tempdir().expect("failed to create temporary directory");
+    let missing = dir.path().join("missing.json");
+    let result: std::io::Result<Persistent<Counts>> = OpenOptions::new().create_if_missing(false).open(&missing).await;
+    assert!(result.is_err());
+
+    let broken = dir.path().join("broken.json");
+    std::fs::write(&broken, "{\"a\":").unwrap();
+    let result: std::io::Result<Persistent<Counts>> = OpenOptions::new().open(&broken).await;
+    assert!(result.is_err());
+
+    let store: Persistent<Counts> = OpenOptions::new().strict(false).open(&broken).await.unwrap();
+    assert!(store.read().await.is_empty());
+    assert!(dir.path().join("broken.json.invalid").exists());
+}
+
+#[tokio::test]
+async fn history_lives_with_the_stores_and_follows_read_only() {
+    let dir = tempfile::tempdir().expect("failed to create temporary directory");
+    let options = OpenOptions::new().directory(dir.path()).prefix("staging_");
+    let entry = Entry {
+        at: 0,
+        guild: GuildId::new(1),
+        action: "audit".to_owned(),
+        user: None,
+        roles: Vec::new(),
+        summary: "test".to_owned(),
+    };
+
+    History::open_with(&options.clone().read_only(true)).unwrap().record(&entry).unwrap();
+    assert!(!dir.path().join("staging_history.sqlite").exists());
+
+    History::open_with(&options).unwrap().record(&entry).unwrap();
+    assert!(dir.path().join("staging_history.sqlite").exists());
+}
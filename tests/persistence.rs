@@ -0,0 +1,190 @@
+use serenity::model::prelude::*;
+
+use mossy_stone_brick_monster_egg::{Persistent, persistent_roles, reaction_roles};
+use mossy_stone_brick_monster_egg::reaction_roles::{Emoji, Selector, SelectorEntry, UnmappedPolicy};
+
+mod common;
+
+fn emoji(emoji: &str) -> Emoji {
+    emoji.parse().unwrap()
+}
+
+#[tokio::test]
+async fn loads_v1_reaction_roles() {
+    let (_dir, path) = common::fixture("reaction_roles_v1.json");
+    let state: Persistent<reaction_roles::State> = Persistent::open(&path).await;
+    let state = state.read().await;
+
+    let selector = state.selector(MessageId::new(829374619283746192)).expect("missing selector");
+    assert_eq!(selector.get_roles(&emoji("🎮")), Some(&[RoleId::new(829374619283746001)][..]));
+    assert_eq!(selector.get_roles(&emoji("🔴")), Some(&[RoleId::new(829374619283746002)][..]));
+    assert_eq!(selector.get_roles(&emoji("<:mossy:829374619283746900>")), Some(&[RoleId::new(829374619283746003)][..]));
+    assert_eq!(selector.iter().count(), 3);
+    assert!(selector.is_enabled());
+
+    let entry = state.entry(MessageId::new(829374619283746192)).unwrap();
+    assert_eq!(entry.channel, None);
+    assert_eq!(entry.last_applied_hash, None);
+
+    let selector = state.selector(MessageId::new(829374619283746193)).expect("missing selector");
+    assert_eq!(selector.get_roles(&emoji("⭐")), Some(&[RoleId::new(829374619283746004)][..]));
+}
+
+#[tokio::test]
+async fn stored_custom_emoji_match_by_id() {
+    let (_dir, path) = common::fixture("reaction_roles_v1.json");
+    let state: Persistent<reaction_roles::State> = Persistent::open(&path).await;
+    let state = state.read().await;
+
+    let selector = state.selector(MessageId::new(829374619283746192)).expect("missing selector");
+    let role = Some(&[RoleId::new(829374619283746003)][..]);
+    assert_eq!(selector.get_roles(&emoji("<:mossy:829374619283746900>")), role);
+    assert_eq!(selector.get_roles(&emoji("<a:mossy:829374619283746900>")), role);
+    assert_eq!(selector.get_roles(&emoji("<:renamed:829374619283746900>")), role);
+    assert_eq!(selector.get_roles(&emoji("<:mossy:829374619283746901>")), None);
+}
+
+#[tokio::test]
+async fn loads_v2_role_bundles() {
+    let (_dir, path) = common::fixture("reaction_roles_v2.json");
+    let state: Persistent<reaction_roles::State> = Persistent::open(&path).await;
+    let state = state.read().await;
+
+    let selector = state.selector(MessageId::new(829374619283746192)).expect("missing selector");
+    let bundle = [RoleId::new(829374619283746001), RoleId::new(829374619283746006)];
+    assert_eq!(selector.get_roles(&emoji("🎮")), Some(&bundle[..]));
+    assert_eq!(selector.get_roles(&emoji("🔴")), Some(&[RoleId::new(829374619283746002)][..]));
+}
+
+#[tokio::test]
+async fn loads_v3_mapped_options() {
+    let (_dir, path) = common::fixture("reaction_roles_v3.json");
+    let state: Persistent<reaction_roles::State> = Persistent::open(&path).await;
+    let state = state.read().await;
+
+    let selector = state.selector(MessageId::new(829374619283746192)).expect("missing selector");
+    let emoji: Vec<&Emoji> = selector.options().iter().map(|option| &option.emoji).collect();
+    assert_eq!(emoji, vec![&self::emoji("🔴"), &self::emoji("🎮")]);
+    assert_eq!(selector.get_roles(&self::emoji("🎮")), Some(&[RoleId::new(829374619283746001), RoleId::new(829374619283746006)][..]));
+    assert!(selector.is_exclusive());
+
+    let option = selector.option(&self::emoji("🔴")).unwrap();
+    assert!(!option.sticky && !option.add_only);
+    assert_eq!(option.description, None);
+    assert_eq!(option.expires_after, None);
+}
+
+#[tokio::test]
+async fn option_flags_survive_save() {
+    let (_dir, path) = common::fixture("reaction_roles_v3.json");
+
+    let state: Persistent<reaction_roles::State> = Persistent::open(&path).await;
+    state.write(|state| {
+        let option = state.selector_mut(MessageId::new(829374619283746192)).unwrap().option_mut(&emoji("🎮")).unwrap();
+        option.sticky = true;
+        option.description = Some("Game nights".to_owned());
+        option.expires_after = Some(24 * 60 * 60);
+    }).await;
+
+    let expected = state.read().await.clone();
+
+    let reopened: Persistent<reaction_roles::State> = Persistent::open(&path).await;
+    let reopened = reopened.read().await;
+    assert!(*reopened == expected);
+    let option = reopened.selector(MessageId::new(829374619283746192)).unwrap().option(&emoji("🎮")).unwrap();
+    assert!(option.sticky);
+    assert_eq!(option.description.as_deref(), Some("Game nights"));
+}
+
+#[tokio::test]
+async fn selector_log_channel_survives_save() {
+    let (_dir, path) = common::fixture("reaction_roles_v3.json");
+
+    let state: Persistent<reaction_roles::State> = Persistent::open(&path).await;
+    assert_eq!(state.read().await.entry(MessageId::new(829374619283746192)).unwrap().log_channel, None);
+    state.write(|state| {
+        state.entry_mut(MessageId::new(829374619283746192)).unwrap().log_channel = Some(ChannelId::new(829374619283745556));
+    }).await;
+
+    let reopened: Persistent<reaction_roles::State> = Persistent::open(&path).await;
+    let reopened = reopened.read().await;
+    assert_eq!(reopened.entry(MessageId::new(829374619283746192)).unwrap().log_channel, Some(ChannelId::new(829374619283745556)));
+}
+
+#[tokio::test]
+async fn loads_v1_persistent_roles() {
+    let (_dir, path) = common::fixture("persistent_roles_v1.json");
+    let state: Persistent<persistent_roles::State> = Persistent::open(&path).await;
+    let state = state.read().await;
+
+    let guild = state.guild(GuildId::new(829374619283740000)).expect("missing guild");
+    assert!(guild.is_persisted(RoleId::new(829374619283746001)));
+    assert!(guild.is_persisted(RoleId::new(829374619283746002)));
+    assert_eq!(guild.user_roles(UserId::new(829374619283741111)), Some(&[RoleId::new(829374619283746001)][..]));
+    assert_eq!(guild.user_roles(UserId::new(829374619283742222)).map(|roles| roles.len()), Some(2));
+}
+
+#[tokio::test]
+async fn reaction_roles_survive_save() {
+    let (_dir, path) = common::fixture("reaction_roles_v1.json");
+
+    let state: Persistent<reaction_roles::State> = Persistent::open(&path).await;
+    state.write(|state| {
+        let mut selector = Selector::new();
+        selector.insert_roles(emoji("🍕"), vec![RoleId::new(829374619283746005), RoleId::new(829374619283746006)]);
+        selector.set_enabled(false);
+        selector.set_unmapped_policy(UnmappedPolicy::Allow { emoji: vec![emoji("👍")].into_iter().collect() });
+        let entry = SelectorEntry::new(ChannelId::new(829374619283745555), selector, "🍕 <@&829374619283746005>");
+        state.insert_selector(MessageId::new(829374619283746194), entry);
+    }).await;
+
+    let expected = state.read().await.clone();
+
+    let reopened: Persistent<reaction_roles::State> = Persistent::open(&path).await;
+    assert!(*reopened.read().await == expected);
+}
+
+#[tokio::test]
+async fn persistent_roles_survive_save() {
+    let (_dir, path) = common::fixture("persistent_roles_v1.json");
+
+    let state: Persistent<persistent_roles::State> = Persistent::open(&path).await;
+    let expected = state.read().await.clone();
+
+    let bytes = serde_json::to_vec(&expected).unwrap();
+    let reloaded: persistent_roles::State = serde_json::from_slice(&bytes).unwrap();
+    assert!(reloaded == expected);
+}
+
+#[tokio::test]
+async fn missing_file_opens_default() {
+    let dir = tempfile::tempdir().unwrap();
+    let state: Persistent<reaction_roles::State> = Persistent::open(dir.path().join("missing.json")).await;
+    assert!(*state.read().await == reaction_roles::State::default());
+}
+
+#[tokio::test]
+async fn compaction_rewrites_legacy_format() {
+    let (_dir, path) = common::fixture("reaction_roles_v1.json");
+
+    let state: Persistent<reaction_roles::State> = Persistent::open(&path).await;
+    state.compact().await;
+
+    let bytes = std::fs::read(&path).unwrap();
+    let reloaded: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+    assert!(reloaded["829374619283746192"]["selector"].is_object());
+
+    let reopened: Persistent<reaction_roles::State> = Persistent::open(&path).await;
+    assert!(*reopened.read().await == *state.read().await);
+}
+
+#[tokio::test]
+async fn legacy_selectors_render_from_mapping() {
+    let (_dir, path) = common::fixture("reaction_roles_v1.json");
+    let state: Persistent<reaction_roles::State> = Persistent::open(&path).await;
+    let state = state.read().await;
+
+    let entry = state.entry(MessageId::new(829374619283746193)).unwrap();
+    assert_eq!(entry.content, None);
+    assert_eq!(entry.render_content(), "⭐ <@&829374619283746004>");
+}
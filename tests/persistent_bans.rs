@@ -0,0 +1,53 @@
+use serenity::model::prelude::*;
+
+use mossy_stone_brick_monster_egg::persistent_roles::{BanPolicy, GuildState};
+
+const USER: UserId = UserId::new(829374619283741111);
+const ROLE: RoleId = RoleId::new(829374619283746001);
+
+fn guild(on_ban: BanPolicy) -> GuildState {
+    let mut guild = GuildState::default();
+    guild.add_role(ROLE, vec![USER]);
+    guild.set_on_ban(on_ban);
+    guild
+}
+
+#[test]
+fn kept_roles_survive_a_ban() {
+    let mut guild = guild(BanPolicy::Keep);
+    assert_eq!(guild.ban(USER), vec![ROLE]);
+    assert!(!guild.is_frozen(USER));
+    assert_eq!(guild.unban(USER), None);
+    assert_eq!(guild.user_roles(USER), Some(&[ROLE][..]));
+}
+
+#[test]
+fn purged_roles_are_forgotten() {
+    let mut guild = guild(BanPolicy::Purge);
+    guild.mark_departed(USER, 100);
+    assert_eq!(guild.ban(USER), vec![ROLE]);
+    assert_eq!(guild.user_roles(USER), None);
+    assert_eq!(guild.departed_at(USER), None);
+    // nothing is left to apply the policy to
+    assert!(guild.ban(USER).is_empty());
+}
+
+#[test]
+fn frozen_roles_wait_for_the_unban() {
+    let mut guild = guild(BanPolicy::Freeze);
+    guild.mark_departed(USER, 100);
+    guild.ban(USER);
+    assert!(guild.is_frozen(USER));
+    // frozen roles outlast the usual retention
+    assert_eq!(guild.prune_departed(200), 0);
+
+    guild.set_restore_on_unban(true);
+    assert_eq!(guild.unban(USER), Some(true));
+    assert!(!guild.is_frozen(USER));
+    assert_eq!(guild.user_roles(USER), Some(&[ROLE][..]));
+
+    guild.ban(USER);
+    guild.set_restore_on_unban(false);
+    assert_eq!(guild.unban(USER), Some(false));
+    assert_eq!(guild.user_roles(USER), None);
+}
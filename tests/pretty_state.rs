@@ -0,0 +1,26 @@
+use std::collections::HashMap;
+
+use mossy_stone_brick_monster_egg::{Persistent, set_pretty_state};
+
+// kept apart from the other persistence tests, since the setting applies to every store
+#[tokio::test]
+async fn pretty_stores_are_stable() {
+    set_pretty_state(true);
+    let dir = tempfile::tempdir().expect("failed to create temporary directory");
+
+    let mut written = Vec::new();
+    for (index, keys) in [["b", "c", "a"], ["c", "a", "b"]].iter().enumerate() {
+        let path = dir.path().join(format!("{}.json", index));
+        let state: Persistent<HashMap<String, u32>> = Persistent::open(&path).await;
+        for key in keys {
+            state.write(|state| state.insert(key.to_string(), 1)).await;
+        }
+        written.push(std::fs::read_to_string(&path).unwrap());
+    }
+
+    assert_eq!(written[0], written[1]);
+    assert_eq!(written[0], "{\n  \"a\": 1,\n  \"b\": 1,\n  \"c\": 1\n}\n");
+
+    let reopened: Persistent<HashMap<String, u32>> = Persistent::open(dir.path().join("0.json")).await;
+    assert_eq!(reopened.read().await.len(), 3);
+}
@@ -0,0 +1,25 @@
+use mossy_stone_brick_monster_egg::reaction_roles::preview::{self, Swatch};
+
+fn swatch(name: &str, colour: u32) -> Swatch {
+    Swatch { emoji: "🎨".to_owned(), name: name.to_owned(), colour }
+}
+
+#[test]
+fn renders_a_bar_per_role() {
+    let png = preview::render(&[swatch("Red", 0xe74c3c), swatch("Blue", 0x3498db), swatch("Plain", 0)]);
+
+    assert_eq!(&png[..8], b"\x89PNG\r\n\x1a\n");
+    assert_eq!(&png[12..16], b"IHDR");
+    let width = u32::from_be_bytes([png[16], png[17], png[18], png[19]]);
+    let height = u32::from_be_bytes([png[20], png[21], png[22], png[23]]);
+    assert_eq!((width, height), (240, 3 * 24 + 2 * 4));
+    assert_eq!(&png[png.len() - 8..png.len() - 4], b"IEND");
+}
+
+#[test]
+fn describes_roles_with_the_closest_square() {
+    assert_eq!(preview::nearest_square(0xe74c3c), "🟥");
+    assert_eq!(preview::nearest_square(0x2ecc71), "🟩");
+    assert_eq!(swatch("Red", 0xe74c3c).describe(), "🎨 🟥 **Red** `#e74c3c`");
+    assert_eq!(swatch("Plain", 0).describe(), "🎨 ⬜ **Plain** (no colour)");
+}
@@ -0,0 +1,48 @@
+use serenity::model::prelude::*;
+
+use mossy_stone_brick_monster_egg::reaction_roles::progress::Tracker;
+
+fn rules_tracker() -> Tracker {
+    let messages = vec![MessageId::new(1), MessageId::new(2), MessageId::new(3)];
+    Tracker::new(RoleId::new(10), messages, 3)
+}
+
+#[test]
+fn completes_once_every_message_is_reacted_to() {
+    let mut tracker = rules_tracker();
+    let user = UserId::new(100);
+
+    assert!(!tracker.react(user, MessageId::new(1)));
+    // reacting twice to one message doesn't count twice
+    assert!(!tracker.react(user, MessageId::new(1)));
+    assert!(!tracker.react(user, MessageId::new(2)));
+    // messages outside the set don't count
+    assert!(!tracker.react(user, MessageId::new(4)));
+    assert!(tracker.react(user, MessageId::new(3)));
+    assert!(tracker.progress.is_empty());
+}
+
+#[test]
+fn removed_reactions_undo_progress() {
+    let mut tracker = rules_tracker();
+    let user = UserId::new(100);
+
+    tracker.react(user, MessageId::new(1));
+    tracker.react(user, MessageId::new(2));
+    tracker.unreact(user, MessageId::new(2));
+    assert!(!tracker.react(user, MessageId::new(3)));
+    assert!(tracker.react(user, MessageId::new(2)));
+}
+
+#[test]
+fn deleted_messages_lower_the_requirement() {
+    let mut tracker = rules_tracker();
+    let user = UserId::new(100);
+
+    tracker.react(user, MessageId::new(1));
+    tracker.react(user, MessageId::new(3));
+    tracker.forget_message(MessageId::new(3));
+    assert_eq!(tracker.required, 2);
+    assert_eq!(tracker.progress[&user].len(), 1);
+    assert!(tracker.react(user, MessageId::new(2)));
+}
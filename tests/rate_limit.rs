@@ -0,0 +1,55 @@
+use serenity::model::prelude::*;
+
+use mossy_stone_brick_monster_egg::command_usage::{command_name, State, Usage};
+use mossy_stone_brick_monster_egg::rate_limit::{Limit, TokenBucket};
+
+const GUILD: GuildId = GuildId::new(829374619283740000);
+const USER: UserId = UserId::new(829374619283741111);
+const OTHER_USER: UserId = UserId::new(829374619283742222);
+
+#[test]
+fn buckets_allow_bursts_then_refill() {
+    let limit = Limit { burst: 2, per_minute: 6 };
+    let mut bucket = TokenBucket::full(limit, 0.0);
+
+    assert_eq!(bucket.take(limit, 0.0), Ok(()));
+    assert_eq!(bucket.take(limit, 0.0), Ok(()));
+    assert_eq!(bucket.take(limit, 0.0), Err(10.0));
+    assert_eq!(bucket.take(limit, 5.0), Err(5.0));
+    assert_eq!(bucket.take(limit, 10.0), Ok(()));
+
+    // refilling stops at the burst
+    assert_eq!(bucket.take(limit, 1000.0), Ok(()));
+    assert_eq!(bucket.take(limit, 1000.0), Ok(()));
+    assert!(bucket.take(limit, 1000.0).is_err());
+}
+
+#[test]
+fn names_commands_without_their_arguments() {
+    assert_eq!(command_name(&["add", "role", "selector", "829374619283749999"]), "add role selector");
+    assert_eq!(command_name(&["set", "role", "selector", "829374619283749999", "option", "🔴", "sticky", "on"]), "set role selector");
+    assert_eq!(command_name(&["warn", "<@829374619283741111>", "spam"]), "warn");
+    assert_eq!(command_name(&["read-only", "on"]), "read-only on");
+    assert_eq!(command_name(&["search", "history", "user:@Gegy"]), "search history");
+}
+
+#[test]
+fn counts_uses_per_command_and_user() {
+    let mut state = State::default();
+    let usage = |user, command: &str, limited| Usage { user, command: command.to_owned(), at: 100, limited };
+    state.record(GUILD, usage(USER, "roles", false));
+    state.record(GUILD, usage(USER, "roles", false));
+    state.record(GUILD, usage(OTHER_USER, "cases", false));
+    state.record(GUILD, usage(USER, "roles", true));
+
+    let stats = state.stats(GUILD);
+    assert_eq!(stats.total, 4);
+    assert_eq!(stats.commands, vec![("roles".to_owned(), 2), ("cases".to_owned(), 1)]);
+    assert_eq!(stats.users, vec![(USER, 2), (OTHER_USER, 1)]);
+    assert_eq!(stats.limited, 1);
+
+    for _ in 0..600 {
+        state.record(GUILD, usage(USER, "roles", false));
+    }
+    assert_eq!(state.stats(GUILD).total, 500);
+}
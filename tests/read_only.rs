@@ -0,0 +1,14 @@
+use mossy_stone_brick_monster_egg::read_only::allows_command;
+
+#[test]
+fn allows_only_commands_that_show_things() {
+    assert!(allows_command(&["read-only", "off"]));
+    assert!(allows_command(&["list", "role", "selectors"]));
+    assert!(allows_command(&["cases", "<@829374619283741111>"]));
+    assert!(allows_command(&["search", "history", "action:audit"]));
+
+    assert!(!allows_command(&["add", "role", "selector", "829374619283749999"]));
+    assert!(!allows_command(&["sync", "my", "roles"]));
+    assert!(!allows_command(&["config", "sync", "now"]));
+    assert!(!allows_command(&["data", "retention", "set", "30"]));
+}
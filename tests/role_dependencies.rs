@@ -0,0 +1,65 @@
+use serenity::model::id::{GuildId, RoleId};
+
+use mossy_stone_brick_monster_egg::role_dependencies::State;
+
+const GUILD: GuildId = GuildId::new(1);
+const MEMBER: RoleId = RoleId::new(10);
+const EVENT_PING: RoleId = RoleId::new(11);
+const EVENT_HOST: RoleId = RoleId::new(12);
+const ARTIST: RoleId = RoleId::new(13);
+
+#[test]
+fn dependents_go_with_their_prerequisite() {
+    let mut state = State::default();
+    assert!(state.add_dependency(GUILD, MEMBER, EVENT_PING));
+
+    assert!(state.is_prerequisite(GUILD, MEMBER));
+    assert!(!state.is_prerequisite(GUILD, EVENT_PING));
+    assert_eq!(state.unmet(GUILD, &[EVENT_PING, ARTIST]), vec![EVENT_PING]);
+    assert!(state.unmet(GUILD, &[MEMBER, EVENT_PING]).is_empty());
+    assert!(state.unmet(GuildId::new(2), &[EVENT_PING]).is_empty());
+}
+
+#[test]
+fn removals_cascade() {
+    let mut state = State::default();
+    state.add_dependency(GUILD, MEMBER, EVENT_PING);
+    state.add_dependency(GUILD, EVENT_PING, EVENT_HOST);
+
+    let mut unmet = state.unmet(GUILD, &[EVENT_PING, EVENT_HOST, ARTIST]);
+    unmet.sort();
+    assert_eq!(unmet, vec![EVENT_PING, EVENT_HOST]);
+}
+
+#[test]
+fn every_prerequisite_is_required() {
+    let mut state = State::default();
+    state.add_dependency(GUILD, MEMBER, EVENT_HOST);
+    state.add_dependency(GUILD, ARTIST, EVENT_HOST);
+
+    assert_eq!(state.unmet(GUILD, &[MEMBER, EVENT_HOST]), vec![EVENT_HOST]);
+    assert!(state.unmet(GUILD, &[MEMBER, ARTIST, EVENT_HOST]).is_empty());
+}
+
+#[test]
+fn cycles_are_refused() {
+    let mut state = State::default();
+    assert!(state.add_dependency(GUILD, MEMBER, EVENT_PING));
+    assert!(state.add_dependency(GUILD, EVENT_PING, EVENT_HOST));
+
+    assert!(!state.add_dependency(GUILD, EVENT_HOST, MEMBER));
+    assert!(!state.add_dependency(GUILD, EVENT_PING, MEMBER));
+    assert!(!state.add_dependency(GUILD, MEMBER, MEMBER));
+    assert!(state.add_dependency(GUILD, MEMBER, EVENT_HOST));
+}
+
+#[test]
+fn removed_dependencies_are_forgotten() {
+    let mut state = State::default();
+    state.add_dependency(GUILD, MEMBER, EVENT_PING);
+
+    assert!(!state.remove_dependency(GUILD, ARTIST, EVENT_PING));
+    assert!(state.remove_dependency(GUILD, MEMBER, EVENT_PING));
+    assert!(state.unmet(GUILD, &[EVENT_PING]).is_empty());
+    assert!(state == State::default());
+}
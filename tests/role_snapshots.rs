@@ -0,0 +1,39 @@
+use std::collections::HashSet;
+
+use serenity::model::prelude::*;
+
+use mossy_stone_brick_monster_egg::role_snapshots::{Snapshot, State};
+
+const GUILD: GuildId = GuildId::new(829374619283740000);
+const USER: UserId = UserId::new(829374619283741111);
+const OTHER_USER: UserId = UserId::new(829374619283742222);
+
+fn role(id: u64) -> RoleId {
+    RoleId::new(829374619283746000 + id)
+}
+
+#[test]
+fn splits_restored_and_missing_roles() {
+    let snapshot = Snapshot { roles: vec![role(1), role(2), role(3), role(4)], left_at: 100 };
+    let existing: HashSet<RoleId> = vec![role(1), role(2), role(3)].into_iter().collect();
+
+    // role 4 was deleted since, so it isn't missing
+    let (restored, missing) = snapshot.diff(&[role(1), role(5)], &existing);
+    assert_eq!(restored, vec![role(1)]);
+    assert_eq!(missing, vec![role(2), role(3)]);
+}
+
+#[test]
+fn snapshots_are_taken_once_and_pruned() {
+    let mut state = State::default();
+    state.record(GUILD, USER, Snapshot { roles: vec![role(1)], left_at: 100 });
+    state.record(GUILD, OTHER_USER, Snapshot { roles: vec![role(2)], left_at: 200 });
+    // members without roles have nothing to report
+    state.record(GUILD, UserId::new(1), Snapshot { roles: Vec::new(), left_at: 200 });
+
+    assert_eq!(state.prune(150), 1);
+    assert_eq!(state.take(GUILD, USER), None);
+    assert_eq!(state.take(GUILD, OTHER_USER), Some(Snapshot { roles: vec![role(2)], left_at: 200 }));
+    assert_eq!(state.take(GUILD, OTHER_USER), None);
+    assert!(state == State::default());
+}
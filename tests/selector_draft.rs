@@ -0,0 +1,49 @@
+use serenity::model::id::RoleId;
+
+use mossy_stone_brick_monster_egg::reaction_roles::draft::{validate, Problem, MAX_OPTIONS};
+
+fn resolve(name: &str) -> Option<RoleId> {
+    match name {
+        "Red" => Some(RoleId::new(1)),
+        "Blue" => Some(RoleId::new(2)),
+        "Member" => Some(RoleId::new(3)),
+        _ => None,
+    }
+}
+
+#[test]
+fn lines_make_a_selector() {
+    let selector = validate("🔴 `Red`\n\n🔵 `Blue` and <@&4> [requires `Member`]", resolve).unwrap();
+    assert_eq!(selector.get_roles(&"🔴".parse().unwrap()), Some(&[RoleId::new(1)][..]));
+    assert_eq!(selector.get_roles(&"🔵".parse().unwrap()), Some(&[RoleId::new(2), RoleId::new(4)][..]));
+    assert_eq!(selector.option(&"🔵".parse().unwrap()).unwrap().requires, vec![RoleId::new(3)]);
+}
+
+#[test]
+fn requirement_lines_are_allowed() {
+    let selector = validate("[requires `Member`]\n🔴 `Red`", resolve).unwrap();
+    assert_eq!(selector.requires(), &[RoleId::new(3)]);
+}
+
+#[test]
+fn every_problem_is_reported() {
+    let problems = validate("🔴 `Red`\nPick one!\n🟢 `Green`\n🔴 `Blue`", resolve).err();
+    assert_eq!(problems, Some(vec![
+        Problem::NoMapping(2),
+        Problem::UnknownRole("Green".to_owned()),
+        Problem::DuplicateEmoji("🔴".parse().unwrap()),
+    ]));
+}
+
+#[test]
+fn empty_forms_are_refused() {
+    assert_eq!(validate("", resolve).err(), Some(vec![Problem::Empty]));
+    assert_eq!(validate("[requires `Member`]", resolve).err(), Some(vec![Problem::Empty]));
+}
+
+#[test]
+fn too_many_options_are_refused() {
+    let lines: Vec<String> = (0..=MAX_OPTIONS).map(|index| format!("<:option:{}> <@&{}>", index + 100, index + 100)).collect();
+    let problems = validate(&lines.join("\n"), resolve).err();
+    assert_eq!(problems, Some(vec![Problem::TooManyOptions(MAX_OPTIONS + 1)]));
+}
@@ -0,0 +1,59 @@
+use serenity::model::id::{GuildId, MessageId};
+
+use mossy_stone_brick_monster_egg::reaction_roles::names::{is_valid_name, State};
+
+const GUILD: GuildId = GuildId::new(1);
+
+#[test]
+fn names_are_short_words() {
+    assert!(is_valid_name("colors"));
+    assert!(is_valid_name("pronouns-2"));
+    assert!(is_valid_name("game_roles"));
+    assert!(!is_valid_name(""));
+    assert!(!is_valid_name("Colors"));
+    assert!(!is_valid_name("two words"));
+    assert!(!is_valid_name("1234"));
+    assert!(!is_valid_name(&"a".repeat(33)));
+}
+
+#[test]
+fn names_resolve_per_guild() {
+    let mut state = State::default();
+    assert!(state.set_name(GUILD, MessageId::new(10), "colors".to_owned()));
+
+    assert_eq!(state.resolve(GUILD, "colors"), Some(MessageId::new(10)));
+    assert_eq!(state.resolve(GuildId::new(2), "colors"), None);
+    assert_eq!(state.name_of(GUILD, MessageId::new(10)), Some("colors"));
+}
+
+#[test]
+fn taken_names_are_refused() {
+    let mut state = State::default();
+    assert!(state.set_name(GUILD, MessageId::new(10), "colors".to_owned()));
+    assert!(!state.set_name(GUILD, MessageId::new(11), "colors".to_owned()));
+    assert!(state.set_name(GUILD, MessageId::new(10), "colors".to_owned()));
+    assert!(state.set_name(GuildId::new(2), MessageId::new(11), "colors".to_owned()));
+}
+
+#[test]
+fn renaming_replaces_the_old_name() {
+    let mut state = State::default();
+    state.set_name(GUILD, MessageId::new(10), "colors".to_owned());
+    state.set_name(GUILD, MessageId::new(10), "colours".to_owned());
+
+    assert_eq!(state.resolve(GUILD, "colors"), None);
+    assert_eq!(state.resolve(GUILD, "colours"), Some(MessageId::new(10)));
+}
+
+#[test]
+fn names_follow_reposts_and_removals() {
+    let mut state = State::default();
+    state.set_name(GUILD, MessageId::new(10), "colors".to_owned());
+
+    state.moved(MessageId::new(10), MessageId::new(20));
+    assert_eq!(state.resolve(GUILD, "colors"), Some(MessageId::new(20)));
+
+    state.forget(MessageId::new(20));
+    assert_eq!(state.resolve(GUILD, "colors"), None);
+    assert!(state == State::default());
+}
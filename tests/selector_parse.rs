@@ -0,0 +1,181 @@
+use std::collections::HashMap;
+
+use proptest::prelude::*;
+use serenity::model::prelude::*;
+
+use mossy_stone_brick_monster_egg::reaction_roles::{self, Emoji, MappingChange, Selector};
+
+const UNICODE_EMOJI: &[&str] = &["🎮", "🔴", "🔵", "🟢", "⭐", "🍕", "🎨", "📌"];
+
+fn emoji() -> impl Strategy<Value=String> {
+    prop_oneof![
+        proptest::sample::select(UNICODE_EMOJI).prop_map(str::to_owned),
+        ("[a-z][a-z_]{1,12}", 1u64..u64::MAX).prop_map(|(name, id)| format!("<:{}:{}>", name, id)),
+    ]
+}
+
+fn line() -> impl Strategy<Value=(String, Vec<u64>, String)> {
+    (emoji(), proptest::collection::vec(1u64..u64::MAX, 1..4), "[A-Za-z ]{0,20}")
+}
+
+proptest! {
+    #[test]
+    fn parses_emoji_role_lines(lines in proptest::collection::vec(line(), 0..16), header in "[A-Za-z ]{0,40}") {
+        let mut content = format!("{}\n", header);
+        let mut expected = HashMap::new();
+
+        for (emoji, roles, label) in &lines {
+            let mentions: Vec<String> = roles.iter().map(|role| format!("<@&{}>", role)).collect();
+            content.push_str(&format!("{} {} {}\n", emoji, label, mentions.join(" and ")));
+
+            let roles = roles.iter().copied().map(RoleId::new).collect::<Vec<_>>();
+            expected.insert(emoji.parse::<Emoji>().unwrap(), roles);
+        }
+
+        let selector = Selector::parse(&content);
+        let parsed: HashMap<Emoji, Vec<RoleId>> = selector.iter()
+            .map(|(emoji, roles)| (emoji.clone(), roles.to_vec()))
+            .collect();
+
+        prop_assert_eq!(parsed, expected);
+    }
+
+    #[test]
+    fn ignores_lines_without_roles(text in "[A-Za-z .,!?]{0,200}") {
+        let selector = Selector::parse(&text);
+        prop_assert_eq!(selector.iter().count(), 0);
+    }
+}
+
+#[test]
+fn resolves_roles_by_name() {
+    let content = "Pick a team!\n🔴 `Red Team` and <@&2>\n🔵 `Blue Team`\n`Not a role` without emoji";
+    let resolve = |name: &str| match name {
+        "Red Team" => Some(RoleId::new(1)),
+        _ => None,
+    };
+
+    let selector = Selector::parse_with(content, resolve);
+    assert_eq!(selector.get_roles(&"🔴".parse().unwrap()), Some(&[RoleId::new(1), RoleId::new(2)][..]));
+    assert_eq!(selector.get_roles(&"🔵".parse().unwrap()), None);
+
+    assert_eq!(Selector::unresolved_names(content, resolve), vec!["Blue Team".to_owned()]);
+}
+
+#[test]
+fn renames_roles_named_in_content() {
+    let content = "Pick a team!\n🔴 `red team`\n🔵 `Blue Team`";
+
+    let renamed = reaction_roles::rename_in_content(content, "Red Team", "Crimson Team");
+    assert_eq!(renamed.as_deref(), Some("Pick a team!\n🔴 `Crimson Team`\n🔵 `Blue Team`"));
+    assert_eq!(reaction_roles::rename_in_content(content, "Green Team", "Lime Team"), None);
+}
+
+#[test]
+fn keeps_emoji_in_document_order() {
+    let content = "🔵 <@&1>\n⭐ <@&2>\n🔴 <@&3>\n🎮 <@&4>\n🍕 <@&5>";
+    let selector = Selector::parse(content);
+
+    let order: Vec<String> = selector.iter().map(|(emoji, _)| emoji.to_string()).collect();
+    assert_eq!(order, vec!["🔵", "⭐", "🔴", "🎮", "🍕"]);
+
+    let restored: Selector = serde_json::from_str(&serde_json::to_string(&selector).unwrap()).unwrap();
+    let restored_order: Vec<String> = restored.iter().map(|(emoji, _)| emoji.to_string()).collect();
+    assert_eq!(restored_order, order);
+
+    let reordered = Selector::parse("⭐ <@&2>\n🔵 <@&1>\n🔴 <@&3>\n🎮 <@&4>\n🍕 <@&5>");
+    assert!(selector != reordered);
+}
+
+#[test]
+fn reparsing_keeps_option_flags() {
+    let mut selector = Selector::parse("🔴 <@&1>\n🔵 <@&2>");
+    selector.option_mut(&"🔴".parse().unwrap()).unwrap().sticky = true;
+    selector.option_mut(&"🔵".parse().unwrap()).unwrap().add_only = true;
+
+    selector.reparse("🔴 <@&3>\n🎮 <@&4>");
+
+    let red = selector.option(&"🔴".parse().unwrap()).unwrap();
+    assert!(red.sticky);
+    assert_eq!(red.roles, vec![RoleId::new(3)]);
+    assert!(!selector.contains(&"🔵".parse().unwrap()));
+    assert!(!selector.option(&"🎮".parse().unwrap()).unwrap().sticky);
+}
+
+#[test]
+fn diffs_mappings_between_registrations() {
+    let previous = Selector::parse("🔴 <@&1>\n🔵 <@&2>\n⭐ <@&3>");
+    let new = Selector::parse("🔴 <@&1>\n🔵 <@&2> <@&4>\n🎮 <@&5>");
+
+    assert_eq!(previous.diff(&new), vec![
+        MappingChange::Changed { emoji: "🔵".parse().unwrap(), from: vec![RoleId::new(2)], to: vec![RoleId::new(2), RoleId::new(4)] },
+        MappingChange::Added { emoji: "🎮".parse().unwrap(), roles: vec![RoleId::new(5)] },
+        MappingChange::Removed { emoji: "⭐".parse().unwrap(), roles: vec![RoleId::new(3)] },
+    ]);
+    assert!(new.diff(&new).is_empty());
+}
+
+#[test]
+fn parses_requirements() {
+    let selector = Selector::parse("[requires <@&9>]\n🔴 <@&1>\n🔵 <@&2> [requires <@&8> `Artist`]");
+
+    assert_eq!(selector.requires(), &[RoleId::new(9)]);
+    assert_eq!(selector.get_roles(&"🔴".parse().unwrap()), Some(&[RoleId::new(1)][..]));
+    let blue = selector.option(&"🔵".parse().unwrap()).unwrap();
+    assert_eq!(blue.roles, vec![RoleId::new(2)]);
+    assert_eq!(blue.requires, vec![RoleId::new(8)]);
+
+    let resolved = Selector::parse_with("🔵 <@&2> [Requires `Artist`]", |name| (name == "Artist").then_some(RoleId::new(7)));
+    assert_eq!(resolved.option(&"🔵".parse().unwrap()).unwrap().requires, vec![RoleId::new(7)]);
+}
+
+#[test]
+fn finds_missing_requirements() {
+    let selector = Selector::parse("[requires <@&9>]\n🔴 <@&1>\n🔵 <@&2> [requires <@&8> <@&9>]");
+
+    assert_eq!(selector.missing_requirements(&"🔴".parse().unwrap(), &[]), vec![RoleId::new(9)]);
+    assert!(selector.missing_requirements(&"🔴".parse().unwrap(), &[RoleId::new(9)]).is_empty());
+    assert_eq!(selector.missing_requirements(&"🔵".parse().unwrap(), &[]), vec![RoleId::new(9), RoleId::new(8)]);
+    assert_eq!(selector.missing_requirements(&"🔵".parse().unwrap(), &[RoleId::new(9)]), vec![RoleId::new(8)]);
+}
+
+#[test]
+fn reparsing_replaces_requirements() {
+    let mut selector = Selector::parse("[requires <@&9>]\n🔴 <@&1> [requires <@&8>]");
+    selector.option_mut(&"🔴".parse().unwrap()).unwrap().sticky = true;
+
+    selector.reparse("🔴 <@&1>");
+
+    let red = selector.option(&"🔴".parse().unwrap()).unwrap();
+    assert!(red.sticky);
+    assert!(red.requires.is_empty());
+    assert!(selector.requires().is_empty());
+}
+
+#[test]
+fn parses_exclusive_groups() {
+    let selector = Selector::parse("--- group: Colors ---\n🔴 <@&1>\n🔵 <@&2>\n---\n🎮 <@&3>\n🎨 <@&4>");
+    let [red, blue, games, art]: [Emoji; 4] = ["🔴", "🔵", "🎮", "🎨"].map(|emoji| emoji.parse().unwrap());
+
+    assert_eq!(selector.option(&red).unwrap().group.as_deref(), Some("colors"));
+    assert_eq!(selector.get_roles(&blue), Some(&[RoleId::new(2)][..]));
+    assert_eq!(selector.option(&games).unwrap().group, None);
+
+    assert!(selector.excludes(&red, &blue));
+    assert!(!selector.excludes(&red, &red));
+    assert!(!selector.excludes(&red, &games));
+    assert!(!selector.excludes(&games, &art));
+    assert!(selector.excludes_any(&blue));
+    assert!(!selector.excludes_any(&art));
+}
+
+#[test]
+fn parses_animated_custom_emoji() {
+    let selector = Selector::parse("<a:party:42> <@&1>\n<:mossy:43> <@&2>");
+    let party = Emoji::Custom { id: EmojiId::new(42), name: Some("party".to_owned()) };
+
+    assert_eq!(selector.get_roles(&party), Some(&[RoleId::new(1)][..]));
+    assert_eq!(selector.get_roles(&"<:renamed:43>".parse().unwrap()), Some(&[RoleId::new(2)][..]));
+    assert_eq!("<:43>".parse::<Emoji>().unwrap(), Emoji::Custom { id: EmojiId::new(43), name: None });
+    assert_eq!(party.to_string(), "<:party:42>");
+}
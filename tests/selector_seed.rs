@@ -0,0 +1,14 @@
+use mossy_stone_brick_monster_egg::reaction_roles::Emoji;
+use mossy_stone_brick_monster_egg::reaction_roles::seed::{progress_line, summary};
+
+#[test]
+fn summarizes_failed_emoji() {
+    assert_eq!(summary(10, &[]), None);
+
+    let unknown: Emoji = "<:gone:42>".parse().unwrap();
+    assert_eq!(
+        summary(10, &[(unknown, "Unknown Emoji".to_owned())]).unwrap(),
+        "⚠️ Added 9 of 10 reactions to the selector. These couldn't be added:\n<:gone:42>: Unknown Emoji",
+    );
+    assert_eq!(progress_line(4, 10), "⏳ Adding reactions to the selector: 4/10");
+}
@@ -0,0 +1,42 @@
+use std::collections::HashSet;
+
+use serenity::model::prelude::*;
+
+use mossy_stone_brick_monster_egg::reaction_roles::sweep::{sample, Drift, Sweep};
+
+fn messages(ids: &[u64]) -> Vec<MessageId> {
+    ids.iter().map(|id| MessageId::new(*id)).collect()
+}
+
+#[test]
+fn samples_selectors_in_turn() {
+    let selectors = messages(&[30, 10, 20, 40]);
+
+    assert_eq!(sample(&selectors, None, 2), messages(&[10, 20]));
+    assert_eq!(sample(&selectors, Some(MessageId::new(20)), 3), messages(&[30, 40, 10]));
+    // a removed selector doesn't stop the sweep from going on after it
+    assert_eq!(sample(&selectors, Some(MessageId::new(25)), 1), messages(&[30]));
+    assert_eq!(sample(&selectors, Some(MessageId::new(40)), 10), messages(&[10, 20, 30, 40]));
+    assert!(sample(&[], None, 5).is_empty());
+}
+
+#[test]
+fn sweeps_once_per_interval() {
+    let mut sweep = Sweep::new(false, 60 * 60);
+    assert!(sweep.is_due(0));
+
+    sweep.last_run_at = Some(1000);
+    assert!(!sweep.is_due(1000 + 60 * 60 - 1));
+    assert!(sweep.is_due(1000 + 60 * 60));
+}
+
+#[test]
+fn finds_drift_both_ways() {
+    let users = |ids: &[u64]| ids.iter().map(|id| UserId::new(*id)).collect::<HashSet<_>>();
+
+    let drift = Drift::compare(&users(&[1, 2, 3]), &users(&[2, 3, 4, 5]));
+    assert_eq!(drift.unrewarded, vec![UserId::new(1)]);
+    assert_eq!(drift.unreacted, vec![UserId::new(4), UserId::new(5)]);
+
+    assert!(Drift::compare(&users(&[1, 2]), &users(&[1, 2])).is_empty());
+}
@@ -0,0 +1,61 @@
+use mossy_stone_brick_monster_egg::slowmode::{DaySet, parse_rate, Schedule, TimeWindow};
+
+// 2021-08-06 was a Friday
+const FRIDAY: i64 = 1628208000;
+const HOUR: i64 = 60 * 60;
+
+fn schedule(days: &str, window: &str) -> Schedule {
+    Schedule::new(days.parse().unwrap(), window.parse().unwrap(), 10)
+}
+
+#[test]
+fn parses_arguments() {
+    assert_eq!("18:00-23:30".parse(), Ok(TimeWindow { start: 18 * 60, end: 23 * 60 + 30 }));
+    assert!("18:00-18:00".parse::<TimeWindow>().is_err());
+    assert!("25:00-26:00".parse::<TimeWindow>().is_err());
+    assert!("fri,sat".parse::<DaySet>().is_ok());
+    assert!("someday".parse::<DaySet>().is_err());
+    assert_eq!(parse_rate("10s"), Some(10));
+    assert_eq!(parse_rate("5m"), Some(300));
+    assert_eq!(parse_rate("7h"), None);
+    assert_eq!(parse_rate("0s"), None);
+}
+
+#[test]
+fn weekday_windows() {
+    let schedule = schedule("weekdays", "18:00-23:00");
+    assert!(!schedule.is_active(FRIDAY + 17 * HOUR));
+    assert!(schedule.is_active(FRIDAY + 18 * HOUR));
+    assert!(!schedule.is_active(FRIDAY + 23 * HOUR));
+    assert!(!schedule.is_active(FRIDAY + 24 * HOUR + 18 * HOUR));
+}
+
+#[test]
+fn windows_wrap_past_midnight() {
+    let schedule = schedule("fri", "22:00-02:00");
+    assert!(schedule.is_active(FRIDAY + 23 * HOUR));
+    assert!(schedule.is_active(FRIDAY + 25 * HOUR));
+    assert!(!schedule.is_active(FRIDAY + 26 * HOUR));
+    assert!(!schedule.is_active(FRIDAY + HOUR));
+}
+
+#[test]
+fn parses_twelve_hour_times() {
+    assert_eq!("8pm-11:30pm".parse(), Ok(TimeWindow { start: 20 * 60, end: 23 * 60 + 30 }));
+    assert_eq!("noon-12am".parse(), Ok(TimeWindow { start: 12 * 60, end: 0 }));
+    assert!("13pm-2pm".parse::<TimeWindow>().is_err());
+}
+
+#[test]
+fn zoned_windows_follow_daylight_saving() {
+    let schedule = schedule("daily", "18:00-19:00").with_zone(chrono_tz::Europe::Berlin);
+
+    // Berlin is UTC+2 in August
+    assert!(schedule.is_active(FRIDAY + 16 * HOUR));
+    assert!(!schedule.is_active(FRIDAY + 18 * HOUR));
+
+    // ...and UTC+1 in December
+    let december = FRIDAY + 119 * 24 * HOUR;
+    assert!(!schedule.is_active(december + 16 * HOUR));
+    assert!(schedule.is_active(december + 17 * HOUR));
+}
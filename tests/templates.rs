@@ -0,0 +1,25 @@
+use serenity::model::id::RoleId;
+
+use mossy_stone_brick_monster_egg::reaction_roles::Selector;
+use mossy_stone_brick_monster_egg::reaction_roles::template::{placeholders, render};
+
+const TEMPLATE: &str = "Pick a colour!\n🔵 <@&1> ({count:<@&1>} members)\n🔴 <@&2> ({count: 2 } members)";
+
+#[test]
+fn finds_placeholders() {
+    assert_eq!(placeholders(TEMPLATE), vec![RoleId::new(1), RoleId::new(2)]);
+    assert!(placeholders("🔵 <@&1> {count:Blue}").is_empty());
+}
+
+#[test]
+fn renders_holder_counts() {
+    let rendered = render(TEMPLATE, |role| if role == RoleId::new(1) { 154 } else { 0 });
+    assert_eq!(rendered, "Pick a colour!\n🔵 <@&1> (154 members)\n🔴 <@&2> (0 members)");
+}
+
+#[test]
+fn rendered_templates_parse_like_the_plain_selector() {
+    let rendered = render(TEMPLATE, |_| 42);
+    let plain = Selector::parse("🔵 <@&1>\n🔴 <@&2>");
+    assert!(Selector::parse(&rendered) == plain);
+}
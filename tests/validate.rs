@@ -0,0 +1,40 @@
+use serde_json::json;
+
+use mossy_stone_brick_monster_egg::validate::{self, Severity};
+
+#[test]
+fn finds_unknown_nested_fields() {
+    let raw = json!({ "discord_token": "x", "telemetry": { "enabled": true, "endpont": "https://example.com" }, "qoutas": {} });
+    let known = json!({ "discord_token": "x", "telemetry": { "enabled": true, "endpoint": null } });
+
+    let mut fields = validate::unknown_fields(&raw, &known);
+    fields.sort();
+    assert_eq!(fields, vec!["qoutas", "telemetry.endpont"]);
+}
+
+#[test]
+fn warns_about_typos_and_refuses_broken_files() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(dir.path().join("config.json"), r#"{ "discord_token": "token", "role_qeue": {} }"#).unwrap();
+    std::fs::write(dir.path().join("audit.json"), r#"{ "log_channels": ["#).unwrap();
+
+    let report = validate::check_files(dir.path());
+    let config: Vec<_> = report.issues.iter().filter(|issue| issue.file == "config.json").collect();
+    assert_eq!(config.len(), 1);
+    assert_eq!(config[0].severity, Severity::Warning);
+    assert!(config[0].message.contains("role_qeue"));
+
+    let audit: Vec<_> = report.issues.iter().filter(|issue| issue.file == "audit.json").collect();
+    assert_eq!(audit.len(), 1);
+    assert_eq!(audit[0].severity, Severity::Fatal);
+    assert!(report.is_fatal());
+}
+
+#[test]
+fn accepts_a_clean_directory() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(dir.path().join("config.json"), r#"{ "discord_token": "token" }"#).unwrap();
+
+    let report = validate::check_files(dir.path());
+    assert!(report.issues.is_empty(), "{:?}", report.issues);
+}
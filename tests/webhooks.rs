@@ -0,0 +1,32 @@
+use serde_json::json;
+
+use mossy_stone_brick_monster_egg::webhooks::{check_url, is_public, render};
+
+#[test]
+fn renders_event_fields() {
+    let event = json!({ "type": "member_joined", "at": 1628208000, "guild": "1", "user": "2" });
+    assert_eq!(render("<@{user}> joined at {at}", &event), "<@2> joined at 1628208000");
+    assert_eq!(render("{missing}", &event), "{missing}");
+    assert_eq!(render("{json}", &event), event.to_string());
+}
+
+#[test]
+fn only_public_addresses_are_public() {
+    for ip in &["127.0.0.1", "10.1.2.3", "172.16.0.1", "192.168.1.1", "169.254.169.254", "100.64.0.1", "0.0.0.0", "::1", "fd00::1", "fe80::1", "::ffff:127.0.0.1"] {
+        assert!(!is_public(ip.parse().unwrap()), "{}", ip);
+    }
+    for ip in &["1.1.1.1", "162.159.128.233", "2606:4700::1111"] {
+        assert!(is_public(ip.parse().unwrap()), "{}", ip);
+    }
+}
+
+#[tokio::test]
+async fn rejects_unsafe_urls() {
+    assert!(check_url("http://1.1.1.1/hook").await.is_err());
+    assert!(check_url("https://169.254.169.254/latest/meta-data").await.is_err());
+    assert!(check_url("https://[::1]/hook").await.is_err());
+    assert!(check_url("https://localhost/hook").await.is_err());
+    assert!(check_url("https://metadata.google.internal/").await.is_err());
+    assert!(check_url("not a url").await.is_err());
+    assert!(check_url("https://1.1.1.1/hook").await.is_ok());
+}